@@ -0,0 +1,298 @@
+// Aho–Corasick
+// 複数のパターンを trie にまとめ、各ノードに失敗リンク (そのノードの文字列の真の接尾辞で、trie にある最も長いもの) を持たせる
+// 入力を 1 バイトずつ読み、遷移がなければ失敗リンクを辿ってから進む。戻る回数は進んだ回数を超えないので、入力の長さ n に対して O(n)
+// 今のノードで終わるパターンは、そのノードと、失敗リンクを辿って着く出力のあるノードのもの
+// 出力リンク (失敗リンクを辿って最初に着く、パターンの終わるノード) を持たせて、出力のないノードは飛ばす
+// 全ての一致を返すのは O(n + k log σ) (k は一致の数、σ はアルファベットの大きさ)
+//
+// 作るのはパターンの長さの和 m に対して O(m log σ)。失敗リンクは trie を浅い順に辿って決める
+// 作った後にパターンを足したり消したりはできない。変えたら作り直す
+//
+// 入力は u8 の Iterator で受け取るので、全体をメモリに載せずに読める
+// 空のパターンはどこにも現れないものとする
+use std::collections::{BTreeMap, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Match {
+    // new に渡した順の番号
+    pub pattern: usize,
+    // 入力の先頭から数えたバイトの位置。start..end が一致した範囲
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    next: BTreeMap<u8, usize>,
+    // 根は根を指す
+    fail: usize,
+    // このノードで終わるパターン。同じパターンを何度渡しても、全て返す
+    outputs: Vec<usize>,
+    // 失敗リンクを辿って最初に着く、outputs の空でないノード
+    dict: Option<usize>,
+}
+
+impl Node {
+    fn new() -> Self {
+        Self {
+            next: BTreeMap::new(),
+            fail: 0,
+            outputs: Vec::new(),
+            dict: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    // nodes[0] は根
+    nodes: Vec<Node>,
+    // 各パターンの長さ
+    lens: Vec<usize>,
+}
+
+impl AhoCorasick {
+    pub fn new<I, P>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let mut nodes = vec![Node::new()];
+        let mut lens = Vec::new();
+        for (index, pattern) in patterns.into_iter().enumerate() {
+            let pattern = pattern.as_ref();
+            lens.push(pattern.len());
+            if pattern.is_empty() {
+                continue;
+            }
+            let mut v = 0;
+            for &c in pattern {
+                v = match nodes[v].next.get(&c) {
+                    Some(&to) => to,
+                    None => {
+                        nodes.push(Node::new());
+                        let to = nodes.len() - 1;
+                        nodes[v].next.insert(c, to);
+                        to
+                    }
+                };
+            }
+            nodes[v].outputs.push(index);
+        }
+        // 浅い順に辿ると、親の失敗リンクは決まっている
+        let mut queue: VecDeque<usize> = nodes[0].next.values().copied().collect();
+        while let Some(v) = queue.pop_front() {
+            let children: Vec<(u8, usize)> =
+                nodes[v].next.iter().map(|(&c, &to)| (c, to)).collect();
+            for (c, child) in children {
+                let mut f = nodes[v].fail;
+                let fail = loop {
+                    if let Some(&to) = nodes[f].next.get(&c) {
+                        break to;
+                    }
+                    if f == 0 {
+                        break 0;
+                    }
+                    f = nodes[f].fail;
+                };
+                nodes[child].fail = fail;
+                nodes[child].dict = if nodes[fail].outputs.is_empty() {
+                    nodes[fail].dict
+                } else {
+                    Some(fail)
+                };
+                queue.push_back(child);
+            }
+        }
+        Self { nodes, lens }
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.lens.len()
+    }
+
+    // 根を含む trie のノードの数
+    pub fn state_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // state から c を読んだ後のノード
+    fn step(&self, mut state: usize, c: u8) -> usize {
+        loop {
+            if let Some(&to) = self.nodes[state].next.get(&c) {
+                return to;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.nodes[state].fail;
+        }
+    }
+
+    // input の中の全ての一致を、終わりの位置の順に返す。重なる一致も全て返す
+    // 終わりが同じなら長い順、同じ範囲ならパターンの番号の順
+    pub fn find_iter<I: IntoIterator<Item = u8>>(&self, input: I) -> FindIter<'_, I::IntoIter> {
+        FindIter {
+            automaton: self,
+            input: input.into_iter(),
+            state: 0,
+            pos: 0,
+            report: None,
+            index: 0,
+        }
+    }
+
+    pub fn is_match<I: IntoIterator<Item = u8>>(&self, input: I) -> bool {
+        self.find_iter(input).next().is_some()
+    }
+
+    // 失敗リンクがノードの文字列の接尾辞で、出力リンクが出力のある最初のノードを指すか確かめる
+    pub fn check_invariants(&self) {
+        // 根からの文字列を復元する
+        let mut labels = vec![Vec::new(); self.nodes.len()];
+        let mut stack = vec![0];
+        while let Some(v) = stack.pop() {
+            for (&c, &to) in &self.nodes[v].next {
+                let mut label = labels[v].clone();
+                label.push(c);
+                labels[to] = label;
+                stack.push(to);
+            }
+        }
+        for (v, node) in self.nodes.iter().enumerate().skip(1) {
+            let (label, fail) = (&labels[v], &labels[node.fail]);
+            assert!(
+                fail.len() < label.len() && label.ends_with(fail),
+                "fail link is not a proper suffix"
+            );
+            // fail より長い真の接尾辞は trie にない
+            let longest = (1..label.len())
+                .map(|i| &label[i..])
+                .find(|suffix| labels.iter().any(|l| l == suffix))
+                .map_or(0, |suffix| suffix.len());
+            assert_eq!(fail.len(), longest, "fail link is not the longest suffix");
+            let mut f = node.fail;
+            while f != 0 && self.nodes[f].outputs.is_empty() {
+                f = self.nodes[f].fail;
+            }
+            let expected = if f != 0 { Some(f) } else { None };
+            assert_eq!(node.dict, expected, "dict link is stale");
+            for &p in &node.outputs {
+                assert_eq!(self.lens[p], label.len(), "output has a wrong length");
+            }
+        }
+    }
+}
+
+pub struct FindIter<'a, I> {
+    automaton: &'a AhoCorasick,
+    input: I,
+    state: usize,
+    // 読んだバイトの数
+    pos: usize,
+    // まだ返していない出力のあるノードと、その outputs の位置
+    report: Option<usize>,
+    index: usize,
+}
+
+impl<'a, I: Iterator<Item = u8>> Iterator for FindIter<'a, I> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let nodes = &self.automaton.nodes;
+        loop {
+            if let Some(v) = self.report {
+                if let Some(&pattern) = nodes[v].outputs.get(self.index) {
+                    self.index += 1;
+                    return Some(Match {
+                        pattern,
+                        start: self.pos - self.automaton.lens[pattern],
+                        end: self.pos,
+                    });
+                }
+                self.report = nodes[v].dict;
+                self.index = 0;
+                continue;
+            }
+            let c = self.input.next()?;
+            self.state = self.automaton.step(self.state, c);
+            self.pos += 1;
+            self.report = if nodes[self.state].outputs.is_empty() {
+                nodes[self.state].dict
+            } else {
+                Some(self.state)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn ushers() {
+        let ac = AhoCorasick::new(["he", "she", "his", "hers"]);
+        ac.check_invariants();
+        let found: Vec<_> = ac
+            .find_iter(b"ushers".iter().copied())
+            .map(|m| (m.pattern, m.start, m.end))
+            .collect();
+        assert_eq!(found, vec![(1, 1, 4), (0, 2, 4), (3, 2, 6)]);
+        assert!(ac.is_match("this".bytes()));
+        assert!(!ac.is_match("hi".bytes()));
+
+        // 同じパターンと空のパターン
+        let ac = AhoCorasick::new(vec!["aa", "", "aa", "a"]);
+        ac.check_invariants();
+        let found: Vec<_> = ac
+            .find_iter("aaa".bytes())
+            .map(|m| (m.pattern, m.start))
+            .collect();
+        assert_eq!(
+            found,
+            vec![(3, 0), (0, 0), (2, 0), (3, 1), (0, 1), (2, 1), (3, 2)]
+        );
+        assert_eq!(ac.pattern_count(), 4);
+    }
+
+    #[test]
+    fn stream() {
+        // 読み込んだ塊を繋いで渡すと、塊の境界を跨ぐ一致も見つかる
+        let ac = AhoCorasick::new(["ERROR", "timeout"]);
+        let chunks: Vec<&[u8]> = vec![b"ok\nERR", b"OR: conn", b"ection time", b"out\n"];
+        let found: Vec<_> = ac
+            .find_iter(chunks.into_iter().flatten().copied())
+            .map(|m| (m.pattern, m.start, m.end))
+            .collect();
+        assert_eq!(found, vec![(0, 3, 8), (1, 21, 28)]);
+    }
+
+    proptest! {
+        // 全ての位置で全てのパターンを比べたものと、順序も含めて同じになる
+        #[test]
+        fn matches_naive(
+            patterns in prop::collection::vec(prop::collection::vec(0..3u8, 0..5), 1..10),
+            input in prop::collection::vec(0..3u8, 0..100),
+        ) {
+            let ac = AhoCorasick::new(&patterns);
+            ac.check_invariants();
+            let mut expected = Vec::new();
+            for (pattern, p) in patterns.iter().enumerate() {
+                if p.is_empty() {
+                    continue;
+                }
+                for start in 0..input.len() {
+                    if input[start..].starts_with(p) {
+                        expected.push(Match { pattern, start, end: start + p.len() });
+                    }
+                }
+            }
+            expected.sort_by_key(|m| (m.end, m.start, m.pattern));
+            let found: Vec<_> = ac.find_iter(input.iter().copied()).collect();
+            prop_assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,706 @@
+// 最近使ったものと何度も使ったものの配分を自動で調整するキャッシュ (ARC, Adaptive Replacement Cache)
+// 値を持つ要素を 2 つの LRU リストに分ける
+//   recent   (T1): 一度だけ使ったもの
+//   frequent (T2): 二度以上使ったもの
+// さらに、追い出した要素のキーだけを、どちらから追い出したかで 2 つの LRU リスト (ghost) に残す
+//   recent_ghost   (B1): recent から追い出したもの
+//   frequent_ghost (B2): frequent から追い出したもの
+// recent_ghost にあるキーを入れ直すのは recent が小さすぎたということなので、recent の目標の大きさ target を増やす
+// frequent_ghost にあるキーなら逆に減らす。追い出すときは、recent が target より大きければ recent から、そうでなければ frequent から追い出す
+// 一度しか使わないものが大量に流れても recent の中で入れ替わるだけで、frequent に入ったものは残る
+//
+// 4 つのリストの要素は lrucache と同じく Vec に置き、前後を位置で持つ。どの操作も O(1)
+// 要素の重さは持たず、容量は値を持つ要素の数。ghost も含めたキーの数は容量の 2 倍までに抑える
+use std::{collections::HashMap, hash::Hash};
+
+pub use cache::Cache;
+
+// 要素の入っているリスト。lists の添字
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Recent = 0,
+    Frequent = 1,
+    RecentGhost = 2,
+    FrequentGhost = 3,
+}
+
+#[derive(Debug, Clone)]
+struct Entry<K, V> {
+    key: K,
+    // ghost では None
+    value: Option<V>,
+    kind: Kind,
+    // 1 つ新しい方
+    prev: Option<usize>,
+    // 1 つ古い方
+    next: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct List {
+    // 最近使った方の端
+    head: Option<usize>,
+    // 最も長く使われていない方の端
+    tail: Option<usize>,
+    len: usize,
+}
+
+// get で数えたヒット率
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Stats {
+    // get をまだ呼んでいなければ 0
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArcCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Vec<Option<Entry<K, V>>>,
+    free: Vec<usize>,
+    lists: [List; 4],
+    capacity: usize,
+    // recent の目標の大きさ。0 から capacity まで
+    target: usize,
+    stats: Stats,
+}
+
+impl<K: Hash + Eq + Clone, V> ArcCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self {
+            map: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            lists: [List::default(); 4],
+            capacity,
+            target: 0,
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // 値を持つ要素の数。ghost は数えない
+    pub fn len(&self) -> usize {
+        self.list(Kind::Recent).len + self.list(Kind::Frequent).len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 一度だけ使った要素の数
+    pub fn recent_len(&self) -> usize {
+        self.list(Kind::Recent).len
+    }
+
+    // 二度以上使った要素の数
+    pub fn frequent_len(&self) -> usize {
+        self.list(Kind::Frequent).len
+    }
+
+    // recent の目標の大きさ
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
+    fn list(&self, kind: Kind) -> &List {
+        &self.lists[kind as usize]
+    }
+
+    fn list_mut(&mut self, kind: Kind) -> &mut List {
+        &mut self.lists[kind as usize]
+    }
+
+    fn entry(&self, i: usize) -> &Entry<K, V> {
+        self.entries[i].as_ref().expect("linked slot is occupied")
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut Entry<K, V> {
+        self.entries[i].as_mut().expect("linked slot is occupied")
+    }
+
+    // 値を持つ要素の位置
+    fn resident(&self, key: &K) -> Option<usize> {
+        let i = *self.map.get(key)?;
+        self.entry(i).value.as_ref().map(|_| i)
+    }
+
+    // 入っているリストから外す
+    fn detach(&mut self, i: usize) {
+        let (kind, prev, next) = {
+            let entry = self.entry(i);
+            (entry.kind, entry.prev, entry.next)
+        };
+        match prev {
+            Some(p) => self.entry_mut(p).next = next,
+            None => self.list_mut(kind).head = next,
+        }
+        match next {
+            Some(n) => self.entry_mut(n).prev = prev,
+            None => self.list_mut(kind).tail = prev,
+        }
+        self.list_mut(kind).len -= 1;
+    }
+
+    // kind のリストの先頭に置く
+    fn attach_front(&mut self, i: usize, kind: Kind) {
+        let head = self.list(kind).head;
+        {
+            let entry = self.entry_mut(i);
+            entry.kind = kind;
+            entry.prev = None;
+            entry.next = head;
+        }
+        match head {
+            Some(h) => self.entry_mut(h).prev = Some(i),
+            None => self.list_mut(kind).tail = Some(i),
+        }
+        let list = self.list_mut(kind);
+        list.head = Some(i);
+        list.len += 1;
+    }
+
+    fn move_front(&mut self, i: usize, kind: Kind) {
+        self.detach(i);
+        self.attach_front(i, kind);
+    }
+
+    // リストと map から外し、位置を空ける
+    fn take(&mut self, i: usize) -> (K, Option<V>) {
+        self.detach(i);
+        let entry = self.entries[i].take().expect("linked slot is occupied");
+        self.free.push(i);
+        self.map.remove(&entry.key);
+        (entry.key, entry.value)
+    }
+
+    // ghost の最も古いキーを忘れる
+    fn forget_oldest(&mut self, kind: Kind) {
+        if let Some(tail) = self.list(kind).tail {
+            self.take(tail);
+        }
+    }
+
+    // 値を取り除き、キーを ghost に残す
+    fn demote(&mut self, i: usize) -> (K, V) {
+        let ghost = match self.entry(i).kind {
+            Kind::Recent => Kind::RecentGhost,
+            _ => Kind::FrequentGhost,
+        };
+        self.move_front(i, ghost);
+        let entry = self.entry_mut(i);
+        let value = entry.value.take().expect("resident has a value");
+        (entry.key.clone(), value)
+    }
+
+    // 追い出す順に位置を返す
+    // recent が target より大きいか、frequent_ghost のキーを入れ直す途中で recent がちょうど target なら recent から先に見る
+    fn victims(&self, frequent_ghost_hit: bool) -> impl Iterator<Item = usize> + '_ {
+        let recent = self.list(Kind::Recent).len;
+        let recent_first =
+            recent > 0 && (recent > self.target || (frequent_ghost_hit && recent == self.target));
+        let (first, second) = if recent_first {
+            (Kind::Recent, Kind::Frequent)
+        } else {
+            (Kind::Frequent, Kind::Recent)
+        };
+        let mut current = self.list(first).tail;
+        let mut rest = Some(second);
+        std::iter::from_fn(move || loop {
+            if let Some(i) = current {
+                current = self.entry(i).prev;
+                return Some(i);
+            }
+            current = self.list(rest.take()?).tail;
+        })
+    }
+
+    // 満杯なら 1 つ追い出して ghost に残す
+    fn make_room(&mut self, frequent_ghost_hit: bool, removed: &mut Vec<(K, V)>) {
+        if self.len() < self.capacity {
+            return;
+        }
+        let victim = self
+            .victims(frequent_ghost_hit)
+            .next()
+            .expect("full cache has a resident");
+        removed.push(self.demote(victim));
+    }
+
+    // 使ったことにして返す。ヒット率に数える
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = match self.resident(key) {
+            Some(i) => i,
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+        };
+        self.stats.hits += 1;
+        self.move_front(i, Kind::Frequent);
+        self.entry_mut(i).value.as_mut()
+    }
+
+    // 使ったことにせずに返す。ヒット率にも数えない
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.resident(key)
+            .and_then(|i| self.entry(i).value.as_ref())
+    }
+
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.resident(key)?;
+        self.entry_mut(i).value.as_mut()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.resident(key).is_some()
+    }
+
+    // key に value を入れ、このために取り除いたものを返す
+    // 同じキーがあれば、置き換えも使ったことにして frequent に移し、古い値を (key, 古い値) として返す
+    // ghost にあるキーなら target を調整してから frequent に入れ、どちらでもなければ recent に入れる
+    // 満杯なら、1 つ追い出してその (key, value) を返す
+    pub fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        let i = match self.map.get(&key) {
+            Some(&i) => i,
+            None => {
+                self.insert_new(key, value, &mut removed);
+                return removed;
+            }
+        };
+        match self.entry(i).kind {
+            Kind::Recent | Kind::Frequent => {
+                let old = self.entry_mut(i).value.replace(value);
+                self.move_front(i, Kind::Frequent);
+                removed.push((key, old.expect("resident has a value")));
+            }
+            Kind::RecentGhost => {
+                let ratio = self.ratio(Kind::FrequentGhost, Kind::RecentGhost);
+                self.target = (self.target + ratio).min(self.capacity);
+                self.make_room(false, &mut removed);
+                self.entry_mut(i).value = Some(value);
+                self.move_front(i, Kind::Frequent);
+            }
+            Kind::FrequentGhost => {
+                let ratio = self.ratio(Kind::RecentGhost, Kind::FrequentGhost);
+                self.target = self.target.saturating_sub(ratio);
+                self.make_room(true, &mut removed);
+                self.entry_mut(i).value = Some(value);
+                self.move_front(i, Kind::Frequent);
+            }
+        }
+        removed
+    }
+
+    // target を動かす幅。当たった ghost が小さいほど大きく動かす
+    fn ratio(&self, other: Kind, hit: Kind) -> usize {
+        (self.list(other).len / self.list(hit).len).max(1)
+    }
+
+    fn insert_new(&mut self, key: K, value: V, removed: &mut Vec<(K, V)>) {
+        let recent_side = self.list(Kind::Recent).len + self.list(Kind::RecentGhost).len;
+        let total =
+            recent_side + self.list(Kind::Frequent).len + self.list(Kind::FrequentGhost).len;
+        if recent_side >= self.capacity {
+            if self.list(Kind::RecentGhost).len > 0 {
+                self.forget_oldest(Kind::RecentGhost);
+                self.make_room(false, removed);
+            } else {
+                // recent だけで満杯なので、ghost に残さずに追い出す
+                let tail = self.list(Kind::Recent).tail.expect("recent is full");
+                let (key, value) = self.take(tail);
+                removed.push((key, value.expect("resident has a value")));
+            }
+        } else if total >= self.capacity {
+            if total >= 2 * self.capacity {
+                self.forget_oldest(Kind::FrequentGhost);
+            }
+            self.make_room(false, removed);
+        }
+        let entry = Entry {
+            key: key.clone(),
+            value: Some(value),
+            kind: Kind::Recent,
+            prev: None,
+            next: None,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.entries[i] = Some(entry);
+                i
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        self.map.insert(key, i);
+        self.attach_front(i, Kind::Recent);
+    }
+
+    // 値を持つ要素を取り除く。ghost のキーも忘れるが、値がないので None を返す
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = *self.map.get(key)?;
+        self.take(i).1
+    }
+
+    // 追い出す順に見て、evictable が true を返した最初のものを取り除き、キーを ghost に残す
+    pub fn pop_where<F: FnMut(&K, &V) -> bool>(&mut self, mut evictable: F) -> Option<(K, V)> {
+        let victim = self.victims(false).find(|&i| {
+            let entry = self.entry(i);
+            evictable(
+                &entry.key,
+                entry.value.as_ref().expect("resident has a value"),
+            )
+        })?;
+        Some(self.demote(victim))
+    }
+
+    // ghost も含めて全て忘れる。ヒット率はそのまま
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.lists = [List::default(); 4];
+        self.target = 0;
+    }
+
+    // recent を最近使った順に返し、続けて frequent を最近使った順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.keys_of(Kind::Recent)
+            .chain(self.keys_of(Kind::Frequent))
+            .map(move |i| {
+                let entry = self.entry(i);
+                (
+                    &entry.key,
+                    entry.value.as_ref().expect("resident has a value"),
+                )
+            })
+    }
+
+    fn keys_of(&self, kind: Kind) -> impl Iterator<Item = usize> + '_ {
+        let mut current = self.list(kind).head;
+        std::iter::from_fn(move || {
+            let i = current?;
+            current = self.entry(i).next;
+            Some(i)
+        })
+    }
+
+    // リストの前後と長さ、値の有無が合い、値を持つ要素とキーの数が上限に収まっているか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        for kind in [
+            Kind::Recent,
+            Kind::Frequent,
+            Kind::RecentGhost,
+            Kind::FrequentGhost,
+        ] {
+            let list = self.list(kind);
+            let mut len = 0;
+            let mut prev = None;
+            let mut current = list.head;
+            while let Some(i) = current {
+                let entry = self.entry(i);
+                assert_eq!(entry.kind, kind, "kind is stale");
+                assert_eq!(entry.prev, prev, "prev is stale");
+                assert_eq!(self.map.get(&entry.key), Some(&i), "map is stale");
+                assert_eq!(
+                    entry.value.is_some(),
+                    matches!(kind, Kind::Recent | Kind::Frequent),
+                    "ghost has a value or resident has none"
+                );
+                len += 1;
+                prev = Some(i);
+                current = entry.next;
+            }
+            assert_eq!(list.tail, prev, "tail is stale");
+            assert_eq!(list.len, len, "len is stale");
+            count += len;
+        }
+        assert_eq!(count, self.map.len(), "map has entries not in the lists");
+        assert_eq!(
+            count + self.free.len(),
+            self.entries.len(),
+            "slot is leaked"
+        );
+        assert!(self.len() <= self.capacity, "too many residents");
+        assert!(
+            self.list(Kind::Recent).len + self.list(Kind::RecentGhost).len <= self.capacity,
+            "recent side exceeds capacity"
+        );
+        assert!(count <= 2 * self.capacity, "too many keys");
+        assert!(self.target <= self.capacity, "target exceeds capacity");
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for ArcCache<K, V> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        ArcCache::len(self)
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        ArcCache::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        ArcCache::get_mut(self, key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        ArcCache::peek(self, key)
+    }
+
+    fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        ArcCache::peek_mut(self, key)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        ArcCache::put(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        ArcCache::remove(self, key)
+    }
+
+    fn pop_victim(&mut self, evictable: &mut dyn FnMut(&K, &V) -> bool) -> Option<(K, V)> {
+        self.pop_where(evictable)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use lrucache::LruCache;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn adapt() {
+        let mut cache = ArcCache::new(2);
+        assert!(cache.put("a", 1).is_empty());
+        assert!(cache.put("b", 2).is_empty());
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!((cache.recent_len(), cache.frequent_len()), (1, 1));
+        // recent が target より大きいので、recent の b が追い出されて ghost に残る
+        assert_eq!(cache.put("c", 3), vec![("b", 2)]);
+        assert!(!cache.contains_key(&"b"));
+        // ghost の b を入れ直すと recent を大きくし、frequent の a を追い出す
+        assert_eq!(cache.put("b", 20), vec![("a", 1)]);
+        assert_eq!(cache.target(), 1);
+        assert_eq!(cache.peek(&"b"), Some(&20));
+        let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(order, vec![("c", 3), ("b", 20)]);
+        cache.check_invariants();
+
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.stats(), Stats { hits: 1, misses: 1 });
+        assert_eq!(cache.stats().hit_rate(), 0.5);
+        assert_eq!(cache.pop_where(|k, _| *k != "c"), Some(("b", 20)));
+        assert_eq!(cache.remove(&"c"), Some(3));
+        assert!(cache.is_empty());
+        cache.check_invariants();
+    }
+
+    // 何度も使うものを使いながら、容量より長い一度しか使わないものを流す
+    // LRU は流したもので何度も使うものまで追い出すが、ARC では流したものが recent の中で入れ替わるだけになる
+    #[test]
+    fn scan_resistant() {
+        let mut arc = ArcCache::new(100);
+        let mut lru = LruCache::new(100);
+        let mut lru_stats = Stats::default();
+        let mut access = |k: u32| {
+            if arc.get(&k).is_none() {
+                arc.put(k, ());
+            }
+            if lru.get(&k).is_some() {
+                lru_stats.hits += 1;
+            } else {
+                lru_stats.misses += 1;
+                lru.put(k, ());
+            }
+        };
+        for k in (0..50).chain(0..50) {
+            access(k);
+        }
+        let mut scan = 1000..;
+        for _ in 0..20 {
+            for k in (0..50).chain(scan.by_ref().take(200)) {
+                access(k);
+            }
+        }
+        arc.check_invariants();
+        // 最初の 50 回以外は、何度も使うものは全てヒットする
+        assert_eq!(arc.stats().hits, 50 + 20 * 50);
+        assert!(arc.stats().hit_rate() > 0.2);
+        // LRU では最初に流したものに追い出された後は、一度もヒットしない
+        assert_eq!(lru_stats.hits, 50 + 50);
+    }
+
+    // ARC の論文の手順を VecDeque でそのまま書いたもの
+    #[derive(Default)]
+    struct Model {
+        capacity: usize,
+        target: usize,
+        // 先頭が最近使った方
+        t1: VecDeque<(u8, u32)>,
+        t2: VecDeque<(u8, u32)>,
+        b1: VecDeque<u8>,
+        b2: VecDeque<u8>,
+    }
+
+    impl Model {
+        fn position<T>(list: &VecDeque<T>, f: impl Fn(&T) -> bool) -> Option<usize> {
+            list.iter().position(f)
+        }
+
+        fn replace(&mut self, in_b2: bool, removed: &mut Vec<(u8, u32)>) {
+            if self.t1.len() + self.t2.len() < self.capacity {
+                return;
+            }
+            let t1 = self.t1.len();
+            if t1 > 0 && (t1 > self.target || (in_b2 && t1 == self.target)) || self.t2.is_empty() {
+                let (k, v) = self.t1.pop_back().unwrap();
+                self.b1.push_front(k);
+                removed.push((k, v));
+            } else {
+                let (k, v) = self.t2.pop_back().unwrap();
+                self.b2.push_front(k);
+                removed.push((k, v));
+            }
+        }
+
+        fn get(&mut self, k: u8) -> Option<u32> {
+            let e = match Self::position(&self.t1, |e| e.0 == k) {
+                Some(i) => self.t1.remove(i).unwrap(),
+                None => self
+                    .t2
+                    .remove(Self::position(&self.t2, |e| e.0 == k)?)
+                    .unwrap(),
+            };
+            self.t2.push_front(e);
+            Some(e.1)
+        }
+
+        fn put(&mut self, k: u8, v: u32) -> Vec<(u8, u32)> {
+            let mut removed = Vec::new();
+            if let Some(old) = self.get(k) {
+                self.t2[0].1 = v;
+                removed.push((k, old));
+            } else if let Some(i) = Self::position(&self.b1, |&b| b == k) {
+                self.target =
+                    (self.target + (self.b2.len() / self.b1.len()).max(1)).min(self.capacity);
+                // replace で b1 の先頭に入ると i がずれるので、先に外す
+                self.b1.remove(i);
+                self.replace(false, &mut removed);
+                self.t2.push_front((k, v));
+            } else if let Some(i) = Self::position(&self.b2, |&b| b == k) {
+                self.target = self
+                    .target
+                    .saturating_sub((self.b1.len() / self.b2.len()).max(1));
+                self.b2.remove(i);
+                self.replace(true, &mut removed);
+                self.t2.push_front((k, v));
+            } else {
+                let l1 = self.t1.len() + self.b1.len();
+                let total = l1 + self.t2.len() + self.b2.len();
+                if l1 >= self.capacity {
+                    if !self.b1.is_empty() {
+                        self.b1.pop_back();
+                        self.replace(false, &mut removed);
+                    } else {
+                        removed.push(self.t1.pop_back().unwrap());
+                    }
+                } else if total >= self.capacity {
+                    if total >= 2 * self.capacity {
+                        self.b2.pop_back();
+                    }
+                    self.replace(false, &mut removed);
+                }
+                self.t1.push_front((k, v));
+            }
+            removed
+        }
+
+        fn remove(&mut self, k: u8) -> Option<u32> {
+            self.b1.retain(|&b| b != k);
+            self.b2.retain(|&b| b != k);
+            match Self::position(&self.t1, |e| e.0 == k) {
+                Some(i) => self.t1.remove(i).map(|e| e.1),
+                None => Self::position(&self.t2, |e| e.0 == k)
+                    .and_then(|i| self.t2.remove(i))
+                    .map(|e| e.1),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Put(u8),
+        Get(u8),
+        Remove(u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => (0..20u8).prop_map(Op::Put),
+            3 => (0..20u8).prop_map(Op::Get),
+            1 => (0..20u8).prop_map(Op::Remove),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn matches_model(capacity in 1..10usize, ops in prop::collection::vec(op(), 0..300)) {
+            let mut cache = ArcCache::new(capacity);
+            let mut expected = Model { capacity, ..Model::default() };
+            for (t, op) in ops.into_iter().enumerate() {
+                let t = t as u32;
+                match op {
+                    Op::Put(k) => prop_assert_eq!(cache.put(k, t), expected.put(k, t)),
+                    Op::Get(k) => prop_assert_eq!(cache.get(&k).copied(), expected.get(k)),
+                    Op::Remove(k) => prop_assert_eq!(cache.remove(&k), expected.remove(k)),
+                }
+                cache.check_invariants();
+                prop_assert_eq!(cache.target(), expected.target);
+                let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+                let expected_order: Vec<_> = expected.t1.iter().chain(expected.t2.iter()).copied().collect();
+                prop_assert_eq!(order, expected_order);
+            }
+        }
+    }
+}
@@ -0,0 +1,621 @@
+// Adaptive Radix Tree (Leis et al., 2013)
+// バイト列のキーを 1 バイトずつ辿る基数木。比較ではなくキーのバイトで子を選ぶので、
+// 探索の手間はキーの数ではなくキーの長さで決まる
+//   - 内部ノードは子の数に合わせて Node4/16/48/256 を使い分け、疎な段でもメモリを無駄にしない
+//   - path compression: 子が 1 つしかない段は、内部ノードの prefix にまとめる
+//   - lazy expansion: 他のキーと分かれた時点で葉を置き、残りのバイトの段は作らない
+// 他のキーの接頭辞になっているキー (b"ab" と b"abc" など) も入れられる
+//
+// キーはバイト列として辞書順に並ぶ
+// usize のキーはビッグエンディアンのバイト列にすると大小の順が保たれるので、OrderedMap<usize, V> も実装する
+use std::{
+    convert::TryInto,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+mod node;
+
+use node::{Children, Inner, Leaf, Node};
+pub use orderedmap::{Op, OrderedMap};
+
+#[derive(Debug)]
+pub struct Art<V> {
+    root: Option<Node<V>>,
+    len: usize,
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn insert<V>(node: Node<V>, key: &[u8], depth: usize, value: V) -> (Node<V>, Option<V>) {
+    match node {
+        Node::Leaf(mut leaf) => {
+            if *leaf.key == *key {
+                let old = mem::replace(&mut leaf.value, value);
+                return (Node::Leaf(leaf), Some(old));
+            }
+            // 2 つのキーが分かれるところまでを prefix にした内部ノードを作る
+            let common = common_prefix(&leaf.key[depth..], &key[depth..]);
+            let mut inner = Inner::new(key[depth..depth + common].to_vec());
+            inner.attach(leaf, depth + common);
+            inner.attach(Leaf::new(key, value), depth + common);
+            (Node::Inner(Box::new(inner)), None)
+        }
+        Node::Inner(mut inner) => {
+            let common = common_prefix(&inner.prefix, &key[depth..]);
+            if common < inner.prefix.len() {
+                // prefix の途中で分かれるので、分かれるところに新しい内部ノードを挟む
+                let mut parent = Inner::new(inner.prefix[..common].to_vec());
+                let byte = inner.prefix[common];
+                inner.prefix.drain(..=common);
+                parent.children.add(byte, Node::Inner(inner));
+                parent.attach(Leaf::new(key, value), depth + common);
+                return (Node::Inner(Box::new(parent)), None);
+            }
+            let depth = depth + common;
+            let byte = match key.get(depth) {
+                Some(&byte) => byte,
+                None => {
+                    let old = match &mut inner.value {
+                        Some(leaf) => Some(mem::replace(&mut leaf.value, value)),
+                        None => {
+                            inner.value = Some(Leaf::new(key, value));
+                            None
+                        }
+                    };
+                    return (Node::Inner(inner), old);
+                }
+            };
+            let old = match inner.children.slot_mut(byte) {
+                Some(slot) => {
+                    let child = slot.take().expect("child is present");
+                    let (child, old) = insert(child, key, depth + 1, value);
+                    *slot = Some(child);
+                    old
+                }
+                None => {
+                    inner.children.add(byte, Node::Leaf(Leaf::new(key, value)));
+                    None
+                }
+            };
+            (Node::Inner(inner), old)
+        }
+    }
+}
+
+fn remove<V>(node: Node<V>, key: &[u8], depth: usize) -> (Option<Node<V>>, Option<V>) {
+    let mut inner = match node {
+        Node::Leaf(leaf) if *leaf.key == *key => return (None, Some(leaf.value)),
+        Node::Leaf(_) => return (Some(node), None),
+        Node::Inner(inner) => inner,
+    };
+    if !key[depth..].starts_with(&inner.prefix) {
+        return (Some(Node::Inner(inner)), None);
+    }
+    let depth = depth + inner.prefix.len();
+    let old = match key.get(depth) {
+        None => inner.value.take().map(|leaf| leaf.value),
+        Some(&byte) => {
+            let slot = match inner.children.slot_mut(byte) {
+                Some(slot) => slot,
+                None => return (Some(Node::Inner(inner)), None),
+            };
+            let child = slot.take().expect("child is present");
+            let (child, old) = remove(child, key, depth + 1);
+            match child {
+                Some(child) => *slot = Some(child),
+                None => inner.children.remove(byte),
+            }
+            old
+        }
+    };
+    if old.is_none() {
+        return (Some(Node::Inner(inner)), None);
+    }
+    (Some(collapse(inner)), old)
+}
+
+// 子とキーが合わせて 1 つになった内部ノードを、残った 1 つに置き換える
+fn collapse<V>(mut inner: Box<Inner<V>>) -> Node<V> {
+    if inner.entries() > 1 {
+        return Node::Inner(inner);
+    }
+    if let Some(leaf) = inner.value.take() {
+        return Node::Leaf(leaf);
+    }
+    match inner.children.take_only() {
+        // 葉は自分のキーを全て持っているので、そのまま上げられる
+        (_, Node::Leaf(leaf)) => Node::Leaf(leaf),
+        (byte, Node::Inner(mut child)) => {
+            inner.prefix.push(byte);
+            inner.prefix.append(&mut child.prefix);
+            child.prefix = mem::take(&mut inner.prefix);
+            Node::Inner(child)
+        }
+    }
+}
+
+impl<V> Art<V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = self.root.as_ref()?;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return (*leaf.key == *key).then_some(&leaf.value),
+                Node::Inner(inner) => {
+                    if !key[depth..].starts_with(&inner.prefix) {
+                        return None;
+                    }
+                    depth += inner.prefix.len();
+                    let byte = match key.get(depth) {
+                        Some(&byte) => byte,
+                        None => return inner.value.as_ref().map(|leaf| &leaf.value),
+                    };
+                    node = inner.children.find(byte)?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let mut node = self.root.as_mut()?;
+        let mut depth = 0;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return (*leaf.key == *key).then_some(&mut leaf.value),
+                Node::Inner(inner) => {
+                    if !key[depth..].starts_with(&inner.prefix) {
+                        return None;
+                    }
+                    depth += inner.prefix.len();
+                    let byte = match key.get(depth) {
+                        Some(&byte) => byte,
+                        None => return inner.value.as_mut().map(|leaf| &mut leaf.value),
+                    };
+                    node = inner.children.slot_mut(byte)?.as_mut()?;
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let (root, old) = match self.root.take() {
+            Some(root) => insert(root, key, 0, value),
+            None => (Node::Leaf(Leaf::new(key, value)), None),
+        };
+        self.root = Some(root);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let (root, old) = remove(self.root.take()?, key, 0);
+        self.root = root;
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    // キーの辞書順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: self.root.iter().map(Cursor::Node).collect(),
+            max_key: None,
+        }
+    }
+
+    // prefix で始まるキーを辞書順に返す
+    pub fn prefix(&self, prefix: &[u8]) -> Iter<'_, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        let mut node = match self.root.as_ref() {
+            Some(root) => root,
+            None => return iter,
+        };
+        let mut depth = 0;
+        loop {
+            let inner = match node {
+                Node::Leaf(leaf) => {
+                    if leaf.key.starts_with(prefix) {
+                        iter.stack.push(Cursor::Node(node));
+                    }
+                    return iter;
+                }
+                Node::Inner(inner) => inner,
+            };
+            let rest = &prefix[depth..];
+            let common = common_prefix(&inner.prefix, rest);
+            if common == rest.len() {
+                // prefix を使い切ったので、この部分木のキーは全て prefix で始まる
+                iter.stack.push(Cursor::Node(node));
+                return iter;
+            }
+            if common < inner.prefix.len() {
+                return iter;
+            }
+            depth += common;
+            node = match inner.children.find(prefix[depth]) {
+                Some(child) => child,
+                None => return iter,
+            };
+            depth += 1;
+        }
+    }
+
+    // min_key <= key <= max_key のエントリを辞書順に返す
+    pub fn range(&self, min_key: &[u8], max_key: &[u8]) -> Iter<'_, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key.into()),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        let mut node = match self.root.as_ref() {
+            Some(root) => root,
+            None => return iter,
+        };
+        // min_key 以上のキーだけを辿れるように積む
+        let mut depth = 0;
+        loop {
+            let inner = match node {
+                Node::Leaf(leaf) => {
+                    if *leaf.key >= *min_key {
+                        iter.stack.push(Cursor::Node(node));
+                    }
+                    return iter;
+                }
+                Node::Inner(inner) => inner,
+            };
+            let rest = &min_key[depth..];
+            let common = common_prefix(&inner.prefix, rest);
+            if common == rest.len() {
+                // この部分木のキーは全て min_key で始まる
+                iter.stack.push(Cursor::Node(node));
+                return iter;
+            }
+            if common < inner.prefix.len() {
+                // prefix の途中で分かれたバイトの大小で、部分木全体の大小が決まる
+                if inner.prefix[common] > rest[common] {
+                    iter.stack.push(Cursor::Node(node));
+                }
+                return iter;
+            }
+            // ちょうど prefix で終わるキーは min_key より小さいので飛ばす
+            depth += common;
+            let byte = min_key[depth];
+            let after = inner.children.position_after(byte);
+            iter.stack.push(Cursor::Children(&inner.children, after));
+            node = match inner.children.find(byte) {
+                Some(child) => child,
+                None => return iter,
+            };
+            depth += 1;
+        }
+    }
+
+    // ノードの表現、子の数、prefix とキーの対応、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        // 部分木のキーの数を返す。path は root からこのノードまでに辿ったバイト列
+        fn check<V>(node: &Node<V>, path: &mut Vec<u8>) -> usize {
+            let inner = match node {
+                Node::Leaf(leaf) => {
+                    assert!(
+                        leaf.key.starts_with(path),
+                        "leaf key does not match its path"
+                    );
+                    return 1;
+                }
+                Node::Inner(inner) => inner,
+            };
+            assert!(inner.entries() > 1, "inner node has a single entry");
+            inner.children.check();
+            let depth = path.len();
+            path.extend_from_slice(&inner.prefix);
+            let mut count = 0;
+            if let Some(leaf) = &inner.value {
+                assert_eq!(&*leaf.key, &path[..], "value key does not end at its node");
+                count += 1;
+            }
+            let mut pos = 0;
+            while let Some((next, byte, child)) = inner.children.next_from(pos) {
+                path.push(byte);
+                count += check(child, path);
+                path.pop();
+                pos = next;
+            }
+            path.truncate(depth);
+            count
+        }
+        let count = self
+            .root
+            .as_ref()
+            .map_or(0, |root| check(root, &mut Vec::new()));
+        assert_eq!(self.len, count, "len is stale");
+    }
+}
+
+impl<V> Default for Art<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Cursor<'a, V> {
+    // まだ開いていない部分木
+    Node(&'a Node<V>),
+    // 内部ノードの子のうち、pos 以降をまだ返していない
+    Children(&'a Children<V>, usize),
+}
+
+pub struct Iter<'a, V> {
+    stack: Vec<Cursor<'a, V>>,
+    max_key: Option<Box<[u8]>>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a [u8], &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf = loop {
+            match self.stack.pop()? {
+                Cursor::Node(Node::Leaf(leaf)) => break leaf,
+                Cursor::Node(Node::Inner(inner)) => {
+                    // ちょうど prefix で終わるキーは、子のどのキーよりも小さい
+                    self.stack.push(Cursor::Children(&inner.children, 0));
+                    if let Some(leaf) = &inner.value {
+                        break leaf;
+                    }
+                }
+                Cursor::Children(children, pos) => {
+                    if let Some((next, _, child)) = children.next_from(pos) {
+                        self.stack.push(Cursor::Children(children, next));
+                        self.stack.push(Cursor::Node(child));
+                    }
+                }
+            }
+        };
+        if self.max_key.as_ref().is_some_and(|max| *leaf.key > **max) {
+            self.stack.clear();
+            return None;
+        }
+        Some((&leaf.key, &leaf.value))
+    }
+}
+
+impl<'a, V> FusedIterator for Iter<'a, V> {}
+
+impl<'a, V> IntoIterator for &'a Art<V> {
+    type Item = (&'a [u8], &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<K: AsRef<[u8]>, V> FromIterator<(K, V)> for Art<V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key.as_ref(), value);
+        }
+        tree
+    }
+}
+
+// OrderedMap<usize, V> の iter。キーのバイト列を usize に戻す
+pub struct UsizeIter<'a, V>(Iter<'a, V>);
+
+impl<'a, V> Iterator for UsizeIter<'a, V> {
+    type Item = (usize, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.0.next()?;
+        let key = key
+            .try_into()
+            .expect("usize keys have the same length as usize");
+        Some((usize::from_be_bytes(key), value))
+    }
+}
+
+impl<'a, V> FusedIterator for UsizeIter<'a, V> {}
+
+impl<V> OrderedMap<usize, V> for Art<V> {
+    type Iter<'a>
+        = UsizeIter<'a, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: usize, value: V) -> Option<V> {
+        Art::insert(self, &key.to_be_bytes(), value)
+    }
+
+    fn get(&self, key: usize) -> Option<&V> {
+        Art::get(self, &key.to_be_bytes())
+    }
+
+    fn remove(&mut self, key: usize) -> Option<V> {
+        Art::remove(self, &key.to_be_bytes())
+    }
+
+    fn iter(&self) -> UsizeIter<'_, V> {
+        UsizeIter(Art::iter(self))
+    }
+
+    fn range(&self, min_key: usize, max_key: usize) -> Vec<(usize, &V)> {
+        UsizeIter(Art::range(
+            self,
+            &min_key.to_be_bytes(),
+            &max_key.to_be_bytes(),
+        ))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = Art::new();
+        assert_eq!(t.get(b"a"), None);
+        assert_eq!(t.remove(b"a"), None);
+        // 接頭辞になっているキーと、空のキーを含む
+        let keys: [&[u8]; 7] = [
+            b"romane", b"romanus", b"romulus", b"rubens", b"ruber", b"rom", b"",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(t.insert(key, i), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(b"rom", 50), Some(5));
+        assert_eq!(t.get(b"rom"), Some(&50));
+        assert_eq!(t.get(b"ro"), None);
+        assert_eq!(t.get(b"romanes"), None);
+        *t.get_mut(b"").unwrap() = 60;
+        assert_eq!(t.len(), 7);
+        for key in [&b"rom"[..], b"romane", b""] {
+            assert!(t.remove(key).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(b"rom"), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k.to_vec(), *v)).collect();
+        assert_eq!(
+            all,
+            vec![
+                (b"romanus".to_vec(), 1),
+                (b"romulus".to_vec(), 2),
+                (b"rubens".to_vec(), 3),
+                (b"ruber".to_vec(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn grows_and_shrinks_nodes() {
+        // 1 つの段に 256 個の子を付けてから減らし、Node4 から Node256 までを往復する
+        let mut t = Art::new();
+        for byte in 0..=255u8 {
+            t.insert(&[b'k', byte], byte);
+            t.check_invariants();
+        }
+        assert_eq!(t.prefix(b"k").count(), 256);
+        for byte in (0..=255u8).rev() {
+            assert_eq!(t.remove(&[b'k', byte]), Some(byte));
+            t.check_invariants();
+            assert_eq!(t.get(&[b'k', byte / 2]), (byte > 0).then_some(&(byte / 2)));
+        }
+        assert!(t.is_empty());
+    }
+
+    #[test]
+    fn prefix_scan() {
+        let t: Art<_> = ["app", "apple", "applet", "apply", "apt", "banana", "ap"]
+            .iter()
+            .map(|k| (k, k.len()))
+            .collect();
+        let keys = |iter: Iter<'_, usize>| -> Vec<String> {
+            iter.map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+                .collect()
+        };
+        assert_eq!(keys(t.prefix(b"appl")), ["apple", "applet", "apply"]);
+        assert_eq!(keys(t.prefix(b"app")), ["app", "apple", "applet", "apply"]);
+        assert_eq!(keys(t.prefix(b"applet")), ["applet"]);
+        assert_eq!(keys(t.prefix(b"applets")), Vec::<String>::new());
+        assert_eq!(keys(t.prefix(b"b")), ["banana"]);
+        assert_eq!(keys(t.prefix(b"c")), Vec::<String>::new());
+        assert_eq!(t.prefix(b"").count(), 7);
+        assert_eq!(
+            keys(t.range(b"apple", b"apt")),
+            ["apple", "applet", "apply", "apt"]
+        );
+        assert_eq!(keys(t.range(b"apq", b"b")), ["apt"]);
+    }
+
+    #[test]
+    fn usize_keys_keep_order() {
+        let mut t = Art::new();
+        for k in [256usize, 1, 65536, 255, 0, usize::MAX] {
+            OrderedMap::insert(&mut t, k, k);
+        }
+        let keys: Vec<_> = OrderedMap::iter(&t).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![0, 1, 255, 256, 65536, usize::MAX]);
+        assert_eq!(
+            OrderedMap::range(&t, 2, 65536),
+            vec![(255, &255), (256, &256), (65536, &65536)]
+        );
+    }
+
+    // 共通の接頭辞や、接頭辞になっているキーができやすいように、短くて文字の少ないキーを作る
+    fn key() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(0..4u8, 0..6)
+    }
+
+    proptest! {
+        // バイト列のキーと prefix は conformance で確かめられないので、ここで BTreeMap と比べる
+        #[test]
+        fn byte_keys_same_as_btree_map_random(
+            ops in prop::collection::vec((0..5u8, key(), key(), 0..100usize), 0..300),
+        ) {
+            let mut tree = Art::new();
+            let mut expected = BTreeMap::new();
+            for (op, a, b, value) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(tree.insert(&a, value), expected.insert(a, value)),
+                    2 => prop_assert_eq!(tree.remove(&a), expected.remove(&a)),
+                    3 => {
+                        prop_assert_eq!(tree.get(&a), expected.get(&a));
+                        let actual: Vec<_> = tree.prefix(&a).collect();
+                        let wanted: Vec<_> = expected
+                            .iter()
+                            .filter(|(k, _)| k.starts_with(&a))
+                            .map(|(k, v)| (&k[..], v))
+                            .collect();
+                        prop_assert_eq!(actual, wanted);
+                    }
+                    _ => {
+                        let actual: Vec<_> = tree.range(&a, &b).collect();
+                        let wanted: Vec<_> = expected
+                            .iter()
+                            .filter(|(k, _)| a <= **k && **k <= b)
+                            .map(|(k, v)| (&k[..], v))
+                            .collect();
+                        prop_assert_eq!(actual, wanted);
+                    }
+                }
+                tree.check_invariants();
+                prop_assert_eq!(tree.len(), expected.len());
+            }
+            prop_assert!(tree.iter().eq(expected.iter().map(|(k, v)| (&k[..], v))));
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// ART のノード
+// 内部ノードは子の数に応じて 4 種類の表現を使い分ける
+//   Node4, Node16: キーのバイトを昇順に並べた配列と、同じ位置の子
+//   Node48       : バイトから子の位置への 256 要素の索引と、48 個の子
+//   Node256      : バイトで直接引く 256 個の子
+// 子が増えたら大きい表現に、減ったら小さい表現に移す
+// 追加と削除が境界で繰り返されても移し替えが続かないように、縮める閾値は広げる閾値より小さくする
+use std::array;
+
+// 縮めたときに、小さい方の表現でも余裕が残る数
+const SHRINK16: usize = 3;
+const SHRINK48: usize = 12;
+const SHRINK256: usize = 40;
+
+#[derive(Debug)]
+pub(crate) struct Leaf<V> {
+    // lazy expansion のため、葉は自分のキーを全て持つ
+    pub(crate) key: Box<[u8]>,
+    pub(crate) value: V,
+}
+
+impl<V> Leaf<V> {
+    pub(crate) fn new(key: &[u8], value: V) -> Box<Self> {
+        Box::new(Self {
+            key: key.into(),
+            value,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Node<V> {
+    Leaf(Box<Leaf<V>>),
+    Inner(Box<Inner<V>>),
+}
+
+#[derive(Debug)]
+pub(crate) struct Inner<V> {
+    // path compression: 子が 1 つしかない段をまとめたバイト列
+    pub(crate) prefix: Vec<u8>,
+    // ちょうど prefix で終わるキー。他のキーの接頭辞になっているキーはここに置く
+    pub(crate) value: Option<Box<Leaf<V>>>,
+    pub(crate) children: Children<V>,
+}
+
+impl<V> Inner<V> {
+    pub(crate) fn new(prefix: Vec<u8>) -> Self {
+        Self {
+            prefix,
+            value: None,
+            children: Children::Node4(Sorted::new()),
+        }
+    }
+
+    // depth バイト目以降で分かれる葉を付ける。キーが depth で終わるなら value に置く
+    pub(crate) fn attach(&mut self, leaf: Box<Leaf<V>>, depth: usize) {
+        match leaf.key.get(depth) {
+            Some(&byte) => self.children.add(byte, Node::Leaf(leaf)),
+            None => self.value = Some(leaf),
+        }
+    }
+
+    // 子とキーの数
+    pub(crate) fn entries(&self) -> usize {
+        self.children.len() + self.value.is_some() as usize
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Sorted<V, const N: usize> {
+    len: usize,
+    keys: [u8; N],
+    children: [Option<Node<V>>; N],
+}
+
+impl<V, const N: usize> Sorted<V, N> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            keys: [0; N],
+            children: array::from_fn(|_| None),
+        }
+    }
+
+    fn position(&self, byte: u8) -> Result<usize, usize> {
+        self.keys[..self.len].binary_search(&byte)
+    }
+
+    fn insert(&mut self, byte: u8, node: Node<V>) {
+        let i = self.position(byte).expect_err("byte is already used");
+        // 末尾の空きを i に回してから埋める
+        self.keys.copy_within(i..self.len, i + 1);
+        self.children[i..=self.len].rotate_right(1);
+        self.keys[i] = byte;
+        self.children[i] = Some(node);
+        self.len += 1;
+    }
+
+    fn remove(&mut self, byte: u8) -> Option<Node<V>> {
+        let i = self.position(byte).ok()?;
+        let node = self.children[i].take();
+        self.keys.copy_within(i + 1..self.len, i);
+        self.children[i..self.len].rotate_left(1);
+        self.len -= 1;
+        node
+    }
+
+    fn drain(&mut self) -> impl Iterator<Item = (u8, Node<V>)> + '_ {
+        let len = std::mem::take(&mut self.len);
+        self.keys[..len]
+            .iter()
+            .zip(&mut self.children[..len])
+            .map(|(&byte, child)| (byte, child.take().expect("child is present")))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Node48<V> {
+    len: usize,
+    // 0 なら子がない。i + 1 なら children[i]
+    index: [u8; 256],
+    children: [Option<Node<V>>; 48],
+}
+
+impl<V> Node48<V> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            index: [0; 256],
+            children: array::from_fn(|_| None),
+        }
+    }
+
+    fn insert(&mut self, byte: u8, node: Node<V>) {
+        let slot = self
+            .children
+            .iter()
+            .position(Option::is_none)
+            .expect("node48 is not full");
+        self.children[slot] = Some(node);
+        self.index[byte as usize] = slot as u8 + 1;
+        self.len += 1;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Node256<V> {
+    len: usize,
+    children: [Option<Node<V>>; 256],
+}
+
+impl<V> Node256<V> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            children: array::from_fn(|_| None),
+        }
+    }
+}
+
+// 大きい表現は Box に入れて、Inner が一番大きい表現の大きさにならないようにする
+#[derive(Debug)]
+pub(crate) enum Children<V> {
+    Node4(Sorted<V, 4>),
+    Node16(Box<Sorted<V, 16>>),
+    Node48(Box<Node48<V>>),
+    Node256(Box<Node256<V>>),
+}
+
+impl<V> Children<V> {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Children::Node4(n) => n.len,
+            Children::Node16(n) => n.len,
+            Children::Node48(n) => n.len,
+            Children::Node256(n) => n.len,
+        }
+    }
+
+    pub(crate) fn find(&self, byte: u8) -> Option<&Node<V>> {
+        let child = match self {
+            Children::Node4(n) => &n.children[n.position(byte).ok()?],
+            Children::Node16(n) => &n.children[n.position(byte).ok()?],
+            Children::Node48(n) => match n.index[byte as usize] {
+                0 => return None,
+                slot => &n.children[slot as usize - 1],
+            },
+            Children::Node256(n) => &n.children[byte as usize],
+        };
+        child.as_ref()
+    }
+
+    // 子を一時的に取り出せるように、子の入った場所を返す
+    pub(crate) fn slot_mut(&mut self, byte: u8) -> Option<&mut Option<Node<V>>> {
+        match self {
+            Children::Node4(n) => {
+                let i = n.position(byte).ok()?;
+                Some(&mut n.children[i])
+            }
+            Children::Node16(n) => {
+                let i = n.position(byte).ok()?;
+                Some(&mut n.children[i])
+            }
+            Children::Node48(n) => match n.index[byte as usize] {
+                0 => None,
+                slot => Some(&mut n.children[slot as usize - 1]),
+            },
+            Children::Node256(n) => {
+                let slot = &mut n.children[byte as usize];
+                slot.is_some().then_some(slot)
+            }
+        }
+    }
+
+    pub(crate) fn add(&mut self, byte: u8, node: Node<V>) {
+        self.grow();
+        match self {
+            Children::Node4(n) => n.insert(byte, node),
+            Children::Node16(n) => n.insert(byte, node),
+            Children::Node48(n) => n.insert(byte, node),
+            Children::Node256(n) => {
+                assert!(n.children[byte as usize].is_none(), "byte is already used");
+                n.children[byte as usize] = Some(node);
+                n.len += 1;
+            }
+        }
+    }
+
+    // byte の子を場所ごと取り除く。slot_mut で取り出した後の空の場所も取り除ける
+    // byte の子が無いときに呼んではいけない
+    pub(crate) fn remove(&mut self, byte: u8) {
+        match self {
+            Children::Node4(n) => {
+                n.remove(byte);
+            }
+            Children::Node16(n) => {
+                n.remove(byte);
+            }
+            Children::Node48(n) => {
+                let slot = std::mem::take(&mut n.index[byte as usize]);
+                if slot != 0 {
+                    n.children[slot as usize - 1] = None;
+                    n.len -= 1;
+                }
+            }
+            Children::Node256(n) => {
+                n.children[byte as usize] = None;
+                n.len -= 1;
+            }
+        }
+        self.shrink();
+    }
+
+    // 子が 1 つだけのとき、そのバイトと子を取り出す
+    pub(crate) fn take_only(&mut self) -> (u8, Node<V>) {
+        assert_eq!(self.len(), 1, "node has more than one child");
+        let (_, byte, _) = self.next_from(0).expect("node has a child");
+        let node = self
+            .slot_mut(byte)
+            .and_then(Option::take)
+            .expect("child is present");
+        self.remove(byte);
+        (byte, node)
+    }
+
+    // pos 以降で最初の子を (次の pos, バイト, 子) で返す
+    // pos は Node4, Node16 では配列の位置、Node48, Node256 ではバイト
+    pub(crate) fn next_from(&self, pos: usize) -> Option<(usize, u8, &Node<V>)> {
+        match self {
+            Children::Node4(n) => sorted_next(n, pos),
+            Children::Node16(n) => sorted_next(n, pos),
+            Children::Node48(n) => (pos..256).find_map(|byte| match n.index[byte] {
+                0 => None,
+                slot => Some((
+                    byte + 1,
+                    byte as u8,
+                    n.children[slot as usize - 1].as_ref()?,
+                )),
+            }),
+            Children::Node256(n) => {
+                (pos..256).find_map(|byte| Some((byte + 1, byte as u8, n.children[byte].as_ref()?)))
+            }
+        }
+    }
+
+    // byte より大きいバイトの子だけを返す next_from の pos
+    pub(crate) fn position_after(&self, byte: u8) -> usize {
+        match self {
+            Children::Node4(n) => n.keys[..n.len].partition_point(|&k| k <= byte),
+            Children::Node16(n) => n.keys[..n.len].partition_point(|&k| k <= byte),
+            Children::Node48(_) | Children::Node256(_) => byte as usize + 1,
+        }
+    }
+
+    // 満杯なら 1 つ大きい表現に移す
+    fn grow(&mut self) {
+        let grown = match self {
+            Children::Node4(n) if n.len == 4 => {
+                let mut grown = Box::new(Sorted::new());
+                for (byte, child) in n.drain() {
+                    grown.insert(byte, child);
+                }
+                Children::Node16(grown)
+            }
+            Children::Node16(n) if n.len == 16 => {
+                let mut grown = Box::new(Node48::new());
+                for (byte, child) in n.drain() {
+                    grown.insert(byte, child);
+                }
+                Children::Node48(grown)
+            }
+            Children::Node48(n) if n.len == 48 => {
+                let mut grown = Box::new(Node256::new());
+                for byte in 0..256 {
+                    if let Some(child) = take48(n, byte) {
+                        grown.children[byte] = Some(child);
+                    }
+                }
+                grown.len = 48;
+                Children::Node256(grown)
+            }
+            _ => return,
+        };
+        *self = grown;
+    }
+
+    // 子が減ったら 1 つ小さい表現に移す
+    fn shrink(&mut self) {
+        let shrunk = match self {
+            Children::Node16(n) if n.len <= SHRINK16 => {
+                let mut shrunk = Sorted::new();
+                for (byte, child) in n.drain() {
+                    shrunk.insert(byte, child);
+                }
+                Children::Node4(shrunk)
+            }
+            Children::Node48(n) if n.len <= SHRINK48 => {
+                let mut shrunk = Box::new(Sorted::new());
+                for byte in 0..256 {
+                    if let Some(child) = take48(n, byte) {
+                        shrunk.insert(byte as u8, child);
+                    }
+                }
+                Children::Node16(shrunk)
+            }
+            Children::Node256(n) if n.len <= SHRINK256 => {
+                let mut shrunk = Box::new(Node48::new());
+                for (byte, child) in n.children.iter_mut().enumerate() {
+                    if let Some(child) = child.take() {
+                        shrunk.insert(byte as u8, child);
+                    }
+                }
+                Children::Node48(shrunk)
+            }
+            _ => return,
+        };
+        *self = shrunk;
+    }
+
+    // 表現ごとの子の数の範囲と、索引の整合性を確かめる
+    pub(crate) fn check(&self) {
+        let len = self.len();
+        let (kind, range) = match self {
+            Children::Node4(n) => {
+                check_sorted(n);
+                ("node4", 1..=4)
+            }
+            Children::Node16(n) => {
+                check_sorted(n);
+                ("node16", SHRINK16 + 1..=16)
+            }
+            Children::Node48(n) => {
+                let used: Vec<_> = n.index.iter().filter(|&&slot| slot != 0).collect();
+                assert_eq!(used.len(), len, "node48 index is stale");
+                for &&slot in &used {
+                    assert!(
+                        n.children[slot as usize - 1].is_some(),
+                        "node48 index points to an empty slot"
+                    );
+                }
+                assert_eq!(
+                    n.children.iter().filter(|c| c.is_some()).count(),
+                    len,
+                    "node48 has unindexed children"
+                );
+                ("node48", SHRINK48 + 1..=48)
+            }
+            Children::Node256(n) => {
+                assert_eq!(
+                    n.children.iter().filter(|c| c.is_some()).count(),
+                    len,
+                    "node256 len is stale"
+                );
+                ("node256", SHRINK256 + 1..=256)
+            }
+        };
+        assert!(range.contains(&len), "{} has {} children", kind, len);
+    }
+}
+
+fn sorted_next<V, const N: usize>(n: &Sorted<V, N>, pos: usize) -> Option<(usize, u8, &Node<V>)> {
+    if pos >= n.len {
+        return None;
+    }
+    let child = n.children[pos].as_ref()?;
+    Some((pos + 1, n.keys[pos], child))
+}
+
+fn take48<V>(n: &mut Node48<V>, byte: usize) -> Option<Node<V>> {
+    match std::mem::take(&mut n.index[byte]) {
+        0 => None,
+        slot => {
+            n.len -= 1;
+            n.children[slot as usize - 1].take()
+        }
+    }
+}
+
+fn check_sorted<V, const N: usize>(n: &Sorted<V, N>) {
+    assert!(
+        n.keys[..n.len].windows(2).all(|w| w[0] < w[1]),
+        "keys are out of order"
+    );
+    assert!(
+        n.children[..n.len].iter().all(Option::is_some),
+        "missing child"
+    );
+    assert!(
+        n.children[n.len..].iter().all(Option::is_none),
+        "child beyond len"
+    );
+}
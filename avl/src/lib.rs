@@ -0,0 +1,504 @@
+// 左右の部分木の高さの差を 1 以下に保つ二分探索木 (AVL 木)
+// insert と remove の帰りに高さを更新し、差が 2 になったノードを回転で戻す
+// 高さは常に 1.44 log2(n + 2) 以下なので、探索、追加、削除は O(log n)
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // 葉の高さが 1。空の部分木は 0
+    height: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Box<Self> {
+        Box::new(Self {
+            key,
+            value,
+            height: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    // 正なら左が高い
+    fn balance(&self) -> isize {
+        height(&self.left) as isize - height(&self.right) as isize
+    }
+
+    fn update_height(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+    }
+}
+
+fn height<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |n| n.height)
+}
+
+//     n          l
+//    / \        / \
+//   l   c  ->  a   n
+//  / \            / \
+// a   b          b   c
+fn rotate_right<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut left = node.left.take().expect("rotate_right needs a left child");
+    node.left = left.right.take();
+    node.update_height();
+    left.right = Some(node);
+    left.update_height();
+    left
+}
+
+fn rotate_left<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut right = node.right.take().expect("rotate_left needs a right child");
+    node.right = right.left.take();
+    node.update_height();
+    right.left = Some(node);
+    right.update_height();
+    right
+}
+
+// 子の高さの差が 2 以内になっている node を、差が 1 以内になるように回転する
+fn rebalance<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    node.update_height();
+    match node.balance() {
+        2 => {
+            // 左の子の右が高いときは、先に左の子を回して左左の形にする
+            if node.left.as_ref().map_or(0, |l| l.balance()) < 0 {
+                node.left = node.left.take().map(rotate_left);
+            }
+            rotate_right(node)
+        }
+        -2 => {
+            if node.right.as_ref().map_or(0, |r| r.balance()) > 0 {
+                node.right = node.right.take().map(rotate_right);
+            }
+            rotate_left(node)
+        }
+        _ => node,
+    }
+}
+
+fn insert<K: Ord, V>(link: Link<K, V>, key: K, value: V) -> (Box<Node<K, V>>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (Node::new(key, value), None),
+    };
+    let old = match key.cmp(&node.key) {
+        Ordering::Equal => Some(mem::replace(&mut node.value, value)),
+        Ordering::Less => {
+            let (left, old) = insert(node.left.take(), key, value);
+            node.left = Some(left);
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = insert(node.right.take(), key, value);
+            node.right = Some(right);
+            old
+        }
+    };
+    // 置き換えただけなら形は変わらない
+    match old {
+        Some(old) => (node, Some(old)),
+        None => (rebalance(node), None),
+    }
+}
+
+fn remove<K: Ord, V>(link: Link<K, V>, key: &K) -> (Link<K, V>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    let old = match key.cmp(&node.key) {
+        Ordering::Less => {
+            let (left, old) = remove(node.left.take(), key);
+            node.left = left;
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = remove(node.right.take(), key);
+            node.right = right;
+            old
+        }
+        Ordering::Equal => {
+            let Node {
+                value, left, right, ..
+            } = *node;
+            // 右の部分木の最小のノードを、消したノードの位置に置く
+            let replaced = match (left, right) {
+                (None, child) | (child, None) => child,
+                (left, Some(right)) => {
+                    let (right, mut min) = remove_min(right);
+                    min.left = left;
+                    min.right = right;
+                    Some(rebalance(min))
+                }
+            };
+            return (replaced, Some(value));
+        }
+    };
+    match old {
+        Some(old) => (Some(rebalance(node)), Some(old)),
+        None => (Some(node), None),
+    }
+}
+
+// 最小のノードを外して、(残りの部分木, 外したノード) を返す
+fn remove_min<K, V>(mut node: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            node.left = left;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AvlTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K: Ord + Copy, V> AvlTree<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 空なら 0
+    pub fn height(&self) -> usize {
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut current = self.root.as_deref_mut();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (root, old) = insert(self.root.take(), key, value);
+        self.root = Some(root);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let (root, old) = remove(self.root.take(), &key);
+        self.root = root;
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::with_capacity(self.height()),
+            max_key: None,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::with_capacity(self.height()),
+            max_key: Some(max_key),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // 左右の高さの差、保持している高さ、キーの順序、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Copy, V>(
+            node: &Node<K, V>,
+            (lower, upper): (Option<K>, Option<K>),
+            count: &mut usize,
+        ) -> usize {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            *count += 1;
+            let left = node
+                .left
+                .as_deref()
+                .map_or(0, |l| check(l, (lower, Some(node.key)), count));
+            let right = node
+                .right
+                .as_deref()
+                .map_or(0, |r| check(r, (Some(node.key), upper), count));
+            assert!(
+                (left as isize - right as isize).abs() <= 1,
+                "node is unbalanced"
+            );
+            assert_eq!(node.height, 1 + left.max(right), "height is stale");
+            node.height
+        }
+        let mut count = 0;
+        if let Some(root) = self.root.as_deref() {
+            check(root, (None, None), &mut count);
+        }
+        assert_eq!(count, self.len, "len does not match the nodes");
+    }
+}
+
+impl<K: Ord + Copy, V> Default for AvlTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.stack.clear();
+            return None;
+        }
+        self.push_left(node.right.as_deref());
+        Some((node.key, &node.value))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a AvlTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// 木を分解しながらキー順に返す
+pub struct IntoIter<K, V> {
+    stack: Vec<Box<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> IntoIter<K, V> {
+    fn push_left(&mut self, mut current: Link<K, V>) {
+        while let Some(mut node) = current {
+            current = node.left.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_left(node.right.take());
+        self.len -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K, V> IntoIterator for AvlTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut iter = IntoIter {
+            stack: Vec::new(),
+            len: self.len,
+        };
+        iter.push_left(self.root);
+        iter
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for AvlTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for AvlTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        AvlTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        AvlTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        AvlTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        AvlTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        AvlTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = AvlTree::new();
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.get(24), Some(&240));
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.into_iter().collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn height_is_logarithmic() {
+        // 昇順に入れても偏らない
+        let n = 100_000;
+        let mut t: AvlTree<_, _> = (0..n).map(|k| (k, k)).collect();
+        t.check_invariants();
+        let bound = 1.44 * ((n + 2) as f64).log2();
+        assert!((t.height() as f64) <= bound, "height {}", t.height());
+        for k in 0..n / 2 {
+            t.remove(k);
+        }
+        t.check_invariants();
+        assert!((t.height() as f64) <= 1.44 * ((n / 2 + 2) as f64).log2());
+    }
+
+    #[test]
+    fn range() {
+        let t: AvlTree<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        let keys: Vec<_> = t.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(t.range(10, 10).count(), 1);
+        assert_eq!(t.range(15, 9).count(), 0);
+        assert_eq!(t.range(199, 1000).count(), 0);
+        assert!(t.iter().map(|(k, _)| k).eq((0..100).map(|k| k * 2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "unbalanced")]
+    fn detects_unbalanced() {
+        let mut t: AvlTree<_, _> = (0..10).map(|k| (k, k)).collect();
+        // 回転せずに右に繋ぐ
+        let mut node = t.root.as_mut().unwrap();
+        while node.right.is_some() {
+            node = node.right.as_mut().unwrap();
+        }
+        let mut child = Node::new(100, 0);
+        child.right = Some(Node::new(101, 0));
+        child.height = 2;
+        node.right = Some(child);
+        t.len += 2;
+        t.check_invariants();
+    }
+}
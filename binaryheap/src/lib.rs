@@ -0,0 +1,283 @@
+// 配列で持つ二分ヒープ
+// 位置 i の子は 2i + 1 と 2i + 2 で、親は子より先に取り出すもの (compare で Greater か Equal) になる
+// push は末尾に置いて親と入れ替えながら上げ、pop は末尾を根に移して子と入れ替えながら下げる。どちらも O(log n)
+// from_vec は下の段から順に下げていく (Floyd の方法) ので、n log n ではなく O(n) で並ぶ
+//
+// 比べ方は型で選ぶ。既定の Max は大きいもの、Min は小さいものから取り出し、クロージャも渡せる
+use std::{cmp::Ordering, iter::FromIterator};
+
+pub use priorityqueue::{Compare, Max, Min, PriorityQueue};
+
+#[derive(Debug, Clone)]
+pub struct BinaryHeap<T, C = Max> {
+    data: Vec<T>,
+    cmp: C,
+}
+
+impl<T: Ord> BinaryHeap<T> {
+    // 大きいものから取り出す
+    pub fn new() -> Self {
+        Self::with_comparator(Max)
+    }
+
+    pub fn from_vec(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Max)
+    }
+}
+
+impl<T: Ord> BinaryHeap<T, Min> {
+    // 小さいものから取り出す
+    pub fn new_min() -> Self {
+        Self::with_comparator(Min)
+    }
+
+    pub fn from_vec_min(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Min)
+    }
+}
+
+impl<T, C: Compare<T>> BinaryHeap<T, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            data: Vec::new(),
+            cmp,
+        }
+    }
+
+    // vec をそのままヒープに並べ替える。O(n)
+    pub fn from_vec_by(vec: Vec<T>, cmp: C) -> Self {
+        let mut heap = Self { data: vec, cmp };
+        for i in (0..heap.data.len() / 2).rev() {
+            heap.sift_down(i, heap.data.len());
+        }
+        heap
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.data.push(value);
+        self.sift_up(self.data.len() - 1);
+    }
+
+    // 次に取り出す要素
+    pub fn peek(&self) -> Option<&T> {
+        self.data.first()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let last = self.data.len().checked_sub(1)?;
+        self.data.swap(0, last);
+        let top = self.data.pop();
+        self.sift_down(0, self.data.len());
+        top
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    // ヒープの並びのまま返す。順序は決めない
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    // compare で小さい順 (取り出す順の逆) に並べる。ヒープの配列の上でヒープソートするので、新たに確保しない
+    // Max なら昇順、Min なら降順になる
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        let mut end = self.data.len();
+        while end > 1 {
+            end -= 1;
+            self.data.swap(0, end);
+            self.sift_down(0, end);
+        }
+        self.data
+    }
+
+    fn before(&self, a: usize, b: usize) -> bool {
+        self.cmp.compare(&self.data[a], &self.data[b]) == Ordering::Greater
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if !self.before(i, parent) {
+                break;
+            }
+            self.data.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    // data[..end] をヒープとして、位置 i の要素を下げる
+    fn sift_down(&mut self, mut i: usize, end: usize) {
+        loop {
+            let mut top = i;
+            for child in [2 * i + 1, 2 * i + 2].iter().copied() {
+                if child < end && self.before(child, top) {
+                    top = child;
+                }
+            }
+            if top == i {
+                break;
+            }
+            self.data.swap(i, top);
+            i = top;
+        }
+    }
+
+    // 親が子より後に取り出すものになっていないか確かめる
+    pub fn check_invariants(&self) {
+        for i in 1..self.data.len() {
+            assert!(!self.before(i, (i - 1) / 2), "child is before its parent");
+        }
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for BinaryHeap<T, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for BinaryHeap<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Self::from_vec(vec)
+    }
+}
+
+impl<T, C: Compare<T> + Default> FromIterator<T> for BinaryHeap<T, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from_vec_by(iter.into_iter().collect(), C::default())
+    }
+}
+
+impl<T, C: Compare<T>> Extend<T> for BinaryHeap<T, C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, C: Compare<T>> PriorityQueue<T> for BinaryHeap<T, C> {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn push(&mut self, value: T) {
+        BinaryHeap::push(self, value)
+    }
+
+    fn peek(&self) -> Option<&T> {
+        BinaryHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        BinaryHeap::pop(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Reverse;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn order() {
+        let mut max = BinaryHeap::from_vec(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+        max.check_invariants();
+        assert_eq!(max.peek(), Some(&9));
+        max.push(7);
+        assert_eq!(max.pop(), Some(9));
+        assert_eq!(max.pop(), Some(7));
+        assert_eq!(max.len(), 7);
+        assert_eq!(max.into_sorted_vec(), vec![1, 1, 2, 3, 4, 5, 6]);
+
+        let mut min: BinaryHeap<_, Min> = vec![3, 1, 4].into_iter().collect();
+        assert_eq!(min.pop(), Some(1));
+        assert_eq!(min.into_sorted_vec(), vec![4, 3]);
+
+        // 締め切りの早いものから取り出す
+        let mut tasks =
+            BinaryHeap::with_comparator(|a: &(u32, &str), b: &(u32, &str)| b.0.cmp(&a.0));
+        tasks.extend(vec![(30, "report"), (10, "mail"), (20, "review")]);
+        tasks.check_invariants();
+        let order: Vec<_> = std::iter::from_fn(|| tasks.pop()).map(|(_, t)| t).collect();
+        assert_eq!(order, vec!["mail", "review", "report"]);
+
+        let mut empty = BinaryHeap::<i32>::new();
+        assert_eq!(empty.pop(), None);
+        assert_eq!(empty.peek(), None);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(i32),
+        Pop,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![(-50..50i32).prop_map(Op::Push), Just(Op::Pop)]
+    }
+
+    // 標準の BinaryHeap と同じ操作列を適用し、全ての結果を比べる
+    fn same_as_std<Q: PriorityQueue<i32>>(
+        mut q: Q,
+        mut expected: std::collections::BinaryHeap<i32>,
+        ops: &[Op],
+    ) -> Result<(), TestCaseError> {
+        for op in ops {
+            match op {
+                Op::Push(v) => {
+                    q.push(*v);
+                    expected.push(*v);
+                }
+                Op::Pop => prop_assert_eq!(q.pop(), expected.pop()),
+            }
+            prop_assert_eq!(q.peek(), expected.peek());
+            prop_assert_eq!(q.len(), expected.len());
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn matches_std(init in prop::collection::vec(-50..50i32, 0..100), ops in prop::collection::vec(op(), 0..200)) {
+            let heap = BinaryHeap::from_vec(init.clone());
+            heap.check_invariants();
+            let mut sorted = init.clone();
+            sorted.sort_unstable();
+            prop_assert_eq!(heap.clone().into_sorted_vec(), sorted);
+            same_as_std(heap, init.iter().copied().collect(), &ops)?;
+
+            // Min は標準の BinaryHeap に Reverse で入れたものと同じ順に取り出す
+            let mut min = BinaryHeap::from_vec_min(init.clone());
+            let mut expected: std::collections::BinaryHeap<_> = init.iter().copied().map(Reverse).collect();
+            for op in &ops {
+                match op {
+                    Op::Push(v) => {
+                        min.push(*v);
+                        expected.push(Reverse(*v));
+                    }
+                    Op::Pop => prop_assert_eq!(min.pop(), expected.pop().map(|r| r.0)),
+                }
+                prop_assert_eq!(min.peek(), expected.peek().map(|r| &r.0));
+            }
+            min.check_invariants();
+        }
+    }
+}
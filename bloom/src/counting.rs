@@ -0,0 +1,196 @@
+// counting Bloom filter
+// 各位置にビットの代わりに 4 ビットのカウンタを持ち、insert で増やし、remove で減らす
+// contains は k か所のカウンタが全て 0 でないかを見る
+//
+// カウンタが上限 (15) に達したら、それ以上は増やさず、remove でも減らさない
+// 上限に達した位置に何個入っているか分からなくなるので、減らすと入っている要素を見失う (偽陰性) ことがあるため
+// 上限のカウンタは 0 に戻らないので、偽陽性は少しずつ増えるが、偽陰性は起きない
+use std::hash::Hash;
+
+use crate::{params, positions, BloomFilter, Error, Result};
+
+// 4 ビットのカウンタの上限
+const MAX_COUNT: u8 = 15;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountingBloomFilter {
+    // 1 バイトに 2 つのカウンタを詰める。偶数番目が下位 4 ビット
+    counters: Vec<u8>,
+    // 64 の倍数。同じ大きさの BloomFilter と同じ位置を見る
+    slots: usize,
+    hashes: u32,
+    // 上限に達したカウンタの数
+    saturated: usize,
+}
+
+impl CountingBloomFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Result<Self> {
+        let (slots, hashes) = params(expected_items, false_positive_rate)?;
+        Self::with_params(slots, hashes)
+    }
+
+    // カウンタの数 (64 の倍数に切り上げる) とハッシュ関数の数を直接決めて作る
+    pub fn with_params(slots: usize, hashes: u32) -> Result<Self> {
+        if slots == 0 || hashes == 0 {
+            return Err(Error::InvalidParams {
+                bits: slots,
+                hashes,
+            });
+        }
+        let slots = slots.div_ceil(64) * 64;
+        Ok(Self {
+            counters: vec![0; slots / 2],
+            slots,
+            hashes,
+            saturated: 0,
+        })
+    }
+
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    // 上限に達して、もう減らせないカウンタの数
+    pub fn saturated(&self) -> usize {
+        self.saturated
+    }
+
+    fn count(&self, i: usize) -> u8 {
+        (self.counters[i / 2] >> (i % 2 * 4)) & 0x0f
+    }
+
+    fn set_count(&mut self, i: usize, count: u8) {
+        let shift = i % 2 * 4;
+        self.counters[i / 2] = (self.counters[i / 2] & !(0x0f << shift)) | (count << shift);
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for i in positions(item, self.slots, self.hashes) {
+            let count = self.count(i);
+            if count < MAX_COUNT {
+                self.set_count(i, count + 1);
+                if count + 1 == MAX_COUNT {
+                    self.saturated += 1;
+                }
+            }
+        }
+    }
+
+    // 入れた item を 1 つ取り除く。contains が false なら何もせず false を返す
+    // 入れていない item を取り除くと、他の要素を見失うことがある
+    pub fn remove<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        if !self.contains(item) {
+            return false;
+        }
+        for i in positions(item, self.slots, self.hashes) {
+            let count = self.count(i);
+            if count < MAX_COUNT {
+                self.set_count(i, count - 1);
+            }
+        }
+        true
+    }
+
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        positions(item, self.slots, self.hashes).all(|i| self.count(i) > 0)
+    }
+
+    // item を入れた回数の上限の見積もり。k か所のカウンタの最小値で、上限に達していれば MAX_COUNT
+    pub fn estimate_count<T: Hash + ?Sized>(&self, item: &T) -> u8 {
+        positions(item, self.slots, self.hashes)
+            .map(|i| self.count(i))
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.iter_mut().for_each(|c| *c = 0);
+        self.saturated = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counters.iter().all(|&c| c == 0)
+    }
+
+    // 0 でないカウンタのビットを立てた BloomFilter。カウンタの 1/4 の大きさで配れる
+    pub fn to_bloom(&self) -> BloomFilter {
+        let mut filter =
+            BloomFilter::with_params(self.slots, self.hashes).expect("params are valid");
+        for i in (0..self.slots).filter(|&i| self.count(i) > 0) {
+            filter.set_bit(i);
+        }
+        filter
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut filter = CountingBloomFilter::new(1000, 0.01).unwrap();
+        filter.insert("a");
+        filter.insert("a");
+        filter.insert("b");
+        assert_eq!(filter.estimate_count("a"), 2);
+        assert!(filter.remove("a"));
+        assert!(filter.contains("a"));
+        assert!(filter.remove("a"));
+        assert!(!filter.contains("a"));
+        assert!(!filter.remove("a"));
+        assert!(filter.contains("b"));
+        assert!(filter.remove("b"));
+        assert!(filter.is_empty());
+    }
+
+    // 上限に達したカウンタは減らさないので、取り除きすぎても見失わない
+    #[test]
+    fn saturated_counters_stick() {
+        let mut filter = CountingBloomFilter::with_params(64, 3).unwrap();
+        for _ in 0..20 {
+            filter.insert(&1u32);
+        }
+        assert_eq!(filter.estimate_count(&1u32), MAX_COUNT);
+        assert!(filter.saturated() > 0);
+        for _ in 0..20 {
+            assert!(filter.remove(&1u32));
+        }
+        assert!(filter.contains(&1u32));
+    }
+
+    proptest! {
+        // 入れた回数から取り除いた回数を引いて 1 以上残っている要素は必ず含み、
+        // to_bloom は同じ要素を含む
+        #[test]
+        fn no_false_negatives(ops in prop::collection::vec((any::<bool>(), 0..50u32), 0..400)) {
+            let mut filter = CountingBloomFilter::with_params(256, 3).unwrap();
+            let mut model: HashMap<u32, usize> = HashMap::new();
+            for (insert, item) in ops {
+                let count = model.entry(item).or_default();
+                if insert {
+                    filter.insert(&item);
+                    *count += 1;
+                } else if *count > 0 {
+                    prop_assert!(filter.remove(&item));
+                    *count -= 1;
+                }
+                let bloom = filter.to_bloom();
+                for (item, &count) in &model {
+                    if count > 0 {
+                        prop_assert!(filter.contains(item));
+                        prop_assert!(bloom.contains(item));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,268 @@
+// Count-Min sketch (Cormode, Muthukrishnan)
+// depth 行 width 列のカウンタを持ち、add は各行で item のハッシュが指す列のカウンタを増やす
+// estimate は各行のカウンタの最小値を返す。他の item と同じ列に当たった分だけ多めに数えるので、
+// 真の回数より小さくなることはない
+//
+// 全ての回数の和を N とすると、確率 1 - delta 以上で 真の回数 + epsilon * N 以下になる
+// そのために width = ceil(e / epsilon)、depth = ceil(ln(1 / delta)) に決める
+//
+// 同じ大きさの sketch はカウンタを足すだけでまとめられるので、shard ごとに数えて後で merge できる
+// TopK は sketch で見積もった回数の多い k 個の item を覚えておき、よく使われる key を見つける
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{positions, Error, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountMinSketch {
+    // 行ごとに width 個ずつ並べる
+    counters: Vec<u64>,
+    width: usize,
+    depth: u32,
+    total: u64,
+}
+
+impl CountMinSketch {
+    // 確率 1 - delta 以上で、見積もりの誤差が epsilon * total 以下になる大きさで作る
+    pub fn new(epsilon: f64, delta: f64) -> Result<Self> {
+        let valid = |x: f64| x > 0.0 && x < 1.0;
+        if !valid(epsilon) || !valid(delta) {
+            return Err(Error::InvalidAccuracy { epsilon, delta });
+        }
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil().max(1.0) as u32;
+        Self::with_params(width, depth)
+    }
+
+    pub fn with_params(width: usize, depth: u32) -> Result<Self> {
+        if width == 0 || depth == 0 {
+            return Err(Error::InvalidSketch { width, depth });
+        }
+        Ok(Self {
+            counters: vec![0; width * depth as usize],
+            width,
+            depth,
+            total: 0,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    // add した回数の和
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // 行ごとの、item のカウンタの位置
+    fn cells<T: Hash + ?Sized>(&self, item: &T) -> impl Iterator<Item = usize> {
+        let width = self.width;
+        positions(item, width, self.depth)
+            .enumerate()
+            .map(move |(row, column)| row * width + column)
+    }
+
+    pub fn add<T: Hash + ?Sized>(&mut self, item: &T, count: u64) {
+        for i in self.cells(item) {
+            self.counters[i] = self.counters[i].saturating_add(count);
+        }
+        self.total = self.total.saturating_add(count);
+    }
+
+    // 真の回数以上で、多くの場合 真の回数 + epsilon * total 以下
+    pub fn estimate<T: Hash + ?Sized>(&self, item: &T) -> u64 {
+        self.cells(item)
+            .map(|i| self.counters[i])
+            .min()
+            .unwrap_or(0)
+    }
+
+    // 同じ大きさの sketch を足し合わせる。両方に add してから作ったものと同じになる
+    pub fn merge(&mut self, other: &Self) -> Result<()> {
+        if (self.width, self.depth) != (other.width, other.depth) {
+            return Err(Error::SketchMismatch {
+                left: (self.width, self.depth),
+                right: (other.width, other.depth),
+            });
+        }
+        for (a, b) in self.counters.iter_mut().zip(&other.counters) {
+            *a = a.saturating_add(*b);
+        }
+        self.total = self.total.saturating_add(other.total);
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.iter_mut().for_each(|c| *c = 0);
+        self.total = 0;
+    }
+}
+
+// sketch で見積もった回数が多い k 個の item
+// 候補から外れた item の回数も sketch には残るので、後で多くなれば候補に戻る
+#[derive(Debug, Clone)]
+pub struct TopK<K> {
+    sketch: CountMinSketch,
+    k: usize,
+    // 候補と、最後に見積もった回数
+    candidates: HashMap<K, u64>,
+}
+
+impl<K: Hash + Eq + Clone> TopK<K> {
+    pub fn new(k: usize, sketch: CountMinSketch) -> Self {
+        Self {
+            sketch,
+            k,
+            candidates: HashMap::with_capacity(k + 1),
+        }
+    }
+
+    pub fn sketch(&self) -> &CountMinSketch {
+        &self.sketch
+    }
+
+    pub fn add(&mut self, key: &K, count: u64) {
+        self.sketch.add(key, count);
+        let estimate = self.sketch.estimate(key);
+        if let Some(c) = self.candidates.get_mut(key) {
+            *c = estimate;
+            return;
+        }
+        if self.candidates.len() < self.k {
+            self.candidates.insert(key.clone(), estimate);
+            return;
+        }
+        // 一番少ない候補より多ければ入れ替える
+        let min = self
+            .candidates
+            .iter()
+            .min_by_key(|(_, &c)| c)
+            .map(|(key, &c)| (key.clone(), c));
+        if let Some((min_key, min_count)) = min {
+            if estimate > min_count {
+                self.candidates.remove(&min_key);
+                self.candidates.insert(key.clone(), estimate);
+            }
+        }
+    }
+
+    // 見積もった回数の多い順
+    pub fn top(&self) -> Vec<(K, u64)> {
+        let mut top: Vec<_> = self
+            .candidates
+            .iter()
+            .map(|(key, &c)| (key.clone(), c))
+            .collect();
+        top.sort_by_key(|&(_, c)| std::cmp::Reverse(c));
+        top
+    }
+
+    // 他の shard の sketch をまとめ、その候補を入れ直す
+    pub fn merge(&mut self, other: &Self) -> Result<()> {
+        self.sketch.merge(&other.sketch)?;
+        let keys: Vec<K> = self
+            .candidates
+            .keys()
+            .chain(other.candidates.keys())
+            .cloned()
+            .collect();
+        self.candidates.clear();
+        for key in keys {
+            self.add(&key, 0);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn error_bound() {
+        let mut sketch = CountMinSketch::new(0.001, 0.01).unwrap();
+        assert_eq!((sketch.width(), sketch.depth()), (2719, 5));
+        for i in 0..10_000u64 {
+            sketch.add(&i, i % 10 + 1);
+        }
+        let bound = (0.001 * sketch.total() as f64) as u64;
+        let within = (0..10_000u64)
+            .filter(|i| {
+                let estimate = sketch.estimate(i);
+                assert!(estimate > i % 10);
+                estimate <= i % 10 + 1 + bound
+            })
+            .count();
+        assert!(within >= 9_900, "within {}", within);
+        assert_eq!(
+            CountMinSketch::new(0.0, 0.1),
+            Err(Error::InvalidAccuracy {
+                epsilon: 0.0,
+                delta: 0.1
+            })
+        );
+        assert_eq!(
+            CountMinSketch::with_params(10, 0),
+            Err(Error::InvalidSketch {
+                width: 10,
+                depth: 0
+            })
+        );
+        let mut small = CountMinSketch::with_params(10, 5).unwrap();
+        assert!(small.merge(&sketch).is_err());
+    }
+
+    #[test]
+    fn top_k() {
+        let sketch = CountMinSketch::new(0.01, 0.01).unwrap();
+        let mut a = TopK::new(3, sketch.clone());
+        let mut b = TopK::new(3, sketch);
+        // 0, 1, 2 が多く、それ以外は 1 回ずつ
+        for i in 0..3000u32 {
+            let shard = if i % 2 == 0 { &mut a } else { &mut b };
+            shard.add(&(i % 3), 1);
+            shard.add(&(i + 100), 1);
+        }
+        a.merge(&b).unwrap();
+        let mut keys: Vec<_> = a.top().into_iter().map(|(key, _)| key).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![0, 1, 2]);
+        assert!(a.top().iter().all(|&(_, c)| c >= 1000));
+    }
+
+    proptest! {
+        // 見積もりは真の回数以上で、merge は両方に add したものと同じになる
+        #[test]
+        fn never_underestimates(
+            a in prop::collection::vec((0..50u32, 1..5u64), 0..200),
+            b in prop::collection::vec((0..50u32, 1..5u64), 0..200),
+        ) {
+            let mut sa = CountMinSketch::with_params(16, 3).unwrap();
+            let mut sb = sa.clone();
+            let mut both = sa.clone();
+            let mut model: HashMap<u32, u64> = HashMap::new();
+            for &(item, count) in &a {
+                sa.add(&item, count);
+                both.add(&item, count);
+                *model.entry(item).or_default() += count;
+            }
+            for &(item, count) in &b {
+                sb.add(&item, count);
+                both.add(&item, count);
+                *model.entry(item).or_default() += count;
+            }
+            sa.merge(&sb).unwrap();
+            prop_assert_eq!(&sa, &both);
+            prop_assert_eq!(sa.total(), model.values().sum::<u64>());
+            for (item, &count) in &model {
+                prop_assert!(sa.estimate(item) >= count);
+            }
+        }
+    }
+}
@@ -0,0 +1,320 @@
+// cuckoo filter (Fan et al., 2014)
+// key そのものではなく、ハッシュから作った数ビットの指紋 (fingerprint) を、4 つずつ入る bucket に置く
+// 指紋は 2 つの bucket i1 = h(x) と i2 = i1 xor h(指紋) のどちらかに置く
+// i2 は指紋だけから i1 に戻せるので、key がなくても指紋を他方の bucket へ追い出せる (partial-key cuckoo hashing)
+//
+// insert は空きがなければ、bucket の指紋を 1 つ追い出して、その指紋のもう一方の bucket へ移すのを繰り返す
+// MAX_KICKS 回で空きに着かなければ、最後に追い出した指紋を victim として持っておき、それ以降の insert は Full にする
+//
+// 同じ偽陽性率なら counting Bloom filter より小さく、remove もできる
+// 偽陽性率はおよそ 2 * BUCKET_SIZE / 2^(指紋のビット数) になる
+use std::hash::Hash;
+
+use crate::{hash_item, mix, Error, Result};
+
+const BUCKET_SIZE: usize = 4;
+const MAX_KICKS: usize = 500;
+// 空きを表す指紋
+const EMPTY: u16 = 0;
+// 全ての bucket を埋めることはできないので、この割合まで入れる見込みで大きさを決める
+const TARGET_LOAD: f64 = 0.95;
+
+// capacity 個入れて偽陽性率が false_positive_rate 以下になる大きさ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sizing {
+    pub buckets: usize,
+    pub fingerprint_bits: u32,
+}
+
+impl Sizing {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Result<Self> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(Error::InvalidRate {
+                rate: false_positive_rate,
+            });
+        }
+        let fingerprint_bits = (2.0 * BUCKET_SIZE as f64 / false_positive_rate)
+            .log2()
+            .ceil()
+            .clamp(1.0, 16.0) as u32;
+        let buckets = ((capacity.max(1) as f64 / (BUCKET_SIZE as f64 * TARGET_LOAD)).ceil()
+            as usize)
+            .next_power_of_two();
+        Ok(Self {
+            buckets,
+            fingerprint_bits,
+        })
+    }
+
+    // 指紋を詰めて持ったときのバイト数
+    pub fn bytes(&self) -> usize {
+        (self.buckets * BUCKET_SIZE * self.fingerprint_bits as usize).div_ceil(8)
+    }
+
+    // 見込みの偽陽性率
+    pub fn false_positive_rate(&self) -> f64 {
+        2.0 * BUCKET_SIZE as f64 / 2f64.powi(self.fingerprint_bits as i32)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CuckooFilter {
+    // buckets * BUCKET_SIZE 個の指紋。説明を簡単にするため、ビット数に関わらず u16 で持つ
+    slots: Vec<u16>,
+    buckets: usize,
+    fingerprint_mask: u16,
+    len: usize,
+    // 置けなかった指紋と、その bucket の 1 つ
+    victim: Option<(usize, u16)>,
+    // 追い出す指紋を選ぶ乱数 (xorshift)
+    rng: u64,
+}
+
+impl CuckooFilter {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Result<Self> {
+        Ok(Self::with_sizing(Sizing::new(
+            capacity,
+            false_positive_rate,
+        )?))
+    }
+
+    pub fn with_sizing(sizing: Sizing) -> Self {
+        let buckets = sizing.buckets.next_power_of_two();
+        let bits = sizing.fingerprint_bits.clamp(1, 16);
+        Self {
+            slots: vec![EMPTY; buckets * BUCKET_SIZE],
+            buckets,
+            fingerprint_mask: (((1u32 << bits) - 1) as u16),
+            len: 0,
+            victim: None,
+            rng: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn load_factor(&self) -> f64 {
+        self.len as f64 / self.slots.len() as f64
+    }
+
+    // 指紋と 1 つ目の bucket
+    fn locate<T: Hash + ?Sized>(&self, item: &T) -> (u16, usize) {
+        let hash = hash_item(item);
+        let fingerprint = match (hash >> 48) as u16 & self.fingerprint_mask {
+            EMPTY => 1,
+            fingerprint => fingerprint,
+        };
+        (fingerprint, hash as usize & (self.buckets - 1))
+    }
+
+    // もう一方の bucket。2 回行うと元に戻る
+    fn alternate(&self, bucket: usize, fingerprint: u16) -> usize {
+        bucket ^ (mix(fingerprint as u64) as usize & (self.buckets - 1))
+    }
+
+    fn bucket(&self, bucket: usize) -> &[u16] {
+        &self.slots[bucket * BUCKET_SIZE..(bucket + 1) * BUCKET_SIZE]
+    }
+
+    fn bucket_mut(&mut self, bucket: usize) -> &mut [u16] {
+        &mut self.slots[bucket * BUCKET_SIZE..(bucket + 1) * BUCKET_SIZE]
+    }
+
+    fn put(&mut self, bucket: usize, fingerprint: u16) -> bool {
+        match self.bucket_mut(bucket).iter_mut().find(|f| **f == EMPTY) {
+            Some(slot) => {
+                *slot = fingerprint;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    // 置けなくなっていれば Full を返す。同じ item は 2 * BUCKET_SIZE 回まで入れられる
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) -> Result<()> {
+        if self.victim.is_some() {
+            return Err(Error::Full);
+        }
+        let (mut fingerprint, i1) = self.locate(item);
+        let i2 = self.alternate(i1, fingerprint);
+        self.len += 1;
+        if self.put(i1, fingerprint) || self.put(i2, fingerprint) {
+            return Ok(());
+        }
+        let mut bucket = if self.next_random() & 1 == 0 { i1 } else { i2 };
+        for _ in 0..MAX_KICKS {
+            let slot = self.next_random() as usize % BUCKET_SIZE;
+            std::mem::swap(&mut self.bucket_mut(bucket)[slot], &mut fingerprint);
+            bucket = self.alternate(bucket, fingerprint);
+            if self.put(bucket, fingerprint) {
+                return Ok(());
+            }
+        }
+        // item の指紋は置けているので Ok を返し、追い出された指紋を victim に残す
+        self.victim = Some((bucket, fingerprint));
+        Ok(())
+    }
+
+    // false なら item は入っていない。true なら入っているか、偽陽性
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        let (fingerprint, i1) = self.locate(item);
+        let i2 = self.alternate(i1, fingerprint);
+        self.bucket(i1).contains(&fingerprint)
+            || self.bucket(i2).contains(&fingerprint)
+            || self.victim.is_some_and(|(bucket, victim)| {
+                victim == fingerprint && (bucket == i1 || bucket == i2)
+            })
+    }
+
+    // 入れた item を 1 つ取り除く。見つからなければ false
+    // 入れていない item を取り除くと、同じ指紋を持つ他の要素を見失うことがある
+    pub fn remove<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        let (fingerprint, i1) = self.locate(item);
+        let i2 = self.alternate(i1, fingerprint);
+        if let Some((bucket, victim)) = self.victim {
+            if victim == fingerprint && (bucket == i1 || bucket == i2) {
+                self.victim = None;
+                self.len -= 1;
+                return true;
+            }
+        }
+        for bucket in [i1, i2].iter().copied() {
+            if let Some(slot) = self
+                .bucket_mut(bucket)
+                .iter_mut()
+                .find(|f| **f == fingerprint)
+            {
+                *slot = EMPTY;
+                self.len -= 1;
+                // 空きができたので victim を戻す
+                if let Some((bucket, victim)) = self.victim.take() {
+                    let other = self.alternate(bucket, victim);
+                    if !self.put(bucket, victim) && !self.put(other, victim) {
+                        self.victim = Some((bucket, victim));
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|f| *f = EMPTY);
+        self.victim = None;
+        self.len = 0;
+    }
+
+    // 指紋の数と len が合うか確かめる
+    pub fn check_invariants(&self) {
+        let stored = self.slots.iter().filter(|&&f| f != EMPTY).count();
+        assert_eq!(
+            stored + self.victim.is_some() as usize,
+            self.len,
+            "len is stale"
+        );
+        assert!(
+            self.slots.iter().all(|&f| f & !self.fingerprint_mask == 0),
+            "fingerprint is wider than the mask"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn sizing() {
+        let sizing = Sizing::new(10_000, 0.01).unwrap();
+        assert_eq!(sizing.fingerprint_bits, 10);
+        assert!(sizing.buckets * BUCKET_SIZE >= 10_000);
+        assert!(sizing.false_positive_rate() <= 0.01);
+        assert!(Sizing::new(10, 0.0).is_err());
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        let mut filter = CuckooFilter::new(10_000, 0.01).unwrap();
+        for i in 0..10_000u64 {
+            filter.insert(&i).unwrap();
+        }
+        filter.check_invariants();
+        assert!((0..10_000u64).all(|i| filter.contains(&i)));
+        let false_positives = (10_000..110_000u64).filter(|i| filter.contains(i)).count();
+        let rate = false_positives as f64 / 100_000.0;
+        assert!(rate < 0.01, "rate {}", rate);
+        for i in 0..5_000u64 {
+            assert!(filter.remove(&i));
+        }
+        assert!((5_000..10_000u64).all(|i| filter.contains(&i)));
+        assert_eq!(filter.len(), 5_000);
+    }
+
+    #[test]
+    fn full() {
+        let mut filter = CuckooFilter::with_sizing(Sizing {
+            buckets: 4,
+            fingerprint_bits: 12,
+        });
+        let inserted = (0..100u32).take_while(|i| filter.insert(i).is_ok()).count();
+        // 16 か所と victim の 1 つ
+        assert!((12..=17).contains(&inserted), "inserted {}", inserted);
+        assert_eq!(filter.len(), inserted);
+        assert_eq!(filter.insert(&1000u32), Err(Error::Full));
+        filter.check_invariants();
+        assert!((0..inserted as u32).all(|i| filter.contains(&i)));
+        // 取り除いて victim の bucket が空くと、また入れられる
+        for i in 0..8u32 {
+            assert!(filter.remove(&i));
+        }
+        assert!((8..inserted as u32).all(|i| filter.contains(&i)));
+        assert!(filter.insert(&1000u32).is_ok());
+        filter.check_invariants();
+    }
+
+    proptest! {
+        // 入れた回数から取り除いた回数を引いて 1 以上残っている要素は必ず含む
+        #[test]
+        fn no_false_negatives(ops in prop::collection::vec((any::<bool>(), 0..100u32), 0..400)) {
+            let mut filter = CuckooFilter::new(512, 0.001).unwrap();
+            let mut model: HashMap<u32, usize> = HashMap::new();
+            for (insert, item) in ops {
+                let count = model.entry(item).or_default();
+                if insert {
+                    if *count < 2 {
+                        filter.insert(&item).unwrap();
+                        *count += 1;
+                    }
+                } else if *count > 0 {
+                    prop_assert!(filter.remove(&item));
+                    *count -= 1;
+                }
+                filter.check_invariants();
+                prop_assert_eq!(filter.len(), model.values().sum::<usize>());
+                for (item, &count) in &model {
+                    if count > 0 {
+                        prop_assert!(filter.contains(item));
+                    }
+                }
+            }
+        }
+    }
+}
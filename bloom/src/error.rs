@@ -0,0 +1,39 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("invalid false positive rate: {rate} (must be greater than 0 and less than 1)")]
+    InvalidRate { rate: f64 },
+    #[error(
+        "invalid parameters: {bits} bits and {hashes} hash functions (both must be at least 1)"
+    )]
+    InvalidParams { bits: usize, hashes: u32 },
+    #[error("filters have different parameters: {left:?} and {right:?} (bits, hash functions)")]
+    Mismatch {
+        left: (usize, u32),
+        right: (usize, u32),
+    },
+    #[error(
+        "invalid sketch accuracy: epsilon {epsilon}, delta {delta} (both must be greater than 0 and less than 1)"
+    )]
+    InvalidAccuracy { epsilon: f64, delta: f64 },
+    #[error("invalid sketch size: {width} columns and {depth} rows (both must be at least 1)")]
+    InvalidSketch { width: usize, depth: u32 },
+    #[error("sketches have different sizes: {left:?} and {right:?} (columns, rows)")]
+    SketchMismatch {
+        left: (usize, u32),
+        right: (usize, u32),
+    },
+    #[error("invalid precision: {precision} (must be from 4 to 16)")]
+    InvalidPrecision { precision: u8 },
+    #[error("sketches have different precisions: {left} and {right}")]
+    PrecisionMismatch { left: u8, right: u8 },
+    #[error("invalid weight: {weight} (must be positive and finite)")]
+    InvalidWeight { weight: f64 },
+    #[error("cuckoo filter is full")]
+    Full,
+    #[error("invalid filter bytes: {0}")]
+    InvalidBytes(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,276 @@
+// HyperLogLog (Flajolet et al.) と、少ないときの扱い (Heule et al., HyperLogLog++)
+// ハッシュの上位 precision ビットで 2^precision 個のレジスタの 1 つを選び、残りのビットの
+// 先頭の 0 の数 + 1 (rank) の最大値を持つ。rank の最大値が大きいほど、多くの異なる item を見たことになる
+// 見積もりは alpha * m^2 / sum(2^-register) で、相対誤差はおよそ 1.04 / sqrt(m)
+// 見積もりが 2.5m 以下で空のレジスタが残っているときは、空のレジスタの割合から求める (linear counting)
+//
+// 少ないうちは 0 でないレジスタだけを (位置, rank) の並びで持ち (sparse)、
+// その並びが全てのレジスタ (dense) より大きくなったら dense に切り替える
+//
+// 同じ precision のものは、レジスタごとに大きい方を取ればまとめられる
+use std::hash::Hash;
+
+use crate::{hash_item, Error, Result};
+
+pub const MIN_PRECISION: u8 = 4;
+pub const MAX_PRECISION: u8 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Registers {
+    // 位置の順に並べた、0 でないレジスタ
+    Sparse(Vec<(u32, u8)>),
+    Dense(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Registers,
+}
+
+impl HyperLogLog {
+    // レジスタは 2^precision 個。precision は MIN_PRECISION から MAX_PRECISION まで
+    pub fn new(precision: u8) -> Result<Self> {
+        if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+            return Err(Error::InvalidPrecision { precision });
+        }
+        Ok(Self {
+            precision,
+            registers: Registers::Sparse(Vec::new()),
+        })
+    }
+
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    fn registers(&self) -> usize {
+        1 << self.precision
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.registers, Registers::Sparse(_))
+    }
+
+    // 見積もりの標準誤差 (相対)
+    pub fn standard_error(&self) -> f64 {
+        1.04 / (self.registers() as f64).sqrt()
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        let hash = hash_item(item);
+        let index = (hash >> (64 - self.precision)) as u32;
+        // 残りのビットが全て 0 でも 64 - precision + 1 で止まるように、番兵のビットを立てる
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        self.update(index, rest.leading_zeros() as u8 + 1);
+    }
+
+    fn update(&mut self, index: u32, rank: u8) {
+        match &mut self.registers {
+            Registers::Dense(registers) => {
+                let register = &mut registers[index as usize];
+                *register = (*register).max(rank);
+            }
+            Registers::Sparse(pairs) => {
+                match pairs.binary_search_by_key(&index, |&(i, _)| i) {
+                    Ok(i) => pairs[i].1 = pairs[i].1.max(rank),
+                    Err(i) => pairs.insert(i, (index, rank)),
+                }
+                // (位置, rank) は 5 バイトとみなし、dense の m バイトを超えたら切り替える
+                if pairs.len() * 5 > self.registers() {
+                    self.densify();
+                }
+            }
+        }
+    }
+
+    fn densify(&mut self) {
+        if let Registers::Sparse(pairs) = &self.registers {
+            let mut registers = vec![0; self.registers()];
+            for &(index, rank) in pairs {
+                registers[index as usize] = rank;
+            }
+            self.registers = Registers::Dense(registers);
+        }
+    }
+
+    // 異なる item の数の見積もり
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers() as f64;
+        let (sum, zeros) = match &self.registers {
+            Registers::Sparse(pairs) => {
+                let zeros = self.registers() - pairs.len();
+                let sum: f64 = pairs
+                    .iter()
+                    .map(|&(_, rank)| 2f64.powi(-(rank as i32)))
+                    .sum();
+                (sum + zeros as f64, zeros)
+            }
+            Registers::Dense(registers) => {
+                let zeros = registers.iter().filter(|&&rank| rank == 0).count();
+                let sum = registers
+                    .iter()
+                    .map(|&rank| 2f64.powi(-(rank as i32)))
+                    .sum();
+                (sum, zeros)
+            }
+        };
+        let alpha = match self.registers() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let raw = alpha * m * m / sum;
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    // other に入れたものも入れたことにする。両方に入れて作ったものと同じ見積もりになる
+    pub fn merge(&mut self, other: &Self) -> Result<()> {
+        if self.precision != other.precision {
+            return Err(Error::PrecisionMismatch {
+                left: self.precision,
+                right: other.precision,
+            });
+        }
+        match &other.registers {
+            Registers::Sparse(pairs) => {
+                for &(index, rank) in pairs {
+                    self.update(index, rank);
+                }
+            }
+            Registers::Dense(theirs) => {
+                self.densify();
+                if let Registers::Dense(ours) = &mut self.registers {
+                    for (a, &b) in ours.iter_mut().zip(theirs) {
+                        *a = (*a).max(b);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.registers = Registers::Sparse(Vec::new());
+    }
+
+    // sparse の並びが位置の順で、rank が取りうる範囲にあるか確かめる
+    pub fn check_invariants(&self) {
+        let max_rank = 64 - self.precision + 1;
+        match &self.registers {
+            Registers::Sparse(pairs) => {
+                assert!(
+                    pairs.windows(2).all(|w| w[0].0 < w[1].0),
+                    "sparse registers are not sorted"
+                );
+                assert!(pairs
+                    .iter()
+                    .all(|&(index, rank)| (index as usize) < self.registers()
+                        && (1..=max_rank).contains(&rank)));
+            }
+            Registers::Dense(registers) => {
+                assert_eq!(registers.len(), self.registers());
+                assert!(registers.iter().all(|&rank| rank <= max_rank));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let mut hll = HyperLogLog::new(14).unwrap();
+        for i in 0..100u64 {
+            hll.insert(&i);
+            hll.insert(&i);
+        }
+        assert!(hll.is_sparse());
+        assert!((hll.estimate() - 100.0).abs() < 2.0, "{}", hll.estimate());
+        for i in 0..200_000u64 {
+            hll.insert(&i);
+        }
+        assert!(!hll.is_sparse());
+        hll.check_invariants();
+        let error = (hll.estimate() - 200_000.0).abs() / 200_000.0;
+        assert!(error < 3.0 * hll.standard_error(), "error {}", error);
+        assert_eq!(
+            HyperLogLog::new(17),
+            Err(Error::InvalidPrecision { precision: 17 })
+        );
+    }
+
+    // 少ないうちは linear counting になる。誤差の上限は merge_matches_union と同じ
+    #[test]
+    fn small_counts() {
+        for start in 0..20u64 {
+            let mut hll = HyperLogLog::new(8).unwrap();
+            for n in 1..=50u64 {
+                hll.insert(&(start * 1000 + n));
+                let bound = 4.0 * hll.standard_error() * n as f64 + 1.0;
+                let error = (hll.estimate() - n as f64).abs();
+                assert!(error <= bound, "n {} estimate {}", n, hll.estimate());
+            }
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = HyperLogLog::new(12).unwrap();
+        let mut b = HyperLogLog::new(12).unwrap();
+        for i in 0..30_000u64 {
+            a.insert(&i);
+            b.insert(&(i + 20_000));
+        }
+        a.merge(&b).unwrap();
+        let error = (a.estimate() - 50_000.0).abs() / 50_000.0;
+        assert!(error < 3.0 * a.standard_error(), "error {}", error);
+        assert_eq!(
+            a.merge(&HyperLogLog::new(10).unwrap()),
+            Err(Error::PrecisionMismatch {
+                left: 12,
+                right: 10
+            })
+        );
+    }
+
+    proptest! {
+        // merge は両方に入れたものと同じで、sparse と dense は同じ見積もりになる
+        #[test]
+        fn merge_matches_union(
+            a in prop::collection::vec(any::<u32>(), 0..300),
+            b in prop::collection::vec(any::<u32>(), 0..300),
+        ) {
+            let mut ha = HyperLogLog::new(8).unwrap();
+            let mut hb = ha.clone();
+            let mut both = ha.clone();
+            a.iter().for_each(|x| { ha.insert(x); both.insert(x) });
+            b.iter().for_each(|x| { hb.insert(x); both.insert(x) });
+            ha.merge(&hb).unwrap();
+            ha.check_invariants();
+            let mut dense = both.clone();
+            dense.densify();
+            // 足す順が違うので、浮動小数点の誤差の分だけずれることがある
+            prop_assert!((ha.estimate() - both.estimate()).abs() < 1e-6);
+            prop_assert!((dense.estimate() - both.estimate()).abs() < 1e-6);
+            // 少ないうちは linear counting になる。その標準偏差はおよそ n / sqrt(2m) で、
+            // standard_error() * n (= 1.04 n / sqrt(m)) より小さいので、4 倍あれば外れることはまずない
+            let distinct = a.iter().chain(&b).collect::<HashSet<_>>().len() as f64;
+            if distinct <= 50.0 {
+                let bound = 4.0 * both.standard_error() * distinct + 1.0;
+                prop_assert!((both.estimate() - distinct).abs() <= bound, "{} {}", both.estimate(), distinct);
+            }
+        }
+    }
+}
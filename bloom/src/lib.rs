@@ -0,0 +1,308 @@
+// Bloom filter
+// m ビットの配列と k 個のハッシュ関数で集合を表す。insert は k か所のビットを立て、
+// contains は k か所が全て立っているかを見る。入れたものは必ず見つかり (偽陰性がない)、
+// 入れていないものを誤って含むと答える (偽陽性) 割合は、要素数 n に対して (1 - e^(-kn/m))^k になる
+//
+// 見込みの要素数 n と偽陽性率 p から、m = -n ln p / (ln 2)^2、k = (m / n) ln 2 に決める
+// k 個のハッシュは、1 つの 64 ビットのハッシュから h1 + i * h2 で作る (Kirsch, Mitzenmacher)
+//
+// ハッシュは std の RandomState ではなく固定の FNV-1a を使うので、to_bytes で書き出したものを
+// 別のプロセスで読み戻しても同じ位置を見る
+//
+// counting に、ビットの代わりに小さなカウンタを持ち、remove もできる版を置く
+// cuckoo に、key の指紋 (fingerprint) を cuckoo hashing で置く、remove ができて省メモリな filter を置く
+// countmin に、同じハッシュで item ごとの回数を見積もる Count-Min sketch を置く
+// hyperloglog に、異なる item の数を見積もる HyperLogLog を置く
+// reservoir に、流れから決まった数の標本を選ぶ reservoir sampling を置く
+use std::{
+    convert::TryInto,
+    hash::{Hash, Hasher},
+};
+
+pub mod counting;
+pub mod countmin;
+pub mod cuckoo;
+mod error;
+pub mod hyperloglog;
+pub mod reservoir;
+
+pub use error::{Error, Result};
+
+const MAGIC: &[u8; 4] = b"BLM1";
+// MAGIC、ビット数 (u64)、ハッシュ関数の数 (u32)
+const HEADER_LEN: usize = 4 + 8 + 4;
+
+// FNV-1a (64 ビット)
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+// FNV は下位ビットの混ざりが弱いので、もう一度かき混ぜる (splitmix64 の最後の段)
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// item の 64 ビットのハッシュ。プロセスをまたいで同じ値になる
+fn hash_item<T: Hash + ?Sized>(item: &T) -> u64 {
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    item.hash(&mut hasher);
+    mix(hasher.finish())
+}
+
+// item を調べる hashes か所の位置。位置は 0 から slots 未満
+fn positions<T: Hash + ?Sized>(item: &T, slots: usize, hashes: u32) -> impl Iterator<Item = usize> {
+    let h1 = hash_item(item);
+    // h2 を奇数にして、位置の数 (64 の倍数) と互いに素にする
+    let h2 = mix(h1) | 1;
+    let slots = slots as u64;
+    (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % slots) as usize)
+}
+
+// 見込みの要素数と偽陽性率から、位置の数とハッシュ関数の数を決める
+fn params(expected_items: usize, false_positive_rate: f64) -> Result<(usize, u32)> {
+    if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+        return Err(Error::InvalidRate {
+            rate: false_positive_rate,
+        });
+    }
+    let n = expected_items.max(1) as f64;
+    let ln2 = std::f64::consts::LN_2;
+    let slots = (-n * false_positive_rate.ln() / (ln2 * ln2)).ceil() as usize;
+    let hashes = ((slots as f64 / n) * ln2).round().max(1.0) as u32;
+    Ok((slots, hashes))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    words: Vec<u64>,
+    // 64 の倍数
+    bits: usize,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    // expected_items 個入れたときの偽陽性率が false_positive_rate になる大きさで作る
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Result<Self> {
+        let (bits, hashes) = params(expected_items, false_positive_rate)?;
+        Self::with_params(bits, hashes)
+    }
+
+    // ビット数 (64 の倍数に切り上げる) とハッシュ関数の数を直接決めて作る
+    pub fn with_params(bits: usize, hashes: u32) -> Result<Self> {
+        if bits == 0 || hashes == 0 {
+            return Err(Error::InvalidParams { bits, hashes });
+        }
+        let words = bits.div_ceil(64);
+        Ok(Self {
+            words: vec![0; words],
+            bits: words * 64,
+            hashes,
+        })
+    }
+
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+
+    pub fn hashes(&self) -> u32 {
+        self.hashes
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for i in positions(item, self.bits, self.hashes) {
+            self.set_bit(i);
+        }
+    }
+
+    pub(crate) fn set_bit(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    // false なら item は入っていない。true なら入っているか、偽陽性
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        positions(item, self.bits, self.hashes).all(|i| self.words[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = 0);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    // 立っているビットの割合から見積もった、いまの偽陽性率
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let ones: u32 = self.words.iter().map(|word| word.count_ones()).sum();
+        (ones as f64 / self.bits as f64).powi(self.hashes as i32)
+    }
+
+    fn check_same_params(&self, other: &Self) -> Result<()> {
+        if (self.bits, self.hashes) == (other.bits, other.hashes) {
+            Ok(())
+        } else {
+            Err(Error::Mismatch {
+                left: (self.bits, self.hashes),
+                right: (other.bits, other.hashes),
+            })
+        }
+    }
+
+    // どちらかに入れたものを含む filter。どちらかに入れてから作ったものと同じになる
+    pub fn union(&self, other: &Self) -> Result<Self> {
+        self.check_same_params(other)?;
+        Ok(Self {
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(a, b)| a | b)
+                .collect(),
+            ..self.clone()
+        })
+    }
+
+    // 両方に入れたものを含む filter。ビットの AND なので、偽陽性率は両方に入れて作ったものより高いことがある
+    pub fn intersection(&self, other: &Self) -> Result<Self> {
+        self.check_same_params(other)?;
+        Ok(Self {
+            words: self
+                .words
+                .iter()
+                .zip(&other.words)
+                .map(|(a, b)| a & b)
+                .collect(),
+            ..self.clone()
+        })
+    }
+
+    // MAGIC、ビット数、ハッシュ関数の数、ビットの配列をリトルエンディアンで並べる
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.words.len() * 8);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.bits as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        for word in &self.words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::InvalidBytes("too short"));
+        }
+        if &bytes[..4] != MAGIC {
+            return Err(Error::InvalidBytes("bad magic"));
+        }
+        let bits = u64::from_le_bytes(bytes[4..12].try_into().unwrap()) as usize;
+        let hashes = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        let body = &bytes[HEADER_LEN..];
+        if bits == 0 || hashes == 0 || !bits.is_multiple_of(64) {
+            return Err(Error::InvalidBytes("bad parameters"));
+        }
+        if body.len() != bits / 8 {
+            return Err(Error::InvalidBytes("length does not match the bit count"));
+        }
+        let words = body
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        Ok(Self {
+            words,
+            bits,
+            hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn false_positive_rate() {
+        let mut filter = BloomFilter::new(10_000, 0.01).unwrap();
+        for i in 0..10_000u64 {
+            filter.insert(&i);
+        }
+        assert!((0..10_000u64).all(|i| filter.contains(&i)));
+        let false_positives = (10_000..110_000u64).filter(|i| filter.contains(i)).count();
+        let rate = false_positives as f64 / 100_000.0;
+        assert!(rate < 0.015, "rate {}", rate);
+        assert!((filter.estimated_false_positive_rate() - 0.01).abs() < 0.005);
+    }
+
+    #[test]
+    fn invalid_params() {
+        assert_eq!(
+            BloomFilter::new(10, 1.0),
+            Err(Error::InvalidRate { rate: 1.0 })
+        );
+        assert!(BloomFilter::new(10, f64::NAN).is_err());
+        assert_eq!(
+            BloomFilter::with_params(0, 3),
+            Err(Error::InvalidParams { bits: 0, hashes: 3 })
+        );
+        let a = BloomFilter::with_params(64, 3).unwrap();
+        let b = BloomFilter::with_params(128, 3).unwrap();
+        assert_eq!(
+            a.union(&b),
+            Err(Error::Mismatch {
+                left: (64, 3),
+                right: (128, 3)
+            })
+        );
+    }
+
+    #[test]
+    fn bytes() {
+        let mut filter = BloomFilter::new(100, 0.05).unwrap();
+        filter.insert("page 42");
+        filter.insert(&7u64);
+        let bytes = filter.to_bytes();
+        let restored = BloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(restored, filter);
+        assert!(restored.contains("page 42") && restored.contains(&7u64));
+        assert!(BloomFilter::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert_eq!(
+            BloomFilter::from_bytes(b"NOPE0000000000000000"),
+            Err(Error::InvalidBytes("bad magic"))
+        );
+    }
+
+    proptest! {
+        // 入れたものは必ず含み、union はどちらかに、intersection は両方に入れたものを必ず含む
+        #[test]
+        fn no_false_negatives(
+            a in prop::collection::hash_set(any::<u32>(), 0..200),
+            b in prop::collection::hash_set(any::<u32>(), 0..200),
+        ) {
+            let mut fa = BloomFilter::new(200, 0.01).unwrap();
+            let mut fb = BloomFilter::new(200, 0.01).unwrap();
+            let mut both = BloomFilter::new(200, 0.01).unwrap();
+            a.iter().for_each(|x| { fa.insert(x); both.insert(x) });
+            b.iter().for_each(|x| { fb.insert(x); both.insert(x) });
+            prop_assert!(a.iter().all(|x| fa.contains(x)));
+            let union = fa.union(&fb).unwrap();
+            prop_assert_eq!(&union, &both);
+            let intersection = fa.intersection(&fb).unwrap();
+            prop_assert!(a.intersection(&b).all(|x| intersection.contains(x)));
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// reservoir sampling
+// 長さの分からない流れから、capacity 個の標本を O(capacity) のメモリで選ぶ
+//
+// 重み付きの選び方 (Efraimidis, Spirakis の A-Res) で、重みを全て 1 にしたものを一様な標本とする
+// 各 item に一様乱数 u から key = u^(1 / weight) を振り、key の大きい capacity 個を残す
+// key は ln(u) / weight と同じ順になるので、小さな重みでも 0 に潰れないよう対数で持つ
+// 残す item は key の小さい順のヒープに置き、新しい key が最小より大きければ入れ替える
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use crate::{mix, Error, Result};
+
+#[derive(Debug, Clone)]
+struct Entry<T> {
+    key: f64,
+    item: T,
+}
+
+// BinaryHeap は大きい順なので、key の比較を逆にして一番小さいものを先頭にする
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.total_cmp(&self.key)
+    }
+}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+#[derive(Debug, Clone)]
+pub struct ReservoirSampler<T> {
+    capacity: usize,
+    heap: BinaryHeap<Entry<T>>,
+    // push した数
+    seen: u64,
+    // xorshift
+    rng: u64,
+}
+
+impl<T> ReservoirSampler<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_seed(capacity, 0)
+    }
+
+    // 同じ seed と同じ流れなら、同じ標本になる
+    pub fn with_seed(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity),
+            seen: 0,
+            rng: mix(seed) | 1,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    // (0, 1) の一様乱数
+    fn next_uniform(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        ((self.rng >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    // どの item も同じ確率 capacity / seen で標本に残る
+    pub fn push(&mut self, item: T) {
+        self.offer(item, 1.0);
+    }
+
+    // weight に比例した確率で選ぶ。weight は正の有限の値
+    pub fn push_weighted(&mut self, item: T, weight: f64) -> Result<()> {
+        if !(weight > 0.0 && weight.is_finite()) {
+            return Err(Error::InvalidWeight { weight });
+        }
+        self.offer(item, weight);
+        Ok(())
+    }
+
+    fn offer(&mut self, item: T, weight: f64) {
+        self.seen += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        let key = self.next_uniform().ln() / weight;
+        if self.heap.len() < self.capacity {
+            self.heap.push(Entry { key, item });
+        } else if self.heap.peek().is_some_and(|min| key > min.key) {
+            self.heap.pop();
+            self.heap.push(Entry { key, item });
+        }
+    }
+
+    // いまの標本。順番に意味はない
+    pub fn samples(&self) -> impl Iterator<Item = &T> {
+        self.heap.iter().map(|entry| &entry.item)
+    }
+
+    pub fn into_samples(self) -> Vec<T> {
+        self.heap.into_iter().map(|entry| entry.item).collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+        self.seen = 0;
+    }
+}
+
+impl<T> Extend<T> for ReservoirSampler<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn uniform() {
+        // 100 個から 10 個選ぶのを 2000 回行うと、各 item は平均 200 回選ばれる
+        let mut counts = vec![0; 100];
+        for seed in 0..2000 {
+            let mut sampler = ReservoirSampler::with_seed(10, seed);
+            sampler.extend(0..100usize);
+            assert_eq!(sampler.len(), 10);
+            sampler.samples().for_each(|&i| counts[i] += 1);
+        }
+        assert!(
+            counts.iter().all(|&c| (140..260).contains(&c)),
+            "{:?}",
+            counts
+        );
+        // 前半と後半で偏らない
+        let first: i32 = counts[..50].iter().sum();
+        assert!((first - 10_000).abs() < 500, "first half {}", first);
+    }
+
+    #[test]
+    fn weighted() {
+        let mut heavy = 0;
+        for seed in 0..1000 {
+            let mut sampler = ReservoirSampler::with_seed(1, seed);
+            for i in 0..10 {
+                let weight = if i == 3 { 10.0 } else { 1.0 };
+                sampler.push_weighted(i, weight).unwrap();
+            }
+            heavy += sampler.samples().filter(|&&i| i == 3).count();
+        }
+        // 10 / 19 の確率で選ばれる
+        assert!((450..600).contains(&heavy), "heavy {}", heavy);
+        let mut sampler = ReservoirSampler::new(1);
+        assert_eq!(
+            sampler.push_weighted(0, 0.0),
+            Err(Error::InvalidWeight { weight: 0.0 })
+        );
+        assert!(sampler.push_weighted(0, f64::NAN).is_err());
+    }
+
+    proptest! {
+        // 標本は capacity と push した数の小さい方の個数で、全て push したものから選ばれる
+        #[test]
+        fn samples_are_pushed_items(capacity in 0..20usize, n in 0..100usize, seed: u64) {
+            let mut sampler = ReservoirSampler::with_seed(capacity, seed);
+            sampler.extend(0..n);
+            prop_assert_eq!(sampler.seen(), n as u64);
+            prop_assert_eq!(sampler.len(), capacity.min(n));
+            let samples: HashSet<_> = sampler.samples().copied().collect();
+            prop_assert_eq!(samples.len(), sampler.len());
+            prop_assert!(samples.iter().all(|&i| i < n));
+        }
+    }
+}
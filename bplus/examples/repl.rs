@@ -0,0 +1,140 @@
+// BPlusTree<String> を対話的に操作する
+//   cargo run -p bplus --features jsonl --example repl -- [file.jsonl]
+// 引数のファイルがあれば JSON Lines として読み込んでから始める
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+};
+
+use bplus::{BPlusTree, Key, OrderedMap, DEFAULT_CAP};
+
+const HELP: &str = "\
+commands:
+  insert <key> <value>  同じキーがあれば値を置き換える
+  get <key>
+  delete <key>
+  range <min> <max>     min <= key <= max のエントリ
+  list                  全てのエントリをキー順に
+  len
+  print                 木の構造
+  dot <path>            Graphviz の dot 形式で書き出す
+  load <path>           JSON Lines を読み込んで追加する
+  save <path>           JSON Lines で書き出す
+  help
+  quit";
+
+fn main() {
+    let mut tree = BPlusTree::new(DEFAULT_CAP);
+    if let Some(path) = std::env::args().nth(1) {
+        match load(&mut tree, &path) {
+            Ok(count) => println!("loaded {} entries from {}", count, path),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+            None => break,
+        };
+        match run(&mut tree, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+// 1 行を実行する。quit なら false を返す
+fn run(tree: &mut BPlusTree<String>, line: &str) -> Result<bool, String> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(command) => command,
+        None => return Ok(true),
+    };
+    match command {
+        "insert" => {
+            let key = parse_key(words.next())?;
+            let value: Vec<_> = words.collect();
+            if value.is_empty() {
+                return Err("usage: insert <key> <value>".to_string());
+            }
+            match OrderedMap::insert(tree, key, value.join(" ")) {
+                Some(old) => println!("replaced {:?}", old),
+                None => println!("inserted"),
+            }
+        }
+        "get" => match tree.search(parse_key(words.next())?) {
+            Some(value) => println!("{}", value),
+            None => println!("(not found)"),
+        },
+        "delete" => match tree.remove(parse_key(words.next())?) {
+            Some(value) => println!("deleted {:?}", value),
+            None => println!("(not found)"),
+        },
+        "range" => {
+            let min_key = parse_key(words.next())?;
+            let max_key = parse_key(words.next())?;
+            for (key, value) in OrderedMap::range(tree, min_key, max_key) {
+                println!("{}: {}", key, value);
+            }
+        }
+        "list" => {
+            for (key, value) in tree.iter() {
+                println!("{}: {}", key, value);
+            }
+        }
+        "len" => println!("{}", tree.len()),
+        "print" => print!("{}", tree),
+        "dot" => {
+            let path = parse_path(words.next())?;
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            tree.to_dot(BufWriter::new(file))
+                .map_err(|e| e.to_string())?;
+            println!("wrote {}", path);
+        }
+        "load" => {
+            let path = parse_path(words.next())?;
+            let count = load(tree, path)?;
+            println!("loaded {} entries", count);
+        }
+        "save" => {
+            let path = parse_path(words.next())?;
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            let count = tree
+                .export_jsonl(BufWriter::new(file))
+                .map_err(|e| e.to_string())?;
+            println!("saved {} entries", count);
+        }
+        "help" => println!("{}", HELP),
+        "quit" | "exit" => return Ok(false),
+        _ => return Err(format!("unknown command {:?}. type help", command)),
+    }
+    Ok(true)
+}
+
+fn load(tree: &mut BPlusTree<String>, path: &str) -> Result<usize, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    tree.import_jsonl(BufReader::new(file))
+        .map_err(|e| e.to_string())
+}
+
+fn parse_key(word: Option<&str>) -> Result<Key, String> {
+    let word = word.ok_or("missing key")?;
+    word.parse().map_err(|_| format!("invalid key {:?}", word))
+}
+
+fn parse_path(word: Option<&str>) -> Result<&str, String> {
+    word.ok_or_else(|| "missing path".to_string())
+}
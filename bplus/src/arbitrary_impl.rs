@@ -0,0 +1,47 @@
+// fuzz などで使う、任意のバイト列から作った木
+use std::{fmt::Display, ops::RangeInclusive};
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{BPlusTree, Key, OrderedMap};
+
+// 小さい cap ほど分割が起きやすい
+const CAPS: RangeInclusive<usize> = 2..=64;
+
+// 同じキーは後の値で置き換える
+impl<'a, T: Arbitrary<'a> + Display> Arbitrary<'a> for BPlusTree<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut tree = Self::new(u.int_in_range(CAPS)?);
+        for entry in u.arbitrary_iter::<(Key, T)>()? {
+            let (key, value) = entry?;
+            OrderedMap::insert(&mut tree, key, value);
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn arbitrary_tree() {
+        let bytes: Vec<u8> = (0..4096).map(|i| (i * 31 % 251) as u8).collect();
+        let mut u = Unstructured::new(&bytes);
+        let tree = BPlusTree::<u16>::arbitrary(&mut u).unwrap();
+        tree.check_invariants();
+        assert!(!tree.is_empty());
+        assert!(CAPS.contains(&tree.cap));
+
+        let mut u = Unstructured::new(&bytes);
+        let (mut tree, ops) =
+            <(BPlusTree<u16>, Vec<crate::Op<Key, u16>>)>::arbitrary(&mut u).unwrap();
+        let mut expected: BTreeMap<_, _> = tree.iter().map(|(k, v)| (k, *v)).collect();
+        for op in ops {
+            assert_eq!(op.clone().apply(&mut tree), op.apply(&mut expected));
+        }
+        tree.check_invariants();
+    }
+}
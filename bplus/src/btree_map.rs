@@ -0,0 +1,114 @@
+// std::collections::BTreeMap との変換と比較
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{BPlusTree, Data, DataPair, InternalNode, Key, LeafNode, Node, NodePair};
+
+// From で作る木の cap
+pub const DEFAULT_CAP: usize = 16;
+
+impl<T: Display> BPlusTree<T> {
+    // キーの昇順に並んだエントリから、下の階層から順に木を組み立てる
+    // 一件ずつ insert するより速い
+    pub(crate) fn bulk_load(cap: usize, entries: impl IntoIterator<Item = (Key, T)>) -> Self {
+        assert!(cap > 0);
+        let mut tree = Self::new(cap);
+        let mut level = Vec::new();
+        let mut leaf = Vec::new();
+        for (key, value) in entries {
+            let data_id = tree.data.len();
+            tree.seq += 1;
+            let mut data = Data::new(data_id, value);
+            data.seq = tree.seq;
+            tree.data.push(data);
+            leaf.push(DataPair::new(key, data_id));
+            if leaf.len() == cap {
+                level.push(Node::Leaf(LeafNode {
+                    cap,
+                    data_ids: std::mem::take(&mut leaf),
+                }));
+            }
+        }
+        if !leaf.is_empty() {
+            level.push(Node::Leaf(LeafNode {
+                cap,
+                data_ids: leaf,
+            }));
+        }
+        // InternalNode は cap + 1 個まで子を持てる
+        while level.len() > 1 {
+            let mut upper = Vec::new();
+            let mut nodes = Vec::new();
+            for node in level {
+                nodes.push(NodePair::new(node.min_key().unwrap(), node));
+                if nodes.len() == cap + 1 {
+                    upper.push(Node::Internal(InternalNode {
+                        cap,
+                        nodes: std::mem::take(&mut nodes),
+                    }));
+                }
+            }
+            if !nodes.is_empty() {
+                upper.push(Node::Internal(InternalNode { cap, nodes }));
+            }
+            level = upper;
+        }
+        tree.node = level.pop();
+        tree
+    }
+}
+
+impl<T: Display> From<BTreeMap<Key, T>> for BPlusTree<T> {
+    fn from(map: BTreeMap<Key, T>) -> Self {
+        Self::bulk_load(DEFAULT_CAP, map)
+    }
+}
+
+// 同じキーが複数ある場合は、後に挿入した値が残る
+impl<T: Display> From<BPlusTree<T>> for BTreeMap<Key, T> {
+    fn from(tree: BPlusTree<T>) -> Self {
+        let mut iter = tree.iter();
+        let ids: Vec<_> = std::iter::from_fn(|| iter.next_data())
+            .map(|(key, data)| (key, data.seq, data.id))
+            .collect();
+        let mut data = tree.data;
+        let mut map = BTreeMap::new();
+        let mut seqs = BTreeMap::new();
+        for (key, seq, id) in ids {
+            let value = data[id].data.take().unwrap();
+            if seqs.get(&key).is_none_or(|s| *s < seq) {
+                seqs.insert(key, seq);
+                map.insert(key, value);
+            }
+        }
+        map
+    }
+}
+
+impl<T: Display + PartialEq> PartialEq<BTreeMap<Key, T>> for BPlusTree<T> {
+    fn eq(&self, other: &BTreeMap<Key, T>) -> bool {
+        self.iter().eq(other.iter().map(|(k, v)| (*k, v)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let map: BTreeMap<Key, i64> = (0..1000).map(|k| (k * 3, -(k as i64))).collect();
+        let mut tree = BPlusTree::from(map.clone());
+        assert!(tree == map);
+        assert_eq!(tree.search(300), Some(&-100));
+        assert_eq!(tree.search(301), None);
+        // 組み立てた木にも挿入できる
+        tree.insert(301, Data::new(0, 7));
+        assert!(tree != map);
+        let back = BTreeMap::from(tree);
+        assert_eq!(back.len(), 1001);
+        assert_eq!(back[&301], 7);
+
+        let empty = BPlusTree::<i64>::from(BTreeMap::new());
+        assert!(empty == BTreeMap::new());
+    }
+}
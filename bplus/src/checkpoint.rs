@@ -0,0 +1,78 @@
+// 木の内容をファイルに書き出し、読み込む
+// 形式は serde_impl と同じ JSON
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{BPlusTree, Result};
+
+impl<T> BPlusTree<T>
+where
+    T: Display + Serialize,
+{
+    // &self で書き出すので、書き出し中も読み込みはできる
+    // 一時ファイルに書いてから rename するので、途中で落ちても path の内容は壊れない
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = tmp_path(path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl<T> BPlusTree<T>
+where
+    T: Display + DeserializeOwned,
+{
+    pub fn open_checkpoint<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BPlusTree, Data, Error};
+
+    #[test]
+    fn checkpoint_and_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.json");
+        let mut b = BPlusTree::<String>::new(4);
+        for k in 0..100 {
+            b.insert(k, Data::new(k, format!("v{}", k)));
+        }
+        b.checkpoint(&path).unwrap();
+        // 既存のファイルは置き換える
+        b.insert(100, Data::new(100, "v100".to_string()));
+        b.checkpoint(&path).unwrap();
+
+        let restored = BPlusTree::<String>::open_checkpoint(&path).unwrap();
+        assert_eq!(restored.entries(), b.entries());
+        assert!(matches!(
+            BPlusTree::<String>::open_checkpoint(dir.path().join("missing")),
+            Err(Error::Io(_))
+        ));
+        std::fs::write(&path, b"{\"cap\":0,\"entries\":[]}").unwrap();
+        assert!(matches!(
+            BPlusTree::<String>::open_checkpoint(&path),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
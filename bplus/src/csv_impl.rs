@@ -0,0 +1,122 @@
+// CSV との変換
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{BPlusTree, Data, Key, Result};
+
+#[derive(Debug, Error)]
+pub enum CsvError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("line {line}: column {column} is missing")]
+    MissingColumn { line: u64, column: usize },
+    #[error("line {line}: invalid key {key:?}")]
+    InvalidKey { line: u64, key: String },
+    #[error("line {line}: invalid value {value:?}")]
+    InvalidValue { line: u64, value: String },
+}
+
+impl<T> BPlusTree<T>
+where
+    T: Display + FromStr,
+{
+    // 1 行目はヘッダーとして読み飛ばす
+    // key_col, value_col は 0 始まりの列番号
+    pub fn from_csv<R: Read>(
+        cap: usize,
+        reader: R,
+        key_col: usize,
+        value_col: usize,
+    ) -> Result<Self> {
+        let mut tree = BPlusTree::try_new(cap)?;
+        for record in csv::Reader::from_reader(reader).records() {
+            let record = record.map_err(CsvError::from)?;
+            let line = record.position().map_or(0, |p| p.line());
+            let column = |column: usize| {
+                record
+                    .get(column)
+                    .ok_or(CsvError::MissingColumn { line, column })
+            };
+            let key = column(key_col)?;
+            let key = key.parse::<Key>().map_err(|_| CsvError::InvalidKey {
+                line,
+                key: key.to_string(),
+            })?;
+            let value = column(value_col)?;
+            let value = value.parse::<T>().map_err(|_| CsvError::InvalidValue {
+                line,
+                value: value.to_string(),
+            })?;
+            tree.insert(key, Data::new(0, value));
+        }
+        Ok(tree)
+    }
+}
+
+impl<T: Display> BPlusTree<T> {
+    // key,value のヘッダーを付けて、キー順に書き出す
+    pub fn to_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer
+            .write_record(["key", "value"])
+            .map_err(CsvError::from)?;
+        for (key, value) in self.entries() {
+            writer
+                .write_record([key.to_string(), value.to_string()])
+                .map_err(CsvError::from)?;
+        }
+        writer
+            .flush()
+            .map_err(|e| CsvError::from(csv::Error::from(e)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn import_and_export() {
+        let input = "name,id,score\nalice,3,90\nbob,1,75\ncarol,2,\"8,0\"\n";
+        let b = BPlusTree::<String>::from_csv(3, input.as_bytes(), 1, 0).unwrap();
+        assert_eq!(b.search(1).map(String::as_str), Some("bob"));
+
+        let b = BPlusTree::<String>::from_csv(3, input.as_bytes(), 1, 2).unwrap();
+        let mut out = Vec::new();
+        b.to_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "key,value\n1,75\n2,\"8,0\"\n3,90\n"
+        );
+    }
+
+    #[test]
+    fn invalid_rows() {
+        let input = "id,score\nx,1\n";
+        assert!(matches!(
+            BPlusTree::<u32>::from_csv(3, input.as_bytes(), 0, 1),
+            Err(Error::Csv(CsvError::InvalidKey { line: 2, .. }))
+        ));
+        let input = "id,score\n1,high\n";
+        assert!(matches!(
+            BPlusTree::<u32>::from_csv(3, input.as_bytes(), 0, 1),
+            Err(Error::Csv(CsvError::InvalidValue { line: 2, .. }))
+        ));
+        let input = "id,score\n1,2\n";
+        assert!(matches!(
+            BPlusTree::<u32>::from_csv(3, input.as_bytes(), 0, 5),
+            Err(Error::Csv(CsvError::MissingColumn { column: 5, .. }))
+        ));
+        assert!(matches!(
+            BPlusTree::<u32>::from_csv(0, input.as_bytes(), 0, 1),
+            Err(Error::InvalidCapacity { cap: 0 })
+        ));
+    }
+}
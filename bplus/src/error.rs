@@ -0,0 +1,36 @@
+use thiserror::Error;
+
+#[cfg(feature = "csv")]
+use crate::CsvError;
+use crate::Key;
+
+// 0 だと leaf を分割したときに空の leaf ができてしまう
+pub const MIN_CAP: usize = 1;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("invalid capacity: {cap} (must be at least {MIN_CAP})")]
+    InvalidCapacity { cap: usize },
+    #[error("duplicate key: {key}")]
+    DuplicateKey { key: Key },
+    #[error("corrupted data: {0}")]
+    Corruption(String),
+    #[cfg(feature = "csv")]
+    #[error(transparent)]
+    Csv(#[from] CsvError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            Error::Io(e.into())
+        } else {
+            Error::Corruption(e.to_string())
+        }
+    }
+}
@@ -0,0 +1,407 @@
+// Hilbert 曲線のキーで点を BPlusTree に入れ、箱の範囲検索をキーの区間の走査にする
+//
+// bounds を各軸 2^bits 個のセルに分け、点はセルの番号を Hilbert 曲線に沿って並べた順位をキーにする
+// 曲線は 2 次元でも 3 次元でも、大きさ 2^l の揃ったセルを連続したキーの区間に写す
+// そこで箱は、箱に収まるセルと箱に掛かる最小のセルまで分割し、それぞれのキーの区間を BPlusTree::scan で読む
+// 区間が MAX_RANGES を超えそうになったら分割をやめ、掛かるだけのセルもまるごと読む。余分な点は座標で除く
+// Z-order でも同じように区間にできるが、Hilbert の方が隣のセルのキーが続きやすく、区間が少なくて済む
+//
+// bounds の外の点は端のセルに入る。見つからなくなることはないが、端のセルに集まるので遅くなる
+use std::ops::RangeInclusive;
+
+use spatial::{Coordinate, Rect, SpatialIndex};
+
+use crate::{btree_map::DEFAULT_CAP, BPlusTree, Data, Key};
+
+// 1 回の検索で読むキーの区間の数の目安
+const MAX_RANGES: usize = 64;
+
+// K 次元のセルの Hilbert 曲線上の順位。各軸の座標は bits ビットに収まっていること
+// J. Skilling, "Programming the Hilbert curve" (2004) の AxesToTranspose で、各軸のビットを並べ替えてから交互に繋ぐ
+pub fn hilbert_key<const K: usize>(cell: [u32; K], bits: u32) -> Key {
+    assert!(
+        K > 0 && K as u32 * bits <= Key::BITS,
+        "key does not fit in {} bits",
+        Key::BITS
+    );
+    let mut x = cell;
+    if bits == 0 {
+        return 0;
+    }
+    // 上のビットから、各軸の向きを曲線の向きに合わせて反転・交換する
+    let mut q = 1u32 << (bits - 1);
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..K {
+            if x[i] & q != 0 {
+                x[0] ^= p;
+            } else {
+                let t = (x[0] ^ x[i]) & p;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+    // Gray 符号に直す
+    for i in 1..K {
+        x[i] ^= x[i - 1];
+    }
+    let mut t = 0;
+    let mut q = 1u32 << (bits - 1);
+    while q > 1 {
+        if x[K - 1] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for axis in x.iter_mut() {
+        *axis ^= t;
+    }
+    // 上のビットから、軸 0, 1, ..., K - 1 の順に 1 ビットずつ繋ぐ
+    let mut key = 0;
+    for b in (0..bits).rev() {
+        for axis in x.iter() {
+            key = (key << 1) | ((axis >> b) & 1) as Key;
+        }
+    }
+    key
+}
+
+// 各軸 min 以上 max 以下のセルを全て含むキーの区間。小さい順に並べ、隣り合う区間は繋ぐ
+// 区間が多くなりすぎないように、箱からはみ出すセルを含むことがある
+pub fn hilbert_ranges<const K: usize>(
+    min: [u32; K],
+    max: [u32; K],
+    bits: u32,
+) -> Vec<RangeInclusive<Key>> {
+    assert!(bits <= 32, "bits must be at most 32");
+    let limit = (1u64 << bits) - 1;
+    if (0..K).any(|a| min[a] > max[a] || u64::from(min[a]) > limit) {
+        return Vec::new();
+    }
+    let mut ranges = Vec::new();
+    // 箱に掛かるが収まらない、大きさ 2^level のセルの原点
+    let mut partial = vec![[0u32; K]];
+    for level in (0..=bits).rev() {
+        let side = 1u64 << level;
+        let split = level > 0 && ranges.len() + (partial.len() << K) <= MAX_RANGES;
+        let mut next = Vec::new();
+        for origin in partial {
+            let inside = (0..K).all(|a| {
+                u64::from(min[a]) <= u64::from(origin[a])
+                    && u64::from(origin[a]) + side - 1 <= u64::from(max[a])
+            });
+            if inside || !split {
+                ranges.push(cell_range(origin, level, bits));
+                continue;
+            }
+            let half = side / 2;
+            for child in 0..1usize << K {
+                let mut corner = origin;
+                for (a, c) in corner.iter_mut().enumerate() {
+                    if child >> a & 1 == 1 {
+                        *c += half as u32;
+                    }
+                }
+                let overlaps = (0..K).all(|a| {
+                    u64::from(corner[a]) <= u64::from(max[a])
+                        && u64::from(min[a]) < u64::from(corner[a]) + half
+                });
+                if overlaps {
+                    next.push(corner);
+                }
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        partial = next;
+    }
+    ranges.sort_unstable_by_key(|r| *r.start());
+    let mut merged: Vec<RangeInclusive<Key>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end().checked_add(1) == Some(*range.start()) => {
+                *last = *last.start()..=*range.end();
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// 原点が origin で大きさ 2^level のセルのキーの区間
+fn cell_range<const K: usize>(origin: [u32; K], level: u32, bits: u32) -> RangeInclusive<Key> {
+    let key = hilbert_key(origin, bits);
+    let width = K as u32 * level;
+    let mask = if width >= Key::BITS {
+        Key::MAX
+    } else {
+        (1 << width) - 1
+    };
+    key & !mask..=key | mask
+}
+
+// Hilbert 曲線のキーで並べた点の集合。同じ座標の点をいくつでも入れられる
+#[derive(Debug)]
+pub struct HilbertTree<T, V, const K: usize> {
+    bounds: Rect<T, K>,
+    // 各軸のセルの数は 2^bits
+    bits: u32,
+    // キーから slots の位置を引く
+    tree: BPlusTree<usize>,
+    // 点と値。削除すると None になる
+    slots: Vec<Option<([T; K], V)>>,
+    // 削除した slots の位置。insert で再利用する
+    free: Vec<usize>,
+}
+
+impl<T: Coordinate, V, const K: usize> HilbertTree<T, V, K> {
+    // bounds は点を数える範囲で、これを各軸 2^bits 個のセルに分ける。bits はキーのビット数を K で割ったもの
+    pub fn new(bounds: Rect<T, K>) -> Self {
+        assert!(K > 0, "dimension must be positive");
+        Self {
+            bounds,
+            bits: (Key::BITS / K as u32).min(32),
+            tree: BPlusTree::new(DEFAULT_CAP),
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn bounds(&self) -> &Rect<T, K> {
+        &self.bounds
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // 点の入るセル。bounds の外は端のセルにする
+    fn cell(&self, point: &[T; K]) -> [u32; K] {
+        let cells = (1u64 << self.bits) as f64;
+        let mut cell = [0; K];
+        for (a, c) in cell.iter_mut().enumerate() {
+            let (lo, hi) = (self.bounds.min()[a].to_f64(), self.bounds.max()[a].to_f64());
+            if hi > lo {
+                let offset = ((point[a].to_f64() - lo) / (hi - lo) * cells).floor();
+                // as は負の数と NaN を 0 にする
+                *c = (offset as u64).min((1 << self.bits) - 1) as u32;
+            }
+        }
+        cell
+    }
+
+    pub fn key(&self, point: &[T; K]) -> Key {
+        hilbert_key(self.cell(point), self.bits)
+    }
+
+    pub fn insert(&mut self, point: [T; K], value: V) {
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some((point, value));
+                slot
+            }
+            None => {
+                self.slots.push(Some((point, value)));
+                self.slots.len() - 1
+            }
+        };
+        self.tree.insert(self.key(&point), Data::new(0, slot));
+    }
+
+    // 同じ座標の点を一つ取り除く
+    pub fn remove(&mut self, point: &[T; K]) -> Option<V> {
+        let key = self.key(point);
+        // BPlusTree::remove はキーで選ぶので、同じセルの点を一旦全て取り出し、残すものを戻す
+        let mut same = Vec::new();
+        while let Some(slot) = self.tree.remove(key) {
+            same.push(slot);
+        }
+        let found = same
+            .iter()
+            .position(|&slot| self.slots[slot].as_ref().map(|(p, _)| p) == Some(point));
+        let removed = found.map(|i| same.swap_remove(i));
+        for slot in same {
+            self.tree.insert(key, Data::new(0, slot));
+        }
+        // export_since は使わないので、削除の記録は残さない
+        self.tree.discard_tombstones(self.tree.seq());
+        let slot = removed?;
+        self.free.push(slot);
+        self.slots[slot].take().map(|(_, value)| value)
+    }
+
+    // 全ての点を曲線の順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&[T; K], &V)> + '_ {
+        self.tree.iter().map(move |(_, &slot)| self.entry(slot))
+    }
+
+    // 各座標が min 以上 max 以下の点を、曲線の順に返す
+    pub fn range<'a>(
+        &'a self,
+        min: &[T; K],
+        max: &[T; K],
+    ) -> impl Iterator<Item = (&'a [T; K], &'a V)> + 'a {
+        let (min, max) = (*min, *max);
+        let ranges = if (0..K).all(|a| min[a] <= max[a]) {
+            hilbert_ranges(self.cell(&min), self.cell(&max), self.bits)
+        } else {
+            Vec::new()
+        };
+        ranges
+            .into_iter()
+            .flat_map(move |keys| self.tree.scan(*keys.start(), *keys.end()))
+            .map(move |(_, &slot)| self.entry(slot))
+            .filter(move |(point, _)| (0..K).all(|a| min[a] <= point[a] && point[a] <= max[a]))
+    }
+
+    fn entry(&self, slot: usize) -> (&[T; K], &V) {
+        let (point, value) = self.slots[slot]
+            .as_ref()
+            .expect("removed slot is reachable from the tree");
+        (point, value)
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> SpatialIndex<T, K> for HilbertTree<T, V, K> {
+    type Value = V;
+
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn query_box(&self, window: &Rect<T, K>) -> Vec<(Rect<T, K>, &V)> {
+        self.range(window.min(), window.max())
+            .map(|(point, value)| (Rect::point(*point), value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn curve() {
+        // 2 次元の 1 段目は U の字に (0, 0), (0, 1), (1, 1), (1, 0) の順に巡る
+        let keys: Vec<_> = [[0, 0], [0, 1], [1, 1], [1, 0]]
+            .iter()
+            .map(|&c| hilbert_key(c, 1))
+            .collect();
+        assert_eq!(keys, vec![0, 1, 2, 3]);
+        // 順に並べたセルは、どの次元でも隣り合う
+        for &bits in [1, 2, 3].iter() {
+            let side = 1u32 << bits;
+            let mut cells: Vec<[u32; 3]> = (0..side.pow(3))
+                .map(|i| [i % side, i / side % side, i / side / side])
+                .collect();
+            cells.sort_by_key(|&c| hilbert_key(c, bits));
+            for (i, w) in cells.windows(2).enumerate() {
+                assert_eq!(hilbert_key(w[0], bits), i as Key);
+                let step: u32 = (0..3).map(|a| w[0][a].abs_diff(w[1][a])).sum();
+                assert_eq!(step, 1, "{:?} and {:?} are not adjacent", w[0], w[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        // 箱の中の 4 セルは曲線の上で続いている
+        assert_eq!(hilbert_ranges([0, 0], [1, 1], 2), vec![0..=3]);
+        assert_eq!(hilbert_ranges([0, 0], [3, 3], 2), vec![0..=15]);
+        // 4 x 4 のセルのキーは、y = 3 の行から
+        // 5 6 9 10 / 4 7 8 11 / 3 2 13 12 / 0 1 14 15
+        assert_eq!(hilbert_ranges([1, 0], [1, 3], 2), vec![1..=2, 6..=7]);
+        assert_eq!(hilbert_ranges([0, 1], [3, 1], 2), vec![2..=3, 12..=13]);
+        assert!(hilbert_ranges([2, 0], [1, 3], 2).is_empty());
+    }
+
+    #[test]
+    fn points() {
+        let mut tree = HilbertTree::new(Rect::new([0.0, 0.0], [100.0, 100.0]));
+        tree.insert([10.0, 10.0], "a");
+        tree.insert([20.0, 80.0], "b");
+        tree.insert([55.0, 45.0], "c");
+        tree.insert([55.0, 45.0], "d");
+        tree.insert([150.0, -5.0], "e");
+        assert_eq!(tree.len(), 5);
+        let mut found: Vec<_> = tree
+            .range(&[0.0, 0.0], &[60.0, 50.0])
+            .map(|(_, v)| *v)
+            .collect();
+        found.sort_unstable();
+        assert_eq!(found, vec!["a", "c", "d"]);
+        // bounds の外の点も見つかる
+        let found: Vec<_> = tree
+            .range(&[100.0, -10.0], &[200.0, 0.0])
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(found, vec!["e"]);
+        assert_eq!(tree.query_box(&Rect::new([0.0; 2], [100.0; 2])).len(), 4);
+
+        assert!(matches!(tree.remove(&[55.0, 45.0]), Some("c") | Some("d")));
+        assert_eq!(tree.remove(&[55.0, 46.0]), None);
+        assert_eq!(tree.range(&[55.0, 45.0], &[55.0, 45.0]).count(), 1);
+        assert_eq!(tree.len(), 4);
+    }
+
+    proptest! {
+        // 区間に分けて読んだ点が、全ての点を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            points in prop::collection::vec([-5..70i32, -5..70i32, -5..70i32], 0..150),
+            removes in prop::collection::vec(0..150usize, 0..40),
+            queries in prop::collection::vec(([-8..72i32, -8..72i32, -8..72i32], [0..40i32, 0..40i32, 0..40i32]), 1..20),
+        ) {
+            let mut tree = HilbertTree::new(Rect::new([0; 3], [63; 3]));
+            let mut expected: Vec<(Rect<i32, 3>, usize)> = Vec::new();
+            for (i, &point) in points.iter().enumerate() {
+                tree.insert(point, i);
+                expected.push((Rect::point(point), i));
+            }
+            for &r in &removes {
+                if expected.is_empty() {
+                    break;
+                }
+                let point = *expected[r % expected.len()].0.min();
+                let value = tree.remove(&point).unwrap();
+                let index = expected.iter().position(|e| *e == (Rect::point(point), value)).unwrap();
+                expected.swap_remove(index);
+            }
+            prop_assert_eq!(tree.len(), expected.len());
+            let keys: Vec<_> = tree.iter().map(|(p, _)| tree.key(p)).collect();
+            prop_assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+            for (min, size) in queries {
+                let max = [min[0] + size[0], min[1] + size[1], min[2] + size[2]];
+                let window = Rect::new(min, max);
+                let mut found: Vec<usize> = tree.query_box(&window).into_iter().map(|(_, &v)| v).collect();
+                let mut scanned: Vec<usize> = expected.query_box(&window).into_iter().map(|(_, &v)| v).collect();
+                found.sort_unstable();
+                scanned.sort_unstable();
+                prop_assert_eq!(found, scanned);
+            }
+        }
+
+        // 区間は小さい順に並んで重ならず、箱の中のセルを全て含む
+        #[test]
+        fn ranges_cover(min in [0..16u32, 0..16u32], size in [0..16u32, 0..16u32]) {
+            let max = [(min[0] + size[0]).min(15), (min[1] + size[1]).min(15)];
+            let ranges = hilbert_ranges(min, max, 4);
+            prop_assert!(ranges.windows(2).all(|w| w[0].end() + 1 < *w[1].start()));
+            for x in min[0]..=max[0] {
+                for y in min[1]..=max[1] {
+                    let key = hilbert_key([x, y], 4);
+                    prop_assert!(ranges.iter().any(|r| r.contains(&key)));
+                }
+            }
+        }
+    }
+}
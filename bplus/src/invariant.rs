@@ -0,0 +1,144 @@
+// 木の構造が壊れていないかの確認。テストや fuzz から呼ぶ
+use std::fmt::Display;
+
+use crate::{BPlusTree, Key, Node};
+
+impl<T: Display> BPlusTree<T> {
+    // 壊れていれば panic する
+    //   - 全ての leaf が同じ深さにある
+    //   - 空のノードが無く、ノードの大きさが cap に収まっている
+    //   - pair.key は子のキーの下限で、次の pair.key 以下に子のキーが収まっている
+    //   - leaf から辿れる Data は削除されておらず、同じ Data を二度指さない
+    pub fn check_invariants(&self) {
+        let mut seen = vec![false; self.data.len()];
+        let mut leaf_depth = None;
+        let mut count = 0;
+        if let Some(node) = &self.node {
+            self.check_node(
+                node,
+                0,
+                (Key::MIN, Key::MAX),
+                &mut leaf_depth,
+                &mut seen,
+                &mut count,
+            );
+        }
+        assert_eq!(count, self.len(), "len does not match the leaves");
+        for &id in &self.free {
+            assert!(!seen[id], "freed data {} is reachable", id);
+            assert!(
+                self.data[id].data.is_none(),
+                "freed data {} has a value",
+                id
+            );
+        }
+    }
+
+    fn check_node(
+        &self,
+        node: &Node,
+        depth: usize,
+        (lower, upper): (Key, Key),
+        leaf_depth: &mut Option<usize>,
+        seen: &mut [bool],
+        count: &mut usize,
+    ) {
+        match node {
+            Node::Internal(internal) => {
+                assert!(!internal.nodes.is_empty(), "empty internal node");
+                assert!(
+                    internal.nodes.len() <= self.cap + 1,
+                    "internal node has {} children",
+                    internal.nodes.len()
+                );
+                for (i, pair) in internal.nodes.iter().enumerate() {
+                    let next = internal.nodes.get(i + 1).map_or(upper, |p| p.key);
+                    assert!(
+                        lower <= pair.key && pair.key <= next,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    assert!(
+                        pair.value.min_key().is_some_and(|min| pair.key <= min),
+                        "key {} is not a lower bound of its child",
+                        pair.key
+                    );
+                    self.check_node(
+                        &pair.value,
+                        depth + 1,
+                        (pair.key, next),
+                        leaf_depth,
+                        seen,
+                        count,
+                    );
+                }
+            }
+            Node::Leaf(leaf) => {
+                assert_eq!(
+                    *leaf_depth.get_or_insert(depth),
+                    depth,
+                    "leaves are at different depths"
+                );
+                assert!(!leaf.data_ids.is_empty(), "empty leaf");
+                assert!(
+                    leaf.data_ids.len() <= self.cap,
+                    "leaf has {} entries",
+                    leaf.data_ids.len()
+                );
+                let mut last = lower;
+                for pair in &leaf.data_ids {
+                    assert!(
+                        last <= pair.key && pair.key <= upper,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    last = pair.key;
+                    let data = self.data.get(pair.value).expect("data id out of range");
+                    assert!(
+                        data.data.is_some(),
+                        "removed data {} is reachable",
+                        pair.value
+                    );
+                    assert!(
+                        !std::mem::replace(&mut seen[pair.value], true),
+                        "data {} is reachable twice",
+                        pair.value
+                    );
+                    *count += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BPlusTree, Data, OrderedMap};
+
+    #[test]
+    fn check_invariants() {
+        let mut b = BPlusTree::<i64>::new(2);
+        b.check_invariants();
+        for k in (0..200).map(|k| (k * 37) % 101) {
+            b.insert(k, Data::new(0, k as i64));
+            b.check_invariants();
+        }
+        for k in (0..101).step_by(2) {
+            OrderedMap::remove(&mut b, k);
+            b.check_invariants();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn detects_broken_order() {
+        let mut b = BPlusTree::<i64>::new(3);
+        for k in 0..20 {
+            b.insert(k, Data::new(0, k as i64));
+        }
+        if let Some(crate::Node::Internal(internal)) = &mut b.node {
+            internal.nodes.swap(0, 1);
+        }
+        b.check_invariants();
+    }
+}
@@ -0,0 +1,87 @@
+// JSON Lines との変換
+// 1 行に 1 エントリ {"key":..,"value":..} を置く
+use std::{
+    fmt::Display,
+    io::{BufRead, Write},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{BPlusTree, Data, Error, Key, Result};
+
+#[derive(Serialize)]
+struct EntryRef<'a, T> {
+    key: Key,
+    value: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Entry<T> {
+    key: Key,
+    value: T,
+}
+
+impl<T> BPlusTree<T>
+where
+    T: Display + Serialize,
+{
+    // キー順に 1 エントリずつ書き出す。書き出したエントリ数を返す
+    pub fn export_jsonl<W: Write>(&self, mut writer: W) -> Result<usize> {
+        let mut count = 0;
+        for (key, value) in self.iter() {
+            serde_json::to_writer(&mut writer, &EntryRef { key, value })?;
+            writer.write_all(b"\n")?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+}
+
+impl<T> BPlusTree<T>
+where
+    T: Display + DeserializeOwned,
+{
+    // 1 行ずつ読んで挿入する。空行は読み飛ばす。挿入したエントリ数を返す
+    pub fn import_jsonl<R: BufRead>(&mut self, reader: R) -> Result<usize> {
+        let mut count = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry<T> = serde_json::from_str(&line)
+                .map_err(|e| Error::Corruption(format!("line {}: {}", i + 1, e)))?;
+            self.insert(entry.key, Data::new(0, entry.value));
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn export_and_import() {
+        let mut b = BPlusTree::<String>::new(3);
+        for k in [5, 3, 9, 1, 7] {
+            b.insert(k, Data::new(0, format!("v{}", k)));
+        }
+        let mut out = Vec::new();
+        assert_eq!(b.export_jsonl(&mut out).unwrap(), 5);
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("{\"key\":1,\"value\":\"v1\"}\n{\"key\":3,"));
+
+        let mut restored = BPlusTree::<String>::new(4);
+        let input = format!("{}\n", text);
+        assert_eq!(restored.import_jsonl(input.as_bytes()).unwrap(), 5);
+        assert_eq!(restored.entries(), b.entries());
+
+        assert!(matches!(
+            restored.import_jsonl(&b"{\"key\":1}\n"[..]),
+            Err(Error::Corruption(_))
+        ));
+    }
+}
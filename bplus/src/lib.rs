@@ -1,4 +1,32 @@
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, iter::FusedIterator, ops::Range};
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl;
+mod btree_map;
+#[cfg(feature = "checkpoint")]
+mod checkpoint;
+#[cfg(feature = "csv")]
+mod csv_impl;
+mod error;
+mod hilbert;
+mod invariant;
+#[cfg(feature = "jsonl")]
+mod jsonl;
+mod print;
+#[cfg(feature = "rkyv")]
+mod rkyv_impl;
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+pub use btree_map::DEFAULT_CAP;
+#[cfg(feature = "csv")]
+pub use csv_impl::CsvError;
+pub use error::{Error, Result, MIN_CAP};
+pub use hilbert::{hilbert_key, hilbert_ranges, HilbertTree};
+pub use orderedmap::{Op, OrderedMap};
+#[cfg(feature = "rkyv")]
+pub use rkyv_impl::{ArchivedFrozenTree, FrozenTree};
+pub use spatial::{Coordinate, Rect, SpatialIndex};
 
 #[derive(Debug)]
 pub struct Data<T>
@@ -6,19 +34,30 @@ where
     T: Display,
 {
     id: usize,
-    data: T,
+    // 削除すると None になる
+    data: Option<T>,
     #[allow(dead_code)]
     next_id: Option<usize>,
+    // 挿入したときの BPlusTree::seq
+    seq: u64,
 }
 
 impl<T: Display> Data<T> {
     pub fn new(id: usize, data: T) -> Self {
         Self {
             id,
-            data,
+            data: Some(data),
             next_id: None,
+            seq: 0,
         }
     }
+
+    // 木から辿れる Data は削除されていない
+    fn value(&self) -> &T {
+        self.data
+            .as_ref()
+            .expect("removed data is reachable from the tree")
+    }
 }
 
 #[derive(Debug)]
@@ -29,23 +68,56 @@ where
     cap: usize,
     node: Option<Node>,
     data: Vec<Data<T>>,
+    // 削除した Data の位置。insert で再利用する
+    free: Vec<usize>,
+    // 挿入と削除ごとに 1 ずつ増える
+    seq: u64,
+    // 削除したキーと、削除したときの seq。export_since で削除を返すために残す
+    // 同じキーを挿入し直すか、discard_tombstones で捨てるまで残る
+    tombstones: BTreeMap<Key, u64>,
 }
 
 impl<T: Display> BPlusTree<T> {
+    // cap が MIN_CAP より小さいと panic する
     pub fn new(cap: usize) -> Self {
-        Self {
+        match Self::try_new(cap) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(cap: usize) -> Result<Self> {
+        if cap < MIN_CAP {
+            return Err(Error::InvalidCapacity { cap });
+        }
+        Ok(Self {
             cap,
             node: None,
             data: Vec::new(),
-        }
+            free: Vec::new(),
+            seq: 0,
+            tombstones: BTreeMap::new(),
+        })
     }
 
     pub fn insert(&mut self, key: Key, mut data: Data<T>) {
         // data.id は self.dataのindexが入る
-        // この値は現在の長さに等しい
-        let data_id = self.data.len();
-        data.id = data_id;
-        self.data.push(data);
+        // 削除した位置があれば再利用し、無ければ末尾に追加する
+        self.seq += 1;
+        data.seq = self.seq;
+        self.tombstones.remove(&key);
+        let data_id = match self.free.pop() {
+            Some(data_id) => {
+                data.id = data_id;
+                self.data[data_id] = data;
+                data_id
+            }
+            None => {
+                data.id = self.data.len();
+                self.data.push(data);
+                self.data.len() - 1
+            }
+        };
 
         if self.node.is_none() {
             let child = Node::Leaf(LeafNode {
@@ -70,12 +142,370 @@ impl<T: Display> BPlusTree<T> {
         }
     }
 
+    // insert と違い、既にキーがあれば挿入しない
+    pub fn try_insert(&mut self, key: Key, data: Data<T>) -> Result<()> {
+        if self.search(key).is_some() {
+            return Err(Error::DuplicateKey { key });
+        }
+        self.insert(key, data);
+        Ok(())
+    }
+
     pub fn search(&self, key: Key) -> Option<&T> {
         self.node
             .as_ref()
             .and_then(|n| n.search(key))
             .and_then(|data_id| self.data.get(data_id))
-            .map(|d| &d.data)
+            .and_then(|d| d.data.as_ref())
+    }
+
+    // 同じキーが複数ある場合は一つだけ削除する
+    // underflow時のマージは行わない。空になったノードだけを取り除く
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let node = self.node.as_mut()?;
+        let data_id = node.remove(key)?;
+        if node.min_key().is_none() {
+            self.node = None;
+        }
+        // 子が一つだけになった root は取り除いて高さを縮める
+        loop {
+            match &mut self.node {
+                Some(Node::Internal(internal)) if internal.nodes.len() == 1 => {
+                    let child = internal.nodes.pop().unwrap().value;
+                    self.node = Some(child);
+                }
+                _ => break,
+            }
+        }
+        self.seq += 1;
+        // 同じキーがまだ残っていれば、キーは削除されていない
+        if self.search(key).is_none() {
+            self.tombstones.insert(key, self.seq);
+        }
+        self.free.push(data_id);
+        self.data[data_id].data.take()
+    }
+
+    // 削除していないエントリの数
+    pub fn len(&self) -> usize {
+        self.data.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // キー順に並べた (key, value)
+    pub fn entries(&self) -> Vec<(Key, &T)> {
+        self.iter().collect()
+    }
+
+    // 最後の挿入か削除に割り当てた番号。まだ何もしていなければ 0
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    // seq より後に挿入したエントリと削除したキーをキー順に返す。削除したキーの値は None
+    // 差分のバックアップでは、前回の export 時の self.seq() を渡す
+    // 同じキーで両方ある場合は、削除を先に返す
+    pub fn export_since(&self, seq: u64) -> impl Iterator<Item = (Key, Option<&T>)> + '_ {
+        let mut iter = self.iter();
+        let mut inserted = std::iter::from_fn(move || loop {
+            let (key, data) = iter.next_data()?;
+            if data.seq > seq {
+                return Some((key, data.value()));
+            }
+        })
+        .peekable();
+        let mut removed = self
+            .tombstones
+            .iter()
+            .filter(move |(_, s)| **s > seq)
+            .map(|(key, _)| *key)
+            .peekable();
+        std::iter::from_fn(move || match (inserted.peek(), removed.peek()) {
+            (Some((k, _)), Some(r)) if r <= k => removed.next().map(|key| (key, None)),
+            (Some(_), _) => inserted.next().map(|(key, value)| (key, Some(value))),
+            (None, _) => removed.next().map(|key| (key, None)),
+        })
+    }
+
+    // seq が up_to_seq 以下の削除の記録を捨てる
+    // 差分のバックアップを up_to_seq まで取り終えたら呼ぶ。捨てた削除は export_since で返らない
+    pub fn discard_tombstones(&mut self, up_to_seq: u64) {
+        self.tombstones.retain(|_, seq| *seq > up_to_seq);
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            data: &self.data,
+            pairs: Pairs::new(&self.node, self.len()),
+        }
+    }
+
+    // min_key 以上 max_key 以下のエントリをキー順に返す
+    // min_key の入る leaf まで根から降りて、そこから辿る。手前の leaf は読まない
+    pub fn scan(&self, min_key: Key, max_key: Key) -> impl Iterator<Item = (Key, &T)> + '_ {
+        // まだ辿っていないノード。末尾から取り出す
+        let mut stack: Vec<&Node> = Vec::new();
+        let mut leaf: std::slice::Iter<'_, DataPair> = [].iter();
+        let mut node = self.node.as_ref();
+        while let Some(n) = node {
+            match n {
+                Node::Internal(internal) => {
+                    // candidates と同じく、min_key より小さい pair.key のうち最後の子から見る
+                    let start = internal.candidates(min_key).start;
+                    stack.extend(
+                        internal
+                            .nodes
+                            .iter()
+                            .skip(start + 1)
+                            .rev()
+                            .map(|p| &p.value),
+                    );
+                    node = internal.nodes.get(start).map(|p| &p.value);
+                }
+                Node::Leaf(l) => {
+                    let start = l.data_ids.partition_point(|p| p.key < min_key);
+                    leaf = l.data_ids[start..].iter();
+                    node = None;
+                }
+            }
+        }
+        std::iter::from_fn(move || loop {
+            if let Some(p) = leaf.next() {
+                return Some((p.key, self.data[p.value].value()));
+            }
+            match stack.pop()? {
+                Node::Internal(internal) => {
+                    stack.extend(internal.nodes.iter().rev().map(|p| &p.value))
+                }
+                Node::Leaf(l) => leaf = l.data_ids.iter(),
+            }
+        })
+        .take_while(move |&(key, _)| key <= max_key)
+    }
+
+    // キー順に (key, &mut value) を返す
+    // 値を書き換えたかは分からないので、返したエントリは全て書き換えたものとして seq を進める
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let len = self.len();
+        IterMut {
+            data: self.data.iter_mut().map(Some).collect(),
+            seq: &mut self.seq,
+            pairs: Pairs::new(&self.node, len),
+        }
+    }
+}
+
+// 木を深さ優先で辿り、leaf の DataPair をキー順に返す。途中で全体をコピーしない
+// 前からと後ろからはそれぞれ別に辿り、残りの数で重ならないようにする
+struct Pairs<'a> {
+    // まだ辿っていないノード。末尾から取り出す
+    front: Vec<&'a Node>,
+    back: Vec<&'a Node>,
+    front_leaf: std::slice::Iter<'a, DataPair>,
+    back_leaf: std::slice::Iter<'a, DataPair>,
+    remaining: usize,
+}
+
+impl<'a> Pairs<'a> {
+    fn new(node: &'a Option<Node>, len: usize) -> Self {
+        Self {
+            front: node.iter().collect(),
+            back: node.iter().collect(),
+            front_leaf: [].iter(),
+            back_leaf: [].iter(),
+            remaining: len,
+        }
+    }
+}
+
+impl<'a> Iterator for Pairs<'a> {
+    type Item = &'a DataPair;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.front_leaf.next() {
+                self.remaining -= 1;
+                return Some(p);
+            }
+            match self.front.pop()? {
+                Node::Internal(internal) => self
+                    .front
+                    .extend(internal.nodes.iter().rev().map(|p| &p.value)),
+                Node::Leaf(leaf) => self.front_leaf = leaf.data_ids.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for Pairs<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.back_leaf.next_back() {
+                self.remaining -= 1;
+                return Some(p);
+            }
+            match self.back.pop()? {
+                Node::Internal(internal) => {
+                    self.back.extend(internal.nodes.iter().map(|p| &p.value))
+                }
+                Node::Leaf(leaf) => self.back_leaf = leaf.data_ids.iter(),
+            }
+        }
+    }
+}
+
+pub struct Iter<'a, T>
+where
+    T: Display,
+{
+    data: &'a [Data<T>],
+    pairs: Pairs<'a>,
+}
+
+impl<'a, T: Display> Iter<'a, T> {
+    fn next_data(&mut self) -> Option<(Key, &'a Data<T>)> {
+        let p = self.pairs.next()?;
+        Some((p.key, &self.data[p.value]))
+    }
+}
+
+impl<'a, T: Display> Iterator for Iter<'a, T> {
+    type Item = (Key, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_data().map(|(key, data)| (key, data.value()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pairs.size_hint()
+    }
+}
+
+impl<'a, T: Display> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let p = self.pairs.next_back()?;
+        Some((p.key, self.data[p.value].value()))
+    }
+}
+
+impl<'a, T: Display> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T: Display> FusedIterator for Iter<'a, T> {}
+
+// Data は data_id の位置から取り出すので、同じ Data を二度返すことはない
+pub struct IterMut<'a, T>
+where
+    T: Display,
+{
+    data: Vec<Option<&'a mut Data<T>>>,
+    seq: &'a mut u64,
+    pairs: Pairs<'a>,
+}
+
+impl<'a, T: Display> IterMut<'a, T> {
+    fn take(&mut self, p: &DataPair) -> (Key, &'a mut T) {
+        let data = self.data[p.value]
+            .take()
+            .expect("data is reachable twice from the tree");
+        *self.seq += 1;
+        data.seq = *self.seq;
+        let value = data
+            .data
+            .as_mut()
+            .expect("removed data is reachable from the tree");
+        (p.key, value)
+    }
+}
+
+impl<'a, T: Display> Iterator for IterMut<'a, T> {
+    type Item = (Key, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let p = self.pairs.next()?;
+        Some(self.take(p))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.pairs.size_hint()
+    }
+}
+
+impl<'a, T: Display> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let p = self.pairs.next_back()?;
+        Some(self.take(p))
+    }
+}
+
+impl<'a, T: Display> ExactSizeIterator for IterMut<'a, T> {}
+
+impl<'a, T: Display> FusedIterator for IterMut<'a, T> {}
+
+impl<'a, T: Display> IntoIterator for &'a BPlusTree<T> {
+    type Item = (Key, &'a T);
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<'a, T: Display> IntoIterator for &'a mut BPlusTree<T> {
+    type Item = (Key, &'a mut T);
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> IterMut<'a, T> {
+        self.iter_mut()
+    }
+}
+
+impl<T: Display> OrderedMap<Key, T> for BPlusTree<T> {
+    type Iter<'a>
+        = Iter<'a, T>
+    where
+        Self: 'a,
+        T: 'a;
+
+    // BPlusTree::insert と違い、同じキーがあれば値を置き換える
+    fn insert(&mut self, key: Key, value: T) -> Option<T> {
+        match self.node.as_ref().and_then(|n| n.search(key)) {
+            Some(data_id) => {
+                self.seq += 1;
+                let data = &mut self.data[data_id];
+                data.seq = self.seq;
+                data.data.replace(value)
+            }
+            None => {
+                BPlusTree::insert(self, key, Data::new(0, value));
+                None
+            }
+        }
+    }
+
+    fn get(&self, key: Key) -> Option<&T> {
+        self.search(key)
+    }
+
+    fn remove(&mut self, key: Key) -> Option<T> {
+        BPlusTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, T> {
+        BPlusTree::iter(self)
     }
 }
 
@@ -117,6 +547,14 @@ impl Node {
         }
     }
 
+    // 削除した data_id を返す
+    fn remove(&mut self, key: Key) -> Option<usize> {
+        match self {
+            Node::Internal(internal) => internal.remove(key),
+            Node::Leaf(leaf) => leaf.remove(key),
+        }
+    }
+
     fn min_key(&self) -> Option<usize> {
         match self {
             Node::Internal(internal) => internal.nodes.first().map(|p| p.key),
@@ -134,7 +572,6 @@ struct InternalNode {
 
 impl InternalNode {
     fn insert(&mut self, key: Key, data_id: usize) -> Option<Node> {
-        // TODO 同値のkeyが存在している場合がおかしいので、要修正
         if self.nodes.is_empty() {
             self.nodes.push(Pair::new(
                 key,
@@ -145,12 +582,18 @@ impl InternalNode {
             ));
             return None;
         }
-        let node = self.find_node_for_insert(key, data_id);
+        let index = self.index_for_insert(key);
+        let node = &mut self.nodes[index];
+        // 最小値より小さいキーは先頭の子に入る。pair.key は子のキーの下限にしておく
+        if key < node.key {
+            node.key = key;
+        }
         let splited = node.value.insert(key, data_id);
         if let Some(n) = splited {
             if let Some(k) = n.min_key() {
-                self.nodes.push(Pair { key: k, value: n });
-                self.nodes.sort_by_key(|p| p.key);
+                // 並び替えると、同じキーの子の間で順序が入れ替わることがある
+                // 分割した子のすぐ右に入れる
+                self.nodes.insert(index + 1, Pair { key: k, value: n });
             }
         }
         if self.is_full() {
@@ -168,43 +611,41 @@ impl InternalNode {
         Node::Internal(new_next)
     }
 
-    fn find_node_for_insert(&mut self, key: Key, data_id: usize) -> &mut NodePair {
-        if self.nodes.is_empty() {
-            self.nodes.push(NodePair::new(
-                key,
-                Node::Leaf(LeafNode {
-                    cap: self.cap,
-                    data_ids: vec![DataPair::new(key, data_id)],
-                }),
-            ))
-        }
-        return self.find_mut_node(key).unwrap();
+    // 同じキーは右側の子に入れる。どの pair.key よりも小さければ先頭の子
+    fn index_for_insert(&self, key: Key) -> usize {
+        self.nodes
+            .iter()
+            .take_while(|pair| pair.key <= key)
+            .count()
+            .saturating_sub(1)
     }
 
-    fn find_mut_node(&mut self, key: Key) -> Option<&mut NodePair> {
-        let exist = self.nodes.iter().any(|pair| pair.key <= key);
-        if exist {
-            self.nodes
-                .iter_mut()
-                .take_while(|pair| pair.key <= key)
-                .last()
-        } else {
-            self.nodes.first_mut()
-        }
+    // key が入っている可能性のある子の位置
+    // 同じキーが続くと分割で左右の子に分かれるので、pair.key == key の子の左隣から見る
+    fn candidates(&self, key: Key) -> Range<usize> {
+        let lt = self.nodes.iter().take_while(|pair| pair.key < key).count();
+        let le = self.nodes.iter().take_while(|pair| pair.key <= key).count();
+        let start = lt.saturating_sub(1);
+        start..le.max(start + 1).min(self.nodes.len())
     }
 
     pub fn search(&self, key: Key) -> Option<usize> {
-        // TODO 同値のkeyが存在している場合がおかしいので、要修正
-        let p = self.find_node(key);
-        p.and_then(|p| p.value.search(key))
+        self.candidates(key)
+            .find_map(|index| self.nodes[index].value.search(key))
     }
 
-    fn find_node(&self, key: Key) -> Option<&NodePair> {
-        self.nodes
-            .iter()
-            .take_while(|pair| pair.key <= key)
-            .last()
-            .or_else(|| self.nodes.first())
+    fn remove(&mut self, key: Key) -> Option<usize> {
+        for index in self.candidates(key) {
+            let node = &mut self.nodes[index].value;
+            if let Some(data_id) = node.remove(key) {
+                // 最小値が消えても、pair.key は下限として正しいのでそのままにする
+                if node.min_key().is_none() {
+                    self.nodes.remove(index);
+                }
+                return Some(data_id);
+            }
+        }
+        None
     }
 
     // capacityに空きがあるかどうか
@@ -246,6 +687,11 @@ impl LeafNode {
         self.data_ids.iter().find(|p| p.key == key).map(|p| p.value)
     }
 
+    fn remove(&mut self, key: Key) -> Option<usize> {
+        let index = self.data_ids.iter().position(|p| p.key == key)?;
+        Some(self.data_ids.remove(index).value)
+    }
+
     // capacityに空きがあるかどうか
     fn is_full(&self) -> bool {
         self.data_ids.len() > self.cap
@@ -254,6 +700,10 @@ impl LeafNode {
 
 #[cfg(test)]
 mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -301,4 +751,260 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn iter() {
+        let mut b = BPlusTree::<i64>::new(3);
+        assert_eq!(b.iter().next(), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            b.insert(k, Data::new(0, -(k as i64)));
+        }
+        let keys: Vec<_> = b.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 11, 12, 13, 14, 24, 25]);
+        assert_eq!(b.iter().nth(5), Some((24, &-24)));
+
+        let rev: Vec<_> = b.iter().rev().map(|(k, _)| k).collect();
+        assert_eq!(rev, vec![25, 24, 14, 13, 12, 11, 10]);
+        let mut iter = b.iter();
+        assert_eq!(iter.len(), 7);
+        assert_eq!(iter.next().map(|(k, _)| k), Some(10));
+        assert_eq!(iter.next_back().map(|(k, _)| k), Some(25));
+        assert_eq!(iter.len(), 5);
+        let middle: Vec<_> = iter.by_ref().map(|(k, _)| k).collect();
+        assert_eq!(middle, vec![11, 12, 13, 14, 24]);
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut b = BPlusTree::<i64>::new(3);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            b.insert(k, Data::new(0, 0));
+        }
+        let seq = b.seq();
+        for (k, v) in &mut b {
+            *v = k as i64;
+        }
+        // 書き換えたエントリは export_since に含まれる
+        assert_eq!(b.export_since(seq).count(), 7);
+        let mut iter = b.iter_mut();
+        assert_eq!(iter.len(), 7);
+        *iter.next_back().unwrap().1 *= 10;
+        assert_eq!(iter.len(), 6);
+
+        let mut entries = Vec::new();
+        for (k, v) in &b {
+            entries.push((k, *v));
+        }
+        assert_eq!(
+            entries,
+            vec![
+                (10, 10),
+                (11, 11),
+                (12, 12),
+                (13, 13),
+                (14, 14),
+                (24, 24),
+                (25, 250)
+            ]
+        );
+    }
+
+    #[test]
+    fn try_new_and_try_insert() {
+        assert!(matches!(
+            BPlusTree::<i64>::try_new(0),
+            Err(Error::InvalidCapacity { cap: 0 })
+        ));
+        let mut b = BPlusTree::<i64>::try_new(MIN_CAP).unwrap();
+        b.try_insert(1, Data::new(0, -1)).unwrap();
+        assert!(matches!(
+            b.try_insert(1, Data::new(0, -2)),
+            Err(Error::DuplicateKey { key: 1 })
+        ));
+        assert_eq!(b.search(1), Some(&-1));
+        assert_eq!(b.len(), 1);
+    }
+
+    #[test]
+    fn export_since() {
+        let mut b = BPlusTree::<i64>::new(3);
+        assert_eq!(b.seq(), 0);
+        for k in [11, 25, 12] {
+            b.insert(k, Data::new(0, -(k as i64)));
+        }
+        let backup = b.seq();
+        assert_eq!(backup, 3);
+        for k in [24, 10] {
+            b.insert(k, Data::new(0, -(k as i64)));
+        }
+        let changed: Vec<_> = b.export_since(backup).collect();
+        assert_eq!(changed, vec![(10, Some(&-10)), (24, Some(&-24))]);
+        assert_eq!(b.export_since(0).count(), 5);
+        assert_eq!(b.export_since(b.seq()).next(), None);
+    }
+
+    #[test]
+    fn export_since_removed() {
+        let mut b = BPlusTree::<i64>::new(3);
+        for k in [11, 25, 12, 24] {
+            b.insert(k, Data::new(0, -(k as i64)));
+        }
+        b.remove(24);
+        let backup = b.seq();
+        // backup 前に削除したキーは返さない
+        assert_eq!(b.export_since(backup).next(), None);
+        assert_eq!(b.remove(11), Some(-11));
+        assert_eq!(b.remove(13), None);
+        b.insert(10, Data::new(0, -10));
+        b.remove(12);
+        // 削除してから挿入し直したキーは、挿入したエントリだけを返す
+        b.insert(12, Data::new(0, 12));
+        let changed: Vec<_> = b.export_since(backup).collect();
+        assert_eq!(changed, vec![(10, Some(&-10)), (11, None), (12, Some(&12))]);
+        assert_eq!(b.seq(), backup + 4);
+
+        let next = b.seq();
+        b.remove(25);
+        b.discard_tombstones(next);
+        assert_eq!(b.tombstones.len(), 1);
+        assert_eq!(b.export_since(backup).last(), Some((25, None)));
+        b.discard_tombstones(b.seq());
+        assert!(b.tombstones.is_empty());
+    }
+
+    #[test]
+    fn export_since_duplicate_key() {
+        let mut b = BPlusTree::<i64>::new(3);
+        b.insert(5, Data::new(0, 1));
+        b.insert(5, Data::new(0, 2));
+        let backup = b.seq();
+        // 同じキーが残っている間は、削除を返さない
+        b.remove(5);
+        assert_eq!(b.export_since(backup).next(), None);
+        b.remove(5);
+        assert_eq!(b.export_since(backup).collect::<Vec<_>>(), vec![(5, None)]);
+    }
+
+    // OrderedMap の操作だけを使い、BTreeMap と同じ結果になることを確認する
+    fn same_as_btree_map<M: OrderedMap<Key, i64>>(mut m: M) {
+        let mut expected = BTreeMap::new();
+        for k in (0..300).map(|k| (k * 37) % 101) {
+            let v = k as i64;
+            assert_eq!(m.insert(k, v), OrderedMap::insert(&mut expected, k, v));
+        }
+        for k in (0..110).step_by(3) {
+            assert_eq!(m.remove(k), OrderedMap::remove(&mut expected, k));
+        }
+        assert_eq!(m.get(1), Some(&1));
+        assert_eq!(m.get(3), None);
+        assert!(m.iter().eq(OrderedMap::iter(&expected)));
+        assert_eq!(m.range(10, 40), OrderedMap::range(&expected, 10, 40));
+        for k in 0..101 {
+            assert_eq!(m.remove(k), OrderedMap::remove(&mut expected, k));
+        }
+        assert_eq!(m.iter().next(), None);
+    }
+
+    #[test]
+    fn scan_duplicates() {
+        // 同じキーが分割で左右の leaf に分かれても、全て返す
+        let mut b = BPlusTree::<i64>::new(2);
+        for (i, k) in [5, 1, 5, 9, 5, 3, 5, 7, 5].iter().enumerate() {
+            b.insert(*k, Data::new(0, i as i64));
+        }
+        let keys: Vec<_> = b.scan(4, 7).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![5, 5, 5, 5, 5, 7]);
+        assert_eq!(b.scan(5, 5).count(), 5);
+        assert_eq!(b.scan(10, 20).count(), 0);
+        assert_eq!(b.scan(7, 4).count(), 0);
+        assert_eq!(b.scan(0, usize::MAX).count(), b.len());
+    }
+
+    #[test]
+    fn ordered_map() {
+        same_as_btree_map(BPlusTree::<i64>::new(3));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(Key, i64),
+        Remove(Key),
+        Get(Key),
+        Range(Key, Key),
+    }
+
+    // 重複や分割が起きやすいように、キーは狭い範囲から選ぶ
+    fn op() -> impl Strategy<Value = Op> {
+        let key = 0..200usize;
+        prop_oneof![
+            4 => (key.clone(), any::<i64>()).prop_map(|(k, v)| Op::Insert(k, v)),
+            2 => key.clone().prop_map(Op::Remove),
+            1 => key.clone().prop_map(Op::Get),
+            1 => (key.clone(), key).prop_map(|(min, max)| Op::Range(min, max)),
+        ]
+    }
+
+    proptest! {
+        // ランダムな操作列を BTreeMap と同時に適用し、全ての結果を比べる
+        #[test]
+        fn same_as_btree_map_random(cap in 2..64usize, ops in prop::collection::vec(op(), 0..400)) {
+            let mut tree = BPlusTree::new(cap);
+            let mut expected = BTreeMap::new();
+            for op in ops {
+                match op {
+                    Op::Insert(k, v) => prop_assert_eq!(
+                        OrderedMap::insert(&mut tree, k, v),
+                        expected.insert(k, v)
+                    ),
+                    Op::Remove(k) => prop_assert_eq!(tree.remove(k), expected.remove(&k)),
+                    Op::Get(k) => prop_assert_eq!(tree.search(k), expected.get(&k)),
+                    Op::Range(min, max) => {
+                        let found: Vec<_> = tree.scan(min, max).collect();
+                        prop_assert_eq!(&found, &OrderedMap::range(&expected, min, max));
+                        prop_assert_eq!(OrderedMap::range(&tree, min, max), found);
+                    }
+                }
+                tree.check_invariants();
+                prop_assert_eq!(tree.len(), expected.len());
+            }
+            prop_assert!(tree.iter().eq(OrderedMap::iter(&expected)));
+            prop_assert!(tree.iter().rev().eq(OrderedMap::iter(&expected).rev()));
+        }
+
+        // backup 時点の内容に export_since の差分を当てると、今の内容になる
+        #[test]
+        fn export_since_restores(
+            cap in 2..16usize,
+            before in prop::collection::vec(op(), 0..200),
+            after in prop::collection::vec(op(), 0..200),
+        ) {
+            let mut tree = BPlusTree::new(cap);
+            let apply = |tree: &mut BPlusTree<i64>, op: &Op| match *op {
+                Op::Insert(k, v) => {
+                    OrderedMap::insert(tree, k, v);
+                }
+                Op::Remove(k) => {
+                    tree.remove(k);
+                }
+                Op::Get(_) | Op::Range(_, _) => {}
+            };
+            for op in &before {
+                apply(&mut tree, op);
+            }
+            let backup = tree.seq();
+            let mut restored: BTreeMap<_, _> = tree.iter().map(|(k, v)| (k, *v)).collect();
+            for op in &after {
+                apply(&mut tree, op);
+            }
+            for (k, v) in tree.export_since(backup) {
+                match v {
+                    Some(v) => restored.insert(k, *v),
+                    None => restored.remove(&k),
+                };
+            }
+            prop_assert!(tree.iter().eq(OrderedMap::iter(&restored)));
+        }
+    }
 }
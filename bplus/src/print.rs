@@ -0,0 +1,139 @@
+// 木の構造の表示。デバッグや examples/repl.rs で使う
+//   Display: 深さごとに字下げしたノードの一覧
+//   to_dot : Graphviz の dot 形式。dot -Tsvg out.dot > out.svg で描ける
+use std::{
+    fmt::{self, Display},
+    io::Write,
+};
+
+use crate::{BPlusTree, Node, Result};
+
+impl<T: Display> Display for BPlusTree<T> {
+    // internal [10, 20]
+    //   leaf [10: a, 11: b]
+    //   leaf [20: c]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.node {
+            None => writeln!(f, "(empty)"),
+            Some(node) => self.fmt_node(f, node, 0),
+        }
+    }
+}
+
+impl<T: Display> BPlusTree<T> {
+    fn fmt_node(&self, f: &mut fmt::Formatter<'_>, node: &Node, depth: usize) -> fmt::Result {
+        let indent = "  ".repeat(depth);
+        match node {
+            Node::Internal(internal) => {
+                let keys: Vec<_> = internal.nodes.iter().map(|p| p.key.to_string()).collect();
+                writeln!(f, "{}internal [{}]", indent, keys.join(", "))?;
+                for pair in &internal.nodes {
+                    self.fmt_node(f, &pair.value, depth + 1)?;
+                }
+                Ok(())
+            }
+            Node::Leaf(leaf) => {
+                let entries: Vec<_> = leaf
+                    .data_ids
+                    .iter()
+                    .map(|p| format!("{}: {}", p.key, self.data[p.value].value()))
+                    .collect();
+                writeln!(f, "{}leaf [{}]", indent, entries.join(", "))
+            }
+        }
+    }
+
+    // ノードごとに record を一つ作り、internal の各キーから子へ辺を引く
+    pub fn to_dot<W: Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "digraph bplus {{")?;
+        writeln!(writer, "  node [shape=record];")?;
+        if let Some(node) = &self.node {
+            self.write_dot_node(&mut writer, node, &mut 0)?;
+        }
+        writeln!(writer, "}}")?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    // next_id は次に使うノードの番号。書き出したノードの番号を返す
+    fn write_dot_node<W: Write>(
+        &self,
+        writer: &mut W,
+        node: &Node,
+        next_id: &mut usize,
+    ) -> Result<usize> {
+        let id = *next_id;
+        *next_id += 1;
+        match node {
+            Node::Internal(internal) => {
+                let fields: Vec<_> = internal
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| format!("<p{}> {}", i, p.key))
+                    .collect();
+                writeln!(writer, "  n{} [label=\"{}\"];", id, fields.join("|"))?;
+                for (i, pair) in internal.nodes.iter().enumerate() {
+                    let child = self.write_dot_node(writer, &pair.value, next_id)?;
+                    writeln!(writer, "  n{}:p{} -> n{};", id, i, child)?;
+                }
+            }
+            Node::Leaf(leaf) => {
+                let fields: Vec<_> = leaf
+                    .data_ids
+                    .iter()
+                    .map(|p| {
+                        let value = self.data[p.value].value().to_string();
+                        format!("{}: {}", p.key, escape(&value))
+                    })
+                    .collect();
+                writeln!(writer, "  n{} [label=\"{}\"];", id, fields.join("|"))?;
+            }
+        }
+        Ok(id)
+    }
+}
+
+// record の label で特別な意味を持つ文字を escape する
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '\\' | '|' | '{' | '}' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BPlusTree, Data};
+
+    fn tree() -> BPlusTree<String> {
+        let mut b = BPlusTree::new(2);
+        for (k, v) in [(10, "a"), (20, "b|c"), (30, "d")] {
+            b.insert(k, Data::new(0, v.to_string()));
+        }
+        b
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(BPlusTree::<String>::new(2).to_string(), "(empty)\n");
+        assert_eq!(
+            tree().to_string(),
+            "internal [10, 20]\n  leaf [10: a]\n  leaf [20: b|c, 30: d]\n"
+        );
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut out = Vec::new();
+        tree().to_dot(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph bplus {\n  node [shape=record];\n  n0 [label=\"<p0> 10|<p1> 20\"];\n  n1 [label=\"10: a\"];\n  n0:p0 -> n1;\n  n2 [label=\"20: b\\|c|30: d\"];\n  n0:p1 -> n2;\n}\n"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// rkyv でのゼロコピーの保存と読み込み
+// serde と同じく内部のノード構造は保存せず、キー順に並べたキーと値だけを保存する
+// 読み込み専用なので、internal node の代わりにキーの二分探索で探す
+//
+//   let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&FrozenTree::from(tree))?;
+//   let archived = rkyv::access::<ArchivedFrozenTree<T>, rkyv::rancor::Error>(&bytes)?;
+//   archived.get(key);
+use std::{collections::BTreeMap, fmt::Display};
+
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::{BPlusTree, Key};
+
+#[derive(Debug, Archive, Serialize, Deserialize)]
+pub struct FrozenTree<T> {
+    // BPlusTree に戻すときの cap
+    cap: u32,
+    // 昇順で重複しない。usize は環境で幅が変わるので u64 で保存する
+    keys: Vec<u64>,
+    // keys[i] の値は values[i]
+    values: Vec<T>,
+}
+
+impl<T: Display> From<BPlusTree<T>> for FrozenTree<T> {
+    fn from(tree: BPlusTree<T>) -> Self {
+        let cap = tree.cap as u32;
+        let (keys, values) = BTreeMap::from(tree)
+            .into_iter()
+            .map(|(key, value)| (key as u64, value))
+            .unzip();
+        Self { cap, keys, values }
+    }
+}
+
+impl<T: Display> From<FrozenTree<T>> for BPlusTree<T> {
+    fn from(frozen: FrozenTree<T>) -> Self {
+        let entries = frozen.keys.into_iter().map(|key| key as Key);
+        Self::bulk_load(frozen.cap as usize, entries.zip(frozen.values))
+    }
+}
+
+impl<T: Archive> ArchivedFrozenTree<T> {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn get(&self, key: Key) -> Option<&T::Archived> {
+        let index = self
+            .keys
+            .binary_search_by_key(&(key as u64), |k| k.to_native())
+            .ok()?;
+        Some(&self.values[index])
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(
+        &self,
+        min_key: Key,
+        max_key: Key,
+    ) -> impl Iterator<Item = (Key, &T::Archived)> + '_ {
+        let start = self
+            .keys
+            .partition_point(|k| k.to_native() < min_key as u64);
+        let end = self
+            .keys
+            .partition_point(|k| k.to_native() <= max_key as u64)
+            .max(start);
+        (start..end).map(move |i| (self.keys[i].to_native() as Key, &self.values[i]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &T::Archived)> + '_ {
+        self.range(Key::MIN, Key::MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rkyv::{rancor::Error, util::AlignedVec};
+
+    use super::*;
+    use crate::Data;
+
+    #[test]
+    fn archive_and_access() {
+        let map: BTreeMap<Key, String> = (0..500).map(|k| (k * 2, format!("v{}", k))).collect();
+        let mut tree = BPlusTree::from(map.clone());
+        // 同じキーは後の値が残る
+        tree.insert(10, Data::new(0, "new".to_string()));
+        let bytes = rkyv::to_bytes::<Error>(&FrozenTree::from(tree)).unwrap();
+
+        // ファイルに書き出して読み込んでも、そのまま参照できる
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.rkyv");
+        std::fs::write(&path, &bytes).unwrap();
+        let mut loaded = AlignedVec::<16>::new();
+        loaded.extend_from_slice(&std::fs::read(&path).unwrap());
+        let archived = rkyv::access::<ArchivedFrozenTree<String>, Error>(&loaded).unwrap();
+        assert_eq!(archived.len(), 500);
+        assert_eq!(archived.get(10).map(|v| v.as_str()), Some("new"));
+        assert_eq!(archived.get(12).map(|v| v.as_str()), Some("v6"));
+        assert!(archived.get(11).is_none());
+        let keys: Vec<_> = archived.range(3, 9).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![4, 6, 8]);
+        assert_eq!(archived.range(9, 3).count(), 0);
+        assert!(archived.iter().map(|(k, _)| k).eq(map.keys().copied()));
+
+        // 壊れたデータは読み込めない
+        assert!(rkyv::access::<ArchivedFrozenTree<String>, Error>(&loaded[1..]).is_err());
+
+        let frozen = rkyv::deserialize::<FrozenTree<String>, Error>(archived).unwrap();
+        let tree = BPlusTree::from(frozen);
+        tree.check_invariants();
+        assert_eq!(tree.search(10).map(|v| v.as_str()), Some("new"));
+        assert_eq!(tree.len(), 500);
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt::Display;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{BPlusTree, Data, Key};
+
+// 内部のノード構造ではなく、cap とキー順のエントリだけを保存する
+#[derive(Serialize)]
+struct TreeRef<'a, T> {
+    cap: usize,
+    entries: Vec<(Key, &'a T)>,
+}
+
+#[derive(Deserialize)]
+struct TreeOwned<T> {
+    cap: usize,
+    entries: Vec<(Key, T)>,
+}
+
+impl<T> Serialize for BPlusTree<T>
+where
+    T: Display + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TreeRef {
+            cap: self.cap,
+            entries: self.entries(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for BPlusTree<T>
+where
+    T: Display + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let owned = TreeOwned::deserialize(deserializer)?;
+        let mut tree = BPlusTree::try_new(owned.cap).map_err(de::Error::custom)?;
+        for (key, value) in owned.entries {
+            tree.insert(key, Data::new(0, value));
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut b = BPlusTree::<String>::new(3);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            b.insert(k, Data::new(0, format!("v{}", k)));
+        }
+        let json = serde_json::to_string(&b).unwrap();
+        assert!(json.starts_with(r#"{"cap":3,"entries":[[10,"v10"],[11,"v11"]"#));
+        let restored: BPlusTree<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.cap, 3);
+        assert_eq!(restored.entries(), b.entries());
+        assert_eq!(restored.search(24).map(String::as_str), Some("v24"));
+    }
+}
@@ -0,0 +1,12 @@
+use thiserror::Error;
+
+// 1 だと子を 1 つしか持てず、木にならない
+pub const MIN_DEGREE: usize = 2;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("invalid degree: {degree} (must be at least {MIN_DEGREE})")]
+    InvalidDegree { degree: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
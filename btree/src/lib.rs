@@ -0,0 +1,552 @@
+// B 木 (values を全ての段に持つ)
+// 最小次数 degree の木では、root 以外のノードは degree - 1 から 2 * degree - 1 個のキーを持つ
+// 内部ノードもキーと値を持つので、探索は途中の段で終わることがある
+// B+ 木と違って葉を繋がないので、範囲の走査は木を上り下りしながら進む
+//
+// degree = 2 なら tree234 と同じ 2-3-4 木になる
+// 追加と削除は root から 1 度だけ降りる (top-down)
+//   insert: 降りる先が満杯なら先に分割しておく
+//   remove: 降りる先のキーが degree - 1 個なら兄弟から借りるか併合しておく
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+mod error;
+
+pub use error::{Error, Result, MIN_DEGREE};
+pub use orderedmap::{Op, OrderedMap};
+
+// Default と FromIterator で使う。キーは 15 から 31 個になる
+pub const DEFAULT_DEGREE: usize = 16;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    // 葉なら空、内部ノードなら keys.len() + 1 個
+    children: Vec<Node<K, V>>,
+}
+
+impl<K: Ord + Copy, V> Node<K, V> {
+    fn new(degree: usize) -> Self {
+        Self {
+            keys: Vec::with_capacity(2 * degree - 1),
+            values: Vec::with_capacity(2 * degree - 1),
+            children: Vec::new(),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    // 満杯の children[i] を、真ん中のキーを self に上げて 2 つに分ける
+    fn split_child(&mut self, i: usize, degree: usize) {
+        let child = &mut self.children[i];
+        let mut right = Node::new(degree);
+        right.keys.extend(child.keys.drain(degree..));
+        right.values.extend(child.values.drain(degree..));
+        if !child.is_leaf() {
+            right.children = child.children.split_off(degree);
+        }
+        let key = child.keys.pop().expect("child is full");
+        let value = child.values.pop().expect("child is full");
+        self.keys.insert(i, key);
+        self.values.insert(i, value);
+        self.children.insert(i + 1, right);
+    }
+
+    // self は満杯ではない
+    fn insert(&mut self, key: K, value: V, degree: usize) -> Option<V> {
+        let mut i = match self.keys.binary_search(&key) {
+            Ok(i) => return Some(mem::replace(&mut self.values[i], value)),
+            Err(i) => i,
+        };
+        if self.is_leaf() {
+            self.keys.insert(i, key);
+            self.values.insert(i, value);
+            return None;
+        }
+        if self.children[i].keys.len() == 2 * degree - 1 {
+            self.split_child(i, degree);
+            // 上がってきたキーと比べて、降りる先を選び直す
+            match key.cmp(&self.keys[i]) {
+                Ordering::Equal => return Some(mem::replace(&mut self.values[i], value)),
+                Ordering::Greater => i += 1,
+                Ordering::Less => {}
+            }
+        }
+        self.children[i].insert(key, value, degree)
+    }
+
+    // self は root か、キーを degree 個以上持つ
+    fn remove(&mut self, key: K, degree: usize) -> Option<V> {
+        match self.keys.binary_search(&key) {
+            Ok(i) if self.is_leaf() => {
+                self.keys.remove(i);
+                Some(self.values.remove(i))
+            }
+            Ok(i) => {
+                // 隣の子から前後のキーを持ってきて置き換える。どちらも細ければ併合して降りる
+                if self.children[i].keys.len() >= degree {
+                    let (key, value) = self.children[i].remove_max(degree);
+                    self.keys[i] = key;
+                    Some(mem::replace(&mut self.values[i], value))
+                } else if self.children[i + 1].keys.len() >= degree {
+                    let (key, value) = self.children[i + 1].remove_min(degree);
+                    self.keys[i] = key;
+                    Some(mem::replace(&mut self.values[i], value))
+                } else {
+                    self.merge_children(i);
+                    self.children[i].remove(key, degree)
+                }
+            }
+            Err(_) if self.is_leaf() => None,
+            Err(i) => {
+                let i = self.fatten_child(i, degree);
+                self.children[i].remove(key, degree)
+            }
+        }
+    }
+
+    fn remove_min(&mut self, degree: usize) -> (K, V) {
+        if self.is_leaf() {
+            return (self.keys.remove(0), self.values.remove(0));
+        }
+        let i = self.fatten_child(0, degree);
+        self.children[i].remove_min(degree)
+    }
+
+    fn remove_max(&mut self, degree: usize) -> (K, V) {
+        if self.is_leaf() {
+            let key = self.keys.pop().expect("node is not empty");
+            let value = self.values.pop().expect("node is not empty");
+            return (key, value);
+        }
+        let i = self.fatten_child(self.children.len() - 1, degree);
+        self.children[i].remove_max(degree)
+    }
+
+    // children[i] がキーを degree 個以上持つようにする。併合で位置がずれたら新しい位置を返す
+    fn fatten_child(&mut self, i: usize, degree: usize) -> usize {
+        if self.children[i].keys.len() >= degree {
+            return i;
+        }
+        if i > 0 && self.children[i - 1].keys.len() >= degree {
+            // 左の兄弟の最大のキーを self に上げ、self のキーを children[i] に下ろす
+            let left = &mut self.children[i - 1];
+            let key = left.keys.pop().expect("left sibling is not empty");
+            let value = left.values.pop().expect("left sibling is not empty");
+            let grandchild = left.children.pop();
+            let key = mem::replace(&mut self.keys[i - 1], key);
+            let value = mem::replace(&mut self.values[i - 1], value);
+            let child = &mut self.children[i];
+            child.keys.insert(0, key);
+            child.values.insert(0, value);
+            if let Some(grandchild) = grandchild {
+                child.children.insert(0, grandchild);
+            }
+            i
+        } else if i + 1 < self.children.len() && self.children[i + 1].keys.len() >= degree {
+            let right = &mut self.children[i + 1];
+            let key = right.keys.remove(0);
+            let value = right.values.remove(0);
+            let grandchild = (!right.is_leaf()).then(|| right.children.remove(0));
+            let key = mem::replace(&mut self.keys[i], key);
+            let value = mem::replace(&mut self.values[i], value);
+            let child = &mut self.children[i];
+            child.keys.push(key);
+            child.values.push(value);
+            child.children.extend(grandchild);
+            i
+        } else if i + 1 < self.children.len() {
+            self.merge_children(i);
+            i
+        } else {
+            self.merge_children(i - 1);
+            i - 1
+        }
+    }
+
+    // children[i], keys[i], children[i + 1] を 1 つのノードにまとめる
+    fn merge_children(&mut self, i: usize) {
+        let right = self.children.remove(i + 1);
+        let key = self.keys.remove(i);
+        let value = self.values.remove(i);
+        let left = &mut self.children[i];
+        left.keys.push(key);
+        left.values.push(value);
+        left.keys.extend(right.keys);
+        left.values.extend(right.values);
+        left.children.extend(right.children);
+    }
+}
+
+#[derive(Debug)]
+pub struct BTree<K, V> {
+    root: Node<K, V>,
+    degree: usize,
+    len: usize,
+}
+
+impl<K: Ord + Copy, V> BTree<K, V> {
+    // degree が MIN_DEGREE より小さいと panic する
+    pub fn new(degree: usize) -> Self {
+        match Self::try_new(degree) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(degree: usize) -> Result<Self> {
+        if degree < MIN_DEGREE {
+            return Err(Error::InvalidDegree { degree });
+        }
+        Ok(Self {
+            root: Node::new(degree),
+            degree,
+            len: 0,
+        })
+    }
+
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 葉までの段の数。全ての葉が同じ深さにあるので、一番左の経路だけ数えればよい
+    pub fn height(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Some(child) = node.children.first() {
+            height += 1;
+            node = child;
+        }
+        height
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut node = &self.root;
+        loop {
+            match node.keys.binary_search(&key) {
+                Ok(i) => return Some(&node.values[i]),
+                Err(i) => node = node.children.get(i)?,
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut node = &mut self.root;
+        loop {
+            match node.keys.binary_search(&key) {
+                Ok(i) => return Some(&mut node.values[i]),
+                Err(i) => node = node.children.get_mut(i)?,
+            }
+        }
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        // root が満杯なら分割して 1 段高くする
+        if self.root.keys.len() == 2 * self.degree - 1 {
+            let old = mem::replace(&mut self.root, Node::new(self.degree));
+            self.root.children.push(old);
+            self.root.split_child(0, self.degree);
+        }
+        let old = self.root.insert(key, value, self.degree);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let old = self.root.remove(key, self.degree);
+        // 併合で root のキーが無くなったら 1 段低くする
+        if self.root.keys.is_empty() {
+            if let Some(child) = self.root.children.pop() {
+                self.root = child;
+            }
+        }
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        iter.push_left(&self.root);
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key),
+        };
+        // 各段で min_key 以上の最初のキーの位置を積んで降りる
+        let mut node = &self.root;
+        loop {
+            let i = node.keys.partition_point(|k| *k < min_key);
+            iter.stack.push((node, i));
+            match node.children.get(i) {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+        iter
+    }
+
+    // キーの数、子の数、葉の深さ、キーの順序、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        // 部分木のキーの数と葉までの深さを返す
+        fn check<K: Ord + Copy, V>(
+            node: &Node<K, V>,
+            min_keys: usize,
+            degree: usize,
+            (lower, upper): (Option<K>, Option<K>),
+        ) -> (usize, usize) {
+            assert!(
+                (min_keys..2 * degree).contains(&node.keys.len()),
+                "node has {} keys",
+                node.keys.len()
+            );
+            assert_eq!(node.keys.len(), node.values.len(), "values are misaligned");
+            assert!(
+                node.keys.windows(2).all(|w| w[0] < w[1])
+                    && lower.is_none_or(|l| l < node.keys[0])
+                    && upper.is_none_or(|u| node.keys[node.keys.len() - 1] < u),
+                "keys are out of order"
+            );
+            if node.is_leaf() {
+                return (node.keys.len(), 1);
+            }
+            assert_eq!(
+                node.children.len(),
+                node.keys.len() + 1,
+                "wrong number of children"
+            );
+            let mut count = node.keys.len();
+            let mut depth = None;
+            for (i, child) in node.children.iter().enumerate() {
+                let lower = if i == 0 {
+                    lower
+                } else {
+                    Some(node.keys[i - 1])
+                };
+                let upper = node.keys.get(i).copied().or(upper);
+                let (n, d) = check(child, degree - 1, degree, (lower, upper));
+                assert!(
+                    depth.is_none_or(|depth| depth == d),
+                    "leaves are at different depths"
+                );
+                depth = Some(d);
+                count += n;
+            }
+            (count, depth.expect("node has children") + 1)
+        }
+        if self.root.keys.is_empty() {
+            assert!(self.root.is_leaf(), "empty root has children");
+            assert_eq!(self.len, 0, "len is stale");
+            return;
+        }
+        // root だけはキーが 1 個でもよい
+        let (count, _) = check(&self.root, 1, self.degree, (None, None));
+        assert_eq!(self.len, count, "len is stale");
+    }
+}
+
+impl<K: Ord + Copy, V> Default for BTree<K, V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEGREE)
+    }
+}
+
+// (ノード, 次に返すキーの位置) を積んでおく。children[..=i] は返し終わっているか、上に積まれている
+pub struct Iter<'a, K, V> {
+    stack: Vec<(&'a Node<K, V>, usize)>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut node: &'a Node<K, V>) {
+        loop {
+            self.stack.push((node, 0));
+            match node.children.first() {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (node, i) = self.stack.last_mut()?;
+            let (node, i) = (*node, mem::replace(i, *i + 1));
+            if i >= node.keys.len() {
+                self.stack.pop();
+                continue;
+            }
+            let key = node.keys[i];
+            if self.max_key.is_some_and(|max| key > max) {
+                self.stack.clear();
+                return None;
+            }
+            if let Some(child) = node.children.get(i + 1) {
+                self.push_left(child);
+            }
+            return Some((key, &node.values[i]));
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a BTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for BTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::default();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for BTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        BTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        BTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        BTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        BTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = BTree::new(2);
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn invalid_degree() {
+        for degree in [0, 1] {
+            assert_eq!(
+                BTree::<usize, ()>::try_new(degree).unwrap_err(),
+                Error::InvalidDegree { degree }
+            );
+        }
+        assert_eq!(BTree::<usize, ()>::new(MIN_DEGREE).degree(), MIN_DEGREE);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid degree")]
+    fn new_panics_on_invalid_degree() {
+        BTree::<usize, ()>::new(1);
+    }
+
+    #[test]
+    fn height_shrinks_with_degree() {
+        // 全ての内部ノードが degree 個以上の子を持つので、高さは log_degree((n + 1) / 2) + 1 以下
+        let n = 100_000;
+        for degree in [2, 4, 16, 64] {
+            let mut t = BTree::new(degree);
+            for k in 0..n {
+                t.insert(k, k);
+            }
+            t.check_invariants();
+            let bound = ((n + 1) as f64 / 2.0).ln() / (degree as f64).ln() + 1.0;
+            assert!((t.height() as f64) <= bound, "degree {}", degree);
+            for k in (0..n).step_by(2) {
+                assert_eq!(t.remove(k), Some(k));
+            }
+            t.check_invariants();
+            assert_eq!(t.len(), n / 2);
+        }
+    }
+
+    #[test]
+    fn range() {
+        let t: BTree<_, _> = (0..1000).map(|k| (k * 2, k)).collect();
+        let keys: Vec<_> = t.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(t.range(10, 10).count(), 1);
+        assert_eq!(t.range(15, 9).count(), 0);
+        assert_eq!(t.range(1999, 5000).count(), 0);
+        // 内部ノードのキーをまたぐ範囲
+        assert!(t
+            .range(101, 1500)
+            .map(|(k, _)| k)
+            .eq((51..=750).map(|k| k * 2)));
+        assert!(t.iter().map(|(k, _)| k).eq((0..1000).map(|k| k * 2)));
+    }
+}
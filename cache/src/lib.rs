@@ -0,0 +1,37 @@
+// 容量を超えたら何かを追い出すキャッシュの共通の操作
+// lrucache などの追い出し方を、使う側を変えずに差し替えられるようにする
+// diskbplus のバッファプールもこの trait でページを持つ
+pub trait Cache<K, V> {
+    // 入れられる重さの合計。重さを指定しなければ 1 つ 1 なので、要素数になる
+    fn capacity(&self) -> usize;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 使ったことにして返す
+    fn get(&mut self, key: &K) -> Option<&V>;
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V>;
+
+    // 使ったことにせずに返す
+    fn peek(&self, key: &K) -> Option<&V>;
+
+    fn peek_mut(&mut self, key: &K) -> Option<&mut V>;
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.peek(key).is_some()
+    }
+
+    // key に value を入れ、このために取り除いたものを返す
+    // 同じキーがあれば値を置き換え、古い値を (key, 古い値) として返す。溢れた分は追い出し方に従って追い出して返す
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)>;
+
+    fn remove(&mut self, key: &K) -> Option<V>;
+
+    // 次に追い出すものから順に見て、evictable が true を返した最初のものを取り除いて返す
+    // バッファプールが pin されたページを飛ばすのに使う
+    fn pop_victim(&mut self, evictable: &mut dyn FnMut(&K, &V) -> bool) -> Option<(K, V)>;
+}
@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, sync::Mutex, thread};
+
+use concurrentbplus::{ConcurrentBPlusTree, ConcurrentOrderedMap, PartitionedBPlusTree};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const KEY_SPACE: u64 = 100_000;
+const OPS_PER_THREAD: usize = 10_000;
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+const PARTITIONS: u64 = 8;
+
+// 操作の割合 (%)。残りは get
+struct Workload {
+    name: &'static str,
+    insert: u64,
+    remove: u64,
+    range: u64,
+}
+
+const WORKLOADS: [Workload; 3] = [
+    Workload {
+        name: "read_heavy",
+        insert: 4,
+        remove: 1,
+        range: 5,
+    },
+    Workload {
+        name: "mixed",
+        insert: 25,
+        remove: 5,
+        range: 5,
+    },
+    Workload {
+        name: "write_heavy",
+        insert: 70,
+        remove: 20,
+        range: 0,
+    },
+];
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn prefill<M: ConcurrentOrderedMap<u64, u64>>(map: &M) {
+    for k in (0..KEY_SPACE).step_by(2) {
+        map.insert(k, k);
+    }
+}
+
+fn run<M: ConcurrentOrderedMap<u64, u64>>(map: &M, threads: usize, workload: &Workload) {
+    thread::scope(|s| {
+        for n in 0..threads {
+            s.spawn(move || {
+                let mut state = (n as u64 + 1) * 0x9E37_79B9_7F4A_7C15;
+                for _ in 0..OPS_PER_THREAD {
+                    let r = xorshift(&mut state);
+                    let key = r % KEY_SPACE;
+                    let op = (r >> 32) % 100;
+                    if op < workload.insert {
+                        map.insert(key, r);
+                    } else if op < workload.insert + workload.remove {
+                        map.remove(&key);
+                    } else if op < workload.insert + workload.remove + workload.range {
+                        criterion::black_box(map.range(&key, &(key + 100)));
+                    } else {
+                        criterion::black_box(map.get(&key));
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn contention(c: &mut Criterion) {
+    for workload in WORKLOADS.iter() {
+        let mut group = c.benchmark_group(workload.name);
+        let tree = ConcurrentBPlusTree::new(64);
+        prefill(&tree);
+        let boundaries = (1..PARTITIONS)
+            .map(|n| n * KEY_SPACE / PARTITIONS)
+            .collect();
+        let partitioned = PartitionedBPlusTree::new(64, boundaries);
+        prefill(&partitioned);
+        let baseline = Mutex::new(BTreeMap::new());
+        prefill(&baseline);
+        for threads in THREADS.iter() {
+            group.bench_with_input(
+                BenchmarkId::new("ConcurrentBPlusTree", threads),
+                threads,
+                |b, &threads| b.iter(|| run(&tree, threads, workload)),
+            );
+            group.bench_with_input(
+                BenchmarkId::new("PartitionedBPlusTree", threads),
+                threads,
+                |b, &threads| b.iter(|| run(&partitioned, threads, workload)),
+            );
+            group.bench_with_input(
+                BenchmarkId::new("Mutex<BTreeMap>", threads),
+                threads,
+                |b, &threads| b.iter(|| run(&baseline, threads, workload)),
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
@@ -0,0 +1,107 @@
+use std::ops::Bound;
+
+use tokio::sync::Mutex;
+
+use crate::ConcurrentBPlusTree;
+
+// range で一度に読む件数。これを読むごとに executor に制御を返す
+const SCAN_CHUNK: usize = 256;
+
+// async なサービスから使うための ConcurrentBPlusTree のラッパー
+// 書き込み待ちでスレッドをブロックしないように、writer は tokio の Mutex で直列化する
+#[derive(Debug)]
+pub struct AsyncBPlusTree<K, V> {
+    tree: ConcurrentBPlusTree<K, V>,
+    writer: Mutex<()>,
+}
+
+impl<K, V> AsyncBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(cap: usize) -> Self {
+        Self {
+            tree: ConcurrentBPlusTree::new(cap),
+            writer: Mutex::new(()),
+        }
+    }
+
+    // 読み込みはロックを取らないので async にする必要がない
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.tree.get(key)
+    }
+
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        let _writer = self.writer.lock().await;
+        // writer を保持しているので、内側の std の Mutex は待たずに取れる
+        self.tree.insert(key, value)
+    }
+
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        let _writer = self.writer.lock().await;
+        self.tree.remove(key)
+    }
+
+    // SCAN_CHUNK 件ごとに yield する
+    // chunk ごとにその時点の最新の木を読むので、全体として一つのスナップショットにはならない
+    pub async fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        let mut result = self
+            .tree
+            .range_limited(Bound::Included(min_key), max_key, SCAN_CHUNK);
+        let mut last_len = result.len();
+        while last_len == SCAN_CHUNK {
+            tokio::task::yield_now().await;
+            let last_key = result.last().map(|(k, _)| k.clone()).unwrap();
+            let mut chunk =
+                self.tree
+                    .range_limited(Bound::Excluded(&last_key), max_key, SCAN_CHUNK);
+            last_len = chunk.len();
+            result.append(&mut chunk);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn insert_and_remove() {
+        let t = AsyncBPlusTree::new(3);
+        assert_eq!(t.insert(1, 10).await, None);
+        assert_eq!(t.insert(1, 11).await, Some(10));
+        assert_eq!(t.get(&1), Some(11));
+        assert_eq!(t.remove(&1).await, Some(11));
+        assert_eq!(t.get(&1), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn range_across_chunks() {
+        let t = Arc::new(AsyncBPlusTree::new(8));
+        let tasks: Vec<_> = (0..4)
+            .map(|n| {
+                let t = Arc::clone(&t);
+                tokio::spawn(async move {
+                    for k in (n * 1000)..((n + 1) * 1000) {
+                        t.insert(k, k * 2).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let all = t.range(&0, &4000).await;
+        assert_eq!(all.len(), 4000);
+        assert!(all
+            .iter()
+            .enumerate()
+            .all(|(i, &(k, v))| k == i && v == k * 2));
+        assert_eq!(t.range(&100, &(SCAN_CHUNK + 99)).await.len(), SCAN_CHUNK);
+        assert_eq!(t.range(&3990, &5000).await.len(), 10);
+    }
+}
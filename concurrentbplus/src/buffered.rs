@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeMap,
+    mem,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::ConcurrentBPlusTree;
+
+// スレッドごとの書き込みバッファ。value が None のものは削除
+type WriteBuffer<K, V> = Mutex<BTreeMap<K, Option<V>>>;
+
+// 書き込みは各スレッドの BufferWriter に溜めておき、バックグラウンドのスレッドが
+// interval ごとにまとめて木へ反映する。読み込みは木から行うので、最大で interval 程度古い
+#[derive(Debug)]
+pub struct BufferedBPlusTree<K, V> {
+    inner: Arc<Inner<K, V>>,
+    shutdown: Option<Sender<()>>,
+    merger: Option<JoinHandle<()>>,
+}
+
+impl<K, V> BufferedBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(cap: usize, interval: Duration) -> Self {
+        let inner = Arc::new(Inner {
+            tree: ConcurrentBPlusTree::new(cap),
+            buffers: Mutex::new(Vec::new()),
+            merged_at: Mutex::new(Instant::now()),
+            merging: Mutex::new(()),
+        });
+        let (shutdown, receiver) = mpsc::channel();
+        let merger = {
+            let inner = Arc::clone(&inner);
+            thread::spawn(move || {
+                // Sender が drop されたら終了する
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                    inner.merge();
+                }
+            })
+        };
+        Self {
+            inner,
+            shutdown: Some(shutdown),
+            merger: Some(merger),
+        }
+    }
+
+    // 書き込みを行うスレッドごとに一つ作る
+    pub fn writer(&self) -> BufferWriter<K, V> {
+        let buffer = Arc::new(Mutex::new(BTreeMap::new()));
+        self.inner.buffers.lock().unwrap().push(Arc::clone(&buffer));
+        BufferWriter { buffer }
+    }
+
+    // バックグラウンドのマージを待たずに、溜まっている書き込みを反映する
+    pub fn merge(&self) {
+        self.inner.merge();
+    }
+
+    // これより前に BufferWriter へ書き込んだものは、木に反映されている
+    pub fn staleness(&self) -> Duration {
+        self.inner.merged_at.lock().unwrap().elapsed()
+    }
+
+    // 最大で interval 程度古い内容を読む
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.inner.tree.get(key)
+    }
+
+    pub fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        self.inner.tree.range(min_key, max_key)
+    }
+
+    // 古さが max_staleness 以内であることを保証した木を返す
+    // 超えている場合はその場でマージしてから返す
+    pub fn read_within(&self, max_staleness: Duration) -> &ConcurrentBPlusTree<K, V> {
+        if self.staleness() > max_staleness {
+            self.inner.merge();
+        }
+        &self.inner.tree
+    }
+}
+
+impl<K, V> Drop for BufferedBPlusTree<K, V> {
+    fn drop(&mut self) {
+        drop(self.shutdown.take());
+        if let Some(merger) = self.merger.take() {
+            let _ = merger.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner<K, V> {
+    tree: ConcurrentBPlusTree<K, V>,
+    buffers: Mutex<Vec<Arc<WriteBuffer<K, V>>>>,
+    // 直近のマージを開始した時刻
+    merged_at: Mutex<Instant>,
+    merging: Mutex<()>,
+}
+
+impl<K, V> Inner<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn merge(&self) {
+        let _merging = self.merging.lock().unwrap();
+        let started = Instant::now();
+        let buffers = {
+            let mut buffers = self.buffers.lock().unwrap();
+            let current = buffers.clone();
+            // 登録分と current の分しか参照がなければ BufferWriter は drop 済み
+            // これ以上書き込まれないので、今回反映したら登録を外す
+            buffers.retain(|b| Arc::strong_count(b) > 2);
+            current
+        };
+        for buffer in buffers {
+            let entries = mem::take(&mut *buffer.lock().unwrap());
+            if !entries.is_empty() {
+                self.tree.apply_batch(entries);
+            }
+        }
+        *self.merged_at.lock().unwrap() = started;
+    }
+}
+
+// 一つのスレッドの書き込みを溜めるハンドル
+// 同じキーへの書き込みは、同じ BufferWriter の中では後のものが勝つ
+// 別の BufferWriter 同士では、どちらが勝つかは決まっていない
+#[derive(Debug)]
+pub struct BufferWriter<K, V> {
+    buffer: Arc<WriteBuffer<K, V>>,
+}
+
+impl<K: Ord, V> BufferWriter<K, V> {
+    pub fn insert(&self, key: K, value: V) {
+        self.buffer.lock().unwrap().insert(key, Some(value));
+    }
+
+    pub fn remove(&self, key: K) {
+        self.buffer.lock().unwrap().insert(key, None);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merge_buffers() {
+        let t = BufferedBPlusTree::new(4, Duration::from_secs(3600));
+        thread::scope(|s| {
+            for n in 0..4 {
+                let w = t.writer();
+                s.spawn(move || {
+                    for k in (n * 100)..((n + 1) * 100) {
+                        w.insert(k, k);
+                    }
+                    w.remove(n * 100);
+                });
+            }
+        });
+        assert_eq!(t.get(&1), None);
+        let tree = t.read_within(Duration::ZERO);
+        assert_eq!(tree.range(&0, &400).len(), 396);
+        assert_eq!(tree.get(&100), None);
+        assert_eq!(tree.get(&101), Some(101));
+        // drop された writer のバッファは登録から外れる
+        t.merge();
+        assert!(t.inner.buffers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn background_merge() {
+        let t = BufferedBPlusTree::new(4, Duration::from_millis(10));
+        let w = t.writer();
+        w.insert(1, "a");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while t.get(&1).is_none() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(t.staleness() < Duration::from_secs(5));
+    }
+}
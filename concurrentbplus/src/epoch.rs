@@ -0,0 +1,78 @@
+use std::{fmt, marker::PhantomData, sync::atomic::Ordering};
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+
+// epoch で保護した root へのポインタ
+pub(crate) struct AtomicRoot<T> {
+    ptr: Atomic<T>,
+}
+
+impl<T> AtomicRoot<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            ptr: Atomic::null(),
+        }
+    }
+
+    pub(crate) fn protect(&self) -> Protected<'_, T> {
+        let guard = epoch::pin();
+        let ptr = self.ptr.load(Ordering::Acquire, &guard).as_raw();
+        Protected {
+            _guard: guard,
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+// 差し替えた root は、次に epoch を進めたスレッドが解放する
+// 木より後まで残ることもあるので、どのスレッドで drop してもよく、借用を含まないものに限る
+impl<T: Send + 'static> AtomicRoot<T> {
+    // 書き込み側で直列化した上で呼ぶこと
+    pub(crate) fn replace(&self, new: Option<T>) {
+        let guard = &epoch::pin();
+        let new = match new {
+            Some(node) => Owned::new(node).into_shared(guard),
+            None => Shared::null(),
+        };
+        let old = self.ptr.swap(new, Ordering::AcqRel, guard);
+        if !old.is_null() {
+            // 差し替え前の root を読んでいるスレッドがいなくなってから解放する
+            unsafe { guard.defer_destroy(old) };
+        }
+    }
+}
+
+impl<T> Drop for AtomicRoot<T> {
+    fn drop(&mut self) {
+        // &mut self なので他のスレッドからは参照されていない
+        unsafe {
+            let guard = epoch::unprotected();
+            let root = self.ptr.load(Ordering::Relaxed, guard);
+            if !root.is_null() {
+                drop(root.into_owned());
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for AtomicRoot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicRoot")
+            .field("ptr", &self.ptr)
+            .finish()
+    }
+}
+
+pub(crate) struct Protected<'a, T> {
+    _guard: Guard,
+    ptr: *const T,
+    _marker: PhantomData<&'a AtomicRoot<T>>,
+}
+
+impl<T> Protected<'_, T> {
+    pub(crate) fn get(&self) -> Option<&T> {
+        // root は defer_destroy でしか解放しないので、guard が生きている間は参照できる
+        unsafe { self.ptr.as_ref() }
+    }
+}
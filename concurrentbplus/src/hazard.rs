@@ -0,0 +1,231 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Mutex,
+    },
+};
+
+// retired がこの数を超えたら、hazard pointer を確認して解放する
+// epoch と違い、遅いスレッドがいても解放が止まるのはそのスレッドが読んでいる root だけ
+const RECLAIM_THRESHOLD: usize = 32;
+
+// 読み込み中のスレッドが、参照している root を公開する場所
+// 一度リストに繋いだ slot は AtomicRoot が drop されるまで解放しない
+struct HazardSlot<T> {
+    ptr: AtomicPtr<T>,
+    active: AtomicBool,
+    next: *const HazardSlot<T>,
+}
+
+// hazard pointer で保護した root へのポインタ
+pub(crate) struct AtomicRoot<T> {
+    ptr: AtomicPtr<T>,
+    slots: AtomicPtr<HazardSlot<T>>,
+    // 差し替えられたが、まだ読まれている可能性のある root
+    retired: Mutex<Vec<*mut T>>,
+}
+
+// 生ポインタは AtomicRoot が所有している T と HazardSlot だけを指す
+unsafe impl<T: Send + Sync> Send for AtomicRoot<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicRoot<T> {}
+
+impl<T> AtomicRoot<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            slots: AtomicPtr::new(ptr::null_mut()),
+            retired: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn protect(&self) -> Protected<'_, T> {
+        let slot = self.acquire_slot();
+        let mut ptr = self.ptr.load(Ordering::Acquire);
+        loop {
+            slot.ptr.store(ptr, Ordering::SeqCst);
+            // 公開した後も root が変わっていなければ、まだ retired に入っていない
+            let current = self.ptr.load(Ordering::SeqCst);
+            if current == ptr {
+                break;
+            }
+            ptr = current;
+        }
+        Protected {
+            slot,
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    // 書き込み側で直列化した上で呼ぶこと
+    pub(crate) fn replace(&self, new: Option<T>) {
+        let new = new
+            .map(|node| Box::into_raw(Box::new(node)))
+            .unwrap_or(ptr::null_mut());
+        let old = self.ptr.swap(new, Ordering::SeqCst);
+        if old.is_null() {
+            return;
+        }
+        let mut retired = self.retired.lock().unwrap();
+        retired.push(old);
+        if retired.len() >= RECLAIM_THRESHOLD {
+            self.reclaim(&mut retired);
+        }
+    }
+
+    fn reclaim(&self, retired: &mut Vec<*mut T>) {
+        let mut hazards = Vec::new();
+        let mut slot = self.slots.load(Ordering::Acquire);
+        while let Some(s) = unsafe { slot.as_ref() } {
+            hazards.push(s.ptr.load(Ordering::SeqCst));
+            slot = s.next as *mut _;
+        }
+        retired.retain(|&p| {
+            if hazards.contains(&p) {
+                return true;
+            }
+            // どの slot にも公開されておらず、root からも外れているので誰も読んでいない
+            drop(unsafe { Box::from_raw(p) });
+            false
+        });
+    }
+
+    fn acquire_slot(&self) -> &HazardSlot<T> {
+        let mut slot = self.slots.load(Ordering::Acquire);
+        while let Some(s) = unsafe { slot.as_ref() } {
+            if s.active
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return s;
+            }
+            slot = s.next as *mut _;
+        }
+        // 空いている slot がなければ先頭に追加する
+        let new = Box::into_raw(Box::new(HazardSlot {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            active: AtomicBool::new(true),
+            next: ptr::null(),
+        }));
+        loop {
+            let head = self.slots.load(Ordering::Acquire);
+            // 公開前なので、まだ他のスレッドからは見えていない
+            unsafe { (*new).next = head };
+            if self
+                .slots
+                .compare_exchange(head, new, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return unsafe { &*new };
+            }
+        }
+    }
+}
+
+impl<T> Drop for AtomicRoot<T> {
+    fn drop(&mut self) {
+        // &mut self なので他のスレッドからは参照されていない
+        let root = *self.ptr.get_mut();
+        if !root.is_null() {
+            drop(unsafe { Box::from_raw(root) });
+        }
+        for p in self.retired.get_mut().unwrap().drain(..) {
+            drop(unsafe { Box::from_raw(p) });
+        }
+        let mut slot = *self.slots.get_mut();
+        while !slot.is_null() {
+            let s = unsafe { Box::from_raw(slot) };
+            slot = s.next as *mut _;
+        }
+    }
+}
+
+impl<T> fmt::Debug for AtomicRoot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicRoot")
+            .field("ptr", &self.ptr.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+pub(crate) struct Protected<'a, T> {
+    slot: &'a HazardSlot<T>,
+    ptr: *const T,
+    _marker: PhantomData<&'a AtomicRoot<T>>,
+}
+
+impl<T> Protected<'_, T> {
+    pub(crate) fn get(&self) -> Option<&T> {
+        // slot に公開している間は reclaim で解放されない
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> Drop for Protected<'_, T> {
+    fn drop(&mut self) {
+        self.slot.ptr.store(ptr::null_mut(), Ordering::Release);
+        self.slot.active.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn protected_root_is_not_reclaimed() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let root = AtomicRoot::new();
+        root.replace(Some(Counted(Arc::clone(&dropped))));
+        let protected = root.protect();
+        for _ in 0..RECLAIM_THRESHOLD {
+            root.replace(Some(Counted(Arc::clone(&dropped))));
+        }
+        // 保護している最初の root 以外は解放されている
+        assert_eq!(dropped.load(Ordering::SeqCst), RECLAIM_THRESHOLD - 1);
+        assert!(protected.get().is_some());
+        drop(protected);
+        for _ in 0..(RECLAIM_THRESHOLD - 1) {
+            root.replace(Some(Counted(Arc::clone(&dropped))));
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 2 * RECLAIM_THRESHOLD - 1);
+        drop(root);
+        assert_eq!(dropped.load(Ordering::SeqCst), 2 * RECLAIM_THRESHOLD);
+    }
+
+    #[test]
+    fn slots_are_reused() {
+        let root: AtomicRoot<usize> = AtomicRoot::new();
+        root.replace(Some(1));
+        {
+            let a = root.protect();
+            let b = root.protect();
+            assert_eq!(a.get(), Some(&1));
+            assert_eq!(b.get(), Some(&1));
+        }
+        let _c = root.protect();
+        let mut slots = 0;
+        let mut slot = root.slots.load(Ordering::Acquire);
+        while let Some(s) = unsafe { slot.as_ref() } {
+            slots += 1;
+            slot = s.next as *mut _;
+        }
+        assert_eq!(slots, 2);
+    }
+}
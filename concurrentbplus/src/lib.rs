@@ -0,0 +1,597 @@
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(not(feature = "hazard-pointer"))]
+mod epoch;
+#[cfg(not(feature = "hazard-pointer"))]
+use epoch::AtomicRoot;
+#[cfg(feature = "hazard-pointer")]
+mod hazard;
+#[cfg(feature = "hazard-pointer")]
+use hazard::AtomicRoot;
+
+mod buffered;
+mod partitioned;
+pub use buffered::{BufferWriter, BufferedBPlusTree};
+pub use partitioned::PartitionedBPlusTree;
+
+#[cfg(feature = "tokio")]
+mod asynctree;
+#[cfg(feature = "tokio")]
+pub use asynctree::AsyncBPlusTree;
+
+// 複数スレッドから共有して使う順序付きmap
+// 参照を返すとロックの外に持ち出せないので、値は clone して返す
+pub trait ConcurrentOrderedMap<K, V>: Send + Sync {
+    fn get(&self, key: &K) -> Option<V>;
+    fn insert(&self, key: K, value: V) -> Option<V>;
+    fn remove(&self, key: &K) -> Option<V>;
+    // min_key <= key <= max_key のエントリをキー順で返す
+    fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)>;
+}
+
+// ベンチマークの比較対象
+impl<K, V> ConcurrentOrderedMap<K, V> for Mutex<BTreeMap<K, V>>
+where
+    K: Ord + Clone + Send,
+    V: Clone + Send,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.lock().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        self.lock().unwrap().insert(key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        self.lock().unwrap().remove(key)
+    }
+
+    fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        if min_key > max_key {
+            return Vec::new();
+        }
+        self.lock()
+            .unwrap()
+            .range(min_key..=max_key)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+}
+
+// 書き込みは writer で直列化し、変更のあったノードだけをコピーして root を差し替える。
+// 読み込みはロックを取らずに root を読み、古い root の解放は epoch で遅延させる。
+// hazard-pointer feature を有効にすると、epoch の代わりに hazard pointer で保護する。
+// 古い root は木が drop された後に別のスレッドで解放されることがあるので、K と V は Send + Sync + 'static に限る
+#[derive(Debug)]
+pub struct ConcurrentBPlusTree<K, V> {
+    cap: usize,
+    root: AtomicRoot<Node<K, V>>,
+    writer: Mutex<()>,
+}
+
+impl<K, V> ConcurrentBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new(cap: usize) -> Self {
+        assert!(cap > 0, "cap must be greater than 0");
+        Self {
+            cap,
+            root: AtomicRoot::new(),
+            writer: Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let root = self.root.protect();
+        root.get().and_then(|n| n.search(key)).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let _writer = self.writer.lock().unwrap();
+        let root = self.root.protect();
+        let (new_root, old) = Node::insert_root(root.get(), self.cap, key, value);
+        self.root.replace(Some(new_root));
+        old
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let _writer = self.writer.lock().unwrap();
+        let root = self.root.protect();
+        let (new_root, old) = Node::remove_root(root.get()?, key)?;
+        self.root.replace(new_root);
+        Some(old)
+    }
+
+    // value が None のものは削除として扱う
+    // まとめて適用し、root の差し替えは最後に一度だけ行う
+    pub fn apply_batch<I>(&self, batch: I)
+    where
+        I: IntoIterator<Item = (K, Option<V>)>,
+    {
+        let _writer = self.writer.lock().unwrap();
+        let root = self.root.protect();
+        // 途中の root は公開しないので、そのまま捨ててよい
+        let mut new_root: Option<Option<Node<K, V>>> = None;
+        for (key, value) in batch {
+            let base = match &new_root {
+                Some(node) => node.as_ref(),
+                None => root.get(),
+            };
+            let next = match value {
+                Some(value) => Some(Some(Node::insert_root(base, self.cap, key, value).0)),
+                None => base
+                    .and_then(|b| Node::remove_root(b, &key))
+                    .map(|(node, _)| node),
+            };
+            if next.is_some() {
+                new_root = next;
+            }
+        }
+        if let Some(new_root) = new_root {
+            self.root.replace(new_root);
+        }
+    }
+
+    pub fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        self.range_limited(Bound::Included(min_key), max_key, usize::MAX)
+    }
+
+    // min_key から順に最大 limit 件を返す
+    // 続きを読むときは、最後に返したキーを Bound::Excluded で渡す
+    pub(crate) fn range_limited(
+        &self,
+        min_key: Bound<&K>,
+        max_key: &K,
+        limit: usize,
+    ) -> Vec<(K, V)> {
+        let mut result = Vec::new();
+        let scan = Scan {
+            min_key,
+            max_key,
+            limit,
+        };
+        if scan.is_empty() {
+            return result;
+        }
+        let root = self.root.protect();
+        if let Some(root) = root.get() {
+            root.search_range(&scan, &mut result);
+        }
+        result
+    }
+}
+
+impl<K, V> ConcurrentOrderedMap<K, V> for ConcurrentBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        ConcurrentBPlusTree::get(self, key)
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        ConcurrentBPlusTree::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        ConcurrentBPlusTree::remove(self, key)
+    }
+
+    fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        ConcurrentBPlusTree::range(self, min_key, max_key)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pair<K, T> {
+    key: K,
+    value: T,
+}
+
+impl<K, T> Pair<K, T> {
+    fn new(key: K, value: T) -> Self {
+        Self { key, value }
+    }
+}
+
+// range の検索条件
+struct Scan<'a, K> {
+    min_key: Bound<&'a K>,
+    max_key: &'a K,
+    limit: usize,
+}
+
+impl<K: Ord> Scan<'_, K> {
+    fn is_empty(&self) -> bool {
+        match self.min_key {
+            Bound::Included(min_key) => min_key > self.max_key,
+            Bound::Excluded(min_key) => min_key >= self.max_key,
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        let above_min = match self.min_key {
+            Bound::Included(min_key) => key >= min_key,
+            Bound::Excluded(min_key) => key > min_key,
+            Bound::Unbounded => true,
+        };
+        above_min && key <= self.max_key
+    }
+
+    // upper 未満のキーだけを持つノードに、検索対象が含まれうるか
+    fn reaches_below(&self, upper: &K) -> bool {
+        match self.min_key {
+            Bound::Included(min_key) | Bound::Excluded(min_key) => upper > min_key,
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+type NodePair<K, V> = Pair<K, Arc<Node<K, V>>>;
+// 変更後のノード、分割した場合の右側のノード、上書き前の値
+type Inserted<K, V> = (Node<K, V>, Option<Node<K, V>>, Option<V>);
+
+// 一度 root から辿れるようになったノードは変更しない
+#[derive(Debug, Clone)]
+enum Node<K, V> {
+    Internal(InternalNode<K, V>),
+    Leaf(LeafNode<K, V>),
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn insert_root(root: Option<&Self>, cap: usize, key: K, value: V) -> (Self, Option<V>) {
+        let root = match root {
+            None => return (Node::Leaf(LeafNode::new(vec![Pair::new(key, value)])), None),
+            Some(root) => root,
+        };
+        let (node, splited, old) = root.insert(cap, key, value);
+        let new_root = match splited {
+            None => node,
+            Some(right) => Node::Internal(InternalNode {
+                nodes: vec![
+                    NodePair::new(node.min_key().clone(), Arc::new(node)),
+                    NodePair::new(right.min_key().clone(), Arc::new(right)),
+                ],
+            }),
+        };
+        (new_root, old)
+    }
+
+    fn remove_root(root: &Self, key: &K) -> Option<(Option<Self>, V)> {
+        let (new_root, old) = root.remove(key)?;
+        let new_root = match new_root {
+            // 子が一つだけになった root は取り除いて高さを縮める
+            Some(Node::Internal(internal)) if internal.nodes.len() == 1 => {
+                Some(Node::clone(&internal.nodes[0].value))
+            }
+            new_root => new_root,
+        };
+        Some((new_root, old))
+    }
+
+    fn insert(&self, cap: usize, key: K, value: V) -> Inserted<K, V> {
+        match self {
+            Node::Internal(internal) => internal.insert(cap, key, value),
+            Node::Leaf(leaf) => leaf.insert(cap, key, value),
+        }
+    }
+
+    // key が存在しなければ None。ノードが空になった場合は Some((None, _)) を返す
+    fn remove(&self, key: &K) -> Option<(Option<Self>, V)> {
+        match self {
+            Node::Internal(internal) => internal.remove(key),
+            Node::Leaf(leaf) => leaf.remove(key),
+        }
+    }
+
+    fn search(&self, key: &K) -> Option<&V> {
+        match self {
+            Node::Internal(internal) => internal.search(key),
+            Node::Leaf(leaf) => leaf.search(key),
+        }
+    }
+
+    fn search_range(&self, scan: &Scan<'_, K>, result: &mut Vec<(K, V)>) {
+        match self {
+            Node::Internal(internal) => internal.search_range(scan, result),
+            Node::Leaf(leaf) => leaf.search_range(scan, result),
+        }
+    }
+
+    // 空のノードは木に残さないので、常に最小値が存在する
+    fn min_key(&self) -> &K {
+        match self {
+            Node::Internal(internal) => &internal.nodes[0].key,
+            Node::Leaf(leaf) => &leaf.data[0].key,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct InternalNode<K, V> {
+    nodes: Vec<NodePair<K, V>>,
+}
+
+impl<K, V> InternalNode<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn insert(&self, cap: usize, key: K, value: V) -> Inserted<K, V> {
+        let index = self.find_index(&key);
+        let (child, splited, old) = self.nodes[index].value.insert(cap, key, value);
+        // 子ノードは Arc で共有しているので、clone しても複製されるのはポインタだけ
+        let mut nodes = self.nodes.clone();
+        nodes[index] = NodePair::new(child.min_key().clone(), Arc::new(child));
+        if let Some(right) = splited {
+            nodes.insert(
+                index + 1,
+                NodePair::new(right.min_key().clone(), Arc::new(right)),
+            );
+        }
+        let mut node = Self { nodes };
+        let splited = if node.is_full(cap) {
+            Some(node.split())
+        } else {
+            None
+        };
+        (Node::Internal(node), splited, old)
+    }
+
+    fn remove(&self, key: &K) -> Option<(Option<Node<K, V>>, V)> {
+        let index = self.find_index(key);
+        let (child, old) = self.nodes[index].value.remove(key)?;
+        let mut nodes = self.nodes.clone();
+        match child {
+            // 最小値が消えても、nodes[index].key は下限として正しいのでそのままにする
+            Some(child) => nodes[index].value = Arc::new(child),
+            None => {
+                nodes.remove(index);
+            }
+        }
+        // underflow時のマージは行わない。空になったノードだけを取り除く
+        if nodes.is_empty() {
+            return Some((None, old));
+        }
+        Some((Some(Node::Internal(Self { nodes })), old))
+    }
+
+    fn split(&mut self) -> Node<K, V> {
+        let right = self.nodes.split_off(self.nodes.len() / 2);
+        Node::Internal(Self { nodes: right })
+    }
+
+    fn search(&self, key: &K) -> Option<&V> {
+        self.nodes[self.find_index(key)].value.search(key)
+    }
+
+    fn search_range(&self, scan: &Scan<'_, K>, result: &mut Vec<(K, V)>) {
+        // nodes[i] が担当するのは nodes[i].key 以上 nodes[i + 1].key 未満
+        // ただし先頭は nodes[0].key 未満も担当する
+        for (i, pair) in self.nodes.iter().enumerate() {
+            if result.len() >= scan.limit || (i > 0 && &pair.key > scan.max_key) {
+                break;
+            }
+            let reachable = self
+                .nodes
+                .get(i + 1)
+                .map(|next| scan.reaches_below(&next.key))
+                .unwrap_or(true);
+            if reachable {
+                pair.value.search_range(scan, result);
+            }
+        }
+    }
+
+    fn find_index(&self, key: &K) -> usize {
+        self.nodes
+            .partition_point(|pair| &pair.key <= key)
+            .saturating_sub(1)
+    }
+
+    // capacityに空きがあるかどうか
+    fn is_full(&self, cap: usize) -> bool {
+        // 暗黙的に最小値のキー分保持しているので、そのサイズ分無視するために１加算
+        self.nodes.len() > (cap + 1)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LeafNode<K, V> {
+    data: Vec<Pair<K, V>>,
+}
+
+impl<K, V> LeafNode<K, V>
+where
+    K: Ord + Clone,
+    V: Clone,
+{
+    fn new(data: Vec<Pair<K, V>>) -> Self {
+        Self { data }
+    }
+
+    fn insert(&self, cap: usize, key: K, value: V) -> Inserted<K, V> {
+        let mut data = self.data.clone();
+        let old = match data.binary_search_by(|p| p.key.cmp(&key)) {
+            Ok(i) => Some(std::mem::replace(&mut data[i].value, value)),
+            Err(i) => {
+                data.insert(i, Pair::new(key, value));
+                None
+            }
+        };
+        let mut node = Self::new(data);
+        let splited = if node.is_full(cap) {
+            Some(node.split())
+        } else {
+            None
+        };
+        (Node::Leaf(node), splited, old)
+    }
+
+    fn remove(&self, key: &K) -> Option<(Option<Node<K, V>>, V)> {
+        let index = self.data.binary_search_by(|p| p.key.cmp(key)).ok()?;
+        let mut data = self.data.clone();
+        let old = data.remove(index).value;
+        if data.is_empty() {
+            return Some((None, old));
+        }
+        Some((Some(Node::Leaf(Self::new(data))), old))
+    }
+
+    fn split(&mut self) -> Node<K, V> {
+        let right = self.data.split_off(self.data.len() / 2);
+        Node::Leaf(Self::new(right))
+    }
+
+    fn search(&self, key: &K) -> Option<&V> {
+        self.data
+            .binary_search_by(|p| p.key.cmp(key))
+            .ok()
+            .map(|i| &self.data[i].value)
+    }
+
+    fn search_range(&self, scan: &Scan<'_, K>, result: &mut Vec<(K, V)>) {
+        let rest = scan.limit - result.len();
+        result.extend(
+            self.data
+                .iter()
+                .filter(|p| scan.contains(&p.key))
+                .take(rest)
+                .map(|p| (p.key.clone(), p.value.clone())),
+        );
+    }
+
+    // capacityに空きがあるかどうか
+    fn is_full(&self, cap: usize) -> bool {
+        self.data.len() > cap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Barrier, thread};
+
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let t = ConcurrentBPlusTree::new(3);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+        }
+        assert_eq!(t.get(&24), Some(-24));
+        assert_eq!(t.get(&10), Some(-10));
+        assert_eq!(t.get(&19), None);
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(&12), Some(120));
+    }
+
+    #[test]
+    fn remove() {
+        let t = ConcurrentBPlusTree::new(3);
+        for k in 0..50 {
+            t.insert(k, k);
+        }
+        for k in (0..50).filter(|k| k % 3 != 0) {
+            assert_eq!(t.remove(&k), Some(k));
+        }
+        assert_eq!(t.remove(&1), None);
+        for k in 0..50 {
+            assert_eq!(t.get(&k), if k % 3 == 0 { Some(k) } else { None });
+        }
+        for k in (0..50).filter(|k| k % 3 == 0) {
+            assert_eq!(t.remove(&k), Some(k));
+        }
+        assert_eq!(t.range(&0, &100), vec![]);
+        t.insert(7, 7);
+        assert_eq!(t.get(&7), Some(7));
+    }
+
+    #[test]
+    fn range() {
+        let t = ConcurrentBPlusTree::new(3);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            t.insert(k, k);
+        }
+        assert_eq!(t.range(&11, &11), vec![(11, 11)]);
+        assert_eq!(
+            t.range(&11, &24),
+            vec![(11, 11), (12, 12), (13, 13), (14, 14), (24, 24)]
+        );
+        assert_eq!(t.range(&0, &100).len(), 7);
+        assert_eq!(t.range(&15, &23), vec![]);
+        assert_eq!(t.range(&24, &11), vec![]);
+    }
+
+    #[test]
+    fn apply_batch() {
+        let t = ConcurrentBPlusTree::new(3);
+        t.apply_batch((0..20).map(|k| (k, Some(k))));
+        t.apply_batch(vec![(3, None), (4, Some(40)), (100, None), (3, Some(30))]);
+        t.apply_batch((5..20).map(|k| (k, None)));
+        assert_eq!(
+            t.range(&0, &100),
+            vec![(0, 0), (1, 1), (2, 2), (3, 30), (4, 40)]
+        );
+    }
+
+    #[test]
+    fn concurrent_insert_and_read() {
+        let t = ConcurrentBPlusTree::new(4);
+        let threads = 4;
+        let per_thread = 500;
+        let barrier = Barrier::new(threads * 2);
+        thread::scope(|s| {
+            for n in 0..threads {
+                let (t, barrier) = (&t, &barrier);
+                s.spawn(move || {
+                    barrier.wait();
+                    for k in (n * per_thread)..((n + 1) * per_thread) {
+                        t.insert(k, k);
+                    }
+                });
+                s.spawn(move || {
+                    barrier.wait();
+                    for k in 0..(threads * per_thread) {
+                        // 書き込み途中でも、見えた値は常に正しい
+                        if let Some(v) = t.get(&k) {
+                            assert_eq!(v, k);
+                        }
+                        let r = t.range(&k, &(k + 10));
+                        assert!(r.windows(2).all(|w| w[0].0 < w[1].0));
+                    }
+                });
+            }
+        });
+        let all = t.range(&0, &usize::MAX);
+        assert_eq!(all.len(), threads * per_thread);
+        assert!(all.iter().all(|(k, v)| k == v));
+    }
+
+    #[test]
+    fn btreemap_baseline() {
+        let m = Mutex::new(BTreeMap::new());
+        assert_eq!(ConcurrentOrderedMap::insert(&m, 1, 10), None);
+        assert_eq!(ConcurrentOrderedMap::insert(&m, 2, 20), None);
+        assert_eq!(ConcurrentOrderedMap::get(&m, &2), Some(20));
+        assert_eq!(
+            ConcurrentOrderedMap::range(&m, &0, &5),
+            vec![(1, 10), (2, 20)]
+        );
+        assert_eq!(ConcurrentOrderedMap::range(&m, &5, &0), vec![]);
+        assert_eq!(ConcurrentOrderedMap::remove(&m, &1), Some(10));
+    }
+}
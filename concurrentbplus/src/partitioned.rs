@@ -0,0 +1,139 @@
+use crate::{ConcurrentBPlusTree, ConcurrentOrderedMap};
+
+// キーの範囲ごとに独立した木へ振り分ける
+// 書き込みのロックは木ごとなので、別のパーティションへの書き込みは並行して進む
+#[derive(Debug)]
+pub struct PartitionedBPlusTree<K, V> {
+    // partitions[i] は boundaries[i - 1] 以上 boundaries[i] 未満のキーを持つ
+    boundaries: Vec<K>,
+    partitions: Vec<ConcurrentBPlusTree<K, V>>,
+}
+
+impl<K, V> PartitionedBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    // boundaries.len() + 1 個のパーティションを作る
+    pub fn new(cap: usize, mut boundaries: Vec<K>) -> Self {
+        boundaries.sort();
+        boundaries.dedup();
+        let partitions = (0..=boundaries.len())
+            .map(|_| ConcurrentBPlusTree::new(cap))
+            .collect();
+        Self {
+            boundaries,
+            partitions,
+        }
+    }
+
+    pub fn partition_count(&self) -> usize {
+        self.partitions.len()
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.route(key).get(key)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.route(&key).insert(key, value)
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.route(key).remove(key)
+    }
+
+    // パーティションはキー順に並んでいるので、先頭から順に繋げればキー順になる
+    pub fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        if min_key > max_key {
+            return Vec::new();
+        }
+        let first = self.partition_index(min_key);
+        let last = self.partition_index(max_key);
+        self.partitions[first..=last]
+            .iter()
+            .flat_map(|p| p.range(min_key, max_key))
+            .collect()
+    }
+
+    fn route(&self, key: &K) -> &ConcurrentBPlusTree<K, V> {
+        &self.partitions[self.partition_index(key)]
+    }
+
+    fn partition_index(&self, key: &K) -> usize {
+        self.boundaries.partition_point(|b| b <= key)
+    }
+}
+
+impl<K, V> ConcurrentOrderedMap<K, V> for PartitionedBPlusTree<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        PartitionedBPlusTree::get(self, key)
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        PartitionedBPlusTree::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        PartitionedBPlusTree::remove(self, key)
+    }
+
+    fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        PartitionedBPlusTree::range(self, min_key, max_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn route() {
+        let t = PartitionedBPlusTree::<_, ()>::new(3, vec![20, 10, 30, 20]);
+        assert_eq!(t.partition_count(), 4);
+        assert_eq!(t.partition_index(&0), 0);
+        assert_eq!(t.partition_index(&10), 1);
+        assert_eq!(t.partition_index(&29), 2);
+        assert_eq!(t.partition_index(&100), 3);
+    }
+
+    #[test]
+    fn range_across_partitions() {
+        let t = PartitionedBPlusTree::new(3, vec![10, 20, 30]);
+        for k in (0..40).rev() {
+            assert_eq!(t.insert(k, k * 10), None);
+        }
+        assert_eq!(t.get(&25), Some(250));
+        assert_eq!(t.remove(&25), Some(250));
+        let r = t.range(&8, &31);
+        let keys: Vec<_> = r.iter().map(|(k, _)| *k).collect();
+        let expected: Vec<_> = (8..=31).filter(|&k| k != 25).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(t.range(&12, &18).len(), 7);
+        assert_eq!(t.range(&18, &12), vec![]);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let t = PartitionedBPlusTree::new(8, vec![1000, 2000, 3000]);
+        thread::scope(|s| {
+            for n in 0..4 {
+                let t = &t;
+                s.spawn(move || {
+                    for k in (n * 1000)..((n + 1) * 1000) {
+                        t.insert(k, k);
+                    }
+                });
+            }
+        });
+        let all = t.range(&0, &usize::MAX);
+        assert_eq!(all.len(), 4000);
+        assert!(all.iter().enumerate().all(|(i, &(k, v))| i == k && k == v));
+    }
+}
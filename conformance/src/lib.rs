@@ -0,0 +1,311 @@
+// OrderedMap の実装が同じように振る舞うかを確かめるテスト
+// どの操作も BTreeMap に同じように適用し、結果を比べる
+// 新しい実装は suite! にコンストラクタを渡すだけで全てのケースを実行できる
+// 木の形などの不変条件は、操作のたびに呼ぶ関数として 3 つ目に渡す
+//
+//   conformance::suite!(bplus, |cap| ::bplus::BPlusTree::<usize>::new(cap));
+//   conformance::suite!(avl, |cap| ::avl::AvlTree::new(), |t, _| t.check_invariants());
+use std::collections::BTreeMap;
+
+pub use orderedmap::{Op, OrderedMap};
+use proptest::{prelude::*, test_runner::TestRunner};
+
+// 分割がちょうど起きる境界を含むように、小さい cap を多めに選ぶ
+pub const CAPS: [usize; 7] = [1, 2, 3, 4, 5, 8, 64];
+
+// 全てのケースを cap ごとに #[test] として定義する
+#[macro_export]
+macro_rules! suite {
+    ($name:ident, |$cap:ident| $new:expr) => {
+        $crate::suite!($name, |$cap| $new, |_, _| ());
+    };
+    ($name:ident, |$cap:ident| $new:expr, $invariants:expr) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::suite!(@cases $cap, $new;
+                insert_and_get,
+                replace_existing,
+                remove,
+                range,
+                split_boundaries,
+                duplicate_keys_at_split,
+                lower_bound_keys,
+                mixed_ops,
+            );
+
+            #[test]
+            #[allow(unused_variables)]
+            fn same_as_btree_map_random() {
+                $crate::same_as_btree_map_random(|$cap| $new, $invariants);
+            }
+        }
+    };
+    (@cases $cap:ident, $new:expr; $($case:ident,)*) => {
+        $(
+            #[test]
+            fn $case() {
+                for &$cap in $crate::CAPS.iter() {
+                    let mut map = $new;
+                    $crate::$case(&mut map, $cap);
+                }
+            }
+        )*
+    };
+}
+
+// ops を map と BTreeMap の両方に適用し、結果が同じことを確かめる
+// 最後に全体を iter で比べる
+pub fn check<M: OrderedMap<usize, usize>>(
+    map: &mut M,
+    cap: usize,
+    ops: impl IntoIterator<Item = Op<usize, usize>>,
+) {
+    let mut expected = BTreeMap::new();
+    for (i, op) in ops.into_iter().enumerate() {
+        let result = op.clone().apply(map);
+        assert_eq!(
+            result,
+            op.clone().apply(&mut expected),
+            "cap {}: op #{} {:?}",
+            cap,
+            i,
+            op
+        );
+    }
+    let entries: Vec<_> = map.iter().map(|(k, v)| (k, *v)).collect();
+    let expected: Vec<_> = expected.into_iter().collect();
+    assert_eq!(entries, expected, "cap {}: entries", cap);
+}
+
+// 昇順、降順、交互の順に入れてから、全てのキーと無いキーを探す
+pub fn insert_and_get<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 8 + 3;
+    let keys = (0..n)
+        .map(|k| k * 3)
+        .chain((0..n).rev().map(|k| k * 3 + 1))
+        .chain((0..n).map(|k| {
+            if k % 2 == 0 {
+                k * 3 + 2
+            } else {
+                (n - k) * 3 + 2
+            }
+        }));
+    let ops = keys
+        .map(|k| Op::Insert(k, k * 10))
+        .chain((0..n * 3 + 3).map(Op::Search))
+        .collect::<Vec<_>>();
+    check(map, cap, ops);
+}
+
+// 既にあるキーは値を置き換え、エントリは増えない
+pub fn replace_existing<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 6;
+    let ops = (0..n)
+        .map(|k| Op::Insert(k, k))
+        .chain((0..n).rev().map(|k| Op::Insert(k, k + 1000)))
+        .chain((0..n).map(Op::Search));
+    check(map, cap, ops);
+}
+
+// 無いキーの削除、全ての削除、削除後の再挿入
+pub fn remove<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 6 + 1;
+    let ops = (0..n)
+        .map(|k| Op::Insert(k * 2, k))
+        .chain((0..n * 2).map(Op::Remove))
+        .chain((0..n).map(|k| Op::Search(k * 2)))
+        .chain((0..n).map(|k| Op::Insert(k, k)))
+        .chain((0..n).step_by(2).map(Op::Remove))
+        .chain((0..n).map(Op::Search));
+    check(map, cap, ops);
+}
+
+// 端を含む範囲、min_key == max_key、min_key > max_key、キーの無い範囲
+pub fn range<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 8;
+    let mut ops: Vec<_> = (0..n).map(|k| Op::Insert(k * 2 + 10, k)).collect();
+    let max = n * 2 + 20;
+    for min_key in (0..max).step_by(3) {
+        for width in [0, 1, 2, cap, cap * 2 + 1, max] {
+            ops.push(Op::Range(min_key, min_key + width));
+        }
+        ops.push(Op::Range(min_key + 1, min_key));
+    }
+    ops.push(Op::Range(0, usize::MAX));
+    ops.push(Op::Range(usize::MAX, usize::MAX));
+    check(map, cap, ops);
+}
+
+// 0 から 3 * cap + 2 個まで、分割がちょうど起きる前後の大きさで確かめる
+pub fn split_boundaries<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    // 前の大きさで入れたエントリを消してから入れ直す
+    let ops = (0..=cap * 3 + 2).flat_map(|n| {
+        (0..n)
+            .map(Op::Remove)
+            .chain((0..n).map(move |k| Op::Insert(k, n)))
+            .chain([Op::Range(0, n), Op::Search(n / 2)])
+    });
+    check(map, cap, ops);
+}
+
+// 分割された後に、左右どちらの leaf にも入りうるキーを置き換える
+pub fn duplicate_keys_at_split<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 4 + 2;
+    let ops = (0..n)
+        .map(|k| Op::Insert(k, k))
+        .chain((0..n).map(|k| Op::Insert(k, k + 1)))
+        .chain((0..n).flat_map(|k| [Op::Remove(k), Op::Insert(k, k + 2), Op::Search(k)]));
+    check(map, cap, ops);
+}
+
+// それまでの最小値より小さいキーを入れ続けると、ノードの下限のキーが更新される
+pub fn lower_bound_keys<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let n = cap * 8 + 5;
+    let ops = (0..n)
+        .rev()
+        .map(|k| Op::Insert(k * 10 + 100, k))
+        .chain((0..n).map(|k| Op::Insert(k * 10 + 95, k)))
+        .chain((0..n * 10 + 110).map(Op::Search))
+        .chain([Op::Range(0, 100), Op::Range(95, 105)]);
+    check(map, cap, ops);
+}
+
+// 決まった擬似乱数で作った操作列
+pub fn mixed_ops<M: OrderedMap<usize, usize>>(map: &mut M, cap: usize) {
+    let mut x = 0x2545_f491_u64 ^ cap as u64;
+    let mut next = move |n: u64| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x % n) as usize
+    };
+    let ops: Vec<_> = (0..2000)
+        .map(|_| match next(8) {
+            0..=3 => Op::Insert(next(300), next(1000)),
+            4 | 5 => Op::Remove(next(300)),
+            6 => Op::Search(next(300)),
+            _ => {
+                let min_key = next(300);
+                Op::Range(min_key, min_key + next(40))
+            }
+        })
+        .collect();
+    check(map, cap, ops);
+}
+
+// ランダムな cap と操作列を map と BTreeMap の両方に適用し、全ての結果が同じことを確かめる
+// 操作のたびに invariants に map と BTreeMap を渡すので、実装ごとの不変条件もその場で確かめられる
+pub fn same_as_btree_map_random<M, N, F>(new: N, invariants: F)
+where
+    M: OrderedMap<usize, usize>,
+    N: Fn(usize) -> M,
+    F: Fn(&M, &BTreeMap<usize, usize>),
+{
+    let strategy = (
+        prop::sample::select(CAPS.to_vec()),
+        prop::collection::vec((0..5u8, 0..200usize, 0..200usize), 0..400),
+    );
+    let result = TestRunner::default().run(&strategy, |(cap, ops)| {
+        let mut map = new(cap);
+        let mut expected = BTreeMap::new();
+        for (op, a, b) in ops {
+            let op = match op {
+                0 | 1 => Op::Insert(a, b),
+                2 => Op::Remove(a),
+                3 => Op::Search(a),
+                _ => Op::Range(a, b),
+            };
+            prop_assert_eq!(
+                op.clone().apply(&mut map),
+                op.apply(&mut expected),
+                "cap {}",
+                cap
+            );
+            invariants(&map, &expected);
+        }
+        prop_assert!(map.iter().eq(OrderedMap::iter(&expected)), "cap {}", cap);
+        Ok(())
+    });
+    if let Err(e) = result {
+        panic!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    suite!(btree_map, |cap| BTreeMap::<usize, usize>::new());
+    suite!(bplus, |cap| ::bplus::BPlusTree::<usize>::new(cap));
+    suite!(unsafebplus, |cap| ::unsafebplus::BPlusTree::new(cap));
+    suite!(arena, |cap| ::unsafebplus::ArenaBPlusTree::new(cap));
+    suite!(avl, |cap| ::avl::AvlTree::new(), |t, _| {
+        t.check_invariants()
+    });
+    suite!(
+        rbtree,
+        |cap| ::rbtree::RBTree::<usize, usize>::new(),
+        |t, _| assert_eq!(t.validate(), Ok(()))
+    );
+    // cap を持たないので、cap を seed にして形を変える
+    suite!(
+        treap,
+        |cap| ::treap::Treap::<usize, usize>::with_seed(cap as u64),
+        |t, expected| {
+            t.check_invariants();
+            assert_eq!(t.len(), expected.len());
+        }
+    );
+    suite!(
+        splay,
+        |cap| ::splay::SplayTree::<usize, usize>::new(),
+        |t, _| t.check_invariants()
+    );
+    // cap から alpha を決めて、どこまで偏りを許すかを変える
+    suite!(
+        scapegoat,
+        |cap| ::scapegoat::ScapegoatTree::<usize, usize>::new(0.55 + (cap % 8) as f64 * 0.05),
+        |t, _| t.check_invariants()
+    );
+    // cap をキーの最大数 2 * degree - 1 とみなして、次数を決める
+    suite!(
+        btree,
+        |cap| ::btree::BTree::<usize, usize>::new(cap.div_ceil(2).max(::btree::MIN_DEGREE)),
+        |t, _| t.check_invariants()
+    );
+    // キーはビッグエンディアンのバイト列として入れる
+    suite!(art, |cap| ::art::Art::new(), |t, _| t.check_invariants());
+    suite!(qptrie, |cap| ::qptrie::QpTrie::new(), |t, _| {
+        t.check_invariants()
+    });
+    suite!(
+        tree234,
+        |cap| ::tree234::Tree234::<usize, usize>::new(),
+        |t, _| t.check_invariants()
+    );
+    // 順序統計量も BTreeMap の並びと比べる
+    suite!(
+        wbtree,
+        |cap| ::wbtree::WBTree::<usize, usize>::new(),
+        |t, expected| {
+            t.check_invariants();
+            for (i, (k, v)) in expected.iter().enumerate() {
+                assert_eq!(t.rank(*k), i);
+                assert_eq!(t.select(i), Some((*k, v)));
+            }
+            assert_eq!(t.select(expected.len()), None);
+        }
+    );
+    // 更新のたびに新しい版で置き換える
+    suite!(persistentmap, |cap| {
+        ::persistentmap::PersistentMap::<usize, usize>::new()
+    });
+    // cap を持たないので、cap を seed にして形を変える
+    suite!(
+        skiplist,
+        |cap| ::skiplist::SkipList::<usize, usize>::with_seed(cap as u64),
+        |l, expected| assert_eq!(l.len(), expected.len())
+    );
+}
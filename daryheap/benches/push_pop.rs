@@ -0,0 +1,75 @@
+// 子の数の違う DaryHeap と binaryheap、std の BinaryHeap を同じ操作で比べる
+//   cargo bench -p daryheap
+// push_heavy は 4 回 push するごとに 1 回 pop する。D が大きいほど木が低く、push が速い
+// drain は全て入れてから全て取り出す。pop で D 個の子を比べるので、D が大きすぎると遅くなる
+use std::collections::BinaryHeap as StdHeap;
+
+use binaryheap::BinaryHeap;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use daryheap::DaryHeap;
+use priorityqueue::PriorityQueue;
+
+const SIZES: [usize; 2] = [1_000, 100_000];
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn values(n: usize) -> Vec<u64> {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+    (0..n).map(|_| xorshift(&mut state)).collect()
+}
+
+fn push_heavy<Q: PriorityQueue<u64> + Default>(values: &[u64]) -> Q {
+    let mut q = Q::default();
+    for (i, &v) in values.iter().enumerate() {
+        q.push(v);
+        if i % 4 == 3 {
+            q.pop();
+        }
+    }
+    q
+}
+
+fn drain<Q: PriorityQueue<u64> + Default>(values: &[u64]) -> u64 {
+    let mut q = Q::default();
+    for &v in values {
+        q.push(v);
+    }
+    let mut sum = 0u64;
+    while let Some(v) = q.pop() {
+        sum = sum.wrapping_add(v);
+    }
+    sum
+}
+
+fn bench_one<Q: PriorityQueue<u64> + Default>(c: &mut Criterion, name: &str) {
+    for n in SIZES {
+        let values = values(n);
+        c.bench_with_input(
+            BenchmarkId::new(format!("push_heavy/{}", name), n),
+            &values,
+            |b, values| b.iter(|| push_heavy::<Q>(values)),
+        );
+        c.bench_with_input(
+            BenchmarkId::new(format!("drain/{}", name), n),
+            &values,
+            |b, values| b.iter(|| drain::<Q>(values)),
+        );
+    }
+}
+
+fn heaps(c: &mut Criterion) {
+    bench_one::<DaryHeap<u64, 2>>(c, "d2");
+    bench_one::<DaryHeap<u64, 4>>(c, "d4");
+    bench_one::<DaryHeap<u64, 8>>(c, "d8");
+    bench_one::<DaryHeap<u64, 16>>(c, "d16");
+    bench_one::<BinaryHeap<u64>>(c, "binaryheap");
+    bench_one::<StdHeap<u64>>(c, "std");
+}
+
+criterion_group!(benches, heaps);
+criterion_main!(benches);
@@ -0,0 +1,277 @@
+// 配列で持つ d 分ヒープ。子の数 D は const generic で決める
+// 位置 i の子は D * i + 1 から D * i + D で、親は (i - 1) / D になる
+// 高さは log_D n なので、push (親と比べて上げる) は D が大きいほど速い
+// pop (D 個の子から先に取り出すものを選んで下げる) は 1 段あたり D 回比べるので、D * log_D n 回になる
+// push が多く pop が少ない使い方では D を大きくし、pop が多ければ 2 や 4 にする。比べ方は benches/push_pop.rs
+//
+// 比べ方は binaryheap と同じく型で選ぶ
+use std::{cmp::Ordering, iter::FromIterator};
+
+pub use priorityqueue::{Compare, Max, Min, PriorityQueue};
+
+#[derive(Debug, Clone)]
+pub struct DaryHeap<T, const D: usize, C = Max> {
+    data: Vec<T>,
+    cmp: C,
+}
+
+impl<T: Ord, const D: usize> DaryHeap<T, D> {
+    // 大きいものから取り出す
+    pub fn new() -> Self {
+        Self::with_comparator(Max)
+    }
+
+    pub fn from_vec(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Max)
+    }
+}
+
+impl<T: Ord, const D: usize> DaryHeap<T, D, Min> {
+    // 小さいものから取り出す
+    pub fn new_min() -> Self {
+        Self::with_comparator(Min)
+    }
+
+    pub fn from_vec_min(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Min)
+    }
+}
+
+impl<T, C: Compare<T>, const D: usize> DaryHeap<T, D, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        assert!(D >= 2, "arity must be at least 2");
+        Self {
+            data: Vec::new(),
+            cmp,
+        }
+    }
+
+    // vec をそのままヒープに並べ替える。子を持つ位置を後ろから下げていくので O(n)
+    pub fn from_vec_by(vec: Vec<T>, cmp: C) -> Self {
+        let mut heap = Self::with_comparator(cmp);
+        heap.data = vec;
+        let len = heap.data.len();
+        for i in (0..len.saturating_sub(1).div_ceil(D)).rev() {
+            heap.sift_down(i, len);
+        }
+        heap
+    }
+
+    pub fn arity(&self) -> usize {
+        D
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.data.push(value);
+        self.sift_up(self.data.len() - 1);
+    }
+
+    // 次に取り出す要素
+    pub fn peek(&self) -> Option<&T> {
+        self.data.first()
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let last = self.data.len().checked_sub(1)?;
+        self.data.swap(0, last);
+        let top = self.data.pop();
+        self.sift_down(0, self.data.len());
+        top
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    // ヒープの並びのまま返す。順序は決めない
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.data
+    }
+
+    // compare で小さい順 (取り出す順の逆) に並べる。Max なら昇順、Min なら降順になる
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        let mut end = self.data.len();
+        while end > 1 {
+            end -= 1;
+            self.data.swap(0, end);
+            self.sift_down(0, end);
+        }
+        self.data
+    }
+
+    fn before(&self, a: usize, b: usize) -> bool {
+        self.cmp.compare(&self.data[a], &self.data[b]) == Ordering::Greater
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / D;
+            if !self.before(i, parent) {
+                break;
+            }
+            self.data.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    // data[..end] をヒープとして、位置 i の要素を下げる
+    fn sift_down(&mut self, mut i: usize, end: usize) {
+        loop {
+            let first = D * i + 1;
+            let mut top = i;
+            for child in first..(first + D).min(end) {
+                if self.before(child, top) {
+                    top = child;
+                }
+            }
+            if top == i {
+                break;
+            }
+            self.data.swap(i, top);
+            i = top;
+        }
+    }
+
+    // 親が子より後に取り出すものになっていないか確かめる
+    pub fn check_invariants(&self) {
+        for i in 1..self.data.len() {
+            assert!(!self.before(i, (i - 1) / D), "child is before its parent");
+        }
+    }
+}
+
+impl<T, C: Compare<T> + Default, const D: usize> Default for DaryHeap<T, D, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T: Ord, const D: usize> From<Vec<T>> for DaryHeap<T, D> {
+    fn from(vec: Vec<T>) -> Self {
+        Self::from_vec(vec)
+    }
+}
+
+impl<T, C: Compare<T> + Default, const D: usize> FromIterator<T> for DaryHeap<T, D, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from_vec_by(iter.into_iter().collect(), C::default())
+    }
+}
+
+impl<T, C: Compare<T>, const D: usize> Extend<T> for DaryHeap<T, D, C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, C: Compare<T>, const D: usize> PriorityQueue<T> for DaryHeap<T, D, C> {
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn push(&mut self, value: T) {
+        DaryHeap::push(self, value)
+    }
+
+    fn peek(&self) -> Option<&T> {
+        DaryHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        DaryHeap::pop(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Reverse;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn order() {
+        let mut heap = DaryHeap::<_, 4>::from_vec(vec![3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5]);
+        heap.check_invariants();
+        assert_eq!(heap.arity(), 4);
+        assert_eq!(heap.peek(), Some(&9));
+        heap.push(8);
+        assert_eq!(heap.pop(), Some(9));
+        assert_eq!(heap.pop(), Some(8));
+        assert_eq!(heap.into_sorted_vec(), vec![1, 1, 2, 3, 3, 4, 5, 5, 5, 6]);
+
+        let mut min: DaryHeap<_, 3, Min> = vec![7, 2, 9, 4].into_iter().collect();
+        min.check_invariants();
+        assert_eq!(min.pop(), Some(2));
+        assert_eq!(min.pop(), Some(4));
+
+        let mut empty = DaryHeap::<i32, 8>::new();
+        assert_eq!(empty.pop(), None);
+        assert_eq!(
+            DaryHeap::<i32, 2>::from_vec(vec![1]).into_sorted_vec(),
+            vec![1]
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(i32),
+        Pop,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![(-50..50i32).prop_map(Op::Push), Just(Op::Pop)]
+    }
+
+    // 標準の BinaryHeap と同じ操作列を適用し、全ての結果を比べる
+    fn same_as_std<const D: usize>(init: &[i32], ops: &[Op]) -> Result<(), TestCaseError> {
+        let mut heap = DaryHeap::<_, D, Min>::from_vec_min(init.to_vec());
+        heap.check_invariants();
+        let mut expected: std::collections::BinaryHeap<_> =
+            init.iter().copied().map(Reverse).collect();
+        for op in ops {
+            match op {
+                Op::Push(v) => {
+                    heap.push(*v);
+                    expected.push(Reverse(*v));
+                }
+                Op::Pop => prop_assert_eq!(heap.pop(), expected.pop().map(|r| r.0)),
+            }
+            prop_assert_eq!(heap.peek(), expected.peek().map(|r| &r.0));
+            prop_assert_eq!(heap.len(), expected.len());
+        }
+        heap.check_invariants();
+        let mut sorted = init.to_vec();
+        sorted.sort_unstable();
+        prop_assert_eq!(
+            DaryHeap::<_, D>::from_vec(init.to_vec()).into_sorted_vec(),
+            sorted
+        );
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn matches_std(init in prop::collection::vec(-50..50i32, 0..100), ops in prop::collection::vec(op(), 0..200)) {
+            same_as_std::<2>(&init, &ops)?;
+            same_as_std::<3>(&init, &ops)?;
+            same_as_std::<4>(&init, &ops)?;
+            same_as_std::<16>(&init, &ops)?;
+        }
+    }
+}
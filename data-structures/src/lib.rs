@@ -0,0 +1,180 @@
+// 各実装をまとめて使うための crate
+// 実装は feature で選ぶ
+//   safe        : bplus (default)
+//   unsafe-fast : unsafebplus
+//   concurrent  : concurrentbplus
+//   disk        : diskbplus
+//   skiplist    : skiplist
+//   avl         : avl
+//   rbtree      : rbtree
+//   treap       : treap
+//   splay       : splay
+//   scapegoat   : scapegoat
+//   btree       : btree
+//   art         : art
+//   patricia    : patricia
+//   qptrie      : qptrie
+//   hamt        : hamt
+//   persistentmap : persistentmap
+//   rrbvec      : rrbvec
+//   fingertree  : fingertree
+//   rope        : rope
+//   gapbuffer   : gapbuffer
+//   piecetable  : piecetable
+//   openhash    : openhash
+//   bloom       : bloom
+//   segtree     : segtree
+//   fenwick     : fenwick
+//   unionfind   : unionfind
+//   intervaltree : intervaltree
+//   rangetree   : rangetree
+//   kdtree      : kdtree
+//   rtree       : rtree
+//   quadtree    : quadtree
+//   octree      : octree
+//   suffixarray : suffixarray
+//   suffixautomaton : suffixautomaton
+//   ahocorasick : ahocorasick
+//   trie        : trie
+//   binaryheap  : binaryheap
+//   daryheap    : daryheap
+//   pairingheap : pairingheap
+//   fibonacciheap : fibonacciheap
+//   leftistheap : leftistheap
+//   skewheap    : skewheap
+//   indexedpq   : indexedpq
+//   lrucache    : lrucache
+//   lfucache    : lfucache
+//   arccache    : arccache
+//   tree234     : tree234
+//   wbtree      : wbtree
+// 実装は crate 名のまま re-export するので、data_structures::bplus::BPlusTree のように使う
+pub use cache::Cache;
+pub use orderedmap::{Op, OrderedMap};
+pub use priorityqueue::{Compare, Max, MergeableHeap, Min, PriorityQueue};
+pub use spatial::{Coordinate, Rect, SpatialIndex};
+pub use textbuffer::TextBuffer;
+
+#[cfg(feature = "ahocorasick")]
+pub use ahocorasick;
+#[cfg(feature = "arccache")]
+pub use arccache;
+#[cfg(feature = "art")]
+pub use art;
+#[cfg(feature = "avl")]
+pub use avl;
+#[cfg(feature = "binaryheap")]
+pub use binaryheap;
+#[cfg(feature = "bloom")]
+pub use bloom;
+#[cfg(feature = "safe")]
+pub use bplus;
+#[cfg(feature = "btree")]
+pub use btree;
+#[cfg(feature = "concurrent")]
+pub use concurrentbplus;
+#[cfg(feature = "daryheap")]
+pub use daryheap;
+#[cfg(feature = "disk")]
+pub use diskbplus;
+#[cfg(feature = "fenwick")]
+pub use fenwick;
+#[cfg(feature = "fibonacciheap")]
+pub use fibonacciheap;
+#[cfg(feature = "fingertree")]
+pub use fingertree;
+#[cfg(feature = "gapbuffer")]
+pub use gapbuffer;
+#[cfg(feature = "hamt")]
+pub use hamt;
+#[cfg(feature = "indexedpq")]
+pub use indexedpq;
+#[cfg(feature = "intervaltree")]
+pub use intervaltree;
+#[cfg(feature = "kdtree")]
+pub use kdtree;
+#[cfg(feature = "leftistheap")]
+pub use leftistheap;
+#[cfg(feature = "lfucache")]
+pub use lfucache;
+#[cfg(feature = "lrucache")]
+pub use lrucache;
+#[cfg(feature = "octree")]
+pub use octree;
+#[cfg(feature = "openhash")]
+pub use openhash;
+#[cfg(feature = "pairingheap")]
+pub use pairingheap;
+#[cfg(feature = "patricia")]
+pub use patricia;
+#[cfg(feature = "persistentmap")]
+pub use persistentmap;
+#[cfg(feature = "piecetable")]
+pub use piecetable;
+#[cfg(feature = "qptrie")]
+pub use qptrie;
+#[cfg(feature = "quadtree")]
+pub use quadtree;
+#[cfg(feature = "rangetree")]
+pub use rangetree;
+#[cfg(feature = "rbtree")]
+pub use rbtree;
+#[cfg(feature = "rope")]
+pub use rope;
+#[cfg(feature = "rrbvec")]
+pub use rrbvec;
+#[cfg(feature = "rtree")]
+pub use rtree;
+#[cfg(feature = "scapegoat")]
+pub use scapegoat;
+#[cfg(feature = "segtree")]
+pub use segtree;
+#[cfg(feature = "skewheap")]
+pub use skewheap;
+#[cfg(feature = "skiplist")]
+pub use skiplist;
+#[cfg(feature = "splay")]
+pub use splay;
+#[cfg(feature = "suffixarray")]
+pub use suffixarray;
+#[cfg(feature = "suffixautomaton")]
+pub use suffixautomaton;
+#[cfg(feature = "treap")]
+pub use treap;
+#[cfg(feature = "tree234")]
+pub use tree234;
+#[cfg(feature = "trie")]
+pub use trie;
+#[cfg(feature = "unionfind")]
+pub use unionfind;
+#[cfg(feature = "unsafe-fast")]
+pub use unsafebplus;
+#[cfg(feature = "wbtree")]
+pub use wbtree;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fill<M: OrderedMap<usize, usize>>(m: &mut M) {
+        for k in 0..100 {
+            m.insert(k, k * 2);
+        }
+    }
+
+    #[cfg(feature = "safe")]
+    #[test]
+    fn safe() {
+        let mut tree = bplus::BPlusTree::new(4);
+        fill(&mut tree);
+        assert_eq!(tree.get(10), Some(&20));
+    }
+
+    #[cfg(feature = "unsafe-fast")]
+    #[test]
+    fn unsafe_fast() {
+        let mut tree = unsafebplus::BPlusTree::new(4);
+        fill(&mut tree);
+        assert_eq!(tree.get(10), Some(&20));
+    }
+}
@@ -0,0 +1,149 @@
+// 非同期にページを読み書きする backend
+// io_uring やオブジェクトストレージなどは AsyncPageStore を実装すれば AsyncDiskBPlusTree で使える
+use std::{future::Future, io::SeekFrom, path::Path};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use crate::{
+    page::{decode_page, encode_page, Page, PageId, PAGE_SIZE},
+    pager::Superblock,
+    Error, Result,
+};
+
+pub trait AsyncPageStore: Send + Sync {
+    fn read_page(&self, id: PageId) -> impl Future<Output = Result<Page>> + Send;
+
+    fn write_page(&self, id: PageId, page: &Page) -> impl Future<Output = Result<()>> + Send;
+
+    // 新しいページを確保する。書き込むまで中身は不定
+    // TokioFileStore は空きページを再利用しない
+    fn allocate(&self) -> impl Future<Output = Result<PageId>> + Send;
+
+    fn root(&self) -> impl Future<Output = Result<Option<PageId>>> + Send;
+
+    fn set_root(&self, root: Option<PageId>) -> impl Future<Output = Result<()>> + Send;
+
+    // 書き込んだページと root を永続化する
+    fn sync(&self) -> impl Future<Output = Result<()>> + Send;
+}
+
+// tokio::fs でファイルを読み書きする
+// ファイルの形式は DiskBPlusTree と同じなので、どちらからも開ける
+#[derive(Debug)]
+pub struct TokioFileStore {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    superblock: Superblock,
+}
+
+impl TokioFileStore {
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+        let len = file.metadata().await?.len();
+        let superblock = if len == 0 {
+            let superblock = Superblock {
+                root: None,
+                page_count: 1,
+                checkpoint_lsn: 0,
+                free_head: None,
+            };
+            let mut buf = vec![0; PAGE_SIZE];
+            superblock.encode(&mut buf);
+            file.write_all(&buf).await?;
+            superblock
+        } else {
+            if len < PAGE_SIZE as u64 {
+                return Err(Error::InvalidFile("file is smaller than a page"));
+            }
+            let mut buf = vec![0; PAGE_SIZE];
+            file.read_exact(&mut buf).await?;
+            let superblock = Superblock::decode(&buf)?;
+            if superblock.page_count * PAGE_SIZE as u64 > len {
+                return Err(Error::InvalidFile("page count out of range"));
+            }
+            superblock
+        };
+        Ok(Self {
+            inner: Mutex::new(Inner { file, superblock }),
+        })
+    }
+}
+
+impl Inner {
+    fn offset(&self, id: PageId) -> Result<u64> {
+        if id == 0 || id >= self.superblock.page_count {
+            return Err(Error::Corrupted("page id out of range"));
+        }
+        Ok(id * PAGE_SIZE as u64)
+    }
+}
+
+impl AsyncPageStore for TokioFileStore {
+    async fn read_page(&self, id: PageId) -> Result<Page> {
+        let mut inner = self.inner.lock().await;
+        let offset = inner.offset(id)?;
+        let mut buf = vec![0; PAGE_SIZE];
+        inner.file.seek(SeekFrom::Start(offset)).await?;
+        inner.file.read_exact(&mut buf).await?;
+        decode_page(&buf).map_err(|e| match e {
+            Error::ChecksumMismatch => Error::CorruptPage { page_id: id },
+            e => e,
+        })
+    }
+
+    async fn write_page(&self, id: PageId, page: &Page) -> Result<()> {
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page(page, &mut buf)?;
+        let mut inner = self.inner.lock().await;
+        let offset = inner.offset(id)?;
+        inner.file.seek(SeekFrom::Start(offset)).await?;
+        inner.file.write_all(&buf).await?;
+        Ok(())
+    }
+
+    async fn allocate(&self) -> Result<PageId> {
+        let mut inner = self.inner.lock().await;
+        let id = inner.superblock.page_count;
+        inner.superblock.page_count += 1;
+        Ok(id)
+    }
+
+    async fn root(&self) -> Result<Option<PageId>> {
+        Ok(self.inner.lock().await.superblock.root)
+    }
+
+    async fn set_root(&self, root: Option<PageId>) -> Result<()> {
+        self.inner.lock().await.superblock.root = root;
+        Ok(())
+    }
+
+    // ページを書き出してから superblock を書き出す
+    async fn sync(&self) -> Result<()> {
+        let mut inner = self.inner.lock().await;
+        let len = inner.superblock.page_count * PAGE_SIZE as u64;
+        if inner.file.metadata().await?.len() < len {
+            inner.file.set_len(len).await?;
+        }
+        inner.file.sync_data().await?;
+        let mut buf = vec![0; PAGE_SIZE];
+        inner.superblock.encode(&mut buf);
+        inner.file.seek(SeekFrom::Start(0)).await?;
+        inner.file.write_all(&buf).await?;
+        inner.file.sync_all().await?;
+        Ok(())
+    }
+}
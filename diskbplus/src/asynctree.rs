@@ -0,0 +1,346 @@
+// AsyncPageStore の上の B+ tree
+// ページの形式と木の形は DiskBPlusTree と同じ
+// async fn は再帰できないので、辿ったページを path に積んで下から順に処理する
+use std::mem;
+
+use crate::{
+    asyncstore::AsyncPageStore,
+    page::{InternalPage, LeafPage, Page, PageId, MAX_VALUE_LEN, PAGE_SIZE},
+    tree::{find_index, leaf_split_point},
+    Error, Key, Result,
+};
+
+#[derive(Debug)]
+pub struct AsyncDiskBPlusTree<S> {
+    store: S,
+}
+
+// 辿った internal ページと、次に辿った子の位置
+struct Step {
+    page_id: PageId,
+    internal: InternalPage,
+    index: usize,
+    // 子が持つキーの下限。木の左端では None
+    child_lower: Option<Key>,
+}
+
+impl<S: AsyncPageStore> AsyncDiskBPlusTree<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub async fn get(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        let leaf_id = match self.find_leaf(key).await? {
+            None => return Ok(None),
+            Some(id) => id,
+        };
+        let leaf = self.read_leaf(leaf_id).await?;
+        Ok(leaf
+            .entries
+            .binary_search_by_key(&key, |(k, _)| *k)
+            .ok()
+            .map(|i| leaf.entries[i].1.clone()))
+    }
+
+    pub async fn insert(&mut self, key: Key, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge { len: value.len() });
+        }
+        let root = match self.store.root().await? {
+            Some(root) => root,
+            None => {
+                let id = self.store.allocate().await?;
+                let leaf = LeafPage {
+                    entries: vec![(key, value.to_vec())],
+                    next: None,
+                };
+                self.store.write_page(id, &Page::Leaf(leaf)).await?;
+                self.store.set_root(Some(id)).await?;
+                return Ok(None);
+            }
+        };
+        let (path, leaf_id, mut leaf) = self.descend(root, key).await?;
+        let old = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+            Ok(i) => Some(mem::replace(&mut leaf.entries[i].1, value.to_vec())),
+            Err(i) => {
+                leaf.entries.insert(i, (key, value.to_vec()));
+                None
+            }
+        };
+        let mut splited = None;
+        if leaf.encoded_size() <= PAGE_SIZE {
+            self.store.write_page(leaf_id, &Page::Leaf(leaf)).await?;
+        } else {
+            let at = leaf_split_point(&leaf.entries);
+            let right = LeafPage {
+                entries: leaf.entries.split_off(at),
+                next: leaf.next,
+            };
+            let right_id = self.store.allocate().await?;
+            leaf.next = Some(right_id);
+            let right_key = right.entries[0].0;
+            self.store.write_page(right_id, &Page::Leaf(right)).await?;
+            self.store.write_page(leaf_id, &Page::Leaf(leaf)).await?;
+            splited = Some((right_key, right_id));
+        }
+        for step in path.into_iter().rev() {
+            let Step {
+                page_id,
+                mut internal,
+                index,
+                ..
+            } = step;
+            let mut changed = false;
+            if key < internal.keys[index] {
+                internal.keys[index] = key;
+                changed = true;
+            }
+            if let Some((right_key, right_id)) = splited.take() {
+                internal.keys.insert(index + 1, right_key);
+                internal.children.insert(index + 1, right_id);
+                changed = true;
+            }
+            if internal.children.len() > InternalPage::MAX_CHILDREN {
+                let at = internal.children.len() / 2;
+                let right = InternalPage {
+                    keys: internal.keys.split_off(at),
+                    children: internal.children.split_off(at),
+                };
+                let right_id = self.store.allocate().await?;
+                let right_key = right.keys[0];
+                self.store
+                    .write_page(right_id, &Page::Internal(right))
+                    .await?;
+                splited = Some((right_key, right_id));
+            }
+            if changed {
+                self.store
+                    .write_page(page_id, &Page::Internal(internal))
+                    .await?;
+            }
+        }
+        if let Some((right_key, right_id)) = splited {
+            let left_key = self.min_key(root).await?;
+            let new_root = self.store.allocate().await?;
+            let internal = InternalPage {
+                keys: vec![left_key, right_key],
+                children: vec![root, right_id],
+            };
+            self.store
+                .write_page(new_root, &Page::Internal(internal))
+                .await?;
+            self.store.set_root(Some(new_root)).await?;
+        }
+        Ok(old)
+    }
+
+    pub async fn remove(&mut self, key: Key) -> Result<Option<Vec<u8>>> {
+        let root = match self.store.root().await? {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        let (path, leaf_id, mut leaf) = self.descend(root, key).await?;
+        let index = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+            Ok(i) => i,
+            Err(_) => return Ok(None),
+        };
+        let (_, old) = leaf.entries.remove(index);
+        if !leaf.entries.is_empty() {
+            self.store.write_page(leaf_id, &Page::Leaf(leaf)).await?;
+            return Ok(Some(old));
+        }
+        // 空になったページを親から取り除く。underflow 時のマージは行わない
+        let mut removed_leaf = Some((leaf_id, leaf.next));
+        let mut emptied = true;
+        for step in path.into_iter().rev() {
+            let Step {
+                page_id,
+                mut internal,
+                index,
+                child_lower,
+            } = step;
+            if let Some((leaf_id, next)) = removed_leaf.take() {
+                self.unlink_leaf(leaf_id, child_lower, next).await?;
+            }
+            internal.keys.remove(index);
+            internal.children.remove(index);
+            if !internal.children.is_empty() {
+                self.store
+                    .write_page(page_id, &Page::Internal(internal))
+                    .await?;
+                emptied = false;
+                break;
+            }
+        }
+        if emptied {
+            self.store.set_root(None).await?;
+        } else {
+            self.shrink_root().await?;
+        }
+        Ok(Some(old))
+    }
+
+    // min_key <= key <= max_key のエントリをキー順で返す
+    pub async fn range(&self, min_key: Key, max_key: Key) -> Result<Vec<(Key, Vec<u8>)>> {
+        let mut result = Vec::new();
+        if min_key > max_key {
+            return Ok(result);
+        }
+        let mut leaf_id = self.find_leaf(min_key).await?;
+        while let Some(id) = leaf_id {
+            let leaf = self.read_leaf(id).await?;
+            for (key, value) in leaf.entries {
+                if key > max_key {
+                    return Ok(result);
+                }
+                if key >= min_key {
+                    result.push((key, value));
+                }
+            }
+            leaf_id = leaf.next;
+        }
+        Ok(result)
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        self.store.sync().await
+    }
+
+    // key を含む leaf まで辿る
+    async fn descend(&self, root: PageId, key: Key) -> Result<(Vec<Step>, PageId, LeafPage)> {
+        let mut path = Vec::new();
+        let mut page_id = root;
+        let mut lower = None;
+        loop {
+            match self.store.read_page(page_id).await? {
+                Page::Leaf(leaf) => return Ok((path, page_id, leaf)),
+                Page::Internal(internal) => {
+                    let index = find_index(&internal.keys, key);
+                    let child_lower = if index == 0 {
+                        lower
+                    } else {
+                        Some(internal.keys[index])
+                    };
+                    let child = internal.children[index];
+                    path.push(Step {
+                        page_id,
+                        internal,
+                        index,
+                        child_lower,
+                    });
+                    page_id = child;
+                    lower = child_lower;
+                }
+            }
+        }
+    }
+
+    // 一つ前の leaf の next を、取り除く leaf の next に付け替える
+    async fn unlink_leaf(
+        &self,
+        removed: PageId,
+        lower: Option<Key>,
+        next: Option<PageId>,
+    ) -> Result<()> {
+        let lower = match lower {
+            None | Some(0) => return Ok(()),
+            Some(lower) => lower,
+        };
+        let mut leaf_id = self.find_leaf(lower - 1).await?;
+        while let Some(id) = leaf_id {
+            let mut leaf = self.read_leaf(id).await?;
+            if leaf.next == Some(removed) {
+                leaf.next = next;
+                return self.store.write_page(id, &Page::Leaf(leaf)).await;
+            }
+            leaf_id = leaf.next;
+        }
+        Err(Error::Corrupted(
+            "leaf is not reachable from its predecessor",
+        ))
+    }
+
+    async fn shrink_root(&self) -> Result<()> {
+        while let Some(root) = self.store.root().await? {
+            match self.store.read_page(root).await? {
+                Page::Internal(internal) if internal.children.len() == 1 => {
+                    self.store.set_root(Some(internal.children[0])).await?;
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    async fn find_leaf(&self, key: Key) -> Result<Option<PageId>> {
+        let mut page_id = match self.store.root().await? {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        loop {
+            match self.store.read_page(page_id).await? {
+                Page::Leaf(_) => return Ok(Some(page_id)),
+                Page::Internal(internal) => {
+                    page_id = internal.children[find_index(&internal.keys, key)];
+                }
+            }
+        }
+    }
+
+    async fn read_leaf(&self, page_id: PageId) -> Result<LeafPage> {
+        match self.store.read_page(page_id).await? {
+            Page::Leaf(leaf) => Ok(leaf),
+            Page::Internal(_) => Err(Error::Corrupted("expected a leaf page")),
+        }
+    }
+
+    async fn min_key(&self, page_id: PageId) -> Result<Key> {
+        let key = match self.store.read_page(page_id).await? {
+            Page::Leaf(leaf) => leaf.entries.first().map(|(k, _)| *k),
+            Page::Internal(internal) => internal.keys.first().copied(),
+        };
+        key.ok_or(Error::Corrupted("empty page in the tree"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{asyncstore::TokioFileStore, DiskBPlusTree};
+
+    #[tokio::test]
+    async fn insert_remove_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let store = TokioFileStore::open(&path).await.unwrap();
+            let mut t = AsyncDiskBPlusTree::new(store);
+            for k in (0..20_000).map(|k| (k * 7919) % 20_000) {
+                assert_eq!(t.insert(k, &k.to_le_bytes()).await.unwrap(), None);
+            }
+            for k in 5_000..15_000 {
+                assert_eq!(t.remove(k).await.unwrap(), Some(k.to_le_bytes().to_vec()));
+            }
+            assert_eq!(
+                t.get(4_999).await.unwrap(),
+                Some(4_999u64.to_le_bytes().to_vec())
+            );
+            assert_eq!(t.get(5_000).await.unwrap(), None);
+            t.flush().await.unwrap();
+        }
+        // DiskBPlusTree からも読める
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, (0..5_000).chain(15_000..20_000).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,325 @@
+// デコード済みのページを保持するキャッシュ
+// 書き込みはキャッシュ上で行い、flush (checkpoint) のときにまとめて Pager に書き戻す
+// checkpoint の間に書き戻すと、superblock と WAL が指す状態とファイルが食い違うので、dirty なページは追い出さない
+// dirty なページで capacity が埋まったら、DiskBPlusTree が操作の切れ目で checkpoint する
+// 書き戻し済みのページは Options::eviction で選んだキャッシュに置き、どれを追い出すかはキャッシュに任せる
+// 追い出し方を差し替えられるように、Cache trait の操作だけを使う
+use std::{collections::BTreeMap, fmt, mem};
+
+use arccache::ArcCache;
+use cache::Cache;
+use lfucache::LfuCache;
+use lrucache::LruCache;
+
+use crate::{
+    page::{Page, PageId},
+    pager::Pager,
+    Error, Result,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+// バッファプールから追い出すページの選び方
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    // 最も長く使われていないページ
+    #[default]
+    Lru,
+    // 使った回数の最も少ないページ。何度も読む root 付近のページが range の走査で追い出されにくい
+    Lfu,
+    // 最近使ったページと何度も使ったページの配分を調整する。range の走査が続いても、何度も使うページが残る
+    Arc,
+}
+
+#[derive(Debug)]
+struct Frame {
+    page: Page,
+    pins: usize,
+}
+
+pub(crate) struct BufferPool {
+    pager: Pager,
+    capacity: usize,
+    // Pager に書き戻したページ。pin されていなければ追い出してよい
+    clean: Box<dyn Cache<PageId, Frame> + Send>,
+    // Pager に書き戻していないページ。flush まで追い出さない
+    dirty: BTreeMap<PageId, Frame>,
+    stats: CacheStats,
+}
+
+impl BufferPool {
+    pub(crate) fn new(pager: Pager, capacity: usize, policy: EvictionPolicy) -> Self {
+        assert!(capacity > 0);
+        let clean: Box<dyn Cache<PageId, Frame> + Send> = match policy {
+            EvictionPolicy::Lru => Box::new(LruCache::new(capacity)),
+            EvictionPolicy::Lfu => Box::new(LfuCache::new(capacity)),
+            EvictionPolicy::Arc => Box::new(ArcCache::new(capacity)),
+        };
+        Self {
+            pager,
+            capacity,
+            clean,
+            dirty: BTreeMap::new(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub(crate) fn pager(&self) -> &Pager {
+        &self.pager
+    }
+
+    pub(crate) fn pager_mut(&mut self) -> &mut Pager {
+        &mut self.pager
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // dirty なページで capacity が埋まっていれば true。flush しないと、この先は capacity を超えて持つ
+    pub(crate) fn needs_flush(&self) -> bool {
+        self.dirty.len() >= self.capacity
+    }
+
+    pub(crate) fn read(&mut self, id: PageId) -> Result<Page> {
+        Ok(self.load(id)?.page.clone())
+    }
+
+    // 書き込んだページは dirty になる。Pager にはまだ書き込まない
+    pub(crate) fn write(&mut self, id: PageId, page: Page) -> Result<()> {
+        if !self.pager.fits(&page) {
+            return Err(Error::PageOverflow {
+                size: page.encoded_size(),
+            });
+        }
+        if let Some(frame) = self.dirty.get_mut(&id) {
+            frame.page = page;
+            return Ok(());
+        }
+        let pins = match self.clean.remove(&id) {
+            Some(frame) => frame.pins,
+            None => {
+                self.reserve();
+                0
+            }
+        };
+        self.dirty.insert(id, Frame { page, pins });
+        Ok(())
+    }
+
+    // pin したページは unpin するまで追い出されない
+    pub(crate) fn pin(&mut self, id: PageId) -> Result<()> {
+        self.load(id)?.pins += 1;
+        Ok(())
+    }
+
+    pub(crate) fn unpin(&mut self, id: PageId) {
+        let frame = match self.dirty.get_mut(&id) {
+            Some(frame) => frame,
+            None => self
+                .clean
+                .peek_mut(&id)
+                .expect("unpin a page not in the pool"),
+        };
+        assert!(frame.pins > 0, "unpin a page not pinned");
+        frame.pins -= 1;
+    }
+
+    // 使わなくなったページを書き戻さずに捨てる
+    pub(crate) fn discard(&mut self, id: PageId) {
+        if let Some(frame) = self.dirty.remove(&id).or_else(|| self.clean.remove(&id)) {
+            assert_eq!(frame.pins, 0, "discard a pinned page");
+        }
+    }
+
+    // dirty なページを全て書き戻してから、Pager を flush する
+    // 書き戻したページは clean なページとしてキャッシュに移す。pin されているものは dirty のまま残す
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        for (&id, frame) in &self.dirty {
+            self.pager.write(id, &frame.page)?;
+        }
+        self.pager.flush()?;
+        for (id, frame) in mem::take(&mut self.dirty) {
+            if frame.pins > 0 {
+                self.dirty.insert(id, frame);
+                continue;
+            }
+            self.reserve();
+            if self.clean.len() < self.capacity {
+                self.clean.put(id, frame);
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, id: PageId) -> Result<&mut Frame> {
+        if self.dirty.contains_key(&id) {
+            self.stats.hits += 1;
+            return Ok(self.dirty.get_mut(&id).expect("checked above"));
+        }
+        if self.clean.contains_key(&id) {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            let page = self.pager.read(id)?;
+            self.reserve();
+            if self.clean.len() >= self.capacity {
+                return Err(Error::BufferPoolExhausted);
+            }
+            let evicted = self.clean.put(id, Frame { page, pins: 0 });
+            debug_assert!(evicted.is_empty(), "clean cache has room");
+        }
+        Ok(self.clean.get_mut(&id).expect("loaded page is in the pool"))
+    }
+
+    // 1 ページ入れる前に呼ぶ。dirty なページと合わせて capacity に達していれば、pin されていない clean なページを追い出す
+    // 追い出せるものが無ければ何もしない。dirty なページはそのまま capacity を超えて持つ
+    fn reserve(&mut self) {
+        if self.clean.len() + self.dirty.len() < self.capacity {
+            return;
+        }
+        if let Some((_id, _)) = self.clean.pop_victim(&mut |_, frame| frame.pins == 0) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(page_id = _id, "page evicted");
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("pager", &self.pager)
+            .field("capacity", &self.capacity)
+            .field("clean", &self.clean.len())
+            .field("dirty", &self.dirty.keys())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{page::LeafPage, Compression};
+
+    fn leaf(key: u64) -> Page {
+        Page::Leaf(LeafPage {
+            entries: vec![(key, vec![])],
+            next: None,
+        })
+    }
+
+    // n 個のページを書いて flush した BufferPool を作る
+    fn flushed_pool(
+        dir: &tempfile::TempDir,
+        capacity: usize,
+        policy: EvictionPolicy,
+        n: u64,
+    ) -> (BufferPool, Vec<PageId>) {
+        let mut pool = BufferPool::new(
+            Pager::open(dir.path().join("tree.db"), Compression::None, None).unwrap(),
+            capacity,
+            policy,
+        );
+        let ids: Vec<_> = (0..n)
+            .map(|_| pool.pager_mut().allocate().unwrap())
+            .collect();
+        for (i, &id) in ids.iter().enumerate() {
+            pool.write(id, leaf(i as u64)).unwrap();
+        }
+        pool.flush().unwrap();
+        (pool, ids)
+    }
+
+    #[test]
+    fn dirty_page_is_kept_until_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut pool, ids) = flushed_pool(&dir, 2, EvictionPolicy::Lru, 3);
+        pool.write(ids[0], leaf(10)).unwrap();
+        assert!(!pool.needs_flush());
+        // capacity を超えても dirty なページは追い出さず、Pager にも書き込まない
+        pool.write(ids[1], leaf(11)).unwrap();
+        pool.write(ids[2], leaf(12)).unwrap();
+        assert!(pool.needs_flush());
+        assert_eq!(pool.pager().read(ids[0]).unwrap(), leaf(0));
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(10));
+        pool.flush().unwrap();
+        assert!(!pool.needs_flush());
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!(pool.pager().read(id).unwrap(), leaf(10 + i as u64));
+        }
+    }
+
+    #[test]
+    fn evict_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        // flush で clean なページとして移すときに ids[0] が追い出される
+        let (mut pool, ids) = flushed_pool(&dir, 2, EvictionPolicy::Lru, 3);
+        assert_eq!(pool.read(ids[1]).unwrap(), leaf(1));
+        // ids[2] が追い出される
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(0));
+        assert_eq!(pool.read(ids[1]).unwrap(), leaf(1));
+        assert_eq!(pool.read(ids[2]).unwrap(), leaf(2));
+        assert_eq!(
+            pool.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 2,
+                evictions: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn lfu_keeps_frequently_used_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut pool, ids) = flushed_pool(&dir, 2, EvictionPolicy::Lfu, 4);
+        pool.read(ids[0]).unwrap();
+        pool.read(ids[0]).unwrap();
+        // 一度ずつしか使わないページが続いても、ids[0] は追い出されない
+        for &id in &ids[1..] {
+            pool.read(id).unwrap();
+        }
+        let misses = pool.stats().misses;
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(0));
+        assert_eq!(pool.stats().misses, misses);
+    }
+
+    #[test]
+    fn arc_skips_pinned_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut pool, ids) = flushed_pool(&dir, 2, EvictionPolicy::Arc, 3);
+        pool.pin(ids[2]).unwrap();
+        pool.pin(ids[1]).unwrap();
+        pool.unpin(ids[1]);
+        // 最も古い ids[2] は pin されているので、ids[1] が追い出される
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(0));
+        let misses = pool.stats().misses;
+        assert_eq!(pool.read(ids[2]).unwrap(), leaf(2));
+        assert_eq!(pool.stats().misses, misses);
+        pool.unpin(ids[2]);
+    }
+
+    #[test]
+    fn pinned_page_is_not_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut pool, ids) = flushed_pool(&dir, 1, EvictionPolicy::Lru, 2);
+        pool.pin(ids[1]).unwrap();
+        assert!(matches!(pool.read(ids[0]), Err(Error::BufferPoolExhausted)));
+        pool.unpin(ids[1]);
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(0));
+        // pin されたまま flush したページはプールに残る
+        pool.pin(ids[0]).unwrap();
+        pool.write(ids[0], leaf(2)).unwrap();
+        pool.flush().unwrap();
+        pool.unpin(ids[0]);
+        assert_eq!(pool.read(ids[0]).unwrap(), leaf(2));
+        assert_eq!(pool.pager().read(ids[0]).unwrap(), leaf(2));
+    }
+}
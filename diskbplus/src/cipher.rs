@@ -0,0 +1,287 @@
+// ページの暗号化
+// AES-GCM などは PageCipher を実装して Options::cipher に渡す
+//
+// 暗号化したページは header をそのまま残し、その後ろに以下を置く
+//   key_id  : u32     暗号化に使った鍵の ID
+//   data_len: u32     暗号化したデータのサイズ
+//   data    : header より後ろ (圧縮した場合は圧縮後) を暗号化したデータ
+// header の flags には FLAG_ENCRYPTED を立て、checksum は暗号化した後のページ全体で計算する
+// header の kind、count、next は暗号化しないが、page_id と合わせて associated data として認証する
+// superblock と空きページは暗号化しない
+use std::{convert::TryInto, fmt::Debug};
+
+use crate::{
+    page::{
+        page_checksum, read_u16, read_u32, write_checksum, PageId, CHECKSUM_AT, FLAGS_AT,
+        HEADER_SIZE, PAGE_SIZE,
+    },
+    Error, Result,
+};
+
+pub(crate) const FLAG_ENCRYPTED: u16 = 4;
+pub(crate) const ENCRYPTED_HEADER_SIZE: usize = 8;
+// overhead の上限。分割した後のページが必ず収まるようにする
+pub(crate) const MAX_OVERHEAD: usize = 64;
+
+// associated data の大きさ。page_id と、checksum より前の header
+pub const AAD_SIZE: usize = 8 + CHECKSUM_AT;
+
+pub trait PageCipher: Debug + Send + Sync {
+    // 暗号化で増えるバイト数。nonce と認証タグの分で、MAX_OVERHEAD 以下であること
+    fn overhead(&self) -> usize;
+
+    // 新しく書き込むページに使う鍵の ID
+    // 変更すると、それ以降に書き込んだページから新しい鍵で暗号化される
+    fn key_id(&self) -> u32;
+
+    // 同じページは同じ鍵で何度も書き直すので、page_id を nonce にしてはいけない
+    // nonce は書き込むたびに重ならないもの (乱数やカウンタ) を使い、返すデータに含めること。その分も overhead に数える
+    // aad は page_id と header で、暗号化はしないが改ざんを検出できるように認証すること
+    fn encrypt(&self, key_id: u32, aad: &[u8; AAD_SIZE], data: &[u8]) -> Result<Vec<u8>>;
+
+    // 改ざんを検出した場合や鍵が無い場合は Error::Cipher を返す
+    fn decrypt(&self, key_id: u32, aad: &[u8; AAD_SIZE], data: &[u8]) -> Result<Vec<u8>>;
+}
+
+// 暗号化のために、ページの末尾に空けておくバイト数
+pub(crate) fn reserved(cipher: &dyn PageCipher) -> usize {
+    ENCRYPTED_HEADER_SIZE + cipher.overhead()
+}
+
+pub(crate) fn is_encrypted(buf: &[u8]) -> bool {
+    read_u16(buf, FLAGS_AT) & FLAG_ENCRYPTED != 0
+}
+
+// header は FLAG_ENCRYPTED を立てた後のもの
+fn aad(page_id: PageId, header: &[u8]) -> [u8; AAD_SIZE] {
+    let mut aad = [0; AAD_SIZE];
+    aad[..8].copy_from_slice(&page_id.to_le_bytes());
+    aad[8..].copy_from_slice(&header[..CHECKSUM_AT]);
+    aad
+}
+
+// plain は encode_page_with で書き込んだページで、先頭から stored bytes を使っている
+pub(crate) fn encrypt_page(
+    cipher: &dyn PageCipher,
+    page_id: PageId,
+    plain: &[u8],
+    stored: usize,
+    buf: &mut [u8],
+) -> Result<()> {
+    let key_id = cipher.key_id();
+    let mut header: [u8; HEADER_SIZE] = plain[..HEADER_SIZE].try_into().unwrap();
+    let flags = read_u16(&header, FLAGS_AT) | FLAG_ENCRYPTED;
+    header[FLAGS_AT..FLAGS_AT + 2].copy_from_slice(&flags.to_le_bytes());
+    let data = cipher.encrypt(key_id, &aad(page_id, &header), &plain[HEADER_SIZE..stored])?;
+    let data_at = HEADER_SIZE + ENCRYPTED_HEADER_SIZE;
+    if data_at + data.len() > PAGE_SIZE {
+        return Err(Error::PageOverflow {
+            size: data_at + data.len(),
+        });
+    }
+    buf.fill(0);
+    buf[..HEADER_SIZE].copy_from_slice(&header);
+    buf[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&key_id.to_le_bytes());
+    buf[HEADER_SIZE + 4..data_at].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    buf[data_at..data_at + data.len()].copy_from_slice(&data);
+    write_checksum(buf);
+    Ok(())
+}
+
+// 復号したページを、暗号化していないページの形式で返す
+pub(crate) fn decrypt_page(
+    cipher: &dyn PageCipher,
+    page_id: PageId,
+    buf: &[u8],
+) -> Result<Vec<u8>> {
+    if read_u32(buf, CHECKSUM_AT) != page_checksum(buf) {
+        return Err(Error::ChecksumMismatch);
+    }
+    let key_id = read_u32(buf, HEADER_SIZE);
+    let data_at = HEADER_SIZE + ENCRYPTED_HEADER_SIZE;
+    let data = buf
+        .get(data_at..data_at + read_u32(buf, HEADER_SIZE + 4) as usize)
+        .ok_or(Error::Corrupted("encrypted data out of range"))?;
+    let body = cipher.decrypt(key_id, &aad(page_id, buf), data)?;
+    if HEADER_SIZE + body.len() > PAGE_SIZE {
+        return Err(Error::Corrupted("decrypted page is too large"));
+    }
+    let mut plain = vec![0; PAGE_SIZE];
+    plain[..HEADER_SIZE].copy_from_slice(&buf[..HEADER_SIZE]);
+    let flags = read_u16(buf, FLAGS_AT) & !FLAG_ENCRYPTED;
+    plain[FLAGS_AT..FLAGS_AT + 2].copy_from_slice(&flags.to_le_bytes());
+    plain[HEADER_SIZE..HEADER_SIZE + body.len()].copy_from_slice(&body);
+    write_checksum(&mut plain);
+    Ok(plain)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{DiskBPlusTree, Options};
+
+    // テスト用。鍵の 1 byte と nonce の 1 byte と xor して、先頭に nonce、末尾に aad も含めた CRC32C を付ける
+    #[derive(Debug)]
+    struct XorCipher {
+        keys: HashMap<u32, u8>,
+        current: u32,
+        // 書き込むたびに増やす
+        nonce: AtomicU64,
+    }
+
+    impl XorCipher {
+        fn new(keys: &[(u32, u8)], current: u32) -> Self {
+            Self {
+                keys: keys.iter().copied().collect(),
+                current,
+                nonce: AtomicU64::new(0),
+            }
+        }
+
+        fn tag(aad: &[u8], nonce: &[u8], plain: &[u8]) -> [u8; 4] {
+            let crc = crc32c::crc32c_append(crc32c::crc32c(aad), nonce);
+            crc32c::crc32c_append(crc, plain).to_le_bytes()
+        }
+    }
+
+    impl PageCipher for XorCipher {
+        fn overhead(&self) -> usize {
+            12
+        }
+
+        fn key_id(&self) -> u32 {
+            self.current
+        }
+
+        fn encrypt(&self, key_id: u32, aad: &[u8; AAD_SIZE], data: &[u8]) -> Result<Vec<u8>> {
+            let key = self.keys[&key_id];
+            let nonce = self.nonce.fetch_add(1, Ordering::Relaxed).to_le_bytes();
+            let mut out = nonce.to_vec();
+            out.extend(data.iter().map(|b| b ^ key ^ nonce[0]));
+            out.extend(Self::tag(aad, &nonce, data));
+            Ok(out)
+        }
+
+        fn decrypt(&self, key_id: u32, aad: &[u8; AAD_SIZE], data: &[u8]) -> Result<Vec<u8>> {
+            let key = self
+                .keys
+                .get(&key_id)
+                .ok_or_else(|| Error::Cipher("unknown key id".into()))?;
+            if data.len() < 12 {
+                return Err(Error::Cipher("too short".into()));
+            }
+            let (nonce, data) = data.split_at(8);
+            let (data, tag) = data.split_at(data.len() - 4);
+            let out: Vec<_> = data.iter().map(|b| b ^ key ^ nonce[0]).collect();
+            if Self::tag(aad, nonce, &out) != tag[..] {
+                return Err(Error::Cipher("authentication failed".into()));
+            }
+            Ok(out)
+        }
+    }
+
+    fn options(keys: &[(u32, u8)], current: u32) -> Options {
+        Options {
+            cipher: Some(Arc::new(XorCipher::new(keys, current))),
+            cache_pages: 8,
+            ..Options::default()
+        }
+    }
+
+    fn value(key: u64) -> Vec<u8> {
+        format!("secret-{:08}", key).into_bytes()
+    }
+
+    #[test]
+    fn encrypt_and_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = DiskBPlusTree::open_with(&path, options(&[(1, 0x5a)], 1)).unwrap();
+            for k in 0..2000 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            t.flush().unwrap();
+        }
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(!bytes.windows(7).any(|w| w == b"secret-"));
+        assert!(matches!(
+            DiskBPlusTree::open(&path).unwrap().get(0),
+            Err(Error::Encrypted)
+        ));
+
+        // 鍵 2 で書き直すと、鍵 1 が無くても読める
+        {
+            let mut t =
+                DiskBPlusTree::open_with(&path, options(&[(1, 0x5a), (2, 0xa5)], 2)).unwrap();
+            t.reencrypt().unwrap();
+        }
+        let t = DiskBPlusTree::open_with(&path, options(&[(2, 0xa5)], 2)).unwrap();
+        t.verify().unwrap();
+        for k in 0..2000 {
+            assert_eq!(t.get(k).unwrap(), Some(value(k)));
+        }
+    }
+
+    // header は暗号化しないが、書き換えて checksum を合わせても復号で検出する
+    #[test]
+    fn header_is_authenticated() {
+        let cipher = XorCipher::new(&[(1, 0x5a)], 1);
+        let page = crate::Page::Leaf(crate::LeafPage {
+            entries: vec![(1, b"one".to_vec()), (2, b"two".to_vec())],
+            next: Some(7),
+        });
+        let mut plain = vec![0; PAGE_SIZE];
+        let stored = crate::encode_page_with(&page, &mut plain, Default::default()).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        encrypt_page(&cipher, 3, &plain, stored, &mut buf).unwrap();
+        // next を書き換える
+        buf[8] ^= 1;
+        write_checksum(&mut buf);
+        assert!(matches!(
+            decrypt_page(&cipher, 3, &buf),
+            Err(Error::Cipher(_))
+        ));
+    }
+
+    #[test]
+    fn internal_page_with_cipher() {
+        let cipher = XorCipher::new(&[(1, 0x5a)], 1);
+        let max = crate::page::InternalPage::max_children(reserved(&cipher));
+        let page = crate::Page::Internal(crate::InternalPage {
+            keys: (0..max as u64).collect(),
+            children: (0..max as u64).collect(),
+        });
+        let mut plain = vec![0; PAGE_SIZE];
+        let stored = crate::encode_page_with(&page, &mut plain, Default::default()).unwrap();
+        let mut buf = vec![0; PAGE_SIZE];
+        encrypt_page(&cipher, 3, &plain, stored, &mut buf).unwrap();
+        assert!(is_encrypted(&buf));
+        assert_eq!(
+            crate::decode_page(&decrypt_page(&cipher, 3, &buf).unwrap()).unwrap(),
+            page
+        );
+        // 同じページを書き直しても、nonce が違うので暗号文は変わる
+        let mut again = vec![0; PAGE_SIZE];
+        encrypt_page(&cipher, 3, &plain, stored, &mut again).unwrap();
+        assert_ne!(again, buf);
+        // 他のページの位置に置いたものは復号できない
+        assert!(matches!(
+            decrypt_page(&cipher, 4, &buf),
+            Err(Error::Cipher(_))
+        ));
+        buf[HEADER_SIZE + ENCRYPTED_HEADER_SIZE] ^= 1;
+        assert!(matches!(
+            decrypt_page(&cipher, 3, &buf),
+            Err(Error::ChecksumMismatch)
+        ));
+    }
+}
@@ -0,0 +1,72 @@
+// leaf ページの圧縮
+// どの方式で圧縮したかはページの flags に記録するので、読み込み時の設定に関わらず読める
+use crate::{Error, Result};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    // 圧縮レベル
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+// flags に立てるビット
+pub(crate) const FLAG_LZ4: u16 = 1;
+pub(crate) const FLAG_ZSTD: u16 = 2;
+pub(crate) const FLAG_COMPRESSED: u16 = FLAG_LZ4 | FLAG_ZSTD;
+
+impl Compression {
+    // 圧縮しない場合は None
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Option<(u16, Vec<u8>)>> {
+        match self {
+            Compression::None => Ok(None),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Ok(Some((FLAG_LZ4, lz4_flex::compress(data)))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => Ok(Some((FLAG_ZSTD, zstd::bulk::compress(data, level)?))),
+        }
+    }
+}
+
+// len は圧縮前の長さ
+#[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+pub(crate) fn decompress(flags: u16, data: &[u8], len: usize) -> Result<Vec<u8>> {
+    let out: Result<Vec<u8>> = match flags & FLAG_COMPRESSED {
+        #[cfg(feature = "lz4")]
+        FLAG_LZ4 => lz4_flex::decompress(data, len)
+            .map_err(|_| Error::Corrupted("failed to decompress a page")),
+        #[cfg(feature = "zstd")]
+        FLAG_ZSTD => zstd::bulk::decompress(data, len)
+            .map_err(|_| Error::Corrupted("failed to decompress a page")),
+        _ => Err(Error::UnsupportedCompression(flags)),
+    };
+    let out = out?;
+    if out.len() != len {
+        return Err(Error::Corrupted("decompressed length mismatch"));
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    // 書き込んだ leaf ページの数
+    pub pages: u64,
+    // 圧縮前のエンコード後のサイズの合計
+    pub raw_bytes: u64,
+    // 実際にページに書き込んだサイズの合計
+    pub stored_bytes: u64,
+}
+
+impl CompressionStats {
+    // 圧縮率。raw_bytes / stored_bytes
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_bytes as f64 / self.stored_bytes as f64
+    }
+}
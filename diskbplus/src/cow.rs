@@ -0,0 +1,662 @@
+// 書き込み中のページを上書きしない copy-on-write の B+ tree
+// 変更したページは新しいページにコピーし、commit で meta の root を切り替える
+// 最後に commit した木のページは上書きしないので、WAL が無くても途中で落ちたら commit 前に戻るだけになる
+//
+// page 0 は meta
+//   magic     : [u8; 4] = "BPCW"
+//   version   : u32
+//   page_size : u32
+//   slot[0]   : 64 bytes 目から
+//   slot[1]   : 128 bytes 目から
+// slot は txn の偶奇で交互に書き込み、txn が大きく checksum の正しい方を使う
+//   txn       : u64
+//   root      : u64  無い場合は NO_PAGE
+//   page_count: u64
+//   checksum  : u32  slot の先頭 24 bytes の CRC32C
+//
+// 空きページは保存しない。開くときに root から辿れないページを空きページとみなす
+// leaf の next はコピーすると付け替えられないので使わず、range は木を辿る
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    mem,
+    path::Path,
+    slice,
+    sync::{Arc, Mutex},
+};
+
+use memmap2::MmapRaw;
+
+use crate::{
+    page::{
+        decode_page, encode_page, InternalPage, LeafPage, Page, PageId, MAX_VALUE_LEN, PAGE_SIZE,
+    },
+    tree::{find_index, leaf_split_point},
+    Error, Key, Result,
+};
+
+const MAGIC: [u8; 4] = *b"BPCW";
+const COW_FORMAT_VERSION: u32 = 1;
+const SLOT_AT: [usize; 2] = [64, 128];
+const SLOT_SIZE: usize = 24;
+const NO_PAGE: u64 = u64::MAX;
+const INITIAL_PAGES: u64 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Meta {
+    txn: u64,
+    root: Option<PageId>,
+    page_count: u64,
+}
+
+// snapshot の txn ごとの数
+type Readers = Arc<Mutex<BTreeMap<u64, usize>>>;
+
+// 上書き前の値、コピー先の PageId、分割した場合は右側のページの最小キーと PageId
+type Inserted = (Option<Vec<u8>>, PageId, Option<(Key, PageId)>);
+
+#[derive(Debug)]
+pub struct CowDiskBPlusTree {
+    file: File,
+    map: Arc<MmapRaw>,
+    committed: Meta,
+    // commit 前の root と page_count
+    root: Option<PageId>,
+    page_count: u64,
+    // この txn で確保したページ。どの snapshot からも見えないので上書きしてよい
+    dirty: HashSet<PageId>,
+    // 空きページと、そのページを解放した txn
+    free: Vec<(u64, PageId)>,
+    // この txn で解放したページ。commit するまでは最後に commit した木が参照している
+    pending_free: Vec<PageId>,
+    readers: Readers,
+}
+
+impl CowDiskBPlusTree {
+    // ファイルが無ければ作成する
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        if is_new {
+            file.set_len(INITIAL_PAGES * PAGE_SIZE as u64)?;
+        }
+        if file.metadata()?.len() < PAGE_SIZE as u64 {
+            return Err(Error::InvalidFile("file is smaller than a page"));
+        }
+        let map = MmapRaw::map_raw(&file)?;
+        let mut tree = Self {
+            file,
+            map: Arc::new(map),
+            committed: Meta {
+                txn: 0,
+                root: None,
+                page_count: 1,
+            },
+            root: None,
+            page_count: 1,
+            dirty: HashSet::new(),
+            free: Vec::new(),
+            pending_free: Vec::new(),
+            readers: Arc::default(),
+        };
+        if is_new {
+            tree.write_meta(tree.committed);
+            tree.map.flush()?;
+        } else {
+            tree.committed = tree.read_meta()?;
+            tree.root = tree.committed.root;
+            tree.page_count = tree.committed.page_count;
+            tree.collect_free()?;
+        }
+        Ok(tree)
+    }
+
+    // commit 前の変更も見える
+    pub fn get(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        get_in(&self.map, self.root, key)
+    }
+
+    pub fn range(&self, min_key: Key, max_key: Key) -> Result<Vec<(Key, Vec<u8>)>> {
+        range_in(&self.map, self.root, min_key, max_key)
+    }
+
+    pub fn insert(&mut self, key: Key, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge { len: value.len() });
+        }
+        let root = match self.root {
+            Some(root) => root,
+            None => {
+                let id = self.allocate()?;
+                let leaf = LeafPage {
+                    entries: vec![(key, value.to_vec())],
+                    next: None,
+                };
+                self.write_page(id, &Page::Leaf(leaf))?;
+                self.root = Some(id);
+                return Ok(None);
+            }
+        };
+        let (old, root, splited) = self.insert_at(root, key, value)?;
+        self.root = Some(root);
+        if let Some((right_key, right_id)) = splited {
+            let left_key = min_key(&read_page(&self.map, root)?)?;
+            let new_root = self.allocate()?;
+            let internal = InternalPage {
+                keys: vec![left_key, right_key],
+                children: vec![root, right_id],
+            };
+            self.write_page(new_root, &Page::Internal(internal))?;
+            self.root = Some(new_root);
+        }
+        Ok(old)
+    }
+
+    pub fn remove(&mut self, key: Key) -> Result<Option<Vec<u8>>> {
+        let root = match self.root {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        let (old, root) = match self.remove_at(root, key)? {
+            None => return Ok(None),
+            Some(removed) => removed,
+        };
+        self.root = root;
+        // 子が一つだけになった root は取り除いて高さを縮める
+        while let Some(root) = self.root {
+            match read_page(&self.map, root)? {
+                Page::Internal(internal) if internal.children.len() == 1 => {
+                    self.free_page(root);
+                    self.root = Some(internal.children[0]);
+                }
+                _ => break,
+            }
+        }
+        Ok(Some(old))
+    }
+
+    // 新しいページをファイルに反映してから、meta の root を切り替える
+    pub fn commit(&mut self) -> Result<()> {
+        if self.root == self.committed.root && self.dirty.is_empty() {
+            return Ok(());
+        }
+        self.map.flush()?;
+        let meta = Meta {
+            txn: self.committed.txn + 1,
+            root: self.root,
+            page_count: self.page_count,
+        };
+        self.write_meta(meta);
+        self.map.flush_range(0, PAGE_SIZE)?;
+        self.committed = meta;
+        self.dirty.clear();
+        let txn = meta.txn;
+        self.free
+            .extend(self.pending_free.drain(..).map(|id| (txn, id)));
+        Ok(())
+    }
+
+    // commit していない変更を捨てる
+    pub fn rollback(&mut self) {
+        self.root = self.committed.root;
+        self.pending_free.clear();
+        self.free.extend(self.dirty.drain().map(|id| (0, id)));
+    }
+
+    // 最後に commit した木を読む。snapshot を持っている間も書き込みや commit はできる
+    pub fn snapshot(&self) -> Snapshot {
+        *self
+            .readers
+            .lock()
+            .unwrap()
+            .entry(self.committed.txn)
+            .or_insert(0) += 1;
+        Snapshot {
+            map: self.map.clone(),
+            root: self.committed.root,
+            txn: self.committed.txn,
+            readers: self.readers.clone(),
+        }
+    }
+
+    // 最後に commit した txn の番号
+    pub fn txn(&self) -> u64 {
+        self.committed.txn
+    }
+
+    fn insert_at(&mut self, page_id: PageId, key: Key, value: &[u8]) -> Result<Inserted> {
+        match read_page(&self.map, page_id)? {
+            Page::Leaf(mut leaf) => {
+                let old = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(i) => Some(mem::replace(&mut leaf.entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        leaf.entries.insert(i, (key, value.to_vec()));
+                        None
+                    }
+                };
+                let page_id = self.copy(page_id)?;
+                if leaf.encoded_size() <= PAGE_SIZE {
+                    self.write_page(page_id, &Page::Leaf(leaf))?;
+                    return Ok((old, page_id, None));
+                }
+                let at = leaf_split_point(&leaf.entries);
+                let right = LeafPage {
+                    entries: leaf.entries.split_off(at),
+                    next: None,
+                };
+                let right_id = self.allocate()?;
+                let right_key = right.entries[0].0;
+                self.write_page(right_id, &Page::Leaf(right))?;
+                self.write_page(page_id, &Page::Leaf(leaf))?;
+                Ok((old, page_id, Some((right_key, right_id))))
+            }
+            Page::Internal(mut internal) => {
+                let index = find_index(&internal.keys, key);
+                let (old, child, splited) = self.insert_at(internal.children[index], key, value)?;
+                internal.children[index] = child;
+                if key < internal.keys[index] {
+                    internal.keys[index] = key;
+                }
+                if let Some((right_key, right_id)) = splited {
+                    internal.keys.insert(index + 1, right_key);
+                    internal.children.insert(index + 1, right_id);
+                }
+                let page_id = self.copy(page_id)?;
+                if internal.children.len() > InternalPage::MAX_CHILDREN {
+                    let at = internal.children.len() / 2;
+                    let right = InternalPage {
+                        keys: internal.keys.split_off(at),
+                        children: internal.children.split_off(at),
+                    };
+                    let right_id = self.allocate()?;
+                    let right_key = right.keys[0];
+                    self.write_page(right_id, &Page::Internal(right))?;
+                    self.write_page(page_id, &Page::Internal(internal))?;
+                    return Ok((old, page_id, Some((right_key, right_id))));
+                }
+                self.write_page(page_id, &Page::Internal(internal))?;
+                Ok((old, page_id, None))
+            }
+        }
+    }
+
+    // 削除した値と、コピー先の PageId を返す。ページが空になった場合は None
+    fn remove_at(
+        &mut self,
+        page_id: PageId,
+        key: Key,
+    ) -> Result<Option<(Vec<u8>, Option<PageId>)>> {
+        match read_page(&self.map, page_id)? {
+            Page::Leaf(mut leaf) => {
+                let index = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(i) => i,
+                    Err(_) => return Ok(None),
+                };
+                let (_, old) = leaf.entries.remove(index);
+                if leaf.entries.is_empty() {
+                    self.free_page(page_id);
+                    return Ok(Some((old, None)));
+                }
+                let page_id = self.copy(page_id)?;
+                self.write_page(page_id, &Page::Leaf(leaf))?;
+                Ok(Some((old, Some(page_id))))
+            }
+            Page::Internal(mut internal) => {
+                let index = find_index(&internal.keys, key);
+                let (old, child) = match self.remove_at(internal.children[index], key)? {
+                    None => return Ok(None),
+                    Some(removed) => removed,
+                };
+                match child {
+                    Some(child) => internal.children[index] = child,
+                    // underflow時のマージは行わない。空になった子だけを取り除く
+                    None => {
+                        internal.keys.remove(index);
+                        internal.children.remove(index);
+                    }
+                }
+                if internal.children.is_empty() {
+                    self.free_page(page_id);
+                    return Ok(Some((old, None)));
+                }
+                let page_id = self.copy(page_id)?;
+                self.write_page(page_id, &Page::Internal(internal))?;
+                Ok(Some((old, Some(page_id))))
+            }
+        }
+    }
+
+    // 書き換えるページの PageId を返す
+    // この txn で確保したページならそのまま書き換え、そうでなければ新しいページにコピーする
+    fn copy(&mut self, page_id: PageId) -> Result<PageId> {
+        if self.dirty.contains(&page_id) {
+            return Ok(page_id);
+        }
+        self.pending_free.push(page_id);
+        self.allocate()
+    }
+
+    fn free_page(&mut self, page_id: PageId) {
+        if self.dirty.remove(&page_id) {
+            self.free.push((0, page_id));
+        } else {
+            self.pending_free.push(page_id);
+        }
+    }
+
+    // 全ての snapshot から見えなくなった空きページを再利用し、無ければファイルの末尾に確保する
+    fn allocate(&mut self) -> Result<PageId> {
+        let oldest = self.readers.lock().unwrap().keys().next().copied();
+        let reusable = self
+            .free
+            .iter()
+            .position(|(freed, _)| oldest.is_none_or(|txn| *freed <= txn));
+        let id = match reusable {
+            Some(i) => self.free.swap_remove(i).1,
+            None => {
+                let id = self.page_count;
+                let needed = (id + 1) * PAGE_SIZE as u64;
+                if needed > self.map.len() as u64 {
+                    // 古い map は snapshot が持っていれば残る
+                    let new_len = needed.max(self.map.len() as u64 * 2);
+                    self.map.flush()?;
+                    self.file.set_len(new_len)?;
+                    self.map = Arc::new(MmapRaw::map_raw(&self.file)?);
+                }
+                self.page_count += 1;
+                id
+            }
+        };
+        self.dirty.insert(id);
+        Ok(id)
+    }
+
+    fn write_page(&mut self, page_id: PageId, page: &Page) -> Result<()> {
+        assert!(self.dirty.contains(&page_id), "write to a live page");
+        let start = page_id as usize * PAGE_SIZE;
+        // SAFETY: dirty なページはどの snapshot からも辿れないので、読み込みと重ならない
+        let buf = unsafe { slice::from_raw_parts_mut(self.map.as_mut_ptr().add(start), PAGE_SIZE) };
+        encode_page(page, buf)
+    }
+
+    // 開いたときに、root から辿れないページを空きページにする
+    fn collect_free(&mut self) -> Result<()> {
+        let mut live = HashSet::new();
+        let mut stack: Vec<_> = self.root.into_iter().collect();
+        while let Some(id) = stack.pop() {
+            if !live.insert(id) {
+                return Err(Error::Corrupted("page is referenced twice"));
+            }
+            if let Page::Internal(internal) = read_page(&self.map, id)? {
+                stack.extend(internal.children);
+            }
+        }
+        self.free = (1..self.page_count)
+            .filter(|id| !live.contains(id))
+            .map(|id| (0, id))
+            .collect();
+        Ok(())
+    }
+
+    fn write_meta(&mut self, meta: Meta) {
+        // SAFETY: snapshot は page 0 を読まない
+        let buf = unsafe { slice::from_raw_parts_mut(self.map.as_mut_ptr(), PAGE_SIZE) };
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&COW_FORMAT_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        let at = SLOT_AT[(meta.txn % 2) as usize];
+        let slot = &mut buf[at..at + SLOT_SIZE + 4];
+        slot[0..8].copy_from_slice(&meta.txn.to_le_bytes());
+        slot[8..16].copy_from_slice(&meta.root.unwrap_or(NO_PAGE).to_le_bytes());
+        slot[16..24].copy_from_slice(&meta.page_count.to_le_bytes());
+        let checksum = crc32c::crc32c(&slot[..SLOT_SIZE]);
+        slot[SLOT_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn read_meta(&self) -> Result<Meta> {
+        // SAFETY: 開いた直後で、まだ誰も書き込んでいない
+        let buf = unsafe { slice::from_raw_parts(self.map.as_ptr(), PAGE_SIZE) };
+        if buf[0..4] != MAGIC {
+            return Err(Error::InvalidFile("bad magic"));
+        }
+        if u32::from_le_bytes(buf[4..8].try_into().unwrap()) != COW_FORMAT_VERSION {
+            return Err(Error::InvalidFile("unsupported file format version"));
+        }
+        if u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize != PAGE_SIZE {
+            return Err(Error::InvalidFile("page size mismatch"));
+        }
+        let slots = SLOT_AT.iter().filter_map(|at| {
+            let slot = &buf[*at..*at + SLOT_SIZE + 4];
+            let checksum = u32::from_le_bytes(slot[SLOT_SIZE..].try_into().unwrap());
+            if checksum != crc32c::crc32c(&slot[..SLOT_SIZE]) {
+                // 書き込み途中で落ちた slot
+                return None;
+            }
+            let root = u64::from_le_bytes(slot[8..16].try_into().unwrap());
+            Some(Meta {
+                txn: u64::from_le_bytes(slot[0..8].try_into().unwrap()),
+                root: if root == NO_PAGE { None } else { Some(root) },
+                page_count: u64::from_le_bytes(slot[16..24].try_into().unwrap()),
+            })
+        });
+        let meta = slots
+            .max_by_key(|meta| meta.txn)
+            .ok_or(Error::CorruptPage { page_id: 0 })?;
+        if meta.page_count == 0 || meta.page_count * PAGE_SIZE as u64 > self.map.len() as u64 {
+            return Err(Error::InvalidFile("page count out of range"));
+        }
+        Ok(meta)
+    }
+}
+
+// commit 済みの木を読む。読み込みにロックは取らない
+#[derive(Debug)]
+pub struct Snapshot {
+    map: Arc<MmapRaw>,
+    root: Option<PageId>,
+    txn: u64,
+    readers: Readers,
+}
+
+impl Snapshot {
+    pub fn txn(&self) -> u64 {
+        self.txn
+    }
+
+    pub fn get(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        get_in(&self.map, self.root, key)
+    }
+
+    pub fn range(&self, min_key: Key, max_key: Key) -> Result<Vec<(Key, Vec<u8>)>> {
+        range_in(&self.map, self.root, min_key, max_key)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut readers = self.readers.lock().unwrap();
+        if let Some(count) = readers.get_mut(&self.txn) {
+            *count -= 1;
+            if *count == 0 {
+                readers.remove(&self.txn);
+            }
+        }
+    }
+}
+
+fn read_page(map: &MmapRaw, page_id: PageId) -> Result<Page> {
+    if page_id == 0 || (page_id + 1) * PAGE_SIZE as u64 > map.len() as u64 {
+        return Err(Error::Corrupted("page id out of range"));
+    }
+    let start = page_id as usize * PAGE_SIZE;
+    // SAFETY: 辿れるページは commit 済みか writer だけが書くページなので、読んでいる間に書き換わらない
+    let buf = unsafe { slice::from_raw_parts(map.as_ptr().add(start), PAGE_SIZE) };
+    decode_page(buf).map_err(|e| match e {
+        Error::ChecksumMismatch => Error::CorruptPage { page_id },
+        e => e,
+    })
+}
+
+fn min_key(page: &Page) -> Result<Key> {
+    let key = match page {
+        Page::Leaf(leaf) => leaf.entries.first().map(|(k, _)| *k),
+        Page::Internal(internal) => internal.keys.first().copied(),
+    };
+    key.ok_or(Error::Corrupted("empty page in the tree"))
+}
+
+fn get_in(map: &MmapRaw, root: Option<PageId>, key: Key) -> Result<Option<Vec<u8>>> {
+    let mut page_id = match root {
+        None => return Ok(None),
+        Some(root) => root,
+    };
+    loop {
+        match read_page(map, page_id)? {
+            Page::Leaf(leaf) => {
+                return Ok(leaf
+                    .entries
+                    .binary_search_by_key(&key, |(k, _)| *k)
+                    .ok()
+                    .map(|i| leaf.entries[i].1.clone()))
+            }
+            Page::Internal(internal) => {
+                page_id = internal.children[find_index(&internal.keys, key)];
+            }
+        }
+    }
+}
+
+// min_key <= key <= max_key のエントリをキー順で返す
+fn range_in(
+    map: &MmapRaw,
+    root: Option<PageId>,
+    min_key: Key,
+    max_key: Key,
+) -> Result<Vec<(Key, Vec<u8>)>> {
+    let mut result = Vec::new();
+    if min_key > max_key {
+        return Ok(result);
+    }
+    let mut stack: Vec<_> = root.into_iter().collect();
+    while let Some(page_id) = stack.pop() {
+        match read_page(map, page_id)? {
+            Page::Leaf(leaf) => result.extend(
+                leaf.entries
+                    .into_iter()
+                    .filter(|(k, _)| min_key <= *k && *k <= max_key),
+            ),
+            Page::Internal(internal) => {
+                let first = find_index(&internal.keys, min_key);
+                let last = find_index(&internal.keys, max_key);
+                stack.extend(internal.children[first..=last].iter().rev());
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn value(key: Key) -> Vec<u8> {
+        format!("value-{}", key)
+            .repeat((key % 7 + 1) as usize)
+            .into_bytes()
+    }
+
+    #[test]
+    fn commit_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = CowDiskBPlusTree::open(&path).unwrap();
+            for k in (0..3000).map(|k| (k * 7919) % 3000) {
+                assert_eq!(t.insert(k, &value(k)).unwrap(), None);
+            }
+            for k in 1000..2000 {
+                assert_eq!(t.remove(k).unwrap(), Some(value(k)));
+            }
+            t.commit().unwrap();
+            assert_eq!(t.txn(), 1);
+            // commit しなかった変更は残らない
+            t.insert(5000, b"lost").unwrap();
+            t.remove(0).unwrap();
+        }
+        let mut t = CowDiskBPlusTree::open(&path).unwrap();
+        assert_eq!(t.get(5000).unwrap(), None);
+        assert_eq!(t.get(0).unwrap(), Some(value(0)));
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, (0..1000).chain(2000..3000).collect::<Vec<_>>());
+
+        t.insert(5000, b"kept").unwrap();
+        t.rollback();
+        assert_eq!(t.get(5000).unwrap(), None);
+        for k in 0..3000 {
+            t.remove(k).unwrap();
+        }
+        t.commit().unwrap();
+        assert_eq!(t.range(0, Key::MAX).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn torn_meta() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = CowDiskBPlusTree::open(&path).unwrap();
+            t.insert(1, b"one").unwrap();
+            t.commit().unwrap();
+            t.insert(2, b"two").unwrap();
+            t.commit().unwrap();
+        }
+        // txn 2 の slot を壊すと、txn 1 の木が見える
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[SLOT_AT[0]] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        let t = CowDiskBPlusTree::open(&path).unwrap();
+        assert_eq!(t.txn(), 1);
+        assert_eq!(t.get(1).unwrap(), Some(b"one".to_vec()));
+        assert_eq!(t.get(2).unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_sees_old_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut t = CowDiskBPlusTree::open(dir.path().join("tree.db")).unwrap();
+        for k in 0..2000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        t.commit().unwrap();
+        let snapshot = t.snapshot();
+        let reader = std::thread::spawn(move || {
+            for _ in 0..20 {
+                assert_eq!(snapshot.range(0, Key::MAX).unwrap().len(), 2000);
+            }
+            snapshot
+        });
+        // snapshot が読んでいるページは再利用されない
+        for round in 0..20 {
+            for k in 0..2000 {
+                t.insert(k, &value(k + round)).unwrap();
+            }
+            t.commit().unwrap();
+        }
+        let snapshot = reader.join().unwrap();
+        assert_eq!(snapshot.get(1999).unwrap(), Some(value(1999)));
+        assert_eq!(snapshot.txn(), 1);
+        drop(snapshot);
+        assert_eq!(t.get(1999).unwrap(), Some(value(1999 + 19)));
+    }
+}
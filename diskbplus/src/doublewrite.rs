@@ -0,0 +1,139 @@
+// checkpoint で書き換えるページを、ファイルを上書きする前にまとめて書き出しておく double-write buffer
+// ページの上書きの途中で落ちても、開くときにここから書き直せば checkpoint を終えた状態になる
+// ここへの書き込みの途中で落ちた場合は、ファイルはまだ前の checkpoint のままなので捨てる
+// ファイルの大きさを変えると fsync が重くなるので、縮めずに先頭から上書きし、count より後ろは読まない
+//
+// header
+//   magic    : [u8; 4] = "BPDW"
+//   count    : u32  ページ数
+//   checksum : u32  header の後ろ全体の CRC32C
+//   reserved : u32
+// count 個の
+//   page_id  : u64
+//   page     : [u8; PAGE_SIZE]
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    page::{PageId, PAGE_SIZE},
+    Result,
+};
+
+const MAGIC: [u8; 4] = *b"BPDW";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 8 + PAGE_SIZE;
+
+#[derive(Debug)]
+pub(crate) struct DoubleWrite {
+    file: File,
+}
+
+impl DoubleWrite {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self { file })
+    }
+
+    // 書き終えて fsync してから返る
+    pub(crate) fn write<'a, I>(&mut self, pages: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (PageId, &'a [u8])>,
+    {
+        let mut buf = vec![0; HEADER_SIZE];
+        let mut count = 0u32;
+        for (id, page) in pages {
+            buf.extend_from_slice(&id.to_le_bytes());
+            buf.extend_from_slice(page);
+            count += 1;
+        }
+        let checksum = crc32c::crc32c(&buf[HEADER_SIZE..]);
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&count.to_le_bytes());
+        buf[8..12].copy_from_slice(&checksum.to_le_bytes());
+        self.file.rewind()?;
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    // 最後まで書き出せていなければ空にする
+    pub(crate) fn read(&mut self) -> Result<Vec<(PageId, Vec<u8>)>> {
+        let mut buf = Vec::new();
+        self.file.rewind()?;
+        self.file.read_to_end(&mut buf)?;
+        if buf.len() < HEADER_SIZE || buf[0..4] != MAGIC {
+            return Ok(Vec::new());
+        }
+        let count = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let body = &buf[HEADER_SIZE..];
+        let body = match body.get(..count * ENTRY_SIZE) {
+            Some(body) if crc32c::crc32c(body) == checksum => body,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(body
+            .chunks(ENTRY_SIZE)
+            .map(|entry| {
+                let id = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+                (id, entry[8..].to_vec())
+            })
+            .collect())
+    }
+
+    // ファイルに反映し終えたら呼ぶ
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        self.file.rewind()?;
+        self.file.write_all(&[0; HEADER_SIZE])?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dw = DoubleWrite::open(dir.path().join("tree.db-dwb")).unwrap();
+        assert_eq!(dw.read().unwrap(), vec![]);
+        let pages = vec![(0, vec![1; PAGE_SIZE]), (5, vec![2; PAGE_SIZE])];
+        dw.write(pages.iter().map(|(id, page)| (*id, &page[..])))
+            .unwrap();
+        assert_eq!(dw.read().unwrap(), pages);
+        // 短くなっても前の内容は残らない
+        dw.write(pages[1..].iter().map(|(id, page)| (*id, &page[..])))
+            .unwrap();
+        assert_eq!(dw.read().unwrap(), pages[1..]);
+        dw.clear().unwrap();
+        assert_eq!(dw.read().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn ignore_torn_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db-dwb");
+        let mut dw = DoubleWrite::open(&path).unwrap();
+        let page = vec![1; PAGE_SIZE];
+        dw.write(vec![(1, &page[..]), (2, &page[..])]).unwrap();
+        // 2 ページ目の途中で切れた状態にする
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 100)
+            .unwrap();
+        assert_eq!(dw.read().unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,37 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("page overflow: encoded size {size} exceeds the page size")]
+    PageOverflow { size: usize },
+    #[error("value too large: {len} bytes")]
+    ValueTooLarge { len: usize },
+    #[error("unsupported page format version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("corrupted page: {0}")]
+    Corrupted(&'static str),
+    #[error("checksum mismatch")]
+    ChecksumMismatch,
+    #[error("corrupt page {page_id}: checksum mismatch")]
+    CorruptPage { page_id: u64 },
+    #[error("page is compressed with an unsupported method: flags {0:#x}")]
+    UnsupportedCompression(u16),
+    #[error("page is encrypted but no cipher is configured")]
+    Encrypted,
+    #[error("page cipher failed: {0}")]
+    Cipher(Box<dyn std::error::Error + Send + Sync>),
+    #[error("keys must be added in increasing order: {key} after {last}")]
+    Unsorted { key: u64, last: u64 },
+    #[error("file format version {found} is older than this library; open it after DiskBPlusTree::migrate")]
+    OutdatedFileVersion { found: u32 },
+    #[error("file format version {found} is newer than this library supports")]
+    UnsupportedFileVersion { found: u32 },
+    #[error("invalid file: {0}")]
+    InvalidFile(&'static str),
+    #[error("all pages in the buffer pool are pinned")]
+    BufferPoolExhausted,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
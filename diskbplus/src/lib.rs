@@ -0,0 +1,38 @@
+#[cfg(feature = "tokio")]
+mod asyncstore;
+#[cfg(feature = "tokio")]
+mod asynctree;
+mod buffer;
+mod cipher;
+mod compression;
+mod cow;
+mod doublewrite;
+mod error;
+#[cfg(feature = "metrics")]
+mod metrics_impl;
+mod migrate;
+mod options;
+pub mod page;
+mod pager;
+mod sst;
+mod tree;
+pub mod wal;
+
+#[cfg(feature = "tokio")]
+pub use asyncstore::{AsyncPageStore, TokioFileStore};
+#[cfg(feature = "tokio")]
+pub use asynctree::AsyncDiskBPlusTree;
+pub use buffer::{CacheStats, EvictionPolicy};
+pub use cipher::{PageCipher, AAD_SIZE};
+pub use compression::{Compression, CompressionStats};
+pub use cow::{CowDiskBPlusTree, Snapshot};
+pub use error::{Error, Result};
+pub use options::Options;
+pub use page::{
+    decode_page, encode_page, encode_page_with, InternalPage, LeafPage, Page, PageId, PAGE_SIZE,
+};
+pub use sst::SstWriter;
+pub use tree::DiskBPlusTree;
+pub use wal::FsyncPolicy;
+
+pub type Key = u64;
@@ -0,0 +1,152 @@
+// metrics feature で、木の状態を metrics の counter と gauge に書き出す
+// 分割は起きるたびに数え、それ以外は record_metrics を呼んだ時点の値を書き出す
+//   diskbplus_splits_total{kind="leaf"|"internal"}  counter
+//   diskbplus_cache_hits_total                       counter
+//   diskbplus_cache_misses_total                     counter
+//   diskbplus_cache_evictions_total                  counter
+//   diskbplus_cache_hit_ratio                        gauge
+//   diskbplus_height                                 gauge  leaf だけなら 1
+//   diskbplus_internal_pages                         gauge
+//   diskbplus_leaf_pages                             gauge
+//   diskbplus_file_pages                             gauge  空きページを含む
+use metrics::{counter, gauge};
+
+use crate::{
+    page::{Page, PageId},
+    DiskBPlusTree, Result,
+};
+
+pub(crate) fn split(kind: &'static str) {
+    counter!("diskbplus_splits_total", "kind" => kind).increment(1);
+}
+
+impl DiskBPlusTree {
+    // 定期的に呼ぶ。internal ページを全て読むので、木の大きさに比例した時間がかかる
+    pub fn record_metrics(&self) -> Result<()> {
+        // ページを読むと統計が変わるので、先に書き出す
+        let stats = self.cache_stats();
+        counter!("diskbplus_cache_hits_total").absolute(stats.hits);
+        counter!("diskbplus_cache_misses_total").absolute(stats.misses);
+        counter!("diskbplus_cache_evictions_total").absolute(stats.evictions);
+        let lookups = stats.hits + stats.misses;
+        if lookups > 0 {
+            gauge!("diskbplus_cache_hit_ratio").set(stats.hits as f64 / lookups as f64);
+        }
+
+        let (height, internal, leaf) = match self.root() {
+            None => (0, 0, 0),
+            Some(root) => {
+                let height = self.height(root)?;
+                let (internal, leaf) = self.count_pages(root, height)?;
+                (height, internal, leaf)
+            }
+        };
+        gauge!("diskbplus_height").set(height as f64);
+        gauge!("diskbplus_internal_pages").set(internal as f64);
+        gauge!("diskbplus_leaf_pages").set(leaf as f64);
+        gauge!("diskbplus_file_pages").set(self.file_pages() as f64);
+        Ok(())
+    }
+
+    // 全ての leaf は同じ深さにあるので、左端だけを辿る
+    fn height(&self, root: PageId) -> Result<usize> {
+        let mut height = 1;
+        let mut id = root;
+        while let Page::Internal(internal) = self.read_page(id)? {
+            id = internal.children[0];
+            height += 1;
+        }
+        Ok(height)
+    }
+
+    // (internal ページの数, leaf ページの数)
+    // leaf は親の children から数えるので読まない
+    fn count_pages(&self, page_id: PageId, height: usize) -> Result<(u64, u64)> {
+        if height == 1 {
+            return Ok((0, 1));
+        }
+        let internal = match self.read_page(page_id)? {
+            Page::Internal(internal) => internal,
+            Page::Leaf(_) => return Ok((0, 1)),
+        };
+        if height == 2 {
+            return Ok((1, internal.children.len() as u64));
+        }
+        let mut count = (1, 0);
+        for child in internal.children {
+            let (i, l) = self.count_pages(child, height - 1)?;
+            count.0 += i;
+            count.1 += l;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    use crate::{DiskBPlusTree, Options};
+
+    #[test]
+    fn record_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            cache_pages: 4,
+            ..Options::default()
+        };
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let mut t = DiskBPlusTree::open_with(dir.path().join("tree.db"), options).unwrap();
+            // 空の木でも書き出せる
+            t.record_metrics().unwrap();
+            for k in 0..20_000u64 {
+                t.insert(k, &k.to_le_bytes().repeat(8)).unwrap();
+            }
+            t.record_metrics().unwrap();
+        });
+        let values: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels: Vec<_> = key.labels().map(|l| l.value().to_string()).collect();
+                (format!("{}{:?}", key.name(), labels), value)
+            })
+            .collect();
+        let get = |name: &str| {
+            values
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v)
+                .unwrap_or_else(|| panic!("{} is not recorded", name))
+        };
+        let gauge = |name: &str| match get(name) {
+            DebugValue::Gauge(v) => v.into_inner(),
+            v => panic!("{} is {:?}", name, v),
+        };
+        let counter = |name: &str| match get(name) {
+            DebugValue::Counter(v) => *v,
+            v => panic!("{} is {:?}", name, v),
+        };
+
+        let leaf = gauge("diskbplus_leaf_pages[]");
+        let internal = gauge("diskbplus_internal_pages[]");
+        assert_eq!(gauge("diskbplus_height[]"), 3.0);
+        // 分割のたびにページが一つ増える
+        assert_eq!(
+            counter("diskbplus_splits_total[\"leaf\"]") as f64,
+            leaf - 1.0
+        );
+        assert_eq!(
+            counter("diskbplus_splits_total[\"internal\"]") as f64,
+            internal - 2.0
+        );
+        assert!(gauge("diskbplus_file_pages[]") > leaf + internal);
+        assert!(counter("diskbplus_cache_evictions_total[]") > 0);
+        let ratio = gauge("diskbplus_cache_hit_ratio[]");
+        assert!(0.0 < ratio && ratio < 1.0);
+    }
+}
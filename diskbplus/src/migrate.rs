@@ -0,0 +1,323 @@
+// 古い形式のファイルを現在の形式に書き換える
+//
+// file format version の変更
+//   1: 最初の形式。ページの header は 16 bytes で checksum が無い
+//   2: ページと superblock に checksum を追加した。ページの header は 20 bytes
+//   3: superblock に空きページのリストの先頭 (free_head) を追加した
+// version 1 のページは header が 4 bytes 小さく、そのまま書き換えると収まらないことがあるので、
+// root から辿れるエントリを新しいファイルに作り直して、元のファイルと置き換える
+use std::{
+    convert::TryInto,
+    ffi::OsString,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    page::{decode_page, InternalPage, LeafPage, Page, PageId, PAGE_SIZE},
+    pager::{double_write_path, Pager, Superblock, FILE_FORMAT_VERSION, MAGIC, NO_PAGE},
+    Compression, DiskBPlusTree, Error, Result, SstWriter,
+};
+
+const V1_HEADER_SIZE: usize = 16;
+const KIND_LEAF: u8 = 1;
+const KIND_INTERNAL: u8 = 2;
+
+// 書き換える前の version を返す。既に現在の形式なら何もしない
+// 置き換えるまで元のファイルは書き換えないので、途中で失敗しても、もう一度呼べば最初からやり直せる
+pub(crate) fn migrate(path: &Path) -> Result<u32> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; PAGE_SIZE];
+    file.read_exact(&mut buf)?;
+    if buf[0..4] != MAGIC {
+        return Err(Error::InvalidFile("bad magic"));
+    }
+    let u64_at = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+    let version = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    match version {
+        FILE_FORMAT_VERSION => {
+            // checksum などの確認
+            Superblock::decode(&buf)?;
+            return Ok(version);
+        }
+        1 | 2 => {}
+        _ => return Err(Error::UnsupportedFileVersion { found: version }),
+    }
+    if u32::from_le_bytes(buf[8..12].try_into().unwrap()) as usize != PAGE_SIZE {
+        return Err(Error::InvalidFile("page size mismatch"));
+    }
+    let root = match u64_at(16) {
+        NO_PAGE => None,
+        root => Some(root),
+    };
+    let superblock = Superblock {
+        root,
+        page_count: u64_at(24),
+        checkpoint_lsn: u64_at(32),
+        free_head: None,
+    };
+    if superblock.page_count == 0
+        || superblock.page_count * PAGE_SIZE as u64 > file.metadata()?.len()
+    {
+        return Err(Error::InvalidFile("page count out of range"));
+    }
+    // 子を左から辿って、エントリをキー順に sst に書き出す
+    // version 1 と 2 のページが混ざっていてもよい
+    let sst_path = suffixed(path, "-migrate.sst");
+    let mut sst = SstWriter::create(&sst_path)?;
+    let mut stack: Vec<_> = root.into_iter().collect();
+    while let Some(id) = stack.pop() {
+        if id == 0 || id >= superblock.page_count {
+            return Err(Error::Corrupted("page id out of range"));
+        }
+        read_page_at(&mut file, id, &mut buf)?;
+        let page = if buf[2] == 1 {
+            decode_v1(&buf)?
+        } else {
+            decode_page(&buf).map_err(|e| match e {
+                Error::ChecksumMismatch => Error::CorruptPage { page_id: id },
+                e => e,
+            })?
+        };
+        match page {
+            Page::Internal(internal) => stack.extend(internal.children.into_iter().rev()),
+            Page::Leaf(leaf) => {
+                for (key, value) in leaf.entries {
+                    sst.add(key, &value)?;
+                }
+            }
+        }
+    }
+    sst.finish()?;
+
+    // WAL が残っていれば開くときに replay するので、checkpoint の LSN は引き継ぐ
+    let new_path = suffixed(path, "-migrate");
+    if new_path.exists() {
+        fs::remove_file(&new_path)?;
+    }
+    DiskBPlusTree::open(&new_path)?.ingest(&sst_path)?;
+    let mut pager = Pager::open(&new_path, Compression::None, None)?;
+    pager.set_checkpoint_lsn(superblock.checkpoint_lsn);
+    pager.flush()?;
+    drop(pager);
+    fs::remove_file(double_write_path(&new_path))?;
+    File::open(&new_path)?.sync_all()?;
+    fs::rename(&new_path, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    fs::remove_file(&sst_path)?;
+    Ok(version)
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn read_page_at(file: &mut File, id: PageId, buf: &mut [u8]) -> Result<()> {
+    file.seek(SeekFrom::Start(id * PAGE_SIZE as u64))?;
+    file.read_exact(buf)?;
+    Ok(())
+}
+
+// version 1 のページ。header の後ろの形式は現在と同じで、slot の offset もページ先頭から
+fn decode_v1(buf: &[u8]) -> Result<Page> {
+    let u16_at = |at: usize| u16::from_le_bytes(buf[at..at + 2].try_into().unwrap()) as usize;
+    let u64_at = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+    if buf[0..2] != *b"BP" {
+        return Err(Error::Corrupted("bad magic"));
+    }
+    let count = u16_at(4);
+    let body_start = V1_HEADER_SIZE + count * 8;
+    match buf[3] {
+        KIND_LEAF => {
+            if body_start + count * 4 > PAGE_SIZE {
+                return Err(Error::Corrupted("entry count out of range"));
+            }
+            let mut entries = Vec::with_capacity(count);
+            for i in 0..count {
+                let slot_at = body_start + i * 4;
+                let (offset, len) = (u16_at(slot_at), u16_at(slot_at + 2));
+                let value = buf
+                    .get(offset..offset + len)
+                    .ok_or(Error::Corrupted("value slot out of range"))?;
+                entries.push((u64_at(V1_HEADER_SIZE + i * 8), value.to_vec()));
+            }
+            let next = match u64_at(8) {
+                NO_PAGE => None,
+                next => Some(next),
+            };
+            Ok(Page::Leaf(LeafPage { entries, next }))
+        }
+        KIND_INTERNAL => {
+            if body_start + count * 8 > PAGE_SIZE {
+                return Err(Error::Corrupted("entry count out of range"));
+            }
+            Ok(Page::Internal(InternalPage {
+                keys: (0..count).map(|i| u64_at(V1_HEADER_SIZE + i * 8)).collect(),
+                children: (0..count).map(|i| u64_at(body_start + i * 8)).collect(),
+            }))
+        }
+        _ => Err(Error::Corrupted("unknown page kind")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DiskBPlusTree, Key};
+
+    fn v1_superblock(root: PageId, page_count: u64) -> Vec<u8> {
+        let mut buf = vec![0; PAGE_SIZE];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&1u32.to_le_bytes());
+        buf[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        buf[16..24].copy_from_slice(&root.to_le_bytes());
+        buf[24..32].copy_from_slice(&page_count.to_le_bytes());
+        buf
+    }
+
+    fn v1_page(kind: u8, keys: &[Key], next: Option<PageId>, body: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; PAGE_SIZE];
+        buf[0..2].copy_from_slice(b"BP");
+        buf[2] = 1;
+        buf[3] = kind;
+        buf[4..6].copy_from_slice(&(keys.len() as u16).to_le_bytes());
+        buf[8..16].copy_from_slice(&next.unwrap_or(NO_PAGE).to_le_bytes());
+        for (i, key) in keys.iter().enumerate() {
+            let at = V1_HEADER_SIZE + i * 8;
+            buf[at..at + 8].copy_from_slice(&key.to_le_bytes());
+        }
+        let at = V1_HEADER_SIZE + keys.len() * 8;
+        buf[at..at + body.len()].copy_from_slice(body);
+        buf
+    }
+
+    // slot の後ろに値を置く
+    fn v1_leaf_with(entries: &[(Key, Vec<u8>)], next: Option<PageId>) -> Vec<u8> {
+        let keys: Vec<_> = entries.iter().map(|(k, _)| *k).collect();
+        let mut offset = V1_HEADER_SIZE + keys.len() * 12;
+        let mut body = Vec::new();
+        for (_, value) in entries {
+            body.extend((offset as u16).to_le_bytes());
+            body.extend((value.len() as u16).to_le_bytes());
+            offset += value.len();
+        }
+        for (_, value) in entries {
+            body.extend(value);
+        }
+        v1_page(KIND_LEAF, &keys, next, &body)
+    }
+
+    // 値が 1 byte の leaf
+    fn v1_leaf(keys: &[Key], next: Option<PageId>) -> Vec<u8> {
+        let entries: Vec<_> = keys.iter().map(|k| (*k, vec![*k as u8])).collect();
+        v1_leaf_with(&entries, next)
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let children: Vec<u8> = [2u64, 3].iter().flat_map(|id| id.to_le_bytes()).collect();
+        let pages = [
+            v1_superblock(1, 4),
+            v1_page(KIND_INTERNAL, &[1, 10], None, &children),
+            v1_leaf(&[1, 2, 3], Some(3)),
+            v1_leaf(&[10, 20], None),
+        ];
+        std::fs::write(&path, pages.concat()).unwrap();
+        assert!(matches!(
+            DiskBPlusTree::open(&path),
+            Err(Error::OutdatedFileVersion { found: 1 })
+        ));
+
+        assert_eq!(DiskBPlusTree::migrate(&path).unwrap(), 1);
+        assert_eq!(DiskBPlusTree::migrate(&path).unwrap(), FILE_FORMAT_VERSION);
+        let mut t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let entries = t.range(0, Key::MAX).unwrap();
+        let expected: Vec<_> = [1, 2, 3, 10, 20]
+            .iter()
+            .map(|k| (*k, vec![*k as u8]))
+            .collect();
+        assert_eq!(entries, expected);
+        // 書き込みも空きページの再利用もできる
+        t.remove(10).unwrap();
+        t.remove(20).unwrap();
+        t.insert(30, b"x").unwrap();
+        t.flush().unwrap();
+        assert_eq!(t.get(30).unwrap(), Some(b"x".to_vec()));
+    }
+
+    // 現在の形式では header が 4 bytes 大きいので、どちらもそのままでは 1 ページに収まらない
+    #[test]
+    fn migrate_full_v1_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        // 子が 255 の internal と、残り 3 bytes まで詰めた leaf
+        let mut full: Vec<_> = (0..300).map(|k| (k, vec![k as u8])).collect();
+        full.push((300, vec![7; 165]));
+        let mut pages = vec![v1_superblock(1, 257), vec![]];
+        pages.push(v1_leaf_with(&full, Some(3)));
+        assert_eq!(V1_HEADER_SIZE + 301 * 12 + 300 + 165, PAGE_SIZE - 3);
+        let mut keys = vec![0];
+        for id in 3..257 {
+            keys.push(1000 + id);
+            let next = if id == 256 { None } else { Some(id + 1) };
+            pages.push(v1_leaf(&[1000 + id], next));
+        }
+        let children: Vec<u8> = (2u64..257).flat_map(|id| id.to_le_bytes()).collect();
+        assert_eq!(keys.len(), 255);
+        pages[1] = v1_page(KIND_INTERNAL, &keys, None, &children);
+        std::fs::write(&path, pages.concat()).unwrap();
+
+        assert_eq!(DiskBPlusTree::migrate(&path).unwrap(), 1);
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let mut expected = full;
+        expected.extend((1003..1257).map(|k| (k, vec![k as u8])));
+        assert_eq!(t.range(0, Key::MAX).unwrap(), expected);
+        assert!(!suffixed(&path, "-migrate").exists());
+        assert!(!suffixed(&path, "-migrate.sst").exists());
+        assert!(!suffixed(&path, "-migrate-dwb").exists());
+    }
+
+    #[test]
+    fn migrate_v2_and_future() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = DiskBPlusTree::open(&path).unwrap();
+            for k in 0..1000 {
+                t.insert(k, &k.to_le_bytes()).unwrap();
+            }
+        }
+        // version 2 の superblock には free_head が無い
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        bytes[44..52].fill(0);
+        let checksum = crate::page::checksum(&bytes[..PAGE_SIZE], 40);
+        bytes[40..44].copy_from_slice(&checksum.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(DiskBPlusTree::migrate(&path).unwrap(), 2);
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        assert_eq!(t.range(0, Key::MAX).unwrap().len(), 1000);
+        drop(t);
+
+        bytes[4..8].copy_from_slice(&(FILE_FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            DiskBPlusTree::open(&path),
+            Err(Error::UnsupportedFileVersion { .. })
+        ));
+        assert!(matches!(
+            DiskBPlusTree::migrate(&path),
+            Err(Error::UnsupportedFileVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{wal::FsyncPolicy, Compression, EvictionPolicy, PageCipher};
+
+// DiskBPlusTree::open_with に渡す設定
+#[derive(Debug, Clone)]
+pub struct Options {
+    // None の場合は WAL を使わない
+    pub wal: Option<FsyncPolicy>,
+    // バッファプールに保持するページ数
+    pub cache_pages: usize,
+    // バッファプールから追い出すページの選び方
+    pub eviction: EvictionPolicy,
+    // leaf ページの圧縮方式。既存のページは設定に関わらず読める
+    pub compression: Compression,
+    // ページの暗号化。暗号化していない既存のページもそのまま読める
+    pub cipher: Option<Arc<dyn PageCipher>>,
+    // tracing feature が有効な場合、これより時間のかかった range を warn で記録する
+    pub slow_range: Duration,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            wal: None,
+            cache_pages: 256,
+            eviction: EvictionPolicy::Lru,
+            compression: Compression::None,
+            cipher: None,
+            slow_range: Duration::from_millis(100),
+        }
+    }
+}
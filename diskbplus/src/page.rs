@@ -0,0 +1,404 @@
+// ノードを固定長のページにエンコードする
+//
+// header (20 bytes, little endian)
+//   magic   : [u8; 2] = "BP"
+//   version : u8
+//   kind    : u8      (1 = leaf, 2 = internal)
+//   count   : u16     エントリ数
+//   flags   : u16     圧縮方式 (compression.rs) と暗号化 (cipher.rs)
+//   next    : u64     leaf の次のページ。無い場合は NO_PAGE
+//   checksum: u32     この欄を 0 にしたページ全体の CRC32C
+// leaf
+//   keys    : [u64; count]
+//   slots   : [(offset: u16, len: u16); count]  value の位置。offset はページ先頭から
+//   values  : slots の後ろに詰めて置く
+//   圧縮した場合は header の後ろに以下を置き、keys 以降を展開すると上の形式になる
+//   raw_len : u32     展開後の header を含めたサイズ
+//   data_len: u32     圧縮したデータのサイズ
+//   data    : 圧縮したデータ
+//   暗号化した場合の形式は cipher.rs
+// internal
+//   keys    : [u64; count]  children[i] の最小キー
+//   children: [u64; count]
+use std::convert::TryInto;
+
+use crate::{
+    cipher::FLAG_ENCRYPTED,
+    compression::{decompress, Compression, FLAG_COMPRESSED},
+    Error, Key, Result,
+};
+
+pub type PageId = u64;
+
+pub const PAGE_SIZE: usize = 4096;
+pub const PAGE_FORMAT_VERSION: u8 = 2;
+// 分割した後のページが必ず収まるように、value の長さを制限する
+pub const MAX_VALUE_LEN: usize = 1024;
+
+const MAGIC: [u8; 2] = *b"BP";
+pub(crate) const HEADER_SIZE: usize = 20;
+pub(crate) const CHECKSUM_AT: usize = 16;
+pub(crate) const FLAGS_AT: usize = 6;
+const COMPRESSED_HEADER_SIZE: usize = 8;
+// 圧縮する場合の展開後のサイズの上限。slot の offset が u16 に収まるようにする
+pub const MAX_LEAF_SIZE: usize = 8 * PAGE_SIZE;
+const KIND_LEAF: u8 = 1;
+const KIND_INTERNAL: u8 = 2;
+const NO_PAGE: u64 = u64::MAX;
+const KEY_SIZE: usize = 8;
+const SLOT_SIZE: usize = 4;
+const CHILD_SIZE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Page {
+    Leaf(LeafPage),
+    Internal(InternalPage),
+}
+
+impl Page {
+    pub fn encoded_size(&self) -> usize {
+        match self {
+            Page::Leaf(leaf) => leaf.encoded_size(),
+            Page::Internal(internal) => internal.encoded_size(),
+        }
+    }
+
+    // 圧縮しない場合に PAGE_SIZE に収まるか
+    pub fn fits(&self) -> bool {
+        self.encoded_size() <= PAGE_SIZE
+    }
+
+    // 圧縮した場合も含めて PAGE_SIZE に収まるか
+    pub fn fits_with(&self, compression: Compression) -> bool {
+        if self.fits() || compression == Compression::None {
+            return self.fits();
+        }
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page_with(self, &mut buf, compression).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeafPage {
+    pub entries: Vec<(Key, Vec<u8>)>,
+    pub next: Option<PageId>,
+}
+
+impl LeafPage {
+    pub fn encoded_size(&self) -> usize {
+        HEADER_SIZE
+            + self
+                .entries
+                .iter()
+                .map(|(_, v)| KEY_SIZE + SLOT_SIZE + v.len())
+                .sum::<usize>()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InternalPage {
+    pub keys: Vec<Key>,
+    pub children: Vec<PageId>,
+}
+
+impl InternalPage {
+    pub const MAX_CHILDREN: usize = (PAGE_SIZE - HEADER_SIZE) / (KEY_SIZE + CHILD_SIZE);
+
+    pub fn encoded_size(&self) -> usize {
+        HEADER_SIZE + self.keys.len() * (KEY_SIZE + CHILD_SIZE)
+    }
+
+    // ページの末尾に reserved bytes を空けておく場合の子の数の上限
+    pub(crate) fn max_children(reserved: usize) -> usize {
+        (PAGE_SIZE - HEADER_SIZE - reserved) / (KEY_SIZE + CHILD_SIZE)
+    }
+}
+
+// buf の長さは PAGE_SIZE であること
+pub fn encode_page(page: &Page, buf: &mut [u8]) -> Result<()> {
+    encode_page_with(page, buf, Compression::None).map(|_| ())
+}
+
+// leaf ページは圧縮して PAGE_SIZE に収まるなら圧縮して書き込む
+// 圧縮しても小さくならない場合は、そのまま書き込む
+// ページに書き込んだ header と body のバイト数を返す
+pub fn encode_page_with(page: &Page, buf: &mut [u8], compression: Compression) -> Result<usize> {
+    assert_eq!(buf.len(), PAGE_SIZE);
+    let size = page.encoded_size();
+    if let Page::Leaf(_) = page {
+        if size <= MAX_LEAF_SIZE {
+            let mut raw = vec![0; size];
+            encode_raw(page, &mut raw)?;
+            if let Some((flag, compressed)) = compression.compress(&raw[HEADER_SIZE..])? {
+                let data_at = HEADER_SIZE + COMPRESSED_HEADER_SIZE;
+                let stored = data_at + compressed.len();
+                if stored <= PAGE_SIZE && stored < size {
+                    buf.fill(0);
+                    buf[..HEADER_SIZE].copy_from_slice(&raw[..HEADER_SIZE]);
+                    buf[FLAGS_AT..FLAGS_AT + 2].copy_from_slice(&flag.to_le_bytes());
+                    buf[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&(size as u32).to_le_bytes());
+                    buf[HEADER_SIZE + 4..data_at]
+                        .copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+                    buf[data_at..stored].copy_from_slice(&compressed);
+                    write_checksum(buf);
+                    return Ok(stored);
+                }
+            }
+        }
+    }
+    if size > PAGE_SIZE {
+        return Err(Error::PageOverflow { size });
+    }
+    buf.fill(0);
+    encode_raw(page, buf)?;
+    write_checksum(buf);
+    Ok(size)
+}
+
+// buf の長さは page.encoded_size() 以上であること
+fn encode_raw(page: &Page, buf: &mut [u8]) -> Result<()> {
+    buf[0..2].copy_from_slice(&MAGIC);
+    buf[2] = PAGE_FORMAT_VERSION;
+    match page {
+        Page::Leaf(leaf) => {
+            buf[3] = KIND_LEAF;
+            buf[4..6].copy_from_slice(&(leaf.entries.len() as u16).to_le_bytes());
+            buf[8..16].copy_from_slice(&leaf.next.unwrap_or(NO_PAGE).to_le_bytes());
+            let count = leaf.entries.len();
+            let slots_start = HEADER_SIZE + count * KEY_SIZE;
+            let mut value_offset = slots_start + count * SLOT_SIZE;
+            for (i, (key, value)) in leaf.entries.iter().enumerate() {
+                if value.len() > MAX_VALUE_LEN {
+                    return Err(Error::ValueTooLarge { len: value.len() });
+                }
+                let key_at = HEADER_SIZE + i * KEY_SIZE;
+                buf[key_at..key_at + KEY_SIZE].copy_from_slice(&key.to_le_bytes());
+                let slot_at = slots_start + i * SLOT_SIZE;
+                buf[slot_at..slot_at + 2].copy_from_slice(&(value_offset as u16).to_le_bytes());
+                buf[slot_at + 2..slot_at + 4].copy_from_slice(&(value.len() as u16).to_le_bytes());
+                buf[value_offset..value_offset + value.len()].copy_from_slice(value);
+                value_offset += value.len();
+            }
+        }
+        Page::Internal(internal) => {
+            if internal.keys.len() != internal.children.len() {
+                return Err(Error::Corrupted("keys and children differ in length"));
+            }
+            buf[3] = KIND_INTERNAL;
+            buf[4..6].copy_from_slice(&(internal.keys.len() as u16).to_le_bytes());
+            buf[8..16].copy_from_slice(&NO_PAGE.to_le_bytes());
+            let count = internal.keys.len();
+            let children_start = HEADER_SIZE + count * KEY_SIZE;
+            for (i, (key, child)) in internal.keys.iter().zip(&internal.children).enumerate() {
+                let key_at = HEADER_SIZE + i * KEY_SIZE;
+                buf[key_at..key_at + KEY_SIZE].copy_from_slice(&key.to_le_bytes());
+                let child_at = children_start + i * CHILD_SIZE;
+                buf[child_at..child_at + CHILD_SIZE].copy_from_slice(&child.to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn write_checksum(buf: &mut [u8]) {
+    let checksum = page_checksum(buf);
+    buf[CHECKSUM_AT..CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+// ページの checksum が一致しない場合は Error::ChecksumMismatch を返す
+// 圧縮されたページは展開してから読む
+pub fn decode_page(buf: &[u8]) -> Result<Page> {
+    if buf.len() != PAGE_SIZE {
+        return Err(Error::Corrupted("wrong page length"));
+    }
+    if buf[0..2] != MAGIC {
+        return Err(Error::Corrupted("bad magic"));
+    }
+    if buf[2] != PAGE_FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(buf[2]));
+    }
+    if read_u32(buf, CHECKSUM_AT) != page_checksum(buf) {
+        return Err(Error::ChecksumMismatch);
+    }
+    let flags = read_u16(buf, FLAGS_AT);
+    if flags & FLAG_ENCRYPTED != 0 {
+        return Err(Error::Encrypted);
+    }
+    if flags & FLAG_COMPRESSED == 0 {
+        return decode_raw(buf);
+    }
+    let size = read_u32(buf, HEADER_SIZE) as usize;
+    if !(HEADER_SIZE..=MAX_LEAF_SIZE).contains(&size) {
+        return Err(Error::Corrupted("raw page size out of range"));
+    }
+    let data_at = HEADER_SIZE + COMPRESSED_HEADER_SIZE;
+    let data = buf
+        .get(data_at..data_at + read_u32(buf, HEADER_SIZE + 4) as usize)
+        .ok_or(Error::Corrupted("compressed data out of range"))?;
+    let mut raw = buf[..HEADER_SIZE].to_vec();
+    raw.extend(decompress(flags, data, size - HEADER_SIZE)?);
+    decode_raw(&raw)
+}
+
+fn decode_raw(buf: &[u8]) -> Result<Page> {
+    let count = read_u16(buf, 4) as usize;
+    match buf[3] {
+        KIND_LEAF => {
+            let slots_start = HEADER_SIZE + count * KEY_SIZE;
+            if slots_start + count * SLOT_SIZE > buf.len() {
+                return Err(Error::Corrupted("entry count out of range"));
+            }
+            let mut entries = Vec::with_capacity(count);
+            for i in 0..count {
+                let key = read_u64(buf, HEADER_SIZE + i * KEY_SIZE);
+                let slot_at = slots_start + i * SLOT_SIZE;
+                let offset = read_u16(buf, slot_at) as usize;
+                let len = read_u16(buf, slot_at + 2) as usize;
+                let value = buf
+                    .get(offset..offset + len)
+                    .ok_or(Error::Corrupted("value slot out of range"))?;
+                entries.push((key, value.to_vec()));
+            }
+            let next = match read_u64(buf, 8) {
+                NO_PAGE => None,
+                next => Some(next),
+            };
+            Ok(Page::Leaf(LeafPage { entries, next }))
+        }
+        KIND_INTERNAL => {
+            let children_start = HEADER_SIZE + count * KEY_SIZE;
+            if children_start + count * CHILD_SIZE > buf.len() {
+                return Err(Error::Corrupted("entry count out of range"));
+            }
+            let keys = (0..count)
+                .map(|i| read_u64(buf, HEADER_SIZE + i * KEY_SIZE))
+                .collect();
+            let children = (0..count)
+                .map(|i| read_u64(buf, children_start + i * CHILD_SIZE))
+                .collect();
+            Ok(Page::Internal(InternalPage { keys, children }))
+        }
+        _ => Err(Error::Corrupted("unknown page kind")),
+    }
+}
+
+pub(crate) fn page_checksum(buf: &[u8]) -> u32 {
+    checksum(buf, CHECKSUM_AT)
+}
+
+// buf[at..at + 4] に置く checksum。その欄は 0 とみなして計算する
+pub(crate) fn checksum(buf: &[u8], at: usize) -> u32 {
+    let crc = crc32c::crc32c(&buf[..at]);
+    let crc = crc32c::crc32c_append(crc, &[0; 4]);
+    crc32c::crc32c_append(crc, &buf[at + 4..])
+}
+
+pub(crate) fn read_u16(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+pub(crate) fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn leaf_roundtrip() {
+        let page = Page::Leaf(LeafPage {
+            entries: vec![(1, b"one".to_vec()), (2, vec![]), (10, vec![7; 100])],
+            next: Some(42),
+        });
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page(&page, &mut buf).unwrap();
+        assert_eq!(decode_page(&buf).unwrap(), page);
+
+        let page = Page::Leaf(LeafPage::default());
+        encode_page(&page, &mut buf).unwrap();
+        assert_eq!(decode_page(&buf).unwrap(), page);
+    }
+
+    #[test]
+    fn internal_roundtrip() {
+        let page = Page::Internal(InternalPage {
+            keys: (0..InternalPage::MAX_CHILDREN as u64).collect(),
+            children: (100..100 + InternalPage::MAX_CHILDREN as u64).collect(),
+        });
+        assert!(page.fits());
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page(&page, &mut buf).unwrap();
+        assert_eq!(decode_page(&buf).unwrap(), page);
+    }
+
+    #[test]
+    fn overflow() {
+        let page = Page::Leaf(LeafPage {
+            entries: (0..5).map(|k| (k, vec![0; MAX_VALUE_LEN])).collect(),
+            next: None,
+        });
+        assert!(!page.fits());
+        let mut buf = vec![0; PAGE_SIZE];
+        assert!(matches!(
+            encode_page(&page, &mut buf),
+            Err(Error::PageOverflow { .. })
+        ));
+        let page = Page::Leaf(LeafPage {
+            entries: vec![(0, vec![0; MAX_VALUE_LEN + 1])],
+            next: None,
+        });
+        assert!(matches!(
+            encode_page(&page, &mut buf),
+            Err(Error::ValueTooLarge { .. })
+        ));
+    }
+
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    #[test]
+    fn compressed_roundtrip() {
+        let methods = [
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+        ];
+        // 圧縮しなければ収まらない大きさにする
+        let page = Page::Leaf(LeafPage {
+            entries: (0..20).map(|k| (k, vec![b'a'; 500])).collect(),
+            next: Some(7),
+        });
+        assert!(!page.fits());
+        assert!(!page.fits_with(Compression::None));
+        let mut buf = vec![0; PAGE_SIZE];
+        for compression in methods {
+            assert!(page.fits_with(compression));
+            let stored = encode_page_with(&page, &mut buf, compression).unwrap();
+            assert!(stored < PAGE_SIZE);
+            assert_eq!(decode_page(&buf).unwrap(), page);
+        }
+    }
+
+    #[test]
+    fn invalid_page() {
+        let mut buf = vec![0; PAGE_SIZE];
+        assert!(matches!(decode_page(&buf), Err(Error::Corrupted(_))));
+        encode_page(&Page::Leaf(LeafPage::default()), &mut buf).unwrap();
+        buf[2] = PAGE_FORMAT_VERSION + 1;
+        assert!(matches!(
+            decode_page(&buf),
+            Err(Error::UnsupportedVersion(_))
+        ));
+        buf[2] = PAGE_FORMAT_VERSION;
+        buf[3] = 9;
+        assert!(matches!(decode_page(&buf), Err(Error::ChecksumMismatch)));
+        // 中身が 1 bit でも変われば検出できる
+        encode_page(&Page::Leaf(LeafPage::default()), &mut buf).unwrap();
+        buf[PAGE_SIZE - 1] ^= 1;
+        assert!(matches!(decode_page(&buf), Err(Error::ChecksumMismatch)));
+    }
+}
@@ -0,0 +1,492 @@
+// ファイルをmmapして、ページ単位で読み書きする
+//
+// page 0 は superblock
+//   magic       : [u8; 4] = "BPDB"
+//   version     : u32
+//   page_size   : u32
+//   reserved    : u32
+//   root        : u64  無い場合は NO_PAGE
+//   page_count  : u64  superblock を含めた使用中のページ数
+//   checkpoint  : u64  ページに反映済みの WAL の最後の LSN
+//   checksum    : u32  この欄を 0 にした superblock 全体の CRC32C
+//   free_head   : u64  空きページのリストの先頭。無い場合は NO_PAGE
+//
+// 空きページは以下の形式で、次の空きページを指す単方向リストにする
+//   magic       : [u8; 2] = "BF"
+//   reserved    : [u8; 6]
+//   next        : u64  無い場合は NO_PAGE
+//   checksum    : u32
+//
+// 書き込んだページは flush までメモリに置いておき、flush で double-write buffer に書き出してからファイルに反映する
+// 開くときに double-write buffer が残っていれば、それで書き直して途中で落ちた flush を終わらせる
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryInto,
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    iter,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use memmap2::MmapMut;
+
+use crate::{
+    cipher::{self, PageCipher, MAX_OVERHEAD},
+    compression::{Compression, CompressionStats},
+    doublewrite::DoubleWrite,
+    page::{checksum, decode_page, encode_page_with, InternalPage, Page, PageId, PAGE_SIZE},
+    Error, Result,
+};
+
+pub(crate) const FILE_FORMAT_VERSION: u32 = 3;
+
+pub(crate) const MAGIC: [u8; 4] = *b"BPDB";
+pub(crate) const NO_PAGE: u64 = u64::MAX;
+const INITIAL_PAGES: u64 = 16;
+const CHECKSUM_AT: usize = 40;
+const FREE_MAGIC: [u8; 2] = *b"BF";
+const FREE_CHECKSUM_AT: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct Pager {
+    file: File,
+    mmap: MmapMut,
+    root: Option<PageId>,
+    page_count: u64,
+    checkpoint_lsn: u64,
+    free_head: Option<PageId>,
+    // flush するまでは、最後に flush した時点の木がまだ参照しているので再利用しない
+    pending_free: Vec<PageId>,
+    // flush でファイルに反映するページ
+    staged: BTreeMap<PageId, Vec<u8>>,
+    double_write: DoubleWrite,
+    compression: Compression,
+    compression_stats: CompressionStats,
+    cipher: Option<Arc<dyn PageCipher>>,
+}
+
+impl Pager {
+    // path の末尾に "-dwb" を付けたファイルを double-write buffer にする
+    pub(crate) fn open<P: AsRef<Path>>(
+        path: P,
+        compression: Compression,
+        cipher: Option<Arc<dyn PageCipher>>,
+    ) -> Result<Self> {
+        if cipher.as_ref().is_some_and(|c| c.overhead() > MAX_OVERHEAD) {
+            return Err(Error::Cipher("cipher overhead is too large".into()));
+        }
+        let double_write = DoubleWrite::open(double_write_path(path.as_ref()))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        if is_new {
+            file.set_len(INITIAL_PAGES * PAGE_SIZE as u64)?;
+        }
+        if file.metadata()?.len() < PAGE_SIZE as u64 {
+            return Err(Error::InvalidFile("file is smaller than a page"));
+        }
+        // ファイルはこの Pager だけが書き換える前提
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        let mut pager = Self {
+            file,
+            mmap,
+            root: None,
+            page_count: 1,
+            checkpoint_lsn: 0,
+            free_head: None,
+            pending_free: Vec::new(),
+            staged: BTreeMap::new(),
+            double_write,
+            compression,
+            compression_stats: CompressionStats::default(),
+            cipher,
+        };
+        if is_new {
+            pager.double_write.clear()?;
+            pager.write_superblock();
+        } else {
+            pager.redo()?;
+            pager.read_superblock()?;
+        }
+        Ok(pager)
+    }
+
+    pub(crate) fn root(&self) -> Option<PageId> {
+        self.root
+    }
+
+    pub(crate) fn set_root(&mut self, root: Option<PageId>) {
+        self.root = root;
+    }
+
+    pub(crate) fn checkpoint_lsn(&self) -> u64 {
+        self.checkpoint_lsn
+    }
+
+    pub(crate) fn set_checkpoint_lsn(&mut self, lsn: u64) {
+        self.checkpoint_lsn = lsn;
+    }
+
+    pub(crate) fn read(&self, id: PageId) -> Result<Page> {
+        let buf = self.page_bytes(id)?;
+        let page = match &self.cipher {
+            Some(c) if cipher::is_encrypted(buf) => {
+                cipher::decrypt_page(c.as_ref(), id, buf).and_then(|plain| decode_page(&plain))
+            }
+            _ => decode_page(buf),
+        };
+        page.map_err(|e| match e {
+            Error::ChecksumMismatch => Error::CorruptPage { page_id: id },
+            e => e,
+        })
+    }
+
+    pub(crate) fn compression_stats(&self) -> CompressionStats {
+        self.compression_stats
+    }
+
+    // 暗号化する場合に、ページの末尾に空けておくバイト数
+    pub(crate) fn reserved(&self) -> usize {
+        self.cipher.as_deref().map_or(0, cipher::reserved)
+    }
+
+    // 圧縮と暗号化をした後に PAGE_SIZE に収まるか
+    pub(crate) fn fits(&self, page: &Page) -> bool {
+        let reserved = self.reserved();
+        if reserved == 0 {
+            return page.fits_with(self.compression);
+        }
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page_with(page, &mut buf, self.compression)
+            .is_ok_and(|stored| stored + reserved <= PAGE_SIZE)
+    }
+
+    // internal ページの子の数の上限
+    pub(crate) fn max_children(&self) -> usize {
+        InternalPage::max_children(self.reserved())
+    }
+
+    pub(crate) fn write(&mut self, id: PageId, page: &Page) -> Result<()> {
+        self.page_range(id)?;
+        let mut buf = vec![0; PAGE_SIZE];
+        let stored = match &self.cipher {
+            None => encode_page_with(page, &mut buf, self.compression)?,
+            Some(c) => {
+                let mut plain = vec![0; PAGE_SIZE];
+                let stored = encode_page_with(page, &mut plain, self.compression)?;
+                cipher::encrypt_page(c.as_ref(), id, &plain, stored, &mut buf)?;
+                stored
+            }
+        };
+        self.staged.insert(id, buf);
+        if let Page::Leaf(_) = page {
+            self.compression_stats.pages += 1;
+            self.compression_stats.raw_bytes += page.encoded_size() as u64;
+            self.compression_stats.stored_bytes += stored as u64;
+        }
+        Ok(())
+    }
+
+    // 空きページがあれば再利用し、無ければファイルの末尾に新しいページを確保する
+    pub(crate) fn allocate(&mut self) -> Result<PageId> {
+        if let Some(id) = self.free_head {
+            self.free_head = self.read_free(id)?;
+            return Ok(id);
+        }
+        let id = self.page_count;
+        let needed = (id + 1) * PAGE_SIZE as u64;
+        if needed > self.mmap.len() as u64 {
+            // 毎回伸ばさないように倍々で確保する
+            let new_len = needed.max(self.mmap.len() as u64 * 2);
+            self.mmap.flush()?;
+            self.file.set_len(new_len)?;
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+        self.page_count += 1;
+        Ok(id)
+    }
+
+    // 使わなくなったページは、次の flush で空きページのリストに加える
+    pub(crate) fn free(&mut self, id: PageId) {
+        self.pending_free.push(id);
+    }
+
+    // 末尾に連続している空きページを取り除いてファイルを縮める
+    // 取り除いたページ数を返す
+    pub(crate) fn vacuum(&mut self) -> Result<u64> {
+        self.flush()?;
+        let mut free = BTreeSet::new();
+        let mut id = self.free_head;
+        while let Some(current) = id {
+            if !free.insert(current) {
+                return Err(Error::Corrupted("free page list has a cycle"));
+            }
+            id = self.read_free(current)?;
+        }
+        let old_count = self.page_count;
+        while free.remove(&(self.page_count - 1)) {
+            self.page_count -= 1;
+        }
+        if self.page_count == old_count {
+            return Ok(0);
+        }
+        // 残った空きページでリストを作り直す
+        self.free_head = None;
+        self.pending_free.extend(free.into_iter().rev());
+        self.flush()?;
+        self.file
+            .set_len(self.page_count.max(INITIAL_PAGES) * PAGE_SIZE as u64)?;
+        self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(old_count - self.page_count)
+    }
+
+    #[cfg(any(test, feature = "metrics"))]
+    pub(crate) fn page_count(&self) -> u64 {
+        self.page_count
+    }
+
+    fn read_free(&self, id: PageId) -> Result<Option<PageId>> {
+        let buf = self.page_bytes(id)?;
+        let stored = u32::from_le_bytes(
+            buf[FREE_CHECKSUM_AT..FREE_CHECKSUM_AT + 4]
+                .try_into()
+                .unwrap(),
+        );
+        if buf[0..2] != FREE_MAGIC || stored != checksum(buf, FREE_CHECKSUM_AT) {
+            return Err(Error::CorruptPage { page_id: id });
+        }
+        let next = u64::from_le_bytes(buf[8..16].try_into().unwrap());
+        Ok(if next == NO_PAGE { None } else { Some(next) })
+    }
+
+    fn write_free(&mut self, id: PageId, next: Option<PageId>) -> Result<()> {
+        self.page_range(id)?;
+        let mut buf = vec![0; PAGE_SIZE];
+        buf[0..2].copy_from_slice(&FREE_MAGIC);
+        buf[8..16].copy_from_slice(&next.unwrap_or(NO_PAGE).to_le_bytes());
+        let checksum = checksum(&buf, FREE_CHECKSUM_AT);
+        buf[FREE_CHECKSUM_AT..FREE_CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+        self.staged.insert(id, buf);
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.commit()?;
+        self.release_free()?;
+        self.double_write.clear()
+    }
+
+    // 書き込んだページと superblock を double-write buffer に書き出してから、ファイルに反映する
+    // 反映の途中で落ちても、開くときに全て反映し直すので、ファイルは前の状態か新しい状態のどちらかになる
+    fn commit(&mut self) -> Result<()> {
+        let mut superblock = vec![0; PAGE_SIZE];
+        self.superblock().encode(&mut superblock);
+        let staged = std::mem::take(&mut self.staged);
+        self.double_write.write(
+            staged
+                .iter()
+                .map(|(id, page)| (*id, &page[..]))
+                .chain(iter::once((0, &superblock[..]))),
+        )?;
+        for (id, page) in &staged {
+            let range = self.page_range(*id)?;
+            self.mmap[range].copy_from_slice(page);
+        }
+        self.mmap.flush()?;
+        self.mmap[0..PAGE_SIZE].copy_from_slice(&superblock);
+        self.mmap.flush_range(0, PAGE_SIZE)?;
+        Ok(())
+    }
+
+    // 前回の flush が double-write buffer を書き出した後で落ちていれば、ファイルに反映し直す
+    fn redo(&mut self) -> Result<()> {
+        let pages = self.double_write.read()?;
+        let needed = match pages.iter().map(|(id, _)| *id + 1).max() {
+            None => return Ok(()),
+            Some(count) => count * PAGE_SIZE as u64,
+        };
+        if needed > self.mmap.len() as u64 {
+            self.file.set_len(needed)?;
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+        for (id, page) in pages {
+            let start = id as usize * PAGE_SIZE;
+            self.mmap[start..start + PAGE_SIZE].copy_from_slice(&page);
+        }
+        self.mmap.flush()?;
+        self.double_write.clear()
+    }
+
+    // 解放したページは、前の superblock が指す木がまだ参照しているかもしれない
+    // 新しい木を指す superblock を書き出した後で空きページのリストに加え、もう一度 superblock を書き出す
+    // 間で落ちた場合は、これらのページが空きページのリストから漏れるだけで済む
+    fn release_free(&mut self) -> Result<()> {
+        if self.pending_free.is_empty() {
+            return Ok(());
+        }
+        for id in std::mem::take(&mut self.pending_free) {
+            let next = self.free_head;
+            self.write_free(id, next)?;
+            self.free_head = Some(id);
+        }
+        self.commit()
+    }
+
+    fn page_range(&self, id: PageId) -> Result<std::ops::Range<usize>> {
+        if id == 0 || id >= self.page_count {
+            return Err(Error::Corrupted("page id out of range"));
+        }
+        let start = id as usize * PAGE_SIZE;
+        Ok(start..start + PAGE_SIZE)
+    }
+
+    // flush 前のページは書き込んだ内容を返す
+    fn page_bytes(&self, id: PageId) -> Result<&[u8]> {
+        let range = self.page_range(id)?;
+        match self.staged.get(&id) {
+            Some(page) => Ok(page),
+            None => Ok(&self.mmap[range]),
+        }
+    }
+
+    fn superblock(&self) -> Superblock {
+        Superblock {
+            root: self.root,
+            page_count: self.page_count,
+            checkpoint_lsn: self.checkpoint_lsn,
+            free_head: self.free_head,
+        }
+    }
+
+    fn write_superblock(&mut self) {
+        let superblock = self.superblock();
+        superblock.encode(&mut self.mmap[0..PAGE_SIZE]);
+    }
+
+    fn read_superblock(&mut self) -> Result<()> {
+        let sb = Superblock::decode(&self.mmap[0..PAGE_SIZE])?;
+        if sb.page_count * PAGE_SIZE as u64 > self.mmap.len() as u64 {
+            return Err(Error::InvalidFile("page count out of range"));
+        }
+        self.root = sb.root;
+        self.page_count = sb.page_count;
+        self.checkpoint_lsn = sb.checkpoint_lsn;
+        self.free_head = sb.free_head;
+        Ok(())
+    }
+}
+
+pub(crate) fn double_write_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("-dwb");
+    PathBuf::from(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Superblock {
+    pub(crate) root: Option<PageId>,
+    pub(crate) page_count: u64,
+    pub(crate) checkpoint_lsn: u64,
+    pub(crate) free_head: Option<PageId>,
+}
+
+impl Superblock {
+    pub(crate) fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&FILE_FORMAT_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        buf[16..24].copy_from_slice(&self.root.unwrap_or(NO_PAGE).to_le_bytes());
+        buf[24..32].copy_from_slice(&self.page_count.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.checkpoint_lsn.to_le_bytes());
+        buf[44..52].copy_from_slice(&self.free_head.unwrap_or(NO_PAGE).to_le_bytes());
+        let checksum = checksum(buf, CHECKSUM_AT);
+        buf[CHECKSUM_AT..CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    pub(crate) fn decode(buf: &[u8]) -> Result<Self> {
+        if buf[0..4] != MAGIC {
+            return Err(Error::InvalidFile("bad magic"));
+        }
+        let version = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+        if version < FILE_FORMAT_VERSION {
+            return Err(Error::OutdatedFileVersion { found: version });
+        }
+        if version > FILE_FORMAT_VERSION {
+            return Err(Error::UnsupportedFileVersion { found: version });
+        }
+        let stored = u32::from_le_bytes(buf[CHECKSUM_AT..CHECKSUM_AT + 4].try_into().unwrap());
+        if stored != checksum(buf, CHECKSUM_AT) {
+            return Err(Error::CorruptPage { page_id: 0 });
+        }
+        let page_size = u32::from_le_bytes(buf[8..12].try_into().unwrap());
+        if page_size as usize != PAGE_SIZE {
+            return Err(Error::InvalidFile("page size mismatch"));
+        }
+        let root = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+        let page_count = u64::from_le_bytes(buf[24..32].try_into().unwrap());
+        let checkpoint_lsn = u64::from_le_bytes(buf[32..40].try_into().unwrap());
+        let free_head = u64::from_le_bytes(buf[44..52].try_into().unwrap());
+        if page_count == 0 {
+            return Err(Error::InvalidFile("page count out of range"));
+        }
+        Ok(Self {
+            root: if root == NO_PAGE { None } else { Some(root) },
+            page_count,
+            checkpoint_lsn,
+            free_head: if free_head == NO_PAGE {
+                None
+            } else {
+                Some(free_head)
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page::LeafPage;
+
+    fn leaf(key: u64) -> Page {
+        Page::Leaf(LeafPage {
+            entries: vec![(key, key.to_le_bytes().to_vec())],
+            next: None,
+        })
+    }
+
+    #[test]
+    fn freed_page_is_kept_until_superblock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let mut pager = Pager::open(&path, Compression::None, None).unwrap();
+        let old = pager.allocate().unwrap();
+        pager.write(old, &leaf(1)).unwrap();
+        pager.set_root(Some(old));
+        pager.flush().unwrap();
+
+        let new = pager.allocate().unwrap();
+        pager.write(new, &leaf(2)).unwrap();
+        pager.set_root(Some(new));
+        pager.free(old);
+        pager.commit().unwrap();
+        // ここで落ちても、superblock は新しい root を指していて、old はまだ空きページになっていない
+        let bytes = std::fs::read(&path).unwrap();
+        let sb = Superblock::decode(&bytes[..PAGE_SIZE]).unwrap();
+        assert_eq!(sb.root, Some(new));
+        assert_eq!(sb.free_head, None);
+        let at = old as usize * PAGE_SIZE;
+        assert_eq!(decode_page(&bytes[at..at + PAGE_SIZE]).unwrap(), leaf(1));
+
+        pager.release_free().unwrap();
+        drop(pager);
+        let mut pager = Pager::open(&path, Compression::None, None).unwrap();
+        assert_eq!(pager.root(), Some(new));
+        assert_eq!(pager.allocate().unwrap(), old);
+    }
+}
@@ -0,0 +1,277 @@
+// キー順に並んだエントリを書き込んだ読み込み専用のファイル
+// DiskBPlusTree::ingest で、leaf をまとめて木に繋げる
+//
+// page 0 は header
+//   magic      : [u8; 4] = "BPST"
+//   version    : u32
+//   page_size  : u32
+//   reserved   : u32
+//   entries    : u64  エントリ数
+//   min_key    : u64
+//   max_key    : u64
+//   leaf_count : u64
+//   checksum   : u32  この欄を 0 にした header 全体の CRC32C
+// page 1 以降は leaf ページで、形式は page.rs と同じ。next は使わない
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    page::{checksum, decode_page, encode_page, LeafPage, Page, MAX_VALUE_LEN, PAGE_SIZE},
+    Error, Key, Result,
+};
+
+const MAGIC: [u8; 4] = *b"BPST";
+const SST_FORMAT_VERSION: u32 = 1;
+const CHECKSUM_AT: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SstHeader {
+    pub(crate) entries: u64,
+    pub(crate) min_key: Key,
+    pub(crate) max_key: Key,
+    pub(crate) leaf_count: u64,
+}
+
+// キーの昇順に add して、最後に finish を呼ぶ
+#[derive(Debug)]
+pub struct SstWriter {
+    file: BufWriter<File>,
+    leaf: LeafPage,
+    header: SstHeader,
+    last_key: Option<Key>,
+}
+
+impl SstWriter {
+    // ファイルが既にあれば上書きする
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        // header は finish で書き込む
+        file.write_all(&[0; PAGE_SIZE])?;
+        Ok(Self {
+            file,
+            leaf: LeafPage::default(),
+            header: SstHeader {
+                entries: 0,
+                min_key: 0,
+                max_key: 0,
+                leaf_count: 0,
+            },
+            last_key: None,
+        })
+    }
+
+    // キーは直前に add したキーより大きいこと
+    pub fn add(&mut self, key: Key, value: &[u8]) -> Result<()> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge { len: value.len() });
+        }
+        if let Some(last) = self.last_key {
+            if key <= last {
+                return Err(Error::Unsorted { key, last });
+            }
+        }
+        self.leaf.entries.push((key, value.to_vec()));
+        if self.leaf.encoded_size() > PAGE_SIZE {
+            let entry = self.leaf.entries.pop().unwrap();
+            self.write_leaf()?;
+            self.leaf.entries.push(entry);
+        }
+        if self.last_key.is_none() {
+            self.header.min_key = key;
+        }
+        self.header.max_key = key;
+        self.header.entries += 1;
+        self.last_key = Some(key);
+        Ok(())
+    }
+
+    // 書き込んだエントリ数を返す
+    pub fn finish(mut self) -> Result<u64> {
+        if !self.leaf.entries.is_empty() {
+            self.write_leaf()?;
+        }
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        let mut buf = vec![0; PAGE_SIZE];
+        self.header.encode(&mut buf);
+        file.sync_data()?;
+        std::io::Seek::rewind(&mut file)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Ok(self.header.entries)
+    }
+
+    fn write_leaf(&mut self) -> Result<()> {
+        let mut buf = vec![0; PAGE_SIZE];
+        encode_page(&Page::Leaf(std::mem::take(&mut self.leaf)), &mut buf)?;
+        self.file.write_all(&buf)?;
+        self.header.leaf_count += 1;
+        Ok(())
+    }
+}
+
+impl SstHeader {
+    fn encode(&self, buf: &mut [u8]) {
+        buf.fill(0);
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..8].copy_from_slice(&SST_FORMAT_VERSION.to_le_bytes());
+        buf[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        buf[16..24].copy_from_slice(&self.entries.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.min_key.to_le_bytes());
+        buf[32..40].copy_from_slice(&self.max_key.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.leaf_count.to_le_bytes());
+        let checksum = checksum(buf, CHECKSUM_AT);
+        buf[CHECKSUM_AT..CHECKSUM_AT + 4].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self> {
+        let u32_at = |at: usize| u32::from_le_bytes(buf[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+        if buf[0..4] != MAGIC {
+            return Err(Error::InvalidFile("bad magic"));
+        }
+        if u32_at(4) != SST_FORMAT_VERSION {
+            return Err(Error::InvalidFile("unsupported file format version"));
+        }
+        if u32_at(CHECKSUM_AT) != checksum(buf, CHECKSUM_AT) {
+            return Err(Error::CorruptPage { page_id: 0 });
+        }
+        if u32_at(8) as usize != PAGE_SIZE {
+            return Err(Error::InvalidFile("page size mismatch"));
+        }
+        Ok(Self {
+            entries: u64_at(16),
+            min_key: u64_at(24),
+            max_key: u64_at(32),
+            leaf_count: u64_at(40),
+        })
+    }
+}
+
+// SstWriter で書いたファイルを先頭から読む
+#[derive(Debug)]
+pub(crate) struct SstReader {
+    file: BufReader<File>,
+    header: SstHeader,
+    read_leaves: u64,
+    last_key: Option<Key>,
+}
+
+impl SstReader {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut buf = vec![0; PAGE_SIZE];
+        file.read_exact(&mut buf)?;
+        Ok(Self {
+            file,
+            header: SstHeader::decode(&buf)?,
+            read_leaves: 0,
+            last_key: None,
+        })
+    }
+
+    pub(crate) fn header(&self) -> SstHeader {
+        self.header
+    }
+
+    // キーの順序も確認する
+    pub(crate) fn next_leaf(&mut self) -> Result<Option<LeafPage>> {
+        if self.read_leaves == self.header.leaf_count {
+            return Ok(None);
+        }
+        let mut buf = vec![0; PAGE_SIZE];
+        self.file.read_exact(&mut buf)?;
+        let page_id = self.read_leaves + 1;
+        self.read_leaves += 1;
+        let leaf = match decode_page(&buf) {
+            Ok(Page::Leaf(leaf)) => leaf,
+            Ok(Page::Internal(_)) => return Err(Error::Corrupted("expected a leaf page")),
+            Err(Error::ChecksumMismatch) => return Err(Error::CorruptPage { page_id }),
+            Err(e) => return Err(e),
+        };
+        for (key, _) in &leaf.entries {
+            if self.last_key.is_some_and(|last| last >= *key) {
+                return Err(Error::Corrupted("sst keys are out of order"));
+            }
+            self.last_key = Some(*key);
+        }
+        Ok(Some(leaf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.sst");
+        let mut w = SstWriter::create(&path).unwrap();
+        for k in 10..1010 {
+            w.add(k, &[k as u8; 20]).unwrap();
+        }
+        assert!(matches!(w.add(10, b""), Err(Error::Unsorted { .. })));
+        assert_eq!(w.finish().unwrap(), 1000);
+
+        let mut r = SstReader::open(&path).unwrap();
+        let header = r.header();
+        assert_eq!((header.min_key, header.max_key), (10, 1009));
+        assert!(header.leaf_count > 1);
+        let mut keys = Vec::new();
+        while let Some(leaf) = r.next_leaf().unwrap() {
+            keys.extend(leaf.entries.iter().map(|(k, _)| *k));
+        }
+        assert_eq!(keys, (10..1010).collect::<Vec<_>>());
+    }
+
+    fn write_sst(path: &Path, keys: impl Iterator<Item = Key>) {
+        let mut w = SstWriter::create(path).unwrap();
+        for k in keys {
+            w.add(k, &k.to_le_bytes()).unwrap();
+        }
+        w.finish().unwrap();
+    }
+
+    #[test]
+    fn ingest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let sst = dir.path().join("run.sst");
+        {
+            let mut t = crate::DiskBPlusTree::open(&path).unwrap();
+            for k in 1000..2000 {
+                t.insert(k, &k.to_le_bytes()).unwrap();
+            }
+            // 右に繋げる
+            write_sst(&sst, 2000..30_000);
+            assert_eq!(t.ingest(&sst).unwrap(), 28_000);
+            // 左に繋げる
+            write_sst(&sst, 0..1000);
+            assert_eq!(t.ingest(&sst).unwrap(), 1000);
+            // 範囲が重なる場合は一件ずつ入れる
+            write_sst(&sst, [500, 40_000].iter().copied());
+            assert_eq!(t.ingest(&sst).unwrap(), 2);
+            for k in 1990..2010 {
+                t.remove(k).unwrap();
+            }
+        }
+        let t = crate::DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        let expected: Vec<_> = (0..1990).chain(2010..30_000).chain(Some(40_000)).collect();
+        assert_eq!(keys, expected);
+        assert_eq!(
+            t.get(29_999).unwrap(),
+            Some(29_999u64.to_le_bytes().to_vec())
+        );
+    }
+}
@@ -0,0 +1,1258 @@
+use std::{
+    cell::RefCell,
+    ffi::OsString,
+    fs::OpenOptions,
+    mem,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    buffer::BufferPool,
+    migrate,
+    page::{InternalPage, LeafPage, Page, PageId, MAX_VALUE_LEN, PAGE_SIZE},
+    pager::Pager,
+    sst::SstReader,
+    wal::{read_records, Wal, WalRecord},
+    CacheStats, CompressionStats, Error, Key, Options, Result,
+};
+
+// mmap したファイル上の B+ tree
+// 子ノードはポインタではなく PageId で参照するので、開き直すと superblock を読むだけで復元できる
+#[derive(Debug)]
+pub struct DiskBPlusTree {
+    // get や range でもキャッシュを更新するので RefCell にする
+    pool: RefCell<BufferPool>,
+    wal: Option<Wal>,
+    #[cfg(feature = "tracing")]
+    slow_range: std::time::Duration,
+}
+
+// 上書き前の値と、分割した場合は右側のページの最小キーと PageId
+type Inserted = (Option<Vec<u8>>, Option<(Key, PageId)>);
+
+// 削除した結果、ノードが空になったかどうか
+enum Removal {
+    Kept,
+    // 空になった leaf は leaf の連結から外す必要があるので、next を返す
+    EmptyLeaf { next: Option<PageId> },
+    EmptyInternal,
+}
+
+impl DiskBPlusTree {
+    // ファイルが無ければ作成する
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, Options::default())
+    }
+
+    // WAL を使う場合は、path の末尾に "-wal" を付けたファイルに書き込む
+    // WAL が残っていれば、options に関わらず replay してから開く
+    pub fn open_with<P: AsRef<Path>>(path: P, options: Options) -> Result<Self> {
+        let wal_path = wal_path(path.as_ref());
+        let pager = Pager::open(&path, options.compression, options.cipher)?;
+        let mut tree = Self {
+            pool: RefCell::new(BufferPool::new(
+                pager,
+                options.cache_pages,
+                options.eviction,
+            )),
+            wal: None,
+            #[cfg(feature = "tracing")]
+            slow_range: options.slow_range,
+        };
+        if wal_path.exists() {
+            tree.recover(&wal_path)?;
+        }
+        if let Some(policy) = options.wal {
+            let next_lsn = tree.pool.get_mut().pager().checkpoint_lsn() + 1;
+            tree.wal = Some(Wal::open(wal_path, policy, next_lsn)?);
+        }
+        Ok(tree)
+    }
+
+    // 古い形式のファイルを現在の形式に書き換える。書き換える前の version を返す
+    // 開いている DiskBPlusTree が無いときに呼ぶこと
+    // この library より新しい形式のファイルは Error::UnsupportedFileVersion になる
+    pub fn migrate<P: AsRef<Path>>(path: P) -> Result<u32> {
+        migrate::migrate(path.as_ref())
+    }
+
+    pub fn get(&self, key: Key) -> Result<Option<Vec<u8>>> {
+        let leaf_id = match self.find_leaf(key)? {
+            None => return Ok(None),
+            Some(id) => id,
+        };
+        let leaf = self.read_leaf(leaf_id)?;
+        Ok(leaf
+            .entries
+            .binary_search_by_key(&key, |(k, _)| *k)
+            .ok()
+            .map(|i| leaf.entries[i].1.clone()))
+    }
+
+    pub fn insert(&mut self, key: Key, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge { len: value.len() });
+        }
+        self.checkpoint_if_full()?;
+        self.log(&WalRecord::Insert {
+            key,
+            value: value.to_vec(),
+        })?;
+        let root = match self.root() {
+            Some(root) => root,
+            None => {
+                let id = self.pager_mut().allocate()?;
+                let leaf = LeafPage {
+                    entries: vec![(key, value.to_vec())],
+                    next: None,
+                };
+                self.write_page(id, Page::Leaf(leaf))?;
+                self.pager_mut().set_root(Some(id));
+                return Ok(None);
+            }
+        };
+        let (old, splited) = self.insert_at(root, key, value)?;
+        if let Some((right_key, right_id)) = splited {
+            let left_key = self.min_key(root)?;
+            let new_root = self.pager_mut().allocate()?;
+            let internal = InternalPage {
+                keys: vec![left_key, right_key],
+                children: vec![root, right_id],
+            };
+            self.write_page(new_root, Page::Internal(internal))?;
+            self.pager_mut().set_root(Some(new_root));
+        }
+        Ok(old)
+    }
+
+    pub fn remove(&mut self, key: Key) -> Result<Option<Vec<u8>>> {
+        self.checkpoint_if_full()?;
+        self.log(&WalRecord::Remove { key })?;
+        let root = match self.root() {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        let (old, removal) = match self.remove_at(root, key, None)? {
+            None => return Ok(None),
+            Some(removed) => removed,
+        };
+        match removal {
+            Removal::EmptyLeaf { .. } | Removal::EmptyInternal => {
+                self.free_page(root)?;
+                self.pager_mut().set_root(None);
+            }
+            Removal::Kept => self.shrink_root()?,
+        }
+        Ok(Some(old))
+    }
+
+    // min_key <= key <= max_key のエントリをキー順で返す
+    pub fn range(&self, min_key: Key, max_key: Key) -> Result<Vec<(Key, Vec<u8>)>> {
+        #[cfg(feature = "tracing")]
+        {
+            // 範囲の走査中に起きた追い出しなどが、この span の中に記録される
+            let _span = tracing::debug_span!("range", min_key, max_key).entered();
+            let start = std::time::Instant::now();
+            let result = self.range_inner(min_key, max_key);
+            let elapsed = start.elapsed();
+            if elapsed >= self.slow_range {
+                tracing::warn!(
+                    min_key,
+                    max_key,
+                    entries = result.as_ref().map_or(0, |r| r.len()),
+                    elapsed_us = elapsed.as_micros() as u64,
+                    "slow range scan"
+                );
+            }
+            result
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.range_inner(min_key, max_key)
+    }
+
+    fn range_inner(&self, min_key: Key, max_key: Key) -> Result<Vec<(Key, Vec<u8>)>> {
+        let mut result = Vec::new();
+        if min_key > max_key {
+            return Ok(result);
+        }
+        let mut leaf_id = self.find_leaf(min_key)?;
+        while let Some(id) = leaf_id {
+            let leaf = self.read_leaf(id)?;
+            for (key, value) in leaf.entries {
+                if key > max_key {
+                    return Ok(result);
+                }
+                if key >= min_key {
+                    result.push((key, value));
+                }
+            }
+            leaf_id = leaf.next;
+        }
+        Ok(result)
+    }
+
+    // mmap の内容をファイルに反映する
+    // 反映済みになった WAL の record は捨てる
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.sync()?;
+            self.pool
+                .get_mut()
+                .pager_mut()
+                .set_checkpoint_lsn(wal.last_lsn());
+        }
+        self.pool.get_mut().flush()?;
+        if let Some(wal) = &mut self.wal {
+            wal.truncate()?;
+        }
+        Ok(())
+    }
+
+    // dirty なページでバッファプールが埋まっていれば checkpoint する
+    // 操作の途中で書き戻すと木が繋がっていない状態がファイルに残るので、操作の切れ目でだけ呼ぶ
+    fn checkpoint_if_full(&mut self) -> Result<()> {
+        if self.pool.get_mut().needs_flush() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // ファイルの末尾の空きページを取り除いてファイルを縮める。取り除いたページ数を返す
+    pub fn vacuum(&mut self) -> Result<u64> {
+        self.flush()?;
+        self.pager_mut().vacuum()
+    }
+
+    // 木の全てのページを書き直す。鍵を変えた後に呼ぶと、全てのページが新しい鍵で暗号化される
+    // 暗号化していなかったファイルでは、子の多い internal ページが収まらず Error::PageOverflow になることがある
+    pub fn reencrypt(&mut self) -> Result<()> {
+        let mut stack: Vec<_> = self.root().into_iter().collect();
+        while let Some(page_id) = stack.pop() {
+            let page = self.read_page(page_id)?;
+            if let Page::Internal(internal) = &page {
+                stack.extend(&internal.children);
+            }
+            self.write_page(page_id, page)?;
+            self.checkpoint_if_full()?;
+        }
+        self.flush()
+    }
+
+    // SstWriter で書いたファイルのエントリを木に加える。加えたエントリ数を返す
+    // キーの範囲が木と重ならなければ、leaf をまとめて作って木の端に繋げる
+    // 重なる場合は一件ずつ insert する
+    // まとめて繋げる場合は WAL に書かず、前後で flush する
+    pub fn ingest<P: AsRef<Path>>(&mut self, path: P) -> Result<u64> {
+        let mut sst = SstReader::open(path)?;
+        let header = sst.header();
+        if header.entries == 0 {
+            return Ok(0);
+        }
+        let edges = self.edges()?;
+        let overlapped =
+            edges.is_some_and(|(_, min, _, max)| min <= header.max_key && header.min_key <= max);
+        if overlapped {
+            let mut count = 0;
+            while let Some(leaf) = sst.next_leaf()? {
+                for (key, value) in leaf.entries {
+                    self.insert(key, &value)?;
+                    count += 1;
+                }
+            }
+            return Ok(count);
+        }
+        self.flush()?;
+        let (first, last) = match edges {
+            None => {
+                let (count, _, sub_root) = self.build_from_sst(&mut sst, None)?;
+                self.pager_mut().set_root(Some(sub_root));
+                self.flush()?;
+                return Ok(count);
+            }
+            Some((first, _, last, _)) => (first, last),
+        };
+        let root = self.root().unwrap();
+        let tree_min = self.min_key(root)?;
+        let right = tree_min < header.min_key;
+        // 左に繋げる場合は、新しい leaf の末尾を木の先頭の leaf に繋げる
+        let next = if right { None } else { Some(first) };
+        let (count, first_new, sub_root) = self.build_from_sst(&mut sst, next)?;
+        if right {
+            let mut leaf = self.read_leaf(last)?;
+            leaf.next = Some(first_new);
+            self.write_page(last, Page::Leaf(leaf))?;
+        }
+        // root に空きがあれば部分木を root の子にし、無ければ新しい root を作る
+        let max_children = self.pool.get_mut().pager().max_children();
+        match self.read_page(root)? {
+            Page::Internal(mut internal) if internal.children.len() < max_children => {
+                if right {
+                    internal.keys.push(header.min_key);
+                    internal.children.push(sub_root);
+                } else {
+                    internal.keys.insert(0, header.min_key);
+                    internal.children.insert(0, sub_root);
+                }
+                self.write_page(root, Page::Internal(internal))?;
+            }
+            _ => {
+                let new_root = self.pager_mut().allocate()?;
+                let internal = if right {
+                    InternalPage {
+                        keys: vec![tree_min, header.min_key],
+                        children: vec![root, sub_root],
+                    }
+                } else {
+                    InternalPage {
+                        keys: vec![header.min_key, tree_min],
+                        children: vec![sub_root, root],
+                    }
+                };
+                self.write_page(new_root, Page::Internal(internal))?;
+                self.pager_mut().set_root(Some(new_root));
+            }
+        }
+        self.flush()?;
+        Ok(count)
+    }
+
+    // 木を辿って、全てのページが読めることとキーの順序を確認する
+    pub fn verify(&self) -> Result<()> {
+        match self.root() {
+            None => Ok(()),
+            Some(root) => self.verify_at(root, None, None),
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.pool.borrow().stats()
+    }
+
+    // Pager に書き戻した leaf ページの統計。キャッシュ上のページは含まない
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.pool.borrow().pager().compression_stats()
+    }
+
+    // checkpoint より後の record をページに反映し、WAL を空にする
+    // 途中で切れた末尾の record は read_records が読み飛ばすので、ここで一緒に捨てる
+    // dirty なページは checkpoint まで書き戻さず、checkpoint の途中で落ちていても Pager::open が double-write buffer から書き直すので、
+    // ファイルは最後に終えた checkpoint 時点の木になっている
+    // Drop や replay 中の flush で checkpoint より後の操作まで書き戻していても、同じ record を順に反映し直せば結果は変わらない
+    fn recover(&mut self, wal_path: &Path) -> Result<()> {
+        self.verify()?;
+        let checkpoint = self.pool.get_mut().pager().checkpoint_lsn();
+        let mut last_lsn = checkpoint;
+        for (lsn, record) in read_records(wal_path)? {
+            if lsn <= checkpoint {
+                continue;
+            }
+            // self.wal は None なので、replay した操作は WAL に書かれない
+            match record {
+                WalRecord::Insert { key, value } => self.insert(key, &value)?,
+                WalRecord::Remove { key } => self.remove(key)?,
+            };
+            last_lsn = lsn;
+        }
+        self.pager_mut().set_checkpoint_lsn(last_lsn);
+        self.pool.get_mut().flush()?;
+        let file = OpenOptions::new().write(true).open(wal_path)?;
+        file.set_len(0)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // ページを書き換える前に呼ぶ
+    fn log(&mut self, record: &WalRecord) -> Result<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.append(record)?;
+        }
+        Ok(())
+    }
+
+    fn insert_at(&mut self, page_id: PageId, key: Key, value: &[u8]) -> Result<Inserted> {
+        match self.read_page(page_id)? {
+            Page::Leaf(mut leaf) => {
+                let old = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(i) => Some(mem::replace(&mut leaf.entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        leaf.entries.insert(i, (key, value.to_vec()));
+                        None
+                    }
+                };
+                let page = Page::Leaf(leaf);
+                if self.pool.get_mut().pager().fits(&page) {
+                    self.write_page(page_id, page)?;
+                    return Ok((old, None));
+                }
+                let mut leaf = match page {
+                    Page::Leaf(leaf) => leaf,
+                    Page::Internal(_) => unreachable!(),
+                };
+                // 圧縮する場合も、エンコード後のサイズで半分に分ける
+                // 以下のようになるので、leaf.nextを引き継ぐ
+                //   before split: leaf->other
+                //   after  split: leaf->right->other
+                let at = leaf_split_point(&leaf.entries);
+                let right = LeafPage {
+                    entries: leaf.entries.split_off(at),
+                    next: leaf.next,
+                };
+                let right_id = self.pager_mut().allocate()?;
+                leaf.next = Some(right_id);
+                let right_key = right.entries[0].0;
+                self.write_page(right_id, Page::Leaf(right))?;
+                self.write_page(page_id, Page::Leaf(leaf))?;
+                #[cfg(feature = "tracing")]
+                tracing::debug!(page_id, right_id, right_key, "leaf split");
+                #[cfg(feature = "metrics")]
+                crate::metrics_impl::split("leaf");
+                Ok((old, Some((right_key, right_id))))
+            }
+            Page::Internal(mut internal) => {
+                let index = find_index(&internal.keys, key);
+                // 子の処理中に追い出されないように、書き戻すまで pin しておく
+                self.pool.get_mut().pin(page_id)?;
+                let inserted = self.insert_at(internal.children[index], key, value);
+                self.pool.get_mut().unpin(page_id);
+                let (old, splited) = inserted?;
+                let mut changed = false;
+                if key < internal.keys[index] {
+                    // 先頭の子に最小値より小さいキーが入った
+                    internal.keys[index] = key;
+                    changed = true;
+                }
+                if let Some((right_key, right_id)) = splited {
+                    internal.keys.insert(index + 1, right_key);
+                    internal.children.insert(index + 1, right_id);
+                    changed = true;
+                }
+                if internal.children.len() > self.pool.get_mut().pager().max_children() {
+                    let at = internal.children.len() / 2;
+                    let right = InternalPage {
+                        keys: internal.keys.split_off(at),
+                        children: internal.children.split_off(at),
+                    };
+                    let right_id = self.pager_mut().allocate()?;
+                    let right_key = right.keys[0];
+                    self.write_page(right_id, Page::Internal(right))?;
+                    self.write_page(page_id, Page::Internal(internal))?;
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(page_id, right_id, right_key, "internal split");
+                    #[cfg(feature = "metrics")]
+                    crate::metrics_impl::split("internal");
+                    return Ok((old, Some((right_key, right_id))));
+                }
+                if changed {
+                    self.write_page(page_id, Page::Internal(internal))?;
+                }
+                Ok((old, None))
+            }
+        }
+    }
+
+    // lower はこのノードが持つキーの下限。木の左端のノードでは None
+    fn remove_at(
+        &mut self,
+        page_id: PageId,
+        key: Key,
+        lower: Option<Key>,
+    ) -> Result<Option<(Vec<u8>, Removal)>> {
+        match self.read_page(page_id)? {
+            Page::Leaf(mut leaf) => {
+                let index = match leaf.entries.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(i) => i,
+                    Err(_) => return Ok(None),
+                };
+                let (_, old) = leaf.entries.remove(index);
+                if leaf.entries.is_empty() {
+                    return Ok(Some((old, Removal::EmptyLeaf { next: leaf.next })));
+                }
+                self.write_page(page_id, Page::Leaf(leaf))?;
+                Ok(Some((old, Removal::Kept)))
+            }
+            Page::Internal(mut internal) => {
+                let index = find_index(&internal.keys, key);
+                let child = internal.children[index];
+                let child_lower = if index == 0 {
+                    lower
+                } else {
+                    Some(internal.keys[index])
+                };
+                self.pool.get_mut().pin(page_id)?;
+                let removed = self.remove_at(child, key, child_lower);
+                self.pool.get_mut().unpin(page_id);
+                let (old, removal) = match removed? {
+                    None => return Ok(None),
+                    Some(removed) => removed,
+                };
+                match removal {
+                    // 最小値が消えても、keys[index] は下限として正しいのでそのままにする
+                    Removal::Kept => return Ok(Some((old, Removal::Kept))),
+                    Removal::EmptyLeaf { next } => self.unlink_leaf(child, child_lower, next)?,
+                    Removal::EmptyInternal => {}
+                }
+                self.free_page(child)?;
+                // underflow時のマージは行わない。空になった子だけを取り除く
+                #[cfg(feature = "tracing")]
+                tracing::debug!(page_id, child, lower = child_lower, "empty child removed");
+                internal.keys.remove(index);
+                internal.children.remove(index);
+                if internal.children.is_empty() {
+                    return Ok(Some((old, Removal::EmptyInternal)));
+                }
+                self.write_page(page_id, Page::Internal(internal))?;
+                Ok(Some((old, Removal::Kept)))
+            }
+        }
+    }
+
+    // 一つ前の leaf の next を、取り除く leaf の next に付け替える
+    fn unlink_leaf(
+        &mut self,
+        removed: PageId,
+        lower: Option<Key>,
+        next: Option<PageId>,
+    ) -> Result<()> {
+        let lower = match lower {
+            // 左端の leaf なので、前の leaf は無い
+            None | Some(0) => return Ok(()),
+            Some(lower) => lower,
+        };
+        let mut leaf_id = self.find_leaf(lower - 1)?;
+        while let Some(id) = leaf_id {
+            let mut leaf = self.read_leaf(id)?;
+            if leaf.next == Some(removed) {
+                leaf.next = next;
+                return self.write_page(id, Page::Leaf(leaf));
+            }
+            leaf_id = leaf.next;
+        }
+        Err(Error::Corrupted(
+            "leaf is not reachable from its predecessor",
+        ))
+    }
+
+    // 子が一つだけになった root は取り除いて高さを縮める
+    fn shrink_root(&mut self) -> Result<()> {
+        while let Some(root) = self.root() {
+            match self.read_page(root)? {
+                Page::Internal(internal) if internal.children.len() == 1 => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(root, child = internal.children[0], "root shrunk");
+                    self.free_page(root)?;
+                    self.pager_mut().set_root(Some(internal.children[0]));
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn free_page(&mut self, id: PageId) -> Result<()> {
+        self.pool.get_mut().discard(id);
+        self.pager_mut().free(id);
+        Ok(())
+    }
+
+    // match の中で RefCell を借用したままにならないよう、必ずこれらを経由する
+    pub(crate) fn read_page(&self, id: PageId) -> Result<Page> {
+        self.pool.borrow_mut().read(id)
+    }
+
+    fn write_page(&mut self, id: PageId, page: Page) -> Result<()> {
+        self.pool.get_mut().write(id, page)
+    }
+
+    pub(crate) fn root(&self) -> Option<PageId> {
+        self.pool.borrow().pager().root()
+    }
+
+    // superblock と空きページを含めた、ファイル上のページ数
+    #[cfg(feature = "metrics")]
+    pub(crate) fn file_pages(&self) -> u64 {
+        self.pool.borrow().pager().page_count()
+    }
+
+    fn pager_mut(&mut self) -> &mut Pager {
+        self.pool.get_mut().pager_mut()
+    }
+
+    // このページ以下のキーは lower <= key < upper の範囲にあること
+    fn verify_at(&self, page_id: PageId, lower: Option<Key>, upper: Option<Key>) -> Result<()> {
+        let in_range = |key: Key| lower.is_none_or(|l| l <= key) && upper.is_none_or(|u| key < u);
+        match self.read_page(page_id)? {
+            Page::Leaf(leaf) => {
+                let keys: Vec<_> = leaf.entries.iter().map(|(k, _)| *k).collect();
+                if keys.is_empty() || !is_sorted(&keys) || !keys.iter().all(|k| in_range(*k)) {
+                    return Err(Error::Corrupted("leaf keys are out of order"));
+                }
+                Ok(())
+            }
+            Page::Internal(internal) => {
+                if internal.keys.is_empty()
+                    || internal.keys.len() != internal.children.len()
+                    || !is_sorted(&internal.keys)
+                    || !internal.keys[1..].iter().all(|k| in_range(*k))
+                {
+                    return Err(Error::Corrupted("internal keys are out of order"));
+                }
+                for (i, child) in internal.children.iter().enumerate() {
+                    let child_lower = if i == 0 {
+                        lower
+                    } else {
+                        Some(internal.keys[i])
+                    };
+                    let child_upper = internal.keys.get(i + 1).copied().or(upper);
+                    self.verify_at(*child, child_lower, child_upper)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // sst の leaf を詰め直して書き込み、その上に internal ページを積む
+    // 末尾の leaf の next は next にする
+    // エントリ数と、先頭の leaf と部分木の root を返す
+    fn build_from_sst(
+        &mut self,
+        sst: &mut SstReader,
+        next: Option<PageId>,
+    ) -> Result<(u64, PageId, PageId)> {
+        // 暗号化する場合も収まるように、空ける分を除いた大きさで詰める
+        let limit = PAGE_SIZE - self.pool.get_mut().pager().reserved();
+        let mut count = 0;
+        let mut level = Vec::new();
+        let mut pending = None;
+        let mut current = LeafPage::default();
+        while let Some(leaf) = sst.next_leaf()? {
+            for entry in leaf.entries {
+                count += 1;
+                current.entries.push(entry);
+                if current.encoded_size() > limit {
+                    let entry = current.entries.pop().unwrap();
+                    let full = mem::replace(
+                        &mut current,
+                        LeafPage {
+                            entries: vec![entry],
+                            next: None,
+                        },
+                    );
+                    self.push_leaf(full, &mut level, &mut pending)?;
+                }
+            }
+        }
+        if !current.entries.is_empty() {
+            self.push_leaf(current, &mut level, &mut pending)?;
+        }
+        if count != sst.header().entries {
+            return Err(Error::Corrupted("sst entry count mismatch"));
+        }
+        let (last_id, mut last) = pending.ok_or(Error::Corrupted("sst has no leaf"))?;
+        last.next = next;
+        self.write_page(last_id, Page::Leaf(last))?;
+        let first = level[0].1;
+        let max_children = self.pool.get_mut().pager().max_children();
+        while level.len() > 1 {
+            let mut upper = Vec::new();
+            for chunk in level.chunks(max_children) {
+                let id = self.pager_mut().allocate()?;
+                let internal = InternalPage {
+                    keys: chunk.iter().map(|(k, _)| *k).collect(),
+                    children: chunk.iter().map(|(_, id)| *id).collect(),
+                };
+                self.write_page(id, Page::Internal(internal))?;
+                self.checkpoint_if_full()?;
+                upper.push((chunk[0].0, id));
+            }
+            level = upper;
+        }
+        Ok((count, first, level[0].1))
+    }
+
+    // 次の leaf の PageId が決まってから、一つ前の leaf を next を付けて書き込む
+    fn push_leaf(
+        &mut self,
+        leaf: LeafPage,
+        level: &mut Vec<(Key, PageId)>,
+        pending: &mut Option<(PageId, LeafPage)>,
+    ) -> Result<()> {
+        let id = self.pager_mut().allocate()?;
+        level.push((leaf.entries[0].0, id));
+        if let Some((prev_id, mut prev)) = pending.replace((id, leaf)) {
+            prev.next = Some(id);
+            self.write_page(prev_id, Page::Leaf(prev))?;
+            // 作っている部分木はまだ木から辿れないので、途中で checkpoint してもよい
+            // 落ちた場合は、書いたページが使われないまま残る
+            self.checkpoint_if_full()?;
+        }
+        Ok(())
+    }
+
+    // 先頭の leaf とその最小キー、末尾の leaf とその最大キー
+    fn edges(&self) -> Result<Option<(PageId, Key, PageId, Key)>> {
+        let (first, last) = match (self.find_leaf(0)?, self.find_leaf(Key::MAX)?) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(None),
+        };
+        let min = self.read_leaf(first)?.entries.first().map(|(k, _)| *k);
+        let max = self.read_leaf(last)?.entries.last().map(|(k, _)| *k);
+        match (min, max) {
+            (Some(min), Some(max)) => Ok(Some((first, min, last, max))),
+            _ => Err(Error::Corrupted("empty page in the tree")),
+        }
+    }
+
+    fn find_leaf(&self, key: Key) -> Result<Option<PageId>> {
+        let mut page_id = match self.root() {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        loop {
+            match self.read_page(page_id)? {
+                Page::Leaf(_) => return Ok(Some(page_id)),
+                Page::Internal(internal) => {
+                    page_id = internal.children[find_index(&internal.keys, key)];
+                }
+            }
+        }
+    }
+
+    fn read_leaf(&self, page_id: PageId) -> Result<LeafPage> {
+        match self.read_page(page_id)? {
+            Page::Leaf(leaf) => Ok(leaf),
+            Page::Internal(_) => Err(Error::Corrupted("expected a leaf page")),
+        }
+    }
+
+    fn min_key(&self, page_id: PageId) -> Result<Key> {
+        let key = match self.read_page(page_id)? {
+            Page::Leaf(leaf) => leaf.entries.first().map(|(k, _)| *k),
+            Page::Internal(internal) => internal.keys.first().copied(),
+        };
+        key.ok_or(Error::Corrupted("empty page in the tree"))
+    }
+}
+
+impl Drop for DiskBPlusTree {
+    fn drop(&mut self) {
+        let _ = self.pool.get_mut().flush();
+    }
+}
+
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("-wal");
+    PathBuf::from(name)
+}
+
+// keys[i] は children[i] の下限。先頭の子はそれ未満のキーも担当する
+pub(crate) fn find_index(keys: &[Key], key: Key) -> usize {
+    keys.partition_point(|k| *k <= key).saturating_sub(1)
+}
+
+fn is_sorted(keys: &[Key]) -> bool {
+    keys.windows(2).all(|w| w[0] < w[1])
+}
+
+// エンコード後のサイズがおおよそ半分になる位置で分割する
+pub(crate) fn leaf_split_point(entries: &[(Key, Vec<u8>)]) -> usize {
+    let size = |(_, v): &(Key, Vec<u8>)| 12 + v.len();
+    let half = entries.iter().map(size).sum::<usize>() / 2;
+    let mut acc = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        acc += size(entry);
+        if acc >= half {
+            return (i + 1).clamp(1, entries.len() - 1);
+        }
+    }
+    entries.len() / 2
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{EvictionPolicy, PAGE_SIZE};
+
+    fn value(key: Key) -> Vec<u8> {
+        // 長さを変えて、leaf の分割位置がばらつくようにする
+        format!("value-{}", key)
+            .repeat((key % 7 + 1) as usize)
+            .into_bytes()
+    }
+
+    #[test]
+    fn insert_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = DiskBPlusTree::open(&path).unwrap();
+            assert_eq!(t.get(1).unwrap(), None);
+            for k in (0..3000).map(|k| (k * 7919) % 3000) {
+                assert_eq!(t.insert(k, &value(k)).unwrap(), None);
+            }
+            assert_eq!(t.insert(10, b"ten").unwrap(), Some(value(10)));
+        }
+        let t = DiskBPlusTree::open(&path).unwrap();
+        assert_eq!(t.get(10).unwrap(), Some(b"ten".to_vec()));
+        assert_eq!(t.get(2999).unwrap(), Some(value(2999)));
+        assert_eq!(t.get(3000).unwrap(), None);
+        let r = t.range(100, 1999).unwrap();
+        assert_eq!(r.len(), 1900);
+        assert!(r.iter().enumerate().all(|(i, (k, _))| *k == 100 + i as Key));
+        assert_eq!(t.range(5, 4).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut t = DiskBPlusTree::open(dir.path().join("tree.db")).unwrap();
+        for k in 0..2000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        // 連続したキーを消して、leaf が丸ごと空になるようにする
+        for k in 500..1500 {
+            assert_eq!(t.remove(k).unwrap(), Some(value(k)));
+        }
+        assert_eq!(t.remove(500).unwrap(), None);
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        let expected: Vec<_> = (0..500).chain(1500..2000).collect();
+        assert_eq!(keys, expected);
+        for k in (0..500).chain(1500..2000) {
+            assert_eq!(t.remove(k).unwrap(), Some(value(k)));
+        }
+        assert_eq!(t.range(0, Key::MAX).unwrap(), vec![]);
+        t.insert(3, b"three").unwrap();
+        assert_eq!(t.get(3).unwrap(), Some(b"three".to_vec()));
+    }
+
+    #[test]
+    fn split_internal() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut t = DiskBPlusTree::open(dir.path().join("tree.db")).unwrap();
+        // leaf の数が InternalPage::MAX_CHILDREN を超えるまで入れる
+        for k in 0..60_000 {
+            t.insert(k, &[]).unwrap();
+        }
+        let root = t.root().unwrap();
+        match t.read_page(root).unwrap() {
+            Page::Internal(internal) => {
+                assert!(matches!(
+                    t.read_page(internal.children[0]).unwrap(),
+                    Page::Internal(_)
+                ));
+            }
+            Page::Leaf(_) => panic!("root must be an internal page"),
+        }
+        assert_eq!(t.get(45_678).unwrap(), Some(vec![]));
+        assert_eq!(t.range(0, Key::MAX).unwrap().len(), 60_000);
+    }
+
+    #[test]
+    fn reuse_free_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut t = DiskBPlusTree::open(dir.path().join("tree.db")).unwrap();
+        for k in 0..2000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        let page_count = t.pool.borrow().pager().page_count();
+        for k in 0..2000 {
+            t.remove(k).unwrap();
+        }
+        // 空いたページは flush した後から使う
+        t.flush().unwrap();
+        for k in 0..2000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        assert_eq!(t.pool.borrow().pager().page_count(), page_count);
+        t.verify().unwrap();
+        assert_eq!(t.range(0, Key::MAX).unwrap().len(), 2000);
+    }
+
+    #[test]
+    fn vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let mut t = DiskBPlusTree::open(&path).unwrap();
+        for k in 0..4000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        t.flush().unwrap();
+        let file_len = std::fs::metadata(&path).unwrap().len();
+        // キー順に入れたので、後ろの leaf ほどファイルの末尾にある
+        for k in 1000..4000 {
+            t.remove(k).unwrap();
+        }
+        assert!(t.vacuum().unwrap() > 0);
+        assert_eq!(t.vacuum().unwrap(), 0);
+        drop(t);
+        assert!(std::fs::metadata(&path).unwrap().len() < file_len);
+        let mut t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        assert_eq!(t.range(0, Key::MAX).unwrap().len(), 1000);
+        for k in 1000..4000 {
+            t.insert(k, &value(k)).unwrap();
+        }
+        assert_eq!(t.get(3999).unwrap(), Some(value(3999)));
+    }
+
+    #[test]
+    fn small_cache() {
+        for eviction in [
+            EvictionPolicy::Lru,
+            EvictionPolicy::Lfu,
+            EvictionPolicy::Arc,
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("tree.db");
+            let options = Options {
+                cache_pages: 4,
+                eviction,
+                ..Options::default()
+            };
+            {
+                let mut t = DiskBPlusTree::open_with(&path, options.clone()).unwrap();
+                for k in 0..3000 {
+                    t.insert(k, &value(k)).unwrap();
+                }
+                let stats = t.cache_stats();
+                assert!(stats.hits > 0);
+                assert!(stats.evictions > 0);
+            }
+            // 追い出しと flush で書き戻した内容を読めること
+            let t = DiskBPlusTree::open_with(&path, options).unwrap();
+            assert_eq!(t.range(0, Key::MAX).unwrap().len(), 3000);
+            assert_eq!(t.get(1234).unwrap(), Some(value(1234)));
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compression() {
+        use crate::Compression;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let json = |k: Key| format!(r#"{{"id":{},"name":"user","tags":["a","b","c"]}}"#, k);
+        {
+            let options = Options {
+                compression: Compression::Lz4,
+                ..Options::default()
+            };
+            let mut t = DiskBPlusTree::open_with(&path, options).unwrap();
+            for k in 0..5000 {
+                t.insert(k, json(k).repeat(10).as_bytes()).unwrap();
+            }
+            t.flush().unwrap();
+            assert!(t.compression_stats().ratio() > 2.0);
+        }
+        // 圧縮しない設定でも読める
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        assert_eq!(
+            t.get(1234).unwrap(),
+            Some(json(1234).repeat(10).into_bytes())
+        );
+        assert_eq!(t.range(0, Key::MAX).unwrap().len(), 5000);
+    }
+
+    #[test]
+    fn write_ahead_log() {
+        use crate::FsyncPolicy;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let options = Options {
+            wal: Some(FsyncPolicy::Always),
+            ..Options::default()
+        };
+        {
+            let mut t = DiskBPlusTree::open_with(&path, options.clone()).unwrap();
+            t.insert(1, b"one").unwrap();
+            t.insert(2, b"two").unwrap();
+            t.remove(1).unwrap();
+            let records = read_records(wal_path(&path)).unwrap();
+            assert_eq!(records.len(), 3);
+            assert_eq!(records[2], (3, WalRecord::Remove { key: 1 }));
+            t.flush().unwrap();
+            assert_eq!(read_records(wal_path(&path)).unwrap(), vec![]);
+        }
+        let mut t = DiskBPlusTree::open_with(&path, options).unwrap();
+        assert_eq!(t.pool.borrow().pager().checkpoint_lsn(), 3);
+        t.insert(3, b"three").unwrap();
+        let records = read_records(wal_path(&path)).unwrap();
+        assert_eq!(
+            records.iter().map(|(lsn, _)| *lsn).collect::<Vec<_>>(),
+            vec![4]
+        );
+    }
+
+    #[test]
+    fn recover_from_wal() {
+        use crate::FsyncPolicy;
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let options = Options {
+                wal: Some(FsyncPolicy::Always),
+                ..Options::default()
+            };
+            let mut t = DiskBPlusTree::open_with(&path, options).unwrap();
+            for k in 0..500 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            t.flush().unwrap();
+            for k in 500..1000 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            for k in 0..100 {
+                t.remove(k).unwrap();
+            }
+            // flush せずに落ちたことにする
+            mem::forget(t);
+        }
+        // 書き込み途中の record が残っている
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(wal_path(&path))
+            .unwrap();
+        wal.write_all(&[0x10, 0, 0, 0, 1, 2]).unwrap();
+
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, (100..1000).collect::<Vec<_>>());
+        assert_eq!(t.pool.borrow().pager().checkpoint_lsn(), 1100);
+        assert_eq!(std::fs::metadata(wal_path(&path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn recover_with_small_cache() {
+        use crate::FsyncPolicy;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let options = Options {
+                cache_pages: 4,
+                wal: Some(FsyncPolicy::Off),
+                ..Options::default()
+            };
+            let mut t = DiskBPlusTree::open_with(&path, options).unwrap();
+            for k in 0..500 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            t.flush().unwrap();
+            // 追い出しと途中の checkpoint が何度も起きる
+            for k in 500..3000 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            for k in (0..3000).step_by(3) {
+                t.remove(k).unwrap();
+            }
+            assert!(t.cache_stats().evictions > 0);
+            // flush せずに落ちたことにする
+            mem::forget(t);
+        }
+
+        let t = DiskBPlusTree::open(&path).unwrap();
+        t.verify().unwrap();
+        let keys: Vec<_> = t
+            .range(0, Key::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, (0..3000).filter(|k| k % 3 != 0).collect::<Vec<_>>());
+        assert_eq!(t.get(2999).unwrap(), Some(value(2999)));
+    }
+
+    // kill_during_checkpoint の子プロセスが書き込むエントリ
+    // i 番目のキーは木の中でばらけるようにして、checkpoint ごとに多くのページを書き換える
+    fn killed_entry(i: u64) -> (Key, Vec<u8>) {
+        (
+            i.wrapping_mul(0x9e37_79b9_7f4a_7c15),
+            i.to_le_bytes().repeat(100),
+        )
+    }
+
+    // kill_during_checkpoint から子プロセスとして実行する。止められるまで書き込み続ける
+    // checkpoint する insert の前に、標準出力に "checkpoint" と書く
+    #[test]
+    #[ignore]
+    fn insert_until_killed() {
+        use crate::FsyncPolicy;
+
+        let path = match std::env::var_os("DISKBPLUS_KILL_PATH") {
+            None => return,
+            Some(path) => path,
+        };
+        let options = Options {
+            cache_pages: 1500,
+            wal: Some(FsyncPolicy::Always),
+            ..Options::default()
+        };
+        let mut t = DiskBPlusTree::open_with(path, options).unwrap();
+        let start = t.range(0, Key::MAX).unwrap().len() as u64;
+        for i in start.. {
+            if t.pool.get_mut().needs_flush() {
+                println!("checkpoint");
+            }
+            let (key, value) = killed_entry(i);
+            t.insert(key, &value).unwrap();
+        }
+    }
+
+    // checkpoint の途中で kill されても、開き直すと insert を返した分までは全て読める
+    #[test]
+    fn kill_during_checkpoint() {
+        use std::{
+            io::{BufRead, BufReader},
+            process::{Command, Stdio},
+            thread,
+            time::Duration,
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        let mut count = 0;
+        for round in 0..8 {
+            let mut child = Command::new(std::env::current_exe().unwrap())
+                .args(["tree::test::insert_until_killed", "--exact", "--ignored"])
+                .arg("--nocapture")
+                .env("DISKBPLUS_KILL_PATH", &path)
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            // checkpoint を始めてから、round ごとに少しずつずらして kill する
+            BufReader::new(child.stdout.take().unwrap())
+                .lines()
+                .find(|line| line.as_ref().unwrap() == "checkpoint")
+                .unwrap()
+                .unwrap();
+            thread::sleep(Duration::from_millis(round * 2));
+            child.kill().unwrap();
+            child.wait().unwrap();
+
+            let t = DiskBPlusTree::open(&path).unwrap();
+            t.verify().unwrap();
+            let entries = t.range(0, Key::MAX).unwrap();
+            assert!(entries.len() >= count);
+            count = entries.len();
+            let mut expected: Vec<_> = (0..count as u64).map(killed_entry).collect();
+            expected.sort();
+            assert_eq!(entries, expected);
+        }
+    }
+
+    #[test]
+    fn corrupt_page() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        {
+            let mut t = DiskBPlusTree::open(&path).unwrap();
+            t.insert(1, b"one").unwrap();
+        }
+        // page 1 (root の leaf) の value を書き換える
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(2 * PAGE_SIZE as u64 - 1))
+            .unwrap();
+        file.write_all(&[0xff]).unwrap();
+        drop(file);
+        let t = DiskBPlusTree::open(&path).unwrap();
+        assert!(matches!(t.get(1), Err(Error::CorruptPage { page_id: 1 })));
+        assert!(matches!(t.verify(), Err(Error::CorruptPage { page_id: 1 })));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.db");
+        std::fs::write(&path, vec![1; PAGE_SIZE]).unwrap();
+        assert!(matches!(
+            DiskBPlusTree::open(&path),
+            Err(Error::InvalidFile(_))
+        ));
+    }
+
+    // 記録された event の message を集める
+    #[cfg(feature = "tracing")]
+    #[derive(Default, Clone)]
+    struct Messages(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for Messages {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Visitor<'a>(&'a mut Vec<String>);
+            impl tracing::field::Visit for Visitor<'_> {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    if field.name() == "message" {
+                        self.0.push(format!("{:?}", value));
+                    }
+                }
+            }
+            event.record(&mut Visitor(&mut self.0.lock().unwrap()));
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            cache_pages: 4,
+            slow_range: std::time::Duration::ZERO,
+            ..Options::default()
+        };
+        let messages = Messages::default();
+        tracing::subscriber::with_default(messages.clone(), || {
+            let mut t = DiskBPlusTree::open_with(dir.path().join("tree.db"), options).unwrap();
+            // internal page も分割されるまで入れる
+            for k in 0..20_000 {
+                t.insert(k, &value(k)).unwrap();
+            }
+            for k in 0..20_000 {
+                t.remove(k).unwrap();
+            }
+            t.range(0, 10).unwrap();
+        });
+        let messages = messages.0.lock().unwrap();
+        for expected in [
+            "leaf split",
+            "internal split",
+            "empty child removed",
+            "root shrunk",
+            "page evicted",
+            "slow range scan",
+        ] {
+            assert!(messages.iter().any(|m| m == expected), "{}", expected);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// 変更をページに反映する前に追記する redo ログ
+//
+// record
+//   len     : u32  payload の長さ
+//   crc     : u32  payload の CRC32C
+//   payload
+//     lsn   : u64
+//     kind  : u8   (1 = insert, 2 = remove)
+//     key   : u64
+//     value : insert の場合のみ。残り全部
+use std::{
+    convert::TryInto,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{Key, Result};
+
+pub type Lsn = u64;
+
+const FRAME_HEADER_SIZE: usize = 8;
+const KIND_INSERT: u8 = 1;
+const KIND_REMOVE: u8 = 2;
+
+// append した record をいつ fsync するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    // append ごとに fsync する
+    Always,
+    // 最後の fsync から指定の時間以上経っていれば、append 時に fsync する
+    Interval(Duration),
+    // fsync は OS に任せる。flush 時のみ fsync する
+    Off,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalRecord {
+    Insert { key: Key, value: Vec<u8> },
+    Remove { key: Key },
+}
+
+#[derive(Debug)]
+pub struct Wal {
+    file: File,
+    policy: FsyncPolicy,
+    last_sync: Instant,
+    next_lsn: Lsn,
+}
+
+impl Wal {
+    // 既存の record の後ろに追記する。next_lsn は次に割り当てる LSN
+    pub fn open<P: AsRef<Path>>(path: P, policy: FsyncPolicy, next_lsn: Lsn) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            policy,
+            last_sync: Instant::now(),
+            next_lsn,
+        })
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> Result<Lsn> {
+        let lsn = self.next_lsn;
+        let mut payload = Vec::with_capacity(32);
+        payload.extend_from_slice(&lsn.to_le_bytes());
+        match record {
+            WalRecord::Insert { key, value } => {
+                payload.push(KIND_INSERT);
+                payload.extend_from_slice(&key.to_le_bytes());
+                payload.extend_from_slice(value);
+            }
+            WalRecord::Remove { key } => {
+                payload.push(KIND_REMOVE);
+                payload.extend_from_slice(&key.to_le_bytes());
+            }
+        }
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32c::crc32c(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        self.file.write_all(&frame)?;
+        self.next_lsn += 1;
+        match self.policy {
+            FsyncPolicy::Always => self.sync()?,
+            FsyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => {
+                self.sync()?
+            }
+            _ => {}
+        }
+        Ok(lsn)
+    }
+
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    // ページに反映済みになった record を捨てる
+    pub fn truncate(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.sync()
+    }
+
+    // 最後に append した record の LSN
+    pub fn last_lsn(&self) -> Lsn {
+        self.next_lsn - 1
+    }
+}
+
+// 先頭から読めるところまで読む。壊れた record 以降は無視する
+pub fn read_records<P: AsRef<Path>>(path: P) -> Result<Vec<(Lsn, WalRecord)>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    let mut records = Vec::new();
+    let mut at = 0;
+    while let Some((record, len)) = decode_record(&buf[at..]) {
+        records.push(record);
+        at += len;
+    }
+    Ok(records)
+}
+
+// 読めた record と、消費したバイト数
+fn decode_record(buf: &[u8]) -> Option<((Lsn, WalRecord), usize)> {
+    let header = buf.get(0..FRAME_HEADER_SIZE)?;
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let payload = buf.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len)?;
+    if len < 17 || crc32c::crc32c(payload) != crc {
+        return None;
+    }
+    let lsn = u64::from_le_bytes(payload[0..8].try_into().unwrap());
+    let key = u64::from_le_bytes(payload[9..17].try_into().unwrap());
+    let record = match payload[8] {
+        KIND_INSERT => WalRecord::Insert {
+            key,
+            value: payload[17..].to_vec(),
+        },
+        KIND_REMOVE if len == 17 => WalRecord::Remove { key },
+        _ => return None,
+    };
+    Some(((lsn, record), FRAME_HEADER_SIZE + len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn append_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.wal");
+        let mut wal = Wal::open(&path, FsyncPolicy::Always, 1).unwrap();
+        let records = vec![
+            WalRecord::Insert {
+                key: 1,
+                value: b"one".to_vec(),
+            },
+            WalRecord::Remove { key: 1 },
+            WalRecord::Insert {
+                key: 2,
+                value: vec![],
+            },
+        ];
+        for r in &records {
+            wal.append(r).unwrap();
+        }
+        assert_eq!(wal.last_lsn(), 3);
+        let read = read_records(&path).unwrap();
+        assert_eq!(
+            read.iter().map(|(lsn, _)| *lsn).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            read.into_iter().map(|(_, r)| r).collect::<Vec<_>>(),
+            records
+        );
+
+        wal.truncate().unwrap();
+        assert_eq!(read_records(&path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn ignore_torn_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tree.wal");
+        let mut wal = Wal::open(&path, FsyncPolicy::Off, 1).unwrap();
+        wal.append(&WalRecord::Remove { key: 1 }).unwrap();
+        wal.append(&WalRecord::Remove { key: 2 }).unwrap();
+        drop(wal);
+        // 2 つ目の record の途中で切れた状態にする
+        let len = std::fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+        let read = read_records(&path).unwrap();
+        assert_eq!(read, vec![(1, WalRecord::Remove { key: 1 })]);
+    }
+}
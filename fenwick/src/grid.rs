@@ -0,0 +1,192 @@
+// 2 次元の Fenwick tree
+// 位置 (i, j) は、行の区間 (i - lowbit(i), i] と列の区間 (j - lowbit(j), j] の長方形の和を持つ
+// 1 点への加算と、左上の角からの長方形の和を O(log rows * log cols) で求める
+// 任意の長方形の和は、左上からの長方形 4 つの足し引き (包除) で求める
+use std::ops::RangeBounds;
+
+use crate::{bounds, lowbit, Group};
+
+#[derive(Debug, Clone)]
+pub struct FenwickGrid<T> {
+    // 行ごとに cols 個ずつ並べる。tree[(i - 1) * cols + (j - 1)] が位置 (i, j) の長方形の和
+    tree: Vec<T>,
+    rows: usize,
+    cols: usize,
+}
+
+impl<T: Group> FenwickGrid<T> {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            tree: vec![T::empty(); rows * cols],
+            rows,
+            cols,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    // (row, col) の要素に delta を足す
+    pub fn add(&mut self, row: usize, col: usize, delta: &T) {
+        assert!(
+            row < self.rows && col < self.cols,
+            "({}, {}) is out of {}x{}",
+            row,
+            col,
+            self.rows,
+            self.cols
+        );
+        let mut i = row + 1;
+        while i <= self.rows {
+            let mut j = col + 1;
+            while j <= self.cols {
+                let cell = &mut self.tree[(i - 1) * self.cols + (j - 1)];
+                *cell = cell.combine(delta);
+                j += lowbit(j);
+            }
+            i += lowbit(i);
+        }
+    }
+
+    // [0, row_end) x [0, col_end) の和
+    pub fn prefix(&self, row_end: usize, col_end: usize) -> T {
+        assert!(
+            row_end <= self.rows && col_end <= self.cols,
+            "({}, {}) is out of {}x{}",
+            row_end,
+            col_end,
+            self.rows,
+            self.cols
+        );
+        let mut sum = T::empty();
+        let mut i = row_end;
+        while i > 0 {
+            let mut j = col_end;
+            while j > 0 {
+                sum = sum.combine(&self.tree[(i - 1) * self.cols + (j - 1)]);
+                j -= lowbit(j);
+            }
+            i -= lowbit(i);
+        }
+        sum
+    }
+
+    // rows x cols の長方形の和
+    pub fn sum<R: RangeBounds<usize>, C: RangeBounds<usize>>(&self, rows: R, cols: C) -> T {
+        let (top, bottom) = bounds(rows, self.rows);
+        let (left, right) = bounds(cols, self.cols);
+        self.prefix(bottom, right)
+            .combine(&self.prefix(top, right).inverse())
+            .combine(&self.prefix(bottom, left).inverse())
+            .combine(&self.prefix(top, left))
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> T {
+        self.sum(row..=row, col..=col)
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: &T) {
+        let delta = value.combine(&self.get(row, col).inverse());
+        self.add(row, col, &delta);
+    }
+}
+
+impl<T: Group> From<Vec<Vec<T>>> for FenwickGrid<T> {
+    // 各行を 1 次元と同じように作ってから、行どうしを同じように親へ足す。O(rows * cols)
+    fn from(grid: Vec<Vec<T>>) -> Self {
+        let rows = grid.len();
+        let cols = grid.first().map_or(0, Vec::len);
+        assert!(
+            grid.iter().all(|row| row.len() == cols),
+            "rows have different lengths"
+        );
+        let mut tree: Vec<T> = grid.into_iter().flatten().collect();
+        for i in 0..rows {
+            for j in 1..=cols {
+                let parent = j + lowbit(j);
+                if parent <= cols {
+                    tree[i * cols + parent - 1] =
+                        tree[i * cols + parent - 1].combine(&tree[i * cols + j - 1]);
+                }
+            }
+        }
+        for i in 1..=rows {
+            let parent = i + lowbit(i);
+            if parent <= rows {
+                for j in 0..cols {
+                    tree[(parent - 1) * cols + j] =
+                        tree[(parent - 1) * cols + j].combine(&tree[(i - 1) * cols + j]);
+                }
+            }
+        }
+        Self { tree, rows, cols }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn rectangles() {
+        let mut grid = FenwickGrid::<i64>::new(4, 5);
+        grid.add(1, 2, &3);
+        grid.add(3, 4, &7);
+        grid.add(0, 0, &1);
+        assert_eq!(grid.sum(.., ..), 11);
+        assert_eq!(grid.sum(1..4, 2..5), 10);
+        assert_eq!(grid.sum(0..2, 0..3), 4);
+        assert_eq!(grid.sum(2..2, ..), 0);
+        grid.set(1, 2, &-1);
+        assert_eq!(grid.get(1, 2), -1);
+        assert_eq!(grid.prefix(4, 5), 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "different lengths")]
+    fn ragged() {
+        let _ = FenwickGrid::from(vec![vec![1, 2], vec![3]]);
+    }
+
+    proptest! {
+        // 全ての長方形の和が、2 次元の Vec で求めたものと同じになる
+        #[test]
+        fn matches_vec(
+            (model, updates) in (0..8usize, 0..8usize).prop_flat_map(|(rows, cols)| {
+                (
+                    prop::collection::vec(prop::collection::vec(-9..10i64, cols), rows),
+                    prop::collection::vec((0..rows.max(1), 0..cols.max(1), -9..10i64), 0..10),
+                )
+            })
+        ) {
+            let mut model = model;
+            let mut grid = FenwickGrid::from(model.clone());
+            let (rows, cols) = (grid.rows(), grid.cols());
+            prop_assert_eq!(rows, model.len());
+            for (r, c, delta) in updates {
+                if rows == 0 || cols == 0 {
+                    break;
+                }
+                model[r][c] += delta;
+                grid.add(r, c, &delta);
+            }
+            for top in 0..=rows {
+                for bottom in top..=rows {
+                    for left in 0..=cols {
+                        for right in left..=cols {
+                            let expected: i64 = model[top..bottom].iter().map(|row| row[left..right].iter().sum::<i64>()).sum();
+                            prop_assert_eq!(grid.sum(top..bottom, left..right), expected);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
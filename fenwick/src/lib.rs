@@ -0,0 +1,276 @@
+// Fenwick tree (Binary Indexed Tree)
+// 1 始まりの位置 i は、i の最下位ビットの長さの区間 (i - lowbit(i), i] の和を持つ
+// 1 点への加算と先頭からの和を、どちらも最下位ビットを足し引きして O(log n) で辿る
+// 区間の和は先頭からの和の差なので、要素は可換群 (逆元のあるモノイド) であればよい
+//
+// kth は、先頭から大きい区間の順に和が足りるか調べて降りる (binary lifting) ので O(log n)
+// 要素が全て 0 以上なら、先頭からの和が単調になるので使える
+//
+// grid に、長方形の和を求める 2 次元の木を置く
+use std::{
+    iter::FromIterator,
+    ops::{Bound, RangeBounds},
+};
+
+pub mod grid;
+
+// 可換群。combine は結合則と交換則を満たし、a.combine(a.inverse()) が empty になる
+pub trait Group: Clone {
+    fn empty() -> Self;
+    fn combine(&self, other: &Self) -> Self;
+    fn inverse(&self) -> Self;
+}
+
+// 整数は wrapping で足すので、符号なしの型でも 2^bits を法とする群になる
+// 途中の和があふれても、最後の和が型に収まれば正しい値になる
+macro_rules! impl_int_group {
+    ($($t:ty),*) => {
+        $(
+            impl Group for $t {
+                fn empty() -> Self {
+                    0
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    self.wrapping_add(*other)
+                }
+
+                fn inverse(&self) -> Self {
+                    self.wrapping_neg()
+                }
+            }
+        )*
+    };
+}
+
+impl_int_group!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+macro_rules! impl_float_group {
+    ($($t:ty),*) => {
+        $(
+            impl Group for $t {
+                fn empty() -> Self {
+                    0.0
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    self + other
+                }
+
+                fn inverse(&self) -> Self {
+                    -self
+                }
+            }
+        )*
+    };
+}
+
+impl_float_group!(f32, f64);
+
+// range を [start, end) にする
+pub(crate) fn bounds<R: RangeBounds<usize>>(range: R, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&s) => s,
+        Bound::Excluded(&s) => s + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&e) => e + 1,
+        Bound::Excluded(&e) => e,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end && end <= len,
+        "range {}..{} is out of 0..{}",
+        start,
+        end,
+        len
+    );
+    (start, end)
+}
+
+fn lowbit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
+
+#[derive(Debug, Clone)]
+pub struct FenwickTree<T> {
+    // tree[i - 1] が位置 i の区間の和
+    tree: Vec<T>,
+}
+
+impl<T: Group> FenwickTree<T> {
+    pub fn new(len: usize) -> Self {
+        Self {
+            tree: vec![T::empty(); len],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // index の要素に delta を足す
+    pub fn add(&mut self, index: usize, delta: &T) {
+        assert!(
+            index < self.len(),
+            "index {} is out of 0..{}",
+            index,
+            self.len()
+        );
+        let mut i = index + 1;
+        while i <= self.len() {
+            self.tree[i - 1] = self.tree[i - 1].combine(delta);
+            i += lowbit(i);
+        }
+    }
+
+    // [0, end) の和
+    pub fn prefix(&self, end: usize) -> T {
+        assert!(
+            end <= self.len(),
+            "end {} is out of 0..={}",
+            end,
+            self.len()
+        );
+        let mut sum = T::empty();
+        let mut i = end;
+        while i > 0 {
+            sum = sum.combine(&self.tree[i - 1]);
+            i -= lowbit(i);
+        }
+        sum
+    }
+
+    pub fn sum<R: RangeBounds<usize>>(&self, range: R) -> T {
+        let (start, end) = bounds(range, self.len());
+        self.prefix(end).combine(&self.prefix(start).inverse())
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.sum(index..=index)
+    }
+
+    pub fn set(&mut self, index: usize, value: &T) {
+        let delta = value.combine(&self.get(index).inverse());
+        self.add(index, &delta);
+    }
+
+    // 全ての要素を 1 つずつ求める。O(n log n)
+    pub fn to_vec(&self) -> Vec<T> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+}
+
+impl<T: Group + PartialOrd> FenwickTree<T> {
+    // [0, i] の和が k より大きくなる最小の i。全体の和が k 以下なら None
+    // 要素が全て 0 以上でなければならない。各要素を個数とみれば、k 番目 (0 始まり) の要素の位置になる
+    pub fn kth(&self, k: &T) -> Option<usize> {
+        let mut pos = 0;
+        // pos までの和
+        let mut sum = T::empty();
+        let mut step = if self.is_empty() {
+            0
+        } else {
+            1 << (usize::BITS - 1 - self.len().leading_zeros())
+        };
+        while step > 0 {
+            if pos + step <= self.len() {
+                let next = sum.combine(&self.tree[pos + step - 1]);
+                if next <= *k {
+                    pos += step;
+                    sum = next;
+                }
+            }
+            step /= 2;
+        }
+        (pos < self.len()).then_some(pos)
+    }
+}
+
+impl<T: Group> From<Vec<T>> for FenwickTree<T> {
+    // 各位置の和を、すぐ上の親に 1 回ずつ足して O(n) で作る
+    fn from(values: Vec<T>) -> Self {
+        let mut tree = values;
+        for i in 1..=tree.len() {
+            let parent = i + lowbit(i);
+            if parent <= tree.len() {
+                tree[parent - 1] = tree[parent - 1].combine(&tree[i - 1]);
+            }
+        }
+        Self { tree }
+    }
+}
+
+impl<T: Group> FromIterator<T> for FenwickTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn sums() {
+        let mut tree: FenwickTree<i64> = (1..=10).collect();
+        assert_eq!(tree.prefix(10), 55);
+        assert_eq!(tree.sum(2..5), 3 + 4 + 5);
+        assert_eq!(tree.sum(..), 55);
+        tree.add(4, &-5);
+        assert_eq!(tree.get(4), 0);
+        tree.set(0, &100);
+        assert_eq!(tree.sum(..=1), 102);
+        assert_eq!(tree.to_vec(), vec![100, 2, 3, 4, 0, 6, 7, 8, 9, 10]);
+
+        // 符号なしでも、途中で 0 を下回らなければ区間の和を求められる
+        let unsigned: FenwickTree<u32> = vec![5, 1, 7].into_iter().collect();
+        assert_eq!(unsigned.sum(1..3), 8);
+    }
+
+    #[test]
+    fn kth() {
+        // 値 i が counts[i] 個ある多重集合
+        let counts = vec![2u32, 0, 3, 1, 0, 4];
+        let tree: FenwickTree<u32> = counts.into_iter().collect();
+        let sorted: Vec<_> = (0..10).map(|k| tree.kth(&k).unwrap()).collect();
+        assert_eq!(sorted, vec![0, 0, 2, 2, 2, 3, 5, 5, 5, 5]);
+        assert_eq!(tree.kth(&10), None);
+        assert_eq!(FenwickTree::<u32>::new(0).kth(&0), None);
+    }
+
+    proptest! {
+        // 区間の和と kth が、Vec で求めたものと同じになる
+        #[test]
+        fn matches_vec(
+            values in prop::collection::vec(0..10i64, 0..50),
+            updates in prop::collection::vec((any::<prop::sample::Index>(), 0..10i64), 0..20),
+            k in 0..200i64,
+        ) {
+            let mut model = values;
+            let mut tree: FenwickTree<i64> = model.iter().copied().collect();
+            for (index, v) in updates {
+                if model.is_empty() {
+                    break;
+                }
+                let i = index.index(model.len());
+                model[i] = v;
+                tree.set(i, &v);
+            }
+            for start in 0..=model.len() {
+                for end in start..=model.len() {
+                    prop_assert_eq!(tree.sum(start..end), model[start..end].iter().sum::<i64>());
+                }
+            }
+            let expected = (0..model.len()).find(|&i| model[..=i].iter().sum::<i64>() > k);
+            prop_assert_eq!(tree.kth(&k), expected);
+        }
+    }
+}
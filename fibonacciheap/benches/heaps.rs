@@ -0,0 +1,220 @@
+// フィボナッチヒープと pairingheap、binaryheap を同じ操作で比べる
+//   cargo bench -p fibonacciheap
+// drain は全て入れてから全て取り出す
+// meld は 1 要素のヒープを 2 つずつ meld して 1 つにし、半分取り出す。leftistheap と skewheap も比べる
+// dijkstra は疎なランダムグラフの最短路で、pairingheap とフィボナッチヒープは decrease_key を使う
+// binaryheap は decrease_key がないので、距離が縮むたびに push し、古いものは取り出したときに読み飛ばす
+use binaryheap::BinaryHeap;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fibonacciheap::FibonacciHeap;
+use leftistheap::LeftistHeap;
+use pairingheap::PairingHeap;
+use priorityqueue::{MergeableHeap, Min, PriorityQueue};
+use skewheap::SkewHeap;
+
+const SIZES: [usize; 2] = [1_000, 100_000];
+// 1 頂点あたりの辺の数
+const DEGREE: usize = 8;
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn values(n: usize) -> Vec<u64> {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+    (0..n).map(|_| xorshift(&mut state)).collect()
+}
+
+// (行き先, 重み) の隣接リスト
+fn graph(n: usize) -> Vec<Vec<(usize, u64)>> {
+    let mut state = 0x2545_f491_4f6c_dd1d;
+    (0..n)
+        .map(|_| {
+            (0..DEGREE)
+                .map(|_| {
+                    let to = xorshift(&mut state) as usize % n;
+                    (to, xorshift(&mut state) % 1000 + 1)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn drain<Q: PriorityQueue<u64> + Default>(values: &[u64]) -> u64 {
+    let mut q = Q::default();
+    for &v in values {
+        q.push(v);
+    }
+    let mut sum = 0u64;
+    while let Some(v) = q.pop() {
+        sum = sum.wrapping_add(v);
+    }
+    sum
+}
+
+fn meld<Q: MergeableHeap<u64> + Default>(values: &[u64]) -> u64 {
+    let mut queue: std::collections::VecDeque<Q> = values
+        .iter()
+        .map(|&v| {
+            let mut q = Q::default();
+            q.push(v);
+            q
+        })
+        .collect();
+    while queue.len() > 1 {
+        let mut a = queue.pop_front().unwrap();
+        a.meld(queue.pop_front().unwrap());
+        queue.push_back(a);
+    }
+    let mut q = queue.pop_front().unwrap_or_default();
+    let mut sum = 0u64;
+    for _ in 0..values.len() / 2 {
+        sum = sum.wrapping_add(q.pop().unwrap());
+    }
+    sum
+}
+
+// pairingheap とフィボナッチヒープで同じ dijkstra を書くため
+trait DecreaseKey: Default {
+    type Handle;
+
+    fn push(&mut self, value: (u64, usize)) -> Self::Handle;
+    fn pop(&mut self) -> Option<(u64, usize)>;
+    fn decrease_key(&mut self, handle: &Self::Handle, value: (u64, usize));
+}
+
+impl DecreaseKey for PairingHeap<(u64, usize), Min> {
+    type Handle = pairingheap::Handle<(u64, usize)>;
+
+    fn push(&mut self, value: (u64, usize)) -> Self::Handle {
+        PairingHeap::push(self, value)
+    }
+
+    fn pop(&mut self) -> Option<(u64, usize)> {
+        PairingHeap::pop(self)
+    }
+
+    fn decrease_key(&mut self, handle: &Self::Handle, value: (u64, usize)) {
+        PairingHeap::decrease_key(self, handle, value).unwrap();
+    }
+}
+
+impl DecreaseKey for FibonacciHeap<(u64, usize), Min> {
+    type Handle = fibonacciheap::Handle<(u64, usize)>;
+
+    fn push(&mut self, value: (u64, usize)) -> Self::Handle {
+        FibonacciHeap::push(self, value)
+    }
+
+    fn pop(&mut self) -> Option<(u64, usize)> {
+        FibonacciHeap::pop(self)
+    }
+
+    fn decrease_key(&mut self, handle: &Self::Handle, value: (u64, usize)) {
+        FibonacciHeap::decrease_key(self, handle, value).unwrap();
+    }
+}
+
+fn dijkstra<Q: DecreaseKey>(graph: &[Vec<(usize, u64)>]) -> Vec<u64> {
+    let mut dist = vec![u64::MAX; graph.len()];
+    let mut handles: Vec<Option<Q::Handle>> = (0..graph.len()).map(|_| None).collect();
+    let mut heap = Q::default();
+    dist[0] = 0;
+    handles[0] = Some(heap.push((0, 0)));
+    while let Some((d, v)) = heap.pop() {
+        for &(to, cost) in &graph[v] {
+            if d + cost < dist[to] {
+                dist[to] = d + cost;
+                match &handles[to] {
+                    Some(handle) => heap.decrease_key(handle, (d + cost, to)),
+                    None => handles[to] = Some(heap.push((d + cost, to))),
+                }
+            }
+        }
+    }
+    dist
+}
+
+fn dijkstra_lazy(graph: &[Vec<(usize, u64)>]) -> Vec<u64> {
+    let mut dist = vec![u64::MAX; graph.len()];
+    let mut heap = BinaryHeap::new_min();
+    dist[0] = 0;
+    heap.push((0, 0));
+    while let Some((d, v)) = heap.pop() {
+        if d > dist[v] {
+            continue;
+        }
+        for &(to, cost) in &graph[v] {
+            if d + cost < dist[to] {
+                dist[to] = d + cost;
+                heap.push((d + cost, to));
+            }
+        }
+    }
+    dist
+}
+
+fn heaps(c: &mut Criterion) {
+    for n in SIZES {
+        let values = values(n);
+        c.bench_with_input(
+            BenchmarkId::new("drain/fibonacci", n),
+            &values,
+            |b, values| b.iter(|| drain::<FibonacciHeap<u64>>(values)),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("drain/pairing", n),
+            &values,
+            |b, values| b.iter(|| drain::<PairingHeap<u64>>(values)),
+        );
+        c.bench_with_input(BenchmarkId::new("drain/binary", n), &values, |b, values| {
+            b.iter(|| drain::<BinaryHeap<u64>>(values))
+        });
+
+        c.bench_with_input(
+            BenchmarkId::new("meld/fibonacci", n),
+            &values,
+            |b, values| b.iter(|| meld::<FibonacciHeap<u64>>(values)),
+        );
+        c.bench_with_input(BenchmarkId::new("meld/pairing", n), &values, |b, values| {
+            b.iter(|| meld::<PairingHeap<u64>>(values))
+        });
+        c.bench_with_input(BenchmarkId::new("meld/leftist", n), &values, |b, values| {
+            b.iter(|| meld::<LeftistHeap<u64>>(values))
+        });
+        c.bench_with_input(BenchmarkId::new("meld/skew", n), &values, |b, values| {
+            b.iter(|| meld::<SkewHeap<u64>>(values))
+        });
+
+        let graph = graph(n);
+        assert_eq!(
+            dijkstra::<FibonacciHeap<_, Min>>(&graph),
+            dijkstra_lazy(&graph)
+        );
+        assert_eq!(
+            dijkstra::<PairingHeap<_, Min>>(&graph),
+            dijkstra_lazy(&graph)
+        );
+        c.bench_with_input(
+            BenchmarkId::new("dijkstra/fibonacci", n),
+            &graph,
+            |b, graph| b.iter(|| dijkstra::<FibonacciHeap<_, Min>>(graph)),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("dijkstra/pairing", n),
+            &graph,
+            |b, graph| b.iter(|| dijkstra::<PairingHeap<_, Min>>(graph)),
+        );
+        c.bench_with_input(
+            BenchmarkId::new("dijkstra/binary", n),
+            &graph,
+            |b, graph| b.iter(|| dijkstra_lazy(graph)),
+        );
+    }
+}
+
+criterion_group!(benches, heaps);
+criterion_main!(benches);
@@ -0,0 +1,630 @@
+// フィボナッチヒープ
+// 根のリストと、各ノードの子のリストを、どちらも双方向の循環リストで持つ。min は根のうち次に取り出すもの
+// push は根のリストに足し、meld は根のリスト同士を繋ぐだけなので、どちらも O(1)
+// pop は min の子を根のリストに移し、同じ次数 (子の数) の根を繋いで次数ごとに 1 つにする (consolidate)。償却 O(log n)
+// decrease_key は親より先に取り出すようになったら親から切り離して根にする
+// 子を 2 つ失った親も切り離していく (cascading cut) ので、次数 d の部分木は F(d + 2) 個以上のノードを持つ
+// これで次数は O(log n) に収まり、decrease_key は償却 O(1) になる
+//
+// ノードの確保と Handle の確かめ方は pairingheap と同じ
+//   - ノードと Handle は Slot を共有し、ノードを解放するときに Slot の指す先を消す
+//   - ヒープごとに Owner を持ち、meld で取り込まれたヒープの Owner は取り込んだ側を指す (union-find)
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    iter::FromIterator,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+mod error;
+
+pub use error::{Error, Result};
+pub use priorityqueue::{Compare, Max, MergeableHeap, Min, PriorityQueue};
+
+type Ptr<T> = NonNull<Node<T>>;
+type Link<T> = Option<Ptr<T>>;
+
+struct Node<T> {
+    value: T,
+    parent: Link<T>,
+    // 子のリストのどれか 1 つ
+    child: Link<T>,
+    // 同じリストの左右。1 つだけなら自分を指す
+    left: Ptr<T>,
+    right: Ptr<T>,
+    degree: usize,
+    // 根でなくなってから子を失ったか
+    mark: bool,
+    slot: Rc<Slot<T>>,
+}
+
+// ノードと Handle で共有する。ノードを解放すると node を None にする
+struct Slot<T> {
+    node: Cell<Link<T>>,
+    owner: RefCell<Rc<Owner>>,
+}
+
+// ヒープごとの印。meld で取り込まれたヒープの印は、取り込んだヒープの印を指す
+#[derive(Default)]
+struct Owner {
+    merged: RefCell<Option<Rc<Owner>>>,
+}
+
+// merged を辿った先の、今も使われているヒープの印。辿った印は全て、そこを直接指すように繋ぎ直す
+fn resolve(start: &Rc<Owner>) -> Rc<Owner> {
+    let mut root = start.clone();
+    loop {
+        let next = root.merged.borrow().clone();
+        match next {
+            Some(next) => root = next,
+            None => break,
+        }
+    }
+    let mut current = start.clone();
+    while !Rc::ptr_eq(&current, &root) {
+        let next = current.merged.replace(Some(root.clone()));
+        current = next.expect("merged owner points to another owner");
+    }
+    root
+}
+
+// 以下の関数の ptr は全て、&mut self を借用している間のヒープのノード
+
+// a のリストと b のリストを 1 つの循環リストに繋ぐ
+unsafe fn splice<T>(a: Ptr<T>, b: Ptr<T>) {
+    let a_right = (*a.as_ptr()).right;
+    let b_left = (*b.as_ptr()).left;
+    (*a.as_ptr()).right = b;
+    (*b.as_ptr()).left = a;
+    (*b_left.as_ptr()).right = a_right;
+    (*a_right.as_ptr()).left = b_left;
+}
+
+// リストから外し、自分だけのリストにする
+unsafe fn unlink<T>(node: Ptr<T>) {
+    let left = (*node.as_ptr()).left;
+    let right = (*node.as_ptr()).right;
+    (*left.as_ptr()).right = right;
+    (*right.as_ptr()).left = left;
+    (*node.as_ptr()).left = node;
+    (*node.as_ptr()).right = node;
+}
+
+// first から右に辿ったリストのノード
+unsafe fn siblings<T>(first: Ptr<T>) -> Vec<Ptr<T>> {
+    let mut nodes = vec![first];
+    let mut node = (*first.as_ptr()).right;
+    while node != first {
+        nodes.push(node);
+        node = (*node.as_ptr()).right;
+    }
+    nodes
+}
+
+// push で得る、ヒープのノードの目印。clone しても同じノードを指す
+pub struct Handle<T> {
+    slot: Rc<Slot<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("live", &self.slot.node.get().is_some())
+            .finish()
+    }
+}
+
+pub struct FibonacciHeap<T, C = Max> {
+    min: Link<T>,
+    len: usize,
+    owner: Rc<Owner>,
+    cmp: C,
+}
+
+impl<T: Ord> FibonacciHeap<T> {
+    // 大きいものから取り出す
+    pub fn new() -> Self {
+        Self::with_comparator(Max)
+    }
+}
+
+impl<T: Ord> FibonacciHeap<T, Min> {
+    // 小さいものから取り出す
+    pub fn new_min() -> Self {
+        Self::with_comparator(Min)
+    }
+}
+
+impl<T, C> FibonacciHeap<T, C> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 次に取り出す要素
+    pub fn peek(&self) -> Option<&T> {
+        // SAFETY: min はこのヒープのノードで、&self を借用している間は変更も解放もされない
+        self.min.map(|min| unsafe { &(*min.as_ptr()).value })
+    }
+
+    // 全てのノード。親は子より前に並ぶ
+    fn nodes(&self) -> Vec<Ptr<T>> {
+        let mut nodes = Vec::with_capacity(self.len);
+        let mut lists: Vec<_> = self.min.into_iter().collect();
+        while let Some(first) = lists.pop() {
+            // SAFETY: 木のリストは全てこのヒープのノードで、&self を借用している間は変更も解放もされない
+            for node in unsafe { siblings(first) } {
+                nodes.push(node);
+                lists.extend(unsafe { (*node.as_ptr()).child });
+            }
+        }
+        nodes
+    }
+
+    // 全て解放する。残っている Handle は使えなくなる
+    pub fn clear(&mut self) {
+        for node in self.nodes() {
+            // SAFETY: nodes はノードを 1 度ずつ返すので、それぞれここで 1 度だけ解放する
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            node.slot.node.set(None);
+        }
+        self.min = None;
+        self.len = 0;
+    }
+
+    // ヒープの並びのまま返す。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        // SAFETY: &self を借用している間は、ノードは変更も解放もされない
+        self.nodes()
+            .into_iter()
+            .map(|node| unsafe { &(*node.as_ptr()).value })
+    }
+
+    // handle がこのヒープのノードを指していれば、そのノード
+    fn node(&self, handle: &Handle<T>) -> Link<T> {
+        let node = handle.slot.node.get()?;
+        let owner = resolve(&handle.slot.owner.borrow());
+        let mine = Rc::ptr_eq(&owner, &self.owner);
+        *handle.slot.owner.borrow_mut() = owner;
+        if mine {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    // handle の指す要素。取り出し済みか、別のヒープの handle なら None
+    pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        // SAFETY: node はこのヒープの木にあり、&self を借用している間は変更も解放もされない
+        self.node(handle)
+            .map(|node| unsafe { &(*node.as_ptr()).value })
+    }
+
+    pub fn contains(&self, handle: &Handle<T>) -> bool {
+        self.node(handle).is_some()
+    }
+}
+
+impl<T, C: Compare<T>> FibonacciHeap<T, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            min: None,
+            len: 0,
+            owner: Rc::new(Owner::default()),
+            cmp,
+        }
+    }
+
+    pub fn push(&mut self, value: T) -> Handle<T> {
+        let slot = Rc::new(Slot {
+            node: Cell::new(None),
+            owner: RefCell::new(self.owner.clone()),
+        });
+        let node = Box::leak(Box::new(Node {
+            value,
+            parent: None,
+            child: None,
+            left: NonNull::dangling(),
+            right: NonNull::dangling(),
+            degree: 0,
+            mark: false,
+            slot: slot.clone(),
+        }));
+        let node = NonNull::from(node);
+        // SAFETY: node は今確保したもので、まだどこからも指されていない
+        unsafe {
+            (*node.as_ptr()).left = node;
+            (*node.as_ptr()).right = node;
+        }
+        slot.node.set(Some(node));
+        self.add_root(node);
+        self.len += 1;
+        Handle { slot }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let min = self.min.take()?;
+        // SAFETY: min はこのヒープの根で、子を根のリストに移してから、リストから外して解放する
+        let node = unsafe {
+            if let Some(child) = (*min.as_ptr()).child.take() {
+                for c in siblings(child) {
+                    (*c.as_ptr()).parent = None;
+                    (*c.as_ptr()).mark = false;
+                }
+                splice(min, child);
+            }
+            let right = (*min.as_ptr()).right;
+            unlink(min);
+            if right != min {
+                self.consolidate(right);
+            }
+            Box::from_raw(min.as_ptr())
+        };
+        node.slot.node.set(None);
+        self.len -= 1;
+        Some(node.value)
+    }
+
+    // handle の指す要素を value にする
+    // value は今の値より先に取り出すもの (compare で Greater か Equal) でなければならず、Min なら値を小さくする
+    pub fn decrease_key(&mut self, handle: &Handle<T>, value: T) -> Result<()> {
+        let node = self.node(handle).ok_or(Error::InvalidHandle)?;
+        // SAFETY: node とその周りのノードはこのヒープの木にあり、&mut self を借用しているので他から参照されていない
+        unsafe {
+            if self.cmp.compare(&value, &(*node.as_ptr()).value) == Ordering::Less {
+                return Err(Error::WorseValue);
+            }
+            (*node.as_ptr()).value = value;
+            if let Some(parent) = (*node.as_ptr()).parent {
+                if self.before(node, parent) {
+                    self.cut(node, parent);
+                    self.cascading_cut(parent);
+                }
+            }
+        }
+        let min = self.min.expect("heap with a node has a min");
+        if self.before(node, min) {
+            self.min = Some(node);
+        }
+        Ok(())
+    }
+
+    // other の要素を全て取り込む。O(1)
+    // other の Handle は、このヒープでそのまま使える
+    pub fn meld(&mut self, mut other: Self) {
+        *other.owner.merged.borrow_mut() = Some(self.owner.clone());
+        if let Some(other_min) = other.min.take() {
+            self.add_root(other_min);
+        }
+        self.len += std::mem::take(&mut other.len);
+    }
+
+    fn before(&self, a: Ptr<T>, b: Ptr<T>) -> bool {
+        // SAFETY: a と b はこのヒープのノード
+        unsafe { self.cmp.compare(&(*a.as_ptr()).value, &(*b.as_ptr()).value) == Ordering::Greater }
+    }
+
+    // node のリストを根のリストに繋ぎ、min を選び直す。node のリストは node より先に取り出すものを持たない
+    fn add_root(&mut self, node: Ptr<T>) {
+        match self.min {
+            Some(min) => {
+                // SAFETY: どちらもこのヒープの根のリスト
+                unsafe { splice(min, node) };
+                if self.before(node, min) {
+                    self.min = Some(node);
+                }
+            }
+            None => self.min = Some(node),
+        }
+    }
+
+    // first のリストの根を、次数が全て異なるまで繋ぎ、min を選び直す
+    unsafe fn consolidate(&mut self, first: Ptr<T>) {
+        let mut by_degree: Vec<Link<T>> = Vec::new();
+        for mut root in siblings(first) {
+            unlink(root);
+            let mut degree = (*root.as_ptr()).degree;
+            loop {
+                if by_degree.len() <= degree {
+                    by_degree.resize(degree + 1, None);
+                }
+                match by_degree[degree].take() {
+                    Some(other) => {
+                        root = self.link(root, other);
+                        degree += 1;
+                    }
+                    None => {
+                        by_degree[degree] = Some(root);
+                        break;
+                    }
+                }
+            }
+        }
+        for root in by_degree.into_iter().flatten() {
+            self.add_root(root);
+        }
+    }
+
+    // 同じ次数の根を、後に取り出す方を子にして繋ぐ。同じなら a を親にする
+    unsafe fn link(&self, a: Ptr<T>, b: Ptr<T>) -> Ptr<T> {
+        let (parent, child) = if self.before(b, a) { (b, a) } else { (a, b) };
+        (*child.as_ptr()).parent = Some(parent);
+        (*child.as_ptr()).mark = false;
+        match (*parent.as_ptr()).child {
+            Some(first) => splice(first, child),
+            None => (*parent.as_ptr()).child = Some(child),
+        }
+        (*parent.as_ptr()).degree += 1;
+        parent
+    }
+
+    // node を parent の子から外して根にする
+    unsafe fn cut(&mut self, node: Ptr<T>, parent: Ptr<T>) {
+        if (*parent.as_ptr()).child == Some(node) {
+            let right = (*node.as_ptr()).right;
+            (*parent.as_ptr()).child = if right == node { None } else { Some(right) };
+        }
+        unlink(node);
+        (*parent.as_ptr()).degree -= 1;
+        (*node.as_ptr()).parent = None;
+        (*node.as_ptr()).mark = false;
+        let min = self.min.expect("heap with a node has a min");
+        splice(min, node);
+    }
+
+    // 子を 2 つ失ったノードを、根に着くか印のないノードに着くまで切り離していく
+    unsafe fn cascading_cut(&mut self, mut node: Ptr<T>) {
+        while let Some(parent) = (*node.as_ptr()).parent {
+            if !(*node.as_ptr()).mark {
+                (*node.as_ptr()).mark = true;
+                return;
+            }
+            self.cut(node, parent);
+            node = parent;
+        }
+    }
+
+    // リストの左右が合うか、親が子より後に取り出すものになっていないか、degree が子の数と合うか、
+    // 次数 d の部分木が F(d + 2) 個以上のノードを持つか、Slot がノードとこのヒープを指しているか確かめる
+    pub fn check_invariants(&self) {
+        let nodes = self.nodes();
+        assert_eq!(nodes.len(), self.len, "len is stale");
+        let mut sizes = HashMap::new();
+        // 子から先に見るので、部分木の大きさを足し上げられる
+        for &node in nodes.iter().rev() {
+            // SAFETY: 木のノードは全てこのヒープのもので、&self を借用している間は変更も解放もされない
+            let n = unsafe { &*node.as_ptr() };
+            assert_eq!(n.slot.node.get(), Some(node), "slot is stale");
+            assert!(
+                Rc::ptr_eq(&resolve(&n.slot.owner.borrow()), &self.owner),
+                "node belongs to another heap"
+            );
+            // SAFETY: 同上
+            unsafe {
+                assert_eq!((*n.right.as_ptr()).left, node, "list is broken");
+                assert_eq!((*n.left.as_ptr()).right, node, "list is broken");
+            }
+            let children = n.child.map_or_else(Vec::new, |c| unsafe { siblings(c) });
+            assert_eq!(children.len(), n.degree, "degree is stale");
+            let mut size = 1;
+            for &c in &children {
+                // SAFETY: 同上
+                assert_eq!(
+                    unsafe { (*c.as_ptr()).parent },
+                    Some(node),
+                    "parent is stale"
+                );
+                assert!(!self.before(c, node), "child is before its parent");
+                size += sizes[&c];
+            }
+            let (mut a, mut b) = (1usize, 1usize);
+            for _ in 0..n.degree {
+                let next = a + b;
+                a = b;
+                b = next;
+            }
+            // b は F(degree + 2)
+            assert!(size >= b, "subtree is too small for its degree");
+            sizes.insert(node, size);
+            if n.parent.is_none() {
+                let min = self.min.expect("heap with a node has a min");
+                assert!(!self.before(node, min), "min is stale");
+            }
+        }
+    }
+}
+
+impl<T, C> Drop for FibonacciHeap<T, C> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for FibonacciHeap<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for FibonacciHeap<T, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T, C: Compare<T> + Default> FromIterator<T> for FibonacciHeap<T, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut heap = Self::default();
+        heap.extend(iter);
+        heap
+    }
+}
+
+impl<T, C: Compare<T>> Extend<T> for FibonacciHeap<T, C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, C: Compare<T>> PriorityQueue<T> for FibonacciHeap<T, C> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, value: T) {
+        FibonacciHeap::push(self, value);
+    }
+
+    fn peek(&self) -> Option<&T> {
+        FibonacciHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        FibonacciHeap::pop(self)
+    }
+}
+
+impl<T, C: Compare<T>> MergeableHeap<T> for FibonacciHeap<T, C> {
+    fn meld(&mut self, other: Self) {
+        FibonacciHeap::meld(self, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn handles() {
+        let mut heap = FibonacciHeap::new_min();
+        let handles: Vec<_> = (0..20).map(|v| heap.push(v * 10)).collect();
+        // pop で consolidate し、木を作ってから切り離す
+        assert_eq!(heap.pop(), Some(0));
+        heap.check_invariants();
+        for (i, handle) in handles.iter().enumerate().skip(10).step_by(3) {
+            assert_eq!(heap.decrease_key(handle, i as i32 - 100), Ok(()));
+            heap.check_invariants();
+        }
+        assert_eq!(heap.decrease_key(&handles[1], 50), Err(Error::WorseValue));
+        assert_eq!(
+            heap.decrease_key(&handles[0], -1),
+            Err(Error::InvalidHandle)
+        );
+        assert_eq!(heap.peek(), Some(&-90));
+        assert_eq!(heap.get(&handles[13]), Some(&-87));
+
+        let mut other: FibonacciHeap<_, Min> = vec![5, 15].into_iter().collect();
+        let extra = other.push(25);
+        assert!(!heap.contains(&extra));
+        heap.meld(other);
+        assert_eq!(heap.decrease_key(&extra, -200), Ok(()));
+        heap.check_invariants();
+        assert_eq!(heap.len(), 22);
+        let order: Vec<_> = std::iter::from_fn(|| heap.pop()).take(4).collect();
+        assert_eq!(order, vec![-200, -90, -87, -84]);
+        heap.check_invariants();
+        heap.clear();
+        assert!(heap.is_empty());
+        assert!(!heap.contains(&handles[5]));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(i32),
+        Pop,
+        // (handle の位置, 値を減らす量)。負なら WorseValue になる
+        Decrease(usize, i32),
+        Meld(Vec<i32>),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (-50..50i32).prop_map(Op::Push),
+            2 => Just(Op::Pop),
+            3 => (any::<usize>(), -3..30i32).prop_map(|(i, d)| Op::Decrease(i, d)),
+            1 => prop::collection::vec(-50..50i32, 0..10).prop_map(Op::Meld),
+        ]
+    }
+
+    proptest! {
+        // 値と通し番号の組を BTreeSet に入れたものと比べる
+        #[test]
+        fn matches_set(ops in prop::collection::vec(op(), 0..200)) {
+            let mut heap = FibonacciHeap::new_min();
+            let mut expected = BTreeSet::new();
+            let mut handles = Vec::new();
+            let mut values = Vec::new();
+            for op in ops {
+                match op {
+                    Op::Push(v) => {
+                        let id = handles.len();
+                        handles.push(heap.push((v, id)));
+                        values.push(Some(v));
+                        expected.insert((v, id));
+                    }
+                    Op::Pop => {
+                        let top = expected.iter().next().copied();
+                        if let Some(top) = top {
+                            expected.remove(&top);
+                            values[top.1] = None;
+                        }
+                        prop_assert_eq!(heap.pop(), top);
+                    }
+                    Op::Decrease(i, d) => {
+                        if handles.is_empty() {
+                            continue;
+                        }
+                        let id = i % handles.len();
+                        let result = heap.decrease_key(&handles[id], (values[id].unwrap_or(0) - d, id));
+                        match values[id] {
+                            None => prop_assert_eq!(result, Err(Error::InvalidHandle)),
+                            Some(_) if d < 0 => prop_assert_eq!(result, Err(Error::WorseValue)),
+                            Some(v) => {
+                                prop_assert_eq!(result, Ok(()));
+                                expected.remove(&(v, id));
+                                expected.insert((v - d, id));
+                                values[id] = Some(v - d);
+                            }
+                        }
+                    }
+                    Op::Meld(vs) => {
+                        let mut other = FibonacciHeap::new_min();
+                        for v in vs {
+                            let id = handles.len();
+                            handles.push(other.push((v, id)));
+                            values.push(Some(v));
+                            expected.insert((v, id));
+                        }
+                        heap.meld(other);
+                    }
+                }
+                heap.check_invariants();
+                prop_assert_eq!(heap.peek(), expected.iter().next());
+                prop_assert_eq!(heap.len(), expected.len());
+            }
+            for (id, handle) in handles.iter().enumerate() {
+                prop_assert_eq!(heap.get(handle).map(|&(v, _)| v), values[id]);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+// 区間の map。区間を始点の順に並べ、measure に 最後の始点 と 終点の最大値 を持つ
+//   insert: 始点で split して間に入れる。O(log n)
+//   overlapping: 終点の最大値が範囲の始めより小さい部分木を飛ばし、始点が範囲の終わりを超えたら止める
+//                重なる区間が k 個なら O(k log n)
+use crate::{FingerTree, Measured, Monoid};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bounds<K> {
+    // 一番後ろの区間の始点。始点の順に並べているので最大の始点でもある
+    pub last_low: Option<K>,
+    pub max_high: Option<K>,
+}
+
+impl<K: Ord + Clone> Monoid for Bounds<K> {
+    fn empty() -> Self {
+        Bounds {
+            last_low: None,
+            max_high: None,
+        }
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Bounds {
+            last_low: other.last_low.clone().or_else(|| self.last_low.clone()),
+            max_high: self.max_high.clone().max(other.max_high.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Interval<K, V> {
+    low: K,
+    high: K,
+    value: V,
+}
+
+impl<K: Ord + Clone, V> Measured for Interval<K, V> {
+    type Measure = Bounds<K>;
+
+    fn measure(&self) -> Bounds<K> {
+        Bounds {
+            last_low: Some(self.low.clone()),
+            max_high: Some(self.high.clone()),
+        }
+    }
+}
+
+pub struct IntervalMap<K: Ord + Clone, V> {
+    tree: FingerTree<Interval<K, V>>,
+}
+
+impl<K: Ord + Clone, V> IntervalMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            tree: FingerTree::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    // 閉区間 [low, high] に value を対応させる。同じ始点の区間は入れた順に並ぶ
+    #[must_use]
+    pub fn insert(&self, low: K, high: K, value: V) -> Self {
+        assert!(low <= high, "interval is empty");
+        let (left, right) = self.tree.split(|b| b.last_low.as_ref() > Some(&low));
+        Self {
+            tree: left.push_back(Interval { low, high, value }).concat(&right),
+        }
+    }
+
+    // [low, high] と重なる区間を始点の順に返す
+    pub fn overlapping<'a>(
+        &'a self,
+        low: &'a K,
+        high: &'a K,
+    ) -> impl Iterator<Item = (&'a K, &'a K, &'a V)> + 'a {
+        self.tree
+            .iter_matching(move |b| b.max_high.as_ref() >= Some(low))
+            .take_while(move |i| i.low <= *high)
+            .map(|i| (&i.low, &i.high, &i.value))
+    }
+
+    // 始点の順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &K, &V)> + '_ {
+        self.tree.iter().map(|i| (&i.low, &i.high, &i.value))
+    }
+}
+
+impl<K: Ord + Clone, V> Clone for IntervalMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Clone, V> Default for IntervalMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn overlapping() {
+        let m = IntervalMap::new()
+            .insert(5, 10, "a")
+            .insert(1, 3, "b")
+            .insert(8, 20, "c")
+            .insert(15, 16, "d");
+        let found: Vec<_> = m.overlapping(&9, &15).map(|(_, _, v)| *v).collect();
+        assert_eq!(found, vec!["a", "c", "d"]);
+        let found: Vec<_> = m.overlapping(&4, &4).collect();
+        assert!(found.is_empty());
+        let lows: Vec<_> = m.iter().map(|(l, _, _)| *l).collect();
+        assert_eq!(lows, vec![1, 5, 8, 15]);
+    }
+
+    proptest! {
+        // 全ての区間を調べた結果と同じ区間を、始点の順に返す
+        #[test]
+        fn same_as_scan_random(
+            intervals in prop::collection::vec((0..100u32, 0..20u32), 0..100),
+            queries in prop::collection::vec((0..120u32, 0..20u32), 1..20),
+        ) {
+            let m = intervals
+                .iter()
+                .enumerate()
+                .fold(IntervalMap::new(), |m, (i, &(low, len))| m.insert(low, low + len, i));
+            m.tree.check_invariants();
+            let mut sorted: Vec<_> = intervals
+                .iter()
+                .enumerate()
+                .map(|(i, &(low, len))| (low, low + len, i))
+                .collect();
+            sorted.sort_by_key(|&(low, _, i)| (low, i));
+            for (low, len) in queries {
+                let high = low + len;
+                let actual: Vec<_> = m.overlapping(&low, &high).map(|(l, h, v)| (*l, *h, *v)).collect();
+                let expected: Vec<_> = sorted
+                    .iter()
+                    .copied()
+                    .filter(|&(l, h, _)| l <= high && h >= low)
+                    .collect();
+                prop_assert_eq!(actual, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,850 @@
+// 2-3 finger tree (Hinze and Paterson, 2006)
+// 両端に 1 から 4 個の要素 (digit) を持ち、その間に 2-3 ノードを要素にした finger tree を持つ
+// 両端への追加と削除は償却 O(1)、concat と split は O(log n)
+//
+// 各部分木は要素の measure をモノイドで畳み込んだ値を持つ
+// split(pred) は先頭からの畳み込みで pred が初めて真になる要素の前で分ける
+// measure の選び方で、添字で引ける列 (seq)、優先度付きキュー (pqueue)、区間の map (interval) になる
+//
+// 中の木の要素は 1 段深い 2-3 ノードだが、型で段を表すと再帰が終わらないので、ノードの深さは実行時に持つ
+// ノードは Arc で持ち、更新は新しい木を返す
+use std::{
+    fmt,
+    iter::{FromIterator, FusedIterator},
+    sync::Arc,
+};
+
+pub mod interval;
+pub mod pqueue;
+pub mod seq;
+
+// 結合則を満たす combine と、その単位元 empty
+pub trait Monoid: Clone {
+    fn empty() -> Self;
+    fn combine(&self, other: &Self) -> Self;
+}
+
+// 要素から measure を求める
+pub trait Measured {
+    type Measure: Monoid;
+
+    fn measure(&self) -> Self::Measure;
+}
+
+type Link<T> = Arc<Node<T>>;
+
+enum Node<T: Measured> {
+    Leaf(T),
+    // 2 つか 3 つの子を持つ
+    Branch(T::Measure, Vec<Link<T>>),
+}
+
+impl<T: Measured> Node<T> {
+    fn measure(&self) -> T::Measure {
+        match self {
+            Node::Leaf(value) => value.measure(),
+            Node::Branch(measure, _) => measure.clone(),
+        }
+    }
+
+    fn children(&self) -> &[Link<T>] {
+        match self {
+            Node::Branch(_, children) => children,
+            Node::Leaf(_) => unreachable!("leaves are only at the top level"),
+        }
+    }
+
+    fn leaf(&self) -> &T {
+        match self {
+            Node::Leaf(value) => value,
+            Node::Branch(..) => unreachable!("branches are only in the middle"),
+        }
+    }
+}
+
+fn branch<T: Measured>(children: Vec<Link<T>>) -> Link<T> {
+    Arc::new(Node::Branch(measure_digit(&children), children))
+}
+
+fn measure_digit<T: Measured>(digit: &[Link<T>]) -> T::Measure {
+    digit
+        .iter()
+        .fold(T::Measure::empty(), |acc, n| acc.combine(&n.measure()))
+}
+
+enum Tree<T: Measured> {
+    Empty,
+    Single(Link<T>),
+    Deep {
+        measure: T::Measure,
+        // 1 から 4 個
+        prefix: Vec<Link<T>>,
+        middle: Arc<Tree<T>>,
+        suffix: Vec<Link<T>>,
+    },
+}
+
+impl<T: Measured> Tree<T> {
+    fn measure(&self) -> T::Measure {
+        match self {
+            Tree::Empty => T::Measure::empty(),
+            Tree::Single(node) => node.measure(),
+            Tree::Deep { measure, .. } => measure.clone(),
+        }
+    }
+}
+
+fn deep<T: Measured>(prefix: Vec<Link<T>>, middle: Arc<Tree<T>>, suffix: Vec<Link<T>>) -> Tree<T> {
+    let measure = measure_digit(&prefix)
+        .combine(&middle.measure())
+        .combine(&measure_digit(&suffix));
+    Tree::Deep {
+        measure,
+        prefix,
+        middle,
+        suffix,
+    }
+}
+
+fn push_front<T: Measured>(tree: &Tree<T>, node: Link<T>) -> Tree<T> {
+    match tree {
+        Tree::Empty => Tree::Single(node),
+        Tree::Single(other) => deep(vec![node], Arc::new(Tree::Empty), vec![Arc::clone(other)]),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => {
+            if prefix.len() == 4 {
+                // 後ろの 3 つを 1 つのノードにして中の木へ送る
+                let middle = push_front(middle, branch(prefix[1..].to_vec()));
+                let prefix = vec![node, Arc::clone(&prefix[0])];
+                deep(prefix, Arc::new(middle), suffix.clone())
+            } else {
+                let prefix = std::iter::once(node)
+                    .chain(prefix.iter().cloned())
+                    .collect();
+                deep(prefix, Arc::clone(middle), suffix.clone())
+            }
+        }
+    }
+}
+
+fn push_back<T: Measured>(tree: &Tree<T>, node: Link<T>) -> Tree<T> {
+    match tree {
+        Tree::Empty => Tree::Single(node),
+        Tree::Single(other) => deep(vec![Arc::clone(other)], Arc::new(Tree::Empty), vec![node]),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => {
+            if suffix.len() == 4 {
+                let middle = push_back(middle, branch(suffix[..3].to_vec()));
+                let suffix = vec![Arc::clone(&suffix[3]), node];
+                deep(prefix.clone(), Arc::new(middle), suffix)
+            } else {
+                let mut suffix = suffix.clone();
+                suffix.push(node);
+                deep(prefix.clone(), Arc::clone(middle), suffix)
+            }
+        }
+    }
+}
+
+fn from_digit<T: Measured>(digit: &[Link<T>]) -> Tree<T> {
+    digit
+        .iter()
+        .fold(Tree::Empty, |tree, n| push_back(&tree, Arc::clone(n)))
+}
+
+// 先頭を除いた木
+fn pop_front<T: Measured>(tree: &Tree<T>) -> Option<(Link<T>, Tree<T>)> {
+    match tree {
+        Tree::Empty => None,
+        Tree::Single(node) => Some((Arc::clone(node), Tree::Empty)),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => Some((
+            Arc::clone(&prefix[0]),
+            deep_front(prefix[1..].to_vec(), middle, suffix.clone()),
+        )),
+    }
+}
+
+fn pop_back<T: Measured>(tree: &Tree<T>) -> Option<(Link<T>, Tree<T>)> {
+    match tree {
+        Tree::Empty => None,
+        Tree::Single(node) => Some((Arc::clone(node), Tree::Empty)),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => {
+            let (last, init) = suffix.split_last().expect("digit is not empty");
+            Some((
+                Arc::clone(last),
+                deep_back(prefix.clone(), middle, init.to_vec()),
+            ))
+        }
+    }
+}
+
+// prefix が空なら、中の木の先頭のノードを開いて prefix にする
+fn deep_front<T: Measured>(
+    prefix: Vec<Link<T>>,
+    middle: &Arc<Tree<T>>,
+    suffix: Vec<Link<T>>,
+) -> Tree<T> {
+    if !prefix.is_empty() {
+        return deep(prefix, Arc::clone(middle), suffix);
+    }
+    match pop_front(middle) {
+        None => from_digit(&suffix),
+        Some((node, middle)) => deep(node.children().to_vec(), Arc::new(middle), suffix),
+    }
+}
+
+fn deep_back<T: Measured>(
+    prefix: Vec<Link<T>>,
+    middle: &Arc<Tree<T>>,
+    suffix: Vec<Link<T>>,
+) -> Tree<T> {
+    if !suffix.is_empty() {
+        return deep(prefix, Arc::clone(middle), suffix);
+    }
+    match pop_back(middle) {
+        None => from_digit(&prefix),
+        Some((node, middle)) => deep(prefix, Arc::new(middle), node.children().to_vec()),
+    }
+}
+
+// 2 から 12 個のノードを 2-3 ノードにまとめる
+fn nodes<T: Measured>(mut links: &[Link<T>]) -> Vec<Link<T>> {
+    let mut out = Vec::new();
+    loop {
+        match links.len() {
+            2 | 3 => {
+                out.push(branch(links.to_vec()));
+                return out;
+            }
+            4 => {
+                out.push(branch(links[..2].to_vec()));
+                out.push(branch(links[2..].to_vec()));
+                return out;
+            }
+            _ => {
+                out.push(branch(links[..3].to_vec()));
+                links = &links[3..];
+            }
+        }
+    }
+}
+
+// left、links、right の順に繋ぐ
+fn append<T: Measured>(left: &Tree<T>, links: Vec<Link<T>>, right: &Tree<T>) -> Tree<T> {
+    match (left, right) {
+        (Tree::Empty, _) => links
+            .into_iter()
+            .rev()
+            .fold(clone_tree(right), |tree, n| push_front(&tree, n)),
+        (_, Tree::Empty) => links
+            .into_iter()
+            .fold(clone_tree(left), |tree, n| push_back(&tree, n)),
+        (Tree::Single(node), _) => {
+            push_front(&append(&Tree::Empty, links, right), Arc::clone(node))
+        }
+        (_, Tree::Single(node)) => push_back(&append(left, links, &Tree::Empty), Arc::clone(node)),
+        (
+            Tree::Deep {
+                prefix,
+                middle: left_middle,
+                suffix: left_suffix,
+                ..
+            },
+            Tree::Deep {
+                prefix: right_prefix,
+                middle: right_middle,
+                suffix,
+                ..
+            },
+        ) => {
+            let inner: Vec<_> = left_suffix
+                .iter()
+                .cloned()
+                .chain(links)
+                .chain(right_prefix.iter().cloned())
+                .collect();
+            let middle = append(left_middle, nodes(&inner), right_middle);
+            deep(prefix.clone(), Arc::new(middle), suffix.clone())
+        }
+    }
+}
+
+fn clone_tree<T: Measured>(tree: &Tree<T>) -> Tree<T> {
+    match tree {
+        Tree::Empty => Tree::Empty,
+        Tree::Single(node) => Tree::Single(Arc::clone(node)),
+        Tree::Deep {
+            measure,
+            prefix,
+            middle,
+            suffix,
+        } => Tree::Deep {
+            measure: measure.clone(),
+            prefix: prefix.clone(),
+            middle: Arc::clone(middle),
+            suffix: suffix.clone(),
+        },
+    }
+}
+
+// acc に前から畳み込んで pred が真になる digit の位置。なければ最後
+fn find_in_digit<T: Measured, P: Fn(&T::Measure) -> bool>(
+    pred: &P,
+    mut acc: T::Measure,
+    digit: &[Link<T>],
+) -> (usize, T::Measure) {
+    for (i, node) in digit.iter().enumerate().take(digit.len() - 1) {
+        let next = acc.combine(&node.measure());
+        if pred(&next) {
+            return (i, acc);
+        }
+        acc = next;
+    }
+    (digit.len() - 1, acc)
+}
+
+type Split<T> = (Tree<T>, Link<T>, Tree<T>);
+
+// pred(acc + measure(tree)) が真のとき、pred が初めて真になるノードとその前後に分ける
+fn split_tree<T: Measured, P: Fn(&T::Measure) -> bool>(
+    pred: &P,
+    acc: &T::Measure,
+    tree: &Tree<T>,
+) -> Split<T> {
+    match tree {
+        Tree::Empty => unreachable!("split needs a non-empty tree"),
+        Tree::Single(node) => (Tree::Empty, Arc::clone(node), Tree::Empty),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => {
+            let after_prefix = acc.combine(&measure_digit(prefix));
+            if pred(&after_prefix) {
+                let (i, _) = find_in_digit(pred, acc.clone(), prefix);
+                return (
+                    from_digit(&prefix[..i]),
+                    Arc::clone(&prefix[i]),
+                    deep_front(prefix[i + 1..].to_vec(), middle, suffix.clone()),
+                );
+            }
+            let after_middle = after_prefix.combine(&middle.measure());
+            if pred(&after_middle) {
+                let (left, node, right) = split_tree(pred, &after_prefix, middle);
+                let acc = after_prefix.combine(&left.measure());
+                let children = node.children();
+                let (i, _) = find_in_digit(pred, acc, children);
+                let (left, right) = (Arc::new(left), Arc::new(right));
+                return (
+                    deep_back(prefix.clone(), &left, children[..i].to_vec()),
+                    Arc::clone(&children[i]),
+                    deep_front(children[i + 1..].to_vec(), &right, suffix.clone()),
+                );
+            }
+            let (i, _) = find_in_digit(pred, after_middle, suffix);
+            (
+                deep_back(prefix.clone(), middle, suffix[..i].to_vec()),
+                Arc::clone(&suffix[i]),
+                from_digit(&suffix[i + 1..]),
+            )
+        }
+    }
+}
+
+// split_tree と同じ要素を、木を作らずに探す
+fn find_tree<'a, T: Measured, P: Fn(&T::Measure) -> bool>(
+    pred: &P,
+    acc: T::Measure,
+    tree: &'a Tree<T>,
+) -> &'a T {
+    let (mut node, mut acc) = match tree {
+        Tree::Empty => unreachable!("find needs a non-empty tree"),
+        Tree::Single(node) => (node, acc),
+        Tree::Deep {
+            prefix,
+            middle,
+            suffix,
+            ..
+        } => {
+            let after_prefix = acc.combine(&measure_digit(prefix));
+            let after_middle = after_prefix.combine(&middle.measure());
+            if pred(&after_prefix) {
+                let (i, acc) = find_in_digit(pred, acc, prefix);
+                (&prefix[i], acc)
+            } else if pred(&after_middle) {
+                return find_tree(pred, after_prefix, middle);
+            } else {
+                let (i, acc) = find_in_digit(pred, after_middle, suffix);
+                (&suffix[i], acc)
+            }
+        }
+    };
+    // 中の木から見つかったノードは、葉まで下りる
+    loop {
+        match &**node {
+            Node::Leaf(value) => return value,
+            Node::Branch(_, children) => {
+                let (i, next) = find_in_digit(pred, acc, children);
+                node = &children[i];
+                acc = next;
+            }
+        }
+    }
+}
+
+pub struct FingerTree<T: Measured> {
+    root: Arc<Tree<T>>,
+}
+
+impl<T: Measured> FingerTree<T> {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Tree::Empty),
+        }
+    }
+
+    fn from_tree(tree: Tree<T>) -> Self {
+        Self {
+            root: Arc::new(tree),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(*self.root, Tree::Empty)
+    }
+
+    // 全ての要素の measure を前から畳み込んだ値
+    pub fn measure(&self) -> T::Measure {
+        self.root.measure()
+    }
+
+    #[must_use]
+    pub fn push_front(&self, value: T) -> Self {
+        Self::from_tree(push_front(&self.root, Arc::new(Node::Leaf(value))))
+    }
+
+    #[must_use]
+    pub fn push_back(&self, value: T) -> Self {
+        Self::from_tree(push_back(&self.root, Arc::new(Node::Leaf(value))))
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        match &*self.root {
+            Tree::Empty => None,
+            Tree::Single(node) => Some(node.leaf()),
+            Tree::Deep { prefix, .. } => Some(prefix[0].leaf()),
+        }
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        match &*self.root {
+            Tree::Empty => None,
+            Tree::Single(node) => Some(node.leaf()),
+            Tree::Deep { suffix, .. } => suffix.last().map(|n| n.leaf()),
+        }
+    }
+
+    // 先頭とそれを除いた木
+    pub fn pop_front(&self) -> Option<(&T, Self)> {
+        let (_, rest) = pop_front(&self.root)?;
+        Some((self.front()?, Self::from_tree(rest)))
+    }
+
+    pub fn pop_back(&self) -> Option<(&T, Self)> {
+        let (_, rest) = pop_back(&self.root)?;
+        Some((self.back()?, Self::from_tree(rest)))
+    }
+
+    #[must_use]
+    pub fn concat(&self, other: &Self) -> Self {
+        Self::from_tree(append(&self.root, Vec::new(), &other.root))
+    }
+
+    // 前から measure を畳み込み、pred が初めて真になる要素の前で分ける
+    // pred は、偽から真に一度だけ変わる (単調な) 述語であること。最後まで偽なら (self, 空)
+    pub fn split<P: Fn(&T::Measure) -> bool>(&self, pred: P) -> (Self, Self) {
+        if self.is_empty() || !pred(&self.measure()) {
+            return (self.clone(), Self::new());
+        }
+        let (left, node, right) = split_tree(&pred, &T::Measure::empty(), &self.root);
+        (
+            Self::from_tree(left),
+            Self::from_tree(push_front(&right, node)),
+        )
+    }
+
+    // split で右側の先頭になる要素
+    pub fn find<P: Fn(&T::Measure) -> bool>(&self, pred: P) -> Option<&T> {
+        if self.is_empty() || !pred(&self.measure()) {
+            return None;
+        }
+        Some(find_tree(&pred, T::Measure::empty(), &self.root))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: vec![Pending::Tree(&self.root)],
+        }
+    }
+
+    // keep が偽になる measure の部分木を飛ばして、残った要素を前から返す
+    // 要素ごとに調べるより速く、条件に合う要素がまばらなときに使う
+    pub fn iter_matching<P: Fn(&T::Measure) -> bool>(&self, keep: P) -> Matching<'_, T, P> {
+        Matching {
+            iter: self.iter(),
+            keep,
+        }
+    }
+
+    // digit の長さ、ノードの子の数と深さ、持っている measure が正しいか確かめる
+    pub fn check_invariants(&self)
+    where
+        T::Measure: PartialEq + fmt::Debug,
+    {
+        fn check_node<T: Measured>(node: &Node<T>, depth: usize)
+        where
+            T::Measure: PartialEq + fmt::Debug,
+        {
+            match node {
+                Node::Leaf(_) => assert_eq!(depth, 0, "leaf is too deep"),
+                Node::Branch(measure, children) => {
+                    assert!(depth > 0, "branch is at the top level");
+                    assert!(
+                        (2..=3).contains(&children.len()),
+                        "node has {} children",
+                        children.len()
+                    );
+                    for child in children {
+                        check_node(child, depth - 1);
+                    }
+                    assert_eq!(measure, &measure_digit(children), "measure is stale");
+                }
+            }
+        }
+        fn check<T: Measured>(tree: &Tree<T>, depth: usize)
+        where
+            T::Measure: PartialEq + fmt::Debug,
+        {
+            match tree {
+                Tree::Empty => {}
+                Tree::Single(node) => check_node(node, depth),
+                Tree::Deep {
+                    measure,
+                    prefix,
+                    middle,
+                    suffix,
+                } => {
+                    for digit in [prefix, suffix] {
+                        assert!((1..=4).contains(&digit.len()), "digit has {}", digit.len());
+                        for node in digit {
+                            check_node(node, depth);
+                        }
+                    }
+                    check(middle, depth + 1);
+                    let expected = measure_digit(prefix)
+                        .combine(&middle.measure())
+                        .combine(&measure_digit(suffix));
+                    assert_eq!(measure, &expected, "measure is stale");
+                }
+            }
+        }
+        check(&self.root, 0);
+    }
+}
+
+// root の Arc を増やすだけなので O(1)
+impl<T: Measured> Clone for FingerTree<T> {
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+        }
+    }
+}
+
+impl<T: Measured> Default for FingerTree<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Measured + fmt::Debug> fmt::Debug for FingerTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+enum Pending<'a, T: Measured> {
+    Tree(&'a Tree<T>),
+    Node(&'a Node<T>),
+}
+
+// これから返す部分を、先に返すものが上に来るように積んでおく
+pub struct Iter<'a, T: Measured> {
+    stack: Vec<Pending<'a, T>>,
+}
+
+impl<'a, T: Measured> Iter<'a, T> {
+    fn next_matching<P: Fn(&T::Measure) -> bool>(&mut self, keep: Option<&P>) -> Option<&'a T> {
+        loop {
+            let pending = self.stack.pop()?;
+            if let Some(keep) = keep {
+                let measure = match pending {
+                    Pending::Tree(tree) => tree.measure(),
+                    Pending::Node(node) => node.measure(),
+                };
+                if !keep(&measure) {
+                    continue;
+                }
+            }
+            match pending {
+                Pending::Node(Node::Leaf(value)) => return Some(value),
+                Pending::Node(Node::Branch(_, children)) => self
+                    .stack
+                    .extend(children.iter().rev().map(|n| Pending::Node(n))),
+                Pending::Tree(Tree::Empty) => {}
+                Pending::Tree(Tree::Single(node)) => self.stack.push(Pending::Node(node)),
+                Pending::Tree(Tree::Deep {
+                    prefix,
+                    middle,
+                    suffix,
+                    ..
+                }) => {
+                    self.stack
+                        .extend(suffix.iter().rev().map(|n| Pending::Node(n)));
+                    self.stack.push(Pending::Tree(middle));
+                    self.stack
+                        .extend(prefix.iter().rev().map(|n| Pending::Node(n)));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T: Measured> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.next_matching::<fn(&T::Measure) -> bool>(None)
+    }
+}
+
+impl<'a, T: Measured> FusedIterator for Iter<'a, T> {}
+
+pub struct Matching<'a, T: Measured, P> {
+    iter: Iter<'a, T>,
+    keep: P,
+}
+
+impl<'a, T: Measured, P: Fn(&T::Measure) -> bool> Iterator for Matching<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.iter.next_matching(Some(&self.keep))
+    }
+}
+
+impl<'a, T: Measured, P: Fn(&T::Measure) -> bool> FusedIterator for Matching<'a, T, P> {}
+
+impl<'a, T: Measured> IntoIterator for &'a FingerTree<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Measured> FromIterator<T> for FingerTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let tree = iter.into_iter().fold(Tree::Empty, |tree, value| {
+            push_back(&tree, Arc::new(Node::Leaf(value)))
+        });
+        Self::from_tree(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // 要素の数と合計を数える
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Stats {
+        count: usize,
+        sum: u64,
+    }
+
+    impl Monoid for Stats {
+        fn empty() -> Self {
+            Stats { count: 0, sum: 0 }
+        }
+
+        fn combine(&self, other: &Self) -> Self {
+            Stats {
+                count: self.count + other.count,
+                sum: self.sum + other.sum,
+            }
+        }
+    }
+
+    impl Measured for u64 {
+        type Measure = Stats;
+
+        fn measure(&self) -> Stats {
+            Stats {
+                count: 1,
+                sum: *self,
+            }
+        }
+    }
+
+    #[test]
+    fn deque_and_measure() {
+        let t: FingerTree<u64> = (1..=100).collect();
+        t.check_invariants();
+        assert_eq!(
+            t.measure(),
+            Stats {
+                count: 100,
+                sum: 5050
+            }
+        );
+        assert_eq!((t.front(), t.back()), (Some(&1), Some(&100)));
+        let t = t.push_front(0).push_back(101);
+        let (first, rest) = t.pop_front().unwrap();
+        let (last, rest) = rest.pop_back().unwrap();
+        rest.check_invariants();
+        assert_eq!((*first, *last), (0, 101));
+        assert!(rest.iter().copied().eq(1..=100));
+        assert!(FingerTree::<u64>::new().pop_front().is_none());
+    }
+
+    #[test]
+    fn split_by_prefix_sum() {
+        let t: FingerTree<u64> = (1..=100).collect();
+        // 1 + 2 + ... + 10 = 55 なので、合計が 50 を超えるのは 10 を足したとき
+        let (left, right) = t.split(|m| m.sum > 50);
+        left.check_invariants();
+        right.check_invariants();
+        assert_eq!(left.back(), Some(&9));
+        assert_eq!(right.front(), Some(&10));
+        assert_eq!(t.find(|m| m.sum > 50), Some(&10));
+        assert_eq!(t.find(|m| m.count > 100), None);
+        let (all, none) = t.split(|m| m.count > 100);
+        assert!(none.is_empty());
+        assert_eq!(all.measure().count, 100);
+        assert!(left.concat(&right).iter().copied().eq(1..=100));
+    }
+
+    #[test]
+    fn iter_matching_skips_subtrees() {
+        let t: FingerTree<u64> = (0..1000)
+            .map(|x| if x % 100 == 7 { 1 } else { 0 })
+            .collect();
+        // 合計が 0 の部分木は飛ばす
+        assert_eq!(t.iter_matching(|m| m.sum > 0).count(), 10);
+        assert_eq!(t.iter_matching(|m| m.count > 0).count(), 1000);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        PushFront(u64),
+        PushBack(u64),
+        PopFront,
+        PopBack,
+        Concat(usize),
+        Split(usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        (0..6u8, 0..100u64, any::<usize>()).prop_map(|(t, x, i)| match t {
+            0 => Op::PushFront(x),
+            1 => Op::PushBack(x),
+            2 => Op::PopFront,
+            3 => Op::PopBack,
+            4 => Op::Concat(i),
+            _ => Op::Split(i),
+        })
+    }
+
+    proptest! {
+        // 版を残しながら操作し、VecDeque の写しと比べる
+        #[test]
+        fn same_as_vec_deque_random(ops in prop::collection::vec(op_strategy(), 0..200)) {
+            let mut versions: Vec<(FingerTree<u64>, VecDeque<u64>)> = vec![(FingerTree::new(), VecDeque::new())];
+            for op in ops {
+                let (t, mut model) = versions.last().unwrap().clone();
+                let t = match op {
+                    Op::PushFront(x) => {
+                        model.push_front(x);
+                        t.push_front(x)
+                    }
+                    Op::PushBack(x) => {
+                        model.push_back(x);
+                        t.push_back(x)
+                    }
+                    Op::PopFront => {
+                        let expected = model.pop_front();
+                        let popped = t.pop_front();
+                        prop_assert_eq!(popped.as_ref().map(|(x, _)| **x), expected);
+                        popped.map_or(t.clone(), |(_, rest)| rest)
+                    }
+                    Op::PopBack => {
+                        let expected = model.pop_back();
+                        let popped = t.pop_back();
+                        prop_assert_eq!(popped.as_ref().map(|(x, _)| **x), expected);
+                        popped.map_or(t.clone(), |(_, rest)| rest)
+                    }
+                    Op::Concat(i) => {
+                        let (other, other_model) = &versions[i % versions.len()];
+                        if model.len() + other_model.len() > 5000 {
+                            continue;
+                        }
+                        model.extend(other_model.iter().copied());
+                        t.concat(other)
+                    }
+                    // i 番目の前で分けて、左右を入れ替えて繋ぐ
+                    Op::Split(i) => {
+                        let i = i % (model.len() + 1);
+                        let (left, right) = t.split(|m| m.count > i);
+                        left.check_invariants();
+                        right.check_invariants();
+                        prop_assert_eq!(left.measure().count, i);
+                        model.rotate_left(i);
+                        right.concat(&left)
+                    }
+                };
+                t.check_invariants();
+                versions.push((t, model));
+            }
+            for (t, model) in &versions {
+                prop_assert!(t.iter().eq(model.iter()));
+                prop_assert_eq!(t.measure().count, model.len());
+                prop_assert_eq!(t.measure().sum, model.iter().sum::<u64>());
+            }
+        }
+    }
+}
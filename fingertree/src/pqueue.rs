@@ -0,0 +1,146 @@
+// 優先度の最大値を measure にした優先度付きキュー
+// 最大の要素は、畳み込んだ最大値が全体の最大値に届く位置で split して取り出す
+// push は償却 O(1)、pop は O(log n)。同じ優先度なら先に入れたものから取り出す
+use std::cmp::Ordering;
+
+use crate::{FingerTree, Measured, Monoid};
+
+// 優先度の最大値。空なら None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Max<P>(pub Option<P>);
+
+impl<P: Ord + Clone> Monoid for Max<P> {
+    fn empty() -> Self {
+        Max(None)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) if a >= b => self.clone(),
+            (_, None) => self.clone(),
+            _ => other.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry<P, T> {
+    priority: P,
+    value: T,
+}
+
+impl<P: Ord + Clone, T> Measured for Entry<P, T> {
+    type Measure = Max<P>;
+
+    fn measure(&self) -> Max<P> {
+        Max(Some(self.priority.clone()))
+    }
+}
+
+pub struct PriorityQueue<P: Ord + Clone, T> {
+    tree: FingerTree<Entry<P, T>>,
+}
+
+impl<P: Ord + Clone, T> PriorityQueue<P, T> {
+    pub fn new() -> Self {
+        Self {
+            tree: FingerTree::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    #[must_use]
+    pub fn push(&self, priority: P, value: T) -> Self {
+        Self {
+            tree: self.tree.push_back(Entry { priority, value }),
+        }
+    }
+
+    // 優先度が最大の要素のうち、最初に入れたもの
+    pub fn peek(&self) -> Option<(&P, &T)> {
+        let max = self.tree.measure();
+        self.tree
+            .find(|m| reaches(m, &max))
+            .map(|e| (&e.priority, &e.value))
+    }
+
+    // peek の要素と、それを除いたキュー
+    pub fn pop(&self) -> Option<((&P, &T), Self)> {
+        let top = self.peek()?;
+        let max = self.tree.measure();
+        let (left, right) = self.tree.split(|m| reaches(m, &max));
+        let (_, right) = right
+            .pop_front()
+            .expect("split keeps the maximum on the right");
+        Some((
+            top,
+            Self {
+                tree: left.concat(&right),
+            },
+        ))
+    }
+}
+
+fn reaches<P: Ord>(m: &Max<P>, max: &Max<P>) -> bool {
+    match (&m.0, &max.0) {
+        (Some(a), Some(b)) => a.cmp(b) != Ordering::Less,
+        _ => false,
+    }
+}
+
+impl<P: Ord + Clone, T> Clone for PriorityQueue<P, T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<P: Ord + Clone, T> Default for PriorityQueue<P, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BinaryHeap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        // 取り出す優先度の順が BinaryHeap と同じで、同じ優先度は入れた順に出る
+        #[test]
+        fn same_as_binary_heap_random(ops in prop::collection::vec((any::<bool>(), 0..20u32), 0..200)) {
+            let mut q = PriorityQueue::new();
+            let mut heap = BinaryHeap::new();
+            for (seq, (push, p)) in ops.into_iter().enumerate() {
+                if push {
+                    q = q.push(p, seq);
+                    // 同じ優先度なら seq の小さい方を先に出す
+                    heap.push((p, std::cmp::Reverse(seq)));
+                } else {
+                    let expected = heap.pop().map(|(p, std::cmp::Reverse(seq))| (p, seq));
+                    let next = match q.pop() {
+                        Some(((p, seq), rest)) => {
+                            prop_assert_eq!(Some((*p, *seq)), expected);
+                            rest
+                        }
+                        None => {
+                            prop_assert_eq!(expected, None);
+                            q.clone()
+                        }
+                    };
+                    q = next;
+                }
+                q.tree.check_invariants();
+                prop_assert_eq!(q.peek().map(|(p, _)| *p), heap.peek().map(|(p, _)| *p));
+            }
+        }
+    }
+}
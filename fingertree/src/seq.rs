@@ -0,0 +1,138 @@
+// 要素の数を measure にした、添字で引ける列
+// get、split_at、concat が O(log n)、両端への追加が償却 O(1)
+use std::{fmt, iter::FromIterator};
+
+use crate::{FingerTree, Measured, Monoid};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size(pub usize);
+
+impl Monoid for Size {
+    fn empty() -> Self {
+        Size(0)
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Size(self.0 + other.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Elem<T>(T);
+
+impl<T> Measured for Elem<T> {
+    type Measure = Size;
+
+    fn measure(&self) -> Size {
+        Size(1)
+    }
+}
+
+pub struct Seq<T> {
+    tree: FingerTree<Elem<T>>,
+}
+
+impl<T> Seq<T> {
+    pub fn new() -> Self {
+        Self {
+            tree: FingerTree::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.measure().0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.tree.find(|s| s.0 > index).map(|e| &e.0)
+    }
+
+    #[must_use]
+    pub fn push_front(&self, value: T) -> Self {
+        Self {
+            tree: self.tree.push_front(Elem(value)),
+        }
+    }
+
+    #[must_use]
+    pub fn push_back(&self, value: T) -> Self {
+        Self {
+            tree: self.tree.push_back(Elem(value)),
+        }
+    }
+
+    #[must_use]
+    pub fn concat(&self, other: &Self) -> Self {
+        Self {
+            tree: self.tree.concat(&other.tree),
+        }
+    }
+
+    // [0, index) と [index, len) に分ける
+    pub fn split_at(&self, index: usize) -> (Self, Self) {
+        let (left, right) = self.tree.split(|s| s.0 > index);
+        (Self { tree: left }, Self { tree: right })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.tree.iter().map(|e| &e.0)
+    }
+}
+
+impl<T> Clone for Seq<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<T> Default for Seq<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Seq<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> FromIterator<T> for Seq<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            tree: iter.into_iter().map(Elem).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn index_split_concat() {
+        let s: Seq<_> = (0..1000).collect();
+        assert_eq!(s.len(), 1000);
+        assert_eq!(s.get(0), Some(&0));
+        assert_eq!(s.get(567), Some(&567));
+        assert_eq!(s.get(1000), None);
+        let (left, right) = s.split_at(300);
+        assert_eq!((left.len(), right.len()), (300, 700));
+        assert_eq!(right.get(0), Some(&300));
+        let rotated = right.concat(&left).push_front(-1);
+        rotated.tree.check_invariants();
+        assert!(rotated
+            .iter()
+            .copied()
+            .eq(std::iter::once(-1).chain(300..1000).chain(0..300)));
+        for i in (0..1001).step_by(37) {
+            assert_eq!(rotated.get(i), rotated.iter().nth(i));
+        }
+    }
+}
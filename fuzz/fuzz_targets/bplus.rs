@@ -0,0 +1,36 @@
+// 先頭の 1 byte で cap を決め、残りを 3 bytes ずつ操作として読む
+//   [0]    : 操作の種類
+//   [1..3] : キー。重複が起きやすいように 0..256 に丸める
+// 操作のたびに木の構造を確認する
+#![no_main]
+use bplus::{BPlusTree, Data, OrderedMap};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (cap, ops) = match data.split_first() {
+        Some((cap, ops)) => (2 + *cap as usize % 62, ops),
+        None => return,
+    };
+    let mut tree = BPlusTree::<u16>::new(cap);
+    for op in ops.chunks_exact(3) {
+        let key = u16::from_le_bytes([op[1], op[2]]);
+        let k = key as usize % 256;
+        match op[0] % 4 {
+            // 同じキーを重複して入れる
+            0 => tree.insert(k, Data::new(0, key)),
+            1 => {
+                OrderedMap::insert(&mut tree, k, key);
+            }
+            2 => {
+                tree.remove(k);
+            }
+            _ => {
+                let found = tree.search(k).is_some();
+                assert_eq!(found, tree.iter().any(|(key, _)| key == k));
+            }
+        }
+        tree.check_invariants();
+    }
+    assert_eq!(tree.iter().count(), tree.len());
+    assert!(tree.iter().rev().eq(tree.entries().into_iter().rev()));
+});
@@ -0,0 +1,31 @@
+// Arbitrary で作った木と操作列を、それぞれの木と BTreeMap に同じように適用して結果を比べる
+#![no_main]
+use std::collections::BTreeMap;
+
+use bplus::{Key, Op};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (
+    bplus::BPlusTree<usize>,
+    unsafebplus::BPlusTree,
+    unsafebplus::ArenaBPlusTree,
+    Vec<Op<Key, usize>>
+)| {
+    let (mut tree, mut unsafe_tree, mut arena_tree, ops) = input;
+    let mut expected: BTreeMap<_, _> = tree.iter().map(|(k, v)| (k, *v)).collect();
+    let mut unsafe_expected: BTreeMap<_, _> = unsafe_tree.iter().map(|(k, v)| (k, *v)).collect();
+    let mut arena_expected: BTreeMap<_, _> = arena_tree.iter().map(|(k, v)| (k, *v)).collect();
+    for op in ops {
+        let result = op.clone().apply(&mut expected);
+        assert_eq!(op.clone().apply(&mut tree), result);
+        tree.check_invariants();
+
+        let result = op.clone().apply(&mut unsafe_expected);
+        assert_eq!(op.clone().apply(&mut unsafe_tree), result);
+        unsafe_tree.check_invariants();
+
+        let result = op.clone().apply(&mut arena_expected);
+        assert_eq!(op.apply(&mut arena_tree), result);
+        arena_tree.check_invariants();
+    }
+});
@@ -0,0 +1,38 @@
+// 入力の形式は bplus と同じ
+// check_invariants で leaf の next が全ての leaf を順に辿れることも確認する
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use unsafebplus::{BPlusTree, OrderedMap};
+
+fuzz_target!(|data: &[u8]| {
+    let (cap, ops) = match data.split_first() {
+        Some((cap, ops)) => (2 + *cap as usize % 62, ops),
+        None => return,
+    };
+    let mut tree = BPlusTree::new(cap);
+    for op in ops.chunks_exact(3) {
+        let key = u16::from_le_bytes([op[1], op[2]]) as usize;
+        let k = key % 256;
+        match op[0] % 4 {
+            // 同じキーを重複して入れる
+            0 => tree.insert(k, key),
+            1 => {
+                OrderedMap::insert(&mut tree, k, key);
+            }
+            2 => {
+                tree.remove(k);
+            }
+            _ => {
+                // next を辿る経路と、木を上から辿る経路の結果を比べる
+                let values: Vec<_> = tree
+                    .iter()
+                    .filter(|(key, _)| *key >= k)
+                    .map(|(_, v)| v)
+                    .collect();
+                assert_eq!(tree.search_range(k, usize::MAX), values);
+            }
+        }
+        tree.check_invariants();
+    }
+    assert_eq!(tree.iter().count(), tree.len());
+});
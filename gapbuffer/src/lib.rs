@@ -0,0 +1,360 @@
+// 小さな文書を編集するための gap buffer
+// UTF-8 のバイト列の途中に空き (gap) を置き、gap の位置をカーソルにする
+//   [0, gap_start) : カーソルより前の文字列
+//   [gap_start, gap_end) : 空き
+//   [gap_end, len) : カーソルより後ろの文字列
+// カーソルでの挿入と削除は gap の端を動かすだけなので O(1) (挿入は償却)
+// 離れた位置を編集するときは、その位置まで gap を動かすので移動した距離に比例する
+//
+// 位置は文字 (char) の数で数える。カーソルより前の文字数を持っておき、位置の変換は gap から数える
+use std::{fmt, ops::Range};
+
+pub use textbuffer::TextBuffer;
+
+// 最初に確保する gap の大きさ
+const MIN_GAP: usize = 64;
+
+// UTF-8 で文字の先頭になるバイトか
+fn is_char_start(b: u8) -> bool {
+    (b as i8) >= -0x40
+}
+
+pub struct GapBuffer {
+    buf: Vec<u8>,
+    gap_start: usize,
+    gap_end: usize,
+    // カーソルより前の文字数
+    cursor: usize,
+    chars: usize,
+    newlines: usize,
+}
+
+impl GapBuffer {
+    pub fn new() -> Self {
+        Self::from("")
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.chars
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.buf.len() - (self.gap_end - self.gap_start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars == 0
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.newlines + 1
+    }
+
+    // gap の前にある文字の数
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // gap を文字の位置 at に動かす。動かした距離に比例する
+    pub fn set_cursor(&mut self, at: usize) {
+        assert!(
+            at <= self.chars,
+            "char index {} is out of bounds for {} chars",
+            at,
+            self.chars
+        );
+        if at < self.cursor {
+            // gap の前から at までのバイトを、gap の後ろへ移す
+            let mut start = self.gap_start;
+            let mut count = self.cursor - at;
+            while count > 0 {
+                start -= 1;
+                if is_char_start(self.buf[start]) {
+                    count -= 1;
+                }
+            }
+            let len = self.gap_start - start;
+            self.buf
+                .copy_within(start..self.gap_start, self.gap_end - len);
+            self.gap_start = start;
+            self.gap_end -= len;
+        } else if at > self.cursor {
+            let end = self.forward(at - self.cursor);
+            let len = end - self.gap_end;
+            self.buf.copy_within(self.gap_end..end, self.gap_start);
+            self.gap_start += len;
+            self.gap_end = end;
+        }
+        self.cursor = at;
+    }
+
+    // gap の後ろから count 文字進んだバイトの位置
+    fn forward(&self, count: usize) -> usize {
+        let after = &self.buf[self.gap_end..];
+        let offset = after
+            .iter()
+            .enumerate()
+            .filter(|&(_, &b)| is_char_start(b))
+            .nth(count)
+            .map_or(after.len(), |(i, _)| i);
+        self.gap_end + offset
+    }
+
+    // カーソルに text を入れ、カーソルを text の後ろに進める
+    pub fn insert_at_cursor(&mut self, text: &str) {
+        if self.gap_end - self.gap_start < text.len() {
+            self.grow(text.len());
+        }
+        self.buf[self.gap_start..self.gap_start + text.len()].copy_from_slice(text.as_bytes());
+        self.gap_start += text.len();
+        let chars = text.chars().count();
+        self.cursor += chars;
+        self.chars += chars;
+        self.newlines += text.matches('\n').count();
+    }
+
+    // gap を広げる。後ろの文字列を新しい末尾へ移す
+    fn grow(&mut self, needed: usize) {
+        let after = self.buf.len() - self.gap_end;
+        let gap = (self.buf.len() + needed).max(MIN_GAP);
+        let mut buf = vec![0; self.gap_start + gap + after];
+        buf[..self.gap_start].copy_from_slice(&self.buf[..self.gap_start]);
+        buf[self.gap_start + gap..].copy_from_slice(&self.buf[self.gap_end..]);
+        self.buf = buf;
+        self.gap_end = self.gap_start + gap;
+    }
+
+    // カーソルの前の 1 文字を消す
+    pub fn backspace(&mut self) -> Option<char> {
+        if self.cursor == 0 {
+            return None;
+        }
+        let c = self.before().chars().next_back()?;
+        self.gap_start -= c.len_utf8();
+        self.cursor -= 1;
+        self.chars -= 1;
+        self.newlines -= (c == '\n') as usize;
+        Some(c)
+    }
+
+    // カーソルの後ろの 1 文字を消す
+    pub fn delete(&mut self) -> Option<char> {
+        let c = self.after().chars().next()?;
+        self.gap_end += c.len_utf8();
+        self.chars -= 1;
+        self.newlines -= (c == '\n') as usize;
+        Some(c)
+    }
+
+    // カーソルより前の文字列
+    pub fn before(&self) -> &str {
+        std::str::from_utf8(&self.buf[..self.gap_start]).expect("text is valid UTF-8")
+    }
+
+    // カーソルより後ろの文字列
+    pub fn after(&self) -> &str {
+        std::str::from_utf8(&self.buf[self.gap_end..]).expect("text is valid UTF-8")
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.before().chars().chain(self.after().chars())
+    }
+
+    // 数えておいた値が、gap の前後の文字列と合っているか確かめる
+    pub fn check_invariants(&self) {
+        let (before, after) = (self.before(), self.after());
+        assert!(self.gap_start <= self.gap_end && self.gap_end <= self.buf.len());
+        assert_eq!(self.cursor, before.chars().count(), "cursor is stale");
+        assert_eq!(
+            self.chars,
+            self.cursor + after.chars().count(),
+            "chars is stale"
+        );
+        assert_eq!(
+            self.newlines,
+            before.matches('\n').count() + after.matches('\n').count(),
+            "newlines is stale"
+        );
+    }
+}
+
+impl TextBuffer for GapBuffer {
+    fn len_chars(&self) -> usize {
+        self.chars
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        self.set_cursor(at);
+        self.insert_at_cursor(text);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.chars,
+            "char range {:?} is out of bounds for {} chars",
+            range,
+            self.chars
+        );
+        self.set_cursor(range.start);
+        let end = self.forward(range.end - range.start);
+        let removed = std::str::from_utf8(&self.buf[self.gap_end..end]).expect("valid UTF-8");
+        self.newlines -= removed.matches('\n').count();
+        self.chars -= range.end - range.start;
+        self.gap_end = end;
+    }
+
+    fn slice_to_string(&self, range: Range<usize>) -> String {
+        self.chars()
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+            .collect()
+    }
+
+    fn len_lines(&self) -> usize {
+        self.newlines + 1
+    }
+
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Default for GapBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// カーソルは末尾に置く
+impl From<&str> for GapBuffer {
+    fn from(text: &str) -> Self {
+        let mut buffer = GapBuffer {
+            buf: Vec::new(),
+            gap_start: 0,
+            gap_end: 0,
+            cursor: 0,
+            chars: 0,
+            newlines: 0,
+        };
+        buffer.insert_at_cursor(text);
+        buffer
+    }
+}
+
+impl From<String> for GapBuffer {
+    fn from(text: String) -> Self {
+        Self::from(text.as_str())
+    }
+}
+
+impl From<GapBuffer> for String {
+    fn from(buffer: GapBuffer) -> String {
+        buffer.to_string()
+    }
+}
+
+impl fmt::Display for GapBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.before())?;
+        f.write_str(self.after())
+    }
+}
+
+impl fmt::Debug for GapBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GapBuffer")
+            .field("before", &self.before())
+            .field("after", &self.after())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn cursor_editing() {
+        let mut b = GapBuffer::from("helo");
+        assert_eq!(b.cursor(), 4);
+        b.set_cursor(3);
+        b.insert_at_cursor("l");
+        assert_eq!((b.before(), b.after()), ("hell", "o"));
+        assert_eq!(b.backspace(), Some('l'));
+        assert_eq!(b.delete(), Some('o'));
+        assert_eq!(b.delete(), None);
+        b.insert_at_cursor("lo、世界\n");
+        b.set_cursor(0);
+        assert_eq!(b.backspace(), None);
+        b.check_invariants();
+        assert_eq!(b.len_chars(), 9);
+        assert_eq!(b.len_lines(), 2);
+        assert_eq!(String::from(b), "hello、世界\n");
+    }
+
+    #[test]
+    fn text_buffer() {
+        let mut b = GapBuffer::new();
+        b.insert(0, "first\nthird");
+        b.insert(6, "second\n");
+        b.remove(0..6);
+        b.check_invariants();
+        assert_eq!(b.text(), "second\nthird");
+        assert_eq!(TextBuffer::char(&b, 7), Some('t'));
+        assert_eq!(b.slice_to_string(3..9), "ond\nth");
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, String),
+        Remove(usize, usize),
+        Backspace(usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (any::<usize>(), "[ab\nあい]{0,40}").prop_map(|(i, s)| Op::Insert(i, s)),
+            (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Remove(a, b)),
+            any::<usize>().prop_map(Op::Backspace),
+        ]
+    }
+
+    proptest! {
+        // 文字の位置で String と同じ操作をして比べる
+        #[test]
+        fn same_as_string_random(ops in prop::collection::vec(op_strategy(), 0..100)) {
+            let mut b = GapBuffer::new();
+            let mut model = String::new();
+            for op in ops {
+                let n = model.len_chars();
+                match op {
+                    Op::Insert(i, s) => {
+                        let i = i % (n + 1);
+                        b.insert(i, &s);
+                        TextBuffer::insert(&mut model, i, &s);
+                    }
+                    Op::Remove(x, y) => {
+                        let (x, y) = (x % (n + 1), y % (n + 1));
+                        b.remove(x.min(y)..x.max(y));
+                        TextBuffer::remove(&mut model, x.min(y)..x.max(y));
+                    }
+                    Op::Backspace(i) => {
+                        b.set_cursor(i % (n + 1));
+                        let expected = (b.cursor() > 0).then(|| {
+                            let c = model.chars().nth(b.cursor() - 1).unwrap();
+                            TextBuffer::remove(&mut model, b.cursor() - 1..b.cursor());
+                            c
+                        });
+                        prop_assert_eq!(b.backspace(), expected);
+                    }
+                }
+                b.check_invariants();
+                prop_assert_eq!(b.to_string(), model.clone());
+                prop_assert_eq!(b.len_bytes(), model.len());
+                prop_assert_eq!(b.len_lines(), TextBuffer::len_lines(&model));
+            }
+        }
+    }
+}
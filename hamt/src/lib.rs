@@ -0,0 +1,590 @@
+// 永続的な Hash Array Mapped Trie (Bagwell, 2001)
+// キーのハッシュを下位から 5 ビットずつ使い、各段で 32 通りに分ける
+// 分岐のノードは、ある子だけを詰めて並べ、ビットマップの popcount で位置を求める
+//
+// insert と remove は自分を変えずに新しい map を返す
+// 変わるのは root から変えた葉までの経路のノードだけで、それ以外のノードは Arc で前の map と共有する
+// そのため 1 回の更新で作るノードは O(log32 n) 個で、古い map はそのまま読み続けられる
+//
+// 64 ビットのハッシュを使い切っても同じになったキーは、衝突のノードにまとめる
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::{FromIterator, FusedIterator},
+    slice,
+    sync::Arc,
+};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+#[derive(Debug)]
+struct Leaf<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+}
+
+#[derive(Debug)]
+enum Entry<K, V> {
+    Leaf(Arc<Leaf<K, V>>),
+    Branch(Arc<Branch<K, V>>),
+    // ハッシュが全て同じキー。2 つ以上ある
+    Collision(Arc<Vec<Arc<Leaf<K, V>>>>),
+}
+
+// Arc を増やすだけなので、K と V に Clone はいらない
+impl<K, V> Clone for Entry<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Entry::Leaf(leaf) => Entry::Leaf(Arc::clone(leaf)),
+            Entry::Branch(branch) => Entry::Branch(Arc::clone(branch)),
+            Entry::Collision(leaves) => Entry::Collision(Arc::clone(leaves)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Branch<K, V> {
+    // ハッシュのこの段の 5 ビットが i の子があれば、i ビット目が立つ
+    bitmap: u32,
+    entries: Vec<Entry<K, V>>,
+}
+
+impl<K, V> Branch<K, V> {
+    fn empty() -> Self {
+        Self {
+            bitmap: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn bit(hash: u64, shift: u32) -> u32 {
+        1 << ((hash >> shift) & MASK)
+    }
+
+    fn position(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+
+    // entry を 1 つ置き換えた複製
+    fn with(&self, pos: usize, entry: Entry<K, V>) -> Self {
+        let mut entries = self.entries.clone();
+        entries[pos] = entry;
+        Self {
+            bitmap: self.bitmap,
+            entries,
+        }
+    }
+}
+
+// shift 以降のビットで 2 つの葉を分ける部分木
+fn split<K, V>(a: Arc<Leaf<K, V>>, b: Arc<Leaf<K, V>>, shift: u32) -> Entry<K, V> {
+    if shift >= u64::BITS {
+        return Entry::Collision(Arc::new(vec![a, b]));
+    }
+    let (bit_a, bit_b) = (
+        Branch::<K, V>::bit(a.hash, shift),
+        Branch::<K, V>::bit(b.hash, shift),
+    );
+    let branch = if bit_a == bit_b {
+        Branch {
+            bitmap: bit_a,
+            entries: vec![split(a, b, shift + BITS)],
+        }
+    } else {
+        let entries = if bit_a < bit_b {
+            vec![Entry::Leaf(a), Entry::Leaf(b)]
+        } else {
+            vec![Entry::Leaf(b), Entry::Leaf(a)]
+        };
+        Branch {
+            bitmap: bit_a | bit_b,
+            entries,
+        }
+    };
+    Entry::Branch(Arc::new(branch))
+}
+
+// leaf を入れた branch の複製と、同じキーがあったかを返す
+fn insert<K: Eq, V>(
+    branch: &Branch<K, V>,
+    leaf: Arc<Leaf<K, V>>,
+    shift: u32,
+) -> (Branch<K, V>, bool) {
+    let bit = Branch::<K, V>::bit(leaf.hash, shift);
+    let pos = branch.position(bit);
+    if branch.bitmap & bit == 0 {
+        let mut entries = Vec::with_capacity(branch.entries.len() + 1);
+        entries.extend_from_slice(&branch.entries[..pos]);
+        entries.push(Entry::Leaf(leaf));
+        entries.extend_from_slice(&branch.entries[pos..]);
+        let branch = Branch {
+            bitmap: branch.bitmap | bit,
+            entries,
+        };
+        return (branch, false);
+    }
+    let (entry, replaced) = match &branch.entries[pos] {
+        Entry::Leaf(old) if old.hash == leaf.hash && old.key == leaf.key => {
+            (Entry::Leaf(leaf), true)
+        }
+        Entry::Leaf(old) => (split(Arc::clone(old), leaf, shift + BITS), false),
+        Entry::Branch(child) => {
+            let (child, replaced) = insert(child, leaf, shift + BITS);
+            (Entry::Branch(Arc::new(child)), replaced)
+        }
+        Entry::Collision(leaves) => {
+            let mut leaves = Vec::clone(leaves);
+            let replaced = match leaves.iter().position(|old| old.key == leaf.key) {
+                Some(i) => {
+                    leaves[i] = leaf;
+                    true
+                }
+                None => {
+                    leaves.push(leaf);
+                    false
+                }
+            };
+            (Entry::Collision(Arc::new(leaves)), replaced)
+        }
+    };
+    (branch.with(pos, entry), replaced)
+}
+
+// key を除いた branch の複製。key がなければ None
+// 子が 1 つの葉だけになった分岐は、親に葉を引き上げてもらう
+fn remove<K: Eq, V>(branch: &Branch<K, V>, hash: u64, key: &K, shift: u32) -> Option<Branch<K, V>> {
+    let bit = Branch::<K, V>::bit(hash, shift);
+    if branch.bitmap & bit == 0 {
+        return None;
+    }
+    let pos = branch.position(bit);
+    let entry = match &branch.entries[pos] {
+        Entry::Leaf(leaf) if leaf.hash == hash && leaf.key == *key => None,
+        Entry::Leaf(_) => return None,
+        Entry::Branch(child) => {
+            let child = remove(child, hash, key, shift + BITS)?;
+            match child.entries.as_slice() {
+                [] => None,
+                [Entry::Leaf(leaf)] => Some(Entry::Leaf(Arc::clone(leaf))),
+                _ => Some(Entry::Branch(Arc::new(child))),
+            }
+        }
+        Entry::Collision(leaves) => {
+            let i = leaves.iter().position(|leaf| leaf.key == *key)?;
+            let mut leaves = Vec::clone(leaves);
+            leaves.remove(i);
+            Some(match leaves.len() {
+                1 => Entry::Leaf(leaves.pop().expect("one leaf is left")),
+                _ => Entry::Collision(Arc::new(leaves)),
+            })
+        }
+    };
+    Some(match entry {
+        Some(entry) => branch.with(pos, entry),
+        None => {
+            let mut entries = branch.entries.clone();
+            entries.remove(pos);
+            Branch {
+                bitmap: branch.bitmap & !bit,
+                entries,
+            }
+        }
+    })
+}
+
+pub struct Hamt<K, V, S = RandomState> {
+    root: Arc<Branch<K, V>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> Hamt<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone> Hamt<K, V, S> {
+    // 更新で作った map は同じ hasher を引き継ぐ
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            root: Arc::new(Branch::empty()),
+            len: 0,
+            hasher,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = self.hasher.hash_one(key);
+        let mut branch = &*self.root;
+        let mut shift = 0;
+        loop {
+            let bit = Branch::<K, V>::bit(hash, shift);
+            if branch.bitmap & bit == 0 {
+                return None;
+            }
+            match &branch.entries[branch.position(bit)] {
+                Entry::Leaf(leaf) => {
+                    return (leaf.hash == hash && leaf.key == *key).then_some(&leaf.value)
+                }
+                Entry::Branch(child) => branch = child,
+                Entry::Collision(leaves) => {
+                    return leaves
+                        .iter()
+                        .find(|leaf| leaf.key == *key)
+                        .map(|leaf| &leaf.value)
+                }
+            }
+            shift += BITS;
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    // key に value を入れた新しい map を返す。self は変わらない
+    #[must_use]
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let hash = self.hasher.hash_one(&key);
+        let leaf = Arc::new(Leaf { hash, key, value });
+        let (root, replaced) = insert(&self.root, leaf, 0);
+        Self {
+            root: Arc::new(root),
+            len: if replaced { self.len } else { self.len + 1 },
+            hasher: self.hasher.clone(),
+        }
+    }
+
+    // key を除いた新しい map を返す。key がなければ self と同じ map を返す
+    #[must_use]
+    pub fn remove(&self, key: &K) -> Self {
+        let hash = self.hasher.hash_one(key);
+        match remove(&self.root, hash, key, 0) {
+            Some(root) => Self {
+                root: Arc::new(root),
+                len: self.len - 1,
+                hasher: self.hasher.clone(),
+            },
+            None => self.clone(),
+        }
+    }
+
+    // ハッシュの順に返すので、キーの順序には意味がない
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: self.root.entries.iter().rev().collect(),
+            collision: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    // ビットマップと子、葉のハッシュと位置、分岐の畳み込み、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Hash + Eq, V, S: BuildHasher>(
+            branch: &Branch<K, V>,
+            hasher: &S,
+            path: u64,
+            shift: u32,
+        ) -> usize {
+            assert_eq!(
+                branch.bitmap.count_ones() as usize,
+                branch.entries.len(),
+                "bitmap does not match entries"
+            );
+            let mut count = 0;
+            let bits = (0..32).filter(|i| branch.bitmap & (1 << i) != 0);
+            for (i, entry) in bits.zip(&branch.entries) {
+                // root からの経路で決まる、ハッシュの下位 shift + 5 ビット
+                let path = path | ((i as u64) << shift);
+                let mask = if shift + BITS >= u64::BITS {
+                    u64::MAX
+                } else {
+                    (1 << (shift + BITS)) - 1
+                };
+                let check_leaf = |leaf: &Leaf<K, V>| {
+                    assert_eq!(leaf.hash, hasher.hash_one(&leaf.key), "hash is stale");
+                    assert_eq!(leaf.hash & mask, path, "leaf is under a wrong entry");
+                };
+                match entry {
+                    Entry::Leaf(leaf) => {
+                        check_leaf(leaf);
+                        count += 1;
+                    }
+                    Entry::Branch(child) => {
+                        assert!(
+                            !matches!(child.entries.as_slice(), [] | [Entry::Leaf(_)]),
+                            "branch should have been collapsed"
+                        );
+                        count += check(child, hasher, path, shift + BITS);
+                    }
+                    Entry::Collision(leaves) => {
+                        assert!(leaves.len() >= 2, "collision has a single leaf");
+                        for leaf in leaves.iter() {
+                            check_leaf(leaf);
+                            assert_eq!(leaf.hash, leaves[0].hash, "collision has different hashes");
+                        }
+                        for (i, leaf) in leaves.iter().enumerate() {
+                            assert!(
+                                leaves[..i].iter().all(|other| other.key != leaf.key),
+                                "collision has duplicate keys"
+                            );
+                        }
+                        count += leaves.len();
+                    }
+                }
+            }
+            count
+        }
+        let count = check(&self.root, &self.hasher, 0, 0);
+        assert_eq!(self.len, count, "len is stale");
+    }
+}
+
+// root の Arc を増やすだけなので O(1)
+impl<K, V, S: Clone> Clone for Hamt<K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone + Default> Default for Hamt<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for Hamt<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            stack: self.root.entries.iter().rev().collect(),
+            collision: [].iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+// まだ開いていない子を積んでおく。衝突のノードは collision から順に返す
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Entry<K, V>>,
+    collision: slice::Iter<'a, Arc<Leaf<K, V>>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf = loop {
+            if let Some(leaf) = self.collision.next() {
+                break leaf;
+            }
+            match self.stack.pop()? {
+                Entry::Leaf(leaf) => break leaf,
+                Entry::Branch(branch) => self.stack.extend(branch.entries.iter().rev()),
+                Entry::Collision(leaves) => self.collision = leaves.iter(),
+            }
+        };
+        self.remaining -= 1;
+        Some((&leaf.key, &leaf.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher + Clone> IntoIterator for &'a Hamt<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Clone + Default> FromIterator<(K, V)> for Hamt<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Self::default(), |map, (key, value)| map.insert(key, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        hash::{BuildHasherDefault, Hasher},
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn versions_are_independent() {
+        let v0: Hamt<&str, i32> = Hamt::new();
+        let v1 = v0.insert("timeout", 30).insert("retries", 3);
+        let v2 = v1.insert("timeout", 60);
+        let v3 = v2.remove(&"retries");
+        for v in [&v0, &v1, &v2, &v3] {
+            v.check_invariants();
+        }
+        assert!(v0.is_empty());
+        assert_eq!(v1.get(&"timeout"), Some(&30));
+        assert_eq!(v2.get(&"timeout"), Some(&60));
+        assert_eq!(v2.get(&"retries"), Some(&3));
+        assert_eq!(v3.get(&"retries"), None);
+        assert_eq!((v1.len(), v2.len(), v3.len()), (2, 2, 1));
+        // 無いキーを消しても同じ map が返る
+        let v4 = v3.remove(&"missing");
+        assert!(Arc::ptr_eq(&v3.root, &v4.root));
+    }
+
+    // 部分木の Arc のアドレスを集める
+    fn nodes<K, V>(branch: &Arc<Branch<K, V>>, out: &mut HashSet<usize>) {
+        out.insert(Arc::as_ptr(branch) as usize);
+        for entry in &branch.entries {
+            match entry {
+                Entry::Leaf(leaf) => {
+                    out.insert(Arc::as_ptr(leaf) as usize);
+                }
+                Entry::Branch(child) => nodes(child, out),
+                Entry::Collision(leaves) => {
+                    out.insert(Arc::as_ptr(leaves) as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let old: Hamt<u64, u64> = (0..10_000).map(|k| (k, k)).collect();
+        let new = old.insert(10_000, 0).remove(&5);
+        let (mut before, mut after) = (HashSet::new(), HashSet::new());
+        nodes(&old.root, &mut before);
+        nodes(&new.root, &mut after);
+        // 新しく作ったのは、2 つの経路の分岐と 1 つの葉だけ
+        let created = after.difference(&before).count();
+        assert!(created <= 2 * 4 + 1, "created {} nodes", created);
+        assert_eq!(old.get(&5), Some(&5));
+        assert_eq!(new.get(&5), None);
+        assert_eq!(new.get(&10_000), Some(&0));
+    }
+
+    #[test]
+    fn snapshots_across_threads() {
+        let base: Hamt<u64, u64> = (0..1000).map(|k| (k, k)).collect();
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let base = base.clone();
+                std::thread::spawn(move || {
+                    let mine = (0..100).fold(base.clone(), |m, k| m.insert(k, t));
+                    mine.check_invariants();
+                    (0..100).all(|k| mine.get(&k) == Some(&t) && base.get(&k) == Some(&k))
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    // 下位 2 ビットしか使わないハッシュで、衝突のノードを作る
+    #[derive(Default)]
+    struct Weak(u64);
+
+    impl Hasher for Weak {
+        fn finish(&self) -> u64 {
+            self.0 & 3
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = self.0.wrapping_mul(31).wrapping_add(b as u64);
+            }
+        }
+    }
+
+    type WeakHamt = Hamt<u32, u32, BuildHasherDefault<Weak>>;
+
+    #[test]
+    fn collisions() {
+        let map: WeakHamt = (0..20).map(|k| (k, k * 10)).collect();
+        map.check_invariants();
+        for k in 0..20 {
+            assert_eq!(map.get(&k), Some(&(k * 10)));
+        }
+        let map = (0..20)
+            .filter(|k| k % 4 != 0)
+            .fold(map, |m, k| m.remove(&k));
+        map.check_invariants();
+        assert_eq!(map.len(), 5);
+        let mut keys: Vec<_> = map.iter().map(|(k, _)| *k).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![0, 4, 8, 12, 16]);
+    }
+
+    proptest! {
+        // 操作ごとに新しい版を作り、最後に全ての版が HashMap の写しと一致するか確かめる
+        #[test]
+        fn same_as_hash_map_random(
+            weak in any::<bool>(),
+            ops in prop::collection::vec((0..3u8, 0..64u32, 0..100u32), 0..200),
+        ) {
+            fn run<S: BuildHasher + Clone + Default>(ops: &[(u8, u32, u32)]) -> Result<(), TestCaseError> {
+                let mut versions = vec![(Hamt::<u32, u32, S>::default(), HashMap::new())];
+                for &(op, key, value) in ops {
+                    let (map, model) = versions.last().unwrap();
+                    let (map, mut model) = (map.clone(), model.clone());
+                    let map = match op {
+                        0 | 1 => {
+                            model.insert(key, value);
+                            map.insert(key, value)
+                        }
+                        _ => {
+                            model.remove(&key);
+                            map.remove(&key)
+                        }
+                    };
+                    map.check_invariants();
+                    versions.push((map, model));
+                }
+                for (map, model) in &versions {
+                    prop_assert_eq!(map.len(), model.len());
+                    let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+                    prop_assert_eq!(&actual, model);
+                    for key in 0..64 {
+                        prop_assert_eq!(map.get(&key), model.get(&key));
+                    }
+                }
+                Ok(())
+            }
+            if weak {
+                run::<BuildHasherDefault<Weak>>(&ops)?;
+            } else {
+                run::<RandomState>(&ops)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+// キーで優先度を変えられる優先度付きキュー
+// (キー, 優先度) を優先度の小さい順の二分ヒープに並べ、キーからヒープ上の位置を HashMap で引く
+// 要素を入れ替えるたびに、入れ替えた 2 つのキーの位置を書き直す
+// change_priority と remove はキーから位置を引き、そこから上げるか下げる。どちらも O(log n)
+// Handle を持ち回らなくても、グラフの頂点番号などのキーでそのまま優先度を変えられる
+use std::{collections::HashMap, hash::Hash, iter::FromIterator};
+
+#[derive(Debug, Clone)]
+pub struct IndexedPriorityQueue<K, P> {
+    heap: Vec<(K, P)>,
+    positions: HashMap<K, usize>,
+}
+
+impl<K: Hash + Eq + Clone, P: Ord> IndexedPriorityQueue<K, P> {
+    pub fn new() -> Self {
+        Self {
+            heap: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            heap: Vec::with_capacity(capacity),
+            positions: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    pub fn priority(&self, key: &K) -> Option<&P> {
+        self.positions.get(key).map(|&i| &self.heap[i].1)
+    }
+
+    // key がなければ入れる。あれば優先度を変え、前の優先度を返す
+    pub fn push(&mut self, key: K, priority: P) -> Option<P> {
+        if self.positions.contains_key(&key) {
+            return self.change_priority(&key, priority);
+        }
+        let i = self.heap.len();
+        self.positions.insert(key.clone(), i);
+        self.heap.push((key, priority));
+        self.sift_up(i);
+        None
+    }
+
+    // 優先度の最も小さい要素
+    pub fn peek_min(&self) -> Option<(&K, &P)> {
+        self.heap.first().map(|(key, priority)| (key, priority))
+    }
+
+    pub fn pop_min(&mut self) -> Option<(K, P)> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        Some(self.remove_at(0))
+    }
+
+    // key の優先度を変え、前の優先度を返す。key がなければ何もせず None
+    // 小さくしても大きくしてもよい
+    pub fn change_priority(&mut self, key: &K, priority: P) -> Option<P> {
+        let i = *self.positions.get(key)?;
+        let old = std::mem::replace(&mut self.heap[i].1, priority);
+        self.sift_up(i);
+        self.sift_down(i);
+        Some(old)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<P> {
+        let i = *self.positions.get(key)?;
+        Some(self.remove_at(i).1)
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+        self.positions.clear();
+    }
+
+    // ヒープの並びのまま返す。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &P)> + '_ {
+        self.heap.iter().map(|(key, priority)| (key, priority))
+    }
+
+    // 位置 i の要素を末尾と入れ替えて取り除き、入れ替えた要素を上げるか下げる
+    fn remove_at(&mut self, i: usize) -> (K, P) {
+        let last = self.heap.len() - 1;
+        self.swap(i, last);
+        let (key, priority) = self.heap.pop().expect("heap is not empty");
+        self.positions.remove(&key);
+        if i < self.heap.len() {
+            self.sift_up(i);
+            self.sift_down(i);
+        }
+        (key, priority)
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        self.heap.swap(a, b);
+        for i in [a, b] {
+            *self
+                .positions
+                .get_mut(&self.heap[i].0)
+                .expect("key in heap has a position") = i;
+        }
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[parent].1 <= self.heap[i].1 {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut top = i;
+            for child in [2 * i + 1, 2 * i + 2] {
+                if child < self.heap.len() && self.heap[child].1 < self.heap[top].1 {
+                    top = child;
+                }
+            }
+            if top == i {
+                break;
+            }
+            self.swap(i, top);
+            i = top;
+        }
+    }
+
+    // 親の優先度が子以下で、positions が全てのキーの位置を指しているか確かめる
+    pub fn check_invariants(&self) {
+        assert_eq!(
+            self.positions.len(),
+            self.heap.len(),
+            "positions and heap have different sizes"
+        );
+        for (i, (key, priority)) in self.heap.iter().enumerate() {
+            assert_eq!(self.positions.get(key), Some(&i), "position is stale");
+            if i > 0 {
+                assert!(
+                    self.heap[(i - 1) / 2].1 <= *priority,
+                    "child is smaller than its parent"
+                );
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, P: Ord> Default for IndexedPriorityQueue<K, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, P: Ord> FromIterator<(K, P)> for IndexedPriorityQueue<K, P> {
+    fn from_iter<I: IntoIterator<Item = (K, P)>>(iter: I) -> Self {
+        let mut queue = Self::new();
+        queue.extend(iter);
+        queue
+    }
+}
+
+impl<K: Hash + Eq + Clone, P: Ord> Extend<(K, P)> for IndexedPriorityQueue<K, P> {
+    fn extend<I: IntoIterator<Item = (K, P)>>(&mut self, iter: I) {
+        for (key, priority) in iter {
+            self.push(key, priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn keys() {
+        let mut queue: IndexedPriorityQueue<_, _> = vec![("a", 5), ("b", 3), ("c", 8), ("d", 1)]
+            .into_iter()
+            .collect();
+        queue.check_invariants();
+        assert_eq!(queue.peek_min(), Some((&"d", &1)));
+        assert_eq!(queue.change_priority(&"c", 0), Some(8));
+        assert_eq!(queue.change_priority(&"d", 9), Some(1));
+        assert_eq!(queue.change_priority(&"z", 0), None);
+        assert_eq!(queue.push("b", 4), Some(3));
+        queue.check_invariants();
+        assert_eq!(queue.remove(&"a"), Some(5));
+        assert_eq!(queue.remove(&"a"), None);
+        assert_eq!(queue.priority(&"b"), Some(&4));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop_min()).collect();
+        assert_eq!(order, vec![("c", 0), ("b", 4), ("d", 9)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn dijkstra() {
+        // (from, to, cost) の無向グラフ
+        let edges = [
+            (0, 1, 7),
+            (0, 2, 9),
+            (0, 5, 14),
+            (1, 2, 10),
+            (1, 3, 15),
+            (2, 3, 11),
+            (2, 5, 2),
+            (3, 4, 6),
+            (4, 5, 9),
+        ];
+        let mut adj = vec![Vec::new(); 6];
+        for &(from, to, cost) in &edges {
+            adj[from].push((to, cost));
+            adj[to].push((from, cost));
+        }
+
+        let mut dist = vec![u32::MAX; adj.len()];
+        let mut queue = IndexedPriorityQueue::new();
+        dist[0] = 0;
+        queue.push(0, 0);
+        while let Some((v, d)) = queue.pop_min() {
+            for &(to, cost) in &adj[v] {
+                if d + cost < dist[to] {
+                    dist[to] = d + cost;
+                    queue.push(to, d + cost);
+                }
+            }
+        }
+        assert_eq!(dist, vec![0, 7, 9, 20, 20, 11]);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(u8, i32),
+        Change(u8, i32),
+        Remove(u8),
+        Pop,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..30u8, -50..50i32).prop_map(|(k, p)| Op::Push(k, p)),
+            (0..30u8, -50..50i32).prop_map(|(k, p)| Op::Change(k, p)),
+            (0..30u8).prop_map(Op::Remove),
+            Just(Op::Pop),
+        ]
+    }
+
+    proptest! {
+        // キーから優先度への HashMap と比べる。同じ優先度のキーはどれを取り出してもよい
+        #[test]
+        fn matches_map(ops in prop::collection::vec(op(), 0..300)) {
+            let mut queue = IndexedPriorityQueue::new();
+            let mut expected = HashMap::new();
+            for op in ops {
+                match op {
+                    Op::Push(k, p) => prop_assert_eq!(queue.push(k, p), expected.insert(k, p)),
+                    Op::Change(k, p) => {
+                        let old = expected.get_mut(&k).map(|old| std::mem::replace(old, p));
+                        prop_assert_eq!(queue.change_priority(&k, p), old);
+                    }
+                    Op::Remove(k) => prop_assert_eq!(queue.remove(&k), expected.remove(&k)),
+                    Op::Pop => match queue.pop_min() {
+                        Some((k, p)) => {
+                            prop_assert_eq!(Some(p), expected.values().min().copied());
+                            prop_assert_eq!(expected.remove(&k), Some(p));
+                        }
+                        None => prop_assert!(expected.is_empty()),
+                    },
+                }
+                queue.check_invariants();
+                prop_assert_eq!(queue.len(), expected.len());
+                prop_assert_eq!(queue.peek_min().map(|(_, p)| *p), expected.values().min().copied());
+            }
+            for (k, p) in &expected {
+                prop_assert_eq!(queue.priority(k), Some(p));
+            }
+        }
+    }
+}
@@ -0,0 +1,427 @@
+// 区間木
+// 閉区間 [low, high] を (low, high) の順に並べた AVL 木で、各ノードに部分木の終点の最大値 (max_high) を持たせる
+// 回転と追加、削除の帰りに max_high も高さと一緒に求め直すので、操作は O(log n) のまま
+//
+// overlapping(low, high) は、max_high が low より小さい部分木には重なる区間がないので飛ばし、
+// 始点が high を超えたノードより後ろ (右) にも重なる区間はないので止める
+// 重なる区間が k 個なら O(k log n) で、始点の順に返す
+// stabbing(p) は p を含む区間で、[p, p] と重なる区間と同じ
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    low: K,
+    high: K,
+    value: V,
+    // この部分木の区間の終点の最大値
+    max_high: K,
+    height: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K: Ord + Clone, V> Node<K, V> {
+    fn new(low: K, high: K, value: V) -> Box<Self> {
+        Box::new(Self {
+            max_high: high.clone(),
+            low,
+            high,
+            value,
+            height: 1,
+            left: None,
+            right: None,
+        })
+    }
+
+    fn cmp_key(&self, low: &K, high: &K) -> Ordering {
+        (low, high).cmp(&(&self.low, &self.high))
+    }
+
+    // 正なら左が高い
+    fn balance(&self) -> isize {
+        height(&self.left) as isize - height(&self.right) as isize
+    }
+
+    // 子から高さと max_high を求め直す
+    fn update(&mut self) {
+        self.height = 1 + height(&self.left).max(height(&self.right));
+        let mut max_high = &self.high;
+        for child in [&self.left, &self.right].iter().copied().flatten() {
+            if child.max_high > *max_high {
+                max_high = &child.max_high;
+            }
+        }
+        self.max_high = max_high.clone();
+    }
+}
+
+fn height<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |n| n.height)
+}
+
+fn rotate_right<K: Ord + Clone, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut left = node.left.take().expect("rotate_right needs a left child");
+    node.left = left.right.take();
+    node.update();
+    left.right = Some(node);
+    left.update();
+    left
+}
+
+fn rotate_left<K: Ord + Clone, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut right = node.right.take().expect("rotate_left needs a right child");
+    node.right = right.left.take();
+    node.update();
+    right.left = Some(node);
+    right.update();
+    right
+}
+
+fn rebalance<K: Ord + Clone, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    node.update();
+    match node.balance() {
+        2 => {
+            if node.left.as_ref().map_or(0, |l| l.balance()) < 0 {
+                node.left = node.left.take().map(rotate_left);
+            }
+            rotate_right(node)
+        }
+        -2 => {
+            if node.right.as_ref().map_or(0, |r| r.balance()) > 0 {
+                node.right = node.right.take().map(rotate_right);
+            }
+            rotate_left(node)
+        }
+        _ => node,
+    }
+}
+
+fn insert<K: Ord + Clone, V>(
+    link: Link<K, V>,
+    low: K,
+    high: K,
+    value: V,
+) -> (Box<Node<K, V>>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (Node::new(low, high, value), None),
+    };
+    let old = match node.cmp_key(&low, &high) {
+        Ordering::Equal => Some(mem::replace(&mut node.value, value)),
+        Ordering::Less => {
+            let (left, old) = insert(node.left.take(), low, high, value);
+            node.left = Some(left);
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = insert(node.right.take(), low, high, value);
+            node.right = Some(right);
+            old
+        }
+    };
+    match old {
+        Some(old) => (node, Some(old)),
+        None => (rebalance(node), None),
+    }
+}
+
+fn remove<K: Ord + Clone, V>(link: Link<K, V>, low: &K, high: &K) -> (Link<K, V>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    let old = match node.cmp_key(low, high) {
+        Ordering::Less => {
+            let (left, old) = remove(node.left.take(), low, high);
+            node.left = left;
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = remove(node.right.take(), low, high);
+            node.right = right;
+            old
+        }
+        Ordering::Equal => {
+            let Node {
+                value, left, right, ..
+            } = *node;
+            let replaced = match (left, right) {
+                (None, child) | (child, None) => child,
+                (left, Some(right)) => {
+                    let (right, mut min) = remove_min(right);
+                    min.left = left;
+                    min.right = right;
+                    Some(rebalance(min))
+                }
+            };
+            return (replaced, Some(value));
+        }
+    };
+    match old {
+        Some(old) => (Some(rebalance(node)), Some(old)),
+        None => (Some(node), None),
+    }
+}
+
+fn remove_min<K: Ord + Clone, V>(mut node: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            node.left = left;
+            (Some(rebalance(node)), min)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IntervalTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K: Ord + Clone, V> IntervalTree<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn height(&self) -> usize {
+        height(&self.root)
+    }
+
+    pub fn get(&self, low: &K, high: &K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match node.cmp_key(low, high) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    // 閉区間 [low, high] に value を対応させる。同じ区間があれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, low: K, high: K, value: V) -> Option<V> {
+        assert!(low <= high, "interval is empty");
+        let (root, old) = insert(self.root.take(), low, high, value);
+        self.root = Some(root);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, low: &K, high: &K) -> Option<V> {
+        let (root, old) = remove(self.root.take(), low, high);
+        self.root = root;
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    // [low, high] と重なる区間を始点の順に返す
+    pub fn overlapping<'a>(&'a self, low: &'a K, high: &'a K) -> Overlapping<'a, K, V> {
+        let mut iter = Overlapping {
+            stack: Vec::with_capacity(self.height()),
+            low,
+            high,
+        };
+        if low <= high {
+            iter.push_left(self.root.as_deref());
+        }
+        iter
+    }
+
+    // point を含む区間を始点の順に返す
+    pub fn stabbing<'a>(&'a self, point: &'a K) -> Overlapping<'a, K, V> {
+        self.overlapping(point, point)
+    }
+
+    // 全ての区間を始点の順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &K, &V)> + '_ {
+        let mut stack = Vec::with_capacity(self.height());
+        let mut current = self.root.as_deref();
+        std::iter::from_fn(move || {
+            while let Some(node) = current {
+                stack.push(node);
+                current = node.left.as_deref();
+            }
+            let node = stack.pop()?;
+            current = node.right.as_deref();
+            Some((&node.low, &node.high, &node.value))
+        })
+    }
+
+    // 順序、高さ、max_high、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Clone, V>(node: &Node<K, V>, count: &mut usize) -> usize {
+            *count += 1;
+            assert!(node.low <= node.high, "interval is empty");
+            let mut max_high = &node.high;
+            let mut heights = [0; 2];
+            for (i, child) in [&node.left, &node.right].iter().enumerate() {
+                if let Some(child) = child.as_deref() {
+                    let order = (&child.low, &child.high).cmp(&(&node.low, &node.high));
+                    let expected = if i == 0 {
+                        Ordering::Less
+                    } else {
+                        Ordering::Greater
+                    };
+                    assert_eq!(order, expected, "intervals are out of order");
+                    heights[i] = check(child, count);
+                    max_high = max_high.max(&child.max_high);
+                }
+            }
+            assert!(
+                (heights[0] as isize - heights[1] as isize).abs() <= 1,
+                "node is unbalanced"
+            );
+            assert_eq!(
+                node.height,
+                1 + heights[0].max(heights[1]),
+                "height is stale"
+            );
+            assert!(node.max_high == *max_high, "max_high is stale");
+            node.height
+        }
+        let mut count = 0;
+        if let Some(root) = self.root.as_deref() {
+            check(root, &mut count);
+        }
+        assert_eq!(count, self.len, "len does not match the nodes");
+    }
+}
+
+impl<K: Ord + Clone, V> Default for IntervalTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, V> FromIterator<(K, K, V)> for IntervalTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (low, high, value) in iter {
+            tree.insert(low, high, value);
+        }
+        tree
+    }
+}
+
+pub struct Overlapping<'a, K, V> {
+    // 始点の順に、まだ返していないノード。左の部分木は全て積んである
+    stack: Vec<&'a Node<K, V>>,
+    low: &'a K,
+    high: &'a K,
+}
+
+impl<'a, K: Ord, V> Overlapping<'a, K, V> {
+    // max_high が low より小さい部分木は積まない
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            if node.max_high < *self.low {
+                return;
+            }
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord, V> Iterator for Overlapping<'a, K, V> {
+    type Item = (&'a K, &'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            // ここから後ろの区間は全て始点が high より大きい
+            if node.low > *self.high {
+                self.stack.clear();
+                return None;
+            }
+            self.push_left(node.right.as_deref());
+            if node.high >= *self.low {
+                return Some((&node.low, &node.high, &node.value));
+            }
+        }
+        None
+    }
+}
+
+impl<K: Ord, V> FusedIterator for Overlapping<'_, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn queries() {
+        let mut tree: IntervalTree<i32, &str> =
+            vec![(5, 10, "a"), (1, 3, "b"), (8, 20, "c"), (15, 16, "d")]
+                .into_iter()
+                .collect();
+        let found: Vec<_> = tree.overlapping(&9, &15).map(|(_, _, v)| *v).collect();
+        assert_eq!(found, vec!["a", "c", "d"]);
+        assert_eq!(tree.overlapping(&4, &4).count(), 0);
+        let found: Vec<_> = tree.stabbing(&3).map(|(l, h, _)| (*l, *h)).collect();
+        assert_eq!(found, vec![(1, 3)]);
+        assert_eq!(tree.insert(5, 10, "e"), Some("a"));
+        assert_eq!(tree.remove(&8, &20), Some("c"));
+        assert_eq!(tree.remove(&8, &20), None);
+        let found: Vec<_> = tree.stabbing(&15).map(|(_, _, v)| *v).collect();
+        assert_eq!(found, vec!["d"]);
+        assert_eq!(tree.get(&5, &10), Some(&"e"));
+        tree.check_invariants();
+    }
+
+    proptest! {
+        // 重なる区間が、全ての区間を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            intervals in prop::collection::vec((0..100i32, 0..20i32), 0..80),
+            removes in prop::collection::vec(any::<prop::sample::Index>(), 0..20),
+            queries in prop::collection::vec((0..120i32, 0..30i32), 1..10),
+        ) {
+            let mut model: Vec<(i32, i32)> = intervals.iter().map(|&(low, len)| (low, low + len)).collect();
+            model.sort_unstable();
+            model.dedup();
+            let mut tree: IntervalTree<i32, ()> = model.iter().map(|&(l, h)| (l, h, ())).collect();
+            tree.check_invariants();
+            for index in removes {
+                if model.is_empty() {
+                    break;
+                }
+                let (l, h) = model.remove(index.index(model.len()));
+                prop_assert_eq!(tree.remove(&l, &h), Some(()));
+                tree.check_invariants();
+            }
+            prop_assert_eq!(tree.len(), model.len());
+            for (low, len) in queries {
+                let high = low + len;
+                let expected: Vec<_> = model.iter().copied().filter(|&(l, h)| l <= high && low <= h).collect();
+                let found: Vec<_> = tree.overlapping(&low, &high).map(|(l, h, _)| (*l, *h)).collect();
+                prop_assert_eq!(found, expected);
+                let expected: Vec<_> = model.iter().copied().filter(|&(l, h)| l <= low && low <= h).collect();
+                let found: Vec<_> = tree.stabbing(&low).map(|(l, h, _)| (*l, *h)).collect();
+                prop_assert_eq!(found, expected);
+            }
+        }
+    }
+}
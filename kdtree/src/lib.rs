@@ -0,0 +1,357 @@
+// k-d tree
+// K 次元の点を、深さ d のノードでは d % K 番目の座標で中央値を境に左右へ分けていく二分木
+// 点の列そのものを木にする。[lo, hi) の根は中央の mid で、左の部分木は [lo, mid)、右は [mid + 1, hi)
+// 左の点の座標は根以下、右の点の座標は根以上になる
+// まとめて作るだけで O(n log n) (中央値は select_nth_unstable で求める) になり、高さは log n に収まる
+//
+// 最近傍は、まず query のある側へ降り、境界までの距離が今の候補より近いときだけ反対側も調べる
+// 範囲 (軸に平行な箱) は、箱が境界のどちら側に掛かるかで降りる方を決める
+// 距離はユークリッド距離の 2 乗を f64 で求める。大きな i64 の座標では丸めが入る
+//
+// 作った後に点を足したり消したりはできない。変えたら作り直す
+use std::{cmp::Ordering, collections::BinaryHeap, iter::FromIterator};
+
+pub use spatial::{Coordinate, Rect, SpatialIndex};
+
+fn compare<T: Coordinate>(a: &T, b: &T) -> Ordering {
+    a.partial_cmp(b).expect("coordinate is NaN")
+}
+
+// 2 点のユークリッド距離の 2 乗
+fn distance<T: Coordinate, const K: usize>(a: &[T; K], b: &[T; K]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(&a, &b)| {
+            let d = a.to_f64() - b.to_f64();
+            d * d
+        })
+        .sum()
+}
+
+// 近い方から k 個の候補。BinaryHeap の先頭が一番遠い
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    distance: f64,
+    index: usize,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+#[derive(Debug, Clone)]
+pub struct KdTree<T, V, const K: usize> {
+    points: Vec<([T; K], V)>,
+}
+
+fn build<T: Coordinate, V, const K: usize>(points: &mut [([T; K], V)], depth: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+    let mid = points.len() / 2;
+    let axis = depth % K;
+    points.select_nth_unstable_by(mid, |a, b| compare(&a.0[axis], &b.0[axis]));
+    let (left, right) = points.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+impl<T: Coordinate, const K: usize> KdTree<T, usize, K> {
+    // 点の列から作る。値は列での位置
+    pub fn from_points(points: &[[T; K]]) -> Self {
+        points.iter().copied().zip(0..).collect()
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> KdTree<T, V, K> {
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // 全ての点を木の中での順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&[T; K], &V)> + '_ {
+        self.points.iter().map(|(p, v)| (p, v))
+    }
+
+    // query に一番近い点と、その距離の 2 乗
+    pub fn nearest(&self, query: &[T; K]) -> Option<(&[T; K], &V, f64)> {
+        self.k_nearest(query, 1).into_iter().next()
+    }
+
+    // query に近い順に k 個の点と、その距離の 2 乗。同じ距離の点はどれが選ばれるか決めない
+    pub fn k_nearest(&self, query: &[T; K], k: usize) -> Vec<(&[T; K], &V, f64)> {
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search(0, self.len(), 0, query, k, &mut heap);
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|c| {
+                let (p, v) = &self.points[c.index];
+                (p, v, c.distance)
+            })
+            .collect()
+    }
+
+    fn search(
+        &self,
+        lo: usize,
+        hi: usize,
+        depth: usize,
+        query: &[T; K],
+        k: usize,
+        heap: &mut BinaryHeap<Candidate>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let point = &self.points[mid].0;
+        let candidate = Candidate {
+            distance: distance(point, query),
+            index: mid,
+        };
+        if heap.len() < k {
+            heap.push(candidate);
+        } else if heap.peek().is_some_and(|far| candidate < *far) {
+            heap.pop();
+            heap.push(candidate);
+        }
+        let axis = depth % K;
+        let diff = query[axis].to_f64() - point[axis].to_f64();
+        let (near, far) = if diff < 0.0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, depth + 1, query, k, heap);
+        // 境界までの距離が今の候補より遠ければ、反対側にもっと近い点はない
+        if heap.len() < k || heap.peek().is_some_and(|c| diff * diff < c.distance) {
+            self.search(far.0, far.1, depth + 1, query, k, heap);
+        }
+    }
+
+    // center からの距離が radius 以下の点と、その距離の 2 乗。順序は決めない
+    pub fn within(&self, center: &[T; K], radius: f64) -> Vec<(&[T; K], &V, f64)> {
+        let radius = radius * radius;
+        let mut found = Vec::new();
+        let mut stack = vec![(0, self.len(), 0)];
+        while let Some((lo, hi, depth)) = stack.pop() {
+            if lo >= hi {
+                continue;
+            }
+            let mid = (lo + hi) / 2;
+            let (point, value) = &self.points[mid];
+            let d = distance(point, center);
+            if d <= radius {
+                found.push((point, value, d));
+            }
+            let axis = depth % K;
+            let diff = center[axis].to_f64() - point[axis].to_f64();
+            if diff <= 0.0 || diff * diff <= radius {
+                stack.push((lo, mid, depth + 1));
+            }
+            if diff >= 0.0 || diff * diff <= radius {
+                stack.push((mid + 1, hi, depth + 1));
+            }
+        }
+        found
+    }
+
+    // 各座標が min 以上 max 以下の点。順序は決めない
+    pub fn range<'a>(
+        &'a self,
+        min: &[T; K],
+        max: &[T; K],
+    ) -> impl Iterator<Item = (&'a [T; K], &'a V)> + 'a {
+        let (min, max) = (*min, *max);
+        let mut stack = vec![(0, self.len(), 0)];
+        std::iter::from_fn(move || {
+            while let Some((lo, hi, depth)) = stack.pop() {
+                if lo >= hi {
+                    continue;
+                }
+                let mid = (lo + hi) / 2;
+                let (point, value) = &self.points[mid];
+                let axis = depth % K;
+                if min[axis] <= point[axis] {
+                    stack.push((lo, mid, depth + 1));
+                }
+                if point[axis] <= max[axis] {
+                    stack.push((mid + 1, hi, depth + 1));
+                }
+                if (0..K).all(|i| min[i] <= point[i] && point[i] <= max[i]) {
+                    return Some((point, value));
+                }
+            }
+            None
+        })
+    }
+
+    // 左の部分木の座標が根以下、右が根以上か確かめる
+    pub fn check_invariants(&self) {
+        fn check<T: Coordinate, V, const K: usize>(
+            points: &[([T; K], V)],
+            lo: usize,
+            hi: usize,
+            depth: usize,
+        ) {
+            if lo >= hi {
+                return;
+            }
+            let mid = (lo + hi) / 2;
+            let axis = depth % K;
+            let pivot = points[mid].0[axis];
+            assert!(
+                points[lo..mid].iter().all(|(p, _)| p[axis] <= pivot),
+                "left point is beyond the split"
+            );
+            assert!(
+                points[mid + 1..hi].iter().all(|(p, _)| p[axis] >= pivot),
+                "right point is before the split"
+            );
+            check(points, lo, mid, depth + 1);
+            check(points, mid + 1, hi, depth + 1);
+        }
+        check(&self.points, 0, self.len(), 0);
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> From<Vec<([T; K], V)>> for KdTree<T, V, K> {
+    fn from(mut points: Vec<([T; K], V)>) -> Self {
+        assert!(K > 0, "dimension must be positive");
+        build(&mut points, 0);
+        Self { points }
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> FromIterator<([T; K], V)> for KdTree<T, V, K> {
+    fn from_iter<I: IntoIterator<Item = ([T; K], V)>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> SpatialIndex<T, K> for KdTree<T, V, K> {
+    type Value = V;
+
+    fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    fn query_box(&self, window: &Rect<T, K>) -> Vec<(Rect<T, K>, &V)> {
+        self.range(window.min(), window.max())
+            .map(|(point, value)| (Rect::point(*point), value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn queries() {
+        let points = [
+            [2.0, 3.0],
+            [5.0, 4.0],
+            [9.0, 6.0],
+            [4.0, 7.0],
+            [8.0, 1.0],
+            [7.0, 2.0],
+        ];
+        let tree = KdTree::from_points(&points);
+        tree.check_invariants();
+        let (point, &index, distance) = tree.nearest(&[9.0, 2.0]).unwrap();
+        assert_eq!((*point, index, distance), ([8.0, 1.0], 4, 2.0));
+        let near: Vec<_> = tree
+            .k_nearest(&[5.0, 5.0], 2)
+            .into_iter()
+            .map(|(_, &i, _)| i)
+            .collect();
+        assert_eq!(near, vec![1, 3]);
+        let mut inside: Vec<_> = tree
+            .range(&[3.0, 1.0], &[8.0, 4.0])
+            .map(|(_, &i)| i)
+            .collect();
+        inside.sort_unstable();
+        assert_eq!(inside, vec![1, 4, 5]);
+        let mut close: Vec<_> = tree
+            .within(&[7.0, 2.0], 1.5)
+            .into_iter()
+            .map(|(_, &i, _)| i)
+            .collect();
+        close.sort_unstable();
+        assert_eq!(close, vec![4, 5]);
+        assert!(KdTree::<f64, (), 3>::from(vec![])
+            .nearest(&[0.0; 3])
+            .is_none());
+    }
+
+    proptest! {
+        // 近い点、箱、円の中の点が、全ての点を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            points in prop::collection::vec([-50..50i32, -50..50i32, -50..50i32], 0..120),
+            queries in prop::collection::vec(([-60..60i32, -60..60i32, -60..60i32], [0..30i32, 0..30i32, 0..30i32], 0..10usize), 1..10),
+        ) {
+            let tree = KdTree::from_points(&points);
+            tree.check_invariants();
+            prop_assert_eq!(tree.len(), points.len());
+            let model: Vec<_> = points.iter().enumerate().map(|(i, p)| (Rect::point(*p), i)).collect();
+            for (query, size, k) in queries {
+                let mut distances: Vec<f64> = points.iter().map(|p| distance(p, &query)).collect();
+                distances.sort_by(f64::total_cmp);
+                let found: Vec<f64> = tree.k_nearest(&query, k).into_iter().map(|(_, _, d)| d).collect();
+                prop_assert_eq!(&found[..], &distances[..k.min(points.len())]);
+
+                let max = [query[0] + size[0], query[1] + size[1], query[2] + size[2]];
+                let mut expected: Vec<usize> = (0..points.len())
+                    .filter(|&i| (0..3).all(|a| query[a] <= points[i][a] && points[i][a] <= max[a]))
+                    .collect();
+                let mut found: Vec<usize> = tree.range(&query, &max).map(|(_, &i)| i).collect();
+                expected.sort_unstable();
+                found.sort_unstable();
+                prop_assert_eq!(found, expected);
+
+                // 全ての点を調べる Vec と同じになる
+                let window = Rect::new(query, max);
+                let mut expected = model.query_box(&window);
+                let mut found = tree.query_box(&window);
+                expected.sort_by_key(|&(_, &i)| i);
+                found.sort_by_key(|&(_, &i)| i);
+                prop_assert_eq!(found, expected);
+
+                let radius = size[0] as f64;
+                let mut expected: Vec<usize> = (0..points.len())
+                    .filter(|&i| distance(&points[i], &query) <= radius * radius)
+                    .collect();
+                let mut found: Vec<usize> = tree.within(&query, radius).into_iter().map(|(_, &i, _)| i).collect();
+                expected.sort_unstable();
+                found.sort_unstable();
+                prop_assert_eq!(found, expected);
+            }
+        }
+    }
+}
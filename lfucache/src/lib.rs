@@ -0,0 +1,696 @@
+// 使った回数の最も少ないものから追い出すキャッシュ (LFU)
+// 使った回数ごとに bucket を作り、bucket を回数の少ない順の双方向リストに並べる
+// bucket の中では、その回数になった順の双方向リストに要素を並べる。回数が同じなら、長く使われていない方から追い出す
+// 要素を使うと、今の bucket から外して回数が 1 つ多い bucket の先頭に移す。その bucket がなければ隣に作り、空になった bucket は外す
+// 追い出すときは、最も回数の少ない bucket の末尾から取る。どの操作も O(1)
+//
+// 要素と bucket は lrucache と同じく Vec に置き、前後を位置で持つ。空いた位置は free に積んで使い回す
+// 要素ごとに重さを持たせられる。容量は重さの合計で、put_weighted で重さを指定しなければ 1 つ 1 になる
+use std::{collections::HashMap, hash::Hash, iter::FusedIterator};
+
+pub use cache::Cache;
+
+#[derive(Debug, Clone)]
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    weight: usize,
+    bucket: usize,
+    // 同じ bucket の中で 1 つ新しい方
+    prev: Option<usize>,
+    // 同じ bucket の中で 1 つ古い方
+    next: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    // 使った回数。入れたときが 1
+    frequency: u64,
+    // その回数になったのが最も新しい要素
+    head: usize,
+    // その回数になったのが最も古い要素
+    tail: usize,
+    // 回数が 1 つ前に少ない bucket
+    lower: Option<usize>,
+    // 回数が 1 つ前に多い bucket
+    higher: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LfuCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Vec<Option<Entry<K, V>>>,
+    free: Vec<usize>,
+    buckets: Vec<Option<Bucket>>,
+    free_buckets: Vec<usize>,
+    // 回数の最も少ない bucket
+    lowest: Option<usize>,
+    // 回数の最も多い bucket
+    highest: Option<usize>,
+    capacity: usize,
+    weight: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> LfuCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self {
+            map: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            buckets: Vec::new(),
+            free_buckets: Vec::new(),
+            lowest: None,
+            highest: None,
+            capacity,
+            weight: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // 入っている要素の重さの合計
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    // key を使った回数。入れたときが 1 で、get と置き換えで増える
+    pub fn frequency(&self, key: &K) -> Option<u64> {
+        self.map
+            .get(key)
+            .map(|&i| self.bucket(self.entry(i).bucket).frequency)
+    }
+
+    fn entry(&self, i: usize) -> &Entry<K, V> {
+        self.entries[i].as_ref().expect("linked slot is occupied")
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut Entry<K, V> {
+        self.entries[i].as_mut().expect("linked slot is occupied")
+    }
+
+    fn bucket(&self, b: usize) -> &Bucket {
+        self.buckets[b].as_ref().expect("linked bucket is occupied")
+    }
+
+    fn bucket_mut(&mut self, b: usize) -> &mut Bucket {
+        self.buckets[b].as_mut().expect("linked bucket is occupied")
+    }
+
+    // 要素 i だけを入れた frequency の bucket を、lower と higher の間に作る
+    fn new_bucket(
+        &mut self,
+        i: usize,
+        frequency: u64,
+        lower: Option<usize>,
+        higher: Option<usize>,
+    ) {
+        let bucket = Bucket {
+            frequency,
+            head: i,
+            tail: i,
+            lower,
+            higher,
+        };
+        let b = match self.free_buckets.pop() {
+            Some(b) => {
+                self.buckets[b] = Some(bucket);
+                b
+            }
+            None => {
+                self.buckets.push(Some(bucket));
+                self.buckets.len() - 1
+            }
+        };
+        match lower {
+            Some(l) => self.bucket_mut(l).higher = Some(b),
+            None => self.lowest = Some(b),
+        }
+        match higher {
+            Some(h) => self.bucket_mut(h).lower = Some(b),
+            None => self.highest = Some(b),
+        }
+        let entry = self.entry_mut(i);
+        entry.bucket = b;
+        entry.prev = None;
+        entry.next = None;
+    }
+
+    // 空になった bucket をリストから外し、位置を空ける
+    fn remove_bucket(&mut self, b: usize) {
+        let bucket = self.buckets[b].take().expect("linked bucket is occupied");
+        self.free_buckets.push(b);
+        match bucket.lower {
+            Some(l) => self.bucket_mut(l).higher = bucket.higher,
+            None => self.lowest = bucket.higher,
+        }
+        match bucket.higher {
+            Some(h) => self.bucket_mut(h).lower = bucket.lower,
+            None => self.highest = bucket.lower,
+        }
+    }
+
+    // 要素を bucket から外す。bucket が空になれば bucket も外す
+    fn detach(&mut self, i: usize) {
+        let (b, prev, next) = {
+            let entry = self.entry(i);
+            (entry.bucket, entry.prev, entry.next)
+        };
+        if prev.is_none() && next.is_none() {
+            self.remove_bucket(b);
+            return;
+        }
+        match prev {
+            Some(p) => self.entry_mut(p).next = next,
+            None => self.bucket_mut(b).head = next.expect("bucket has another entry"),
+        }
+        match next {
+            Some(n) => self.entry_mut(n).prev = prev,
+            None => self.bucket_mut(b).tail = prev.expect("bucket has another entry"),
+        }
+    }
+
+    // 要素を bucket の先頭に置く
+    fn attach_front(&mut self, i: usize, b: usize) {
+        let head = self.bucket(b).head;
+        {
+            let entry = self.entry_mut(i);
+            entry.bucket = b;
+            entry.prev = None;
+            entry.next = Some(head);
+        }
+        self.entry_mut(head).prev = Some(i);
+        self.bucket_mut(b).head = i;
+    }
+
+    // 使った回数を 1 増やし、1 つ多い bucket の先頭に移す
+    fn touch(&mut self, i: usize) {
+        let b = self.entry(i).bucket;
+        let (frequency, lower, higher) = {
+            let bucket = self.bucket(b);
+            (bucket.frequency + 1, bucket.lower, bucket.higher)
+        };
+        match higher {
+            Some(h) if self.bucket(h).frequency == frequency => {
+                self.detach(i);
+                self.attach_front(i, h);
+            }
+            _ => {
+                // b から外してから新しい bucket を b の隣に作る。b が空になれば、b のあった所に作る
+                self.detach(i);
+                let lower = if self.buckets[b].is_some() {
+                    Some(b)
+                } else {
+                    lower
+                };
+                self.new_bucket(i, frequency, lower, higher);
+            }
+        }
+    }
+
+    // 回数 1 の bucket の先頭に置く
+    fn attach_new(&mut self, i: usize) {
+        match self.lowest {
+            Some(l) if self.bucket(l).frequency == 1 => self.attach_front(i, l),
+            lowest => self.new_bucket(i, 1, None, lowest),
+        }
+    }
+
+    // bucket と map から外し、位置を空ける
+    fn take(&mut self, i: usize) -> (K, V) {
+        self.detach(i);
+        let entry = self.entries[i].take().expect("linked slot is occupied");
+        self.free.push(i);
+        self.map.remove(&entry.key);
+        self.weight -= entry.weight;
+        (entry.key, entry.value)
+    }
+
+    // 追い出す順に位置を返す。回数の少ない bucket から、bucket の中では古い方から
+    fn victims(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut current = self.lowest.map(|b| self.bucket(b).tail);
+        std::iter::from_fn(move || {
+            let i = current?;
+            let entry = self.entry(i);
+            current = match entry.prev {
+                Some(p) => Some(p),
+                None => self
+                    .bucket(entry.bucket)
+                    .higher
+                    .map(|h| self.bucket(h).tail),
+            };
+            Some(i)
+        })
+    }
+
+    // 使ったことにして返す
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = *self.map.get(key)?;
+        self.touch(i);
+        Some(&mut self.entry_mut(i).value)
+    }
+
+    // 使ったことにせずに返す
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&i| &self.entry(i).value)
+    }
+
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = *self.map.get(key)?;
+        Some(&mut self.entry_mut(i).value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    // 重さ 1 で入れる。取り除いたものを返す
+    pub fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        self.put_weighted(key, value, 1)
+    }
+
+    // 重さ weight で入れ、このために取り除いたものを返す
+    // 同じキーがあれば、置き換えも使ったことにして回数を増やし、古い値を (key, 古い値) として先に返す
+    // 続けて、入れたもの以外から追い出す順に追い出したものを返す。入れたばかりのものは回数 1 なので、追い出すと入れる意味がない
+    // 容量より重いものは入れずに、そのまま返す
+    pub fn put_weighted(&mut self, key: K, value: V, weight: usize) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        if weight > self.capacity {
+            if let Some(old) = self.remove(&key) {
+                removed.push((key.clone(), old));
+            }
+            removed.push((key, value));
+            return removed;
+        }
+        let i = match self.map.get(&key) {
+            Some(&i) => {
+                let entry = self.entry_mut(i);
+                let old = std::mem::replace(&mut entry.value, value);
+                let old_weight = std::mem::replace(&mut entry.weight, weight);
+                self.weight = self.weight - old_weight + weight;
+                self.touch(i);
+                removed.push((key, old));
+                i
+            }
+            None => {
+                let entry = Entry {
+                    key: key.clone(),
+                    value,
+                    weight,
+                    bucket: usize::MAX,
+                    prev: None,
+                    next: None,
+                };
+                let i = match self.free.pop() {
+                    Some(i) => {
+                        self.entries[i] = Some(entry);
+                        i
+                    }
+                    None => {
+                        self.entries.push(Some(entry));
+                        self.entries.len() - 1
+                    }
+                };
+                self.map.insert(key, i);
+                self.attach_new(i);
+                self.weight += weight;
+                i
+            }
+        };
+        // 入れたものだけなら容量に収まるので、飛ばしていけば必ず収まる
+        while self.weight > self.capacity {
+            let victim = self
+                .victims()
+                .find(|&v| v != i)
+                .expect("entries other than the new one are left");
+            removed.push(self.take(victim));
+        }
+        removed
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = *self.map.get(key)?;
+        Some(self.take(i).1)
+    }
+
+    // 使った回数の最も少ないものを取り除く。同じ回数なら最も長く使われていないもの
+    pub fn pop_lfu(&mut self) -> Option<(K, V)> {
+        let victim = self.victims().next()?;
+        Some(self.take(victim))
+    }
+
+    // 追い出す順に見て、evictable が true を返した最初のものを取り除く
+    pub fn pop_lfu_where<F: FnMut(&K, &V) -> bool>(&mut self, mut evictable: F) -> Option<(K, V)> {
+        let victim = self.victims().find(|&i| {
+            let entry = self.entry(i);
+            evictable(&entry.key, &entry.value)
+        })?;
+        Some(self.take(victim))
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.buckets.clear();
+        self.free_buckets.clear();
+        self.lowest = None;
+        self.highest = None;
+        self.weight = 0;
+    }
+
+    // 追い出す順の逆に返す。使った回数の多い方から、同じ回数なら最近その回数になった方から
+    // rev で追い出す順になる
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            cache: self,
+            front: self.highest.map(|b| self.bucket(b).head),
+            back: self.lowest.map(|b| self.bucket(b).tail),
+            remaining: self.len(),
+        }
+    }
+
+    // bucket が回数の少ない順に空でなく並び、要素の前後と bucket が合い、map と重さの合計が合うか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        let mut weight = 0;
+        let mut buckets = 0;
+        let mut lower: Option<usize> = None;
+        let mut current = self.lowest;
+        while let Some(b) = current {
+            let bucket = self.bucket(b);
+            assert_eq!(bucket.lower, lower, "lower is stale");
+            if let Some(l) = lower {
+                assert!(
+                    self.bucket(l).frequency < bucket.frequency,
+                    "buckets are not in frequency order"
+                );
+            }
+            assert!(bucket.frequency > 0, "frequency starts at 1");
+            let mut prev = None;
+            let mut i = Some(bucket.head);
+            while let Some(e) = i {
+                let entry = self.entry(e);
+                assert_eq!(entry.bucket, b, "bucket is stale");
+                assert_eq!(entry.prev, prev, "prev is stale");
+                assert_eq!(self.map.get(&entry.key), Some(&e), "map is stale");
+                count += 1;
+                weight += entry.weight;
+                prev = Some(e);
+                i = entry.next;
+            }
+            assert_eq!(Some(bucket.tail), prev, "tail is stale");
+            buckets += 1;
+            lower = Some(b);
+            current = bucket.higher;
+        }
+        assert_eq!(self.highest, lower, "highest is stale");
+        assert_eq!(count, self.map.len(), "map has entries not in the buckets");
+        assert_eq!(
+            count + self.free.len(),
+            self.entries.len(),
+            "slot is leaked"
+        );
+        assert_eq!(
+            buckets + self.free_buckets.len(),
+            self.buckets.len(),
+            "bucket is leaked"
+        );
+        assert_eq!(weight, self.weight, "weight is stale");
+        assert!(self.weight <= self.capacity, "weight exceeds capacity");
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    cache: &'a LfuCache<K, V>,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a, K: Hash + Eq + Clone, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let cache = self.cache;
+        let entry = cache.entry(self.front?);
+        self.front = match entry.next {
+            Some(n) => Some(n),
+            None => cache
+                .bucket(entry.bucket)
+                .lower
+                .map(|l| cache.bucket(l).head),
+        };
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let cache = self.cache;
+        let entry = cache.entry(self.back?);
+        self.back = match entry.prev {
+            Some(p) => Some(p),
+            None => cache
+                .bucket(entry.bucket)
+                .higher
+                .map(|h| cache.bucket(h).tail),
+        };
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Hash + Eq + Clone, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for LfuCache<K, V> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        LfuCache::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        LfuCache::get_mut(self, key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        LfuCache::peek(self, key)
+    }
+
+    fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        LfuCache::peek_mut(self, key)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        LfuCache::put(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        LfuCache::remove(self, key)
+    }
+
+    fn pop_victim(&mut self, evictable: &mut dyn FnMut(&K, &V) -> bool) -> Option<(K, V)> {
+        self.pop_lfu_where(evictable)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn frequency() {
+        let mut cache = LfuCache::new(3);
+        assert!(cache.put("a", 1).is_empty());
+        assert!(cache.put("b", 2).is_empty());
+        assert!(cache.put("c", 3).is_empty());
+        cache.get(&"a");
+        cache.get(&"a");
+        cache.get(&"b");
+        assert_eq!(cache.frequency(&"a"), Some(3));
+        // 回数の最も少ない c が追い出される
+        assert_eq!(cache.put("d", 4), vec![("c", 3)]);
+        // 入れたばかりの d は回数 1 だが、次に入れるものでは追い出される
+        assert_eq!(cache.put("e", 5), vec![("d", 4)]);
+        // peek では回数は変わらない
+        assert_eq!(cache.peek(&"e"), Some(&5));
+        assert_eq!(cache.frequency(&"e"), Some(1));
+        // 置き換えも使ったことになる
+        assert_eq!(cache.put("e", 50), vec![("e", 5)]);
+        let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(order, vec![("a", 1), ("e", 50), ("b", 2)]);
+        let victims: Vec<_> = cache.iter().rev().map(|(k, _)| *k).collect();
+        assert_eq!(victims, vec!["b", "e", "a"]);
+        cache.check_invariants();
+
+        // b は飛ばして、次に追い出す e を取り除く
+        assert_eq!(cache.pop_lfu_where(|k, _| *k != "b"), Some(("e", 50)));
+        assert_eq!(cache.pop_lfu(), Some(("b", 2)));
+        assert_eq!(cache.len(), 1);
+        cache.check_invariants();
+    }
+
+    // 一度だけ読むものが続いても、何度も使うものは残る
+    #[test]
+    fn scan_resistant() {
+        let mut cache = LfuCache::new(4);
+        for _ in 0..3 {
+            for k in 0..2 {
+                cache.put(k, ());
+            }
+        }
+        for k in 100..200 {
+            cache.put(k, ());
+        }
+        assert!(cache.contains_key(&0));
+        assert!(cache.contains_key(&1));
+        assert_eq!(cache.len(), 4);
+        cache.check_invariants();
+    }
+
+    #[test]
+    fn weights() {
+        let mut cache = LfuCache::new(10);
+        cache.put_weighted("a", 'a', 4);
+        cache.put_weighted("b", 'b', 3);
+        cache.put("c", 'c');
+        cache.get(&"a");
+        assert_eq!(cache.weight(), 8);
+        // 重さ 6 を入れるには、回数 1 の中で古い b と c を追い出す
+        assert_eq!(
+            cache.put_weighted("d", 'd', 6),
+            vec![("b", 'b'), ("c", 'c')]
+        );
+        assert_eq!(cache.weight(), 10);
+        // 容量より重いものは入らず、同じキーの古い値も取り除かれる
+        assert_eq!(
+            cache.put_weighted("a", 'A', 11),
+            vec![("a", 'a'), ("a", 'A')]
+        );
+        assert_eq!(cache.weight(), 6);
+        cache.check_invariants();
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Put(u8, u8),
+        Get(u8),
+        Peek(u8),
+        Remove(u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..12u8, 1..5u8).prop_map(|(k, w)| Op::Put(k, w)),
+            3 => (0..12u8).prop_map(Op::Get),
+            1 => (0..12u8).prop_map(Op::Peek),
+            1 => (0..12u8).prop_map(Op::Remove),
+        ]
+    }
+
+    // (key, value, weight, 回数, その回数になった時刻)
+    type Model = Vec<(u8, u32, usize, u64, u32)>;
+
+    // 追い出す順の逆に並べる
+    fn sorted(model: &Model) -> Vec<(u8, u32)> {
+        let mut model = model.clone();
+        model.sort_by_key(|e| std::cmp::Reverse((e.3, e.4)));
+        model.iter().map(|e| (e.0, e.1)).collect()
+    }
+
+    proptest! {
+        // 要素ごとに回数と最後に使った時刻を持つ Vec と比べる
+        #[test]
+        fn matches_model(capacity in 1..12usize, ops in prop::collection::vec(op(), 0..200)) {
+            let mut cache = LfuCache::new(capacity);
+            let mut expected: Model = Vec::new();
+            for (t, op) in ops.into_iter().enumerate() {
+                let t = t as u32;
+                match op {
+                    Op::Put(k, w) => {
+                        let w = w as usize;
+                        let mut removed = Vec::new();
+                        let frequency = match expected.iter().position(|e| e.0 == k) {
+                            Some(i) => {
+                                let e = expected.remove(i);
+                                removed.push((k, e.1));
+                                e.3 + 1
+                            }
+                            None => 1,
+                        };
+                        if w > capacity {
+                            removed.push((k, t));
+                        } else {
+                            while expected.iter().map(|e| e.2).sum::<usize>() + w > capacity {
+                                let i = (0..expected.len()).min_by_key(|&i| (expected[i].3, expected[i].4)).unwrap();
+                                let (k, v, ..) = expected.remove(i);
+                                removed.push((k, v));
+                            }
+                            expected.push((k, t, w, frequency, t));
+                        }
+                        prop_assert_eq!(cache.put_weighted(k, t, w), removed);
+                    }
+                    Op::Get(k) => {
+                        let found = expected.iter_mut().find(|e| e.0 == k).map(|e| {
+                            e.3 += 1;
+                            e.4 = t;
+                            e.1
+                        });
+                        prop_assert_eq!(cache.get(&k).copied(), found);
+                    }
+                    Op::Peek(k) => {
+                        let found = expected.iter().find(|e| e.0 == k).map(|e| e.1);
+                        prop_assert_eq!(cache.peek(&k).copied(), found);
+                    }
+                    Op::Remove(k) => {
+                        let found = expected.iter().position(|e| e.0 == k).map(|i| expected.remove(i).1);
+                        prop_assert_eq!(cache.remove(&k), found);
+                    }
+                }
+                cache.check_invariants();
+                let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+                prop_assert_eq!(order, sorted(&expected));
+                let victims: Vec<_> = cache.iter().rev().map(|(k, v)| (*k, *v)).collect();
+                let mut expected_victims = sorted(&expected);
+                expected_victims.reverse();
+                prop_assert_eq!(victims, expected_victims);
+            }
+        }
+    }
+}
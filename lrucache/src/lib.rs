@@ -0,0 +1,464 @@
+// 最も長く使われていないものから追い出すキャッシュ (LRU)
+// 要素を使った順の双方向リストに並べ、キーからリスト上の位置を HashMap で引く
+// リストのノードは Vec に置き、前後を位置で持つ。空いた位置は free に積んで使い回す
+// get と put は、使った要素をリストの先頭 (最近使った方) に移し、溢れたら末尾から追い出す。どれも O(1)
+//
+// 要素ごとに重さを持たせられる。容量は重さの合計で、put_weighted で重さを指定しなければ 1 つ 1 になる
+use std::{collections::HashMap, hash::Hash, iter::FusedIterator};
+
+pub use cache::Cache;
+
+#[derive(Debug, Clone)]
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    weight: usize,
+    // 1 つ新しい方
+    prev: Option<usize>,
+    // 1 つ古い方
+    next: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LruCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Vec<Option<Entry<K, V>>>,
+    free: Vec<usize>,
+    // 最近使った方の端
+    head: Option<usize>,
+    // 最も長く使われていない方の端
+    tail: Option<usize>,
+    capacity: usize,
+    weight: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self {
+            map: HashMap::new(),
+            entries: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+            capacity,
+            weight: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    // 入っている要素の重さの合計
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    fn entry(&self, i: usize) -> &Entry<K, V> {
+        self.entries[i].as_ref().expect("linked slot is occupied")
+    }
+
+    fn entry_mut(&mut self, i: usize) -> &mut Entry<K, V> {
+        self.entries[i].as_mut().expect("linked slot is occupied")
+    }
+
+    // リストから外す
+    fn detach(&mut self, i: usize) {
+        let (prev, next) = {
+            let entry = self.entry(i);
+            (entry.prev, entry.next)
+        };
+        match prev {
+            Some(p) => self.entry_mut(p).next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(n) => self.entry_mut(n).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    // リストの先頭に置く
+    fn attach_front(&mut self, i: usize) {
+        let head = self.head;
+        {
+            let entry = self.entry_mut(i);
+            entry.prev = None;
+            entry.next = head;
+        }
+        match head {
+            Some(h) => self.entry_mut(h).prev = Some(i),
+            None => self.tail = Some(i),
+        }
+        self.head = Some(i);
+    }
+
+    // リストと map から外し、位置を空ける
+    fn take(&mut self, i: usize) -> (K, V) {
+        self.detach(i);
+        let entry = self.entries[i].take().expect("linked slot is occupied");
+        self.free.push(i);
+        self.map.remove(&entry.key);
+        self.weight -= entry.weight;
+        (entry.key, entry.value)
+    }
+
+    // 使ったことにして返す
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = *self.map.get(key)?;
+        self.detach(i);
+        self.attach_front(i);
+        Some(&mut self.entry_mut(i).value)
+    }
+
+    // 使ったことにせずに返す
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&i| &self.entry(i).value)
+    }
+
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = *self.map.get(key)?;
+        Some(&mut self.entry_mut(i).value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    // 重さ 1 で入れる。取り除いたものを返す
+    pub fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        self.put_weighted(key, value, 1)
+    }
+
+    // 重さ weight で入れ、このために取り除いたものを返す
+    // 同じキーがあれば、古い値を (key, 古い値) として先に返し、続けて追い出したものを古い順に返す
+    // 容量より重いものは入れずに、そのまま返す
+    pub fn put_weighted(&mut self, key: K, value: V, weight: usize) -> Vec<(K, V)> {
+        let mut removed = Vec::new();
+        if weight > self.capacity {
+            if let Some(old) = self.remove(&key) {
+                removed.push((key.clone(), old));
+            }
+            removed.push((key, value));
+            return removed;
+        }
+        match self.map.get(&key) {
+            Some(&i) => {
+                let entry = self.entry_mut(i);
+                let old = std::mem::replace(&mut entry.value, value);
+                let old_weight = std::mem::replace(&mut entry.weight, weight);
+                self.weight = self.weight - old_weight + weight;
+                self.detach(i);
+                self.attach_front(i);
+                removed.push((key, old));
+            }
+            None => {
+                let entry = Entry {
+                    key: key.clone(),
+                    value,
+                    weight,
+                    prev: None,
+                    next: None,
+                };
+                let i = match self.free.pop() {
+                    Some(i) => {
+                        self.entries[i] = Some(entry);
+                        i
+                    }
+                    None => {
+                        self.entries.push(Some(entry));
+                        self.entries.len() - 1
+                    }
+                };
+                self.map.insert(key, i);
+                self.attach_front(i);
+                self.weight += weight;
+            }
+        }
+        // 入れたものは先頭にあり、それだけなら容量に収まるので、追い出されない
+        while self.weight > self.capacity {
+            removed.extend(self.pop_lru());
+        }
+        removed
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = *self.map.get(key)?;
+        Some(self.take(i).1)
+    }
+
+    // 最も長く使われていないものを取り除く
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let tail = self.tail?;
+        Some(self.take(tail))
+    }
+
+    // 最も長く使われていないものから順に見て、evictable が true を返した最初のものを取り除く
+    pub fn pop_lru_where<F: FnMut(&K, &V) -> bool>(&mut self, mut evictable: F) -> Option<(K, V)> {
+        let mut current = self.tail;
+        while let Some(i) = current {
+            let entry = self.entry(i);
+            if evictable(&entry.key, &entry.value) {
+                return Some(self.take(i));
+            }
+            current = entry.prev;
+        }
+        None
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.entries.clear();
+        self.free.clear();
+        self.head = None;
+        self.tail = None;
+        self.weight = 0;
+    }
+
+    // 最近使った順に返す。rev で古い順になる
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            cache: self,
+            front: self.head,
+            back: self.tail,
+            remaining: self.len(),
+        }
+    }
+
+    // リストの前後が合い、map と重さの合計がリストと合い、重さが容量に収まっているか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        let mut weight = 0;
+        let mut prev = None;
+        let mut current = self.head;
+        while let Some(i) = current {
+            let entry = self.entry(i);
+            assert_eq!(entry.prev, prev, "prev is stale");
+            assert_eq!(self.map.get(&entry.key), Some(&i), "map is stale");
+            count += 1;
+            weight += entry.weight;
+            prev = Some(i);
+            current = entry.next;
+        }
+        assert_eq!(self.tail, prev, "tail is stale");
+        assert_eq!(count, self.map.len(), "map has entries not in the list");
+        assert_eq!(
+            count + self.free.len(),
+            self.entries.len(),
+            "slot is leaked"
+        );
+        assert_eq!(weight, self.weight, "weight is stale");
+        assert!(self.weight <= self.capacity, "weight exceeds capacity");
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    cache: &'a LruCache<K, V>,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a, K: Hash + Eq + Clone, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.cache.entry(self.front?);
+        self.front = entry.next;
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let entry = self.cache.entry(self.back?);
+        self.back = entry.prev;
+        self.remaining -= 1;
+        Some((&entry.key, &entry.value))
+    }
+}
+
+impl<'a, K: Hash + Eq + Clone, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Hash + Eq + Clone, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<K: Hash + Eq + Clone, V> Cache<K, V> for LruCache<K, V> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        LruCache::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        LruCache::get_mut(self, key)
+    }
+
+    fn peek(&self, key: &K) -> Option<&V> {
+        LruCache::peek(self, key)
+    }
+
+    fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        LruCache::peek_mut(self, key)
+    }
+
+    fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        LruCache::put(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        LruCache::remove(self, key)
+    }
+
+    fn pop_victim(&mut self, evictable: &mut dyn FnMut(&K, &V) -> bool) -> Option<(K, V)> {
+        self.pop_lru_where(evictable)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn recency() {
+        let mut cache = LruCache::new(3);
+        assert!(cache.put("a", 1).is_empty());
+        assert!(cache.put("b", 2).is_empty());
+        assert!(cache.put("c", 3).is_empty());
+        // a を使ったので、b が最も古くなる
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.put("d", 4), vec![("b", 2)]);
+        // peek では順番は変わらない
+        assert_eq!(cache.peek(&"c"), Some(&3));
+        assert_eq!(cache.put("a", 10), vec![("a", 1)]);
+        let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(order, vec![("a", 10), ("d", 4), ("c", 3)]);
+        let oldest: Vec<_> = cache.iter().rev().map(|(k, _)| *k).collect();
+        assert_eq!(oldest, vec!["c", "d", "a"]);
+        cache.check_invariants();
+
+        // c は飛ばして、次に古い d を取り除く
+        assert_eq!(cache.pop_lru_where(|k, _| *k != "c"), Some(("d", 4)));
+        assert_eq!(cache.remove(&"c"), Some(3));
+        assert_eq!(cache.len(), 1);
+        cache.check_invariants();
+    }
+
+    #[test]
+    fn weights() {
+        let mut cache = LruCache::new(10);
+        cache.put_weighted("a", 'a', 4);
+        cache.put_weighted("b", 'b', 3);
+        cache.put("c", 'c');
+        assert_eq!(cache.weight(), 8);
+        // 重さ 6 を入れるには、最も古い a を追い出せば足りる
+        assert_eq!(cache.put_weighted("d", 'd', 6), vec![("a", 'a')]);
+        assert_eq!(cache.weight(), 10);
+        // 容量より重いものは入らず、同じキーの古い値も取り除かれる
+        assert_eq!(
+            cache.put_weighted("d", 'D', 11),
+            vec![("d", 'd'), ("d", 'D')]
+        );
+        assert_eq!(cache.weight(), 4);
+        cache.check_invariants();
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Put(u8, u8),
+        Get(u8),
+        Peek(u8),
+        Remove(u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..12u8, 1..5u8).prop_map(|(k, w)| Op::Put(k, w)),
+            2 => (0..12u8).prop_map(Op::Get),
+            1 => (0..12u8).prop_map(Op::Peek),
+            1 => (0..12u8).prop_map(Op::Remove),
+        ]
+    }
+
+    proptest! {
+        // 最近使った順に (key, value, weight) を並べた Vec と比べる
+        #[test]
+        fn matches_list(capacity in 1..12usize, ops in prop::collection::vec(op(), 0..200)) {
+            let mut cache = LruCache::new(capacity);
+            let mut expected: Vec<(u8, u32, usize)> = Vec::new();
+            for (t, op) in ops.into_iter().enumerate() {
+                let t = t as u32;
+                match op {
+                    Op::Put(k, w) => {
+                        let w = w as usize;
+                        let mut removed = Vec::new();
+                        if let Some(i) = expected.iter().position(|e| e.0 == k) {
+                            removed.push((k, expected.remove(i).1));
+                        }
+                        if w > capacity {
+                            removed.push((k, t));
+                        } else {
+                            expected.insert(0, (k, t, w));
+                            while expected.iter().map(|e| e.2).sum::<usize>() > capacity {
+                                let (k, v, _) = expected.pop().unwrap();
+                                removed.push((k, v));
+                            }
+                        }
+                        prop_assert_eq!(cache.put_weighted(k, t, w), removed);
+                    }
+                    Op::Get(k) => {
+                        let found = expected.iter().position(|e| e.0 == k).map(|i| {
+                            let e = expected.remove(i);
+                            expected.insert(0, e);
+                            e.1
+                        });
+                        prop_assert_eq!(cache.get(&k).copied(), found);
+                    }
+                    Op::Peek(k) => {
+                        let found = expected.iter().find(|e| e.0 == k).map(|e| e.1);
+                        prop_assert_eq!(cache.peek(&k).copied(), found);
+                    }
+                    Op::Remove(k) => {
+                        let found = expected.iter().position(|e| e.0 == k).map(|i| expected.remove(i).1);
+                        prop_assert_eq!(cache.remove(&k), found);
+                    }
+                }
+                cache.check_invariants();
+                let order: Vec<_> = cache.iter().map(|(k, v)| (*k, *v)).collect();
+                let expected_order: Vec<_> = expected.iter().map(|e| (e.0, e.1)).collect();
+                prop_assert_eq!(order, expected_order);
+            }
+        }
+    }
+}
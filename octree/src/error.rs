@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("invalid leaf capacity: {capacity} (must be at least 1)")]
+    InvalidCapacity { capacity: usize },
+    #[error("rect is outside the bounds of the tree")]
+    OutOfBounds,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,56 @@
+// 視錐台 (frustum) のような、平面で囲まれた凸な領域
+// 各平面は normal・p + offset >= 0 の側を内側とし、全ての平面の内側にある点の集まりを領域とする
+use spatial::{Coordinate, Rect};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: [f64; 3],
+    pub offset: f64,
+}
+
+impl Plane {
+    pub fn new(normal: [f64; 3], offset: f64) -> Self {
+        Self { normal, offset }
+    }
+
+    // 内側なら正。normal の長さが 1 なら平面までの距離
+    pub fn signed_distance(&self, point: &[f64; 3]) -> f64 {
+        (0..3).map(|i| self.normal[i] * point[i]).sum::<f64>() + self.offset
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    planes: Vec<Plane>,
+}
+
+impl Frustum {
+    // カメラなら near、far、左右上下の 6 枚
+    pub fn new(planes: Vec<Plane>) -> Self {
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        &self.planes
+    }
+
+    pub fn contains_point(&self, point: &[f64; 3]) -> bool {
+        self.planes.iter().all(|p| p.signed_distance(point) >= 0.0)
+    }
+
+    // どの平面についても、箱の角のうち一番内側のものが内側にあれば true
+    // 箱が全ての平面と交わりつつ領域の外にある (角の近くの) 場合も true になる
+    pub fn intersects<T: Coordinate>(&self, rect: &Rect<T, 3>) -> bool {
+        self.planes.iter().all(|plane| {
+            let mut corner = [0.0; 3];
+            for (axis, c) in corner.iter_mut().enumerate() {
+                *c = if plane.normal[axis] >= 0.0 {
+                    rect.max()[axis].to_f64()
+                } else {
+                    rect.min()[axis].to_f64()
+                };
+            }
+            plane.signed_distance(&corner) >= 0.0
+        })
+    }
+}
@@ -0,0 +1,206 @@
+// 線形探査、Robin Hood、cuckoo、hopscotch、SwissTable、std の HashMap を同じ操作で比べる。どれも std の RandomState でハッシュする
+//   cargo bench -p openhash
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::Hash,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use openhash::{
+    cuckoo::CuckooMap, hopscotch::HopscotchMap, robinhood::RobinHoodMap, swiss::SwissMap,
+    OpenHashMap,
+};
+
+const SIZES: [usize; 2] = [1_000, 100_000];
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// 重複の少ないランダムな順のキー
+fn keys(n: usize) -> Vec<usize> {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+    (0..n)
+        .map(|_| (xorshift(&mut state) % (n as u64 * 4)) as usize)
+        .collect()
+}
+
+// 2 つの map を同じ形で呼ぶための最小の操作
+trait Map<K> {
+    fn new() -> Self;
+    fn insert(&mut self, key: K);
+    fn contains(&self, key: &K) -> bool;
+    fn remove(&mut self, key: &K);
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for HashMap<K, K, RandomState> {
+    fn new() -> Self {
+        HashMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        HashMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        HashMap::remove(self, key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for OpenHashMap<K, K, RandomState> {
+    fn new() -> Self {
+        OpenHashMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        OpenHashMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        OpenHashMap::remove(self, key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for RobinHoodMap<K, K, RandomState> {
+    fn new() -> Self {
+        RobinHoodMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        RobinHoodMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        RobinHoodMap::remove(self, key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for CuckooMap<K, K, RandomState> {
+    fn new() -> Self {
+        CuckooMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        CuckooMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        CuckooMap::remove(self, key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for HopscotchMap<K, K, RandomState> {
+    fn new() -> Self {
+        HopscotchMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        HopscotchMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        HopscotchMap::remove(self, key);
+    }
+}
+
+impl<K: Hash + Eq + Copy> Map<K> for SwissMap<K, K, RandomState> {
+    fn new() -> Self {
+        SwissMap::new()
+    }
+
+    fn insert(&mut self, key: K) {
+        SwissMap::insert(self, key, key);
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+
+    fn remove(&mut self, key: &K) {
+        SwissMap::remove(self, key);
+    }
+}
+
+fn fill<M: Map<usize>>(keys: &[usize]) -> M {
+    let mut map = M::new();
+    for k in keys {
+        map.insert(*k);
+    }
+    map
+}
+
+fn bench_one<M: Map<usize>>(c: &mut Criterion, name: &str) {
+    for n in SIZES {
+        let keys = keys(n);
+        c.bench_with_input(
+            BenchmarkId::new(format!("insert/{}", name), n),
+            &keys,
+            |b, keys| b.iter(|| fill::<M>(keys)),
+        );
+
+        let map = fill::<M>(&keys);
+        c.bench_with_input(
+            BenchmarkId::new(format!("get_hit/{}", name), n),
+            &keys,
+            |b, keys| b.iter(|| keys.iter().filter(|k| map.contains(k)).count()),
+        );
+        // キーは n * 4 未満なので、それ以上は必ず外れる
+        let misses: Vec<_> = keys.iter().map(|k| k + n * 4).collect();
+        c.bench_with_input(
+            BenchmarkId::new(format!("get_miss/{}", name), n),
+            &misses,
+            |b, misses| b.iter(|| misses.iter().filter(|k| map.contains(k)).count()),
+        );
+        c.bench_with_input(
+            BenchmarkId::new(format!("remove/{}", name), n),
+            &keys,
+            |b, keys| {
+                b.iter_batched(
+                    || fill::<M>(keys),
+                    |mut map| {
+                        for k in keys {
+                            map.remove(k);
+                        }
+                        map
+                    },
+                    criterion::BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+}
+
+fn hash_map(c: &mut Criterion) {
+    bench_one::<OpenHashMap<usize, usize>>(c, "openhash");
+    bench_one::<RobinHoodMap<usize, usize>>(c, "robinhood");
+    bench_one::<CuckooMap<usize, usize>>(c, "cuckoo");
+    bench_one::<HopscotchMap<usize, usize>>(c, "hopscotch");
+    bench_one::<SwissMap<usize, usize>>(c, "swiss");
+    bench_one::<HashMap<usize, usize>>(c, "std");
+}
+
+criterion_group!(benches, hash_map);
+criterion_main!(benches);
@@ -0,0 +1,420 @@
+// cuckoo hashing
+// 配列を 2 つの表に分け、key は 1 つ目の表の h1 か 2 つ目の表の h2 のどちらかにだけ置く
+// そのため探索は 2 か所を見るだけで、最悪でも O(1)
+//
+// insert は h1 に置き、そこにいた要素を追い出して、その要素のもう一方の位置へ移す。これを空きに着くまで繰り返す
+// 追い出しが MAX_KICKS 回を超えたら循環しているとみなし、置けなかった要素を小さな stash に入れる
+// stash も埋まっていたら、ハッシュの seed を変えて全体を入れ直す
+// 2 つのハッシュは、seed と key のハッシュの下位 32 ビットと上位 32 ビットから取る
+//
+// ハッシュが偏っていて (BuildHasher が定数を返すなど) 入れ直しても置けない場合は、
+// MAX_REBUILDS 回で諦めて panic する。止まらなくなったり、配列を際限なく大きくしたりはしない
+//
+// 入れ直しが続かないように、要素数は配列全体の 1/2 までにし、超えると倍にする。1/8 を下回ると半分にする
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    mem,
+};
+
+use crate::{slots_for, Iter, Slot, MIN_CAPACITY};
+
+// 1 回の insert で追い出す回数の上限
+const MAX_KICKS: usize = 32;
+// seed を変えて入れ直すのがこの回数続けて失敗したら、配列を倍にする
+const RETRIES_BEFORE_GROW: usize = 4;
+// 1 回の入れ直しで seed を変える回数の上限
+const MAX_REBUILDS: usize = 16;
+// 表に置けなかった要素を持っておける数。探索で stash も見るので小さくしておく
+const MAX_STASH: usize = 4;
+
+#[derive(Clone)]
+pub struct CuckooMap<K, V, S = RandomState> {
+    // 前半が 1 つ目の表、後半が 2 つ目の表
+    slots: Vec<Option<Slot<K, V>>>,
+    // 表に置けなかった要素。ハッシュが偏っていなければ、ほとんど空のまま
+    stash: Vec<Slot<K, V>>,
+    len: usize,
+    seed: u64,
+    rehashes: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> CuckooMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> CuckooMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            slots: Vec::new(),
+            stash: Vec::new(),
+            len: 0,
+            seed: 0,
+            rehashes: 0,
+            hasher,
+        }
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            // slots_for は負荷率 3/4 で数えるので、1/2 になるように倍にしておく
+            map.rebuild(slots_for(capacity) * 2, Vec::new());
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    // 循環して seed を変えた回数
+    pub fn rehashes(&self) -> usize {
+        self.rehashes
+    }
+
+    fn hash(&self, key: &K) -> u64 {
+        self.hasher.hash_one((self.seed, key))
+    }
+
+    // 1 つ目の表の位置と 2 つ目の表の位置
+    fn positions(&self, hash: u64) -> (usize, usize) {
+        let half = self.slots.len() / 2;
+        let mask = half - 1;
+        (hash as usize & mask, half + ((hash >> 32) as usize & mask))
+    }
+
+    // key のある位置。stash にあれば slots.len() に stash での位置を足したもの
+    fn find(&self, hash: u64, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let (a, b) = self.positions(hash);
+        let holds = |i: usize| {
+            self.slots[i]
+                .as_ref()
+                .is_some_and(|slot| slot.hash == hash && slot.key == *key)
+        };
+        if holds(a) {
+            Some(a)
+        } else if holds(b) {
+            Some(b)
+        } else {
+            self.stash
+                .iter()
+                .position(|slot| slot.hash == hash && slot.key == *key)
+                .map(|i| self.slots.len() + i)
+        }
+    }
+
+    fn slot(&self, i: usize) -> &Slot<K, V> {
+        match self.slots.get(i) {
+            Some(slot) => slot.as_ref().expect("found slot is occupied"),
+            None => &self.stash[i - self.slots.len()],
+        }
+    }
+
+    fn slot_mut(&mut self, i: usize) -> &mut Slot<K, V> {
+        let n = self.slots.len();
+        match self.slots.get_mut(i) {
+            Some(slot) => slot.as_mut().expect("found slot is occupied"),
+            None => &mut self.stash[i - n],
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let i = self.find(self.hash(key), key)?;
+        Some(&self.slot(i).value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.find(self.hash(key), key)?;
+        Some(&mut self.slot_mut(i).value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hash(&key);
+        if let Some(i) = self.find(hash, &key) {
+            return Some(mem::replace(&mut self.slot_mut(i).value, value));
+        }
+        let slot = Slot { hash, key, value };
+        if (self.len + 1) * 2 > self.slots.len() {
+            let slots = (self.slots.len() * 2).max(MIN_CAPACITY);
+            self.rebuild(slots, vec![slot]);
+        } else if let Err(slot) = self.place_or_stash(slot) {
+            self.rehashes += 1;
+            self.seed = next_seed(self.seed);
+            self.rebuild(self.slots.len(), vec![slot]);
+        }
+        self.len += 1;
+        None
+    }
+
+    // 追い出しを繰り返して空きに置く。MAX_KICKS 回で着かなければ、置けなかった要素を返す
+    fn place(&mut self, mut slot: Slot<K, V>) -> Result<(), Slot<K, V>> {
+        let mut i = self.positions(slot.hash).0;
+        for _ in 0..MAX_KICKS {
+            slot = match self.slots[i].replace(slot) {
+                None => return Ok(()),
+                Some(evicted) => evicted,
+            };
+            let (a, b) = self.positions(slot.hash);
+            i = if i == a { b } else { a };
+        }
+        Err(slot)
+    }
+
+    // 表に置けなければ stash に入れる。stash も埋まっていれば slot を返す
+    fn place_or_stash(&mut self, slot: Slot<K, V>) -> Result<(), Slot<K, V>> {
+        match self.place(slot) {
+            Err(slot) if self.stash.len() < MAX_STASH => {
+                self.stash.push(slot);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    // 配列を slots 個にして、いまの要素と pending を入れ直す
+    // 置けない要素が出たら seed を変えてやり直し、何度も失敗するなら配列を倍にする
+    fn rebuild(&mut self, mut slots: usize, mut pending: Vec<Slot<K, V>>) {
+        pending.extend(mem::take(&mut self.slots).into_iter().flatten());
+        pending.append(&mut self.stash);
+        for attempt in 1..=MAX_REBUILDS {
+            self.slots = (0..slots).map(|_| None).collect();
+            let failed = loop {
+                let mut slot = match pending.pop() {
+                    Some(slot) => slot,
+                    None => return,
+                };
+                slot.hash = self.hash(&slot.key);
+                if let Err(slot) = self.place_or_stash(slot) {
+                    break slot;
+                }
+            };
+            pending.push(failed);
+            pending.extend(mem::take(&mut self.slots).into_iter().flatten());
+            pending.append(&mut self.stash);
+            self.rehashes += 1;
+            self.seed = next_seed(self.seed);
+            if attempt % RETRIES_BEFORE_GROW == 0 {
+                slots *= 2;
+            }
+        }
+        panic!(
+            "CuckooMap could not place {} keys after {} rehashes; the hasher may map many keys to the same hash",
+            pending.len(),
+            MAX_REBUILDS
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.find(self.hash(key), key)?;
+        let removed = match self.slots.get_mut(i) {
+            Some(slot) => slot.take().expect("found slot is occupied"),
+            None => self.stash.swap_remove(i - self.slots.len()),
+        };
+        self.len -= 1;
+        if self.slots.len() > MIN_CAPACITY && self.len * 8 < self.slots.len() {
+            self.rebuild(self.slots.len() / 2, Vec::new());
+        }
+        Some(removed.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.stash.clear();
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: self.stash.iter(),
+            remaining: self.len,
+        }
+    }
+
+    // ハッシュ、各要素がそのハッシュで決まる表の位置か stash にあること、len、負荷率が正しいか確かめる
+    pub fn check_invariants(&self) {
+        assert!(
+            self.slots.is_empty() || self.slots.len().is_power_of_two(),
+            "slots is not a power of two"
+        );
+        let half = self.slots.len() / 2;
+        let mut count = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+            count += 1;
+            assert_eq!(slot.hash, self.hash(&slot.key), "hash is stale");
+            let (a, b) = self.positions(slot.hash);
+            assert_eq!(
+                i,
+                if i < half { a } else { b },
+                "slot is in a wrong position"
+            );
+        }
+        assert!(self.stash.len() <= MAX_STASH, "stash is too large");
+        for slot in &self.stash {
+            assert_eq!(slot.hash, self.hash(&slot.key), "hash is stale");
+        }
+        assert_eq!(count + self.stash.len(), self.len, "len is stale");
+        assert!(self.len * 2 <= self.slots.len(), "load factor is too high");
+    }
+}
+
+fn next_seed(seed: u64) -> u64 {
+    seed.wrapping_add(0x9e37_79b9_7f4a_7c15)
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for CuckooMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for CuckooMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            slots: self.slots.iter(),
+            overflow: self.stash.iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a CuckooMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for CuckooMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for CuckooMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::BuildHasherDefault,
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::testing::Constant;
+
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = CuckooMap::new();
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("a", 2), Some(1));
+        assert_eq!(map.get(&"a"), Some(&2));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.remove(&"a"), Some(2));
+        assert!(map.is_empty());
+        map.check_invariants();
+    }
+
+    // 負荷率 1/2 まで入れると、いくつかの insert で循環して seed を変える
+    #[test]
+    fn rehash_on_cycle() {
+        let mut map = CuckooMap::<u64, u64, Fixed>::default();
+        for k in 0..20_000 {
+            map.insert(k, k * 2);
+        }
+        map.check_invariants();
+        assert!(map.rehashes() > 0);
+        assert!((0..20_000).all(|k| map.get(&k) == Some(&(k * 2))));
+        assert_eq!(map.get(&20_000), None);
+    }
+
+    // 同じハッシュの key は表の 2 か所と stash にしか置けない
+    #[test]
+    fn colliding_keys_use_stash() {
+        let mut map = CuckooMap::<u64, u64, Constant>::default();
+        for k in 0..6 {
+            map.insert(k, k * 2);
+        }
+        map.check_invariants();
+        assert!((0..6).all(|k| map.get(&k) == Some(&(k * 2))));
+        assert_eq!(map.iter().count(), 6);
+        assert_eq!(map.remove(&3), Some(6));
+        assert_eq!(map.get(&3), None);
+        map.check_invariants();
+    }
+
+    #[test]
+    #[should_panic(expected = "could not place")]
+    fn too_many_colliding_keys() {
+        let mut map = CuckooMap::<u64, u64, Constant>::default();
+        for k in 0..7 {
+            map.insert(k, k);
+        }
+    }
+
+    proptest! {
+        // HashMap と同じ操作をして比べる
+        #[test]
+        fn same_as_hash_map_random(ops in prop::collection::vec((0..3u8, 0..256u32, 0..100u32), 0..500)) {
+            let mut map = CuckooMap::<u32, u32, Fixed>::default();
+            let mut model = HashMap::new();
+            for (op, key, value) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(map.insert(key, value), model.insert(key, value)),
+                    _ => prop_assert_eq!(map.remove(&key), model.remove(&key)),
+                }
+                map.check_invariants();
+                prop_assert_eq!(map.len(), model.len());
+            }
+            let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+            prop_assert_eq!(&actual, &model);
+            for key in 0..256 {
+                prop_assert_eq!(map.get(&key), model.get(&key));
+            }
+        }
+    }
+}
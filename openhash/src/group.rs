@@ -0,0 +1,151 @@
+// swiss の制御バイトを、WIDTH 個ずつまとめて比べる
+// x86_64 では SSE2 で 16 バイトを 1 命令で比べ、それ以外では u64 に 8 バイトを詰めてビット演算で比べる (SWAR)
+// 比べた結果は、一致したバイトの位置にビットが立つ BitMask で返す
+
+// 空き
+pub(crate) const EMPTY: u8 = 0xff;
+// 削除した跡 (墓標)。探索はここで止めない
+pub(crate) const DELETED: u8 = 0x80;
+// 要素のある位置は、ハッシュの上位 7 ビット (0x00..=0x7f) を持つ
+
+// 一致したバイトの位置を小さい順に返す
+#[derive(Clone, Copy)]
+pub(crate) struct BitMask(u64);
+
+impl BitMask {
+    pub(crate) fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    pub(crate) fn lowest(self) -> Option<usize> {
+        self.any()
+            .then(|| self.0.trailing_zeros() as usize >> BITMASK_SHIFT)
+    }
+}
+
+impl Iterator for BitMask {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let i = self.lowest()?;
+        self.0 &= self.0 - 1;
+        Some(i)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod imp {
+    use std::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8,
+    };
+
+    use super::{BitMask, EMPTY};
+
+    pub(crate) const WIDTH: usize = 16;
+    // movemask は 1 バイトにつき 1 ビット
+    pub(super) const BITMASK_SHIFT: u32 = 0;
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct Group(__m128i);
+
+    impl Group {
+        pub(crate) fn load(ctrl: &[u8]) -> Self {
+            assert!(ctrl.len() >= WIDTH, "group is out of the control bytes");
+            // SAFETY: ctrl は WIDTH バイト以上ある。loadu は揃っていないアドレスから読める
+            Group(unsafe { _mm_loadu_si128(ctrl.as_ptr() as *const __m128i) })
+        }
+
+        pub(crate) fn match_byte(self, byte: u8) -> BitMask {
+            // SAFETY: この mod は SSE2 が有効なときだけ使う
+            let mask =
+                unsafe { _mm_movemask_epi8(_mm_cmpeq_epi8(self.0, _mm_set1_epi8(byte as i8))) };
+            BitMask(mask as u16 as u64)
+        }
+
+        pub(crate) fn match_empty(self) -> BitMask {
+            self.match_byte(EMPTY)
+        }
+
+        // EMPTY と DELETED だけ最上位ビットが立っている
+        pub(crate) fn match_empty_or_deleted(self) -> BitMask {
+            // SAFETY: match_byte と同じ
+            BitMask(unsafe { _mm_movemask_epi8(self.0) } as u16 as u64)
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+mod imp {
+    use std::convert::TryInto;
+
+    use super::BitMask;
+
+    pub(crate) const WIDTH: usize = 8;
+    // 一致したバイトの最上位ビットを立てるので、位置はビットの位置の 1/8
+    pub(super) const BITMASK_SHIFT: u32 = 3;
+
+    fn repeat(byte: u8) -> u64 {
+        u64::from_ne_bytes([byte; WIDTH])
+    }
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct Group(u64);
+
+    impl Group {
+        pub(crate) fn load(ctrl: &[u8]) -> Self {
+            let bytes = ctrl[..WIDTH].try_into().expect("slice has WIDTH bytes");
+            // 先頭のバイトを下位に置き、trailing_zeros で位置を求められるようにする
+            Group(u64::from_le_bytes(bytes))
+        }
+
+        // 0 になったバイトを探す。一致の後ろのバイトで誤って立つことがあるが、key を比べるので問題ない
+        pub(crate) fn match_byte(self, byte: u8) -> BitMask {
+            let cmp = self.0 ^ repeat(byte);
+            BitMask(cmp.wrapping_sub(repeat(0x01)) & !cmp & repeat(0x80))
+        }
+
+        // EMPTY (0xff) だけ上位 2 ビットが両方立っている
+        pub(crate) fn match_empty(self) -> BitMask {
+            BitMask(self.0 & (self.0 << 1) & repeat(0x80))
+        }
+
+        pub(crate) fn match_empty_or_deleted(self) -> BitMask {
+            BitMask(self.0 & repeat(0x80))
+        }
+    }
+}
+
+use imp::BITMASK_SHIFT;
+pub(crate) use imp::{Group, WIDTH};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_bytes() {
+        let mut ctrl = [EMPTY; WIDTH];
+        ctrl[1] = 0x12;
+        ctrl[3] = DELETED;
+        ctrl[5] = 0x12;
+        ctrl[6] = 0x34;
+        let group = Group::load(&ctrl);
+        assert_eq!(group.match_byte(0x12).collect::<Vec<_>>(), vec![1, 5]);
+        assert_eq!(group.match_byte(0x34).lowest(), Some(6));
+        assert!(!group.match_byte(0x56).any());
+        let empty: Vec<_> = group.match_empty().collect();
+        assert_eq!(
+            empty,
+            (0..WIDTH)
+                .filter(|i| ![1, 3, 5, 6].contains(i))
+                .collect::<Vec<_>>()
+        );
+        let free: Vec<_> = group.match_empty_or_deleted().collect();
+        assert_eq!(
+            free,
+            (0..WIDTH)
+                .filter(|i| ![1, 5, 6].contains(i))
+                .collect::<Vec<_>>()
+        );
+    }
+}
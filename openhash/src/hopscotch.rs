@@ -0,0 +1,433 @@
+// hopscotch hashing
+// key は home から HOP 個先までの近所 (neighborhood) のどこかにだけ置く
+// 各位置は、自分を home とする要素が近所のどこにあるかをビットマップ (hop) で持つので、
+// 探索は hop の立っているビットの位置だけを見ればよく、見る範囲は HOP 個の連続した位置に収まる
+//
+// insert は home から線形に空きを探し、空きが近所の外なら、空きより前にある要素を
+// その要素の近所の中で空きへ動かして、空きを home に近づけていく
+// 近づけられない、または空きが遠すぎるときは配列を倍にする
+// 倍にすると負荷率が 1/8 を下回るときは、それ以上大きくせずに overflow に入れる
+// 同じ home の key が HOP 個より多いと配列を大きくしても置けないので、ハッシュが偏っていても配列は際限なく大きくならない
+//
+// 線形探査より高い負荷率 (7/8) まで入れても探索の長さが HOP で抑えられる。1/8 を下回ると半分にする
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    mem,
+};
+
+use crate::{Iter, ProbeStats, Slot};
+
+// 近所の大きさ。hop のビット数
+const HOP: usize = 32;
+// 空きを探す距離の上限
+const MAX_SCAN: usize = 512;
+// 近所が配列を一周しないように、配列は HOP 以上にする
+const MIN_SLOTS: usize = HOP;
+
+#[derive(Clone)]
+pub struct HopscotchMap<K, V, S = RandomState> {
+    slots: Vec<Option<Slot<K, V>>>,
+    // hops[i] の j ビット目が立っていれば、i + j の要素の home が i
+    hops: Vec<u32>,
+    // 近所に置けなかった要素。ハッシュが偏っていなければ空のまま
+    overflow: Vec<Slot<K, V>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> HopscotchMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> HopscotchMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            slots: Vec::new(),
+            hops: Vec::new(),
+            overflow: Vec::new(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            let slots = (capacity * 8).div_ceil(7).next_power_of_two();
+            map.rebuild(slots.max(MIN_SLOTS), Vec::new());
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len() / 8 * 7
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        hash as usize & self.mask()
+    }
+
+    fn distance(&self, from: usize, to: usize) -> usize {
+        to.wrapping_sub(from) & self.mask()
+    }
+
+    // key のある位置。overflow にあれば slots.len() に overflow での位置を足したもの
+    fn find(&self, hash: u64, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let home = self.home(hash);
+        let mut hop = self.hops[home];
+        while hop != 0 {
+            let i = (home + hop.trailing_zeros() as usize) & self.mask();
+            match &self.slots[i] {
+                Some(slot) if slot.hash == hash && slot.key == *key => return Some(i),
+                _ => hop &= hop - 1,
+            }
+        }
+        self.overflow
+            .iter()
+            .position(|slot| slot.hash == hash && slot.key == *key)
+            .map(|i| self.slots.len() + i)
+    }
+
+    fn slot(&self, i: usize) -> &Slot<K, V> {
+        match self.slots.get(i) {
+            Some(slot) => slot.as_ref().expect("found slot is occupied"),
+            None => &self.overflow[i - self.slots.len()],
+        }
+    }
+
+    fn slot_mut(&mut self, i: usize) -> &mut Slot<K, V> {
+        let n = self.slots.len();
+        match self.slots.get_mut(i) {
+            Some(slot) => slot.as_mut().expect("found slot is occupied"),
+            None => &mut self.overflow[i - n],
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        Some(&self.slot(i).value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        Some(&mut self.slot_mut(i).value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        if let Some(i) = self.find(hash, &key) {
+            return Some(mem::replace(&mut self.slot_mut(i).value, value));
+        }
+        let slot = Slot { hash, key, value };
+        self.len += 1;
+        if self.len * 8 > self.slots.len() * 7 {
+            let slots = (self.slots.len() * 2).max(MIN_SLOTS);
+            self.rebuild(slots, vec![slot]);
+        } else if let Err(slot) = self.place(slot) {
+            if self.can_grow(self.slots.len()) {
+                self.rebuild(self.slots.len() * 2, vec![slot]);
+            } else {
+                self.overflow.push(slot);
+            }
+        }
+        None
+    }
+
+    // slots 個の配列を倍にしても、負荷率が 1/8 を下回らないか
+    fn can_grow(&self, slots: usize) -> bool {
+        self.len * 8 >= slots * 2
+    }
+
+    // 近所の中に空きを作って置く。作れなければ slot を返す
+    fn place(&mut self, slot: Slot<K, V>) -> Result<(), Slot<K, V>> {
+        let home = self.home(slot.hash);
+        let mut free = match (0..MAX_SCAN.min(self.slots.len()))
+            .map(|d| (home + d) & self.mask())
+            .find(|&i| self.slots[i].is_none())
+        {
+            Some(free) => free,
+            None => return Err(slot),
+        };
+        while self.distance(home, free) >= HOP {
+            free = match self.hop_back(free) {
+                Some(free) => free,
+                None => return Err(slot),
+            };
+        }
+        self.hops[home] |= 1 << self.distance(home, free);
+        self.slots[free] = Some(slot);
+        Ok(())
+    }
+
+    // 空き free より前にある要素のうち、その要素の近所の中で free へ動かせるものを動かし、新しい空きを返す
+    // 空きをできるだけ前へ進めるように、free から遠い home から順に見る
+    fn hop_back(&mut self, free: usize) -> Option<usize> {
+        for k in (1..HOP).rev() {
+            let bucket = free.wrapping_sub(k) & self.mask();
+            // bucket の要素のうち、free より前にあるもの
+            let movable = self.hops[bucket] & ((1 << k) - 1);
+            if movable == 0 {
+                continue;
+            }
+            let j = movable.trailing_zeros() as usize;
+            let from = (bucket + j) & self.mask();
+            self.slots[free] = self.slots[from].take();
+            self.hops[bucket] ^= (1 << j) | (1 << k);
+            return Some(from);
+        }
+        None
+    }
+
+    // 配列を slots 個にして、いまの要素と pending を入れ直す。置けない要素が出たら倍にしてやり直す
+    // 倍にできなければ、置けない要素は overflow に入れる
+    fn rebuild(&mut self, mut slots: usize, mut pending: Vec<Slot<K, V>>) {
+        pending.extend(mem::take(&mut self.slots).into_iter().flatten());
+        pending.append(&mut self.overflow);
+        loop {
+            self.slots = (0..slots).map(|_| None).collect();
+            self.hops = vec![0; slots];
+            let grow = self.can_grow(slots);
+            let failed = loop {
+                let slot = match pending.pop() {
+                    Some(slot) => slot,
+                    None => return,
+                };
+                match self.place(slot) {
+                    Ok(()) => {}
+                    Err(slot) if grow => break slot,
+                    Err(slot) => self.overflow.push(slot),
+                }
+            };
+            pending.push(failed);
+            pending.extend(mem::take(&mut self.slots).into_iter().flatten());
+            slots *= 2;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        let removed = match self.slots.get_mut(i) {
+            Some(slot) => {
+                let removed = slot.take().expect("found slot is occupied");
+                let home = self.home(removed.hash);
+                self.hops[home] &= !(1 << self.distance(home, i));
+                removed
+            }
+            None => self.overflow.swap_remove(i - self.slots.len()),
+        };
+        self.len -= 1;
+        if self.slots.len() > MIN_SLOTS && self.len * 8 < self.slots.len() {
+            self.rebuild(self.slots.len() / 2, Vec::new());
+        }
+        Some(removed.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.hops.iter_mut().for_each(|hop| *hop = 0);
+        self.overflow.clear();
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: self.overflow.iter(),
+            remaining: self.len,
+        }
+    }
+
+    pub fn probe_stats(&self) -> ProbeStats {
+        ProbeStats::new(self.slots.iter().enumerate().filter_map(|(i, slot)| {
+            slot.as_ref()
+                .map(|slot| self.distance(self.home(slot.hash), i))
+        }))
+    }
+
+    // ハッシュ、各要素が近所の中にあって home の hop に記録されているか overflow にあること、len、負荷率が正しいか確かめる
+    pub fn check_invariants(&self) {
+        assert!(
+            self.slots.is_empty() || self.slots.len().is_power_of_two(),
+            "slots is not a power of two"
+        );
+        assert_eq!(
+            self.slots.len(),
+            self.hops.len(),
+            "hops does not match slots"
+        );
+        let mut count = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+            count += 1;
+            assert_eq!(slot.hash, self.hasher.hash_one(&slot.key), "hash is stale");
+            let home = self.home(slot.hash);
+            let d = self.distance(home, i);
+            assert!(d < HOP, "slot is out of the neighborhood");
+            assert!(self.hops[home] & (1 << d) != 0, "hop does not record slot");
+        }
+        for slot in &self.overflow {
+            assert_eq!(slot.hash, self.hasher.hash_one(&slot.key), "hash is stale");
+        }
+        assert_eq!(count + self.overflow.len(), self.len, "len is stale");
+        let recorded: u32 = self.hops.iter().map(|hop| hop.count_ones()).sum();
+        assert_eq!(recorded as usize, count, "hop records an empty slot");
+        assert!(
+            self.len * 8 <= self.slots.len() * 7,
+            "load factor is too high"
+        );
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for HopscotchMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for HopscotchMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            slots: self.slots.iter(),
+            overflow: self.overflow.iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a HopscotchMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for HopscotchMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for HopscotchMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::BuildHasherDefault,
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::testing::Constant;
+
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = HopscotchMap::new();
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("a", 2), Some(1));
+        assert_eq!(map.get(&"a"), Some(&2));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.remove(&"a"), Some(2));
+        assert!(map.is_empty());
+        map.check_invariants();
+    }
+
+    // 負荷率 7/8 近くまで入れても、全ての要素が近所の中にある
+    #[test]
+    fn high_load_factor() {
+        let mut map =
+            HopscotchMap::<u64, u64, Fixed>::with_capacity_and_hasher(7000, Fixed::default());
+        let capacity = map.capacity();
+        for k in 0..capacity as u64 {
+            map.insert(k, k);
+        }
+        map.check_invariants();
+        assert!(map.probe_stats().max < HOP);
+        assert!((0..capacity as u64).all(|k| map.get(&k) == Some(&k)));
+    }
+
+    // 同じ home の key が近所に入り切らなくても、配列を大きくし続けずに overflow に置く
+    #[test]
+    fn colliding_keys_use_overflow() {
+        let mut map = HopscotchMap::<u64, u64, Constant>::default();
+        for k in 0..1000 {
+            map.insert(k, k * 2);
+        }
+        map.check_invariants();
+        assert!(map.slots.len() <= 1000 * 8);
+        assert!((0..1000).all(|k| map.get(&k) == Some(&(k * 2))));
+        assert_eq!(map.iter().count(), 1000);
+        for k in 0..990 {
+            assert_eq!(map.remove(&k), Some(k * 2));
+        }
+        map.check_invariants();
+        assert!((990..1000).all(|k| map.get(&k) == Some(&(k * 2))));
+    }
+
+    proptest! {
+        // HashMap と同じ操作をして比べる
+        #[test]
+        fn same_as_hash_map_random(ops in prop::collection::vec((0..3u8, 0..256u32, 0..100u32), 0..500)) {
+            let mut map = HopscotchMap::<u32, u32, Fixed>::default();
+            let mut model = HashMap::new();
+            for (op, key, value) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(map.insert(key, value), model.insert(key, value)),
+                    _ => prop_assert_eq!(map.remove(&key), model.remove(&key)),
+                }
+                map.check_invariants();
+                prop_assert_eq!(map.len(), model.len());
+            }
+            let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+            prop_assert_eq!(&actual, &model);
+            for key in 0..256 {
+                prop_assert_eq!(map.get(&key), model.get(&key));
+            }
+        }
+    }
+}
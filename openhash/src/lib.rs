@@ -0,0 +1,487 @@
+// 線形探査 (linear probing) の open addressing によるハッシュ map
+// 要素は 2 の冪の大きさの配列に直接置き、ハッシュで決まる位置 (home) から空きまで 1 つずつ進んで探す
+//
+// 削除では墓標 (tombstone) を残さず、後ろに続く要素を 1 つずつ前へ詰める (backward shift)
+// そのため、どの要素も home から自分の位置までの間に空きがない状態を保ち、探索は空きに着いたら止められる
+//
+// 要素数が容量の 3/4 を超えると容量を倍にし、1/8 を下回ると半分にする
+//
+// robinhood に、同じ配列と同じ容量の決め方で、探索の長さを均す Robin Hood hashing の版を置く
+// cuckoo に、key を 2 か所のどちらかにだけ置き、探索を最悪でも O(1) にする cuckoo hashing の版を置く
+// hopscotch に、key を home の近くにだけ置き、高い負荷率でも探索を短く保つ hopscotch hashing の版を置く
+// swiss に、制御バイトを SIMD でまとめて比べる SwissTable の版を置く
+pub mod cuckoo;
+mod group;
+pub mod hopscotch;
+pub mod robinhood;
+pub mod swiss;
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::{FromIterator, FusedIterator},
+    mem, slice,
+};
+
+// 最初に確保する容量
+pub(crate) const MIN_CAPACITY: usize = 8;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Slot<K, V> {
+    pub(crate) hash: u64,
+    pub(crate) key: K,
+    pub(crate) value: V,
+}
+
+// 各要素の home から位置までの距離 (探索で余分に進む数) をまとめたもの
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeStats {
+    pub max: usize,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl ProbeStats {
+    pub(crate) fn new(distances: impl Iterator<Item = usize>) -> Self {
+        let (mut count, mut max, mut sum, mut squares) = (0, 0, 0, 0);
+        for d in distances {
+            count += 1;
+            max = max.max(d);
+            sum += d;
+            squares += d * d;
+        }
+        if count == 0 {
+            return ProbeStats {
+                max: 0,
+                mean: 0.0,
+                variance: 0.0,
+            };
+        }
+        let mean = sum as f64 / count as f64;
+        ProbeStats {
+            max,
+            mean,
+            variance: squares as f64 / count as f64 - mean * mean,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OpenHashMap<K, V, S = RandomState> {
+    slots: Vec<Option<Slot<K, V>>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> OpenHashMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> OpenHashMap<K, V, S> {
+    // 最初の insert まで配列を確保しない
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            slots: Vec::new(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    // capacity 個入れるまで配列を作り直さない
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            map.resize(slots_for(capacity));
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 配列を作り直さずに入れられる要素の数
+    pub fn capacity(&self) -> usize {
+        self.slots.len() / 4 * 3
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        hash as usize & self.mask()
+    }
+
+    // key のある位置。なければ None
+    fn find(&self, hash: u64, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut i = self.home(hash);
+        loop {
+            match &self.slots[i] {
+                None => return None,
+                Some(slot) if slot.hash == hash && slot.key == *key => return Some(i),
+                Some(_) => i = (i + 1) & self.mask(),
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_ref().map(|slot| &slot.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_mut().map(|slot| &mut slot.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    // key があれば値を入れ替えて前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        if let Some(i) = self.find(hash, &key) {
+            let slot = self.slots[i].as_mut().expect("found slot is occupied");
+            return Some(mem::replace(&mut slot.value, value));
+        }
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            self.resize((self.slots.len() * 2).max(MIN_CAPACITY));
+        }
+        self.place(Slot { hash, key, value });
+        self.len += 1;
+        None
+    }
+
+    // home から最初の空きに置く。key がまだないことは呼び出し側で確かめておく
+    fn place(&mut self, slot: Slot<K, V>) {
+        let mut i = self.home(slot.hash);
+        while self.slots[i].is_some() {
+            i = (i + 1) & self.mask();
+        }
+        self.slots[i] = Some(slot);
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let mut hole = self.find(self.hasher.hash_one(key), key)?;
+        let removed = self.slots[hole].take().expect("found slot is occupied");
+        // 空いた位置から次の空きまでの要素のうち、home が空いた位置より前にあるものを空いた位置へ詰める
+        // 詰めた要素の元の位置が新しく空くので、そこから続ける (Knuth の Algorithm R)
+        let mut next = hole;
+        loop {
+            next = (next + 1) & self.mask();
+            let hash = match &self.slots[next] {
+                Some(slot) => slot.hash,
+                None => break,
+            };
+            // home が hole と next の間 (hole, next] にあれば、動かすと home より前に来てしまう
+            if self.distance(hash, next) >= self.distance(hash, hole) {
+                self.slots[hole] = self.slots[next].take();
+                hole = next;
+            }
+        }
+        self.len -= 1;
+        if self.slots.len() > MIN_CAPACITY && self.len * 8 < self.slots.len() {
+            self.resize(self.slots.len() / 2);
+        }
+        Some(removed.value)
+    }
+
+    // 配列を slots 個にして入れ直す。ハッシュは持っているので計算し直さない
+    fn resize(&mut self, slots: usize) {
+        let old = mem::replace(&mut self.slots, (0..slots).map(|_| None).collect());
+        for slot in old.into_iter().flatten() {
+            self.place(slot);
+        }
+    }
+
+    // 配列は残しておく
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.len = 0;
+    }
+
+    // 配列の順に返すので、キーの順序には意味がない
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    pub fn probe_stats(&self) -> ProbeStats {
+        ProbeStats::new(
+            self.slots
+                .iter()
+                .enumerate()
+                .filter_map(|(i, slot)| slot.as_ref().map(|slot| self.distance(slot.hash, i))),
+        )
+    }
+
+    fn distance(&self, hash: u64, i: usize) -> usize {
+        i.wrapping_sub(self.home(hash)) & self.mask()
+    }
+
+    // ハッシュ、home から位置までに空きがないこと、len、負荷率が正しいか確かめる
+    pub fn check_invariants(&self) {
+        assert!(
+            self.slots.is_empty() || self.slots.len().is_power_of_two(),
+            "slots is not a power of two"
+        );
+        let mut count = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+            count += 1;
+            assert_eq!(slot.hash, self.hasher.hash_one(&slot.key), "hash is stale");
+            let home = self.home(slot.hash);
+            for d in 0..self.distance(slot.hash, i) {
+                assert!(
+                    self.slots[(home + d) & self.mask()].is_some(),
+                    "empty slot between home and slot"
+                );
+            }
+        }
+        assert_eq!(count, self.len, "len is stale");
+        assert!(
+            self.len * 4 <= self.slots.len() * 3,
+            "load factor is too high"
+        );
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for OpenHashMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for OpenHashMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    pub(crate) slots: slice::Iter<'a, Option<Slot<K, V>>>,
+    // 配列に置けずに別に持っている要素。配列の後に返す
+    pub(crate) overflow: slice::Iter<'a, Slot<K, V>>,
+    pub(crate) remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let slot = match self.slots.find_map(|slot| slot.as_ref()) {
+            Some(slot) => slot,
+            None => self.overflow.next()?,
+        };
+        self.remaining -= 1;
+        Some((&slot.key, &slot.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a OpenHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for OpenHashMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for OpenHashMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+// capacity 個入れても負荷率が 3/4 以下になる配列の大きさ
+pub(crate) fn slots_for(capacity: usize) -> usize {
+    (capacity * 4)
+        .div_ceil(3)
+        .next_power_of_two()
+        .max(MIN_CAPACITY)
+}
+
+// ハッシュが偏ったときの振る舞いを確かめるための BuildHasher
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    // どの key も同じハッシュになる
+    #[derive(Default)]
+    pub(crate) struct ConstantHasher;
+
+    impl Hasher for ConstantHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    pub(crate) type Constant = BuildHasherDefault<ConstantHasher>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        hash::{BuildHasherDefault, Hasher},
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = OpenHashMap::new();
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("b", 2), None);
+        assert_eq!(map.insert("a", 3), Some(1));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&"a"), Some(&3));
+        *map.get_mut(&"b").unwrap() += 10;
+        assert_eq!(map.remove(&"b"), Some(12));
+        assert_eq!(map.remove(&"b"), None);
+        assert!(!map.contains_key(&"b"));
+        map.check_invariants();
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn resize_policy() {
+        let mut map: OpenHashMap<u32, u32> = OpenHashMap::with_capacity(100);
+        let capacity = map.capacity();
+        assert!(capacity >= 100);
+        map.extend((0..100).map(|k| (k, k)));
+        assert_eq!(map.capacity(), capacity);
+        map.extend((100..1000).map(|k| (k, k)));
+        assert!(map.capacity() >= 1000);
+        for k in 0..990 {
+            map.remove(&k);
+        }
+        map.check_invariants();
+        // 1/8 を下回ったら縮む
+        assert!(map.capacity() < 100);
+        assert_eq!(map.keys().count(), 10);
+    }
+
+    // 下位 2 ビットしか使わないハッシュで、長い探索の列を作る
+    #[derive(Default)]
+    struct Weak(u64);
+
+    impl Hasher for Weak {
+        fn finish(&self) -> u64 {
+            self.0 & 3
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = self.0.wrapping_mul(31).wrapping_add(b as u64);
+            }
+        }
+    }
+
+    type WeakMap = OpenHashMap<u32, u32, BuildHasherDefault<Weak>>;
+
+    #[test]
+    fn backward_shift() {
+        let mut map: WeakMap = (0..20).map(|k| (k, k * 10)).collect();
+        assert!(map.probe_stats().max >= 16);
+        for k in (0..20).filter(|k| k % 3 == 0) {
+            assert_eq!(map.remove(&k), Some(k * 10));
+            map.check_invariants();
+        }
+        for k in 0..20 {
+            assert_eq!(map.get(&k), (k % 3 != 0).then_some(&(k * 10)));
+        }
+    }
+
+    proptest! {
+        // HashMap と同じ操作をして比べる
+        #[test]
+        fn same_as_hash_map_random(
+            weak in any::<bool>(),
+            ops in prop::collection::vec((0..3u8, 0..64u32, 0..100u32), 0..300),
+        ) {
+            fn run<S: BuildHasher + Default>(ops: &[(u8, u32, u32)]) -> Result<(), TestCaseError> {
+                let mut map = OpenHashMap::<u32, u32, S>::default();
+                let mut model = HashMap::new();
+                for &(op, key, value) in ops {
+                    match op {
+                        0 | 1 => prop_assert_eq!(map.insert(key, value), model.insert(key, value)),
+                        _ => prop_assert_eq!(map.remove(&key), model.remove(&key)),
+                    }
+                    map.check_invariants();
+                    prop_assert_eq!(map.len(), model.len());
+                }
+                let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+                prop_assert_eq!(&actual, &model);
+                for key in 0..64 {
+                    prop_assert_eq!(map.get(&key), model.get(&key));
+                }
+                Ok(())
+            }
+            if weak {
+                run::<BuildHasherDefault<Weak>>(&ops)?;
+            } else {
+                run::<RandomState>(&ops)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+// Robin Hood hashing の線形探査
+// insert で空きを探す途中、自分より home からの距離が短い要素に会ったら、その位置を奪い、
+// 追い出した要素で探索を続ける。同じ塊 (cluster) の中では要素が home の順に並ぶ
+//
+// 占める位置の集まりは OpenHashMap と変わらないので、距離の平均は同じになる
+// 距離の長い要素が短い要素から位置を奪うので、最大値と分散が小さくなる
+// 探索では、いま進んだ距離より距離の短い要素に会った時点で、key がないと分かる
+//
+// 削除は、後ろに続く要素を home にいる要素か空きに当たるまで 1 つずつ前へ詰める
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    mem,
+};
+
+use crate::{slots_for, Iter, ProbeStats, Slot, MIN_CAPACITY};
+
+#[derive(Clone)]
+pub struct RobinHoodMap<K, V, S = RandomState> {
+    slots: Vec<Option<Slot<K, V>>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> RobinHoodMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> RobinHoodMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            slots: Vec::new(),
+            len: 0,
+            hasher,
+        }
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            map.resize(slots_for(capacity));
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len() / 4 * 3
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    fn home(&self, hash: u64) -> usize {
+        hash as usize & self.mask()
+    }
+
+    fn distance(&self, hash: u64, i: usize) -> usize {
+        i.wrapping_sub(self.home(hash)) & self.mask()
+    }
+
+    fn find(&self, hash: u64, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let mut i = self.home(hash);
+        let mut d = 0;
+        loop {
+            match &self.slots[i] {
+                Some(slot) if slot.hash == hash && slot.key == *key => return Some(i),
+                // 距離の短い要素に会ったら、key があればこの要素より前に置かれている
+                Some(slot) if self.distance(slot.hash, i) >= d => {
+                    i = (i + 1) & self.mask();
+                    d += 1;
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_ref().map(|slot| &slot.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_mut().map(|slot| &mut slot.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        if let Some(i) = self.find(hash, &key) {
+            let slot = self.slots[i].as_mut().expect("found slot is occupied");
+            return Some(mem::replace(&mut slot.value, value));
+        }
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            self.resize((self.slots.len() * 2).max(MIN_CAPACITY));
+        }
+        self.place(Slot { hash, key, value });
+        self.len += 1;
+        None
+    }
+
+    // 距離の短い要素から位置を奪いながら、空きまで進む
+    fn place(&mut self, mut slot: Slot<K, V>) {
+        let mut i = self.home(slot.hash);
+        let mut d = 0;
+        loop {
+            let other_d = match &self.slots[i] {
+                None => {
+                    self.slots[i] = Some(slot);
+                    return;
+                }
+                Some(other) => self.distance(other.hash, i),
+            };
+            if other_d < d {
+                let other = self.slots[i].as_mut().expect("slot is occupied");
+                mem::swap(other, &mut slot);
+                d = other_d;
+            }
+            i = (i + 1) & self.mask();
+            d += 1;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let mut hole = self.find(self.hasher.hash_one(key), key)?;
+        let removed = self.slots[hole].take().expect("found slot is occupied");
+        // 要素は home の順に並んでいるので、home にいない要素は 1 つ前へ詰めてよい
+        loop {
+            let next = (hole + 1) & self.mask();
+            match &self.slots[next] {
+                Some(slot) if self.home(slot.hash) != next => {
+                    self.slots[hole] = self.slots[next].take();
+                    hole = next;
+                }
+                _ => break,
+            }
+        }
+        self.len -= 1;
+        if self.slots.len() > MIN_CAPACITY && self.len * 8 < self.slots.len() {
+            self.resize(self.slots.len() / 2);
+        }
+        Some(removed.value)
+    }
+
+    fn resize(&mut self, slots: usize) {
+        let old = mem::replace(&mut self.slots, (0..slots).map(|_| None).collect());
+        for slot in old.into_iter().flatten() {
+            self.place(slot);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    pub fn probe_stats(&self) -> ProbeStats {
+        ProbeStats::new(
+            self.slots
+                .iter()
+                .enumerate()
+                .filter_map(|(i, slot)| slot.as_ref().map(|slot| self.distance(slot.hash, i))),
+        )
+    }
+
+    // OpenHashMap の条件に加えて、空きでない隣り合う位置で距離が 2 以上増えないことを確かめる
+    // (増えるなら、後ろの要素が前の要素から位置を奪えたはず)
+    pub fn check_invariants(&self) {
+        assert!(
+            self.slots.is_empty() || self.slots.len().is_power_of_two(),
+            "slots is not a power of two"
+        );
+        let mut count = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            let slot = match slot {
+                Some(slot) => slot,
+                None => continue,
+            };
+            count += 1;
+            assert_eq!(slot.hash, self.hasher.hash_one(&slot.key), "hash is stale");
+            let d = self.distance(slot.hash, i);
+            if d > 0 {
+                let prev = self.slots[(i + self.mask()) & self.mask()]
+                    .as_ref()
+                    .expect("empty slot between home and slot");
+                let prev_d = self.distance(prev.hash, (i + self.mask()) & self.mask());
+                assert!(prev_d + 1 >= d, "slot should have displaced a richer one");
+            }
+        }
+        assert_eq!(count, self.len, "len is stale");
+        assert!(
+            self.len * 4 <= self.slots.len() * 3,
+            "load factor is too high"
+        );
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for RobinHoodMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for RobinHoodMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a RobinHoodMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for RobinHoodMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for RobinHoodMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::BuildHasherDefault,
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::OpenHashMap;
+
+    // 同じ key に同じハッシュを返すように、seed を固定した hasher を使う
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = RobinHoodMap::new();
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("a", 2), Some(1));
+        assert_eq!(map.get(&"a"), Some(&2));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.remove(&"a"), Some(2));
+        assert!(map.is_empty());
+        map.check_invariants();
+    }
+
+    // 同じ key を同じ順に入れると、平均は同じで、最大値と分散は小さいか同じになる
+    #[test]
+    fn lower_variance_than_linear_probing() {
+        let keys: Vec<u64> = (0..5000).map(|k| k * 7919).collect();
+        let robin: RobinHoodMap<_, _, Fixed> = keys.iter().map(|&k| (k, k)).collect();
+        let linear: OpenHashMap<_, _, Fixed> = keys.iter().map(|&k| (k, k)).collect();
+        let (r, l) = (robin.probe_stats(), linear.probe_stats());
+        assert!((r.mean - l.mean).abs() < 1e-9, "{:?} {:?}", r, l);
+        assert!(r.max <= l.max, "{:?} {:?}", r, l);
+        assert!(r.variance <= l.variance + 1e-9, "{:?} {:?}", r, l);
+    }
+
+    proptest! {
+        // HashMap と同じ操作をして比べ、OpenHashMap と探索の距離の平均が同じか確かめる
+        #[test]
+        fn same_as_hash_map_random(ops in prop::collection::vec((0..3u8, 0..64u32, 0..100u32), 0..300)) {
+            let mut map = RobinHoodMap::<u32, u32, Fixed>::default();
+            let mut linear = OpenHashMap::<u32, u32, Fixed>::default();
+            let mut model = HashMap::new();
+            for (op, key, value) in ops {
+                match op {
+                    0 | 1 => {
+                        linear.insert(key, value);
+                        prop_assert_eq!(map.insert(key, value), model.insert(key, value));
+                    }
+                    _ => {
+                        linear.remove(&key);
+                        prop_assert_eq!(map.remove(&key), model.remove(&key));
+                    }
+                }
+                map.check_invariants();
+                prop_assert_eq!(map.len(), model.len());
+                let (r, l) = (map.probe_stats(), linear.probe_stats());
+                prop_assert!((r.mean - l.mean).abs() < 1e-9);
+                prop_assert!(r.max <= l.max);
+            }
+            let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+            prop_assert_eq!(&actual, &model);
+            for key in 0..64 {
+                prop_assert_eq!(map.get(&key), model.get(&key));
+            }
+        }
+    }
+}
@@ -0,0 +1,402 @@
+// SwissTable (Abseil の flat_hash_map、hashbrown) に倣ったハッシュ map
+// 各位置に 1 バイトの制御バイトを持ち、空き、削除した跡、ハッシュの上位 7 ビット (h2) のどれかを入れる
+// 探索は制御バイトを group::WIDTH 個ずつまとめて h2 と比べ (SSE2 なら 16 バイトを 1 命令で)、
+// 一致した位置の key だけを比べる。group に空きがあれば、そこで key がないと分かる
+//
+// group の始まりは、ハッシュの下位ビット (h1) から group の幅ずつ増える幅で進む (triangular probing)
+// 位置の数は group の幅以上の 2 の冪にするので、全ての group を一度ずつ回る
+// 配列の末尾に先頭の WIDTH 個の制御バイトの写しを置き、末尾をまたぐ group も 1 回で読めるようにする
+//
+// 削除は、前後に空きがなく group の幅だけ続いているときだけ墓標を残し、それ以外は空きに戻す
+// 要素と墓標が 7/8 に達したら、要素が半分を超えていれば倍に、そうでなければ同じ大きさで入れ直して墓標を消す
+// 要素が 1/8 を下回ると半分にする
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    mem,
+};
+
+use crate::{
+    group::{Group, DELETED, EMPTY, WIDTH},
+    Iter, Slot,
+};
+
+// 写しが先頭と重ならないように、位置の数は group の幅以上にする
+const MIN_BUCKETS: usize = if WIDTH > 8 { WIDTH } else { 8 };
+
+fn h1(hash: u64) -> usize {
+    hash as usize
+}
+
+fn h2(hash: u64) -> u8 {
+    (hash >> 57) as u8
+}
+
+// buckets 個の位置に入れられる要素と墓標の数
+fn capacity_of(buckets: usize) -> usize {
+    buckets / 8 * 7
+}
+
+#[derive(Clone)]
+pub struct SwissMap<K, V, S = RandomState> {
+    // buckets + WIDTH バイト。末尾の WIDTH バイトは先頭の写し
+    ctrl: Vec<u8>,
+    slots: Vec<Option<Slot<K, V>>>,
+    len: usize,
+    // 空きを埋めてよい残りの数。墓標を埋めても減らない
+    growth_left: usize,
+    hasher: S,
+}
+
+impl<K: Hash + Eq, V> SwissMap<K, V> {
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> SwissMap<K, V, S> {
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            ctrl: Vec::new(),
+            slots: Vec::new(),
+            len: 0,
+            growth_left: 0,
+            hasher,
+        }
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        let mut map = Self::with_hasher(hasher);
+        if capacity > 0 {
+            let buckets = (capacity * 8).div_ceil(7).next_power_of_two();
+            map.rebuild(buckets.max(MIN_BUCKETS));
+        }
+        map
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        capacity_of(self.slots.len())
+    }
+
+    fn mask(&self) -> usize {
+        self.slots.len() - 1
+    }
+
+    // h1 から始まる group の先頭の位置を順に返す
+    fn probe(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let mask = self.mask();
+        let mut pos = h1(hash) & mask;
+        let mut stride = 0;
+        std::iter::from_fn(move || {
+            let current = pos;
+            stride += WIDTH;
+            pos = (pos + stride) & mask;
+            Some(current)
+        })
+    }
+
+    fn find(&self, hash: u64, key: &K) -> Option<usize> {
+        if self.slots.is_empty() {
+            return None;
+        }
+        for pos in self.probe(hash) {
+            let group = Group::load(&self.ctrl[pos..]);
+            for bit in group.match_byte(h2(hash)) {
+                let i = (pos + bit) & self.mask();
+                if matches!(&self.slots[i], Some(slot) if slot.hash == hash && slot.key == *key) {
+                    return Some(i);
+                }
+            }
+            if group.match_empty().any() {
+                return None;
+            }
+        }
+        unreachable!("probe never ends")
+    }
+
+    // hash の要素を置ける、最初の空きか墓標の位置
+    fn find_insert_slot(&self, hash: u64) -> usize {
+        for pos in self.probe(hash) {
+            if let Some(bit) = Group::load(&self.ctrl[pos..])
+                .match_empty_or_deleted()
+                .lowest()
+            {
+                return (pos + bit) & self.mask();
+            }
+        }
+        unreachable!("probe never ends")
+    }
+
+    // 先頭の WIDTH 個は末尾の写しも書き換える
+    fn set_ctrl(&mut self, i: usize, byte: u8) {
+        self.ctrl[i] = byte;
+        if i < WIDTH {
+            let buckets = self.slots.len();
+            self.ctrl[buckets + i] = byte;
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_ref().map(|slot| &slot.value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        self.slots[i].as_mut().map(|slot| &mut slot.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        if let Some(i) = self.find(hash, &key) {
+            let slot = self.slots[i].as_mut().expect("found slot is occupied");
+            return Some(mem::replace(&mut slot.value, value));
+        }
+        if self.slots.is_empty() {
+            self.rebuild(MIN_BUCKETS);
+        }
+        let mut i = self.find_insert_slot(hash);
+        if self.growth_left == 0 && self.ctrl[i] == EMPTY {
+            // 要素が半分を超えていれば倍に、そうでなければ墓標を消すだけ
+            let buckets = self.slots.len();
+            if self.len + 1 > capacity_of(buckets) / 2 {
+                self.rebuild(buckets * 2);
+            } else {
+                self.rebuild(buckets);
+            }
+            i = self.find_insert_slot(hash);
+        }
+        if self.ctrl[i] == EMPTY {
+            self.growth_left -= 1;
+        }
+        self.set_ctrl(i, h2(hash));
+        self.slots[i] = Some(Slot { hash, key, value });
+        self.len += 1;
+        None
+    }
+
+    // 位置を buckets 個にして入れ直す。墓標はなくなる
+    fn rebuild(&mut self, buckets: usize) {
+        let old = mem::replace(&mut self.slots, (0..buckets).map(|_| None).collect());
+        self.ctrl = vec![EMPTY; buckets + WIDTH];
+        self.growth_left = capacity_of(buckets);
+        for slot in old.into_iter().flatten() {
+            let i = self.find_insert_slot(slot.hash);
+            self.set_ctrl(i, h2(slot.hash));
+            self.slots[i] = Some(slot);
+            self.growth_left -= 1;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.find(self.hasher.hash_one(key), key)?;
+        let removed = self.slots[i].take().expect("found slot is occupied");
+        // i を含む WIDTH 個の位置が空きなしで続いていれば、そこを通り過ぎた探索があるかもしれない
+        let mask = self.mask();
+        let full = |d: &usize| self.ctrl[d & mask] != EMPTY;
+        let before = (1..WIDTH)
+            .map(|d| i.wrapping_sub(d))
+            .take_while(full)
+            .count();
+        let after = (1..WIDTH).map(|d| i + d).take_while(full).count();
+        if before + 1 + after >= WIDTH {
+            self.set_ctrl(i, DELETED);
+        } else {
+            self.set_ctrl(i, EMPTY);
+            self.growth_left += 1;
+        }
+        self.len -= 1;
+        let buckets = self.slots.len();
+        if buckets > MIN_BUCKETS && self.len * 8 < buckets {
+            self.rebuild(buckets / 2);
+        }
+        Some(removed.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.iter_mut().for_each(|slot| *slot = None);
+        self.ctrl.iter_mut().for_each(|byte| *byte = EMPTY);
+        self.growth_left = capacity_of(self.slots.len());
+        self.len = 0;
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    // 墓標の数
+    pub fn tombstones(&self) -> usize {
+        self.ctrl[..self.slots.len()]
+            .iter()
+            .filter(|&&byte| byte == DELETED)
+            .count()
+    }
+
+    // 制御バイトと要素、写し、ハッシュ、各要素が探索で見つかること、len、growth_left が正しいか確かめる
+    pub fn check_invariants(&self) {
+        let buckets = self.slots.len();
+        if buckets == 0 {
+            assert!(self.ctrl.is_empty() && self.len == 0);
+            return;
+        }
+        assert!(
+            buckets.is_power_of_two() && buckets >= MIN_BUCKETS,
+            "buckets is not a power of two"
+        );
+        assert_eq!(self.ctrl.len(), buckets + WIDTH, "ctrl has a wrong length");
+        assert_eq!(self.ctrl[..WIDTH], self.ctrl[buckets..], "mirror is stale");
+        let mut count = 0;
+        for (i, slot) in self.slots.iter().enumerate() {
+            match slot {
+                Some(slot) => {
+                    count += 1;
+                    assert_eq!(slot.hash, self.hasher.hash_one(&slot.key), "hash is stale");
+                    assert_eq!(self.ctrl[i], h2(slot.hash), "ctrl does not hold h2");
+                    assert_eq!(
+                        self.find(slot.hash, &slot.key),
+                        Some(i),
+                        "slot is unreachable"
+                    );
+                }
+                None => assert!(
+                    self.ctrl[i] == EMPTY || self.ctrl[i] == DELETED,
+                    "ctrl is full at an empty slot"
+                ),
+            }
+        }
+        assert_eq!(count, self.len, "len is stale");
+        assert_eq!(
+            self.growth_left + self.len + self.tombstones(),
+            capacity_of(buckets),
+            "growth_left is stale"
+        );
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> Default for SwissMap<K, V, S> {
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for SwissMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let iter = Iter {
+            slots: self.slots.iter(),
+            overflow: [].iter(),
+            remaining: self.len,
+        };
+        f.debug_map().entries(iter).finish()
+    }
+}
+
+impl<'a, K: Hash + Eq, V, S: BuildHasher> IntoIterator for &'a SwissMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> Extend<(K, V)> for SwissMap<K, V, S> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher + Default> FromIterator<(K, V)> for SwissMap<K, V, S> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::BuildHasherDefault,
+    };
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = SwissMap::new();
+        assert_eq!(map.insert("a", 1), None);
+        assert_eq!(map.insert("a", 2), Some(1));
+        assert_eq!(map.get(&"a"), Some(&2));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.remove(&"a"), Some(2));
+        assert!(map.is_empty());
+        map.check_invariants();
+    }
+
+    // 入れて消すのを繰り返しても、墓標を消すだけで配列は大きくならない
+    #[test]
+    fn tombstones_are_reclaimed() {
+        let mut map = SwissMap::<u64, u64, Fixed>::with_capacity_and_hasher(1000, Fixed::default());
+        let capacity = map.capacity();
+        map.extend((0..800).map(|k| (k, k)));
+        for k in 800..20_000 {
+            map.insert(k, k);
+            map.remove(&(k - 800));
+        }
+        map.check_invariants();
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.len(), 800);
+        assert!((19_200..20_000).all(|k| map.get(&k) == Some(&k)));
+    }
+
+    proptest! {
+        // HashMap と同じ操作をして比べる
+        #[test]
+        fn same_as_hash_map_random(ops in prop::collection::vec((0..3u8, 0..256u32, 0..100u32), 0..500)) {
+            let mut map = SwissMap::<u32, u32, Fixed>::default();
+            let mut model = HashMap::new();
+            for (op, key, value) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(map.insert(key, value), model.insert(key, value)),
+                    _ => prop_assert_eq!(map.remove(&key), model.remove(&key)),
+                }
+                map.check_invariants();
+                prop_assert_eq!(map.len(), model.len());
+            }
+            let actual: HashMap<_, _> = map.iter().map(|(k, v)| (*k, *v)).collect();
+            prop_assert_eq!(&actual, &model);
+            for key in 0..256 {
+                prop_assert_eq!(map.get(&key), model.get(&key));
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+
+mod op;
+
+pub use op::Op;
+
+// キー順に並んだ map の共通の操作
+// bplus と unsafebplus の木を、型引数で差し替えて使えるようにする
+pub trait OrderedMap<K: Ord + Copy, V> {
+    type Iter<'a>: Iterator<Item = (K, &'a V)>
+    where
+        Self: 'a,
+        V: 'a;
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+
+    fn get(&self, key: K) -> Option<&V>;
+
+    fn remove(&mut self, key: K) -> Option<V>;
+
+    // キー順に (key, value) を返す
+    fn iter(&self) -> Self::Iter<'_>;
+
+    // min_key <= key <= max_key のエントリをキー順で返す
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        self.iter()
+            .skip_while(|(k, _)| *k < min_key)
+            .take_while(|(k, _)| *k <= max_key)
+            .collect()
+    }
+}
+
+// 比較の基準にできるように、標準の BTreeMap にも実装しておく
+impl<K: Ord + Copy, V> OrderedMap<K, V> for BTreeMap<K, V> {
+    type Iter<'a>
+        = std::iter::Map<
+        std::collections::btree_map::Iter<'a, K, V>,
+        fn((&'a K, &'a V)) -> (K, &'a V),
+    >
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        BTreeMap::get(self, &key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        BTreeMap::remove(self, &key)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        BTreeMap::iter(self).map(|(k, v)| (*k, v))
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        if min_key > max_key {
+            return Vec::new();
+        }
+        BTreeMap::range(self, min_key..=max_key)
+            .map(|(k, v)| (*k, v))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 実装に依らない操作だけを使う
+    fn exercise<M: OrderedMap<usize, i64>>(m: &mut M) {
+        for k in [5, 1, 9, 3, 7] {
+            assert_eq!(m.insert(k, -(k as i64)), None);
+        }
+        assert_eq!(m.insert(3, 30), Some(-3));
+        assert_eq!(m.get(3), Some(&30));
+        assert_eq!(m.remove(9), Some(-9));
+        assert_eq!(m.remove(9), None);
+        let keys: Vec<_> = m.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![1, 3, 5, 7]);
+        assert_eq!(m.range(2, 5), vec![(3, &30), (5, &-5)]);
+        assert_eq!(m.range(5, 2), vec![]);
+    }
+
+    #[test]
+    fn btree_map() {
+        exercise(&mut BTreeMap::new());
+    }
+}
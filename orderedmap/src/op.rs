@@ -0,0 +1,70 @@
+// OrderedMap への操作。fuzz や property test の入力に使う
+// arbitrary feature を有効にすると、バイト列から操作列を作れる
+use crate::OrderedMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Search(K),
+    // min_key, max_key
+    Range(K, K),
+}
+
+impl<K: Ord + Copy, V: Clone> Op<K, V> {
+    // 操作の結果を (key, value) の列で返す。実装どうしを比べられるように値は複製する
+    //   Insert: 置き換えた前の値
+    //   Remove: 削除した値
+    //   Search: 見つけた値
+    //   Range : 範囲内の全てのエントリ
+    pub fn apply<M: OrderedMap<K, V>>(self, map: &mut M) -> Vec<(K, V)> {
+        match self {
+            Op::Insert(key, value) => map
+                .insert(key, value)
+                .map(|v| (key, v))
+                .into_iter()
+                .collect(),
+            Op::Remove(key) => map.remove(key).map(|v| (key, v)).into_iter().collect(),
+            Op::Search(key) => map.get(key).map(|v| (key, v.clone())).into_iter().collect(),
+            Op::Range(min_key, max_key) => map
+                .range(min_key, max_key)
+                .into_iter()
+                .map(|(k, v)| (k, v.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let mut m = BTreeMap::new();
+        assert_eq!(Op::Insert(1, 'a').apply(&mut m), vec![]);
+        assert_eq!(Op::Insert(1, 'b').apply(&mut m), vec![(1, 'a')]);
+        assert_eq!(Op::Insert(3, 'c').apply(&mut m), vec![]);
+        assert_eq!(Op::Search(1).apply(&mut m), vec![(1, 'b')]);
+        assert_eq!(Op::Range(0, 5).apply(&mut m), vec![(1, 'b'), (3, 'c')]);
+        assert_eq!(Op::Remove(1).apply(&mut m), vec![(1, 'b')]);
+        assert_eq!(Op::Search(1).apply(&mut m), vec![]);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_ops() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let bytes: Vec<u8> = (1..1024).map(|i| (i * 31 % 251) as u8).collect();
+        let ops = Vec::<Op<u8, u8>>::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+        assert!(!ops.is_empty());
+        let mut m = BTreeMap::new();
+        for op in ops {
+            op.apply(&mut m);
+        }
+    }
+}
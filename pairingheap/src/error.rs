@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("handle does not point to an element of this heap")]
+    InvalidHandle,
+    #[error("new value would be taken out later than the current one")]
+    WorseValue,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,567 @@
+// ペアリングヒープ
+// 各ノードが一番左の子と右の兄弟を持つ多分木で、根が次に取り出すものになる
+// 2 つの木を繋ぐ (link) ときは、後に取り出す方の根を、先に取り出す方の根の一番左の子にする
+// push と meld は根同士を link するだけなので O(1)
+// pop は根の子を左から 2 つずつ link し、できた木を右から順に link する (two-pass)。償却 O(log n)
+// decrease_key は値を変えたノードを親から切り離して根と link する。償却 O(log n) 以下で、Ω(log log n) より速くはできないことが知られている
+//
+// ノードは Box で確保して生ポインタで繋ぐ。push が返す Handle で、後からそのノードの値を変えられる
+// Handle は取り出されたノードや別のヒープのノードを指していないか、使う前に確かめる
+//   - ノードと Handle は Slot を共有し、ノードを解放するときに Slot の指す先を消す
+//   - ヒープごとに Owner を持ち、meld で取り込まれたヒープの Owner は取り込んだ側を指す (union-find)
+// なので meld した後も、取り込まれた側の Handle を取り込んだヒープでそのまま使える
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    fmt,
+    iter::FromIterator,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+mod error;
+
+pub use error::{Error, Result};
+pub use priorityqueue::{Compare, Max, MergeableHeap, Min, PriorityQueue};
+
+type Link<T> = Option<NonNull<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    // 一番左の子
+    child: Link<T>,
+    // 右の兄弟
+    next: Link<T>,
+    // 左の兄弟。一番左の子なら親で、根なら None
+    prev: Link<T>,
+    slot: Rc<Slot<T>>,
+}
+
+// ノードと Handle で共有する。ノードを解放すると node を None にする
+struct Slot<T> {
+    node: Cell<Link<T>>,
+    owner: RefCell<Rc<Owner>>,
+}
+
+// ヒープごとの印。meld で取り込まれたヒープの印は、取り込んだヒープの印を指す
+#[derive(Default)]
+struct Owner {
+    merged: RefCell<Option<Rc<Owner>>>,
+}
+
+// merged を辿った先の、今も使われているヒープの印。辿った印は全て、そこを直接指すように繋ぎ直す
+fn resolve(start: &Rc<Owner>) -> Rc<Owner> {
+    let mut root = start.clone();
+    loop {
+        let next = root.merged.borrow().clone();
+        match next {
+            Some(next) => root = next,
+            None => break,
+        }
+    }
+    let mut current = start.clone();
+    while !Rc::ptr_eq(&current, &root) {
+        let next = current.merged.replace(Some(root.clone()));
+        current = next.expect("merged owner points to another owner");
+    }
+    root
+}
+
+// push で得る、ヒープのノードの目印。clone しても同じノードを指す
+pub struct Handle<T> {
+    slot: Rc<Slot<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("live", &self.slot.node.get().is_some())
+            .finish()
+    }
+}
+
+pub struct PairingHeap<T, C = Max> {
+    root: Link<T>,
+    len: usize,
+    owner: Rc<Owner>,
+    cmp: C,
+}
+
+impl<T: Ord> PairingHeap<T> {
+    // 大きいものから取り出す
+    pub fn new() -> Self {
+        Self::with_comparator(Max)
+    }
+}
+
+impl<T: Ord> PairingHeap<T, Min> {
+    // 小さいものから取り出す
+    pub fn new_min() -> Self {
+        Self::with_comparator(Min)
+    }
+}
+
+impl<T, C> PairingHeap<T, C> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 次に取り出す要素
+    pub fn peek(&self) -> Option<&T> {
+        // SAFETY: 根はこのヒープのノードで、&self を借用している間は変更も解放もされない
+        self.root.map(|root| unsafe { &(*root.as_ptr()).value })
+    }
+
+    // 全て解放する。残っている Handle は使えなくなる
+    pub fn clear(&mut self) {
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+        while let Some(node) = stack.pop() {
+            // SAFETY: 木のノードはそれぞれ 1 度だけ積まれ、ここで解放する
+            let node = unsafe { Box::from_raw(node.as_ptr()) };
+            node.slot.node.set(None);
+            stack.extend(node.child);
+            stack.extend(node.next);
+        }
+        self.len = 0;
+    }
+
+    // 木の並びのまま返す。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut stack: Vec<_> = self.root.into_iter().collect();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            // SAFETY: &self を借用している間は、ノードは変更も解放もされない
+            let node = unsafe { &*node.as_ptr() };
+            stack.extend(node.child);
+            stack.extend(node.next);
+            Some(&node.value)
+        })
+    }
+
+    // handle がこのヒープのノードを指していれば、そのノード
+    fn node(&self, handle: &Handle<T>) -> Link<T> {
+        let node = handle.slot.node.get()?;
+        let owner = resolve(&handle.slot.owner.borrow());
+        let mine = Rc::ptr_eq(&owner, &self.owner);
+        *handle.slot.owner.borrow_mut() = owner;
+        if mine {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+    // handle の指す要素。取り出し済みか、別のヒープの handle なら None
+    pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        // SAFETY: node はこのヒープの木にあり、&self を借用している間は変更も解放もされない
+        self.node(handle)
+            .map(|node| unsafe { &(*node.as_ptr()).value })
+    }
+
+    pub fn contains(&self, handle: &Handle<T>) -> bool {
+        self.node(handle).is_some()
+    }
+}
+
+impl<T, C: Compare<T>> PairingHeap<T, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            root: None,
+            len: 0,
+            owner: Rc::new(Owner::default()),
+            cmp,
+        }
+    }
+
+    pub fn push(&mut self, value: T) -> Handle<T> {
+        let slot = Rc::new(Slot {
+            node: Cell::new(None),
+            owner: RefCell::new(self.owner.clone()),
+        });
+        let node = NonNull::from(Box::leak(Box::new(Node {
+            value,
+            child: None,
+            next: None,
+            prev: None,
+            slot: slot.clone(),
+        })));
+        slot.node.set(Some(node));
+        self.root = Some(match self.root {
+            Some(root) => self.link(root, node),
+            None => node,
+        });
+        self.len += 1;
+        Handle { slot }
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let root = self.root.take()?;
+        // SAFETY: root はこのヒープの根で、木から外してここで解放する
+        let node = unsafe { Box::from_raw(root.as_ptr()) };
+        let Node {
+            value, child, slot, ..
+        } = *node;
+        slot.node.set(None);
+        self.root = self.merge_pairs(child);
+        self.len -= 1;
+        Some(value)
+    }
+
+    // handle の指す要素を value にする
+    // value は今の値より先に取り出すもの (compare で Greater か Equal) でなければならず、Min なら値を小さくする
+    pub fn decrease_key(&mut self, handle: &Handle<T>, value: T) -> Result<()> {
+        let node = self.node(handle).ok_or(Error::InvalidHandle)?;
+        // SAFETY: node とその周りのノードはこのヒープの木にあり、&mut self を借用しているので他から参照されていない
+        unsafe {
+            if self.cmp.compare(&value, &(*node.as_ptr()).value) == Ordering::Less {
+                return Err(Error::WorseValue);
+            }
+            (*node.as_ptr()).value = value;
+            // 根でなければ、部分木ごと切り離して根と link する
+            if let Some(prev) = (*node.as_ptr()).prev.take() {
+                let next = (*node.as_ptr()).next.take();
+                if (*prev.as_ptr()).child == Some(node) {
+                    (*prev.as_ptr()).child = next;
+                } else {
+                    (*prev.as_ptr()).next = next;
+                }
+                if let Some(next) = next {
+                    (*next.as_ptr()).prev = Some(prev);
+                }
+                let root = self.root.expect("heap with a node has a root");
+                self.root = Some(self.link(root, node));
+            }
+        }
+        Ok(())
+    }
+
+    // other の要素を全て取り込む。O(1)
+    // other の Handle は、このヒープでそのまま使える
+    pub fn meld(&mut self, mut other: Self) {
+        *other.owner.merged.borrow_mut() = Some(self.owner.clone());
+        if let Some(other_root) = other.root.take() {
+            self.root = Some(match self.root {
+                Some(root) => self.link(root, other_root),
+                None => other_root,
+            });
+        }
+        self.len += std::mem::take(&mut other.len);
+    }
+
+    fn before(&self, a: NonNull<Node<T>>, b: NonNull<Node<T>>) -> bool {
+        // SAFETY: a と b はこのヒープのノード
+        unsafe { self.cmp.compare(&(*a.as_ptr()).value, &(*b.as_ptr()).value) == Ordering::Greater }
+    }
+
+    // 根同士を繋ぎ、新しい根を返す。同じなら a を根にする
+    fn link(&self, a: NonNull<Node<T>>, b: NonNull<Node<T>>) -> NonNull<Node<T>> {
+        let (parent, child) = if self.before(b, a) { (b, a) } else { (a, b) };
+        // SAFETY: a と b はこのヒープの別々の木の根で、&mut self を借用した操作の中からだけ呼ぶ
+        unsafe {
+            let first = (*parent.as_ptr()).child;
+            if let Some(first) = first {
+                (*first.as_ptr()).prev = Some(child);
+            }
+            (*child.as_ptr()).next = first;
+            (*child.as_ptr()).prev = Some(parent);
+            (*parent.as_ptr()).child = Some(child);
+        }
+        parent
+    }
+
+    // first から右に並ぶ兄弟を、左から 2 つずつ link し、できた木を右から順に link する
+    fn merge_pairs(&self, mut current: Link<T>) -> Link<T> {
+        // 兄弟の並びから外して根にし、右の兄弟を返す
+        // SAFETY: 兄弟は全てこのヒープのノードで、取り出した根の子だったもの
+        let detach = |node: NonNull<Node<T>>| unsafe {
+            (*node.as_ptr()).prev = None;
+            (*node.as_ptr()).next.take()
+        };
+        let mut trees = Vec::new();
+        while let Some(a) = current {
+            current = match detach(a) {
+                Some(b) => {
+                    let next = detach(b);
+                    trees.push(self.link(a, b));
+                    next
+                }
+                None => {
+                    trees.push(a);
+                    None
+                }
+            };
+        }
+        trees
+            .into_iter()
+            .rev()
+            .reduce(|right, left| self.link(left, right))
+    }
+
+    // 親が子より後に取り出すものになっていないか、prev が繋がりと合うか、Slot がノードとこのヒープを指しているか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        let mut stack: Vec<_> = self.root.into_iter().collect();
+        if let Some(root) = self.root {
+            // SAFETY: 根はこのヒープのノード
+            let root = unsafe { &*root.as_ptr() };
+            assert!(
+                root.prev.is_none() && root.next.is_none(),
+                "root has a parent or siblings"
+            );
+        }
+        while let Some(node) = stack.pop() {
+            count += 1;
+            // SAFETY: 木のノードは全てこのヒープのもので、&self を借用している間は変更も解放もされない
+            let n = unsafe { &*node.as_ptr() };
+            assert_eq!(n.slot.node.get(), Some(node), "slot is stale");
+            assert!(
+                Rc::ptr_eq(&resolve(&n.slot.owner.borrow()), &self.owner),
+                "node belongs to another heap"
+            );
+            let mut prev = node;
+            let mut child = n.child;
+            while let Some(c) = child {
+                // SAFETY: 同上
+                let c_ref = unsafe { &*c.as_ptr() };
+                assert_eq!(c_ref.prev, Some(prev), "prev is stale");
+                assert!(!self.before(c, node), "child is before its parent");
+                stack.push(c);
+                prev = c;
+                child = c_ref.next;
+            }
+        }
+        assert_eq!(count, self.len, "len is stale");
+    }
+}
+
+impl<T, C> Drop for PairingHeap<T, C> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for PairingHeap<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for PairingHeap<T, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T, C: Compare<T> + Default> FromIterator<T> for PairingHeap<T, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut heap = Self::default();
+        heap.extend(iter);
+        heap
+    }
+}
+
+impl<T, C: Compare<T>> Extend<T> for PairingHeap<T, C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, C: Compare<T>> PriorityQueue<T> for PairingHeap<T, C> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, value: T) {
+        PairingHeap::push(self, value);
+    }
+
+    fn peek(&self) -> Option<&T> {
+        PairingHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        PairingHeap::pop(self)
+    }
+}
+
+impl<T, C: Compare<T>> MergeableHeap<T> for PairingHeap<T, C> {
+    fn meld(&mut self, other: Self) {
+        PairingHeap::meld(self, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn handles() {
+        let mut heap = PairingHeap::new();
+        let a = heap.push(3);
+        let b = heap.push(5);
+        heap.extend(vec![1, 4, 1]);
+        heap.check_invariants();
+        assert_eq!(heap.peek(), Some(&5));
+
+        // Max なので、値を大きくすると先に取り出す
+        assert_eq!(heap.decrease_key(&a, 9), Ok(()));
+        assert_eq!(heap.decrease_key(&b, 2), Err(Error::WorseValue));
+        heap.check_invariants();
+        assert_eq!(heap.get(&a), Some(&9));
+        assert_eq!(heap.pop(), Some(9));
+        assert_eq!(heap.get(&a), None);
+        assert_eq!(heap.decrease_key(&a, 10), Err(Error::InvalidHandle));
+
+        // 別のヒープの handle は、meld するまで使えない
+        let mut other = PairingHeap::new();
+        let c = other.push(0);
+        assert!(!heap.contains(&c));
+        heap.meld(other);
+        heap.check_invariants();
+        assert_eq!(heap.decrease_key(&c, 7), Ok(()));
+        assert_eq!(heap.len(), 5);
+        let order: Vec<_> = std::iter::from_fn(|| heap.pop()).collect();
+        assert_eq!(order, vec![7, 5, 4, 1, 1]);
+        assert!(heap.is_empty());
+        assert!(!heap.contains(&b));
+    }
+
+    #[test]
+    fn dijkstra() {
+        // (from, to, cost) の無向グラフ
+        let edges = [
+            (0, 1, 7),
+            (0, 2, 9),
+            (0, 5, 14),
+            (1, 2, 10),
+            (1, 3, 15),
+            (2, 3, 11),
+            (2, 5, 2),
+            (3, 4, 6),
+            (4, 5, 9),
+        ];
+        let mut adj = vec![Vec::new(); 6];
+        for &(from, to, cost) in &edges {
+            adj[from].push((to, cost));
+            adj[to].push((from, cost));
+        }
+
+        let mut dist = vec![u32::MAX; adj.len()];
+        let mut handles: Vec<Option<Handle<(u32, usize)>>> = vec![None; adj.len()];
+        let mut heap = PairingHeap::new_min();
+        dist[0] = 0;
+        handles[0] = Some(heap.push((0, 0)));
+        while let Some((d, v)) = heap.pop() {
+            for &(to, cost) in &adj[v] {
+                let candidate = d + cost;
+                if candidate < dist[to] {
+                    dist[to] = candidate;
+                    match &handles[to] {
+                        Some(handle) => heap.decrease_key(handle, (candidate, to)).unwrap(),
+                        None => handles[to] = Some(heap.push((candidate, to))),
+                    }
+                }
+            }
+            heap.check_invariants();
+        }
+        assert_eq!(dist, vec![0, 7, 9, 20, 20, 11]);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(i32),
+        Pop,
+        // (handle の位置, 値を減らす量)。負なら WorseValue になる
+        Decrease(usize, i32),
+        Meld(Vec<i32>),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (-50..50i32).prop_map(Op::Push),
+            2 => Just(Op::Pop),
+            3 => (any::<usize>(), -3..30i32).prop_map(|(i, d)| Op::Decrease(i, d)),
+            1 => prop::collection::vec(-50..50i32, 0..10).prop_map(Op::Meld),
+        ]
+    }
+
+    proptest! {
+        // 値と通し番号の組を BTreeSet に入れたものと比べる
+        #[test]
+        fn matches_set(ops in prop::collection::vec(op(), 0..200)) {
+            let mut heap = PairingHeap::new_min();
+            let mut expected = BTreeSet::new();
+            let mut handles = Vec::new();
+            let mut values = Vec::new();
+            for op in ops {
+                match op {
+                    Op::Push(v) => {
+                        let id = handles.len();
+                        handles.push(heap.push((v, id)));
+                        values.push(Some(v));
+                        expected.insert((v, id));
+                    }
+                    Op::Pop => {
+                        let top = expected.iter().next().copied();
+                        if let Some(top) = top {
+                            expected.remove(&top);
+                            values[top.1] = None;
+                        }
+                        prop_assert_eq!(heap.pop(), top);
+                    }
+                    Op::Decrease(i, d) => {
+                        if handles.is_empty() {
+                            continue;
+                        }
+                        let id = i % handles.len();
+                        let result = heap.decrease_key(&handles[id], (values[id].unwrap_or(0) - d, id));
+                        match values[id] {
+                            None => prop_assert_eq!(result, Err(Error::InvalidHandle)),
+                            Some(_) if d < 0 => prop_assert_eq!(result, Err(Error::WorseValue)),
+                            Some(v) => {
+                                prop_assert_eq!(result, Ok(()));
+                                expected.remove(&(v, id));
+                                expected.insert((v - d, id));
+                                values[id] = Some(v - d);
+                            }
+                        }
+                    }
+                    Op::Meld(vs) => {
+                        let mut other = PairingHeap::new_min();
+                        for v in vs {
+                            let id = handles.len();
+                            handles.push(other.push((v, id)));
+                            values.push(Some(v));
+                            expected.insert((v, id));
+                        }
+                        heap.meld(other);
+                    }
+                }
+                heap.check_invariants();
+                prop_assert_eq!(heap.peek(), expected.iter().next());
+                prop_assert_eq!(heap.len(), expected.len());
+            }
+            for (id, handle) in handles.iter().enumerate() {
+                prop_assert_eq!(heap.get(handle).map(|&(v, _)| v), values[id]);
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+// PATRICIA trie (圧縮した二分木のトライ)
+// キーはビット列で、バイト列 key の先頭 len ビットで表す。IP アドレスの経路なら (アドレス, プレフィックス長)
+// 分岐のないビットは 1 つのノードにまとめるので、ノードは値を持つか、子を 2 つ持つかのどちらか
+// キーの数を n とするとノードは 2n 個未満になり、探索はキーのビット数に比例する
+//
+// longest_prefix_match(key) は、key の先頭と一致するキーのうち最も長いものを返す
+// 経路表で宛先アドレスから経路を選ぶ操作にあたる
+use std::iter::FusedIterator;
+
+// key の i ビット目 (上位ビットから数える)
+fn bit(key: &[u8], i: usize) -> usize {
+    ((key[i / 8] >> (7 - i % 8)) & 1) as usize
+}
+
+// a の先頭 a_len ビットと b の先頭 b_len ビットで、先頭から一致するビットの数
+fn common_bits(a: &[u8], a_len: usize, b: &[u8], b_len: usize) -> usize {
+    let len = a_len.min(b_len);
+    let mut common = 0;
+    for (x, y) in a.iter().zip(b) {
+        let diff = x ^ y;
+        if diff != 0 {
+            common += diff.leading_zeros() as usize;
+            break;
+        }
+        common += 8;
+        if common >= len {
+            break;
+        }
+    }
+    common.min(len)
+}
+
+// 先頭 len ビットだけを残し、残りのビットを 0 にしたバイト列
+fn truncate(key: &[u8], len: usize) -> Box<[u8]> {
+    assert!(
+        len <= key.len() * 8,
+        "prefix length {} exceeds key of {} bits",
+        len,
+        key.len() * 8
+    );
+    let mut bytes = key[..len.div_ceil(8)].to_vec();
+    if !len.is_multiple_of(8) {
+        *bytes.last_mut().expect("len is not zero") &= 0xff << (8 - len % 8);
+    }
+    bytes.into()
+}
+
+type Link<V> = Option<Box<Node<V>>>;
+
+#[derive(Debug)]
+struct Node<V> {
+    // root からこのノードまでのビット列。len ビットの後ろは 0
+    key: Box<[u8]>,
+    len: usize,
+    value: Option<V>,
+    // len ビット目が 0 か 1 かで分ける
+    children: [Link<V>; 2],
+}
+
+impl<V> Node<V> {
+    fn new(key: Box<[u8]>, len: usize, value: Option<V>) -> Box<Self> {
+        Box::new(Self {
+            key,
+            len,
+            value,
+            children: [None, None],
+        })
+    }
+}
+
+fn insert<V>(link: &mut Link<V>, key: &[u8], len: usize, value: V) -> Option<V> {
+    let node = match link {
+        Some(node) => node,
+        None => {
+            *link = Some(Node::new(truncate(key, len), len, Some(value)));
+            return None;
+        }
+    };
+    let common = common_bits(&node.key, node.len, key, len);
+    if common == node.len {
+        if len == node.len {
+            return node.value.replace(value);
+        }
+        // node は key の接頭辞なので、次のビットで子に降りる
+        return insert(&mut node.children[bit(key, node.len)], key, len, value);
+    }
+    let old = link.take().expect("link is not empty");
+    let side = bit(&old.key, common);
+    let mut parent = if common == len {
+        // key は node の接頭辞なので、key のノードを node の親にする
+        Node::new(truncate(key, len), len, Some(value))
+    } else {
+        // 2 つが分かれるビットに、値のない分岐のノードを挟む
+        let mut branch = Node::new(truncate(key, common), common, None);
+        branch.children[1 - side] = Some(Node::new(truncate(key, len), len, Some(value)));
+        branch
+    };
+    parent.children[side] = Some(old);
+    *link = Some(parent);
+    None
+}
+
+fn remove<V>(link: &mut Link<V>, key: &[u8], len: usize) -> Option<V> {
+    let node = link.as_mut()?;
+    if common_bits(&node.key, node.len, key, len) < node.len {
+        return None;
+    }
+    let old = if len == node.len {
+        node.value.take()?
+    } else {
+        remove(&mut node.children[bit(key, node.len)], key, len)?
+    };
+    // 値がなく子も 2 つないノードは、残った子 (か何もないもの) に置き換える
+    if node.value.is_none() {
+        match &mut node.children {
+            [Some(_), Some(_)] => {}
+            [child @ Some(_), None] | [None, child @ Some(_)] | [child @ None, None] => {
+                *link = child.take();
+            }
+        }
+    }
+    Some(old)
+}
+
+#[derive(Debug)]
+pub struct Patricia<V> {
+    root: Link<V>,
+    len: usize,
+}
+
+impl<V> Patricia<V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // key の先頭 len ビットをキーとして入れる。既にキーがあれば値を置き換えて、前の値を返す
+    // len が key のビット数を超えると panic する
+    pub fn insert(&mut self, key: &[u8], len: usize, value: V) -> Option<V> {
+        let old = insert(&mut self.root, key, len, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    // key の先頭 len ビットと完全に一致するキーの値
+    pub fn get(&self, key: &[u8], len: usize) -> Option<&V> {
+        let mut node = self.root.as_deref()?;
+        loop {
+            if node.len > len || common_bits(&node.key, node.len, key, len) < node.len {
+                return None;
+            }
+            if node.len == len {
+                return node.value.as_ref();
+            }
+            node = node.children[bit(key, node.len)].as_deref()?;
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &[u8], len: usize) -> Option<&mut V> {
+        let mut node = self.root.as_deref_mut()?;
+        loop {
+            if node.len > len || common_bits(&node.key, node.len, key, len) < node.len {
+                return None;
+            }
+            if node.len == len {
+                return node.value.as_mut();
+            }
+            node = node.children[bit(key, node.len)].as_deref_mut()?;
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8], len: usize) -> Option<V> {
+        let old = remove(&mut self.root, key, len);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    // key の全てのビットについて、先頭が一致するキーのうち最も長いものを (プレフィックス長, 値) で返す
+    pub fn longest_prefix_match(&self, key: &[u8]) -> Option<(usize, &V)> {
+        let bits = key.len() * 8;
+        let mut best = None;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.len > bits || common_bits(&node.key, node.len, key, bits) < node.len {
+                break;
+            }
+            if let Some(value) = &node.value {
+                best = Some((node.len, value));
+            }
+            if node.len == bits {
+                break;
+            }
+            current = node.children[bit(key, node.len)].as_deref();
+        }
+        best
+    }
+
+    // キーを (バイト列, ビット数, 値) で返す
+    // 接頭辞になっているキーが先で、同じ長さまで一致するなら次のビットが 0 の方が先
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: self.root.as_deref().into_iter().collect(),
+        }
+    }
+
+    // 各ノードのキーと子の対応、圧縮、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        // 部分木のキーの数を返す
+        fn check<V>(node: &Node<V>) -> usize {
+            assert_eq!(
+                &*node.key,
+                &*truncate(&node.key, node.len),
+                "key is not truncated"
+            );
+            assert!(
+                node.value.is_some() || node.children.iter().all(Option::is_some),
+                "node without value has fewer than two children"
+            );
+            let mut count = node.value.is_some() as usize;
+            for (side, child) in node.children.iter().enumerate() {
+                if let Some(child) = child.as_deref() {
+                    assert!(
+                        child.len > node.len
+                            && common_bits(&child.key, child.len, &node.key, node.len) == node.len
+                            && bit(&child.key, node.len) == side,
+                        "child is not under its parent"
+                    );
+                    count += check(child);
+                }
+            }
+            count
+        }
+        let count = self.root.as_deref().map_or(0, check);
+        assert_eq!(self.len, count, "len is stale");
+    }
+}
+
+impl<V> Default for Patricia<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 行きがけ順に辿る。値のないノードは飛ばす
+pub struct Iter<'a, V> {
+    stack: Vec<&'a Node<V>>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a [u8], usize, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.stack.pop()?;
+            self.stack
+                .extend(node.children.iter().rev().filter_map(|c| c.as_deref()));
+            if let Some(value) = &node.value {
+                return Some((&node.key, node.len, value));
+            }
+        }
+    }
+}
+
+impl<'a, V> FusedIterator for Iter<'a, V> {}
+
+impl<'a, V> IntoIterator for &'a Patricia<V> {
+    type Item = (&'a [u8], usize, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn routing_table() {
+        let mut table = Patricia::new();
+        assert_eq!(table.longest_prefix_match(&[10, 0, 0, 1]), None);
+        assert_eq!(table.insert(&[0, 0, 0, 0], 0, "default"), None);
+        assert_eq!(table.insert(&[10, 0, 0, 0], 8, "10/8"), None);
+        assert_eq!(table.insert(&[10, 1, 0, 0], 16, "10.1/16"), None);
+        assert_eq!(table.insert(&[10, 1, 128, 0], 17, "10.1.128/17"), None);
+        assert_eq!(table.insert(&[192, 168, 0, 0], 16, "192.168/16"), None);
+        table.check_invariants();
+
+        assert_eq!(
+            table.longest_prefix_match(&[10, 1, 200, 3]),
+            Some((17, &"10.1.128/17"))
+        );
+        assert_eq!(
+            table.longest_prefix_match(&[10, 1, 2, 3]),
+            Some((16, &"10.1/16"))
+        );
+        assert_eq!(
+            table.longest_prefix_match(&[10, 2, 2, 3]),
+            Some((8, &"10/8"))
+        );
+        assert_eq!(
+            table.longest_prefix_match(&[8, 8, 8, 8]),
+            Some((0, &"default"))
+        );
+
+        // プレフィックス長より後ろのビットは無視する
+        assert_eq!(
+            table.insert(&[10, 1, 255, 255], 17, "replaced"),
+            Some("10.1.128/17")
+        );
+        assert_eq!(table.get(&[10, 1, 128, 0], 17), Some(&"replaced"));
+        assert_eq!(table.get(&[10, 1, 0, 0], 15), None);
+        *table.get_mut(&[10, 0, 0, 0], 8).unwrap() = "ten";
+
+        assert_eq!(table.remove(&[10, 1, 0, 0], 16), Some("10.1/16"));
+        assert_eq!(table.remove(&[10, 1, 0, 0], 16), None);
+        assert_eq!(table.remove(&[0, 0, 0, 0], 0), Some("default"));
+        table.check_invariants();
+        assert_eq!(
+            table.longest_prefix_match(&[10, 1, 2, 3]),
+            Some((8, &"ten"))
+        );
+        assert_eq!(table.longest_prefix_match(&[8, 8, 8, 8]), None);
+
+        let routes: Vec<_> = table
+            .iter()
+            .map(|(k, len, v)| (k.to_vec(), len, *v))
+            .collect();
+        assert_eq!(
+            routes,
+            vec![
+                (vec![10], 8, "ten"),
+                (vec![10, 1, 128], 17, "replaced"),
+                (vec![192, 168], 16, "192.168/16"),
+            ]
+        );
+    }
+
+    #[test]
+    fn ipv6() {
+        let mut table = Patricia::new();
+        let doc = [0x20, 0x01, 0x0d, 0xb8];
+        table.insert(&doc, 32, 1);
+        let mut addr = [0u8; 16];
+        addr[..4].copy_from_slice(&doc);
+        addr[15] = 1;
+        table.insert(&addr, 128, 2);
+        assert_eq!(table.longest_prefix_match(&addr), Some((128, &2)));
+        addr[15] = 2;
+        assert_eq!(table.longest_prefix_match(&addr), Some((32, &1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "prefix length 33 exceeds key of 32 bits")]
+    fn too_long_prefix() {
+        Patricia::new().insert(&[10, 0, 0, 0], 33, ());
+    }
+
+    // BTreeMap<Vec<bool>, _> の順は、接頭辞が先で、次のビットが 0 の方が先になる
+    fn bits(key: &[u8], len: usize) -> Vec<bool> {
+        (0..len).map(|i| bit(key, i) == 1).collect()
+    }
+
+    proptest! {
+        // ランダムな操作列を、ビット列をキーにした BTreeMap と同時に適用して結果を比べる
+        #[test]
+        fn same_as_btree_map_random(
+            ops in prop::collection::vec((0..4u8, prop::array::uniform2(0..4u8), 0..=16usize, 0..100usize), 0..300),
+        ) {
+            let mut trie = Patricia::new();
+            let mut expected = BTreeMap::new();
+            for (op, key, len, value) in ops {
+                // 上位ビットに寄せて、共通の接頭辞ができやすくする
+                let key = [key[0] << 6, key[1] << 6];
+                match op {
+                    0 | 1 => prop_assert_eq!(trie.insert(&key, len, value), expected.insert(bits(&key, len), value)),
+                    2 => prop_assert_eq!(trie.remove(&key, len), expected.remove(&bits(&key, len))),
+                    _ => {
+                        prop_assert_eq!(trie.get(&key, len), expected.get(&bits(&key, len)));
+                        let query = bits(&key, 16);
+                        let wanted = expected
+                            .iter()
+                            .filter(|(k, _)| query.starts_with(k))
+                            .max_by_key(|(k, _)| k.len())
+                            .map(|(k, v)| (k.len(), v));
+                        prop_assert_eq!(trie.longest_prefix_match(&key), wanted);
+                    }
+                }
+                trie.check_invariants();
+            }
+            let actual: Vec<_> = trie.iter().map(|(k, len, v)| (bits(k, len), *v)).collect();
+            let wanted: Vec<_> = expected.into_iter().collect();
+            prop_assert_eq!(actual, wanted);
+        }
+    }
+}
@@ -0,0 +1,706 @@
+// 永続的な順序付き map。wbtree と同じ重みで平衡を保つ二分探索木を、ノードを書き換えずに作り直す
+// insert と remove は自分を変えずに新しい版を返す
+// 作り直すのは root から変えたノードまでの経路 (と回転したノード) だけで、残りの部分木は Arc で前の版と共有する
+//
+// diff(old, new) は 2 つの版の違いをキー順に返す
+// 同じ Arc の部分木は中を見ずに飛ばすので、同じ版から少しだけ変えた版どうしなら、変わったところの近くしか辿らない
+use std::{
+    cmp::Ordering,
+    fmt,
+    iter::{FromIterator, FusedIterator},
+    ptr,
+    sync::Arc,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+// 片方の重みが、もう片方の DELTA 倍を超えたら偏っているとみなす
+const DELTA: usize = 3;
+// 回転で持ち上げる子の内側の重みが、外側の GAMMA 倍以上なら二重回転にする
+const GAMMA: usize = 2;
+
+type Link<K, V> = Option<Arc<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // このノードを根とする部分木のノードの数
+    size: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+fn size<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+fn weight<K, V>(link: &Link<K, V>) -> usize {
+    size(link) + 1
+}
+
+fn node<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Arc<Node<K, V>> {
+    Arc::new(Node {
+        key,
+        value,
+        size: 1 + size(&left) + size(&right),
+        left,
+        right,
+    })
+}
+
+// 左右の重みの比が DELTA 以内になるように回転してノードを作る
+// 左右のどちらかは、平衡していた部分木から 1 つ増えたか減っただけのもの
+fn balance<K: Copy, V: Clone>(
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+) -> Arc<Node<K, V>> {
+    if weight(&right) > DELTA * weight(&left) {
+        let r = right.expect("heavy side is not empty");
+        if weight(&r.left) < GAMMA * weight(&r.right) {
+            let left = node(key, value, left, r.left.clone());
+            node(r.key, r.value.clone(), Some(left), r.right.clone())
+        } else {
+            let rl = r.left.as_ref().expect("inner side is heavier");
+            let left = node(key, value, left, rl.left.clone());
+            let right = node(r.key, r.value.clone(), rl.right.clone(), r.right.clone());
+            node(rl.key, rl.value.clone(), Some(left), Some(right))
+        }
+    } else if weight(&left) > DELTA * weight(&right) {
+        let l = left.expect("heavy side is not empty");
+        if weight(&l.right) < GAMMA * weight(&l.left) {
+            let right = node(key, value, l.right.clone(), right);
+            node(l.key, l.value.clone(), l.left.clone(), Some(right))
+        } else {
+            let lr = l.right.as_ref().expect("inner side is heavier");
+            let left = node(l.key, l.value.clone(), l.left.clone(), lr.left.clone());
+            let right = node(key, value, lr.right.clone(), right);
+            node(lr.key, lr.value.clone(), Some(left), Some(right))
+        }
+    } else {
+        node(key, value, left, right)
+    }
+}
+
+// key に value を入れた部分木と、キーが増えたかを返す
+fn insert<K: Ord + Copy, V: Clone>(link: &Link<K, V>, key: K, value: V) -> (Arc<Node<K, V>>, bool) {
+    let n = match link {
+        Some(n) => n,
+        None => return (node(key, value, None, None), true),
+    };
+    match key.cmp(&n.key) {
+        Ordering::Equal => (node(key, value, n.left.clone(), n.right.clone()), false),
+        Ordering::Less => {
+            let (left, added) = insert(&n.left, key, value);
+            let (left, right) = (Some(left), n.right.clone());
+            (rebuild(n, left, right, added), added)
+        }
+        Ordering::Greater => {
+            let (right, added) = insert(&n.right, key, value);
+            let (left, right) = (n.left.clone(), Some(right));
+            (rebuild(n, left, right, added), added)
+        }
+    }
+}
+
+// n の子を付け替えたノード。大きさが変わったときだけ回転を考える
+fn rebuild<K: Copy, V: Clone>(
+    n: &Node<K, V>,
+    left: Link<K, V>,
+    right: Link<K, V>,
+    resized: bool,
+) -> Arc<Node<K, V>> {
+    if resized {
+        balance(n.key, n.value.clone(), left, right)
+    } else {
+        node(n.key, n.value.clone(), left, right)
+    }
+}
+
+// key を除いた部分木。key がなければ None
+fn remove<K: Ord + Copy, V: Clone>(link: &Link<K, V>, key: K) -> Option<Link<K, V>> {
+    let n = link.as_ref()?;
+    Some(match key.cmp(&n.key) {
+        Ordering::Equal => glue(&n.left, &n.right),
+        Ordering::Less => {
+            let left = remove(&n.left, key)?;
+            Some(rebuild(n, left, n.right.clone(), true))
+        }
+        Ordering::Greater => {
+            let right = remove(&n.right, key)?;
+            Some(rebuild(n, n.left.clone(), right, true))
+        }
+    })
+}
+
+// 消したノードの左右を繋ぐ。重い方から端のノードを外して根にする
+fn glue<K: Copy, V: Clone>(left: &Link<K, V>, right: &Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, link) | (link, None) => link.clone(),
+        (Some(l), Some(r)) => Some(if l.size > r.size {
+            let (left, (key, value)) = remove_max(l);
+            node(key, value, left, right.clone())
+        } else {
+            let (right, (key, value)) = remove_min(r);
+            node(key, value, left.clone(), right)
+        }),
+    }
+}
+
+fn remove_min<K: Copy, V: Clone>(n: &Node<K, V>) -> (Link<K, V>, (K, V)) {
+    match &n.left {
+        None => (n.right.clone(), (n.key, n.value.clone())),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            (Some(rebuild(n, left, n.right.clone(), true)), min)
+        }
+    }
+}
+
+fn remove_max<K: Copy, V: Clone>(n: &Node<K, V>) -> (Link<K, V>, (K, V)) {
+    match &n.right {
+        None => (n.left.clone(), (n.key, n.value.clone())),
+        Some(right) => {
+            let (right, max) = remove_max(right);
+            (Some(rebuild(n, n.left.clone(), right, true)), max)
+        }
+    }
+}
+
+pub struct PersistentMap<K, V> {
+    root: Link<K, V>,
+}
+
+impl<K: Ord + Copy, V: Clone> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        Self { root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        fn height<K, V>(link: &Link<K, V>) -> usize {
+            link.as_ref()
+                .map_or(0, |n| 1 + height(&n.left).max(height(&n.right)))
+        }
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: K) -> bool {
+        self.get(key).is_some()
+    }
+
+    // key に value を入れた新しい版を返す。self は変わらない
+    #[must_use]
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let (root, _) = insert(&self.root, key, value);
+        Self { root: Some(root) }
+    }
+
+    // key を除いた新しい版を返す。key がなければ self と同じ版を返す
+    #[must_use]
+    pub fn remove(&self, key: K) -> Self {
+        match remove(&self.root, key) {
+            Some(root) => Self { root },
+            None => self.clone(),
+        }
+    }
+
+    // key より小さいキーの数
+    fn rank(&self, key: K) -> usize {
+        let mut rank = 0;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return rank + size(&node.left),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => {
+                    rank += size(&node.left) + 1;
+                    node.right.as_deref()
+                }
+            };
+        }
+        rank
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let remaining = if min_key > max_key {
+            0
+        } else {
+            self.rank(max_key) + self.get(max_key).is_some() as usize - self.rank(min_key)
+        };
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining,
+        };
+        if remaining == 0 {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // old から new への変更をキー順に返す
+    pub fn diff<'a>(old: &'a Self, new: &'a Self) -> Diff<'a, K, V>
+    where
+        V: PartialEq,
+    {
+        let mut diff = Diff {
+            old: Vec::new(),
+            new: Vec::new(),
+        };
+        push(&mut diff.old, &old.root);
+        push(&mut diff.new, &new.root);
+        diff
+    }
+
+    // キーの順序、部分木の大きさ、重みの比が正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Copy, V>(node: &Node<K, V>, (lower, upper): (Option<K>, Option<K>)) {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            if let Some(left) = node.left.as_deref() {
+                check(left, (lower, Some(node.key)));
+            }
+            if let Some(right) = node.right.as_deref() {
+                check(right, (Some(node.key), upper));
+            }
+            assert_eq!(
+                node.size,
+                1 + size(&node.left) + size(&node.right),
+                "size is stale"
+            );
+            let (left, right) = (weight(&node.left), weight(&node.right));
+            assert!(
+                left <= DELTA * right && right <= DELTA * left,
+                "node is unbalanced"
+            );
+        }
+        if let Some(root) = self.root.as_deref() {
+            check(root, (None, None));
+        }
+    }
+}
+
+// root の Arc を増やすだけなので O(1)
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+        }
+    }
+}
+
+impl<K: Ord + Copy, V: Clone> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Copy + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+// range は件数を先に数えるので、終わりのキーを比べずに remaining で止める
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        self.remaining -= 1;
+        Some((node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V: Clone> IntoIterator for &'a PersistentMap<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Self::new(), |map, (key, value)| map.insert(key, value))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change<'a, K, V> {
+    // new にだけある
+    Added(K, &'a V),
+    // old にだけある
+    Removed(K, &'a V),
+    // 両方にあって値が違う。(old の値, new の値)
+    Changed(K, &'a V, &'a V),
+}
+
+// まだ開いていない部分木か、次に比べるエントリ
+enum Step<'a, K, V> {
+    Tree(&'a Arc<Node<K, V>>),
+    Entry(&'a Node<K, V>),
+}
+
+// 参照しか持たないので、K と V に Copy はいらない
+impl<'a, K, V> Clone for Step<'a, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for Step<'a, K, V> {}
+
+fn push<'a, K, V>(stack: &mut Vec<Step<'a, K, V>>, link: &'a Link<K, V>) {
+    if let Some(node) = link {
+        stack.push(Step::Tree(node));
+    }
+}
+
+// 部分木を 左の部分木、根のエントリ、右の部分木 に開く
+fn expand<K, V>(stack: &mut Vec<Step<'_, K, V>>) {
+    if let Some(Step::Tree(node)) = stack.pop() {
+        push(stack, &node.right);
+        stack.push(Step::Entry(node));
+        push(stack, &node.left);
+    }
+}
+
+// 2 つの版を、まだ見ていない部分を先頭から順に並べたスタックで持つ
+// 先頭がどちらも部分木で同じ Arc なら、中身も同じなので両方捨てる
+// そうでなければ大きい方の部分木を開き、エントリどうしになったらキーを比べる
+pub struct Diff<'a, K, V> {
+    old: Vec<Step<'a, K, V>>,
+    new: Vec<Step<'a, K, V>>,
+}
+
+impl<'a, K: Ord + Copy, V: PartialEq> Iterator for Diff<'a, K, V> {
+    type Item = Change<'a, K, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match (self.old.last().copied(), self.new.last().copied()) {
+                (None, None) => return None,
+                (Some(Step::Tree(a)), Some(Step::Tree(b))) if Arc::ptr_eq(a, b) => {
+                    self.old.pop();
+                    self.new.pop();
+                }
+                (Some(Step::Tree(a)), Some(Step::Tree(b))) => {
+                    if a.size >= b.size {
+                        expand(&mut self.old);
+                    } else {
+                        expand(&mut self.new);
+                    }
+                }
+                (Some(Step::Tree(_)), _) => expand(&mut self.old),
+                (_, Some(Step::Tree(_))) => expand(&mut self.new),
+                (Some(Step::Entry(a)), None) => {
+                    self.old.pop();
+                    return Some(Change::Removed(a.key, &a.value));
+                }
+                (None, Some(Step::Entry(b))) => {
+                    self.new.pop();
+                    return Some(Change::Added(b.key, &b.value));
+                }
+                (Some(Step::Entry(a)), Some(Step::Entry(b))) => match a.key.cmp(&b.key) {
+                    Ordering::Less => {
+                        self.old.pop();
+                        return Some(Change::Removed(a.key, &a.value));
+                    }
+                    Ordering::Greater => {
+                        self.new.pop();
+                        return Some(Change::Added(b.key, &b.value));
+                    }
+                    Ordering::Equal => {
+                        self.old.pop();
+                        self.new.pop();
+                        if !ptr::eq(a, b) && a.value != b.value {
+                            return Some(Change::Changed(a.key, &a.value, &b.value));
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V: PartialEq> FusedIterator for Diff<'a, K, V> {}
+
+// OrderedMap は &mut self で更新するので、新しい版で置き換える
+impl<K: Ord + Copy, V: Clone> OrderedMap<K, V> for PersistentMap<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = self.get(key).cloned();
+        *self = PersistentMap::insert(self, key, value);
+        old
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        PersistentMap::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        let old = self.get(key).cloned();
+        *self = PersistentMap::remove(self, key);
+        old
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        PersistentMap::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        PersistentMap::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashSet};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn versions_are_independent() {
+        let v0 = PersistentMap::new();
+        let v1 = v0.insert(1, "a").insert(2, "b").insert(3, "c");
+        let v2 = v1.insert(2, "B").remove(3);
+        let v3 = v2.remove(10);
+        for v in [&v0, &v1, &v2, &v3] {
+            v.check_invariants();
+        }
+        assert!(v0.is_empty());
+        let all: Vec<_> = v1.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(1, "a"), (2, "b"), (3, "c")]);
+        let all: Vec<_> = v2.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(1, "a"), (2, "B")]);
+        // 無いキーを消しても同じ版が返る
+        assert!(Arc::ptr_eq(
+            v2.root.as_ref().unwrap(),
+            v3.root.as_ref().unwrap()
+        ));
+    }
+
+    fn nodes<K, V>(link: &Link<K, V>, out: &mut HashSet<usize>) {
+        if let Some(node) = link {
+            out.insert(Arc::as_ptr(node) as usize);
+            nodes(&node.left, out);
+            nodes(&node.right, out);
+        }
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let old: PersistentMap<_, _> = (0..10_000).map(|k| (k, k)).collect();
+        let new = old.insert(5000, 0);
+        let (mut before, mut after) = (HashSet::new(), HashSet::new());
+        nodes(&old.root, &mut before);
+        nodes(&new.root, &mut after);
+        // 置き換えただけなら、作り直すのは経路のノードだけ
+        assert_eq!(after.difference(&before).count(), {
+            let mut depth = 0;
+            let mut current = old.root.as_deref();
+            while let Some(node) = current {
+                depth += 1;
+                current = match 5000.cmp(&node.key) {
+                    Ordering::Equal => break,
+                    Ordering::Less => node.left.as_deref(),
+                    Ordering::Greater => node.right.as_deref(),
+                };
+            }
+            depth
+        });
+        assert_eq!(old.get(5000), Some(&5000));
+        assert_eq!(new.get(5000), Some(&0));
+    }
+
+    #[test]
+    fn diff() {
+        let old: PersistentMap<_, _> = (0..1000).map(|k| (k, k)).collect();
+        let new = old
+            .insert(10, 100)
+            .remove(500)
+            .insert(2000, 0)
+            .insert(20, 20);
+        let changes: Vec<_> = PersistentMap::diff(&old, &new).collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::Changed(10, &10, &100),
+                Change::Removed(500, &500),
+                Change::Added(2000, &0),
+            ]
+        );
+        assert_eq!(PersistentMap::diff(&new, &new).count(), 0);
+        let empty = PersistentMap::new();
+        assert_eq!(PersistentMap::diff(&empty, &old).count(), 1000);
+        // 別々に作った版どうしでも比べられる
+        let rebuilt: PersistentMap<_, _> = new.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(PersistentMap::diff(&new, &rebuilt).count(), 0);
+    }
+
+    #[test]
+    fn height_is_logarithmic() {
+        let n = 100_000;
+        let mut t: PersistentMap<_, _> = (0..n).map(|k| (k, k)).collect();
+        t.check_invariants();
+        let bound = ((n + 1) as f64).ln() / (4.0f64 / 3.0).ln();
+        assert!((t.height() as f64) <= bound);
+        for k in 0..n / 2 {
+            t = t.remove(k);
+        }
+        t.check_invariants();
+        assert_eq!(t.len(), n / 2);
+        assert_eq!(t.range(n / 2 - 10, n / 2 + 2).len(), 3);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, usize),
+        Remove(usize),
+        Search(usize),
+        Range(usize, usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        (0..5u8, 0..200usize, 0..200usize).prop_map(|(t, a, b)| match t {
+            0 | 1 => Op::Insert(a, b),
+            2 => Op::Remove(a),
+            3 => Op::Search(a),
+            _ => Op::Range(a.min(b), a.max(b)),
+        })
+    }
+
+    proptest! {
+        // 操作ごとに版を残し、最後に全ての版と、隣り合わない版どうしの diff を確かめる
+        #[test]
+        fn same_as_btree_map_random(ops in prop::collection::vec(op_strategy(), 0..200)) {
+            let mut versions = vec![(PersistentMap::new(), BTreeMap::new())];
+            for op in ops {
+                let (map, model) = versions.last().unwrap();
+                let (mut map, mut model) = (map.clone(), model.clone());
+                match op {
+                    Op::Insert(k, v) => {
+                        map = map.insert(k, v);
+                        model.insert(k, v);
+                    }
+                    Op::Remove(k) => {
+                        map = map.remove(k);
+                        model.remove(&k);
+                    }
+                    Op::Search(k) => prop_assert_eq!(map.get(k), model.get(&k)),
+                    Op::Range(a, b) => {
+                        let actual: Vec<_> = map.range(a, b).collect();
+                        let expected: Vec<_> = model.range(a..=b).map(|(k, v)| (*k, v)).collect();
+                        prop_assert_eq!(actual, expected);
+                    }
+                }
+                map.check_invariants();
+                versions.push((map, model));
+            }
+            for (map, model) in &versions {
+                let actual: Vec<_> = map.iter().collect();
+                let expected: Vec<_> = model.iter().map(|(k, v)| (*k, v)).collect();
+                prop_assert_eq!(actual, expected);
+            }
+            for (i, (old, old_model)) in versions.iter().enumerate().step_by(7) {
+                for (new, new_model) in versions[i..].iter().step_by(5) {
+                    let keys: std::collections::BTreeSet<_> =
+                        old_model.keys().chain(new_model.keys()).collect();
+                    let expected: Vec<_> = keys
+                        .into_iter()
+                        .filter_map(|k| match (old_model.get(k), new_model.get(k)) {
+                            (Some(a), None) => Some(Change::Removed(*k, a)),
+                            (None, Some(b)) => Some(Change::Added(*k, b)),
+                            (Some(a), Some(b)) if a != b => Some(Change::Changed(*k, a, b)),
+                            _ => None,
+                        })
+                        .collect();
+                    let actual: Vec<_> = PersistentMap::diff(old, new).collect();
+                    prop_assert_eq!(actual, expected);
+                }
+            }
+        }
+    }
+}
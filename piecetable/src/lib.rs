@@ -0,0 +1,514 @@
+// 文字列を編集するための piece table
+// 元の文字列 (original) と、追加した文字列を後ろに足していく add を書き換えずに持ち、
+// 文書はそのどちらかの区間 (piece) を並べたものとして表す
+//
+// piece の列は fingertree に 文字数、バイト数、改行の数 を measure にして持つ
+// 文字の位置で split して piece を入れ替えるので、編集は O(log n)
+// fingertree は更新で新しい木を返し、original と add は書き換えないので、
+// piece の列を残しておくだけで、その時点の文書に戻せる (undo と snapshot)
+use std::{
+    fmt,
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use fingertree::{FingerTree, Measured, Monoid};
+pub use textbuffer::TextBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub chars: usize,
+    pub bytes: usize,
+    pub newlines: usize,
+}
+
+impl Monoid for Metrics {
+    fn empty() -> Self {
+        Metrics {
+            chars: 0,
+            bytes: 0,
+            newlines: 0,
+        }
+    }
+
+    fn combine(&self, other: &Self) -> Self {
+        Metrics {
+            chars: self.chars + other.chars,
+            bytes: self.bytes + other.bytes,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+}
+
+fn metrics(text: &str) -> Metrics {
+    Metrics {
+        chars: text.chars().count(),
+        bytes: text.len(),
+        newlines: text.matches('\n').count(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Original,
+    Add,
+}
+
+#[derive(Debug, Clone)]
+struct Piece {
+    source: Source,
+    // バッファの中のバイトの位置
+    start: usize,
+    metrics: Metrics,
+}
+
+impl Measured for Piece {
+    type Measure = Metrics;
+
+    fn measure(&self) -> Metrics {
+        self.metrics
+    }
+}
+
+type Pieces = FingerTree<Piece>;
+
+// PieceTable ごとに違う番号を振る
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// ある時点の文書。piece の列を共有するだけなので O(1) で作れる
+// piece は作った PieceTable のバッファを指すので、他の PieceTable には戻せない
+#[derive(Clone)]
+pub struct Snapshot {
+    table: u64,
+    pieces: Pieces,
+}
+
+pub struct PieceTable {
+    // snapshot がこの PieceTable のものか確かめる
+    id: u64,
+    original: Arc<str>,
+    add: String,
+    pieces: Pieces,
+    // 編集する前の piece の列
+    undo: Vec<Pieces>,
+    redo: Vec<Pieces>,
+}
+
+impl PieceTable {
+    pub fn new() -> Self {
+        Self::from("")
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.pieces.measure().chars
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.pieces.measure().bytes
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.pieces.measure().newlines + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    fn text_of(&self, piece: &Piece) -> &str {
+        let buffer = match piece.source {
+            Source::Original => &*self.original,
+            Source::Add => &self.add,
+        };
+        &buffer[piece.start..piece.start + piece.metrics.bytes]
+    }
+
+    // piece を先頭から chars 文字のところで 2 つに分ける
+    fn split_piece(&self, piece: &Piece, chars: usize) -> (Piece, Piece) {
+        let text = self.text_of(piece);
+        let bytes = text
+            .char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(i, _)| i);
+        let head = Piece {
+            source: piece.source,
+            start: piece.start,
+            metrics: metrics(&text[..bytes]),
+        };
+        let tail = Piece {
+            source: piece.source,
+            start: piece.start + bytes,
+            metrics: metrics(&text[bytes..]),
+        };
+        (head, tail)
+    }
+
+    // 文字の位置 at の前後に分ける。at が piece の途中なら piece を分ける
+    fn split_at(&self, at: usize) -> (Pieces, Pieces) {
+        let (left, right) = self.pieces.split(|m| m.chars > at);
+        let offset = at - left.measure().chars;
+        if offset == 0 {
+            return (left, right);
+        }
+        let (piece, rest) = right.pop_front().expect("at is inside a piece");
+        let (head, tail) = self.split_piece(piece, offset);
+        (left.push_back(head), rest.push_front(tail))
+    }
+
+    fn edit(&mut self, pieces: Pieces) {
+        let old = std::mem::replace(&mut self.pieces, pieces);
+        self.undo.push(old);
+        self.redo.clear();
+    }
+
+    // 文字の位置 at に text を入れる
+    pub fn insert(&mut self, at: usize, text: &str) {
+        self.check_char_index(at);
+        if text.is_empty() {
+            return;
+        }
+        let (left, right) = self.split_at(at);
+        let start = self.add.len();
+        self.add.push_str(text);
+        // add の末尾で終わる piece の直後に入れるなら、piece を延ばす (続けて打った文字をまとめる)
+        let left = match left.pop_back() {
+            Some((last, init))
+                if last.source == Source::Add && last.start + last.metrics.bytes == start =>
+            {
+                let piece = Piece {
+                    source: Source::Add,
+                    start: last.start,
+                    metrics: last.metrics.combine(&metrics(text)),
+                };
+                init.push_back(piece)
+            }
+            _ => left.push_back(Piece {
+                source: Source::Add,
+                start,
+                metrics: metrics(text),
+            }),
+        };
+        self.edit(left.concat(&right));
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.check_range(&range);
+        if range.start == range.end {
+            return;
+        }
+        let (left, _) = self.split_at(range.start);
+        let (_, right) = self.split_at(range.end);
+        self.edit(left.concat(&right));
+    }
+
+    // 直前の編集を取り消す。取り消せなければ false
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(pieces) => {
+                let current = std::mem::replace(&mut self.pieces, pieces);
+                self.redo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // undo で取り消した編集をやり直す
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(pieces) => {
+                let current = std::mem::replace(&mut self.pieces, pieces);
+                self.undo.push(current);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            table: self.id,
+            pieces: self.pieces.clone(),
+        }
+    }
+
+    // snapshot の時点の文書に戻す。戻すことも 1 つの編集として undo できる
+    // 他の PieceTable で作った snapshot を渡すと panic する
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        assert_eq!(
+            snapshot.table, self.id,
+            "snapshot is from another PieceTable"
+        );
+        self.edit(snapshot.pieces.clone());
+    }
+
+    pub fn char(&self, index: usize) -> Option<char> {
+        if index >= self.len_chars() {
+            return None;
+        }
+        let (left, right) = self.pieces.split(|m| m.chars > index);
+        let piece = right.front()?;
+        self.text_of(piece)
+            .chars()
+            .nth(index - left.measure().chars)
+    }
+
+    // piece の文字列を前から返す
+    pub fn chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        self.pieces.iter().map(move |piece| self.text_of(piece))
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.iter().count()
+    }
+
+    fn check_char_index(&self, index: usize) {
+        assert!(
+            index <= self.len_chars(),
+            "char index {} is out of bounds for {} chars",
+            index,
+            self.len_chars()
+        );
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len_chars(),
+            "char range {:?} is out of bounds for {} chars",
+            range,
+            self.len_chars()
+        );
+    }
+
+    // piece が空でなく、バッファの文字の境界を指していて、数え上げが正しいか確かめる
+    pub fn check_invariants(&self) {
+        self.pieces.check_invariants();
+        for piece in self.pieces.iter() {
+            assert!(piece.metrics.bytes > 0, "piece is empty");
+            let buffer = match piece.source {
+                Source::Original => &*self.original,
+                Source::Add => &self.add,
+            };
+            let end = piece.start + piece.metrics.bytes;
+            assert!(end <= buffer.len(), "piece is out of the buffer");
+            assert!(
+                buffer.is_char_boundary(piece.start) && buffer.is_char_boundary(end),
+                "piece splits a char"
+            );
+            assert_eq!(
+                piece.metrics,
+                metrics(self.text_of(piece)),
+                "metrics is stale"
+            );
+        }
+    }
+}
+
+impl TextBuffer for PieceTable {
+    fn len_chars(&self) -> usize {
+        PieceTable::len_chars(self)
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        PieceTable::insert(self, at, text)
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        PieceTable::remove(self, range)
+    }
+
+    fn slice_to_string(&self, range: Range<usize>) -> String {
+        self.check_range(&range);
+        let (left, right) = self.pieces.split(|m| m.chars > range.start);
+        right
+            .iter()
+            .flat_map(|piece| self.text_of(piece).chars())
+            .skip(range.start - left.measure().chars)
+            .take(range.end - range.start)
+            .collect()
+    }
+
+    fn char(&self, index: usize) -> Option<char> {
+        PieceTable::char(self, index)
+    }
+
+    fn len_lines(&self) -> usize {
+        PieceTable::len_lines(self)
+    }
+}
+
+impl Default for PieceTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&str> for PieceTable {
+    fn from(text: &str) -> Self {
+        let pieces = if text.is_empty() {
+            FingerTree::new()
+        } else {
+            FingerTree::new().push_back(Piece {
+                source: Source::Original,
+                start: 0,
+                metrics: metrics(text),
+            })
+        };
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            original: Arc::from(text),
+            add: String::new(),
+            pieces,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for PieceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl fmt::Debug for PieceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn edit_and_undo() {
+        let mut t = PieceTable::from("the quick fox");
+        t.insert(10, "brown ");
+        t.remove(0..4);
+        assert_eq!(t.to_string(), "quick brown fox");
+        assert!(t.undo());
+        assert_eq!(t.to_string(), "the quick brown fox");
+        assert!(t.undo());
+        assert_eq!(t.to_string(), "the quick fox");
+        assert!(!t.undo());
+        assert!(t.redo());
+        assert_eq!(t.to_string(), "the quick brown fox");
+        // 新しく編集すると redo は消える
+        t.insert(0, "> ");
+        assert!(!t.redo());
+        t.check_invariants();
+        assert_eq!(t.char(2), Some('t'));
+        assert_eq!(t.slice_to_string(6..11), "quick");
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut t = PieceTable::from("line 1\nline 2\n");
+        let before = t.snapshot();
+        t.insert(7, "inserted\n");
+        t.remove(0..5);
+        assert_eq!(t.len_lines(), 4);
+        t.restore(&before);
+        assert_eq!(t.to_string(), "line 1\nline 2\n");
+        assert!(t.undo());
+        assert_eq!(t.to_string(), "1\ninserted\nline 2\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "another PieceTable")]
+    fn restore_snapshot_from_another_table() {
+        let a = PieceTable::from("hello world");
+        let mut b = PieceTable::from("hi");
+        b.restore(&a.snapshot());
+    }
+
+    #[test]
+    fn typing_extends_a_piece() {
+        let mut t = PieceTable::from("hello");
+        for (i, c) in " world".chars().enumerate() {
+            t.insert(5 + i, &c.to_string());
+        }
+        assert_eq!(t.to_string(), "hello world");
+        assert_eq!(t.piece_count(), 2);
+        t.check_invariants();
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, String),
+        Remove(usize, usize),
+        Undo,
+        Redo,
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (any::<usize>(), "[ab\nあい]{0,10}").prop_map(|(i, s)| Op::Insert(i, s)),
+            2 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Remove(a, b)),
+            1 => Just(Op::Undo),
+            1 => Just(Op::Redo),
+        ]
+    }
+
+    proptest! {
+        // 文字の位置で String と同じ操作をし、undo と redo は String の履歴と比べる
+        #[test]
+        fn same_as_string_random(
+            original in "[ab\nあい]{0,20}",
+            ops in prop::collection::vec(op_strategy(), 0..100),
+        ) {
+            let mut t = PieceTable::from(original.as_str());
+            let mut model = original.clone();
+            let (mut undo, mut redo): (Vec<String>, Vec<String>) = (Vec::new(), Vec::new());
+            for op in ops {
+                let n = TextBuffer::len_chars(&model);
+                match op {
+                    Op::Insert(i, s) => {
+                        let i = i % (n + 1);
+                        t.insert(i, &s);
+                        if !s.is_empty() {
+                            undo.push(model.clone());
+                            redo.clear();
+                            TextBuffer::insert(&mut model, i, &s);
+                        }
+                    }
+                    Op::Remove(a, b) => {
+                        let (a, b) = (a % (n + 1), b % (n + 1));
+                        t.remove(a.min(b)..a.max(b));
+                        if a != b {
+                            undo.push(model.clone());
+                            redo.clear();
+                            TextBuffer::remove(&mut model, a.min(b)..a.max(b));
+                        }
+                    }
+                    Op::Undo => {
+                        prop_assert_eq!(t.undo(), !undo.is_empty());
+                        if let Some(prev) = undo.pop() {
+                            redo.push(std::mem::replace(&mut model, prev));
+                        }
+                    }
+                    Op::Redo => {
+                        prop_assert_eq!(t.redo(), !redo.is_empty());
+                        if let Some(next) = redo.pop() {
+                            undo.push(std::mem::replace(&mut model, next));
+                        }
+                    }
+                }
+                t.check_invariants();
+                prop_assert_eq!(t.to_string(), model.clone());
+                prop_assert_eq!(t.len_lines(), TextBuffer::len_lines(&model));
+                let n = TextBuffer::len_chars(&model);
+                for i in (0..n).step_by(5) {
+                    prop_assert_eq!(t.char(i), model.chars().nth(i));
+                    prop_assert_eq!(t.slice_to_string(i..n.min(i + 7)), model.slice_to_string(i..n.min(i + 7)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+// 要素の比べ方。Greater の方を先に取り出す
+pub trait Compare<T> {
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+// 大きいものから取り出す
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Max;
+
+impl<T: Ord> Compare<T> for Max {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+// 小さいものから取り出す
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Min;
+
+impl<T: Ord> Compare<T> for Min {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> Ordering> Compare<T> for F {
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
+
+// 優先度付きキューの共通の操作
+// binaryheap などを、比べ方を変えずに差し替えて使えるようにする
+pub trait PriorityQueue<T> {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, value: T);
+
+    // 次に取り出す要素
+    fn peek(&self) -> Option<&T>;
+
+    fn pop(&mut self) -> Option<T>;
+}
+
+// 2 つのキューを 1 つにまとめられる優先度付きキュー
+// pairingheap、fibonacciheap、leftistheap、skewheap を、まとめる操作の多い使い方で差し替えられるようにする
+pub trait MergeableHeap<T>: PriorityQueue<T> {
+    // other の要素を全て取り込む
+    fn meld(&mut self, other: Self);
+}
+
+// 比較の基準にできるように、標準の BinaryHeap にも実装しておく
+impl<T: Ord> PriorityQueue<T> for BinaryHeap<T> {
+    fn len(&self) -> usize {
+        BinaryHeap::len(self)
+    }
+
+    fn push(&mut self, value: T) {
+        BinaryHeap::push(self, value)
+    }
+
+    fn peek(&self) -> Option<&T> {
+        BinaryHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        BinaryHeap::pop(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compare() {
+        assert_eq!(Max.compare(&1, &2), Ordering::Less);
+        assert_eq!(Min.compare(&1, &2), Ordering::Greater);
+        let by_len = |a: &&str, b: &&str| a.len().cmp(&b.len());
+        assert_eq!(by_len.compare(&"abc", &"de"), Ordering::Greater);
+    }
+
+    #[test]
+    fn binary_heap() {
+        let mut heap = BinaryHeap::new();
+        for v in [3, 1, 4, 1, 5] {
+            PriorityQueue::push(&mut heap, v);
+        }
+        assert_eq!(PriorityQueue::peek(&heap), Some(&5));
+        assert_eq!(PriorityQueue::pop(&mut heap), Some(5));
+        assert_eq!(PriorityQueue::len(&heap), 4);
+    }
+}
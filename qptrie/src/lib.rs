@@ -0,0 +1,643 @@
+// qp-trie (quadbit popcount trie, Tony Finch)
+// キーを 4 ビット (nibble) ずつ見る crit-bit 木
+//   - 分岐のノードは、キーが分かれる nibble の位置と、どの nibble の子があるかのビットマップだけを持つ
+//   - 子はある分だけ詰めて並べ、ビットマップの popcount で位置を求める
+//   - 葉はキーを全て持ち、探索は分岐の nibble だけを辿ってから最後に葉のキーと比べる
+// ART の Node4/16/48/256 と違って空きの場所を持たないので、疎なキーの集合ではメモリが少なくて済む
+//
+// キーが終わった位置は、どの nibble よりも小さい印として扱う
+// そのため他のキーの接頭辞になっているキーも入れられ、辞書順も保たれる
+// API は art と同じで、usize のキーはビッグエンディアンのバイト列として OrderedMap<usize, V> も実装する
+use std::{
+    convert::TryInto,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+// i 番目の nibble を 1 から 16 で、キーが終わっていれば 0 で返す。ビットマップのビットの位置になる
+fn nibble(key: &[u8], i: usize) -> u32 {
+    match key.get(i / 2) {
+        Some(&byte) if i.is_multiple_of(2) => (byte >> 4) as u32 + 1,
+        Some(&byte) => (byte & 0xf) as u32 + 1,
+        None => 0,
+    }
+}
+
+// 2 つのキーで最初に異なる nibble の位置。同じキーなら None
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    let bytes = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let i = bytes * 2;
+    if nibble(a, i) != nibble(b, i) {
+        Some(i)
+    } else if nibble(a, i + 1) != nibble(b, i + 1) {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Leaf<V> {
+    key: Box<[u8]>,
+    value: V,
+}
+
+#[derive(Debug)]
+struct Branch<V> {
+    // 子のキーが分かれる nibble の位置
+    index: usize,
+    // nibble(key, index) のビットが立っている子がある
+    bitmap: u32,
+    // ビットの順に詰めた子。2 つ以上ある
+    twigs: Box<[Node<V>]>,
+}
+
+impl<V> Branch<V> {
+    fn has(&self, bit: u32) -> bool {
+        self.bitmap & (1 << bit) != 0
+    }
+
+    // bit より小さいビットの子の数。bit の子があればその位置になる
+    fn position(&self, bit: u32) -> usize {
+        (self.bitmap & ((1 << bit) - 1)).count_ones() as usize
+    }
+
+    fn twig(&self, key: &[u8]) -> Option<&Node<V>> {
+        let bit = nibble(key, self.index);
+        self.has(bit).then(|| &self.twigs[self.position(bit)])
+    }
+
+    fn add(&mut self, bit: u32, node: Node<V>) {
+        debug_assert!(!self.has(bit), "twig already exists");
+        let mut twigs = mem::take(&mut self.twigs).into_vec();
+        twigs.insert(self.position(bit), node);
+        self.twigs = twigs.into_boxed_slice();
+        self.bitmap |= 1 << bit;
+    }
+
+    fn take(&mut self, bit: u32) -> Node<V> {
+        let mut twigs = mem::take(&mut self.twigs).into_vec();
+        let node = twigs.remove(self.position(bit));
+        self.twigs = twigs.into_boxed_slice();
+        self.bitmap &= !(1 << bit);
+        node
+    }
+}
+
+#[derive(Debug)]
+enum Node<V> {
+    Leaf(Box<Leaf<V>>),
+    Branch(Branch<V>),
+}
+
+impl<V> Node<V> {
+    // 部分木のキーはどれも index より前の nibble が同じなので、比べるときはどの葉を使ってもよい
+    fn any_leaf(&self) -> &Leaf<V> {
+        let mut node = self;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return leaf,
+                Node::Branch(branch) => node = &branch.twigs[0],
+            }
+        }
+    }
+}
+
+// key に最も近い葉。key の nibble の子があれば辿り、なければ最初の子に降りる
+fn closest<'a, V>(mut node: &'a Node<V>, key: &[u8]) -> &'a Leaf<V> {
+    loop {
+        match node {
+            Node::Leaf(leaf) => return leaf,
+            Node::Branch(branch) => node = branch.twig(key).unwrap_or(&branch.twigs[0]),
+        }
+    }
+}
+
+// branch の中から key の葉を取り除く。取り除いた結果、子が 1 つになった分岐は親が畳む
+fn remove<V>(branch: &mut Branch<V>, key: &[u8]) -> Option<V> {
+    let bit = nibble(key, branch.index);
+    if !branch.has(bit) {
+        return None;
+    }
+    let i = branch.position(bit);
+    match &mut branch.twigs[i] {
+        Node::Leaf(leaf) if *leaf.key == *key => {}
+        Node::Leaf(_) => return None,
+        Node::Branch(child) => {
+            let old = remove(child, key)?;
+            if child.twigs.len() == 1 {
+                let only = mem::take(&mut child.twigs).into_vec().pop();
+                branch.twigs[i] = only.expect("one twig is left");
+            }
+            return Some(old);
+        }
+    }
+    match branch.take(bit) {
+        Node::Leaf(leaf) => Some(leaf.value),
+        Node::Branch(_) => unreachable!("twig is a leaf"),
+    }
+}
+
+#[derive(Debug)]
+pub struct QpTrie<V> {
+    root: Option<Node<V>>,
+    len: usize,
+}
+
+impl<V> QpTrie<V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let mut node = self.root.as_ref()?;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return (*leaf.key == *key).then_some(&leaf.value),
+                Node::Branch(branch) => node = branch.twig(key)?,
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        let mut node = self.root.as_mut()?;
+        loop {
+            match node {
+                Node::Leaf(leaf) => return (*leaf.key == *key).then_some(&mut leaf.value),
+                Node::Branch(branch) => {
+                    let bit = nibble(key, branch.index);
+                    if !branch.has(bit) {
+                        return None;
+                    }
+                    node = &mut branch.twigs[branch.position(bit)];
+                }
+            }
+        }
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: &[u8], value: V) -> Option<V> {
+        let leaf = Box::new(Leaf {
+            key: key.into(),
+            value,
+        });
+        let root = match &mut self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(Node::Leaf(leaf));
+                self.len += 1;
+                return None;
+            }
+        };
+        // 最も近い葉と最初に異なる nibble が、新しい葉を分ける位置になる
+        let index = match first_difference(&closest(root, key).key, key) {
+            Some(index) => index,
+            None => {
+                let old = self.get_mut(key).expect("closest leaf has the key");
+                return Some(mem::replace(old, leaf.value));
+            }
+        };
+        // index より前で分かれる分岐は、key の nibble の子を辿る
+        let mut node = root;
+        while matches!(node, Node::Branch(branch) if branch.index < index) {
+            node = match node {
+                Node::Branch(branch) => {
+                    let bit = nibble(key, branch.index);
+                    &mut branch.twigs[branch.position(bit)]
+                }
+                Node::Leaf(_) => unreachable!("node is a branch"),
+            };
+        }
+        match node {
+            Node::Branch(branch) if branch.index == index => {
+                branch.add(nibble(key, index), Node::Leaf(leaf));
+            }
+            _ => {
+                // node の部分木全体と新しい葉を、index で分ける分岐を挟む
+                let placeholder = Node::Branch(Branch {
+                    index,
+                    bitmap: 0,
+                    twigs: Box::new([]),
+                });
+                let old = mem::replace(node, placeholder);
+                let old_bit = nibble(&old.any_leaf().key, index);
+                if let Node::Branch(branch) = node {
+                    branch.add(old_bit, old);
+                    branch.add(nibble(key, index), Node::Leaf(leaf));
+                }
+            }
+        }
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let old = match self.root.as_mut()? {
+            Node::Leaf(leaf) if *leaf.key == *key => match self.root.take() {
+                Some(Node::Leaf(leaf)) => leaf.value,
+                _ => unreachable!("root is a leaf"),
+            },
+            Node::Leaf(_) => return None,
+            Node::Branch(branch) => {
+                let old = remove(branch, key)?;
+                if branch.twigs.len() == 1 {
+                    self.root = mem::take(&mut branch.twigs).into_vec().pop();
+                }
+                old
+            }
+        };
+        self.len -= 1;
+        Some(old)
+    }
+
+    // キーの辞書順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: self.root.iter().collect(),
+            max_key: None,
+        }
+    }
+
+    // prefix で始まるキーを辞書順に返す
+    pub fn prefix(&self, prefix: &[u8]) -> Iter<'_, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        let mut node = match self.root.as_ref() {
+            Some(root) => root,
+            None => return iter,
+        };
+        // prefix の nibble を全て調べる分岐までは、prefix の nibble の子を辿る
+        while let Node::Branch(branch) = node {
+            if branch.index >= prefix.len() * 2 {
+                break;
+            }
+            node = match branch.twig(prefix) {
+                Some(twig) => twig,
+                None => return iter,
+            };
+        }
+        // ここから下のキーは prefix の長さまで同じなので、1 つ比べれば全体が決まる
+        if node.any_leaf().key.starts_with(prefix) {
+            iter.stack.push(node);
+        }
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリを辞書順に返す
+    pub fn range(&self, min_key: &[u8], max_key: &[u8]) -> Iter<'_, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key.into()),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        let mut node = match self.root.as_ref() {
+            Some(root) => root,
+            None => return iter,
+        };
+        // 最も近い葉と min_key が分かれる位置より前は、min_key の nibble の子を辿る
+        // 辿った子より後ろの子は min_key より大きいので積んでおく
+        let closest = closest(node, min_key);
+        let index = first_difference(&closest.key, min_key);
+        while let Node::Branch(branch) = node {
+            if index.is_some_and(|index| branch.index >= index) {
+                break;
+            }
+            let i = branch.position(nibble(min_key, branch.index));
+            iter.stack.extend(branch.twigs[i + 1..].iter().rev());
+            node = &branch.twigs[i];
+        }
+        let index = match index {
+            Some(index) => index,
+            // min_key そのものの葉に着いた
+            None => {
+                iter.stack.push(node);
+                return iter;
+            }
+        };
+        let bit = nibble(min_key, index);
+        match node {
+            Node::Branch(branch) if branch.index == index => {
+                let i = branch.position(bit);
+                iter.stack.extend(branch.twigs[i..].iter().rev());
+            }
+            // 部分木のキーは index より前が min_key と同じなので、index の nibble で大小が決まる
+            _ => {
+                if nibble(&closest.key, index) > bit {
+                    iter.stack.push(node);
+                }
+            }
+        }
+        iter
+    }
+
+    // 分岐のビットマップと子の対応、nibble の位置、len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        // 部分木のキーの数を返す
+        fn check<V>(node: &Node<V>) -> usize {
+            let branch = match node {
+                Node::Leaf(_) => return 1,
+                Node::Branch(branch) => branch,
+            };
+            assert!(branch.bitmap < 1 << 17, "bitmap has an unknown bit");
+            assert_eq!(
+                branch.bitmap.count_ones() as usize,
+                branch.twigs.len(),
+                "bitmap does not match twigs"
+            );
+            assert!(branch.twigs.len() >= 2, "branch has a single twig");
+            let first = node.any_leaf();
+            let mut count = 0;
+            let bits = (0..17).filter(|&bit| branch.has(bit));
+            for (bit, twig) in bits.zip(branch.twigs.iter()) {
+                if let Node::Branch(child) = twig {
+                    assert!(child.index > branch.index, "index does not increase");
+                }
+                let mut stack = vec![twig];
+                while let Some(node) = stack.pop() {
+                    match node {
+                        Node::Leaf(leaf) => {
+                            assert_eq!(
+                                nibble(&leaf.key, branch.index),
+                                bit,
+                                "leaf is under a wrong twig"
+                            );
+                            assert!(
+                                first_difference(&leaf.key, &first.key)
+                                    .is_none_or(|i| i >= branch.index),
+                                "leaf differs before its branch"
+                            );
+                        }
+                        Node::Branch(child) => stack.extend(child.twigs.iter()),
+                    }
+                }
+                count += check(twig);
+            }
+            count
+        }
+        let count = self.root.as_ref().map_or(0, check);
+        assert_eq!(self.len, count, "len is stale");
+    }
+}
+
+impl<V> Default for QpTrie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// まだ開いていない部分木を積んでおく。子は順に並んでいるので、逆順に積めば辞書順に取り出せる
+pub struct Iter<'a, V> {
+    stack: Vec<&'a Node<V>>,
+    max_key: Option<Box<[u8]>>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a [u8], &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf = loop {
+            match self.stack.pop()? {
+                Node::Leaf(leaf) => break leaf,
+                Node::Branch(branch) => self.stack.extend(branch.twigs.iter().rev()),
+            }
+        };
+        if self.max_key.as_ref().is_some_and(|max| *leaf.key > **max) {
+            self.stack.clear();
+            return None;
+        }
+        Some((&leaf.key, &leaf.value))
+    }
+}
+
+impl<'a, V> FusedIterator for Iter<'a, V> {}
+
+impl<'a, V> IntoIterator for &'a QpTrie<V> {
+    type Item = (&'a [u8], &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Iter<'a, V> {
+        self.iter()
+    }
+}
+
+impl<K: AsRef<[u8]>, V> FromIterator<(K, V)> for QpTrie<V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        for (key, value) in iter {
+            trie.insert(key.as_ref(), value);
+        }
+        trie
+    }
+}
+
+// OrderedMap<usize, V> の iter。キーのバイト列を usize に戻す
+pub struct UsizeIter<'a, V>(Iter<'a, V>);
+
+impl<'a, V> Iterator for UsizeIter<'a, V> {
+    type Item = (usize, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value) = self.0.next()?;
+        let key = key
+            .try_into()
+            .expect("usize keys have the same length as usize");
+        Some((usize::from_be_bytes(key), value))
+    }
+}
+
+impl<'a, V> FusedIterator for UsizeIter<'a, V> {}
+
+impl<V> OrderedMap<usize, V> for QpTrie<V> {
+    type Iter<'a>
+        = UsizeIter<'a, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: usize, value: V) -> Option<V> {
+        QpTrie::insert(self, &key.to_be_bytes(), value)
+    }
+
+    fn get(&self, key: usize) -> Option<&V> {
+        QpTrie::get(self, &key.to_be_bytes())
+    }
+
+    fn remove(&mut self, key: usize) -> Option<V> {
+        QpTrie::remove(self, &key.to_be_bytes())
+    }
+
+    fn iter(&self) -> UsizeIter<'_, V> {
+        UsizeIter(QpTrie::iter(self))
+    }
+
+    fn range(&self, min_key: usize, max_key: usize) -> Vec<(usize, &V)> {
+        UsizeIter(QpTrie::range(
+            self,
+            &min_key.to_be_bytes(),
+            &max_key.to_be_bytes(),
+        ))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = QpTrie::new();
+        assert_eq!(t.get(b"a"), None);
+        assert_eq!(t.remove(b"a"), None);
+        // 接頭辞になっているキーと、空のキーを含む
+        let keys: [&[u8]; 7] = [
+            b"romane", b"romanus", b"romulus", b"rubens", b"ruber", b"rom", b"",
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(t.insert(key, i), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(b"rom", 50), Some(5));
+        assert_eq!(t.get(b"rom"), Some(&50));
+        assert_eq!(t.get(b"ro"), None);
+        assert_eq!(t.get(b"romanes"), None);
+        *t.get_mut(b"").unwrap() = 60;
+        assert_eq!(t.len(), 7);
+        for key in [&b"rom"[..], b"romane", b""] {
+            assert!(t.remove(key).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(b"rom"), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k.to_vec(), *v)).collect();
+        assert_eq!(
+            all,
+            vec![
+                (b"romanus".to_vec(), 1),
+                (b"romulus".to_vec(), 2),
+                (b"rubens".to_vec(), 3),
+                (b"ruber".to_vec(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_nibbles() {
+        // 1 つの分岐に 16 個の nibble と終わりの印の 17 個の子を付けてから減らす
+        let mut t = QpTrie::new();
+        t.insert(b"k", 256);
+        for byte in (0..=255u8).step_by(17) {
+            t.insert(&[b'k', byte], byte as usize);
+            t.check_invariants();
+        }
+        let keys: Vec<_> = t.prefix(b"k").map(|(_, v)| *v).collect();
+        let mut expected = vec![256];
+        expected.extend((0..=255).step_by(17));
+        assert_eq!(keys, expected);
+        for byte in (0..=255u8).step_by(17) {
+            assert_eq!(t.remove(&[b'k', byte]), Some(byte as usize));
+            t.check_invariants();
+        }
+        assert_eq!(t.len(), 1);
+    }
+
+    #[test]
+    fn prefix_scan() {
+        let t: QpTrie<_> = ["app", "apple", "applet", "apply", "apt", "banana", "ap"]
+            .iter()
+            .map(|k| (k, k.len()))
+            .collect();
+        let keys = |iter: Iter<'_, usize>| -> Vec<String> {
+            iter.map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+                .collect()
+        };
+        assert_eq!(keys(t.prefix(b"appl")), ["apple", "applet", "apply"]);
+        assert_eq!(keys(t.prefix(b"app")), ["app", "apple", "applet", "apply"]);
+        assert_eq!(keys(t.prefix(b"applet")), ["applet"]);
+        assert_eq!(keys(t.prefix(b"applets")), Vec::<String>::new());
+        assert_eq!(keys(t.prefix(b"b")), ["banana"]);
+        assert_eq!(keys(t.prefix(b"c")), Vec::<String>::new());
+        assert_eq!(t.prefix(b"").count(), 7);
+        assert_eq!(
+            keys(t.range(b"apple", b"apt")),
+            ["apple", "applet", "apply", "apt"]
+        );
+        assert_eq!(keys(t.range(b"apq", b"b")), ["apt"]);
+    }
+
+    #[test]
+    fn usize_keys_keep_order() {
+        let mut t = QpTrie::new();
+        for k in [256usize, 1, 65536, 255, 0, usize::MAX] {
+            OrderedMap::insert(&mut t, k, k);
+        }
+        let keys: Vec<_> = OrderedMap::iter(&t).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![0, 1, 255, 256, 65536, usize::MAX]);
+        assert_eq!(
+            OrderedMap::range(&t, 2, 65536),
+            vec![(255, &255), (256, &256), (65536, &65536)]
+        );
+    }
+
+    // 共通の接頭辞や、接頭辞になっているキーができやすいように、短くて文字の少ないキーを作る
+    // 同じバイトの上下の nibble で分かれるように、0x00, 0x01, 0x10, 0x11 を使う
+    fn key() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(prop::sample::select(vec![0x00, 0x01, 0x10, 0x11]), 0..6)
+    }
+
+    proptest! {
+        // バイト列のキーと prefix は conformance で確かめられないので、ここで BTreeMap と比べる
+        #[test]
+        fn byte_keys_same_as_btree_map_random(
+            ops in prop::collection::vec((0..5u8, key(), key(), 0..100usize), 0..300),
+        ) {
+            let mut trie = QpTrie::new();
+            let mut expected = BTreeMap::new();
+            for (op, a, b, value) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(trie.insert(&a, value), expected.insert(a, value)),
+                    2 => prop_assert_eq!(trie.remove(&a), expected.remove(&a)),
+                    3 => {
+                        prop_assert_eq!(trie.get(&a), expected.get(&a));
+                        let actual: Vec<_> = trie.prefix(&a).collect();
+                        let wanted: Vec<_> = expected
+                            .iter()
+                            .filter(|(k, _)| k.starts_with(&a))
+                            .map(|(k, v)| (&k[..], v))
+                            .collect();
+                        prop_assert_eq!(actual, wanted);
+                    }
+                    _ => {
+                        let actual: Vec<_> = trie.range(&a, &b).collect();
+                        let wanted: Vec<_> = expected
+                            .iter()
+                            .filter(|(k, _)| a <= **k && **k <= b)
+                            .map(|(k, v)| (&k[..], v))
+                            .collect();
+                        prop_assert_eq!(actual, wanted);
+                    }
+                }
+                trie.check_invariants();
+                prop_assert_eq!(trie.len(), expected.len());
+            }
+            prop_assert!(trie.iter().eq(expected.iter().map(|(k, v)| (&k[..], v))));
+        }
+    }
+}
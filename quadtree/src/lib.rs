@@ -0,0 +1,424 @@
+// region quadtree
+// 決めた範囲 (bounds) を縦横に 2 等分した 4 つの領域へ、再帰的に分けていく木
+// 各ノードは自分の領域に入る (箱, 値) を持ち、capacity を超えたら 4 つに分けて、子の領域に収まるものを子へ移す
+// 分ける線に触れる箱は分けたノードに残る。深さが max_depth のノードはそれ以上分けない
+//
+// 削除で部分木の要素が capacity 以下に減ったら、子を畳んで 1 つのノードに戻す
+// 範囲の問い合わせは、領域が重ならない部分木を飛ばす
+// 当たり判定は、同じノードの要素どうしと、祖先のノードの要素とだけを比べればよい
+//
+// 要素の数が多かったり、大きさが偏ったりするなら R-tree の方がよい
+use std::mem;
+
+pub use error::{Error, Result};
+pub use spatial::{Coordinate, Rect, SpatialIndex};
+
+mod error;
+
+// 左下、右下、左上、右上の 4 つに分ける
+fn quadrants<T: Coordinate>(region: &Rect<T, 2>) -> [Rect<T, 2>; 4] {
+    let (min, max, mid) = (*region.min(), *region.max(), region.center());
+    [
+        Rect::new(min, mid),
+        Rect::new([mid[0], min[1]], [max[0], mid[1]]),
+        Rect::new([min[0], mid[1]], [mid[0], max[1]]),
+        Rect::new(mid, max),
+    ]
+}
+
+#[derive(Debug, Clone)]
+struct Node<T, V> {
+    region: Rect<T, 2>,
+    // どの子にも収まらない要素。葉なら全ての要素
+    items: Vec<(Rect<T, 2>, V)>,
+    children: Option<Box<[Node<T, V>; 4]>>,
+    // 部分木の要素の数
+    len: usize,
+}
+
+impl<T: Coordinate, V> Node<T, V> {
+    fn new(region: Rect<T, 2>) -> Self {
+        Self {
+            region,
+            items: Vec::new(),
+            children: None,
+            len: 0,
+        }
+    }
+
+    // rect が収まる子の位置。分ける線に触れる rect はどの子にも入れない
+    // 違う子の要素は線で離れているので、重なりを調べるのは同じノードと祖先の要素だけでよい
+    fn child_for(&self, rect: &Rect<T, 2>) -> Option<usize> {
+        self.children.as_ref()?;
+        let mid = self.region.center();
+        let mut index = 0;
+        for (axis, &mid) in mid.iter().enumerate() {
+            if rect.min()[axis] > mid {
+                index |= 1 << axis;
+            } else if rect.max()[axis] >= mid {
+                return None;
+            }
+        }
+        Some(index)
+    }
+
+    // 部分木の要素を全て out に移し、子をなくす
+    fn drain_into(&mut self, out: &mut Vec<(Rect<T, 2>, V)>) {
+        out.append(&mut self.items);
+        if let Some(mut children) = self.children.take() {
+            for child in children.iter_mut() {
+                child.drain_into(out);
+            }
+        }
+        self.len = 0;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Quadtree<T, V> {
+    root: Node<T, V>,
+    capacity: usize,
+    max_depth: usize,
+}
+
+impl<T: Coordinate, V> Quadtree<T, V> {
+    pub const DEFAULT_CAPACITY: usize = 8;
+    pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+    // bounds の中に入る要素を持つ木
+    pub fn new(bounds: Rect<T, 2>) -> Self {
+        Self {
+            root: Node::new(bounds),
+            capacity: Self::DEFAULT_CAPACITY,
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    // ノードが capacity 個を超えたら分け、深さ max_depth より深くは分けない
+    pub fn with_limits(bounds: Rect<T, 2>, capacity: usize, max_depth: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(Error::InvalidCapacity { capacity });
+        }
+        Ok(Self {
+            root: Node::new(bounds),
+            capacity,
+            max_depth,
+        })
+    }
+
+    pub fn bounds(&self) -> &Rect<T, 2> {
+        &self.root.region
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.len == 0
+    }
+
+    // 根だけなら 0
+    pub fn depth(&self) -> usize {
+        fn depth<T, V>(node: &Node<T, V>) -> usize {
+            node.children
+                .as_ref()
+                .map_or(0, |c| 1 + c.iter().map(depth).max().unwrap_or(0))
+        }
+        depth(&self.root)
+    }
+
+    // rect が bounds に収まらなければ入れない
+    pub fn insert(&mut self, rect: Rect<T, 2>, value: V) -> Result<()> {
+        if !self.root.region.contains(&rect) {
+            return Err(Error::OutOfBounds);
+        }
+        let mut node = &mut self.root;
+        let mut depth = 0;
+        loop {
+            node.len += 1;
+            match node.child_for(&rect) {
+                Some(i) => {
+                    node = &mut node.children.as_mut().expect("node has children")[i];
+                    depth += 1;
+                }
+                None => break,
+            }
+        }
+        node.items.push((rect, value));
+        if node.children.is_none() && node.items.len() > self.capacity && depth < self.max_depth {
+            Self::subdivide(node);
+        }
+        Ok(())
+    }
+
+    // 4 つの子を作り、子に収まる要素を移す
+    fn subdivide(node: &mut Node<T, V>) {
+        let [a, b, c, d] = quadrants(&node.region);
+        node.children = Some(Box::new([
+            Node::new(a),
+            Node::new(b),
+            Node::new(c),
+            Node::new(d),
+        ]));
+        for (rect, value) in mem::take(&mut node.items) {
+            match node.child_for(&rect) {
+                Some(i) => {
+                    let child = &mut node.children.as_mut().expect("node has children")[i];
+                    child.items.push((rect, value));
+                    child.len += 1;
+                }
+                None => node.items.push((rect, value)),
+            }
+        }
+    }
+
+    // 箱が rect と等しい要素を 1 つ外して値を返す。同じ箱が複数あれば、どれを外すかは決めない
+    pub fn remove(&mut self, rect: &Rect<T, 2>) -> Option<V> {
+        fn remove<T: Coordinate, V>(
+            node: &mut Node<T, V>,
+            rect: &Rect<T, 2>,
+            capacity: usize,
+        ) -> Option<V> {
+            let value = match node.child_for(rect) {
+                Some(i) => remove(
+                    &mut node.children.as_mut().expect("node has children")[i],
+                    rect,
+                    capacity,
+                )?,
+                None => {
+                    let i = node.items.iter().position(|(r, _)| r == rect)?;
+                    node.items.swap_remove(i).1
+                }
+            };
+            node.len -= 1;
+            // 部分木が 1 つのノードに収まるなら、子を畳む
+            if node.children.is_some() && node.len <= capacity {
+                let mut items = Vec::with_capacity(node.len);
+                node.drain_into(&mut items);
+                node.items = items;
+                node.len = node.items.len();
+            }
+            Some(value)
+        }
+        remove(&mut self.root, rect, self.capacity)
+    }
+
+    // 全ての要素。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = (&Rect<T, 2>, &V)> + '_ {
+        self.walk(|_| true)
+    }
+
+    // 箱が region と重なる要素。順序は決めない
+    pub fn query<'a>(
+        &'a self,
+        region: &'a Rect<T, 2>,
+    ) -> impl Iterator<Item = (&'a Rect<T, 2>, &'a V)> + 'a {
+        self.walk(move |rect| rect.intersects(region))
+    }
+
+    // 領域が keep を満たすノードだけを降りて、箱が keep を満たす要素を返す
+    fn walk<'a, F>(&'a self, keep: F) -> impl Iterator<Item = (&'a Rect<T, 2>, &'a V)> + 'a
+    where
+        F: Fn(&Rect<T, 2>) -> bool + 'a,
+    {
+        let mut stack = vec![&self.root];
+        let mut items = [].iter();
+        std::iter::from_fn(move || loop {
+            if let Some((rect, value)) = items.by_ref().find(|(rect, _)| keep(rect)) {
+                return Some((rect, value));
+            }
+            let node = stack.pop()?;
+            items = node.items.iter();
+            if let Some(children) = &node.children {
+                stack.extend(children.iter().filter(|c| c.len > 0 && keep(&c.region)));
+            }
+        })
+    }
+
+    // 箱が重なる要素の組を全て返す。組の順序は決めない
+    pub fn intersecting_pairs(&self) -> Vec<(&V, &V)> {
+        // 祖先のノードの要素は、子孫のどの要素とも重なりうる
+        fn walk<'a, T: Coordinate, V>(
+            node: &'a Node<T, V>,
+            ancestors: &mut Vec<&'a (Rect<T, 2>, V)>,
+            pairs: &mut Vec<(&'a V, &'a V)>,
+        ) {
+            for (i, (rect, value)) in node.items.iter().enumerate() {
+                for (other, other_value) in ancestors.iter().copied().chain(&node.items[i + 1..]) {
+                    if rect.intersects(other) {
+                        pairs.push((other_value, value));
+                    }
+                }
+            }
+            let mark = ancestors.len();
+            ancestors.extend(&node.items);
+            if let Some(children) = &node.children {
+                for child in children.iter() {
+                    walk(child, ancestors, pairs);
+                }
+            }
+            ancestors.truncate(mark);
+        }
+        let mut pairs = Vec::new();
+        walk(&self.root, &mut Vec::new(), &mut pairs);
+        pairs
+    }
+
+    // 要素がノードの領域に収まり、子に収まる要素が残っておらず、len が合うか確かめる
+    pub fn check_invariants(&self) {
+        fn check<T: Coordinate, V>(node: &Node<T, V>, capacity: usize) -> usize {
+            assert!(
+                node.items.iter().all(|(r, _)| node.region.contains(r)),
+                "item is outside its node"
+            );
+            let mut count = node.items.len();
+            if let Some(children) = &node.children {
+                assert!(
+                    node.items.iter().all(|(r, _)| node.child_for(r).is_none()),
+                    "item fits in a child"
+                );
+                let quadrants = quadrants(&node.region);
+                for (child, quadrant) in children.iter().zip(&quadrants) {
+                    assert!(child.region == *quadrant, "child region is wrong");
+                    count += check(child, capacity);
+                }
+                assert!(count > capacity, "subtree should be collapsed");
+            }
+            assert_eq!(node.len, count, "len is stale");
+            count
+        }
+        check(&self.root, self.capacity);
+    }
+}
+
+impl<T: Coordinate, V> SpatialIndex<T, 2> for Quadtree<T, V> {
+    type Value = V;
+
+    fn len(&self) -> usize {
+        self.root.len
+    }
+
+    fn query_box(&self, window: &Rect<T, 2>) -> Vec<(Rect<T, 2>, &V)> {
+        let window = *window;
+        self.walk(move |rect| rect.intersects(&window))
+            .map(|(rect, value)| (*rect, value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect<i32, 2> {
+        Rect::new([x, y], [x + w, y + h])
+    }
+
+    #[test]
+    fn query_and_collide() {
+        let mut tree = Quadtree::with_limits(rect(0, 0, 100, 100), 2, 8).unwrap();
+        tree.insert(rect(10, 10, 5, 5), "a").unwrap();
+        tree.insert(rect(12, 12, 5, 5), "b").unwrap();
+        tree.insert(rect(70, 70, 5, 5), "c").unwrap();
+        tree.insert(rect(45, 45, 10, 10), "d").unwrap();
+        tree.insert(Rect::point([54, 55]), "e").unwrap();
+        assert_eq!(
+            tree.insert(rect(95, 95, 10, 10), "f"),
+            Err(Error::OutOfBounds)
+        );
+        assert!(tree.depth() >= 1);
+        tree.check_invariants();
+
+        let mut found: Vec<_> = tree.query(&rect(0, 0, 50, 50)).map(|(_, v)| *v).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec!["a", "b", "d"]);
+        let mut pairs: Vec<_> = tree
+            .intersecting_pairs()
+            .into_iter()
+            .map(|(a, b)| if a < b { (*a, *b) } else { (*b, *a) })
+            .collect();
+        pairs.sort_unstable();
+        assert_eq!(pairs, vec![("a", "b"), ("d", "e")]);
+
+        assert_eq!(tree.remove(&rect(70, 70, 5, 5)), Some("c"));
+        assert_eq!(tree.remove(&rect(70, 70, 5, 5)), None);
+        assert_eq!(tree.len(), 4);
+        tree.check_invariants();
+        assert_eq!(
+            Quadtree::<i32, ()>::with_limits(rect(0, 0, 1, 1), 0, 1).unwrap_err(),
+            Error::InvalidCapacity { capacity: 0 }
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(i32, i32, i32, i32),
+        Remove(prop::sample::Index),
+    }
+
+    proptest! {
+        // 重なる要素と重なる組が、全ての要素を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            ops in prop::collection::vec(prop_oneof![
+                3 => (0..64i32, 0..64i32, 0..8i32, 0..8i32).prop_map(|(x, y, w, h)| Op::Insert(x, y, w, h)),
+                1 => any::<prop::sample::Index>().prop_map(Op::Remove),
+            ], 0..150),
+            capacity in 1..6usize,
+            windows in prop::collection::vec((0..64i32, 0..64i32, 0..20i32, 0..20i32), 1..5),
+        ) {
+            let mut tree = Quadtree::with_limits(rect(0, 0, 64, 64), capacity, 5).unwrap();
+            let mut model: Vec<(Rect<i32, 2>, usize)> = Vec::new();
+            for (next, op) in ops.into_iter().enumerate() {
+                match op {
+                    Op::Insert(x, y, w, h) => {
+                        let r = rect(x, y, w.min(64 - x), h.min(64 - y));
+                        tree.insert(r, next).unwrap();
+                        model.push((r, next));
+                    }
+                    Op::Remove(index) => {
+                        if model.is_empty() {
+                            continue;
+                        }
+                        let (r, _) = model[index.index(model.len())];
+                        let value = tree.remove(&r).unwrap();
+                        let i = model.iter().position(|&(mr, mv)| mr == r && mv == value).unwrap();
+                        model.swap_remove(i);
+                    }
+                }
+                tree.check_invariants();
+            }
+            prop_assert_eq!(tree.len(), model.len());
+            for (x, y, w, h) in windows {
+                let window = rect(x, y, w, h);
+                let mut expected: Vec<usize> = model.iter().filter(|(r, _)| r.intersects(&window)).map(|&(_, v)| v).collect();
+                let mut found: Vec<usize> = tree.query(&window).map(|(_, &v)| v).collect();
+                expected.sort_unstable();
+                found.sort_unstable();
+                prop_assert_eq!(found, expected);
+
+                // 全ての要素を調べる Vec と同じになる
+                let mut expected = model.query_box(&window);
+                let mut found = tree.query_box(&window);
+                expected.sort_by_key(|&(_, &v)| v);
+                found.sort_by_key(|&(_, &v)| v);
+                prop_assert_eq!(found, expected);
+            }
+            let mut expected = Vec::new();
+            for (i, (a, va)) in model.iter().enumerate() {
+                for (b, vb) in &model[i + 1..] {
+                    if a.intersects(b) {
+                        expected.push((*va.min(vb), *va.max(vb)));
+                    }
+                }
+            }
+            let mut found: Vec<_> = tree.intersecting_pairs().into_iter().map(|(&a, &b)| (a.min(b), a.max(b))).collect();
+            expected.sort_unstable();
+            found.sort_unstable();
+            prop_assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+// 2 次元の range tree
+// 点を x の順に並べ、x の区間を二分していく木の各ノードに、その区間の点を y の順に並べた列を持たせる (BST of BSTs)
+// 長方形 [x0, x1] x [y0, y1] は、x の区間を O(log n) 個のノードで覆い、それぞれの列を y で二分探索すれば求まる
+//
+// fractional cascading
+// 子の列は親の列を 2 つに分けたものなので、親の列の各位置について「そこより前に左の子へ行く点がいくつあるか」を持っておく
+// すると y の二分探索は根で 1 回だけで、子での位置はこの数から O(1) で求まる
+// 数えるのは O(log n)、k 個の点を返すのは O(log n + k) で、メモリは O(n log n)
+//
+// 作った後に点を足したり消したりはできない。変えたら作り直す
+use std::{
+    iter::FromIterator,
+    ops::{Bound, RangeBounds},
+};
+
+#[derive(Debug, Clone)]
+struct Level {
+    // 各ブロック (木のノード) の点を y の順に並べたもの。点は x の順の番号で持つ
+    order: Vec<usize>,
+    // lefts[p] は、この段の位置 p より前で、左の子へ行く点の数
+    lefts: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RangeTree<K, V> {
+    // (x, y) の順に並べた点
+    points: Vec<(K, K, V)>,
+    // levels[d] は深さ d のノードを左から並べたもの。深さ d のノード [lo, hi) は、位置 lo..hi を使う
+    levels: Vec<Level>,
+}
+
+// pred が true になる位置が前に並ぶとき、最初に false になる位置
+fn partition(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+// range に入る key の位置の範囲。key(i) は i について昇順
+fn span<'a, K, R>(range: &R, len: usize, key: impl Fn(usize) -> &'a K) -> (usize, usize)
+where
+    K: Ord + 'a,
+    R: RangeBounds<K>,
+{
+    let start = match range.start_bound() {
+        Bound::Included(start) => partition(len, |i| key(i) < start),
+        Bound::Excluded(start) => partition(len, |i| key(i) <= start),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => partition(len, |i| key(i) <= end),
+        Bound::Excluded(end) => partition(len, |i| key(i) < end),
+        Bound::Unbounded => len,
+    };
+    (start, end.max(start))
+}
+
+impl<K: Ord, V> RangeTree<K, V> {
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    // 全ての点を (x, y) の順に返す
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &K, &V)> + '_ {
+        self.points.iter().map(|(x, y, v)| (x, y, v))
+    }
+
+    // x が xs に、y が ys に入る点の数
+    pub fn count<X: RangeBounds<K>, Y: RangeBounds<K>>(&self, xs: X, ys: Y) -> usize {
+        let mut count = 0;
+        self.visit(&xs, &ys, |_, start, end| count += end - start);
+        count
+    }
+
+    // x が xs に、y が ys に入る点。順序は決めない
+    pub fn query<X: RangeBounds<K>, Y: RangeBounds<K>>(
+        &self,
+        xs: X,
+        ys: Y,
+    ) -> impl Iterator<Item = (&K, &K, &V)> + '_ {
+        let mut blocks = Vec::new();
+        self.visit(&xs, &ys, |level, start, end| {
+            blocks.push(&self.levels[level].order[start..end])
+        });
+        blocks.into_iter().flatten().map(move |&i| {
+            let (x, y, v) = &self.points[i];
+            (x, y, v)
+        })
+    }
+
+    // 長方形を覆うノードごとに、段と、その段での y の範囲の位置を渡す
+    fn visit<X, Y, F>(&self, xs: &X, ys: &Y, mut f: F)
+    where
+        X: RangeBounds<K>,
+        Y: RangeBounds<K>,
+        F: FnMut(usize, usize, usize),
+    {
+        let len = self.len();
+        let (a, b) = span(xs, len, |i| &self.points[i].0);
+        if a == b {
+            return;
+        }
+        // y の二分探索は根の列で 1 回だけ
+        let root = &self.levels[0].order;
+        let (start, end) = span(ys, len, |p| &self.points[root[p]].1);
+        self.descend(0, 0, len, (a, b), (start, end), &mut f);
+    }
+
+    fn descend<F: FnMut(usize, usize, usize)>(
+        &self,
+        level: usize,
+        lo: usize,
+        hi: usize,
+        (a, b): (usize, usize),
+        (start, end): (usize, usize),
+        f: &mut F,
+    ) {
+        if start == end || hi <= a || b <= lo {
+            return;
+        }
+        if a <= lo && hi <= b {
+            f(level, start, end);
+            return;
+        }
+        // ノードの大きさが 1 なら、上のどちらかで返っている
+        let mid = (lo + hi) / 2;
+        let lefts = &self.levels[level].lefts;
+        let (left_start, left_end) = (lo + lefts[start] - lefts[lo], lo + lefts[end] - lefts[lo]);
+        let (right_start, right_end) = (
+            mid + (start - lo) - (left_start - lo),
+            mid + (end - lo) - (left_end - lo),
+        );
+        self.descend(level + 1, lo, mid, (a, b), (left_start, left_end), f);
+        self.descend(level + 1, mid, hi, (a, b), (right_start, right_end), f);
+    }
+
+    // 各段のブロックが y の順に並び、lefts が子のブロックと合うか確かめる
+    pub fn check_invariants(&self) {
+        let len = self.len();
+        assert!(
+            self.points
+                .windows(2)
+                .all(|w| (&w[0].0, &w[0].1) <= (&w[1].0, &w[1].1)),
+            "points are out of order"
+        );
+        let mut blocks = vec![(0, len)];
+        for (depth, level) in self.levels.iter().enumerate() {
+            assert_eq!(level.order.len(), len, "level has a wrong length");
+            assert_eq!(level.lefts.len(), len + 1, "level has a wrong length");
+            let mut next = Vec::new();
+            for &(lo, hi) in &blocks {
+                let block = &level.order[lo..hi];
+                let mut sorted: Vec<usize> = (lo..hi).collect();
+                sorted.sort_by(|&i, &j| (&self.points[i].1, i).cmp(&(&self.points[j].1, j)));
+                assert_eq!(block, &sorted[..], "block is out of order");
+                if hi - lo <= 1 {
+                    continue;
+                }
+                let mid = (lo + hi) / 2;
+                for p in lo..hi {
+                    let left = (level.lefts[p + 1] - level.lefts[p]) == 1;
+                    assert_eq!(left, level.order[p] < mid, "lefts is stale");
+                }
+                next.push((lo, mid));
+                next.push((mid, hi));
+            }
+            blocks = next;
+            if depth + 1 == self.levels.len() {
+                assert!(blocks.is_empty(), "a block is not split");
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> From<Vec<(K, K, V)>> for RangeTree<K, V> {
+    // 段ごとに、各ブロックを左の子へ行く点と右の子へ行く点に安定に分ける。O(n log n)
+    fn from(mut points: Vec<(K, K, V)>) -> Self {
+        points.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        let len = points.len();
+        let mut order: Vec<usize> = (0..len).collect();
+        order.sort_by(|&i, &j| (&points[i].1, i).cmp(&(&points[j].1, j)));
+        let mut levels = Vec::new();
+        let mut blocks = if len > 1 { vec![(0, len)] } else { vec![] };
+        while !blocks.is_empty() {
+            let mut left = vec![false; len];
+            let mut next_order = order.clone();
+            let mut next_blocks = Vec::new();
+            for &(lo, hi) in &blocks {
+                let mid = (lo + hi) / 2;
+                let (mut l, mut r) = (lo, mid);
+                for p in lo..hi {
+                    let i = order[p];
+                    left[p] = i < mid;
+                    let slot = if i < mid { &mut l } else { &mut r };
+                    next_order[*slot] = i;
+                    *slot += 1;
+                }
+                for &(lo, hi) in [(lo, mid), (mid, hi)].iter() {
+                    if hi - lo > 1 {
+                        next_blocks.push((lo, hi));
+                    }
+                }
+            }
+            let mut lefts = vec![0; len + 1];
+            for p in 0..len {
+                lefts[p + 1] = lefts[p] + left[p] as usize;
+            }
+            levels.push(Level { order, lefts });
+            order = next_order;
+            blocks = next_blocks;
+        }
+        // 一番下の段は大きさ 1 のノードだけで、分けない
+        levels.push(Level {
+            order,
+            lefts: vec![0; len + 1],
+        });
+        Self { points, levels }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, K, V)> for RangeTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, K, V)>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn rectangles() {
+        let tree: RangeTree<i32, &str> = vec![
+            (1, 5, "a"),
+            (3, 3, "b"),
+            (3, 8, "c"),
+            (6, 1, "d"),
+            (7, 6, "e"),
+            (9, 9, "f"),
+        ]
+        .into_iter()
+        .collect();
+        tree.check_invariants();
+        assert_eq!(tree.count(2..=7, 2..=6), 2);
+        let mut found: Vec<_> = tree.query(2..=7, 2..=6).map(|(_, _, v)| *v).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec!["b", "e"]);
+        assert_eq!(tree.count(.., ..), 6);
+        assert_eq!(tree.count(3..4, ..), 2);
+        assert_eq!(tree.count(.., 9..), 1);
+        assert_eq!(tree.count((Bound::Excluded(3), Bound::Excluded(6)), ..), 0);
+        assert_eq!(RangeTree::<i32, ()>::from(vec![]).count(.., ..), 0);
+    }
+
+    proptest! {
+        // 数えた数と返した点が、全ての点を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            points in prop::collection::vec((0..30i32, 0..30i32), 0..100),
+            queries in prop::collection::vec((0..32i32, 0..32i32, 0..32i32, 0..32i32), 1..20),
+        ) {
+            let tree: RangeTree<i32, usize> = points.iter().enumerate().map(|(i, &(x, y))| (x, y, i)).collect();
+            tree.check_invariants();
+            prop_assert_eq!(tree.len(), points.len());
+            for (x0, x1, y0, y1) in queries {
+                let mut expected: Vec<usize> = (0..points.len())
+                    .filter(|&i| (x0..=x1).contains(&points[i].0) && (y0..y1).contains(&points[i].1))
+                    .collect();
+                let mut found: Vec<usize> = tree.query(x0..=x1, y0..y1).map(|(_, _, &i)| i).collect();
+                found.sort_unstable();
+                expected.sort_unstable();
+                prop_assert_eq!(tree.count(x0..=x1, y0..y1), expected.len());
+                prop_assert_eq!(found, expected);
+            }
+        }
+    }
+}
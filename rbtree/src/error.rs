@@ -0,0 +1,20 @@
+use thiserror::Error;
+
+// validate で見つかった、赤黒木の条件を満たさない箇所
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("root is red")]
+    RedRoot,
+    #[error("red node has a red child")]
+    DoubleRed,
+    #[error("right child is red")]
+    RightLeaningRed,
+    #[error("black height differs: {left} on the left, {right} on the right")]
+    BlackHeight { left: usize, right: usize },
+    #[error("keys are out of order")]
+    OutOfOrder,
+    #[error("len is {len} but the tree has {count} nodes")]
+    Len { len: usize, count: usize },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
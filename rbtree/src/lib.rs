@@ -0,0 +1,572 @@
+// 赤黒木 (left-leaning red-black tree)
+// 赤いリンクは左の子にだけ許し、2-3 木の 3 ノードを赤いリンクで繋いだ 2 つのノードで表す
+//   - root は黒
+//   - 赤いノードの子は黒
+//   - root から空の部分木までの黒いノードの数はどの経路でも同じ
+// 高さは 2 log2(n + 1) 以下なので、探索、追加、削除は O(log n)
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+mod error;
+
+pub use error::{Error, Result};
+pub use orderedmap::{Op, OrderedMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Color {
+    Red,
+    Black,
+}
+
+impl Color {
+    fn flip(&mut self) {
+        *self = match self {
+            Color::Red => Color::Black,
+            Color::Black => Color::Red,
+        };
+    }
+}
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // 親からこのノードへのリンクの色
+    color: Color,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Box<Self> {
+        Box::new(Self {
+            key,
+            value,
+            color: Color::Red,
+            left: None,
+            right: None,
+        })
+    }
+}
+
+fn is_red<K, V>(link: &Link<K, V>) -> bool {
+    link.as_ref().is_some_and(|n| n.color == Color::Red)
+}
+
+fn is_left_red<K, V>(link: &Link<K, V>) -> bool {
+    link.as_ref().is_some_and(|n| is_red(&n.left))
+}
+
+// 右に傾いた赤いリンクを左に傾ける
+fn rotate_left<K, V>(mut h: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut x = h.right.take().expect("rotate_left needs a right child");
+    h.right = x.left.take();
+    x.color = h.color;
+    h.color = Color::Red;
+    x.left = Some(h);
+    x
+}
+
+fn rotate_right<K, V>(mut h: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut x = h.left.take().expect("rotate_right needs a left child");
+    h.left = x.right.take();
+    x.color = h.color;
+    h.color = Color::Red;
+    x.right = Some(h);
+    x
+}
+
+// 4 ノードを分割する。削除では逆に 4 ノードを作るのにも使う
+fn flip_colors<K, V>(h: &mut Node<K, V>) {
+    h.color.flip();
+    for child in h.left.iter_mut().chain(h.right.iter_mut()) {
+        child.color.flip();
+    }
+}
+
+// 帰りがけに左に傾いた形に戻す
+fn fix_up<K, V>(mut h: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    if is_red(&h.right) && !is_red(&h.left) {
+        h = rotate_left(h);
+    }
+    if is_red(&h.left) && is_left_red(&h.left) {
+        h = rotate_right(h);
+    }
+    if is_red(&h.left) && is_red(&h.right) {
+        flip_colors(&mut h);
+    }
+    h
+}
+
+fn insert<K: Ord, V>(link: Link<K, V>, key: K, value: V) -> (Box<Node<K, V>>, Option<V>) {
+    let mut h = match link {
+        Some(h) => h,
+        None => return (Node::new(key, value), None),
+    };
+    let old = match key.cmp(&h.key) {
+        Ordering::Equal => Some(mem::replace(&mut h.value, value)),
+        Ordering::Less => {
+            let (left, old) = insert(h.left.take(), key, value);
+            h.left = Some(left);
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = insert(h.right.take(), key, value);
+            h.right = Some(right);
+            old
+        }
+    };
+    (fix_up(h), old)
+}
+
+// h か h の左の子を赤にして、左に降りられるようにする
+fn move_red_left<K, V>(mut h: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    flip_colors(&mut h);
+    if is_left_red(&h.right) {
+        h.right = h.right.take().map(rotate_right);
+        h = rotate_left(h);
+        flip_colors(&mut h);
+    }
+    h
+}
+
+fn move_red_right<K, V>(mut h: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    flip_colors(&mut h);
+    if is_left_red(&h.left) {
+        h = rotate_right(h);
+        flip_colors(&mut h);
+    }
+    h
+}
+
+// 最小のノードを外して、(残りの部分木, 外したノード) を返す
+// h か h の左の子が赤であること
+fn remove_min<K, V>(mut h: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    if h.left.is_none() {
+        // 左に傾いているので、左が空なら右も空
+        return (None, h);
+    }
+    if !is_red(&h.left) && !is_left_red(&h.left) {
+        h = move_red_left(h);
+    }
+    let (left, min) = remove_min(h.left.take().expect("moved red keeps the left child"));
+    h.left = left;
+    (Some(fix_up(h)), min)
+}
+
+// key は木にあること。h か h の子が赤であること
+// 降りる先が 2 ノードにならないように赤いリンクを運びながら降り、帰りに直す
+fn remove<K: Ord, V>(mut h: Box<Node<K, V>>, key: &K, removed: &mut Option<V>) -> Link<K, V> {
+    if *key < h.key {
+        if !is_red(&h.left) && !is_left_red(&h.left) {
+            h = move_red_left(h);
+        }
+        let left = h.left.take().expect("key is in the tree");
+        h.left = remove(left, key, removed);
+    } else {
+        if is_red(&h.left) {
+            h = rotate_right(h);
+        }
+        if *key == h.key && h.right.is_none() {
+            *removed = Some(h.value);
+            return None;
+        }
+        if !is_red(&h.right) && !is_left_red(&h.right) {
+            h = move_red_right(h);
+        }
+        let right = h.right.take().expect("key is in the tree");
+        if *key == h.key {
+            // 右の部分木の最小のエントリと入れ替えてから消す
+            let (right, min) = remove_min(right);
+            let min = *min;
+            h.key = min.key;
+            *removed = Some(mem::replace(&mut h.value, min.value));
+            h.right = right;
+        } else {
+            h.right = remove(right, key, removed);
+        }
+    }
+    Some(fix_up(h))
+}
+
+#[derive(Debug)]
+pub struct RBTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K: Ord + Copy, V> RBTree<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        fn height<K, V>(link: &Link<K, V>) -> usize {
+            link.as_ref()
+                .map_or(0, |n| 1 + height(&n.left).max(height(&n.right)))
+        }
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut current = self.root.as_deref_mut();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (mut root, old) = insert(self.root.take(), key, value);
+        root.color = Color::Black;
+        self.root = Some(root);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        // 削除は key があるものとして形を変えながら降りるので、先に確かめる
+        self.get(key)?;
+        let mut root = self.root.take().expect("key is in the tree");
+        if !is_red(&root.left) && !is_red(&root.right) {
+            root.color = Color::Red;
+        }
+        let mut removed = None;
+        self.root = remove(root, &key, &mut removed);
+        if let Some(root) = &mut self.root {
+            root.color = Color::Black;
+        }
+        self.len -= 1;
+        removed
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // 色の条件、キーの順序、len を確かめて、最初に見つかった違反を返す
+    pub fn validate(&self) -> Result<()> {
+        // 部分木の黒いノードの数を返す
+        fn check<K: Ord + Copy, V>(
+            node: &Node<K, V>,
+            (lower, upper): (Option<K>, Option<K>),
+            count: &mut usize,
+        ) -> Result<usize> {
+            if !(lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u)) {
+                return Err(Error::OutOfOrder);
+            }
+            if is_red(&node.right) {
+                return Err(Error::RightLeaningRed);
+            }
+            if node.color == Color::Red && is_red(&node.left) {
+                return Err(Error::DoubleRed);
+            }
+            *count += 1;
+            let left = match node.left.as_deref() {
+                Some(l) => check(l, (lower, Some(node.key)), count)?,
+                None => 0,
+            };
+            let right = match node.right.as_deref() {
+                Some(r) => check(r, (Some(node.key), upper), count)?,
+                None => 0,
+            };
+            if left != right {
+                return Err(Error::BlackHeight { left, right });
+            }
+            Ok(left + (node.color == Color::Black) as usize)
+        }
+        let mut count = 0;
+        if let Some(root) = self.root.as_deref() {
+            if root.color == Color::Red {
+                return Err(Error::RedRoot);
+            }
+            check(root, (None, None), &mut count)?;
+        }
+        if count != self.len {
+            return Err(Error::Len {
+                len: self.len,
+                count,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<K: Ord + Copy, V> Default for RBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.stack.clear();
+            return None;
+        }
+        self.push_left(node.right.as_deref());
+        Some((node.key, &node.value))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a RBTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// 木を分解しながらキー順に返す
+pub struct IntoIter<K, V> {
+    stack: Vec<Box<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> IntoIter<K, V> {
+    fn push_left(&mut self, mut current: Link<K, V>) {
+        while let Some(mut node) = current {
+            current = node.left.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_left(node.right.take());
+        self.len -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K, V> IntoIterator for RBTree<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut iter = IntoIter {
+            stack: Vec::new(),
+            len: self.len,
+        };
+        iter.push_left(self.root);
+        iter
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for RBTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for RBTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        RBTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        RBTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        RBTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        RBTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        RBTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = RBTree::new();
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.validate().unwrap();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.get(24), Some(&240));
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.validate().unwrap();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.into_iter().collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn height_is_logarithmic() {
+        // 昇順に入れても偏らない
+        let n = 100_000;
+        let mut t: RBTree<_, _> = (0..n).map(|k| (k, k)).collect();
+        t.validate().unwrap();
+        assert!((t.height() as f64) <= 2.0 * ((n + 1) as f64).log2());
+        for k in (0..n).rev().step_by(2) {
+            t.remove(k);
+        }
+        t.validate().unwrap();
+        assert_eq!(t.len(), n / 2);
+        assert!((t.height() as f64) <= 2.0 * ((n / 2 + 1) as f64).log2());
+    }
+
+    #[test]
+    fn range() {
+        let t: RBTree<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        let keys: Vec<_> = t.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(t.range(10, 10).count(), 1);
+        assert_eq!(t.range(15, 9).count(), 0);
+        assert_eq!(t.range(199, 1000).count(), 0);
+        assert!(t.iter().map(|(k, _)| k).eq((0..100).map(|k| k * 2)));
+    }
+
+    #[test]
+    fn validate_detects_violations() {
+        let tree = || (0..20).map(|k| (k, k)).collect::<RBTree<_, _>>();
+
+        let mut t = tree();
+        t.root.as_mut().unwrap().color = Color::Red;
+        assert_eq!(t.validate(), Err(Error::RedRoot));
+
+        let mut t = tree();
+        t.root.as_mut().unwrap().right.as_mut().unwrap().color = Color::Red;
+        assert_eq!(t.validate(), Err(Error::RightLeaningRed));
+
+        let mut t = tree();
+        let left = t.root.as_mut().unwrap().left.as_mut().unwrap();
+        left.color = Color::Red;
+        left.left.as_mut().unwrap().color = Color::Red;
+        assert_eq!(t.validate(), Err(Error::DoubleRed));
+
+        // 黒い子を赤にすると、その部分木だけ黒の数が減る
+        let mut t = tree();
+        t.root.as_mut().unwrap().left.as_mut().unwrap().color = Color::Red;
+        assert!(matches!(t.validate(), Err(Error::BlackHeight { .. })));
+
+        let mut t = tree();
+        t.root.as_mut().unwrap().key = 100;
+        assert_eq!(t.validate(), Err(Error::OutOfOrder));
+
+        let mut t = tree();
+        t.len += 1;
+        assert_eq!(t.validate(), Err(Error::Len { len: 21, count: 20 }));
+    }
+}
@@ -0,0 +1,693 @@
+// 大きな文字列を編集するための rope
+// 葉に最大 MAX_LEAF バイトの文字列を持ち、高さで平衡を保つ (AVL と同じ条件) 二分木にする
+// 各ノードは部分木の 文字数、バイト数、改行の数 を持つので、文字の位置と行の位置を O(log n) で変換できる
+//
+// 編集は split と join で行う
+//   join(l, r): 高さの差に比例する手間で、平衡を保ったまま繋ぐ
+//   split(at):  at を含む葉までの経路で分け、途中の部分木を join し直す。O(log n)
+// ノードは Arc で共有するので、clone と slice は元の rope をコピーしない
+use std::{fmt, ops::Range, sync::Arc};
+
+pub use textbuffer::TextBuffer;
+
+// 葉の文字列の最大バイト数。テストでは分岐ができるように小さくする
+const MAX_LEAF: usize = if cfg!(test) { 16 } else { 1024 };
+
+#[derive(Debug)]
+struct Node {
+    chars: usize,
+    bytes: usize,
+    newlines: usize,
+    // 葉は 0
+    height: usize,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Leaf(String),
+    Branch(Arc<Node>, Arc<Node>),
+}
+
+fn leaf(text: String) -> Arc<Node> {
+    Arc::new(Node {
+        chars: text.chars().count(),
+        bytes: text.len(),
+        newlines: text.bytes().filter(|&b| b == b'\n').count(),
+        height: 0,
+        kind: Kind::Leaf(text),
+    })
+}
+
+fn branch(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    Arc::new(Node {
+        chars: left.chars + right.chars,
+        bytes: left.bytes + right.bytes,
+        newlines: left.newlines + right.newlines,
+        height: 1 + left.height.max(right.height),
+        kind: Kind::Branch(left, right),
+    })
+}
+
+// 文字の位置をバイトの位置にする
+fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(i, _)| i)
+}
+
+// text を MAX_LEAF バイト以下の葉に分け、高さの揃った木にする
+fn build(text: &str) -> Option<Arc<Node>> {
+    fn build_leaves(leaves: &[Arc<Node>]) -> Arc<Node> {
+        match leaves {
+            [leaf] => Arc::clone(leaf),
+            _ => {
+                let (left, right) = leaves.split_at(leaves.len() / 2);
+                branch(build_leaves(left), build_leaves(right))
+            }
+        }
+    }
+    let mut leaves = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_LEAF);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        leaves.push(leaf(rest[..end].to_string()));
+        rest = &rest[end..];
+    }
+    (!leaves.is_empty()).then(|| build_leaves(&leaves))
+}
+
+fn children(node: &Node) -> (&Arc<Node>, &Arc<Node>) {
+    match &node.kind {
+        Kind::Branch(left, right) => (left, right),
+        Kind::Leaf(_) => unreachable!("higher node is a branch"),
+    }
+}
+
+fn join(left: Option<Arc<Node>>, right: Option<Arc<Node>>) -> Option<Arc<Node>> {
+    match (left, right) {
+        (None, node) | (node, None) => node,
+        (Some(left), Some(right)) => Some(join_nodes(left, right)),
+    }
+}
+
+// left の後ろに right を繋ぐ。小さな葉どうしは 1 つの葉にまとめる
+fn join_nodes(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    if let (Kind::Leaf(l), Kind::Leaf(r)) = (&left.kind, &right.kind) {
+        if l.len() + r.len() <= MAX_LEAF {
+            return leaf(format!("{}{}", l, r));
+        }
+    }
+    if left.height > right.height + 1 {
+        // left の右端を、right と高さの近いところまで下りて繋ぐ
+        let (ll, lr) = children(&left);
+        let t = join_nodes(Arc::clone(lr), right);
+        rebalance(Arc::clone(ll), t)
+    } else if right.height > left.height + 1 {
+        let (rl, rr) = children(&right);
+        let t = join_nodes(left, Arc::clone(rl));
+        rebalance(t, Arc::clone(rr))
+    } else {
+        branch(left, right)
+    }
+}
+
+// 高さの差が 2 以下の left と right を、回転して平衡した木にする
+fn rebalance(left: Arc<Node>, right: Arc<Node>) -> Arc<Node> {
+    if right.height > left.height + 1 {
+        let (rl, rr) = children(&right);
+        if rl.height <= rr.height {
+            branch(branch(left, Arc::clone(rl)), Arc::clone(rr))
+        } else {
+            let (rll, rlr) = children(rl);
+            branch(
+                branch(left, Arc::clone(rll)),
+                branch(Arc::clone(rlr), Arc::clone(rr)),
+            )
+        }
+    } else if left.height > right.height + 1 {
+        let (ll, lr) = children(&left);
+        if lr.height <= ll.height {
+            branch(Arc::clone(ll), branch(Arc::clone(lr), right))
+        } else {
+            let (lrl, lrr) = children(lr);
+            branch(
+                branch(Arc::clone(ll), Arc::clone(lrl)),
+                branch(Arc::clone(lrr), right),
+            )
+        }
+    } else {
+        branch(left, right)
+    }
+}
+
+type Halves = (Option<Arc<Node>>, Option<Arc<Node>>);
+
+// 文字の位置 at の前後に分ける
+fn split(node: &Arc<Node>, at: usize) -> Halves {
+    if at == 0 {
+        return (None, Some(Arc::clone(node)));
+    }
+    if at == node.chars {
+        return (Some(Arc::clone(node)), None);
+    }
+    match &node.kind {
+        Kind::Leaf(text) => {
+            let (left, right) = text.split_at(byte_offset(text, at));
+            (Some(leaf(left.to_string())), Some(leaf(right.to_string())))
+        }
+        Kind::Branch(left, right) => {
+            if at <= left.chars {
+                let (a, b) = split(left, at);
+                (a, join(b, Some(Arc::clone(right))))
+            } else {
+                let (a, b) = split(right, at - left.chars);
+                (join(Some(Arc::clone(left)), a), b)
+            }
+        }
+    }
+}
+
+// 葉に収まれば葉の中に入れ、収まらなければ葉を分けて繋ぎ直す
+fn insert(node: &Arc<Node>, at: usize, text: &str) -> Arc<Node> {
+    match &node.kind {
+        Kind::Leaf(s) => {
+            let i = byte_offset(s, at);
+            let joined = format!("{}{}{}", &s[..i], text, &s[i..]);
+            if joined.len() <= MAX_LEAF {
+                leaf(joined)
+            } else {
+                build(&joined).expect("text is not empty")
+            }
+        }
+        Kind::Branch(left, right) => {
+            if at <= left.chars {
+                join_nodes(insert(left, at, text), Arc::clone(right))
+            } else {
+                join_nodes(Arc::clone(left), insert(right, at - left.chars, text))
+            }
+        }
+    }
+}
+
+fn remove(node: &Arc<Node>, range: Range<usize>) -> Option<Arc<Node>> {
+    if range.start >= range.end {
+        return Some(Arc::clone(node));
+    }
+    if range.start == 0 && range.end >= node.chars {
+        return None;
+    }
+    match &node.kind {
+        Kind::Leaf(s) => {
+            let (start, end) = (byte_offset(s, range.start), byte_offset(s, range.end));
+            Some(leaf(format!("{}{}", &s[..start], &s[end..])))
+        }
+        Kind::Branch(left, right) => {
+            let n = left.chars;
+            let l = remove(left, range.start.min(n)..range.end.min(n));
+            let r = remove(right, range.start.max(n) - n..range.end.max(n) - n);
+            join(l, r)
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Rope {
+    root: Option<Arc<Node>>,
+}
+
+impl Rope {
+    pub fn new() -> Self {
+        Self { root: None }
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.chars)
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.bytes)
+    }
+
+    // 改行の数 + 1。空の rope も 1 行とみなす
+    pub fn len_lines(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.newlines) + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn char(&self, index: usize) -> Option<char> {
+        let mut node = self.root.as_deref()?;
+        let mut index = index;
+        if index >= node.chars {
+            return None;
+        }
+        loop {
+            match &node.kind {
+                Kind::Leaf(text) => return text.chars().nth(index),
+                Kind::Branch(left, right) => {
+                    if index < left.chars {
+                        node = left;
+                    } else {
+                        index -= left.chars;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // 文字の位置 at に text を入れる
+    pub fn insert(&mut self, at: usize, text: &str) {
+        self.check_char_index(at);
+        if text.is_empty() {
+            return;
+        }
+        self.root = match &self.root {
+            Some(root) => Some(insert(root, at, text)),
+            None => build(text),
+        };
+    }
+
+    // range の文字を消す
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.check_range(&range);
+        if let Some(root) = &self.root {
+            self.root = remove(root, range);
+        }
+    }
+
+    // 後ろに other を繋ぐ。O(log n)
+    pub fn append(&mut self, other: Rope) {
+        self.root = join(self.root.take(), other.root);
+    }
+
+    // at から後ろを切り離して返す
+    pub fn split_off(&mut self, at: usize) -> Rope {
+        self.check_char_index(at);
+        let (left, right) = match &self.root {
+            Some(root) => split(root, at),
+            None => (None, None),
+        };
+        self.root = left;
+        Rope { root: right }
+    }
+
+    // range の文字の rope。元の rope と葉を共有する
+    pub fn slice(&self, range: Range<usize>) -> Rope {
+        self.check_range(&range);
+        let mut rope = self.clone();
+        let tail = rope.split_off(range.start);
+        let mut rope = tail;
+        rope.split_off(range.end - range.start);
+        rope
+    }
+
+    // index (<= len_chars) より前にある改行の数。index の文字がある行の番号になる
+    pub fn char_to_line(&self, index: usize) -> usize {
+        self.check_char_index(index);
+        let mut lines = 0;
+        let mut index = index;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            match &node.kind {
+                Kind::Leaf(text) => {
+                    lines += text.chars().take(index).filter(|&c| c == '\n').count();
+                    break;
+                }
+                Kind::Branch(left, right) => {
+                    if index <= left.chars {
+                        current = Some(left);
+                    } else {
+                        lines += left.newlines;
+                        index -= left.chars;
+                        current = Some(right);
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    // line 行目の先頭の文字の位置。line == len_lines なら len_chars
+    pub fn line_to_char(&self, line: usize) -> usize {
+        assert!(
+            line <= self.len_lines(),
+            "line {} is out of bounds for {} lines",
+            line,
+            self.len_lines()
+        );
+        if line == 0 {
+            return 0;
+        }
+        if line == self.len_lines() {
+            return self.len_chars();
+        }
+        // line 個目の改行の直後を探す
+        let mut rest = line;
+        let mut offset = 0;
+        let mut node = self.root.as_deref().expect("rope has a newline");
+        loop {
+            match &node.kind {
+                Kind::Leaf(text) => {
+                    let (i, _) = text
+                        .chars()
+                        .enumerate()
+                        .filter(|&(_, c)| c == '\n')
+                        .nth(rest - 1)
+                        .expect("leaf has the newline");
+                    return offset + i + 1;
+                }
+                Kind::Branch(left, right) => {
+                    if rest <= left.newlines {
+                        node = left;
+                    } else {
+                        rest -= left.newlines;
+                        offset += left.chars;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // line 行目。行末の改行を含む
+    pub fn line(&self, line: usize) -> Rope {
+        assert!(
+            line < self.len_lines(),
+            "line {} is out of bounds for {} lines",
+            line,
+            self.len_lines()
+        );
+        self.slice(self.line_to_char(line)..self.line_to_char(line + 1))
+    }
+
+    // 葉の文字列を前から返す
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            stack: self.root.as_deref().into_iter().collect(),
+        }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    fn check_char_index(&self, index: usize) {
+        assert!(
+            index <= self.len_chars(),
+            "char index {} is out of bounds for {} chars",
+            index,
+            self.len_chars()
+        );
+    }
+
+    fn check_range(&self, range: &Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len_chars(),
+            "char range {:?} is out of bounds for {} chars",
+            range,
+            self.len_chars()
+        );
+    }
+
+    // 高さの差、各ノードの数え上げ、葉の大きさが正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check(node: &Node) {
+            match &node.kind {
+                Kind::Leaf(text) => {
+                    assert!(!text.is_empty(), "leaf is empty");
+                    assert!(text.len() <= MAX_LEAF, "leaf overflows");
+                    assert_eq!(node.height, 0, "height is stale");
+                    assert_eq!(node.chars, text.chars().count(), "chars is stale");
+                    assert_eq!(node.bytes, text.len(), "bytes is stale");
+                    assert_eq!(
+                        node.newlines,
+                        text.matches('\n').count(),
+                        "newlines is stale"
+                    );
+                }
+                Kind::Branch(left, right) => {
+                    check(left);
+                    check(right);
+                    assert!(
+                        left.height.abs_diff(right.height) <= 1,
+                        "node is unbalanced"
+                    );
+                    assert_eq!(
+                        node.height,
+                        1 + left.height.max(right.height),
+                        "height is stale"
+                    );
+                    assert_eq!(node.chars, left.chars + right.chars, "chars is stale");
+                    assert_eq!(node.bytes, left.bytes + right.bytes, "bytes is stale");
+                    assert_eq!(
+                        node.newlines,
+                        left.newlines + right.newlines,
+                        "newlines is stale"
+                    );
+                }
+            }
+        }
+        if let Some(root) = &self.root {
+            check(root);
+        }
+    }
+}
+
+impl TextBuffer for Rope {
+    fn len_chars(&self) -> usize {
+        Rope::len_chars(self)
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        Rope::insert(self, at, text)
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        Rope::remove(self, range)
+    }
+
+    fn slice_to_string(&self, range: Range<usize>) -> String {
+        self.slice(range).to_string()
+    }
+
+    fn char(&self, index: usize) -> Option<char> {
+        Rope::char(self, index)
+    }
+
+    fn len_lines(&self) -> usize {
+        Rope::len_lines(self)
+    }
+}
+
+impl From<&str> for Rope {
+    fn from(text: &str) -> Self {
+        Self { root: build(text) }
+    }
+}
+
+impl From<String> for Rope {
+    fn from(text: String) -> Self {
+        Self::from(text.as_str())
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_string(), f)
+    }
+}
+
+impl PartialEq for Rope {
+    fn eq(&self, other: &Self) -> bool {
+        self.len_bytes() == other.len_bytes() && self.chars().eq(other.chars())
+    }
+}
+
+impl Eq for Rope {}
+
+// まだ返していない部分木を、先に返すものが上に来るように積んでおく
+pub struct Chunks<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            match &self.stack.pop()?.kind {
+                Kind::Leaf(text) => return Some(text),
+                Kind::Branch(left, right) => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+    }
+}
+
+impl<'a> std::iter::FusedIterator for Chunks<'a> {}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn edit() {
+        let mut rope = Rope::from("hello world");
+        rope.insert(5, ",");
+        rope.insert(12, "!");
+        assert_eq!(rope.to_string(), "hello, world!");
+        rope.remove(0..7);
+        assert_eq!(rope.to_string(), "world!");
+        rope.insert(0, "こんにちは、");
+        assert_eq!(rope.len_chars(), 12);
+        assert_eq!(rope.char(6), Some('w'));
+        assert_eq!(rope.slice(3..8).to_string(), "ちは、wo");
+        let tail = rope.split_off(6);
+        assert_eq!(
+            (rope.to_string(), tail.to_string()),
+            ("こんにちは、".into(), "world!".into())
+        );
+        rope.append(tail);
+        rope.check_invariants();
+        assert_eq!(rope, Rope::from("こんにちは、world!"));
+    }
+
+    #[test]
+    fn text_buffer() {
+        fn edit<B: TextBuffer>(b: &mut B) {
+            b.insert(0, "first\nthird");
+            b.insert(6, "second\n");
+            b.remove(0..6);
+        }
+        let mut rope = Rope::new();
+        let mut string = String::new();
+        edit(&mut rope);
+        edit(&mut string);
+        assert_eq!(TextBuffer::text(&rope), string);
+        assert_eq!(TextBuffer::len_lines(&rope), 2);
+    }
+
+    #[test]
+    fn lines() {
+        let rope = Rope::from("first\nsecond\n\nfourth");
+        assert_eq!(rope.len_lines(), 4);
+        assert_eq!(rope.line_to_char(1), 6);
+        assert_eq!(rope.line_to_char(3), 14);
+        assert_eq!(rope.line_to_char(4), rope.len_chars());
+        assert_eq!(rope.char_to_line(0), 0);
+        assert_eq!(rope.char_to_line(5), 0);
+        assert_eq!(rope.char_to_line(6), 1);
+        assert_eq!(rope.char_to_line(rope.len_chars()), 3);
+        assert_eq!(rope.line(1).to_string(), "second\n");
+        assert_eq!(rope.line(2).to_string(), "\n");
+        assert_eq!(rope.line(3).to_string(), "fourth");
+        assert_eq!(Rope::new().len_lines(), 1);
+    }
+
+    #[test]
+    fn large_document_stays_balanced() {
+        let mut rope = Rope::new();
+        for i in 0..5000 {
+            // いつも同じ位置に入れても偏らない
+            rope.insert(rope.len_chars() / 3, &format!("{}\n", i % 10));
+        }
+        rope.check_invariants();
+        assert_eq!(rope.len_lines(), 5001);
+        let leaves = rope.chunks().count();
+        let bound = 1.45 * ((leaves + 2) as f64).log2();
+        assert!((rope.root.as_ref().unwrap().height as f64) <= bound);
+        // slice は葉を共有する
+        let slice = rope.slice(100..9000);
+        slice.check_invariants();
+        assert_eq!(slice.len_chars(), 8900);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, String),
+        Remove(usize, usize),
+        Slice(usize, usize),
+        SplitAppend(usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            2 => (any::<usize>(), "[ab\nあい]{0,40}").prop_map(|(i, s)| Op::Insert(i, s)),
+            1 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Remove(a, b)),
+            1 => (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Slice(a, b)),
+            1 => any::<usize>().prop_map(Op::SplitAppend),
+        ]
+    }
+
+    proptest! {
+        // 文字の位置で String と同じ操作をして比べる
+        #[test]
+        fn same_as_string_random(ops in prop::collection::vec(op_strategy(), 0..100)) {
+            let mut rope = Rope::new();
+            let mut model: Vec<char> = Vec::new();
+            for op in ops {
+                let n = model.len();
+                match op {
+                    Op::Insert(i, s) => {
+                        let i = i % (n + 1);
+                        rope.insert(i, &s);
+                        model.splice(i..i, s.chars());
+                    }
+                    Op::Remove(a, b) => {
+                        let (a, b) = (a % (n + 1), b % (n + 1));
+                        let range = a.min(b)..a.max(b);
+                        rope.remove(range.clone());
+                        model.drain(range);
+                    }
+                    Op::Slice(a, b) => {
+                        let (a, b) = (a % (n + 1), b % (n + 1));
+                        let range = a.min(b)..a.max(b);
+                        let slice = rope.slice(range.clone());
+                        slice.check_invariants();
+                        prop_assert_eq!(slice.to_string(), model[range].iter().collect::<String>());
+                    }
+                    // 切り離した後ろを前に繋ぎ直す
+                    Op::SplitAppend(i) => {
+                        let i = i % (n + 1);
+                        let mut tail = rope.split_off(i);
+                        tail.append(rope);
+                        rope = tail;
+                        model.rotate_left(i);
+                    }
+                }
+                rope.check_invariants();
+                let text: String = model.iter().collect();
+                prop_assert_eq!(rope.to_string(), text.clone());
+                prop_assert_eq!(rope.len_bytes(), text.len());
+                prop_assert_eq!(rope.len_lines(), text.matches('\n').count() + 1);
+                let n = model.len();
+                for i in (0..=n).step_by(7) {
+                    let lines = model[..i].iter().filter(|&&c| c == '\n').count();
+                    prop_assert_eq!(rope.char_to_line(i), lines);
+                    prop_assert!(rope.line_to_char(lines) <= i);
+                }
+                for line in 0..rope.len_lines() {
+                    let start = rope.line_to_char(line);
+                    prop_assert!(start == 0 || model[start - 1] == '\n');
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,701 @@
+// 永続的なベクタ (RRB tree, Bagwell and Rompf, 2011)
+// 32 分木で、葉に要素を最大 32 個ずつ持つ。どの葉も同じ深さにある
+// 最後以外の子がどれも満杯の分岐は、添字を 5 ビットずつ切り出すだけで子を選べる
+// concat や slice で満杯でない子ができた分岐 (relaxed) は、子までの要素数の累積を持ち、そこから子を探す
+//
+// 更新は root から変えた葉までの経路だけを作り直し、残りの部分木は Arc で前の版と共有する
+//   get / update / push_back / split_at / slice : O(log n)
+//   concat : O(log n)。繋ぎ目の経路の分岐だけを作り直し、子が増えすぎたら詰め直す
+use std::{
+    fmt,
+    iter::{FromIterator, FusedIterator},
+    ops::Range,
+    slice,
+    sync::Arc,
+};
+
+const BITS: usize = 5;
+// 分岐の子と葉の要素の最大数
+const WIDTH: usize = 1 << BITS;
+// concat で詰め直さずに残してよい、分岐の子の余分な数
+const EXTRA: usize = 2;
+
+#[derive(Debug)]
+enum Node<T> {
+    Leaf(Vec<T>),
+    Branch(Branch<T>),
+}
+
+#[derive(Debug)]
+struct Branch<T> {
+    // 部分木の要素数
+    len: usize,
+    children: Vec<Arc<Node<T>>>,
+    // relaxed なら、i 番目までの子の要素数の合計を i 番目に持つ
+    sizes: Option<Vec<usize>>,
+}
+
+impl<T> Node<T> {
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(items) => items.len(),
+            Node::Branch(branch) => branch.len,
+        }
+    }
+
+    // 葉なら要素の数、分岐なら子の数
+    fn slots(&self) -> usize {
+        match self {
+            Node::Leaf(items) => items.len(),
+            Node::Branch(branch) => branch.children.len(),
+        }
+    }
+
+    fn branch(&self) -> &Branch<T> {
+        match self {
+            Node::Branch(branch) => branch,
+            Node::Leaf(_) => unreachable!("expected a branch"),
+        }
+    }
+}
+
+impl<T> Branch<T> {
+    // height の高さの分岐を作る。子は height - 1 の高さ
+    fn new(children: Vec<Arc<Node<T>>>, height: usize) -> Self {
+        let full = 1 << (BITS * height);
+        let len = children.iter().map(|c| c.len()).sum();
+        let dense = children
+            .split_last()
+            .is_none_or(|(_, init)| init.iter().all(|c| c.len() == full));
+        let sizes = (!dense).then(|| {
+            children
+                .iter()
+                .scan(0, |sum, c| {
+                    *sum += c.len();
+                    Some(*sum)
+                })
+                .collect()
+        });
+        Self {
+            len,
+            children,
+            sizes,
+        }
+    }
+
+    // index (< len) を持つ子の位置と、その子の中での添字
+    fn position(&self, index: usize, height: usize) -> (usize, usize) {
+        let shift = BITS * height;
+        let mut i = index >> shift;
+        match &self.sizes {
+            None => (i, index - (i << shift)),
+            Some(sizes) => {
+                // 子の要素数は 1 << shift 以下なので、i より前の子にはない
+                while sizes[i] <= index {
+                    i += 1;
+                }
+                (i, index - if i == 0 { 0 } else { sizes[i - 1] })
+            }
+        }
+    }
+}
+
+fn branch<T>(children: Vec<Arc<Node<T>>>, height: usize) -> Arc<Node<T>> {
+    Arc::new(Node::Branch(Branch::new(children, height)))
+}
+
+// value だけを持つ height の高さの部分木
+fn singleton<T>(value: T, height: usize) -> Arc<Node<T>> {
+    if height == 0 {
+        Arc::new(Node::Leaf(vec![value]))
+    } else {
+        branch(vec![singleton(value, height - 1)], height)
+    }
+}
+
+fn update<T: Clone>(node: &Node<T>, height: usize, index: usize, value: T) -> Node<T> {
+    match node {
+        Node::Leaf(items) => {
+            let mut items = items.clone();
+            items[index] = value;
+            Node::Leaf(items)
+        }
+        Node::Branch(b) => {
+            let (i, sub) = b.position(index, height);
+            let mut children = b.children.clone();
+            children[i] = Arc::new(update(&b.children[i], height - 1, sub, value));
+            Node::Branch(Branch {
+                len: b.len,
+                children,
+                sizes: b.sizes.clone(),
+            })
+        }
+    }
+}
+
+// 一番右の経路に value を足す。部分木が満杯なら value を返す
+fn push<T: Clone>(node: &Node<T>, height: usize, value: T) -> Result<Arc<Node<T>>, T> {
+    match node {
+        Node::Leaf(items) if items.len() < WIDTH => {
+            let mut items = items.clone();
+            items.push(value);
+            Ok(Arc::new(Node::Leaf(items)))
+        }
+        Node::Leaf(_) => Err(value),
+        Node::Branch(b) => {
+            let mut children = b.children.clone();
+            let last = children.last_mut().expect("branch is not empty");
+            match push(last, height - 1, value) {
+                Ok(child) => *last = child,
+                Err(value) if children.len() < WIDTH => children.push(singleton(value, height - 1)),
+                Err(value) => return Err(value),
+            }
+            Ok(branch(children, height))
+        }
+    }
+}
+
+type Halves<T> = (Option<Arc<Node<T>>>, Option<Arc<Node<T>>>);
+
+// 添字 index の前後に分ける。空になった側は None
+fn split<T: Clone>(node: &Arc<Node<T>>, height: usize, index: usize) -> Halves<T> {
+    if index == 0 {
+        return (None, Some(Arc::clone(node)));
+    }
+    if index == node.len() {
+        return (Some(Arc::clone(node)), None);
+    }
+    match &**node {
+        Node::Leaf(items) => (
+            Some(Arc::new(Node::Leaf(items[..index].to_vec()))),
+            Some(Arc::new(Node::Leaf(items[index..].to_vec()))),
+        ),
+        Node::Branch(b) => {
+            let (i, sub) = b.position(index, height);
+            let (left, right) = split(&b.children[i], height - 1, sub);
+            let left = b.children[..i].iter().cloned().chain(left).collect();
+            let right = right
+                .into_iter()
+                .chain(b.children[i + 1..].iter().cloned())
+                .collect();
+            (Some(branch(left, height)), Some(branch(right, height)))
+        }
+    }
+}
+
+// a と b を繋いだ、高さ max(ha, hb) + 1 の分岐
+// 繋ぎ目の経路を下りて、同じ高さになったところで 2 つの部分木を並べ、上りながら子を並べ直す
+fn merge<T: Clone>(a: &Arc<Node<T>>, ha: usize, b: &Arc<Node<T>>, hb: usize) -> Arc<Node<T>> {
+    if ha == 0 && hb == 0 {
+        let (a_items, b_items) = match (&**a, &**b) {
+            (Node::Leaf(a), Node::Leaf(b)) => (a, b),
+            _ => unreachable!("height 0 is a leaf"),
+        };
+        let leaves = if a_items.len() + b_items.len() <= WIDTH {
+            let items = a_items.iter().chain(b_items).cloned().collect();
+            vec![Arc::new(Node::Leaf(items))]
+        } else {
+            vec![Arc::clone(a), Arc::clone(b)]
+        };
+        return branch(leaves, 1);
+    }
+    let empty = &[][..];
+    let (left, middle, right, height) = if ha > hb {
+        let (last, init) = a
+            .branch()
+            .children
+            .split_last()
+            .expect("branch is not empty");
+        (init, merge(last, ha - 1, b, hb), empty, ha)
+    } else if ha < hb {
+        let (first, tail) = b
+            .branch()
+            .children
+            .split_first()
+            .expect("branch is not empty");
+        (empty, merge(a, ha, first, hb - 1), tail, hb)
+    } else {
+        let (last, init) = a
+            .branch()
+            .children
+            .split_last()
+            .expect("branch is not empty");
+        let (first, tail) = b
+            .branch()
+            .children
+            .split_first()
+            .expect("branch is not empty");
+        (init, merge(last, ha - 1, first, hb - 1), tail, ha)
+    };
+    let nodes = left
+        .iter()
+        .chain(&middle.branch().children)
+        .chain(right)
+        .cloned()
+        .collect();
+    rebalance(nodes, height - 1)
+}
+
+// height の高さの nodes を子に持つ分岐を作り、それを子に持つ分岐を返す
+// nodes は 2 * WIDTH 個以下なので、分岐は 1 つか 2 つになる
+fn rebalance<T: Clone>(nodes: Vec<Arc<Node<T>>>, height: usize) -> Arc<Node<T>> {
+    let slots: usize = nodes.iter().map(|n| n.slots()).sum();
+    // 子が最小の数より EXTRA 個を超えて多ければ、詰め直して最小の数にする
+    let nodes = if nodes.len() > slots.div_ceil(WIDTH) + EXTRA {
+        repack(&nodes, height)
+    } else {
+        nodes
+    };
+    let parents = nodes
+        .chunks(WIDTH)
+        .map(|chunk| branch(chunk.to_vec(), height + 1))
+        .collect();
+    branch(parents, height + 2)
+}
+
+// nodes の中身を並べ直して、最後以外を満杯にする
+fn repack<T: Clone>(nodes: &[Arc<Node<T>>], height: usize) -> Vec<Arc<Node<T>>> {
+    if height == 0 {
+        let items: Vec<_> = nodes
+            .iter()
+            .flat_map(|n| match &**n {
+                Node::Leaf(items) => items.iter().cloned(),
+                Node::Branch(_) => unreachable!("height 0 is a leaf"),
+            })
+            .collect();
+        items
+            .chunks(WIDTH)
+            .map(|chunk| Arc::new(Node::Leaf(chunk.to_vec())))
+            .collect()
+    } else {
+        let children: Vec<_> = nodes
+            .iter()
+            .flat_map(|n| n.branch().children.iter().cloned())
+            .collect();
+        children
+            .chunks(WIDTH)
+            .map(|chunk| branch(chunk.to_vec(), height))
+            .collect()
+    }
+}
+
+pub struct RrbVec<T> {
+    root: Arc<Node<T>>,
+    // 葉の高さを 0 とした root の高さ
+    height: usize,
+}
+
+impl<T: Clone> RrbVec<T> {
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(Node::Leaf(Vec::new())),
+            height: 0,
+        }
+    }
+
+    // 子が 1 つしかない root を取り除く
+    fn from_root(mut root: Arc<Node<T>>, mut height: usize) -> Self {
+        while let Node::Branch(b) = &*root {
+            if b.children.len() != 1 {
+                break;
+            }
+            root = Arc::clone(&b.children[0]);
+            height -= 1;
+        }
+        Self { root, height }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut node = &*self.root;
+        let mut height = self.height;
+        loop {
+            match node {
+                Node::Leaf(items) => return items.get(index),
+                Node::Branch(b) => {
+                    let (i, sub) = b.position(index, height);
+                    node = &b.children[i];
+                    index = sub;
+                    height -= 1;
+                }
+            }
+        }
+    }
+
+    // index の要素を value に変えた新しい版を返す。self は変わらない
+    #[must_use]
+    pub fn update(&self, index: usize, value: T) -> Self {
+        assert!(
+            index < self.len(),
+            "index out of bounds: the len is {} but the index is {}",
+            self.len(),
+            index
+        );
+        Self {
+            root: Arc::new(update(&self.root, self.height, index, value)),
+            height: self.height,
+        }
+    }
+
+    // 末尾に value を足した新しい版を返す
+    #[must_use]
+    pub fn push_back(&self, value: T) -> Self {
+        match push(&self.root, self.height, value) {
+            Ok(root) => Self {
+                root,
+                height: self.height,
+            },
+            // 満杯なら root の上に分岐を足す
+            Err(value) => {
+                let children = vec![Arc::clone(&self.root), singleton(value, self.height)];
+                Self {
+                    root: branch(children, self.height + 1),
+                    height: self.height + 1,
+                }
+            }
+        }
+    }
+
+    // self の後ろに other を繋いだ新しい版を返す
+    #[must_use]
+    pub fn concat(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+        let height = self.height.max(other.height) + 1;
+        let root = merge(&self.root, self.height, &other.root, other.height);
+        Self::from_root(root, height)
+    }
+
+    // [0, index) と [index, len) に分ける
+    pub fn split_at(&self, index: usize) -> (Self, Self) {
+        assert!(
+            index <= self.len(),
+            "split index {} exceeds len {}",
+            index,
+            self.len()
+        );
+        let (left, right) = split(&self.root, self.height, index);
+        let half = |root: Option<Arc<Node<T>>>| match root {
+            Some(root) => Self::from_root(root, self.height),
+            None => Self::new(),
+        };
+        (half(left), half(right))
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "slice {:?} is out of bounds for len {}",
+            range,
+            self.len()
+        );
+        let (init, _) = self.split_at(range.end);
+        init.split_at(range.start).1
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            stack: vec![slice::from_ref(&self.root).iter()],
+            leaf: [].iter(),
+            remaining: self.len(),
+        }
+    }
+
+    // 葉の深さ、子と要素の数、len と sizes、relaxed でない分岐の子が満杯かを確かめる
+    pub fn check_invariants(&self) {
+        fn check<T>(node: &Node<T>, height: usize, root: bool) {
+            match node {
+                Node::Leaf(items) => {
+                    assert_eq!(height, 0, "leaves are at different depths");
+                    assert!(items.len() <= WIDTH, "leaf overflows");
+                    assert!(root || !items.is_empty(), "leaf is empty");
+                }
+                Node::Branch(b) => {
+                    assert!(height > 0, "leaves are at different depths");
+                    assert!(!b.children.is_empty(), "branch is empty");
+                    assert!(b.children.len() <= WIDTH, "branch overflows");
+                    assert!(!root || b.children.len() > 1, "root has a single child");
+                    for child in &b.children {
+                        check(child, height - 1, false);
+                    }
+                    let len: usize = b.children.iter().map(|c| c.len()).sum();
+                    assert_eq!(b.len, len, "len is stale");
+                    match &b.sizes {
+                        None => {
+                            let full = 1 << (BITS * height);
+                            let (_, init) = b.children.split_last().expect("not empty");
+                            assert!(
+                                init.iter().all(|c| c.len() == full),
+                                "dense branch has a partial child"
+                            );
+                        }
+                        Some(sizes) => {
+                            let expected: Vec<_> = b
+                                .children
+                                .iter()
+                                .scan(0, |sum, c| {
+                                    *sum += c.len();
+                                    Some(*sum)
+                                })
+                                .collect();
+                            assert_eq!(sizes, &expected, "sizes are stale");
+                        }
+                    }
+                }
+            }
+        }
+        check(&self.root, self.height, true);
+    }
+}
+
+// root の Arc を増やすだけなので O(1)
+impl<T> Clone for RrbVec<T> {
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+            height: self.height,
+        }
+    }
+}
+
+impl<T: Clone> Default for RrbVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for RrbVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for RrbVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+// まだ見ていない子の列を深さごとに積み、今の葉の残りを leaf に持つ
+pub struct Iter<'a, T> {
+    stack: Vec<slice::Iter<'a, Arc<Node<T>>>>,
+    leaf: slice::Iter<'a, T>,
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(item) = self.leaf.next() {
+                self.remaining -= 1;
+                return Some(item);
+            }
+            let children = self.stack.last_mut()?;
+            match children.next().map(|c| &**c) {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Node::Leaf(items)) => self.leaf = items.iter(),
+                Some(Node::Branch(b)) => self.stack.push(b.children.iter()),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+impl<'a, T: Clone> IntoIterator for &'a RrbVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+// 葉を満杯にしながら下から積み上げる
+impl<T: Clone> FromIterator<T> for RrbVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut iter = iter.into_iter().peekable();
+        let mut nodes: Vec<Arc<Node<T>>> = Vec::new();
+        while iter.peek().is_some() {
+            let items: Vec<_> = iter.by_ref().take(WIDTH).collect();
+            nodes.push(Arc::new(Node::Leaf(items)));
+        }
+        if nodes.is_empty() {
+            return Self::new();
+        }
+        let mut height = 0;
+        while nodes.len() > 1 {
+            height += 1;
+            nodes = nodes
+                .chunks(WIDTH)
+                .map(|chunk| branch(chunk.to_vec(), height))
+                .collect();
+        }
+        Self {
+            root: nodes.pop().expect("one node is left"),
+            height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn push_get_update() {
+        let v0: RrbVec<usize> = RrbVec::new();
+        let v1 = (0..5000).fold(v0.clone(), |v, i| v.push_back(i));
+        let v2 = v1.update(1234, 0);
+        for v in [&v0, &v1, &v2] {
+            v.check_invariants();
+        }
+        assert!(v0.is_empty());
+        assert_eq!(v1.len(), 5000);
+        assert_eq!(v1.get(1234), Some(&1234));
+        assert_eq!(v2.get(1234), Some(&0));
+        assert_eq!(v1.get(5000), None);
+        assert!(v1.iter().copied().eq(0..5000));
+        assert_eq!(v1, (0..5000).collect());
+    }
+
+    #[test]
+    fn concat_and_slice() {
+        let a: RrbVec<_> = (0..100).collect();
+        let b: RrbVec<_> = (100..1000).collect();
+        let c = a.concat(&b);
+        c.check_invariants();
+        assert!(c.iter().copied().eq(0..1000));
+        // 途中で切った版どうしを繋ぐと relaxed な分岐ができる
+        let d = c.slice(17..900).concat(&c.slice(3..500));
+        d.check_invariants();
+        assert!(d.iter().copied().eq((17..900).chain(3..500)));
+        for (i, x) in (17..900).chain(3..500).enumerate() {
+            assert_eq!(d.get(i), Some(&x));
+        }
+        let (left, right) = d.split_at(1000);
+        left.check_invariants();
+        right.check_invariants();
+        assert_eq!(left.len() + right.len(), d.len());
+        assert_eq!(left.concat(&right), d);
+    }
+
+    #[test]
+    fn many_small_concats_stay_shallow() {
+        // 1 つずつ繋いでも、葉は詰まり、高さは満杯の木と同じくらいに収まる
+        let v = (0..20_000).fold(RrbVec::new(), |v, i| {
+            let one: RrbVec<_> = std::iter::once(i).collect();
+            v.concat(&one)
+        });
+        v.check_invariants();
+        assert!(v.iter().copied().eq(0..20_000));
+        assert!(v.height <= 3, "height is {}", v.height);
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let v: RrbVec<_> = (0..WIDTH * WIDTH * 4).collect();
+        let w = v.update(5, 0);
+        // 変えたのは root から葉までの経路だけ
+        let (vb, wb) = (v.root.branch(), w.root.branch());
+        let shared = vb
+            .children
+            .iter()
+            .zip(&wb.children)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count();
+        assert_eq!(shared, vb.children.len() - 1);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(usize),
+        Update(usize, usize),
+        Concat(usize),
+        Slice(usize, usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        (0..4u8, 0..200usize, any::<usize>()).prop_map(|(t, a, b)| match t {
+            0 => Op::Push(a),
+            1 => Op::Update(a, b),
+            2 => Op::Concat(b),
+            _ => Op::Slice(a, b),
+        })
+    }
+
+    proptest! {
+        // 版を残しながら操作し、Vec の写しと比べる。Concat は前の版と繋ぐ
+        #[test]
+        fn same_as_vec_random(ops in prop::collection::vec(op_strategy(), 0..100)) {
+            let mut versions: Vec<(RrbVec<usize>, Vec<usize>)> = vec![(RrbVec::new(), Vec::new())];
+            let mut next = 0;
+            for op in ops {
+                let (v, model) = versions.last().unwrap().clone();
+                let (v, model) = match op {
+                    Op::Push(n) => {
+                        let items: Vec<_> = (next..next + n).collect();
+                        next += n;
+                        let v = items.iter().fold(v, |v, &x| v.push_back(x));
+                        (v, model.into_iter().chain(items).collect())
+                    }
+                    Op::Update(_, _) if model.is_empty() => continue,
+                    Op::Update(i, x) => {
+                        let i = i % model.len();
+                        let mut model = model;
+                        model[i] = x;
+                        (v.update(i, x), model)
+                    }
+                    Op::Concat(i) => {
+                        let (other, other_model) = &versions[i % versions.len()];
+                        if model.len() + other_model.len() > 20_000 {
+                            continue;
+                        }
+                        let model = model.iter().chain(other_model).copied().collect();
+                        (v.concat(other), model)
+                    }
+                    Op::Slice(a, b) => {
+                        let (a, b) = (a % (model.len() + 1), b % (model.len() + 1));
+                        let range = a.min(b)..a.max(b);
+                        (v.slice(range.clone()), model[range].to_vec())
+                    }
+                };
+                v.check_invariants();
+                versions.push((v, model));
+            }
+            for (v, model) in &versions {
+                prop_assert_eq!(v.len(), model.len());
+                prop_assert!(v.iter().eq(model.iter()));
+                for (i, x) in model.iter().enumerate().step_by(7) {
+                    prop_assert_eq!(v.get(i), Some(x));
+                }
+            }
+        }
+    }
+}
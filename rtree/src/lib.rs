@@ -0,0 +1,629 @@
+// R-tree
+// 箱 (軸に平行な直方体) に値を対応させ、重なる箱や近い箱を探す木
+// 葉は (箱, 値) を、内部ノードは (子の全ての箱を含む一番小さい箱, 子) を MIN_ENTRIES から MAX_ENTRIES 個持ち、葉は全て同じ深さにある
+// キーの範囲ではなく箱で引くので、地図上の図形のように重なり合うものを入れられる
+//
+// 追加 (Guttman) は、入れたときに箱の広がる面積が一番小さい子へ降り、溢れたノードは二次の分割 (quadratic split) で 2 つに分ける
+// 削除は、要素の数が MIN_ENTRIES を下回ったノードを外し、その下の要素を全て入れ直す
+// まとめて作るときは STR (Sort-Tile-Recursive) で、中心の座標で軸ごとに並べて板に切り、葉を詰めて作る
+// STR で作った木は、端のノードが MIN_ENTRIES より少なくなることがある
+//
+// 最近傍は、箱までの距離の近い順にノードと要素をヒープから取り出していく (best-first)
+// 要素を取り出した時点で、残りはどれもそれより遠い
+use std::{cmp::Ordering, collections::BinaryHeap, iter::FromIterator, mem};
+
+pub use spatial::{Coordinate, Rect, SpatialIndex};
+
+const MAX_ENTRIES: usize = 16;
+const MIN_ENTRIES: usize = 6;
+
+// (箱, 値) か (箱, 子) の列
+type Entries<T, E, const K: usize> = Vec<(Rect<T, K>, E)>;
+
+#[derive(Debug, Clone)]
+enum Node<T, V, const K: usize> {
+    Leaf(Entries<T, V, K>),
+    Internal(Entries<T, Box<Node<T, V, K>>, K>),
+}
+
+impl<T: Coordinate, V, const K: usize> Node<T, V, K> {
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(entries) => entries.len(),
+            Node::Internal(children) => children.len(),
+        }
+    }
+
+    // 全ての要素を含む一番小さい箱。空のノードには使わない
+    fn rect(&self) -> Rect<T, K> {
+        match self {
+            Node::Leaf(entries) => bounding(entries),
+            Node::Internal(children) => bounding(children),
+        }
+    }
+
+    // 下にある要素を全て out に移す
+    fn drain_into(self, out: &mut Entries<T, V, K>) {
+        match self {
+            Node::Leaf(entries) => out.extend(entries),
+            Node::Internal(children) => {
+                for (_, child) in children {
+                    child.drain_into(out);
+                }
+            }
+        }
+    }
+}
+
+// other を含めるために増える面積
+fn enlargement<T: Coordinate, const K: usize>(rect: &Rect<T, K>, other: &Rect<T, K>) -> f64 {
+    rect.union(other).area() - rect.area()
+}
+
+// axis の軸の真ん中の座標。整数でも切り捨てない
+fn center<T: Coordinate, const K: usize>(rect: &Rect<T, K>, axis: usize) -> f64 {
+    (rect.min()[axis].to_f64() + rect.max()[axis].to_f64()) / 2.0
+}
+
+fn bounding<T: Coordinate, E, const K: usize>(entries: &[(Rect<T, K>, E)]) -> Rect<T, K> {
+    let (first, rest) = entries.split_first().expect("node is empty");
+    rest.iter().fold(first.0, |rect, (r, _)| rect.union(r))
+}
+
+// 二次の分割。一緒にすると一番無駄な面積が出る 2 つを種にし、
+// 残りはどちらに入れるかで広がる面積の差が大きいものから、広がりの小さい方へ入れる
+fn split<T: Coordinate, E, const K: usize>(
+    mut entries: Entries<T, E, K>,
+) -> (Entries<T, E, K>, Entries<T, E, K>) {
+    let (mut first, mut second, mut worst) = (0, 1, f64::NEG_INFINITY);
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            let (a, b) = (&entries[i].0, &entries[j].0);
+            let waste = a.union(b).area() - a.area() - b.area();
+            if waste > worst {
+                first = i;
+                second = j;
+                worst = waste;
+            }
+        }
+    }
+    // second の方が後ろなので、先に外しても first の位置は変わらない
+    let second = entries.swap_remove(second);
+    let first = entries.swap_remove(first);
+    let (mut left_rect, mut right_rect) = (first.0, second.0);
+    let (mut left, mut right) = (vec![first], vec![second]);
+    while !entries.is_empty() {
+        // 残りを全て入れないと MIN_ENTRIES に届かないなら、全て入れる
+        if left.len() + entries.len() <= MIN_ENTRIES {
+            left.append(&mut entries);
+            break;
+        }
+        if right.len() + entries.len() <= MIN_ENTRIES {
+            right.append(&mut entries);
+            break;
+        }
+        let (index, _) = entries
+            .iter()
+            .map(|(r, _)| (enlargement(&left_rect, r) - enlargement(&right_rect, r)).abs())
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("entries is not empty");
+        let entry = entries.swap_remove(index);
+        let (to_left, to_right) = (
+            enlargement(&left_rect, &entry.0),
+            enlargement(&right_rect, &entry.0),
+        );
+        let goes_left = match to_left.total_cmp(&to_right) {
+            Ordering::Less => true,
+            Ordering::Greater => false,
+            Ordering::Equal => (left_rect.area(), left.len()) <= (right_rect.area(), right.len()),
+        };
+        if goes_left {
+            left_rect = left_rect.union(&entry.0);
+            left.push(entry);
+        } else {
+            right_rect = right_rect.union(&entry.0);
+            right.push(entry);
+        }
+    }
+    (left, right)
+}
+
+// rect を入れたときに広がる面積が一番小さい子。同じなら小さい子
+fn choose<T: Coordinate, E, const K: usize>(
+    entries: &[(Rect<T, K>, E)],
+    rect: &Rect<T, K>,
+) -> usize {
+    entries
+        .iter()
+        .map(|(r, _)| (enlargement(r, rect), r.area()))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)))
+        .map(|(i, _)| i)
+        .expect("internal node is not empty")
+}
+
+// 溢れたら分けたもう片方を返す
+fn insert<T: Coordinate, V, const K: usize>(
+    node: &mut Node<T, V, K>,
+    rect: Rect<T, K>,
+    value: V,
+) -> Option<Node<T, V, K>> {
+    match node {
+        Node::Leaf(entries) => {
+            entries.push((rect, value));
+            if entries.len() <= MAX_ENTRIES {
+                return None;
+            }
+            let (left, right) = split(mem::take(entries));
+            *entries = left;
+            Some(Node::Leaf(right))
+        }
+        Node::Internal(children) => {
+            let i = choose(children, &rect);
+            let grown = children[i].0.union(&rect);
+            match insert(&mut children[i].1, rect, value) {
+                None => {
+                    children[i].0 = grown;
+                    return None;
+                }
+                Some(sibling) => {
+                    children[i].0 = children[i].1.rect();
+                    children.push((sibling.rect(), Box::new(sibling)));
+                }
+            }
+            if children.len() <= MAX_ENTRIES {
+                return None;
+            }
+            let (left, right) = split(mem::take(children));
+            *children = left;
+            Some(Node::Internal(right))
+        }
+    }
+}
+
+// 少なくなりすぎたノードは外し、その下の要素を orphans に移す
+fn remove<T: Coordinate, V, const K: usize>(
+    node: &mut Node<T, V, K>,
+    rect: &Rect<T, K>,
+    orphans: &mut Entries<T, V, K>,
+) -> Option<V> {
+    match node {
+        Node::Leaf(entries) => {
+            let i = entries.iter().position(|(r, _)| r == rect)?;
+            Some(entries.swap_remove(i).1)
+        }
+        Node::Internal(children) => {
+            for i in 0..children.len() {
+                if !children[i].0.contains(rect) {
+                    continue;
+                }
+                if let Some(value) = remove(&mut children[i].1, rect, orphans) {
+                    if children[i].1.len() < MIN_ENTRIES {
+                        let (_, child) = children.swap_remove(i);
+                        child.drain_into(orphans);
+                    } else {
+                        children[i].0 = children[i].1.rect();
+                    }
+                    return Some(value);
+                }
+            }
+            None
+        }
+    }
+}
+
+// 中心の座標で axis の順に並べ、板に切って次の軸で同じように切る。最後の軸で MAX_ENTRIES 個ずつの組にする
+fn tile<T: Coordinate, E, const K: usize>(
+    mut entries: Entries<T, E, K>,
+    axis: usize,
+    out: &mut Vec<Entries<T, E, K>>,
+) {
+    let groups = entries.len().div_ceil(MAX_ENTRIES);
+    entries.sort_by(|a, b| center(&a.0, axis).total_cmp(&center(&b.0, axis)));
+    if axis + 1 == K || groups <= 1 {
+        split_even(entries, groups, out);
+        return;
+    }
+    // 残りの軸それぞれで同じ数に切れるように、板の数は groups の (K - axis) 乗根
+    let slabs = (groups as f64).powf(1.0 / (K - axis) as f64).ceil() as usize;
+    let mut pieces = Vec::with_capacity(slabs);
+    split_even(entries, slabs, &mut pieces);
+    for piece in pieces {
+        tile(piece, axis + 1, out);
+    }
+}
+
+// 順を保って parts 個のほぼ同じ長さに分ける
+fn split_even<E>(mut items: Vec<E>, parts: usize, out: &mut Vec<Vec<E>>) {
+    let len = items.len();
+    let mut pieces = Vec::with_capacity(parts);
+    for i in (0..parts).rev() {
+        pieces.push(items.split_off(len * i / parts));
+    }
+    out.extend(pieces.into_iter().rev());
+}
+
+#[derive(Debug, Clone)]
+pub struct RTree<T, V, const K: usize> {
+    root: Node<T, V, K>,
+    len: usize,
+}
+
+impl<T: Coordinate, V, const K: usize> RTree<T, V, K> {
+    pub fn new() -> Self {
+        assert!(K > 0, "dimension must be positive");
+        Self {
+            root: Node::Leaf(Vec::new()),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 葉までの深さ。葉だけなら 1
+    pub fn height(&self) -> usize {
+        let mut height = 1;
+        let mut node = &self.root;
+        while let Node::Internal(children) = node {
+            height += 1;
+            node = &children[0].1;
+        }
+        height
+    }
+
+    // 全ての要素を含む一番小さい箱
+    pub fn bounds(&self) -> Option<Rect<T, K>> {
+        (self.root.len() > 0).then(|| self.root.rect())
+    }
+
+    pub fn insert(&mut self, rect: Rect<T, K>, value: V) {
+        self.insert_entry(rect, value);
+        self.len += 1;
+    }
+
+    fn insert_entry(&mut self, rect: Rect<T, K>, value: V) {
+        if let Some(sibling) = insert(&mut self.root, rect, value) {
+            let old = mem::replace(&mut self.root, Node::Internal(Vec::new()));
+            self.root = Node::Internal(vec![
+                (old.rect(), Box::new(old)),
+                (sibling.rect(), Box::new(sibling)),
+            ]);
+        }
+    }
+
+    // 箱が rect と等しい要素を 1 つ外して値を返す。同じ箱が複数あれば、どれを外すかは決めない
+    pub fn remove(&mut self, rect: &Rect<T, K>) -> Option<V> {
+        let mut orphans = Vec::new();
+        let value = remove(&mut self.root, rect, &mut orphans)?;
+        self.len -= 1;
+        // 子が 1 つだけの根は、その子に置き換える
+        while let Node::Internal(children) = &mut self.root {
+            match children.len() {
+                0 => self.root = Node::Leaf(Vec::new()),
+                1 => {
+                    let (_, child) = children.pop().expect("root has a child");
+                    self.root = *child;
+                }
+                _ => break,
+            }
+        }
+        for (rect, value) in orphans {
+            self.insert_entry(rect, value);
+        }
+        Some(value)
+    }
+
+    // 全ての要素。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = (&Rect<T, K>, &V)> + '_ {
+        self.walk(|_| true)
+    }
+
+    // 箱が window と重なる要素。順序は決めない
+    pub fn search<'a>(
+        &'a self,
+        window: &'a Rect<T, K>,
+    ) -> impl Iterator<Item = (&'a Rect<T, K>, &'a V)> + 'a {
+        self.walk(move |rect| rect.intersects(window))
+    }
+
+    // keep が true になる箱の子だけを降りて、keep が true になる要素を返す
+    fn walk<'a, F>(&'a self, keep: F) -> impl Iterator<Item = (&'a Rect<T, K>, &'a V)> + 'a
+    where
+        F: Fn(&Rect<T, K>) -> bool + 'a,
+    {
+        let mut stack = vec![&self.root];
+        let mut leaf = [].iter();
+        std::iter::from_fn(move || loop {
+            if let Some((rect, value)) = leaf.by_ref().find(|(rect, _)| keep(rect)) {
+                return Some((rect, value));
+            }
+            match stack.pop()? {
+                Node::Leaf(entries) => leaf = entries.iter(),
+                Node::Internal(children) => stack.extend(
+                    children
+                        .iter()
+                        .filter(|(rect, _)| keep(rect))
+                        .map(|(_, child)| &**child),
+                ),
+            }
+        })
+    }
+
+    // point から近い順に、要素とその箱までの距離の 2 乗を返す
+    pub fn nearest(&self, point: &[T; K]) -> Nearest<'_, T, V, K> {
+        let mut heap = BinaryHeap::new();
+        if self.root.len() > 0 {
+            heap.push(Visit {
+                distance: self.root.rect().distance(point),
+                item: Item::Node(&self.root),
+            });
+        }
+        Nearest {
+            heap,
+            point: *point,
+        }
+    }
+
+    // 箱が子を全て含む一番小さい箱か、葉が同じ深さか、ノードが溢れていないか確かめる
+    pub fn check_invariants(&self) {
+        fn check<T: Coordinate, V, const K: usize>(
+            node: &Node<T, V, K>,
+            depth: usize,
+            leaf_depth: &mut Option<usize>,
+        ) -> usize {
+            assert!(node.len() <= MAX_ENTRIES, "node overflows");
+            match node {
+                Node::Leaf(entries) => {
+                    assert_eq!(
+                        *leaf_depth.get_or_insert(depth),
+                        depth,
+                        "leaves are at different depths"
+                    );
+                    entries.len()
+                }
+                Node::Internal(children) => {
+                    assert!(!children.is_empty(), "internal node is empty");
+                    let mut count = 0;
+                    for (rect, child) in children {
+                        assert!(child.len() > 0, "child is empty");
+                        assert!(*rect == child.rect(), "bounding box is stale");
+                        count += check(child, depth + 1, leaf_depth);
+                    }
+                    count
+                }
+            }
+        }
+        let count = check(&self.root, 0, &mut None);
+        assert_eq!(count, self.len, "len does not match the entries");
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> Default for RTree<T, V, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> From<Vec<(Rect<T, K>, V)>> for RTree<T, V, K> {
+    // STR でまとめて作る。葉を詰めて作った後、ノードの箱を同じように詰めて上の段を作る
+    fn from(entries: Vec<(Rect<T, K>, V)>) -> Self {
+        let mut tree = Self::new();
+        tree.len = entries.len();
+        let mut groups = Vec::new();
+        tile(entries, 0, &mut groups);
+        let mut nodes: Vec<_> = groups.into_iter().map(Node::Leaf).collect();
+        while nodes.len() > 1 {
+            let children = nodes
+                .into_iter()
+                .map(|node| (node.rect(), Box::new(node)))
+                .collect();
+            let mut groups = Vec::new();
+            tile(children, 0, &mut groups);
+            nodes = groups.into_iter().map(Node::Internal).collect();
+        }
+        if let Some(root) = nodes.pop() {
+            tree.root = root;
+        }
+        tree
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> FromIterator<(Rect<T, K>, V)> for RTree<T, V, K> {
+    fn from_iter<I: IntoIterator<Item = (Rect<T, K>, V)>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T: Coordinate, V, const K: usize> SpatialIndex<T, K> for RTree<T, V, K> {
+    type Value = V;
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn query_box(&self, window: &Rect<T, K>) -> Vec<(Rect<T, K>, &V)> {
+        let window = *window;
+        self.walk(move |rect| rect.intersects(&window))
+            .map(|(rect, value)| (*rect, value))
+            .collect()
+    }
+}
+
+enum Item<'a, T, V, const K: usize> {
+    Node(&'a Node<T, V, K>),
+    Entry(&'a Rect<T, K>, &'a V),
+}
+
+struct Visit<'a, T, V, const K: usize> {
+    distance: f64,
+    item: Item<'a, T, V, K>,
+}
+
+// BinaryHeap は大きい順なので、距離の比較を逆にして一番近いものを先頭にする
+impl<T, V, const K: usize> Ord for Visit<'_, T, V, K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+impl<T, V, const K: usize> PartialOrd for Visit<'_, T, V, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, V, const K: usize> PartialEq for Visit<'_, T, V, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T, V, const K: usize> Eq for Visit<'_, T, V, K> {}
+
+pub struct Nearest<'a, T, V, const K: usize> {
+    heap: BinaryHeap<Visit<'a, T, V, K>>,
+    point: [T; K],
+}
+
+impl<'a, T: Coordinate, V, const K: usize> Iterator for Nearest<'a, T, V, K> {
+    type Item = (&'a Rect<T, K>, &'a V, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(Visit { distance, item }) = self.heap.pop() {
+            let node = match item {
+                Item::Entry(rect, value) => return Some((rect, value, distance)),
+                Item::Node(node) => node,
+            };
+            match node {
+                Node::Leaf(entries) => {
+                    for (rect, value) in entries {
+                        self.heap.push(Visit {
+                            distance: rect.distance(&self.point),
+                            item: Item::Entry(rect, value),
+                        });
+                    }
+                }
+                Node::Internal(children) => {
+                    for (rect, child) in children {
+                        self.heap.push(Visit {
+                            distance: rect.distance(&self.point),
+                            item: Item::Node(child),
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rect<i32, 2> {
+        Rect::new([x, y], [x + w, y + h])
+    }
+
+    #[test]
+    fn queries() {
+        let mut tree = RTree::new();
+        tree.insert(rect(0, 0, 2, 2), "park");
+        tree.insert(rect(5, 5, 3, 1), "lake");
+        tree.insert(rect(1, 1, 5, 5), "forest");
+        tree.insert(Rect::point([9, 0]), "tower");
+        let mut found: Vec<_> = tree.search(&rect(2, 2, 1, 1)).map(|(_, v)| *v).collect();
+        found.sort_unstable();
+        assert_eq!(found, vec!["forest", "park"]);
+        let near: Vec<_> = tree.nearest(&[9, 2]).map(|(_, v, d)| (*v, d)).collect();
+        assert_eq!(
+            near,
+            vec![
+                ("tower", 4.0),
+                ("forest", 9.0),
+                ("lake", 10.0),
+                ("park", 49.0)
+            ]
+        );
+        assert_eq!(tree.remove(&rect(1, 1, 5, 5)), Some("forest"));
+        assert_eq!(tree.remove(&rect(1, 1, 5, 5)), None);
+        assert_eq!(tree.bounds(), Some(rect(0, 0, 9, 6)));
+        tree.check_invariants();
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(i32, i32, i32, i32),
+        Remove(prop::sample::Index),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..100i32, 0..100i32, 0..10i32, 0..10i32).prop_map(|(x, y, w, h)| Op::Insert(x, y, w, h)),
+            1 => any::<prop::sample::Index>().prop_map(Op::Remove),
+        ]
+    }
+
+    proptest! {
+        // 追加と削除の後も、重なる要素と近い順の距離が、全ての要素を調べたものと同じになる
+        #[test]
+        fn matches_scan(
+            initial in prop::collection::vec((0..100i32, 0..100i32, 0..10i32, 0..10i32), 0..150),
+            ops in prop::collection::vec(op(), 0..150),
+            windows in prop::collection::vec((0..100i32, 0..100i32, 0..30i32, 0..30i32), 1..5),
+        ) {
+            let mut model: Vec<(Rect<i32, 2>, usize)> = initial
+                .iter()
+                .enumerate()
+                .map(|(i, &(x, y, w, h))| (rect(x, y, w, h), i))
+                .collect();
+            let mut tree: RTree<i32, usize, 2> = model.iter().copied().collect();
+            tree.check_invariants();
+            let mut next = model.len();
+            for op in ops {
+                match op {
+                    Op::Insert(x, y, w, h) => {
+                        tree.insert(rect(x, y, w, h), next);
+                        model.push((rect(x, y, w, h), next));
+                        next += 1;
+                    }
+                    Op::Remove(index) => {
+                        if model.is_empty() {
+                            prop_assert_eq!(tree.remove(&rect(0, 0, 1, 1)), None);
+                            continue;
+                        }
+                        let (r, _) = model[index.index(model.len())];
+                        let value = tree.remove(&r).unwrap();
+                        let i = model.iter().position(|&(mr, mv)| mr == r && mv == value).unwrap();
+                        model.swap_remove(i);
+                    }
+                }
+                tree.check_invariants();
+            }
+            prop_assert_eq!(tree.len(), model.len());
+            for (x, y, w, h) in windows {
+                let window = rect(x, y, w, h);
+                // 全ての要素を調べる Vec と同じになる
+                let mut expected = model.query_box(&window);
+                let mut found = tree.query_box(&window);
+                expected.sort_by_key(|&(_, &v)| v);
+                found.sort_by_key(|&(_, &v)| v);
+                prop_assert_eq!(found, expected);
+
+                let mut expected: Vec<f64> = model.iter().map(|(r, _)| r.distance(&[x, y])).collect();
+                expected.sort_by(f64::total_cmp);
+                let found: Vec<f64> = tree.nearest(&[x, y]).map(|(_, _, d)| d).collect();
+                prop_assert_eq!(found, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("invalid alpha: {alpha} (must be greater than 0.5 and less than 1)")]
+    InvalidAlpha { alpha: f64 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
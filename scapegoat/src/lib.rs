@@ -0,0 +1,498 @@
+// 偏りすぎた部分木だけを作り直して平衡を保つ二分探索木 (scapegoat tree)
+// ノードは key, value と左右の子しか持たず、高さや色などの情報を持たない
+//
+// alpha (0.5 < alpha < 1) で偏りをどこまで許すかを決める
+//   - 追加したノードの深さが log_{1/alpha}(len) を超えたら、根に向かって戻りながら
+//     子の大きさが alpha * 自分の大きさを超える祖先 (scapegoat) を探し、その部分木を作り直す
+//   - 削除で len が alpha * max_len を下回ったら、木全体を作り直す
+// alpha が小さいほど木は低くなり、作り直しが増える
+// 探索は最悪 O(log n)、追加と削除は償却で O(log n)
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+mod error;
+
+pub use error::{Error, Result};
+pub use orderedmap::{Op, OrderedMap};
+
+pub const DEFAULT_ALPHA: f64 = 0.7;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+fn count<K, V>(link: &Link<K, V>) -> usize {
+    let mut count = 0;
+    let mut stack: Vec<&Node<K, V>> = link.as_deref().into_iter().collect();
+    while let Some(node) = stack.pop() {
+        count += 1;
+        stack.extend(node.left.as_deref());
+        stack.extend(node.right.as_deref());
+    }
+    count
+}
+
+// 部分木のノードをキー順に並べる。子は外しておく
+fn flatten<K, V>(link: Link<K, V>, nodes: &mut Vec<Box<Node<K, V>>>) {
+    let mut stack = Vec::new();
+    let mut current = link;
+    loop {
+        while let Some(mut node) = current {
+            current = node.left.take();
+            stack.push(node);
+        }
+        let mut node = match stack.pop() {
+            Some(node) => node,
+            None => return,
+        };
+        current = node.right.take();
+        nodes.push(node);
+    }
+}
+
+// キー順に並んだノードから、高さが最小の木を作る
+fn build<K, V>(len: usize, nodes: &mut impl Iterator<Item = Box<Node<K, V>>>) -> Link<K, V> {
+    if len == 0 {
+        return None;
+    }
+    let left = build(len / 2, nodes);
+    let mut node = nodes.next().expect("len matches the nodes");
+    node.left = left;
+    node.right = build(len - len / 2 - 1, nodes);
+    Some(node)
+}
+
+fn rebuild<K, V>(link: &mut Link<K, V>) {
+    let mut nodes = Vec::new();
+    flatten(link.take(), &mut nodes);
+    *link = build(nodes.len(), &mut nodes.into_iter());
+}
+
+enum Inserted<V> {
+    Replaced(V),
+    Balanced,
+    // 深すぎるノードを追加したが、まだ scapegoat が見つかっていない
+    // size は戻ってきた部分木の大きさ
+    TooDeep { size: usize },
+}
+
+#[derive(Debug)]
+pub struct ScapegoatTree<K, V> {
+    root: Link<K, V>,
+    alpha: f64,
+    len: usize,
+    // 最後に木全体を作り直してからの len の最大値
+    max_len: usize,
+}
+
+impl<K: Ord + Copy, V> ScapegoatTree<K, V> {
+    pub fn new(alpha: f64) -> Self {
+        match Self::try_new(alpha) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(alpha: f64) -> Result<Self> {
+        if !(alpha > 0.5 && alpha < 1.0) {
+            return Err(Error::InvalidAlpha { alpha });
+        }
+        Ok(Self {
+            root: None,
+            alpha,
+            len: 0,
+            max_len: 0,
+        })
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        fn height<K, V>(link: &Link<K, V>) -> usize {
+            link.as_ref()
+                .map_or(0, |n| 1 + height(&n.left).max(height(&n.right)))
+        }
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut current = self.root.as_deref_mut();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let max_depth = self.max_depth(self.len + 1);
+        let inserted = insert(&mut self.root, key, value, 0, max_depth, self.alpha);
+        match inserted {
+            Inserted::Replaced(old) => return Some(old),
+            // 必ずどこかの祖先が scapegoat になるが、浮動小数の誤差に備えて全体を作り直す
+            Inserted::TooDeep { .. } => rebuild(&mut self.root),
+            Inserted::Balanced => {}
+        }
+        self.len += 1;
+        self.max_len = self.max_len.max(self.len);
+        None
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let removed = remove(&mut self.root, &key)?;
+        self.len -= 1;
+        if (self.len as f64) < self.alpha * self.max_len as f64 {
+            rebuild(&mut self.root);
+            self.max_len = self.len;
+        }
+        Some(removed)
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // キーの順序、len、どのノードも log_{1/alpha}(max_len) より深くないことを確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Copy, V>(
+            node: &Node<K, V>,
+            (lower, upper): (Option<K>, Option<K>),
+            depth: usize,
+            max_depth: usize,
+        ) -> usize {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            assert!(depth <= max_depth, "node is too deep");
+            let left = node.left.as_deref().map_or(0, |l| {
+                check(l, (lower, Some(node.key)), depth + 1, max_depth)
+            });
+            let right = node.right.as_deref().map_or(0, |r| {
+                check(r, (Some(node.key), upper), depth + 1, max_depth)
+            });
+            1 + left + right
+        }
+        let max_depth = self.max_depth(self.max_len);
+        let count = self
+            .root
+            .as_deref()
+            .map_or(0, |root| check(root, (None, None), 0, max_depth));
+        assert_eq!(count, self.len, "len does not match the nodes");
+        assert!(self.len <= self.max_len, "max_len is smaller than len");
+    }
+
+    // len 個のノードの木で許す深さ。root が 0
+    fn max_depth(&self, len: usize) -> usize {
+        if len <= 1 {
+            return 0;
+        }
+        ((len as f64).ln() / (1.0 / self.alpha).ln()).floor() as usize
+    }
+}
+
+fn insert<K: Ord, V>(
+    link: &mut Link<K, V>,
+    key: K,
+    value: V,
+    depth: usize,
+    max_depth: usize,
+    alpha: f64,
+) -> Inserted<V> {
+    let node = match link {
+        Some(node) => node,
+        None => {
+            *link = Some(Box::new(Node {
+                key,
+                value,
+                left: None,
+                right: None,
+            }));
+            return if depth > max_depth {
+                Inserted::TooDeep { size: 1 }
+            } else {
+                Inserted::Balanced
+            };
+        }
+    };
+    let (child, sibling) = match key.cmp(&node.key) {
+        Ordering::Equal => return Inserted::Replaced(mem::replace(&mut node.value, value)),
+        Ordering::Less => (&mut node.left, &node.right),
+        Ordering::Greater => (&mut node.right, &node.left),
+    };
+    match insert(child, key, value, depth + 1, max_depth, alpha) {
+        Inserted::TooDeep { size: child_size } => {
+            // 戻ってきた側は数え終わっているので、反対側だけを数える
+            let size = 1 + child_size + count(sibling);
+            if child_size as f64 > alpha * size as f64 {
+                rebuild(link);
+                Inserted::Balanced
+            } else {
+                Inserted::TooDeep { size }
+            }
+        }
+        inserted => inserted,
+    }
+}
+
+fn remove<K: Ord, V>(link: &mut Link<K, V>, key: &K) -> Option<V> {
+    let node = link.as_mut()?;
+    match key.cmp(&node.key) {
+        Ordering::Less => remove(&mut node.left, key),
+        Ordering::Greater => remove(&mut node.right, key),
+        Ordering::Equal => {
+            let mut node = link.take().expect("node is checked above");
+            *link = match (node.left.take(), node.right.take()) {
+                (None, child) | (child, None) => child,
+                (left, Some(right)) => {
+                    // 右の部分木の最小のノードを、消したノードの位置に置く
+                    let (right, mut min) = remove_min(right);
+                    min.left = left;
+                    min.right = right;
+                    Some(min)
+                }
+            };
+            Some(node.value)
+        }
+    }
+}
+
+// 最小のノードを外して、(残りの部分木, 外したノード) を返す
+fn remove_min<K, V>(mut node: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            node.left = left;
+            (Some(node), min)
+        }
+    }
+}
+
+impl<K: Ord + Copy, V> Default for ScapegoatTree<K, V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_ALPHA)
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.stack.clear();
+            return None;
+        }
+        self.push_left(node.right.as_deref());
+        Some((node.key, &node.value))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a ScapegoatTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for ScapegoatTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::default();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for ScapegoatTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        ScapegoatTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        ScapegoatTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        ScapegoatTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        ScapegoatTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        ScapegoatTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = ScapegoatTree::new(0.6);
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn invalid_alpha() {
+        for alpha in [0.5, 1.0, 0.0, 2.0, f64::NAN] {
+            assert!(ScapegoatTree::<usize, ()>::try_new(alpha).is_err());
+        }
+        assert_eq!(
+            ScapegoatTree::<usize, ()>::try_new(0.5).unwrap_err(),
+            Error::InvalidAlpha { alpha: 0.5 }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid alpha")]
+    fn new_panics_on_invalid_alpha() {
+        ScapegoatTree::<usize, ()>::new(1.0);
+    }
+
+    #[test]
+    fn alpha_bounds_height() {
+        // 昇順に入れても偏らない。alpha が小さいほど低い
+        let n = 100_000;
+        let mut heights = Vec::new();
+        for alpha in [0.55, 0.7, 0.9] {
+            let mut t = ScapegoatTree::new(alpha);
+            for k in 0..n {
+                t.insert(k, k);
+            }
+            t.check_invariants();
+            let bound = (n as f64).ln() / (1.0 / alpha).ln() + 1.0;
+            assert!((t.height() as f64) <= bound);
+            heights.push(t.height());
+            // len が alpha * max_len を下回るたびに全体を作り直す
+            for k in 0..n * 3 / 4 {
+                t.remove(k);
+            }
+            t.check_invariants();
+            assert!((t.len() as f64) >= alpha * t.max_len as f64);
+            assert!(t.max_len < n);
+        }
+        assert!(heights.windows(2).all(|w| w[0] <= w[1]), "{:?}", heights);
+    }
+
+    #[test]
+    fn range() {
+        let t: ScapegoatTree<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        let keys: Vec<_> = t.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(t.range(10, 10).count(), 1);
+        assert_eq!(t.range(15, 9).count(), 0);
+        assert_eq!(t.range(199, 1000).count(), 0);
+        assert!(t.iter().map(|(k, _)| k).eq((0..100).map(|k| k * 2)));
+    }
+}
@@ -0,0 +1,417 @@
+// 遅延伝播 (lazy propagation) 付きのセグメント木
+// 区間への更新 (作用) を、区間を覆うノードに溜めておき、子を見るときに初めて子へ渡す
+// 区間の更新と区間の畳み込みを、どちらも O(log n) で行う
+//
+// 作用は Action で表す。作用もモノイドで、a.combine(b) は a をしてから b をしたものと同じ作用
+// apply は長さ len の区間を畳み込んだ値に作用させた値で、畳み込みと入れ替えられる
+// (apply(x.combine(y)) == apply(x).combine(apply(y))) 必要がある
+//
+// 葉の数を 2 のべきに揃えて 2 * size 個の配列に置き、根から葉への経路を先に push してから、
+// 添字だけで下から登る (再帰を使わない)
+use std::{fmt, iter::FromIterator, ops::RangeBounds};
+
+use crate::{bounds, Max, Min, Monoid, Sum};
+
+pub trait Action<T: Monoid>: Monoid {
+    // 長さ len の区間を畳み込んだ value に作用させる
+    fn apply(&self, value: &T, len: usize) -> T;
+}
+
+// 区間の各要素に足す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Add<T>(pub T);
+
+// 区間の各要素を置き換える。None は何もしない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Assign<T>(pub Option<T>);
+
+macro_rules! impl_actions {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for Add<$t> {
+                fn empty() -> Self {
+                    Add(0 as $t)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    Add(self.0 + other.0)
+                }
+            }
+
+            impl Action<Sum<$t>> for Add<$t> {
+                fn apply(&self, value: &Sum<$t>, len: usize) -> Sum<$t> {
+                    Sum(value.0 + self.0 * len as $t)
+                }
+            }
+
+            // 要素のない区間 (empty) は empty のまま
+            impl Action<Min<$t>> for Add<$t> {
+                fn apply(&self, value: &Min<$t>, _: usize) -> Min<$t> {
+                    if *value == Min::empty() { *value } else { Min(value.0 + self.0) }
+                }
+            }
+
+            impl Action<Max<$t>> for Add<$t> {
+                fn apply(&self, value: &Max<$t>, _: usize) -> Max<$t> {
+                    if *value == Max::empty() { *value } else { Max(value.0 + self.0) }
+                }
+            }
+
+            impl Monoid for Assign<$t> {
+                fn empty() -> Self {
+                    Assign(None)
+                }
+
+                // 後からの置き換えが勝つ
+                fn combine(&self, other: &Self) -> Self {
+                    Assign(other.0.or(self.0))
+                }
+            }
+
+            impl Action<Sum<$t>> for Assign<$t> {
+                fn apply(&self, value: &Sum<$t>, len: usize) -> Sum<$t> {
+                    self.0.map_or(*value, |v| Sum(v * len as $t))
+                }
+            }
+
+            impl Action<Min<$t>> for Assign<$t> {
+                fn apply(&self, value: &Min<$t>, _: usize) -> Min<$t> {
+                    self.0.map_or(*value, Min)
+                }
+            }
+
+            impl Action<Max<$t>> for Assign<$t> {
+                fn apply(&self, value: &Max<$t>, _: usize) -> Max<$t> {
+                    self.0.map_or(*value, Max)
+                }
+            }
+        )*
+    };
+}
+
+impl_actions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+#[derive(Clone)]
+pub struct LazySegmentTree<T, A> {
+    // 0 番は使わない。葉は size から size + len - 1
+    nodes: Vec<T>,
+    // 内部ノードに溜めた、子にまだ渡していない作用
+    lazy: Vec<A>,
+    len: usize,
+    size: usize,
+    // size = 2^log
+    log: u32,
+}
+
+impl<T: Monoid, A: Action<T>> LazySegmentTree<T, A> {
+    pub fn new(len: usize) -> Self {
+        Self::from(vec![T::empty(); len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // ノード k が覆う葉の数
+    fn width(&self, k: usize) -> usize {
+        self.size >> (usize::BITS - 1 - k.leading_zeros())
+    }
+
+    fn pull(&mut self, k: usize) {
+        self.nodes[k] = self.nodes[2 * k].combine(&self.nodes[2 * k + 1]);
+    }
+
+    fn apply_node(&mut self, k: usize, action: &A) {
+        self.nodes[k] = action.apply(&self.nodes[k], self.width(k));
+        if k < self.size {
+            self.lazy[k] = self.lazy[k].combine(action);
+        }
+    }
+
+    fn push(&mut self, k: usize) {
+        let action = std::mem::replace(&mut self.lazy[k], A::empty());
+        self.apply_node(2 * k, &action);
+        self.apply_node(2 * k + 1, &action);
+    }
+
+    // 葉 p の上の作用を全て葉まで渡す
+    fn push_path(&mut self, p: usize) {
+        for i in (1..=self.log).rev() {
+            self.push(p >> i);
+        }
+    }
+
+    pub fn get(&mut self, index: usize) -> &T {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        let p = index + self.size;
+        self.push_path(p);
+        &self.nodes[p]
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        let p = index + self.size;
+        self.push_path(p);
+        self.nodes[p] = value;
+        for i in 1..=self.log {
+            self.pull(p >> i);
+        }
+    }
+
+    // [l, r) の両端を含むノードより上の作用を渡す。両端を覆うノードだけ push すればよい
+    fn push_bounds(&mut self, l: usize, r: usize) {
+        for i in (1..=self.log).rev() {
+            if (l >> i) << i != l {
+                self.push(l >> i);
+            }
+            if (r >> i) << i != r {
+                self.push((r - 1) >> i);
+            }
+        }
+    }
+
+    // 子を畳み込むので &mut self を取る
+    pub fn query<R: RangeBounds<usize>>(&mut self, range: R) -> T {
+        let (start, end) = bounds(range, self.len);
+        if start == end {
+            return T::empty();
+        }
+        let (mut l, mut r) = (start + self.size, end + self.size);
+        self.push_bounds(l, r);
+        let mut left = T::empty();
+        let mut right = T::empty();
+        while l < r {
+            if l % 2 == 1 {
+                left = left.combine(&self.nodes[l]);
+                l += 1;
+            }
+            if r % 2 == 1 {
+                r -= 1;
+                right = self.nodes[r].combine(&right);
+            }
+            l /= 2;
+            r /= 2;
+        }
+        left.combine(&right)
+    }
+
+    // 全ての要素の畳み込み。根を見るだけなので O(1)
+    pub fn all(&self) -> &T {
+        &self.nodes[1]
+    }
+
+    // range の各要素に action を作用させる
+    pub fn apply<R: RangeBounds<usize>>(&mut self, range: R, action: A) {
+        let (start, end) = bounds(range, self.len);
+        if start == end {
+            return;
+        }
+        let (l, r) = (start + self.size, end + self.size);
+        self.push_bounds(l, r);
+        let (mut l2, mut r2) = (l, r);
+        while l2 < r2 {
+            if l2 % 2 == 1 {
+                self.apply_node(l2, &action);
+                l2 += 1;
+            }
+            if r2 % 2 == 1 {
+                r2 -= 1;
+                self.apply_node(r2, &action);
+            }
+            l2 /= 2;
+            r2 /= 2;
+        }
+        // 作用させたノードの祖先を求め直す
+        for i in 1..=self.log {
+            if (l >> i) << i != l {
+                self.pull(l >> i);
+            }
+            if (r >> i) << i != r {
+                self.pull((r - 1) >> i);
+            }
+        }
+    }
+
+    // 全ての作用を葉まで渡して、葉を返す
+    pub fn to_vec(&mut self) -> Vec<T> {
+        for k in 1..self.size {
+            self.push(k);
+        }
+        self.nodes[self.size..self.size + self.len].to_vec()
+    }
+}
+
+impl<T: Monoid, A: Action<T>> From<Vec<T>> for LazySegmentTree<T, A> {
+    fn from(values: Vec<T>) -> Self {
+        let len = values.len();
+        let size = len.next_power_of_two();
+        let mut nodes = vec![T::empty(); size];
+        nodes.extend(values);
+        nodes.resize(2 * size, T::empty());
+        let mut tree = Self {
+            nodes,
+            lazy: vec![A::empty(); size],
+            len,
+            size,
+            log: size.trailing_zeros(),
+        };
+        for k in (1..size).rev() {
+            tree.pull(k);
+        }
+        tree
+    }
+}
+
+impl<T: Monoid, A: Action<T>> FromIterator<T> for LazySegmentTree<T, A> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T: Monoid + fmt::Debug, A: Action<T>> fmt::Debug for LazySegmentTree<T, A> {
+    // 作用を渡さずに見られるよう、葉ごとに祖先の作用を下から順に作用させて表示する
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let leaves = (0..self.len).map(|i| {
+            let p = i + self.size;
+            (1..=self.log)
+                .map(|d| &self.lazy[p >> d])
+                .fold(self.nodes[p].clone(), |value, action| {
+                    action.apply(&value, 1)
+                })
+        });
+        f.debug_list().entries(leaves).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // x を a * x + b にする。和には a * sum + b * len で作用する
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Affine(i64, i64);
+
+    impl Monoid for Affine {
+        fn empty() -> Self {
+            Affine(1, 0)
+        }
+
+        // self をしてから other をする
+        fn combine(&self, other: &Self) -> Self {
+            Affine(other.0 * self.0, other.0 * self.1 + other.1)
+        }
+    }
+
+    impl Action<Sum<i64>> for Affine {
+        fn apply(&self, value: &Sum<i64>, len: usize) -> Sum<i64> {
+            Sum(self.0 * value.0 + self.1 * len as i64)
+        }
+    }
+
+    #[test]
+    fn range_add_and_assign() {
+        let mut tree: LazySegmentTree<Sum<i64>, Add<i64>> = (0..10).map(Sum).collect();
+        tree.apply(2..5, Add(10));
+        assert_eq!(tree.query(..), Sum(45 + 30));
+        assert_eq!(tree.query(4..6), Sum(14 + 5));
+        assert_eq!(*tree.get(3), Sum(13));
+        tree.set(3, Sum(0));
+        assert_eq!(*tree.all(), Sum(45 + 30 - 13));
+
+        let mut min: LazySegmentTree<Min<i32>, Assign<i32>> =
+            vec![5, 3, 8, 1, 9, 7].into_iter().map(Min).collect();
+        min.apply(1..4, Assign(Some(6)));
+        assert_eq!(min.query(0..4), Min(5));
+        min.apply(0..2, Assign(Some(2)));
+        min.apply(.., Assign(None));
+        assert_eq!(
+            min.to_vec(),
+            vec![2, 2, 6, 6, 9, 7]
+                .into_iter()
+                .map(Min)
+                .collect::<Vec<_>>()
+        );
+
+        let mut max: LazySegmentTree<Max<i64>, Add<i64>> = LazySegmentTree::new(5);
+        max.apply(1..3, Add(4));
+        assert_eq!(max.query(..), Max(i64::MIN));
+    }
+
+    #[test]
+    fn custom_action() {
+        let mut tree: LazySegmentTree<Sum<i64>, Affine> =
+            vec![1, 2, 3, 4, 5].into_iter().map(Sum).collect();
+        tree.apply(1..4, Affine(2, 1));
+        tree.apply(0..3, Affine(1, -1));
+        // [0, 4, 6, 9, 5]
+        assert_eq!(tree.query(..), Sum(24));
+        assert_eq!(tree.query(1..3), Sum(10));
+        assert_eq!(
+            format!("{:?}", tree),
+            "[Sum(0), Sum(4), Sum(6), Sum(9), Sum(5)]"
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Affine(usize, usize, i64, i64),
+        Set(usize, i64),
+        Query(usize, usize),
+    }
+
+    // 係数は 0 (置き換え) か 1 (足す) にして、値が大きくなりすぎないようにする
+    fn op(len: usize) -> impl Strategy<Value = Op> {
+        let range = (0..=len, 0..=len).prop_map(|(a, b)| (a.min(b), a.max(b)));
+        prop_oneof![
+            (range.clone(), 0..2i64, -5..6i64).prop_map(|((l, r), a, b)| Op::Affine(l, r, a, b)),
+            (0..len.max(1), -5..6i64).prop_map(|(i, v)| Op::Set(i, v)),
+            range.prop_map(|(l, r)| Op::Query(l, r)),
+        ]
+    }
+
+    proptest! {
+        // 区間の作用と区間の和が、Vec に 1 つずつ作用させたものと同じになる
+        #[test]
+        fn matches_vec(
+            (values, ops) in (1..40usize).prop_flat_map(|len| {
+                (prop::collection::vec(-5..6i64, len), prop::collection::vec(op(len), 0..50))
+            })
+        ) {
+            let mut model = values;
+            let mut tree: LazySegmentTree<Sum<i64>, Affine> = model.iter().copied().map(Sum).collect();
+            for op in ops {
+                match op {
+                    Op::Affine(l, r, a, b) => {
+                        model[l..r].iter_mut().for_each(|x| *x = a * *x + b);
+                        tree.apply(l..r, Affine(a, b));
+                    }
+                    Op::Set(i, v) => {
+                        model[i] = v;
+                        tree.set(i, Sum(v));
+                    }
+                    Op::Query(l, r) => {
+                        prop_assert_eq!(tree.query(l..r), Sum(model[l..r].iter().sum()));
+                    }
+                }
+            }
+            prop_assert_eq!(format!("{:?}", tree), format!("{:?}", model.iter().copied().map(Sum).collect::<Vec<_>>()));
+            prop_assert_eq!(tree.to_vec(), model.into_iter().map(Sum).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// セグメント木
+// 配列の各区間について、要素をモノイドで畳み込んだ値を持つ完全二分木
+// 1 点の更新と、任意の区間の畳み込みを O(log n) で行う
+//
+// 再帰は使わず、2n 個の配列に置く (ノード i の子は 2i と 2i + 1、葉は n から 2n - 1)
+// 区間の畳み込みは両端から根に向かって登り、左端で集めた値と右端で集めた値を最後に繋ぐ
+// 左右の順を保つので、combine が可換でなくてよい
+//
+// lazy に、区間への更新もできる遅延伝播付きの木を置く
+// persistent に、更新のたびに新しい版を作り、古い版も問い合わせられる永続木を置く
+// sparse に、変えない配列の区間の最小値などを O(1) で求める sparse table を置く
+use std::{
+    fmt,
+    iter::FromIterator,
+    ops::{Bound, RangeBounds},
+};
+
+pub mod lazy;
+pub mod persistent;
+pub mod sparse;
+
+// 結合則を満たす combine と、その単位元 empty
+pub trait Monoid: Clone {
+    fn empty() -> Self;
+    fn combine(&self, other: &Self) -> Self;
+}
+
+// 和
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sum<T>(pub T);
+
+// 最小値。空の区間は型の最大値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Min<T>(pub T);
+
+// 最大値。空の区間は型の最小値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Max<T>(pub T);
+
+macro_rules! impl_monoids {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for Sum<$t> {
+                fn empty() -> Self {
+                    Sum(0 as $t)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    Sum(self.0 + other.0)
+                }
+            }
+
+            impl Monoid for Min<$t> {
+                fn empty() -> Self {
+                    Min(<$t>::MAX)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    Min(if other.0 < self.0 { other.0 } else { self.0 })
+                }
+            }
+
+            impl Monoid for Max<$t> {
+                fn empty() -> Self {
+                    Max(<$t>::MIN)
+                }
+
+                fn combine(&self, other: &Self) -> Self {
+                    Max(if other.0 > self.0 { other.0 } else { self.0 })
+                }
+            }
+        )*
+    };
+}
+
+impl_monoids!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+// range を [start, end) にする
+pub(crate) fn bounds<R: RangeBounds<usize>>(range: R, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&s) => s,
+        Bound::Excluded(&s) => s + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&e) => e + 1,
+        Bound::Excluded(&e) => e,
+        Bound::Unbounded => len,
+    };
+    assert!(
+        start <= end && end <= len,
+        "range {}..{} is out of 0..{}",
+        start,
+        end,
+        len
+    );
+    (start, end)
+}
+
+#[derive(Clone)]
+pub struct SegmentTree<T> {
+    // 0 番は使わない
+    nodes: Vec<T>,
+    len: usize,
+}
+
+impl<T: Monoid> SegmentTree<T> {
+    // 全て empty の len 個の要素で作る
+    pub fn new(len: usize) -> Self {
+        Self {
+            nodes: vec![T::empty(); 2 * len],
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        &self.nodes[self.len + index]
+    }
+
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        let mut i = self.len + index;
+        self.nodes[i] = value;
+        while i > 1 {
+            i /= 2;
+            self.nodes[i] = self.nodes[2 * i].combine(&self.nodes[2 * i + 1]);
+        }
+    }
+
+    // いまの値から新しい値を作って置く
+    pub fn update<F: FnOnce(&T) -> T>(&mut self, index: usize, f: F) {
+        let value = f(self.get(index));
+        self.set(index, value);
+    }
+
+    // range の要素を左から順に畳み込む。空の区間は empty
+    pub fn query<R: RangeBounds<usize>>(&self, range: R) -> T {
+        let (start, end) = bounds(range, self.len);
+        let (mut l, mut r) = (start + self.len, end + self.len);
+        let mut left = T::empty();
+        let mut right = T::empty();
+        while l < r {
+            if l % 2 == 1 {
+                left = left.combine(&self.nodes[l]);
+                l += 1;
+            }
+            if r % 2 == 1 {
+                r -= 1;
+                right = self.nodes[r].combine(&right);
+            }
+            l /= 2;
+            r /= 2;
+        }
+        left.combine(&right)
+    }
+
+    // 全ての要素の畳み込み
+    pub fn all(&self) -> T {
+        self.query(..)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.nodes[self.len..].iter()
+    }
+}
+
+impl<T: Monoid> From<Vec<T>> for SegmentTree<T> {
+    // 葉を置いてから、下の段から順に親を求める。O(n)
+    fn from(values: Vec<T>) -> Self {
+        let len = values.len();
+        let mut nodes = vec![T::empty(); len];
+        nodes.extend(values);
+        for i in (1..len).rev() {
+            nodes[i] = nodes[2 * i].combine(&nodes[2 * i + 1]);
+        }
+        Self { nodes, len }
+    }
+}
+
+impl<T: Monoid> FromIterator<T> for SegmentTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T: Monoid + fmt::Debug> fmt::Debug for SegmentTree<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    // 可換でないモノイド
+    #[derive(Debug, Clone, PartialEq)]
+    struct Concat(String);
+
+    impl Monoid for Concat {
+        fn empty() -> Self {
+            Concat(String::new())
+        }
+
+        fn combine(&self, other: &Self) -> Self {
+            Concat(self.0.clone() + &other.0)
+        }
+    }
+
+    #[test]
+    fn query() {
+        let mut tree: SegmentTree<Sum<i64>> = (1..=10).map(Sum).collect();
+        assert_eq!(tree.all(), Sum(55));
+        assert_eq!(tree.query(2..5), Sum(3 + 4 + 5));
+        assert_eq!(tree.query(3..3), Sum(0));
+        tree.set(4, Sum(100));
+        assert_eq!(tree.query(..=4), Sum(1 + 2 + 3 + 4 + 100));
+        tree.update(0, |v| Sum(v.0 * 10));
+        assert_eq!(*tree.get(0), Sum(10));
+
+        let min: SegmentTree<Min<u32>> = vec![5, 3, 8, 1, 9].into_iter().map(Min).collect();
+        assert_eq!(min.query(0..3), Min(3));
+        assert_eq!(min.query(4..4), Min(u32::MAX));
+
+        let words: SegmentTree<Concat> = "abcdefg".chars().map(|c| Concat(c.to_string())).collect();
+        assert_eq!(words.query(1..6), Concat("bcdef".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of")]
+    fn out_of_range() {
+        SegmentTree::<Sum<i32>>::new(4).query(2..5);
+    }
+
+    proptest! {
+        // 各区間の畳み込みが、Vec を順に畳み込んだものと同じになる
+        #[test]
+        fn matches_vec(
+            values in prop::collection::vec(any::<char>(), 0..40),
+            updates in prop::collection::vec((any::<prop::sample::Index>(), any::<char>()), 0..20),
+        ) {
+            let mut model = values;
+            let mut tree: SegmentTree<Concat> = model.iter().map(|c| Concat(c.to_string())).collect();
+            for (index, c) in updates {
+                if model.is_empty() {
+                    break;
+                }
+                let i = index.index(model.len());
+                model[i] = c;
+                tree.set(i, Concat(c.to_string()));
+            }
+            for start in 0..=model.len() {
+                for end in start..=model.len() {
+                    let expected: String = model[start..end].iter().collect();
+                    prop_assert_eq!(&tree.query(start..end).0, &expected);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,302 @@
+// 永続セグメント木
+// set は自分を変えずに新しい版を返す。作り直すのは根から変えた葉までの経路の O(log n) 個のノードだけで、
+// 残りの部分木は Arc で前の版と共有する
+// 古い版もそのまま区間の畳み込みができるので、全体を複製せずに過去の任意の版を問い合わせられる
+//
+// History は版を順に積み、k 番目の版を引けるようにする
+use std::{iter::FromIterator, ops::RangeBounds, sync::Arc};
+
+use crate::{bounds, Monoid};
+
+type Link<T> = Option<Arc<Node<T>>>;
+
+#[derive(Debug)]
+struct Node<T> {
+    // この部分木の区間の畳み込み
+    value: T,
+    // 葉は両方 None
+    left: Link<T>,
+    right: Link<T>,
+}
+
+fn value<T: Monoid>(link: &Link<T>) -> T {
+    link.as_ref().map_or_else(T::empty, |n| n.value.clone())
+}
+
+fn branch<T: Monoid>(left: Link<T>, right: Link<T>) -> Link<T> {
+    Some(Arc::new(Node {
+        value: value(&left).combine(&value(&right)),
+        left,
+        right,
+    }))
+}
+
+fn leaf<T>(value: T) -> Link<T> {
+    Some(Arc::new(Node {
+        value,
+        left: None,
+        right: None,
+    }))
+}
+
+// values を [lo, hi) の木にする
+fn build<T: Monoid>(values: &mut [Option<T>], lo: usize, hi: usize) -> Link<T> {
+    if hi - lo == 1 {
+        return leaf(values[lo].take().expect("each leaf is built once"));
+    }
+    let mid = lo + (hi - lo) / 2;
+    branch(build(values, lo, mid), build(values, mid, hi))
+}
+
+// [lo, hi) を覆う node の index の葉を value にした、新しい node
+fn set<T: Monoid>(node: &Arc<Node<T>>, lo: usize, hi: usize, index: usize, value: T) -> Link<T> {
+    if hi - lo == 1 {
+        return leaf(value);
+    }
+    let mid = lo + (hi - lo) / 2;
+    let child = |link: &Link<T>| link.as_ref().expect("branch has both children").clone();
+    if index < mid {
+        branch(
+            set(&child(&node.left), lo, mid, index, value),
+            node.right.clone(),
+        )
+    } else {
+        branch(
+            node.left.clone(),
+            set(&child(&node.right), mid, hi, index, value),
+        )
+    }
+}
+
+// [lo, hi) を覆う node の、[start, end) との共通部分の畳み込み
+fn query<T: Monoid>(link: &Link<T>, lo: usize, hi: usize, start: usize, end: usize) -> T {
+    let node = match link {
+        Some(node) if start < hi && lo < end => node,
+        _ => return T::empty(),
+    };
+    if start <= lo && hi <= end {
+        return node.value.clone();
+    }
+    let mid = lo + (hi - lo) / 2;
+    query(&node.left, lo, mid, start, end).combine(&query(&node.right, mid, hi, start, end))
+}
+
+#[derive(Debug)]
+pub struct PersistentSegmentTree<T> {
+    root: Link<T>,
+    len: usize,
+}
+
+// 根を共有するだけなので O(1)
+impl<T> Clone for PersistentSegmentTree<T> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T: Monoid> PersistentSegmentTree<T> {
+    pub fn new(len: usize) -> Self {
+        Self::from(vec![T::empty(); len])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        let (mut node, mut lo, mut hi) = (self.root.as_ref().unwrap(), 0, self.len);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let next = if index < mid {
+                hi = mid;
+                &node.left
+            } else {
+                lo = mid;
+                &node.right
+            };
+            node = next.as_ref().expect("branch has both children");
+        }
+        &node.value
+    }
+
+    // index を value にした新しい版。自分は変わらない
+    pub fn set(&self, index: usize, value: T) -> Self {
+        assert!(
+            index < self.len,
+            "index {} is out of 0..{}",
+            index,
+            self.len
+        );
+        Self {
+            root: set(self.root.as_ref().unwrap(), 0, self.len, index, value),
+            len: self.len,
+        }
+    }
+
+    pub fn query<R: RangeBounds<usize>>(&self, range: R) -> T {
+        let (start, end) = bounds(range, self.len);
+        query(&self.root, 0, self.len, start, end)
+    }
+
+    pub fn all(&self) -> T {
+        value(&self.root)
+    }
+
+    // 2 つの版が同じ木を指しているか
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        match (&self.root, &other.root) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: Monoid> From<Vec<T>> for PersistentSegmentTree<T> {
+    fn from(values: Vec<T>) -> Self {
+        let len = values.len();
+        let mut values: Vec<_> = values.into_iter().map(Some).collect();
+        Self {
+            root: if len == 0 {
+                None
+            } else {
+                build(&mut values, 0, len)
+            },
+            len,
+        }
+    }
+}
+
+impl<T: Monoid> FromIterator<T> for PersistentSegmentTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+// 版の列。0 番は最初の木で、set のたびに 1 つ増える
+#[derive(Debug, Clone)]
+pub struct History<T> {
+    versions: Vec<PersistentSegmentTree<T>>,
+}
+
+impl<T: Monoid> History<T> {
+    pub fn new(initial: PersistentSegmentTree<T>) -> Self {
+        Self {
+            versions: vec![initial],
+        }
+    }
+
+    // 版の数。最初の版があるので 1 以上
+    pub fn versions(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn latest(&self) -> &PersistentSegmentTree<T> {
+        self.versions
+            .last()
+            .expect("history has the initial version")
+    }
+
+    pub fn version(&self, version: usize) -> Option<&PersistentSegmentTree<T>> {
+        self.versions.get(version)
+    }
+
+    // 最新の版の index を value にした版を積み、その番号を返す
+    pub fn set(&mut self, index: usize, value: T) -> usize {
+        let next = self.latest().set(index, value);
+        self.versions.push(next);
+        self.versions.len() - 1
+    }
+
+    // version 番の版の、range の畳み込み
+    pub fn query<R: RangeBounds<usize>>(&self, version: usize, range: R) -> T {
+        self.versions[version].query(range)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::Sum;
+
+    // 木のノードのアドレスを集める
+    fn nodes<T>(link: &Link<T>, seen: &mut HashSet<*const Node<T>>) {
+        if let Some(node) = link {
+            if seen.insert(Arc::as_ptr(node)) {
+                nodes(&node.left, seen);
+                nodes(&node.right, seen);
+            }
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let mut history = History::new(
+            (0..100)
+                .map(Sum)
+                .collect::<PersistentSegmentTree<Sum<i64>>>(),
+        );
+        assert_eq!(history.set(10, Sum(1000)), 1);
+        assert_eq!(history.set(20, Sum(2000)), 2);
+        assert_eq!(history.query(0, ..), Sum(4950));
+        assert_eq!(history.query(1, ..), Sum(4950 - 10 + 1000));
+        assert_eq!(
+            history.query(2, 15..25),
+            Sum((15..25).sum::<i64>() - 20 + 2000)
+        );
+        assert_eq!(*history.version(1).unwrap().get(20), Sum(20));
+        assert!(history.version(3).is_none());
+
+        // 新しい版で作ったノードは、経路の分だけ
+        let mut seen = HashSet::new();
+        nodes(&history.version(0).unwrap().root, &mut seen);
+        let first = seen.len();
+        nodes(&history.latest().root, &mut seen);
+        assert_eq!(first, 199);
+        assert!(seen.len() - first <= 2 * 8, "{}", seen.len() - first);
+    }
+
+    proptest! {
+        // 各版の区間の和が、その時点の Vec の和と同じになる
+        #[test]
+        fn matches_snapshots(
+            values in prop::collection::vec(-100..100i64, 1..30),
+            updates in prop::collection::vec((any::<prop::sample::Index>(), -100..100i64), 0..20),
+        ) {
+            let mut snapshots = vec![values.clone()];
+            let mut history = History::new(values.into_iter().map(Sum).collect());
+            for (index, v) in updates {
+                let mut next = snapshots.last().unwrap().clone();
+                let i = index.index(next.len());
+                next[i] = v;
+                snapshots.push(next);
+                prop_assert_eq!(history.set(i, Sum(v)), snapshots.len() - 1);
+            }
+            prop_assert_eq!(history.versions(), snapshots.len());
+            for (version, snapshot) in snapshots.iter().enumerate() {
+                for start in 0..=snapshot.len() {
+                    for end in start..=snapshot.len() {
+                        prop_assert_eq!(history.query(version, start..end), Sum(snapshot[start..end].iter().sum()));
+                    }
+                }
+            }
+        }
+    }
+}
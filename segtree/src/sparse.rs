@@ -0,0 +1,143 @@
+// sparse table
+// 変えない配列について、各位置 i から長さ 2^k の区間の畳み込みを全ての k について求めておく (O(n log n))
+// 区間 [l, r) は、長さ 2^k (2^k <= r - l < 2^(k+1)) の 2 つの区間 [l, l + 2^k) と [r - 2^k, r) で覆える
+// 2 つは重なるが、同じ要素を 2 回畳み込んでも変わらない (冪等な) 演算なら、2 つを繋ぐだけの O(1) で求まる
+//
+// 要素を変えるならセグメント木を使う
+use std::{iter::FromIterator, ops::RangeBounds};
+
+use crate::{bounds, Max, Min, Monoid};
+
+// x.combine(x) == x を満たすモノイド。重なった区間を繋いでもよい
+pub trait Idempotent: Monoid {}
+
+// 最大公約数。空の区間は 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gcd<T>(pub T);
+
+macro_rules! impl_idempotent {
+    ($($t:ty),*) => {
+        $(
+            impl Idempotent for Min<$t> {}
+            impl Idempotent for Max<$t> {}
+        )*
+    };
+}
+
+impl_idempotent!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+macro_rules! impl_gcd {
+    ($($t:ty),*) => {
+        $(
+            impl Monoid for Gcd<$t> {
+                fn empty() -> Self {
+                    Gcd(0)
+                }
+
+                // ユークリッドの互除法
+                fn combine(&self, other: &Self) -> Self {
+                    let (mut a, mut b) = (self.0, other.0);
+                    while b != 0 {
+                        let r = a % b;
+                        a = b;
+                        b = r;
+                    }
+                    Gcd(a)
+                }
+            }
+
+            impl Idempotent for Gcd<$t> {}
+        )*
+    };
+}
+
+impl_gcd!(u8, u16, u32, u64, u128, usize);
+
+#[derive(Debug, Clone)]
+pub struct SparseTable<T> {
+    // levels[k][i] は [i, i + 2^k) の畳み込み
+    levels: Vec<Vec<T>>,
+}
+
+impl<T: Idempotent> SparseTable<T> {
+    pub fn len(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> &T {
+        &self.levels[0][index]
+    }
+
+    // range の畳み込み。空の区間は empty
+    pub fn query<R: RangeBounds<usize>>(&self, range: R) -> T {
+        let (start, end) = bounds(range, self.len());
+        if start == end {
+            return T::empty();
+        }
+        let k = (usize::BITS - 1 - (end - start).leading_zeros()) as usize;
+        self.levels[k][start].combine(&self.levels[k][end - (1 << k)])
+    }
+}
+
+impl<T: Idempotent> From<Vec<T>> for SparseTable<T> {
+    fn from(values: Vec<T>) -> Self {
+        let mut levels = vec![values];
+        let mut width = 1;
+        while 2 * width <= levels[0].len() {
+            let prev = levels.last().expect("levels has the values");
+            let next = (0..prev.len() - width)
+                .map(|i| prev[i].combine(&prev[i + width]))
+                .collect();
+            levels.push(next);
+            width *= 2;
+        }
+        Self { levels }
+    }
+}
+
+impl<T: Idempotent> FromIterator<T> for SparseTable<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn query() {
+        let min: SparseTable<Min<i32>> = vec![5, 3, 8, 1, 9, 7, 2].into_iter().map(Min).collect();
+        assert_eq!(min.query(0..3), Min(3));
+        assert_eq!(min.query(4..), Min(2));
+        assert_eq!(min.query(4..6), Min(7));
+        assert_eq!(min.query(2..2), Min(i32::MAX));
+        assert_eq!(*min.get(4), Min(9));
+
+        let gcd: SparseTable<Gcd<u64>> = vec![12, 18, 24, 7, 14].into_iter().map(Gcd).collect();
+        assert_eq!(gcd.query(0..3), Gcd(6));
+        assert_eq!(gcd.query(3..5), Gcd(7));
+        assert_eq!(gcd.query(..), Gcd(1));
+        assert!(SparseTable::<Max<u8>>::from(vec![]).is_empty());
+    }
+
+    proptest! {
+        // 全ての区間の最大値が、Vec で求めたものと同じになる
+        #[test]
+        fn matches_vec(values in prop::collection::vec(any::<i32>(), 0..70)) {
+            let table: SparseTable<Max<i32>> = values.iter().copied().map(Max).collect();
+            for start in 0..=values.len() {
+                for end in start..=values.len() {
+                    let expected = values[start..end].iter().copied().max().unwrap_or(i32::MIN);
+                    prop_assert_eq!(table.query(start..end), Max(expected));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// skew heap
+// 左偏木から rank を除いたもの。meld で右の背骨同士を繋ぎ合わせたあと、通ったノードの左右を必ず入れ替える
+// 右の背骨が長くなっても、次の meld で左に回るので、meld、push、pop は償却 O(log n) になる (1 回では O(n) もありうる)
+// ノードに rank を持たず、meld の後に上へ戻らないので、上から下への 1 回の走査でまとめられる
+// from_vec は 1 つだけのヒープを前から 2 つずつ meld していくので O(n)
+//
+// 背骨は左右どちらも O(n) まで長くなりうるので、meld も drop も走査も再帰しない
+use std::{cmp::Ordering, collections::VecDeque, fmt, iter::FromIterator};
+
+pub use priorityqueue::{Compare, Max, MergeableHeap, Min, PriorityQueue};
+
+type Link<T> = Option<Box<Node<T>>>;
+
+struct Node<T> {
+    value: T,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+pub struct SkewHeap<T, C = Max> {
+    root: Link<T>,
+    len: usize,
+    cmp: C,
+}
+
+impl<T: Ord> SkewHeap<T> {
+    // 大きいものから取り出す
+    pub fn new() -> Self {
+        Self::with_comparator(Max)
+    }
+
+    pub fn from_vec(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Max)
+    }
+}
+
+impl<T: Ord> SkewHeap<T, Min> {
+    // 小さいものから取り出す
+    pub fn new_min() -> Self {
+        Self::with_comparator(Min)
+    }
+
+    pub fn from_vec_min(vec: Vec<T>) -> Self {
+        Self::from_vec_by(vec, Min)
+    }
+}
+
+impl<T, C> SkewHeap<T, C> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // 次に取り出す要素
+    pub fn peek(&self) -> Option<&T> {
+        self.root.as_ref().map(|root| &root.value)
+    }
+
+    pub fn clear(&mut self) {
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+        self.len = 0;
+    }
+
+    // 木の並びのまま返す。順序は決めない
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut stack: Vec<_> = self.root.iter().collect();
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.right.as_ref());
+            stack.extend(node.left.as_ref());
+            Some(&node.value)
+        })
+    }
+}
+
+impl<T, C: Compare<T>> SkewHeap<T, C> {
+    pub fn with_comparator(cmp: C) -> Self {
+        Self {
+            root: None,
+            len: 0,
+            cmp,
+        }
+    }
+
+    // 1 つだけのヒープを 2 つずつ meld していく。O(n)
+    pub fn from_vec_by(vec: Vec<T>, cmp: C) -> Self {
+        let mut heap = Self::with_comparator(cmp);
+        heap.len = vec.len();
+        let mut queue: VecDeque<_> = vec
+            .into_iter()
+            .map(|value| {
+                Box::new(Node {
+                    value,
+                    left: None,
+                    right: None,
+                })
+            })
+            .collect();
+        while let Some(a) = queue.pop_front() {
+            match queue.pop_front() {
+                Some(b) => queue.extend(heap.merge(Some(a), Some(b))),
+                None => {
+                    heap.root = Some(a);
+                    break;
+                }
+            }
+        }
+        heap
+    }
+
+    pub fn push(&mut self, value: T) {
+        let node = Box::new(Node {
+            value,
+            left: None,
+            right: None,
+        });
+        let root = self.root.take();
+        self.root = self.merge(root, Some(node));
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let root = self.root.take()?;
+        let Node {
+            value, left, right, ..
+        } = *root;
+        self.root = self.merge(left, right);
+        self.len -= 1;
+        Some(value)
+    }
+
+    // other の要素を全て取り込む。償却 O(log n)
+    pub fn meld(&mut self, mut other: Self) {
+        let root = self.root.take();
+        self.root = self.merge(root, other.root.take());
+        self.len += std::mem::take(&mut other.len);
+    }
+
+    fn before(&self, a: &Node<T>, b: &Node<T>) -> bool {
+        self.cmp.compare(&a.value, &b.value) == Ordering::Greater
+    }
+
+    // 2 つの木を繋ぐ。右の背骨を上から辿り、先に取り出す方を置いた場所の左に、残りを繋いでいく
+    // 置いたノードは、元の左の子を右に移す
+    fn merge(&self, mut a: Link<T>, mut b: Link<T>) -> Link<T> {
+        let mut merged = None;
+        let mut slot = &mut merged;
+        loop {
+            let (x, y) = match (a, b) {
+                (None, rest) | (rest, None) => {
+                    *slot = rest;
+                    return merged;
+                }
+                (Some(x), Some(y)) => (x, y),
+            };
+            let (mut top, other) = if self.before(&y, &x) { (y, x) } else { (x, y) };
+            a = top.right.take();
+            b = Some(other);
+            top.right = top.left.take();
+            slot = &mut slot.insert(top).left;
+        }
+    }
+
+    // 親が子より後に取り出すものになっていないか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        let mut stack: Vec<_> = self.root.iter().collect();
+        while let Some(node) = stack.pop() {
+            count += 1;
+            for child in node.left.iter().chain(node.right.iter()) {
+                assert!(!self.before(child, node), "child is before its parent");
+                stack.push(child);
+            }
+        }
+        assert_eq!(count, self.len, "len is stale");
+    }
+}
+
+impl<T, C> Drop for SkewHeap<T, C> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: fmt::Debug, C> fmt::Debug for SkewHeap<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T, C: Compare<T> + Default> Default for SkewHeap<T, C> {
+    fn default() -> Self {
+        Self::with_comparator(C::default())
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for SkewHeap<T> {
+    fn from(vec: Vec<T>) -> Self {
+        Self::from_vec(vec)
+    }
+}
+
+impl<T, C: Compare<T> + Default> FromIterator<T> for SkewHeap<T, C> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self::from_vec_by(iter.into_iter().collect(), C::default())
+    }
+}
+
+impl<T, C: Compare<T>> Extend<T> for SkewHeap<T, C> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T, C: Compare<T>> PriorityQueue<T> for SkewHeap<T, C> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, value: T) {
+        SkewHeap::push(self, value)
+    }
+
+    fn peek(&self) -> Option<&T> {
+        SkewHeap::peek(self)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        SkewHeap::pop(self)
+    }
+}
+
+impl<T, C: Compare<T>> MergeableHeap<T> for SkewHeap<T, C> {
+    fn meld(&mut self, other: Self) {
+        SkewHeap::meld(self, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn order() {
+        let mut heap = SkewHeap::from_vec(vec![3, 1, 4, 1, 5, 9, 2, 6]);
+        heap.check_invariants();
+        assert_eq!(heap.peek(), Some(&9));
+        let mut other: SkewHeap<_> = vec![8, 7].into_iter().collect();
+        other.push(10);
+        heap.meld(other);
+        heap.check_invariants();
+        assert_eq!(heap.len(), 11);
+        let order: Vec<_> = std::iter::from_fn(|| heap.pop()).collect();
+        assert_eq!(order, vec![10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 1]);
+
+        let mut min = SkewHeap::from_vec_min(vec![5, 2, 8]);
+        assert_eq!(min.pop(), Some(2));
+        assert_eq!(min.pop(), Some(5));
+    }
+
+    // 昇順に push すると左の背骨が n になる。drop や meld で再帰しないか確かめる
+    #[test]
+    fn long_left_spine() {
+        let mut heap = SkewHeap::new();
+        heap.extend(0..100_000);
+        assert_eq!(heap.peek(), Some(&99_999));
+        assert_eq!(heap.pop(), Some(99_999));
+        assert_eq!(heap.len(), 99_999);
+        heap.meld((100_000..200_000).collect());
+        assert_eq!(heap.pop(), Some(199_999));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Push(i32),
+        Pop,
+        Meld(Vec<i32>),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (-50..50i32).prop_map(Op::Push),
+            2 => Just(Op::Pop),
+            1 => prop::collection::vec(-50..50i32, 0..20).prop_map(Op::Meld),
+        ]
+    }
+
+    // 標準の BinaryHeap と同じ操作列を適用し、全ての結果を比べる
+    proptest! {
+        #[test]
+        fn matches_std(init in prop::collection::vec(-50..50i32, 0..100), ops in prop::collection::vec(op(), 0..200)) {
+            let mut heap = SkewHeap::from_vec(init.clone());
+            let mut expected: std::collections::BinaryHeap<_> = init.into_iter().collect();
+            heap.check_invariants();
+            for op in ops {
+                match op {
+                    Op::Push(v) => {
+                        heap.push(v);
+                        expected.push(v);
+                    }
+                    Op::Pop => prop_assert_eq!(heap.pop(), expected.pop()),
+                    Op::Meld(vs) => {
+                        heap.meld(SkewHeap::from_vec(vs.clone()));
+                        expected.extend(vs);
+                    }
+                }
+                heap.check_invariants();
+                prop_assert_eq!(heap.peek(), expected.peek());
+                prop_assert_eq!(heap.len(), expected.len());
+            }
+        }
+    }
+}
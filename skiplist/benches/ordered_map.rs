@@ -0,0 +1,87 @@
+// OrderedMap の実装を同じ操作で比べる
+//   cargo bench -p skiplist
+use std::collections::BTreeMap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use orderedmap::OrderedMap;
+use skiplist::SkipList;
+
+const SIZES: [usize; 2] = [1_000, 100_000];
+const CAP: usize = 32;
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// 重複の少ないランダムな順のキー
+fn keys(n: usize) -> Vec<usize> {
+    let mut state = 0x9e37_79b9_7f4a_7c15;
+    (0..n)
+        .map(|_| (xorshift(&mut state) % (n as u64 * 4)) as usize)
+        .collect()
+}
+
+fn fill<M: OrderedMap<usize, usize>>(map: &mut M, keys: &[usize]) {
+    for &k in keys {
+        map.insert(k, k);
+    }
+}
+
+fn bench_one<M: OrderedMap<usize, usize>>(
+    c: &mut Criterion,
+    name: &str,
+    new: impl Fn() -> M + Copy,
+) {
+    for n in SIZES {
+        let keys = keys(n);
+        c.bench_with_input(
+            BenchmarkId::new(format!("insert/{}", name), n),
+            &keys,
+            |b, keys| b.iter(|| fill(&mut new(), keys)),
+        );
+
+        let mut map = new();
+        fill(&mut map, &keys);
+        c.bench_with_input(
+            BenchmarkId::new(format!("get/{}", name), n),
+            &keys,
+            |b, keys| b.iter(|| keys.iter().filter(|k| map.get(**k).is_some()).count()),
+        );
+        c.bench_with_input(
+            BenchmarkId::new(format!("range/{}", name), n),
+            &keys,
+            |b, keys| {
+                b.iter(|| {
+                    keys.iter()
+                        .take(100)
+                        .map(|k| map.range(*k, k + 100).len())
+                        .sum::<usize>()
+                })
+            },
+        );
+        c.bench_with_input(
+            BenchmarkId::new(format!("scan/{}", name), n),
+            &map,
+            |b, map| b.iter(|| map.iter().count()),
+        );
+    }
+}
+
+fn ordered_map(c: &mut Criterion) {
+    bench_one(c, "skiplist", SkipList::new);
+    bench_one(c, "bplus", || bplus::BPlusTree::<usize>::new(CAP));
+    bench_one(c, "unsafebplus", || unsafebplus::BPlusTree::new(CAP));
+    // キーの最大数 2 * degree - 1 を CAP に近づける
+    bench_one(c, "btree", || btree::BTree::new(CAP / 2));
+    bench_one(c, "art", art::Art::new);
+    bench_one(c, "qptrie", qptrie::QpTrie::new);
+    bench_one(c, "rbtree", rbtree::RBTree::new);
+    bench_one(c, "tree234", tree234::Tree234::new);
+    bench_one(c, "btree_map", BTreeMap::new);
+}
+
+criterion_group!(benches, ordered_map);
+criterion_main!(benches);
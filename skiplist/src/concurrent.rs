@@ -0,0 +1,646 @@
+// ロックを取らずに複数スレッドから使える skip list
+// 各段の next を CAS で繋ぎ替え、外したノードの解放は epoch で遅延させる
+//
+// 削除は 2 段階で行う
+//   1. 値のポインタに印を付ける (論理削除)。ここで削除が確定する
+//   2. 各段の next に上から印を付け、find で前のノードから外す (物理削除)
+// 印の付いた next を見つけたスレッドは、誰でもそのノードを外してよい
+//
+// ノードは繋がっている段の数を refs で数え、どの段からも外れたら解放する
+// 外したノードと置き換えた値は、次に epoch を進めたスレッドが解放する
+// リストが drop された後に別のスレッドで解放されることもあるので、K と V は Send + Sync + 'static に限る
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use concurrentbplus::ConcurrentOrderedMap;
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+
+use crate::MAX_LEVEL;
+
+// 値のポインタの下位ビットを削除の印に使うので、2 byte 以上に揃える
+#[repr(align(2))]
+struct Value<V>(V);
+
+struct Node<K, V> {
+    key: K,
+    // 印が付いていれば削除済み
+    value: Atomic<Value<V>>,
+    // まだ外れていない段の数
+    refs: AtomicUsize,
+    // 印が付いていれば、この段から外すところ
+    next: Box<[Atomic<Node<K, V>>]>,
+}
+
+impl<K, V> Node<K, V>
+where
+    K: Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    fn is_removed(&self, guard: &Guard) -> bool {
+        self.value.load(Ordering::Acquire, guard).tag() == 1
+    }
+
+    // 上の段から印を付ける。既に付いていても構わない
+    fn mark_tower(&self, guard: &Guard) {
+        for next in self.next.iter().rev() {
+            next.fetch_or(1, Ordering::AcqRel, guard);
+        }
+    }
+
+    // n 段分外れたことにする。どの段からも外れたら解放する
+    //
+    // SAFETY: ptr はこのノードを指していて、n 段分は既に外してあること
+    unsafe fn release(&self, ptr: Shared<'_, Self>, n: usize, guard: &Guard) {
+        if self.refs.fetch_sub(n, Ordering::AcqRel) == n {
+            guard.defer_destroy(ptr.with_tag(0));
+        }
+    }
+}
+
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        // 解放されるときには、どのスレッドからも参照されていない
+        unsafe {
+            let value = self.value.load(Ordering::Relaxed, epoch::unprotected());
+            if !value.is_null() {
+                drop(value.with_tag(0).into_owned());
+            }
+        }
+    }
+}
+
+// find の結果。preds[l] は l 段目で key より小さい最後のノードの next
+// succs[l] はその次のノード
+struct Position<'g, K, V> {
+    preds: [&'g Atomic<Node<K, V>>; MAX_LEVEL],
+    succs: [Shared<'g, Node<K, V>>; MAX_LEVEL],
+}
+
+// insert と remove は CAS で行い、get と range は印を無視して辿るだけで書き込まない
+// range は途中の変更を含むことがあるが、返したエントリはどれも呼び出し中のある時点で存在している
+pub struct ConcurrentSkipList<K, V> {
+    head: [Atomic<Node<K, V>>; MAX_LEVEL],
+    len: AtomicUsize,
+    // 高さを決める乱数の元。fetch_add した値を混ぜて使う
+    seed: AtomicU64,
+}
+
+impl<K, V> ConcurrentSkipList<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            head: Default::default(),
+            len: AtomicUsize::new(0),
+            seed: AtomicU64::new(0),
+        }
+    }
+
+    // 他のスレッドが変更中なら、その途中の数になる
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let guard = &epoch::pin();
+        let node = unsafe { self.lower_bound(key, guard).as_ref()? };
+        if node.key != *key {
+            return None;
+        }
+        let value = node.value.load(Ordering::Acquire, guard);
+        if value.tag() == 1 {
+            return None;
+        }
+        // 値は置き換えても defer_destroy で解放するので、guard の間は読める
+        Some(unsafe { value.deref() }.0.clone())
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let guard = &epoch::pin();
+        let mut value = Owned::new(Value(value));
+        let height = self.random_height();
+        loop {
+            let pos = self.find(&key, guard);
+            if let Some(node) = unsafe { pos.succs[0].as_ref() }.filter(|n| n.key == key) {
+                let current = node.value.load(Ordering::Acquire, guard);
+                if current.tag() == 1 {
+                    // 削除中なので、外すのを手伝ってからやり直す
+                    node.mark_tower(guard);
+                    continue;
+                }
+                match node.value.compare_exchange(
+                    current,
+                    value,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                    guard,
+                ) {
+                    Ok(_) => unsafe {
+                        let old = current.deref().0.clone();
+                        guard.defer_destroy(current);
+                        return Some(old);
+                    },
+                    Err(e) => {
+                        value = e.new;
+                        continue;
+                    }
+                }
+            }
+
+            let node = Owned::new(Node {
+                key: key.clone(),
+                value: Atomic::from(value),
+                refs: AtomicUsize::new(height),
+                next: pos.succs[..height]
+                    .iter()
+                    .map(|s| Atomic::from(*s))
+                    .collect(),
+            });
+            // 一番下の段に繋いだ時点で追加が確定する
+            match pos.preds[0].compare_exchange(
+                pos.succs[0],
+                node,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                guard,
+            ) {
+                Ok(node) => {
+                    self.len.fetch_add(1, Ordering::Relaxed);
+                    self.link_upper(&key, node, pos, guard);
+                    return None;
+                }
+                Err(e) => {
+                    // 公開していないノードなので、値を取り出して作り直す
+                    let node = e.new;
+                    value = unsafe {
+                        node.value
+                            .swap(Shared::null(), Ordering::Relaxed, guard)
+                            .into_owned()
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let guard = &epoch::pin();
+        let node = unsafe { self.find(key, guard).succs[0].as_ref()? };
+        if node.key != *key {
+            return None;
+        }
+        let mut current = node.value.load(Ordering::Acquire, guard);
+        loop {
+            if current.tag() == 1 {
+                // 他のスレッドが先に削除した
+                return None;
+            }
+            match node.value.compare_exchange(
+                current,
+                current.with_tag(1),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                guard,
+            ) {
+                Ok(_) => break,
+                Err(e) => current = e.current,
+            }
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        let old = unsafe { current.deref() }.0.clone();
+        node.mark_tower(guard);
+        // 探索の途中で印の付いたノードを外す
+        self.find(key, guard);
+        Some(old)
+    }
+
+    // min_key <= key <= max_key のエントリをキー順で返す
+    pub fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        let mut result = Vec::new();
+        if min_key > max_key {
+            return result;
+        }
+        let guard = &epoch::pin();
+        let mut current = self.lower_bound(min_key, guard);
+        while let Some(node) = unsafe { current.as_ref() } {
+            if node.key > *max_key {
+                break;
+            }
+            let value = node.value.load(Ordering::Acquire, guard);
+            if value.tag() == 0 {
+                result.push((node.key.clone(), unsafe { value.deref() }.0.clone()));
+            }
+            current = node.next[0].load(Ordering::Acquire, guard).with_tag(0);
+        }
+        result
+    }
+
+    // 2 段目より上を繋ぐ。途中で削除されたら残りの段は諦める
+    fn link_upper<'g>(
+        &'g self,
+        key: &K,
+        ptr: Shared<'g, Node<K, V>>,
+        mut pos: Position<'g, K, V>,
+        guard: &'g Guard,
+    ) {
+        let node = unsafe { ptr.deref() };
+        let height = node.next.len();
+        for level in 1..height {
+            loop {
+                let next = node.next[level].load(Ordering::Acquire, guard);
+                let succ = pos.succs[level];
+                if next.tag() == 1
+                    || next != succ
+                        && node.next[level]
+                            .compare_exchange(
+                                next,
+                                succ,
+                                Ordering::AcqRel,
+                                Ordering::Acquire,
+                                guard,
+                            )
+                            .is_err()
+                {
+                    // 印が付いたので、この段から上は繋がない
+                    unsafe { node.release(ptr, height - level, guard) };
+                    return self.unlink_if_removed(key, node, guard);
+                }
+                if pos.preds[level]
+                    .compare_exchange(succ, ptr, Ordering::AcqRel, Ordering::Acquire, guard)
+                    .is_ok()
+                {
+                    break;
+                }
+                if node.is_removed(guard) {
+                    unsafe { node.release(ptr, height - level, guard) };
+                    return self.unlink_if_removed(key, node, guard);
+                }
+                pos = self.find(key, guard);
+            }
+        }
+        self.unlink_if_removed(key, node, guard);
+    }
+
+    // remove の find が終わった後に繋いだ段が残らないように、もう一度外す
+    fn unlink_if_removed(&self, key: &K, node: &Node<K, V>, guard: &Guard) {
+        if node.is_removed(guard) {
+            node.mark_tower(guard);
+            self.find(key, guard);
+        }
+    }
+
+    // 各段で key より小さい最後のノードを探す
+    // 途中で印の付いたノードを見つけたら外す
+    fn find<'g>(&'g self, key: &K, guard: &'g Guard) -> Position<'g, K, V> {
+        'retry: loop {
+            let mut preds = [&self.head[0]; MAX_LEVEL];
+            let mut succs = [Shared::null(); MAX_LEVEL];
+            let mut pred: &'g [Atomic<Node<K, V>>] = &self.head;
+            for level in (0..MAX_LEVEL).rev() {
+                let mut current = pred[level].load(Ordering::Acquire, guard);
+                if current.tag() == 1 {
+                    // pred 自身が外されるところなので、先頭からやり直す
+                    continue 'retry;
+                }
+                while let Some(node) = unsafe { current.as_ref() } {
+                    let next = node.next[level].load(Ordering::Acquire, guard);
+                    if next.tag() == 1 {
+                        match pred[level].compare_exchange(
+                            current,
+                            next.with_tag(0),
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                            guard,
+                        ) {
+                            Ok(_) => {
+                                unsafe { node.release(current, 1, guard) };
+                                current = next.with_tag(0);
+                                continue;
+                            }
+                            Err(_) => continue 'retry,
+                        }
+                    }
+                    if node.key >= *key {
+                        break;
+                    }
+                    pred = &node.next;
+                    current = next;
+                }
+                preds[level] = &pred[level];
+                succs[level] = current;
+            }
+            return Position { preds, succs };
+        }
+    }
+
+    // key 以上の最初のノード。find と違って、印の付いたノードは外さずに飛ばすだけ
+    // 外されたノードの next は古いままなので、そこから下の段には降りない
+    fn lower_bound<'g>(&'g self, key: &K, guard: &'g Guard) -> Shared<'g, Node<K, V>> {
+        let mut pred: &'g [Atomic<Node<K, V>>] = &self.head;
+        let mut current = Shared::null();
+        for level in (0..MAX_LEVEL).rev() {
+            current = pred[level].load(Ordering::Acquire, guard).with_tag(0);
+            while let Some(node) = unsafe { current.as_ref() } {
+                let next = node.next[level].load(Ordering::Acquire, guard);
+                if next.tag() == 1 {
+                    current = next.with_tag(0);
+                    continue;
+                }
+                if node.key >= *key {
+                    break;
+                }
+                pred = &node.next;
+                current = next;
+            }
+        }
+        current
+    }
+
+    // SkipList と同じく、1 / 4 の確率で一段高くする
+    fn random_height(&self) -> usize {
+        // splitmix64
+        let mut z = self
+            .seed
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let mut bits = z ^ (z >> 31);
+        let mut height = 1;
+        while height < MAX_LEVEL && bits & 3 == 0 {
+            height += 1;
+            bits >>= 2;
+        }
+        height
+    }
+}
+
+impl<K, V> Default for ConcurrentSkipList<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Drop for ConcurrentSkipList<K, V> {
+    fn drop(&mut self) {
+        // &mut self なので他のスレッドは触っていない
+        // 外したノードは defer_destroy 済みなので、一番下の段に残っているものだけを解放する
+        unsafe {
+            let guard = epoch::unprotected();
+            let mut current = self.head[0].load(Ordering::Relaxed, guard);
+            while !current.is_null() {
+                let node = current.with_tag(0).into_owned();
+                current = node.next[0].load(Ordering::Relaxed, guard);
+                drop(node);
+            }
+        }
+    }
+}
+
+impl<K, V> ConcurrentOrderedMap<K, V> for ConcurrentSkipList<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        ConcurrentSkipList::get(self, key)
+    }
+
+    fn insert(&self, key: K, value: V) -> Option<V> {
+        ConcurrentSkipList::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        ConcurrentSkipList::remove(self, key)
+    }
+
+    fn range(&self, min_key: &K, max_key: &K) -> Vec<(K, V)> {
+        ConcurrentSkipList::range(self, min_key, max_key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        sync::{atomic::AtomicU64, Barrier, Mutex},
+        thread,
+    };
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let s = ConcurrentSkipList::new();
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(s.insert(k, -k), None);
+        }
+        assert_eq!(s.len(), 7);
+        assert_eq!(s.get(&24), Some(-24));
+        assert_eq!(s.get(&19), None);
+        assert_eq!(s.insert(12, 120), Some(-12));
+        assert_eq!(s.get(&12), Some(120));
+        assert_eq!(s.remove(&12), Some(120));
+        assert_eq!(s.remove(&12), None);
+        assert_eq!(s.get(&12), None);
+        assert_eq!(s.insert(12, 1), None);
+        assert_eq!(
+            s.range(&11, &24),
+            vec![(11, -11), (12, 1), (13, -13), (14, -14), (24, -24)]
+        );
+        assert_eq!(s.range(&24, &11), vec![]);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert!(s.remove(&k).is_some());
+        }
+        assert!(s.is_empty());
+        assert_eq!(s.range(&0, &100), vec![]);
+    }
+
+    #[test]
+    fn concurrent_insert_and_remove() {
+        let s = ConcurrentSkipList::new();
+        let threads = 4;
+        let per_thread = 2000;
+        let barrier = Barrier::new(threads);
+        thread::scope(|scope| {
+            for n in 0..threads {
+                let (s, barrier) = (&s, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    // 隣のスレッドと半分ずつ重なるキーを触る
+                    let keys = (n * per_thread / 2)..((n + 2) * per_thread / 2);
+                    for k in keys.clone() {
+                        s.insert(k, k);
+                    }
+                    for k in keys.filter(|k| k % 2 == 1) {
+                        s.remove(&k);
+                    }
+                    let r = s.range(&0, &usize::MAX);
+                    assert!(r.windows(2).all(|w| w[0].0 < w[1].0));
+                });
+            }
+        });
+        let expected: Vec<_> = (0..(threads + 1) * per_thread / 2)
+            .filter(|k| k % 2 == 0)
+            .map(|k| (k, k))
+            .collect();
+        assert_eq!(s.range(&0, &usize::MAX), expected);
+        assert_eq!(s.len(), expected.len());
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    enum Call {
+        Insert(u64),
+        Remove,
+        Get,
+    }
+
+    // 1 つのキーに対する操作の記録。start と end は共通の時計で測る
+    #[derive(Debug, Clone, Copy)]
+    struct Event {
+        call: Call,
+        ret: Option<u64>,
+        start: u64,
+        end: u64,
+    }
+
+    // どの操作もキーを 1 つしか触らないので、キーごとに確かめれば全体も線形化可能になる
+    // 終わった操作より後に始まった操作を先に並べないように、順序を総当たりで探す
+    fn linearizable(events: &[Event]) -> bool {
+        assert!(events.len() <= 64);
+        fn search(
+            events: &[Event],
+            done: u64,
+            state: Option<u64>,
+            seen: &mut HashSet<(u64, Option<u64>)>,
+        ) -> bool {
+            if done.count_ones() as usize == events.len() {
+                return true;
+            }
+            if !seen.insert((done, state)) {
+                return false;
+            }
+            let pending = || (0..events.len()).filter(move |i| done & (1 << i) == 0);
+            // 残りの操作のうち最も早く終わったものより後に始まった操作は、まだ選べない
+            let first_end = pending().map(|i| events[i].end).min().unwrap();
+            for i in pending().filter(|&i| events[i].start < first_end) {
+                let e = events[i];
+                if e.ret != state {
+                    continue;
+                }
+                let next = match e.call {
+                    Call::Insert(v) => Some(v),
+                    Call::Remove => None,
+                    Call::Get => state,
+                };
+                if search(events, done | (1 << i), next, seen) {
+                    return true;
+                }
+            }
+            false
+        }
+        search(events, 0, None, &mut HashSet::new())
+    }
+
+    #[test]
+    fn checker() {
+        let event = |call, ret, start, end| Event {
+            call,
+            ret,
+            start,
+            end,
+        };
+        // 重なっているので、insert を先に並べれば説明できる
+        assert!(linearizable(&[
+            event(Call::Get, Some(1), 0, 3),
+            event(Call::Insert(1), None, 1, 2),
+        ]));
+        // insert が終わった後の get は値を見なければならない
+        assert!(!linearizable(&[
+            event(Call::Insert(1), None, 0, 1),
+            event(Call::Get, None, 2, 3),
+        ]));
+        // 同じ値を 2 回削除することはできない
+        assert!(!linearizable(&[
+            event(Call::Insert(1), None, 0, 1),
+            event(Call::Remove, Some(1), 2, 5),
+            event(Call::Remove, Some(1), 3, 4),
+        ]));
+    }
+
+    #[test]
+    fn linearizability_stress() {
+        let threads = 4;
+        let keys = 2;
+        let ops = 16;
+        for round in 0..2000 {
+            let s = ConcurrentSkipList::new();
+            // 高さの違うノードが混ざるように、他のキーも入れておく
+            for k in (0..40).step_by(3) {
+                s.insert(k, u64::MAX);
+            }
+            let clock = AtomicU64::new(0);
+            let history = Mutex::new(BTreeMap::<u64, Vec<Event>>::new());
+            let barrier = Barrier::new(threads);
+            thread::scope(|scope| {
+                for n in 0..threads as u64 {
+                    let (s, clock, history, barrier) = (&s, &clock, &history, &barrier);
+                    scope.spawn(move || {
+                        let mut state = (round * 16 + n + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                        let mut events = Vec::new();
+                        barrier.wait();
+                        for i in 0..ops {
+                            state ^= state << 13;
+                            state ^= state >> 7;
+                            state ^= state << 17;
+                            let key = 1 + 3 * (state % keys);
+                            let call = match (state >> 8) % 3 {
+                                0 => Call::Insert(n * 1000 + i),
+                                1 => Call::Remove,
+                                _ => Call::Get,
+                            };
+                            let start = clock.fetch_add(1, Ordering::SeqCst);
+                            let ret = match call {
+                                Call::Insert(v) => s.insert(key, v),
+                                Call::Remove => s.remove(&key),
+                                Call::Get => s.get(&key),
+                            };
+                            let end = clock.fetch_add(1, Ordering::SeqCst);
+                            events.push((
+                                key,
+                                Event {
+                                    call,
+                                    ret,
+                                    start,
+                                    end,
+                                },
+                            ));
+                        }
+                        let mut history = history.lock().unwrap();
+                        for (key, event) in events {
+                            history.entry(key).or_default().push(event);
+                        }
+                    });
+                }
+            });
+            for (key, events) in history.into_inner().unwrap() {
+                assert!(
+                    linearizable(&events),
+                    "round {} key {}: {:?}",
+                    round,
+                    key,
+                    events
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// 確率的に高さを決めたノードを、高さごとの連結リストで繋いだ順序付き map
+// ノードは Vec に置き、次のノードを位置で持つので unsafe を使わない
+// 削除したノードの位置は free に入れて、次の insert で再利用する
+use std::iter::FusedIterator;
+
+mod concurrent;
+
+pub use concurrent::ConcurrentSkipList;
+pub use concurrentbplus::ConcurrentOrderedMap;
+pub use orderedmap::{Op, OrderedMap};
+
+// 1 / 4 の確率で一段高くなるので、4^16 個程度までは探索が O(log n) に収まる
+pub const MAX_LEVEL: usize = 16;
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // next[level] は level 段目の次のノード。長さがこのノードの高さ
+    next: Vec<Option<usize>>,
+}
+
+#[derive(Debug)]
+pub struct SkipList<K, V> {
+    // 削除した位置は None
+    nodes: Vec<Option<Node<K, V>>>,
+    free: Vec<usize>,
+    // 先頭から見た各段の最初のノード
+    head: [Option<usize>; MAX_LEVEL],
+    // 使っている段の数。空なら 0
+    level: usize,
+    len: usize,
+    // 高さを決める xorshift の状態。0 にはしない
+    rng: u64,
+}
+
+impl<K: Ord + Copy, V> SkipList<K, V> {
+    pub fn new() -> Self {
+        Self::with_seed(DEFAULT_SEED)
+    }
+
+    // 同じ seed と操作列なら同じ形になる
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: [None; MAX_LEVEL],
+            level: 0,
+            len: 0,
+            rng: seed.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let node = self.node(self.next(self.predecessors(key)[0], 0)?);
+        (node.key == key).then_some(&node.value)
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let id = self.next(self.predecessors(key)[0], 0)?;
+        let node = self.nodes[id].as_mut().expect("freed node is linked");
+        (node.key == key).then_some(&mut node.value)
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut update = self.predecessors(key);
+        if let Some(id) = self.next(update[0], 0) {
+            let node = self.nodes[id].as_mut().expect("freed node is linked");
+            if node.key == key {
+                return Some(std::mem::replace(&mut node.value, value));
+            }
+        }
+        let height = self.random_height();
+        if height > self.level {
+            // 新しい段は先頭から繋ぐ
+            for u in &mut update[self.level..height] {
+                *u = None;
+            }
+            self.level = height;
+        }
+        let next = (0..height).map(|l| self.next(update[l], l)).collect();
+        let node = Node { key, value, next };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        for (l, u) in update.iter().enumerate().take(height) {
+            self.set_next(*u, l, Some(id));
+        }
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let update = self.predecessors(key);
+        let id = self.next(update[0], 0)?;
+        if self.node(id).key != key {
+            return None;
+        }
+        let node = self.nodes[id].take().expect("freed node is linked");
+        // update はどの段でも key より小さい最後のノードなので、その次が id になっている
+        for (l, next) in node.next.iter().enumerate() {
+            self.set_next(update[l], l, *next);
+        }
+        while self.level > 0 && self.head[self.level - 1].is_none() {
+            self.level -= 1;
+        }
+        self.free.push(id);
+        self.len -= 1;
+        Some(node.value)
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            list: self,
+            next: self.head[0],
+            max_key: None,
+            remaining: self.len,
+        }
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    // 最初のエントリまでは上の段から探し、後は一番下の段を辿る
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let next = if min_key > max_key {
+            None
+        } else {
+            self.next(self.predecessors(min_key)[0], 0)
+        };
+        Iter {
+            list: self,
+            next,
+            max_key: Some(max_key),
+            remaining: self.len,
+        }
+    }
+
+    // 各段で key より小さい最後のノード。None は先頭
+    fn predecessors(&self, key: K) -> [Option<usize>; MAX_LEVEL] {
+        let mut update = [None; MAX_LEVEL];
+        let mut current = None;
+        for level in (0..self.level).rev() {
+            while let Some(id) = self.next(current, level) {
+                if self.node(id).key >= key {
+                    break;
+                }
+                current = Some(id);
+            }
+            update[level] = current;
+        }
+        update
+    }
+
+    fn next(&self, at: Option<usize>, level: usize) -> Option<usize> {
+        match at {
+            None => self.head[level],
+            Some(id) => self.node(id).next[level],
+        }
+    }
+
+    fn set_next(&mut self, at: Option<usize>, level: usize, next: Option<usize>) {
+        match at {
+            None => self.head[level] = next,
+            Some(id) => self.nodes[id].as_mut().expect("freed node is linked").next[level] = next,
+        }
+    }
+
+    fn node(&self, id: usize) -> &Node<K, V> {
+        self.nodes[id].as_ref().expect("freed node is linked")
+    }
+
+    // 1 以上 MAX_LEVEL 以下。高さ h になる確率は (1/4)^(h-1) * 3/4
+    fn random_height(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let mut bits = self.rng;
+        let mut height = 1;
+        while height < MAX_LEVEL && bits & 3 == 0 {
+            height += 1;
+            bits >>= 2;
+        }
+        height
+    }
+}
+
+impl<K: Ord + Copy, V> Default for SkipList<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 一番下の段を辿る。range の場合は max_key を超えたら終わる
+pub struct Iter<'a, K, V> {
+    list: &'a SkipList<K, V>,
+    next: Option<usize>,
+    max_key: Option<K>,
+    // 残りの数の上限
+    remaining: usize,
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.list.node(self.next?);
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.next = None;
+            return None;
+        }
+        self.next = node.next[0];
+        self.remaining -= 1;
+        Some((node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (self.next, self.max_key) {
+            (None, _) => (0, Some(0)),
+            (Some(_), None) => (self.remaining, Some(self.remaining)),
+            (Some(_), Some(_)) => (0, Some(self.remaining)),
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a SkipList<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for SkipList<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        SkipList::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        SkipList::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        SkipList::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        SkipList::iter(self)
+    }
+
+    // 先頭から辿らずに、min_key まで上の段から探す
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        SkipList::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut s = SkipList::new();
+        assert_eq!(s.get(1), None);
+        for k in (0..1000).rev() {
+            assert_eq!(s.insert(k, k * 10), None);
+        }
+        assert_eq!(s.insert(500, 0), Some(5000));
+        assert_eq!(s.len(), 1000);
+        assert_eq!(s.get(500), Some(&0));
+        *s.get_mut(500).unwrap() = 1;
+        assert_eq!(s.get(500), Some(&1));
+        assert_eq!(s.get(1000), None);
+        for k in (0..1000).step_by(2) {
+            assert_eq!(s.remove(k), Some(if k == 500 { 1 } else { k * 10 }));
+        }
+        assert_eq!(s.remove(0), None);
+        assert_eq!(s.len(), 500);
+        assert!(s.iter().map(|(k, _)| k).eq((1..1000).step_by(2)));
+        // 削除した位置を再利用する
+        s.insert(0, 0);
+        assert_eq!(s.nodes.len(), 1000);
+        for k in 0..1000 {
+            s.remove(k);
+        }
+        assert!(s.is_empty());
+        assert_eq!(s.level, 0);
+        assert_eq!(s.iter().next(), None);
+    }
+
+    #[test]
+    fn range() {
+        let mut s = SkipList::new();
+        for k in 0..100 {
+            s.insert(k * 2, k);
+        }
+        let keys: Vec<_> = s.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(s.range(10, 10).count(), 1);
+        assert_eq!(s.range(15, 9).count(), 0);
+        assert_eq!(s.range(199, usize::MAX).count(), 0);
+        assert_eq!(s.range(0, usize::MAX).count(), 100);
+        assert_eq!(s.iter().size_hint(), (100, Some(100)));
+    }
+}
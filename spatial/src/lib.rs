@@ -0,0 +1,69 @@
+mod rect;
+
+pub use rect::{Coordinate, Rect};
+
+// 空間の索引の共通の操作
+// kdtree、quadtree、octree、rtree を、データの形や次元に合わせて差し替えて使えるようにする
+pub trait SpatialIndex<T: Coordinate, const K: usize> {
+    type Value;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 箱が window と重なる要素の箱と値。点を持つ索引は点だけの箱を返す。順序は決めない
+    fn query_box(&self, window: &Rect<T, K>) -> Vec<(Rect<T, K>, &Self::Value)>;
+}
+
+// 比較の基準にできるように、全ての要素を調べる Vec にも実装しておく
+impl<T: Coordinate, V, const K: usize> SpatialIndex<T, K> for Vec<(Rect<T, K>, V)> {
+    type Value = V;
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn query_box(&self, window: &Rect<T, K>) -> Vec<(Rect<T, K>, &V)> {
+        self.iter()
+            .filter(|(rect, _)| rect.intersects(window))
+            .map(|(rect, value)| (*rect, value))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rect() {
+        let a = Rect::new([0, 0], [4, 2]);
+        let b = Rect::new([4, 1], [6, 5]);
+        assert!(a.intersects(&b));
+        assert!(!a.contains(&b));
+        assert_eq!(a.union(&b), Rect::new([0, 0], [6, 5]));
+        assert_eq!(a.area(), 8.0);
+        assert_eq!(b.center(), [5, 3]);
+        assert_eq!(a.distance(&[7, 4]), 13.0);
+        assert_eq!(a.distance(&[1, 1]), 0.0);
+        assert!(a.contains(&Rect::point([4, 2])));
+    }
+
+    #[test]
+    fn vec() {
+        let items = vec![
+            (Rect::new([0.0, 0.0], [1.0, 1.0]), "a"),
+            (Rect::new([2.0, 2.0], [3.0, 3.0]), "b"),
+            (Rect::point([1.5, 0.5]), "c"),
+        ];
+        let found: Vec<_> = items
+            .query_box(&Rect::new([1.0, 0.0], [2.0, 1.0]))
+            .into_iter()
+            .map(|(_, v)| *v)
+            .collect();
+        assert_eq!(found, vec!["a", "c"]);
+        assert_eq!(items.query_box(&Rect::new([0.0; 2], [9.0; 2])).len(), 3);
+    }
+}
@@ -0,0 +1,129 @@
+// K 次元の軸に平行な箱と、その面積や距離
+// 面積や距離は f64 で求める。大きな i64 の座標では丸めが入る
+
+// 座標に使える数
+pub trait Coordinate: Copy + PartialOrd {
+    fn to_f64(self) -> f64;
+
+    // a <= b のときの、a と b の真ん中。整数は切り捨てる
+    fn midpoint(a: Self, b: Self) -> Self;
+}
+
+macro_rules! impl_coordinate {
+    ($two:expr; $($t:ty),*) => {
+        $(
+            impl Coordinate for $t {
+                fn to_f64(self) -> f64 {
+                    self as f64
+                }
+
+                fn midpoint(a: Self, b: Self) -> Self {
+                    a + (b - a) / $two
+                }
+            }
+        )*
+    };
+}
+
+impl_coordinate!(2; i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+impl_coordinate!(2.0; f32, f64);
+
+fn min<T: Coordinate>(a: T, b: T) -> T {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
+
+fn max<T: Coordinate>(a: T, b: T) -> T {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+// 各座標が min 以上 max 以下の点の集まり。境界を含む
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect<T, const K: usize> {
+    min: [T; K],
+    max: [T; K],
+}
+
+impl<T: Coordinate, const K: usize> Rect<T, K> {
+    pub fn new(min: [T; K], max: [T; K]) -> Self {
+        assert!(
+            (0..K).all(|i| min[i] <= max[i]),
+            "min is not below max on every axis"
+        );
+        Self { min, max }
+    }
+
+    // 1 点だけの箱
+    pub fn point(point: [T; K]) -> Self {
+        Self {
+            min: point,
+            max: point,
+        }
+    }
+
+    pub fn min(&self) -> &[T; K] {
+        &self.min
+    }
+
+    pub fn max(&self) -> &[T; K] {
+        &self.max
+    }
+
+    // 境界が触れるだけでも重なるとみなす
+    pub fn intersects(&self, other: &Self) -> bool {
+        (0..K).all(|i| self.min[i] <= other.max[i] && other.min[i] <= self.max[i])
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        (0..K).all(|i| self.min[i] <= other.min[i] && other.max[i] <= self.max[i])
+    }
+
+    // 両方を含む一番小さい箱
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = *self;
+        for i in 0..K {
+            union.min[i] = min(self.min[i], other.min[i]);
+            union.max[i] = max(self.max[i], other.max[i]);
+        }
+        union
+    }
+
+    pub fn area(&self) -> f64 {
+        (0..K)
+            .map(|i| self.max[i].to_f64() - self.min[i].to_f64())
+            .product()
+    }
+
+    // 各軸の真ん中の座標
+    pub fn center(&self) -> [T; K] {
+        let mut center = self.min;
+        for (i, c) in center.iter_mut().enumerate() {
+            *c = T::midpoint(self.min[i], self.max[i]);
+        }
+        center
+    }
+
+    // point から箱の一番近い点までのユークリッド距離の 2 乗。中にあれば 0
+    pub fn distance(&self, point: &[T; K]) -> f64 {
+        (0..K)
+            .map(|i| {
+                let p = point[i].to_f64();
+                let d = if p < self.min[i].to_f64() {
+                    self.min[i].to_f64() - p
+                } else if p > self.max[i].to_f64() {
+                    p - self.max[i].to_f64()
+                } else {
+                    0.0
+                };
+                d * d
+            })
+            .sum()
+    }
+}
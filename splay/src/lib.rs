@@ -0,0 +1,441 @@
+// 触ったノードを root に持ってくる二分探索木 (splay tree)
+// 平衡のための情報は持たず、get、insert、remove のたびに回転で形を変える
+//
+// 計算量は償却で考える
+//   - m 回の操作の合計は O((m + n) log n)。1 回の操作は最悪 O(n) になりうる
+//   - よく触るキーほど root の近くに残るので、偏ったアクセスでは平衡木より速い
+//     (static optimality: 各キーのアクセス頻度を p とすると、合計は O(Σ log(1 / p)))
+//   - 最近触ったキーはすぐ見つかる (working set)
+// 形を変えるのは access と get_mut。get は OrderedMap と同じく &self で、形を変えずに探す
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V) -> Box<Self> {
+        Box::new(Self {
+            key,
+            value,
+            left: None,
+            right: None,
+        })
+    }
+}
+
+// key のノード、なければ最後に通ったノードを root にする (top-down splay)
+// 通ったノードを key より小さい側と大きい側に分けて積み、最後に root の左右に繋ぐ
+// 木が一直線になっていても再帰しないので、スタックを使い切らない
+fn splay<K: Ord, V>(mut t: Box<Node<K, V>>, key: &K) -> Box<Node<K, V>> {
+    // smaller[i + 1] は smaller[i] の右の子になる
+    let mut smaller: Vec<Box<Node<K, V>>> = Vec::new();
+    // larger[i + 1] は larger[i] の左の子になる
+    let mut larger: Vec<Box<Node<K, V>>> = Vec::new();
+    loop {
+        match key.cmp(&t.key) {
+            Ordering::Equal => break,
+            Ordering::Less => {
+                let mut left = match t.left.take() {
+                    Some(left) => left,
+                    None => break,
+                };
+                if *key < left.key {
+                    // zig-zig は先に回転してから降りる
+                    t.left = left.right.take();
+                    left.right = Some(t);
+                    t = left;
+                    left = match t.left.take() {
+                        Some(left) => left,
+                        None => break,
+                    };
+                }
+                larger.push(mem::replace(&mut t, left));
+            }
+            Ordering::Greater => {
+                let mut right = match t.right.take() {
+                    Some(right) => right,
+                    None => break,
+                };
+                if *key > right.key {
+                    t.right = right.left.take();
+                    right.left = Some(t);
+                    t = right;
+                    right = match t.right.take() {
+                        Some(right) => right,
+                        None => break,
+                    };
+                }
+                smaller.push(mem::replace(&mut t, right));
+            }
+        }
+    }
+    let mut left = t.left.take();
+    while let Some(mut node) = smaller.pop() {
+        node.right = left;
+        left = Some(node);
+    }
+    let mut right = t.right.take();
+    while let Some(mut node) = larger.pop() {
+        node.left = right;
+        right = Some(node);
+    }
+    t.left = left;
+    t.right = right;
+    t
+}
+
+#[derive(Debug)]
+pub struct SplayTree<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+impl<K: Ord + Copy, V> SplayTree<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // root のキー。直前に触ったキーになる
+    pub fn root_key(&self) -> Option<K> {
+        self.root.as_ref().map(|n| n.key)
+    }
+
+    // key を root に持ってきてから返す
+    // 見つからなくても、最後に通ったノードが root になる
+    pub fn access(&mut self, key: K) -> Option<&V> {
+        self.get_mut(key).map(|v| &*v)
+    }
+
+    // access と同じく key を root に持ってくる
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let root = splay(self.root.take()?, &key);
+        let root = self.root.insert(root);
+        (root.key == key).then_some(&mut root.value)
+    }
+
+    // 形を変えずに探す
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    // どちらの場合も key が root になる
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut root = match self.root.take() {
+            Some(root) => splay(root, &key),
+            None => {
+                self.root = Some(Node::new(key, value));
+                self.len += 1;
+                return None;
+            }
+        };
+        let mut node = Node::new(key, value);
+        match key.cmp(&root.key) {
+            Ordering::Equal => {
+                let old = mem::replace(&mut root.value, node.value);
+                self.root = Some(root);
+                return Some(old);
+            }
+            Ordering::Less => {
+                node.left = root.left.take();
+                node.right = Some(root);
+            }
+            Ordering::Greater => {
+                node.right = root.right.take();
+                node.left = Some(root);
+            }
+        }
+        self.root = Some(node);
+        self.len += 1;
+        None
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let mut root = splay(self.root.take()?, &key);
+        if root.key != key {
+            self.root = Some(root);
+            return None;
+        }
+        // 左の部分木の最大のキーを root にすると右の子が空くので、そこに右の部分木を繋ぐ
+        self.root = match root.left.take() {
+            None => root.right.take(),
+            Some(left) => {
+                let mut left = splay(left, &key);
+                left.right = root.right.take();
+                Some(left)
+            }
+        };
+        self.len -= 1;
+        Some(root.value)
+    }
+
+    // キー順に (key, value) を返す。形は変えない
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す。形は変えない
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        let mut max = 0;
+        let mut stack: Vec<_> = self.root.as_deref().map(|n| (n, 1)).into_iter().collect();
+        while let Some((node, depth)) = stack.pop() {
+            max = max.max(depth);
+            for child in node.left.iter().chain(node.right.iter()) {
+                stack.push((child, depth + 1));
+            }
+        }
+        max
+    }
+
+    // キーの順序と len が正しいか確かめる
+    pub fn check_invariants(&self) {
+        let mut count = 0;
+        let mut stack = Vec::new();
+        if let Some(root) = self.root.as_deref() {
+            stack.push((root, None, None));
+        }
+        while let Some((node, lower, upper)) = stack.pop() {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            count += 1;
+            if let Some(left) = node.left.as_deref() {
+                stack.push((left, lower, Some(node.key)));
+            }
+            if let Some(right) = node.right.as_deref() {
+                stack.push((right, Some(node.key), upper));
+            }
+        }
+        assert_eq!(count, self.len, "len does not match the nodes");
+    }
+}
+
+impl<K, V> Drop for SplayTree<K, V> {
+    // 一直線の木を再帰で解放するとスタックを使い切るので、積みながら解放する
+    fn drop(&mut self) {
+        let mut stack: Vec<_> = self.root.take().into_iter().collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.left.take());
+            stack.extend(node.right.take());
+        }
+    }
+}
+
+impl<K: Ord + Copy, V> Default for SplayTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.stack.clear();
+            return None;
+        }
+        self.push_left(node.right.as_deref());
+        Some((node.key, &node.value))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a SplayTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for SplayTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for SplayTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        SplayTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        SplayTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        SplayTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        SplayTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        SplayTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = SplayTree::new();
+        assert_eq!(t.access(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            assert_eq!(t.root_key(), Some(k));
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn access_moves_to_root() {
+        let mut t: SplayTree<_, _> = (0..100).map(|k| (k, k)).collect();
+        assert_eq!(t.access(37), Some(&37));
+        assert_eq!(t.root_key(), Some(37));
+        // get は形を変えない
+        assert_eq!(t.get(80), Some(&80));
+        assert_eq!(t.root_key(), Some(37));
+        // 見つからなければ、隣のキーが root になる
+        assert_eq!(t.access(1000), None);
+        assert_eq!(t.root_key(), Some(99));
+        t.check_invariants();
+    }
+
+    #[test]
+    fn deep_tree() {
+        // 昇順に入れると左に一直線の木になる
+        let n = 200_000;
+        let mut t: SplayTree<_, _> = (0..n).map(|k| (k, k)).collect();
+        assert_eq!(t.height(), n);
+        // 一番深いキーを触ると、高さはおよそ半分になる
+        assert_eq!(t.access(0), Some(&0));
+        assert!(t.height() <= n / 2 + 2);
+        t.check_invariants();
+        // 同じキーを続けて触ると、2 回目からは root で見つかる
+        t.access(n / 3);
+        let height = t.height();
+        assert_eq!(t.access(n / 3), Some(&(n / 3)));
+        assert_eq!(t.height(), height);
+    }
+
+    proptest! {
+        // 形を変える access でも、結果と中身は BTreeMap と同じ
+        // access 以外の操作は conformance で確かめる
+        #[test]
+        fn access_same_as_btree_map_random(
+            entries in prop::collection::btree_map(0..200usize, 0..200usize, 0..200),
+            keys in prop::collection::vec(0..200usize, 0..400),
+        ) {
+            let mut tree: SplayTree<_, _> = entries.iter().map(|(&k, &v)| (k, v)).collect();
+            for key in keys {
+                prop_assert_eq!(tree.access(key), entries.get(&key));
+                tree.check_invariants();
+            }
+            prop_assert!(tree.iter().eq(OrderedMap::iter(&entries)));
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// 接尾辞配列と LCP 配列
+// 接尾辞配列は、テキストの全ての接尾辞を辞書順に並べたときの開始位置の列
+// パターンが現れる位置は、パターンで始まる接尾辞なので、接尾辞配列の上で連続した区間になる
+// 区間は二分探索で求まり、長さ m のパターンで O(m log n)
+//
+// 作るのは SA-IS (Nong, Zhang, Chan 2009) で O(n)
+// 接尾辞を、次の文字より小さい S 型と大きい L 型に分け、S 型の直前が L 型の位置 (LMS) だけを先に並べる
+// LMS の順が決まれば、残りは L 型を前から、S 型を後ろから、バケットに入れていくだけで並ぶ (induced sort)
+// LMS の順は、LMS から次の LMS までの部分文字列に番号を付けた、長さ n / 2 以下の列の接尾辞配列から再帰的に求める
+//
+// LCP 配列は隣り合う接尾辞の共通接頭辞の長さで、Kasai らの方法で O(n) で求める
+//
+// テキストはバイト列として扱う。位置はバイトの位置
+// 作った後にテキストを変えることはできない。変えたら作り直す
+use std::ops::Range;
+
+// 埋まっていない位置
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+pub struct SuffixArray {
+    text: Vec<u8>,
+    // 接尾辞を辞書順に並べた開始位置
+    suffixes: Vec<usize>,
+    // lcp[i] は suffixes[i] と suffixes[i + 1] の接尾辞の共通接頭辞の長さ
+    lcp: Vec<usize>,
+}
+
+impl SuffixArray {
+    pub fn new<T: Into<Vec<u8>>>(text: T) -> Self {
+        let text = text.into();
+        let s: Vec<usize> = text.iter().map(|&b| b as usize).collect();
+        let suffixes = sa_is(&s, u8::MAX as usize);
+        let lcp = kasai(&text, &suffixes);
+        Self {
+            text,
+            suffixes,
+            lcp,
+        }
+    }
+
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    // テキストの長さ
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn suffixes(&self) -> &[usize] {
+        &self.suffixes
+    }
+
+    pub fn lcp(&self) -> &[usize] {
+        &self.lcp
+    }
+
+    // pattern で始まる接尾辞の、接尾辞配列の上での区間
+    fn span(&self, pattern: &[u8]) -> Range<usize> {
+        let start = self
+            .suffixes
+            .partition_point(|&i| &self.text[i..] < pattern);
+        let len = self.suffixes[start..].partition_point(|&i| self.text[i..].starts_with(pattern));
+        start..start + len
+    }
+
+    // pattern が現れる全ての位置を小さい順に返す。重なって現れるものも含む
+    // 空のパターンは全ての位置に現れるものとする
+    pub fn find_all<P: AsRef<[u8]>>(&self, pattern: P) -> Vec<usize> {
+        let mut found = self.suffixes[self.span(pattern.as_ref())].to_vec();
+        found.sort_unstable();
+        found
+    }
+
+    // pattern が現れる回数
+    pub fn count<P: AsRef<[u8]>>(&self, pattern: P) -> usize {
+        self.span(pattern.as_ref()).len()
+    }
+
+    pub fn contains<P: AsRef<[u8]>>(&self, pattern: P) -> bool {
+        !self.span(pattern.as_ref()).is_empty()
+    }
+
+    // 2 回以上現れる最も長い部分文字列。同じ長さなら辞書順で最初のもの
+    // 2 回現れる部分文字列は、隣り合う接尾辞の共通接頭辞なので、LCP の最大から求まる
+    pub fn longest_repeat(&self) -> Option<&[u8]> {
+        let (i, &len) = self
+            .lcp
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, len)| len)?;
+        if len == 0 {
+            return None;
+        }
+        let start = self.suffixes[i];
+        Some(&self.text[start..start + len])
+    }
+
+    // 接尾辞が辞書順に並び、LCP が隣り合う接尾辞と合うか確かめる。O(n^2)
+    pub fn check_invariants(&self) {
+        let n = self.len();
+        assert_eq!(self.suffixes.len(), n, "suffix array has a wrong length");
+        assert_eq!(
+            self.lcp.len(),
+            n.saturating_sub(1),
+            "lcp has a wrong length"
+        );
+        let mut seen = vec![false; n];
+        for &i in &self.suffixes {
+            assert!(i < n && !seen[i], "suffix array is not a permutation");
+            seen[i] = true;
+        }
+        for (w, &len) in self.suffixes.windows(2).zip(&self.lcp) {
+            let (a, b) = (&self.text[w[0]..], &self.text[w[1]..]);
+            assert!(a < b, "suffixes are out of order");
+            let common = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+            assert_eq!(len, common, "lcp is stale");
+        }
+    }
+}
+
+// s の接尾辞配列。s の値は upper 以下
+fn sa_is(s: &[usize], upper: usize) -> Vec<usize> {
+    let n = s.len();
+    match n {
+        0 => return Vec::new(),
+        1 => return vec![0],
+        2 => return if s[0] < s[1] { vec![0, 1] } else { vec![1, 0] },
+        _ => {}
+    }
+    // ls[i] は位置 i の接尾辞が S 型か。最後の文字は L 型とする
+    let mut ls = vec![false; n];
+    for i in (0..n - 1).rev() {
+        ls[i] = if s[i] == s[i + 1] {
+            ls[i + 1]
+        } else {
+            s[i] < s[i + 1]
+        };
+    }
+    // 文字 c のバケットは L 型、S 型の順に並ぶ。sum_l[c] と sum_s[c] はそれぞれの先頭の位置
+    let mut sum_l = vec![0; upper + 1];
+    let mut sum_s = vec![0; upper + 1];
+    for (&c, &is_s) in s.iter().zip(&ls) {
+        if is_s {
+            // S 型の文字は次の文字より小さいので、c + 1 は upper を超えない
+            sum_l[c + 1] += 1;
+        } else {
+            sum_s[c] += 1;
+        }
+    }
+    for c in 0..=upper {
+        sum_s[c] += sum_l[c];
+        if c < upper {
+            sum_l[c + 1] += sum_s[c];
+        }
+    }
+
+    // 並んだ LMS から、全ての接尾辞を並べる
+    let induce = |sa: &mut [usize], lms: &[usize]| {
+        sa.iter_mut().for_each(|x| *x = NONE);
+        // LMS を S 型のバケットの前から入れる
+        let mut buf = sum_s.clone();
+        for &d in lms {
+            sa[buf[s[d]]] = d;
+            buf[s[d]] += 1;
+        }
+        // L 型を前から。最後の接尾辞は最も小さい L 型
+        buf.copy_from_slice(&sum_l);
+        sa[buf[s[n - 1]]] = n - 1;
+        buf[s[n - 1]] += 1;
+        for i in 0..n {
+            let v = sa[i];
+            if v != NONE && v >= 1 && !ls[v - 1] {
+                sa[buf[s[v - 1]]] = v - 1;
+                buf[s[v - 1]] += 1;
+            }
+        }
+        // S 型を後ろから。バケットの末尾は次の文字の L 型の先頭の手前
+        buf.copy_from_slice(&sum_l);
+        for i in (0..n).rev() {
+            let v = sa[i];
+            if v != NONE && v >= 1 && ls[v - 1] {
+                buf[s[v - 1] + 1] -= 1;
+                sa[buf[s[v - 1] + 1]] = v - 1;
+            }
+        }
+    };
+
+    // lms_map[i] は位置 i が何番目の LMS か
+    let mut lms_map = vec![NONE; n + 1];
+    let mut lms = Vec::new();
+    for i in 1..n {
+        if !ls[i - 1] && ls[i] {
+            lms_map[i] = lms.len();
+            lms.push(i);
+        }
+    }
+    let m = lms.len();
+    let mut sa = vec![NONE; n];
+    // LMS を出てきた順に置いて並べると、LMS 部分文字列の順に並ぶ
+    induce(&mut sa, &lms);
+    if m > 0 {
+        let mut sorted_lms: Vec<usize> =
+            sa.iter().copied().filter(|&v| lms_map[v] != NONE).collect();
+        // LMS 部分文字列に、順序を保った番号を付ける。同じ部分文字列には同じ番号
+        let mut rec_s = vec![0; m];
+        let mut rec_upper = 0;
+        for i in 1..m {
+            let (l, r) = (sorted_lms[i - 1], sorted_lms[i]);
+            let end = |p: usize| lms.get(lms_map[p] + 1).copied().unwrap_or(n);
+            let (end_l, end_r) = (end(l), end(r));
+            // 末尾まで続く部分文字列は、他のどれとも違う
+            let same =
+                end_l - l == end_r - r && end_l < n && end_r < n && s[l..=end_l] == s[r..=end_r];
+            if !same {
+                rec_upper += 1;
+            }
+            rec_s[lms_map[r]] = rec_upper;
+        }
+        let rec_sa = sa_is(&rec_s, rec_upper);
+        for (slot, &i) in sorted_lms.iter_mut().zip(&rec_sa) {
+            *slot = lms[i];
+        }
+        induce(&mut sa, &sorted_lms);
+    }
+    sa
+}
+
+// Kasai らの方法。テキストの位置の順に、前の位置の LCP から 1 引いたところから比べ始める
+fn kasai(text: &[u8], suffixes: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut rank = vec![0; n];
+    for (r, &i) in suffixes.iter().enumerate() {
+        rank[i] = r;
+    }
+    let mut lcp = vec![0; n - 1];
+    let mut h: usize = 0;
+    for i in 0..n {
+        h = h.saturating_sub(1);
+        if rank[i] + 1 == n {
+            h = 0;
+            continue;
+        }
+        let j = suffixes[rank[i] + 1];
+        while i + h < n && j + h < n && text[i + h] == text[j + h] {
+            h += 1;
+        }
+        lcp[rank[i]] = h;
+    }
+    lcp
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn banana() {
+        let sa = SuffixArray::new("banana");
+        sa.check_invariants();
+        assert_eq!(sa.suffixes(), &[5, 3, 1, 0, 4, 2]);
+        assert_eq!(sa.lcp(), &[1, 3, 0, 0, 2]);
+        assert_eq!(sa.find_all("ana"), vec![1, 3]);
+        assert_eq!(sa.find_all("a"), vec![1, 3, 5]);
+        assert_eq!(sa.find_all("nab"), Vec::<usize>::new());
+        assert_eq!(sa.find_all(""), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(sa.count("na"), 2);
+        assert!(sa.contains("banana"));
+        assert!(!sa.contains("bananas"));
+        assert_eq!(sa.longest_repeat(), Some(&b"ana"[..]));
+
+        let empty = SuffixArray::new("");
+        empty.check_invariants();
+        assert!(empty.find_all("a").is_empty());
+        assert_eq!(empty.longest_repeat(), None);
+        assert_eq!(SuffixArray::new("abc").longest_repeat(), None);
+    }
+
+    #[test]
+    fn repetitive() {
+        // LMS 部分文字列が同じになり、再帰で並べる
+        for text in [
+            "mmiissiissiippii",
+            "aaaaaaaaaa",
+            "abababababab",
+            "abcabcabcabd",
+        ]
+        .iter()
+        {
+            let sa = SuffixArray::new(*text);
+            sa.check_invariants();
+        }
+        let sa = SuffixArray::new("abababababab");
+        assert_eq!(sa.find_all("abab"), vec![0, 2, 4, 6, 8]);
+        assert_eq!(sa.longest_repeat(), Some(&b"ababababab"[..]));
+    }
+
+    proptest! {
+        // 接尾辞を全て並べたものと同じになり、見つけた位置が全ての位置を調べたものと同じになる
+        #[test]
+        fn matches_naive(
+            text in prop::collection::vec(0..4u8, 0..200),
+            patterns in prop::collection::vec(prop::collection::vec(0..4u8, 0..5), 1..10),
+        ) {
+            let sa = SuffixArray::new(text.clone());
+            sa.check_invariants();
+            let mut expected: Vec<usize> = (0..text.len()).collect();
+            expected.sort_by(|&a, &b| text[a..].cmp(&text[b..]));
+            prop_assert_eq!(sa.suffixes(), &expected[..]);
+            for pattern in patterns {
+                let expected: Vec<usize> = (0..text.len())
+                    .filter(|&i| text[i..].starts_with(&pattern))
+                    .collect();
+                prop_assert_eq!(sa.count(&pattern), expected.len());
+                prop_assert_eq!(sa.find_all(&pattern), expected);
+            }
+        }
+    }
+}
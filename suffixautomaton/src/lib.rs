@@ -0,0 +1,279 @@
+// 接尾辞オートマトン
+// テキストの全ての部分文字列をちょうど受理する最小の DFA。状態は 2n - 1 個以下、遷移は 3n - 4 個以下になる
+// 各状態は、テキスト中で終わる位置の集合 (endpos) が同じ部分文字列をまとめたもの
+// len は状態の最も長い部分文字列の長さで、suffix link はその接尾辞のうち endpos が違う最も長いものの状態を指す
+// 状態の部分文字列の長さは len(link) + 1 から len までなので、異なる部分文字列の数は len - len(link) の和になる
+//
+// 1 文字ずつ末尾に足していける (Blumer らの方法)。1 文字あたり償却 O(log σ) で、σ はアルファベットの大きさ
+// 足すたびに異なる部分文字列の数を更新するので、いつでも O(1) で求まる
+// 最長共通部分文字列は、相手の文字列を遷移で辿り、辿れなくなったら suffix link で短くして続ける。O(m log σ)
+//
+// 接尾辞配列と違い、先に全てのテキストを揃えなくていい。テキストはバイト列として扱う
+use std::{collections::BTreeMap, iter::FromIterator};
+
+#[derive(Debug, Clone)]
+struct State {
+    // この状態の最も長い部分文字列の長さ
+    len: usize,
+    // 根は None
+    link: Option<usize>,
+    next: BTreeMap<u8, usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SuffixAutomaton {
+    // states[0] は空文字列の状態 (根)
+    states: Vec<State>,
+    // テキスト全体の状態
+    last: usize,
+    // 異なる空でない部分文字列の数
+    distinct: usize,
+}
+
+impl SuffixAutomaton {
+    pub fn new() -> Self {
+        Self {
+            states: vec![State {
+                len: 0,
+                link: None,
+                next: BTreeMap::new(),
+            }],
+            last: 0,
+            distinct: 0,
+        }
+    }
+
+    // テキストの長さ
+    pub fn len(&self) -> usize {
+        self.states[self.last].len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 根を含む状態の数
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // テキストの末尾に c を足す
+    pub fn push(&mut self, c: u8) {
+        let cur = self.states.len();
+        self.states.push(State {
+            len: self.len() + 1,
+            link: None,
+            next: BTreeMap::new(),
+        });
+        // テキストの接尾辞の状態を短い方へ辿り、c で出ていけない状態に cur への遷移を足す
+        let mut p = Some(self.last);
+        while let Some(v) = p {
+            if self.states[v].next.contains_key(&c) {
+                break;
+            }
+            self.states[v].next.insert(c, cur);
+            p = self.states[v].link;
+        }
+        let link = match p {
+            None => 0,
+            Some(p) => {
+                let q = self.states[p].next[&c];
+                if self.states[p].len + 1 == self.states[q].len {
+                    q
+                } else {
+                    // q の部分文字列のうち、長さ len(p) + 1 以下のものだけ endpos に新しい位置が加わる
+                    // それらを q の複製に分ける
+                    let clone = self.states.len();
+                    let state = State {
+                        len: self.states[p].len + 1,
+                        ..self.states[q].clone()
+                    };
+                    self.states.push(state);
+                    let mut p = Some(p);
+                    while let Some(v) = p {
+                        match self.states[v].next.get_mut(&c) {
+                            Some(to) if *to == q => *to = clone,
+                            _ => break,
+                        }
+                        p = self.states[v].link;
+                    }
+                    self.states[q].link = Some(clone);
+                    clone
+                }
+            }
+        };
+        self.states[cur].link = Some(link);
+        self.last = cur;
+        // 複製は q の長さの範囲を分けるだけなので、数は cur の分だけ増える
+        self.distinct += self.states[cur].len - self.states[link].len;
+    }
+
+    // 異なる空でない部分文字列の数
+    pub fn distinct_substrings(&self) -> usize {
+        self.distinct
+    }
+
+    // pattern がテキストの部分文字列か
+    pub fn contains<P: AsRef<[u8]>>(&self, pattern: P) -> bool {
+        let mut v = 0;
+        for c in pattern.as_ref() {
+            match self.states[v].next.get(c) {
+                Some(&to) => v = to,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    // テキストと other に共通する最も長い部分文字列。other の中で最初に見つかったものを返す
+    pub fn longest_common_substring<'a>(&self, other: &'a [u8]) -> &'a [u8] {
+        let (mut v, mut len) = (0, 0);
+        let (mut best, mut end) = (0, 0);
+        for (i, c) in other.iter().enumerate() {
+            // other[..=i] の接尾辞のうち、テキストの部分文字列になる最も長いものを v と len で持つ
+            while v != 0 && !self.states[v].next.contains_key(c) {
+                v = self.states[v].link.expect("only the root has no link");
+                len = self.states[v].len;
+            }
+            match self.states[v].next.get(c) {
+                Some(&to) => {
+                    v = to;
+                    len += 1;
+                }
+                None => len = 0,
+            }
+            if len > best {
+                best = len;
+                end = i + 1;
+            }
+        }
+        &other[end - best..end]
+    }
+
+    // suffix link が短い状態を指し、遷移先の len が遷移元より長いか確かめる
+    // 異なる部分文字列の数が len - len(link) の和と合うかも確かめる
+    pub fn check_invariants(&self) {
+        let mut distinct = 0;
+        for (i, state) in self.states.iter().enumerate() {
+            match state.link {
+                None => assert_eq!(i, 0, "only the root has no link"),
+                Some(link) => {
+                    assert!(
+                        self.states[link].len < state.len,
+                        "link is not shorter than the state"
+                    );
+                    distinct += state.len - self.states[link].len;
+                }
+            }
+            for &to in state.next.values() {
+                assert!(
+                    self.states[to].len > state.len,
+                    "transition does not lengthen the state"
+                );
+            }
+        }
+        assert_eq!(distinct, self.distinct, "distinct count is stale");
+        assert!(
+            self.states.len() <= (2 * self.len()).max(2),
+            "too many states"
+        );
+    }
+}
+
+impl Default for SuffixAutomaton {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extend<u8> for SuffixAutomaton {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        for c in iter {
+            self.push(c);
+        }
+    }
+}
+
+impl FromIterator<u8> for SuffixAutomaton {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut automaton = Self::new();
+        automaton.extend(iter);
+        automaton
+    }
+}
+
+impl From<&str> for SuffixAutomaton {
+    fn from(text: &str) -> Self {
+        text.bytes().collect()
+    }
+}
+
+impl From<&[u8]> for SuffixAutomaton {
+    fn from(text: &[u8]) -> Self {
+        text.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn substrings() {
+        let mut sa = SuffixAutomaton::new();
+        assert_eq!(sa.distinct_substrings(), 0);
+        assert!(sa.contains(""));
+        // "aba" の部分文字列は a, b, ab, ba, aba
+        sa.extend(b"aba".iter().copied());
+        sa.check_invariants();
+        assert_eq!(sa.distinct_substrings(), 5);
+        // "abab" では bab と abab が加わる
+        sa.push(b'b');
+        sa.check_invariants();
+        assert_eq!(sa.distinct_substrings(), 7);
+        assert!(sa.contains("bab"));
+        assert!(!sa.contains("bb"));
+        assert_eq!(sa.len(), 4);
+
+        let text = SuffixAutomaton::from("xabcdey");
+        assert_eq!(text.longest_common_substring(b"zzbcdzz"), b"bcd");
+        assert_eq!(text.longest_common_substring(b"qqq"), b"");
+        assert_eq!(SuffixAutomaton::from("aaaa").distinct_substrings(), 4);
+    }
+
+    // 全ての部分文字列の集合
+    fn substrings_of(text: &[u8]) -> HashSet<&[u8]> {
+        (0..text.len())
+            .flat_map(|i| (i + 1..=text.len()).map(move |j| &text[i..j]))
+            .collect()
+    }
+
+    proptest! {
+        // 1 文字ずつ足すたびに、全ての部分文字列を数えたものと同じになる
+        #[test]
+        fn matches_naive(
+            text in prop::collection::vec(0..3u8, 0..40),
+            other in prop::collection::vec(0..3u8, 0..20),
+            patterns in prop::collection::vec(prop::collection::vec(0..3u8, 0..6), 1..10),
+        ) {
+            let mut sa = SuffixAutomaton::new();
+            for (i, &c) in text.iter().enumerate() {
+                sa.push(c);
+                prop_assert_eq!(sa.distinct_substrings(), substrings_of(&text[..=i]).len());
+            }
+            sa.check_invariants();
+            let all = substrings_of(&text);
+            for pattern in &patterns {
+                prop_assert_eq!(sa.contains(pattern), pattern.is_empty() || all.contains(&pattern[..]));
+            }
+            let common = sa.longest_common_substring(&other);
+            prop_assert!(common.is_empty() || all.contains(common));
+            let longest = substrings_of(&other).into_iter().filter(|s| all.contains(s)).map(|s| s.len()).max();
+            prop_assert_eq!(common.len(), longest.unwrap_or(0));
+        }
+    }
+}
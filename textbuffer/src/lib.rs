@@ -0,0 +1,96 @@
+use std::ops::Range;
+
+// 文字列を編集するバッファの共通の操作
+// rope と gapbuffer を、文書の大きさに合わせて差し替えて使えるようにする
+// 位置はどれも文字 (char) の数で数える
+pub trait TextBuffer {
+    fn len_chars(&self) -> usize;
+
+    // at の前に text を入れる
+    fn insert(&mut self, at: usize, text: &str);
+
+    fn remove(&mut self, range: Range<usize>);
+
+    fn slice_to_string(&self, range: Range<usize>) -> String;
+
+    fn is_empty(&self) -> bool {
+        self.len_chars() == 0
+    }
+
+    fn char(&self, index: usize) -> Option<char> {
+        (index < self.len_chars())
+            .then(|| self.slice_to_string(index..index + 1).chars().next())
+            .flatten()
+    }
+
+    // 改行の数 + 1
+    fn len_lines(&self) -> usize {
+        self.text().matches('\n').count() + 1
+    }
+
+    fn text(&self) -> String {
+        self.slice_to_string(0..self.len_chars())
+    }
+}
+
+// 文字の位置をバイトの位置にする
+fn byte_offset(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(i, _)| i)
+}
+
+// 比較の基準にできるように、String にも実装しておく。位置の変換に O(n) かかる
+impl TextBuffer for String {
+    fn len_chars(&self) -> usize {
+        self.chars().count()
+    }
+
+    fn insert(&mut self, at: usize, text: &str) {
+        assert!(at <= self.len_chars(), "char index {} is out of bounds", at);
+        self.insert_str(byte_offset(self, at), text);
+    }
+
+    fn remove(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len_chars(),
+            "char range {:?} is out of bounds",
+            range
+        );
+        let (start, end) = (byte_offset(self, range.start), byte_offset(self, range.end));
+        self.replace_range(start..end, "");
+    }
+
+    fn slice_to_string(&self, range: Range<usize>) -> String {
+        self.chars()
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 実装に依らない操作だけを使う
+    fn exercise<B: TextBuffer>(b: &mut B) {
+        assert!(b.is_empty());
+        b.insert(0, "hello world");
+        b.insert(5, ",");
+        b.insert(0, "あ");
+        assert_eq!(b.text(), "あhello, world");
+        b.remove(1..8);
+        assert_eq!(b.text(), "あworld");
+        assert_eq!(b.char(1), Some('w'));
+        assert_eq!(b.char(6), None);
+        b.insert(1, "\n\n");
+        assert_eq!(b.len_lines(), 3);
+        assert_eq!(b.slice_to_string(2..5), "\nwo");
+    }
+
+    #[test]
+    fn string() {
+        exercise(&mut String::new());
+    }
+}
@@ -0,0 +1,507 @@
+// キーについては二分探索木、ランダムな優先度についてはヒープになっている木 (treap)
+// 形は優先度だけで決まるので、高さの期待値は O(log n) になる
+// 追加と削除も split と merge で行う
+//   split(key)  : key より小さいキーの木と、key 以上のキーの木に分ける
+//   merge(other): 全てのキーが other より小さい木と other を繋ぐ
+// どちらも O(log n) で、部分木の大きさを持っているので len も O(1) で分かる
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // 親の方が大きい
+    priority: u64,
+    // このノードを根とする部分木のノードの数
+    size: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Node<K, V> {
+    fn update_size(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+fn size<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+// key より小さいキーの木と、key 以上のキーの木に分ける
+fn split<K: Ord, V>(link: Link<K, V>, key: &K) -> (Link<K, V>, Link<K, V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    if node.key < *key {
+        let (left, right) = split(node.right.take(), key);
+        node.right = left;
+        node.update_size();
+        (Some(node), right)
+    } else {
+        let (left, right) = split(node.left.take(), key);
+        node.left = right;
+        node.update_size();
+        (left, Some(node))
+    }
+}
+
+// left の全てのキーは right のキーより小さいこと
+fn merge<K, V>(left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority > right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.update_size();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.update_size();
+                Some(right)
+            }
+        }
+    }
+}
+
+fn remove<K: Ord, V>(link: &mut Link<K, V>, key: &K) -> Option<V> {
+    let node = link.as_mut()?;
+    let removed = match key.cmp(&node.key) {
+        Ordering::Less => remove(&mut node.left, key),
+        Ordering::Greater => remove(&mut node.right, key),
+        Ordering::Equal => {
+            let node = *link.take().expect("node is checked above");
+            *link = merge(node.left, node.right);
+            return Some(node.value);
+        }
+    };
+    if removed.is_some() {
+        node.update_size();
+    }
+    removed
+}
+
+#[derive(Debug)]
+pub struct Treap<K, V> {
+    root: Link<K, V>,
+    // 優先度を作る xorshift の状態。0 にはしない
+    rng: u64,
+}
+
+impl<K: Ord + Copy, V> Treap<K, V> {
+    pub fn new() -> Self {
+        Self::with_seed(DEFAULT_SEED)
+    }
+
+    // 同じ seed と操作列なら同じ形になる
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            root: None,
+            rng: seed.max(1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        fn height<K, V>(link: &Link<K, V>) -> usize {
+            link.as_ref()
+                .map_or(0, |n| 1 + height(&n.left).max(height(&n.right)))
+        }
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut current = self.root.as_deref_mut();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(v) = self.get_mut(key) {
+            return Some(mem::replace(v, value));
+        }
+        let node = Box::new(Node {
+            key,
+            value,
+            priority: self.next_priority(),
+            size: 1,
+            left: None,
+            right: None,
+        });
+        let (left, right) = split(self.root.take(), &key);
+        self.root = merge(merge(left, Some(node)), right);
+        None
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        remove(&mut self.root, &key)
+    }
+
+    // key より小さいキーの木と、key 以上のキーの木に分ける
+    pub fn split(mut self, key: K) -> (Self, Self) {
+        let (left, right) = split(self.root.take(), &key);
+        // 右の木は別の乱数列を使う
+        let rng = self.next_priority();
+        (
+            Self {
+                root: left,
+                rng: self.rng,
+            },
+            Self { root: right, rng },
+        )
+    }
+
+    // self の全てのキーが other のどのキーよりも小さいこと
+    pub fn merge(mut self, other: Self) -> Self {
+        if let (Some(max), Some(min)) = (self.last_key(), other.first_key()) {
+            assert!(max < min, "keys of merged treaps overlap");
+        }
+        self.root = merge(self.root.take(), other.root);
+        self
+    }
+
+    pub fn first_key(&self) -> Option<K> {
+        let mut node = self.root.as_deref()?;
+        while let Some(left) = node.left.as_deref() {
+            node = left;
+        }
+        Some(node.key)
+    }
+
+    pub fn last_key(&self) -> Option<K> {
+        let mut node = self.root.as_deref()?;
+        while let Some(right) = node.right.as_deref() {
+            node = right;
+        }
+        Some(node.key)
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: None,
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            max_key: Some(max_key),
+        };
+        if min_key > max_key {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // キーの順序、優先度のヒープ条件、部分木の大きさが正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Copy, V>(node: &Node<K, V>, (lower, upper): (Option<K>, Option<K>)) {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            for child in node.left.iter().chain(node.right.iter()) {
+                assert!(child.priority <= node.priority, "heap order is broken");
+            }
+            if let Some(left) = node.left.as_deref() {
+                check(left, (lower, Some(node.key)));
+            }
+            if let Some(right) = node.right.as_deref() {
+                check(right, (Some(node.key), upper));
+            }
+            assert_eq!(
+                node.size,
+                1 + size(&node.left) + size(&node.right),
+                "size is stale"
+            );
+        }
+        if let Some(root) = self.root.as_deref() {
+            check(root, (None, None));
+        }
+    }
+
+    fn next_priority(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+impl<K: Ord + Copy, V> Default for Treap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    max_key: Option<K>,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if self.max_key.is_some_and(|max_key| node.key > max_key) {
+            self.stack.clear();
+            return None;
+        }
+        self.push_left(node.right.as_deref());
+        Some((node.key, &node.value))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a Treap<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+// 木を分解しながらキー順に返す
+pub struct IntoIter<K, V> {
+    stack: Vec<Box<Node<K, V>>>,
+    len: usize,
+}
+
+impl<K, V> IntoIter<K, V> {
+    fn push_left(&mut self, mut current: Link<K, V>) {
+        while let Some(mut node) = current {
+            current = node.left.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut node = self.stack.pop()?;
+        self.push_left(node.right.take());
+        self.len -= 1;
+        Some((node.key, node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+impl<K, V> IntoIterator for Treap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> IntoIter<K, V> {
+        let mut iter = IntoIter {
+            stack: Vec::new(),
+            len: size(&self.root),
+        };
+        iter.push_left(self.root);
+        iter
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for Treap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut treap = Self::new();
+        for (key, value) in iter {
+            treap.insert(key, value);
+        }
+        treap
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for Treap<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        Treap::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        Treap::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        Treap::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        Treap::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        Treap::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = Treap::new();
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        assert_eq!((t.first_key(), t.last_key()), (Some(10), Some(24)));
+        let all: Vec<_> = t.into_iter().collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let t: Treap<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        let (left, right) = t.split(51);
+        left.check_invariants();
+        right.check_invariants();
+        assert_eq!((left.len(), right.len()), (26, 74));
+        assert_eq!(left.last_key(), Some(50));
+        assert_eq!(right.first_key(), Some(52));
+
+        // 境界のキーは右に入る
+        let (mid, right) = right.split(100);
+        assert_eq!(mid.last_key(), Some(98));
+        assert_eq!(right.first_key(), Some(100));
+
+        // 端で分けると片方が空になる
+        let (empty, right) = right.split(0);
+        assert!(empty.is_empty());
+
+        let mut t = left.merge(mid).merge(empty).merge(right);
+        t.check_invariants();
+        assert_eq!(t.len(), 100);
+        assert!(t.iter().map(|(k, _)| k).eq((0..100).map(|k| k * 2)));
+        t.insert(1, 0);
+        t.check_invariants();
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn merge_rejects_overlap() {
+        let a: Treap<_, _> = (0..10).map(|k| (k, k)).collect();
+        let b: Treap<_, _> = (5..15).map(|k| (k, k)).collect();
+        a.merge(b);
+    }
+
+    #[test]
+    fn height_is_logarithmic() {
+        // 昇順に入れても、形は優先度で決まる
+        let n = 100_000;
+        let t: Treap<_, _> = (0..n).map(|k| (k, k)).collect();
+        t.check_invariants();
+        assert!((t.height() as f64) < 4.0 * (n as f64).log2());
+    }
+
+    proptest! {
+        // split してから merge すると元に戻る
+        #[test]
+        fn split_merge_roundtrip(
+            keys in prop::collection::btree_set(0..1000usize, 0..200),
+            at in 0..1000usize,
+        ) {
+            let treap: Treap<_, _> = keys.iter().map(|&k| (k, k)).collect();
+            let (left, right) = treap.split(at);
+            left.check_invariants();
+            right.check_invariants();
+            prop_assert!(left.iter().all(|(k, _)| k < at));
+            prop_assert!(right.iter().all(|(k, _)| k >= at));
+            let merged = left.merge(right);
+            merged.check_invariants();
+            prop_assert!(merged.iter().map(|(k, _)| k).eq(keys.iter().copied()));
+        }
+    }
+}
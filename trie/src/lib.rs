@@ -0,0 +1,356 @@
+// 文字列をキーにする trie
+// 1 文字を 1 本の辺にし、根からの辺の文字を繋いだものがノードのキーになる。子は文字の順に BTreeMap で持つ
+// 探索や追加はキーの長さ L に対して O(L log σ) で、σ は 1 つのノードの子の数
+//
+// 編集距離 (Levenshtein 距離) での検索
+// word と、根からノードまでの文字列の編集距離の DP の行を、trie を降りながら 1 行ずつ作る
+// 同じ接頭辞を持つキーは行を共有するので、キーごとに DP をやり直さない
+// 行の最小値が k を超えたら、その下のどのキーも距離が k を超えるので降りない
+// word の長さを m とすると、1 ノードあたり O(m) で、k が小さいほど調べるノードは少ない
+use std::{collections::BTreeMap, iter::FromIterator};
+
+#[derive(Debug, Clone)]
+struct Node<V> {
+    value: Option<V>,
+    children: BTreeMap<char, Node<V>>,
+}
+
+impl<V> Node<V> {
+    fn new() -> Self {
+        Self {
+            value: None,
+            children: BTreeMap::new(),
+        }
+    }
+
+    // 取り除いた値と、このノードが空になったか
+    fn remove(&mut self, mut key: std::str::Chars<'_>) -> (Option<V>, bool) {
+        let removed = match key.next() {
+            None => self.value.take(),
+            Some(c) => {
+                let child = match self.children.get_mut(&c) {
+                    Some(child) => child,
+                    None => return (None, false),
+                };
+                let (removed, empty) = child.remove(key);
+                if empty {
+                    self.children.remove(&c);
+                }
+                removed
+            }
+        };
+        (removed, self.value.is_none() && self.children.is_empty())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trie<V> {
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> Trie<V> {
+    pub fn new() -> Self {
+        Self {
+            root: Node::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn node(&self, key: &str) -> Option<&Node<V>> {
+        key.chars()
+            .try_fold(&self.root, |node, c| node.children.get(&c))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.node(key)?.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        key.chars()
+            .try_fold(&mut self.root, |node, c| node.children.get_mut(&c))?
+            .value
+            .as_mut()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: &str, value: V) -> Option<V> {
+        let node = key.chars().fold(&mut self.root, |node, c| {
+            node.children.entry(c).or_insert_with(Node::new)
+        });
+        let old = node.value.replace(value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    // 値のなくなった葉は、親から取り除く
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let (removed, _) = self.root.remove(key.chars());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    // キーの辞書順に (key, value) を返す
+    pub fn iter(&self) -> impl Iterator<Item = (String, &V)> + '_ {
+        Self::walk(String::new(), &self.root)
+    }
+
+    // prefix で始まるキーを辞書順に返す
+    pub fn prefix<'a>(&'a self, prefix: &str) -> impl Iterator<Item = (String, &'a V)> + 'a {
+        let start = self.node(prefix).map(|node| (prefix.to_string(), node));
+        start
+            .into_iter()
+            .flat_map(|(key, node)| Self::walk(key, node))
+    }
+
+    // node の部分木のキーを辞書順に返す。key は node のキー
+    fn walk(key: String, node: &Node<V>) -> impl Iterator<Item = (String, &V)> + '_ {
+        let mut stack = vec![(key, node)];
+        std::iter::from_fn(move || {
+            while let Some((key, node)) = stack.pop() {
+                // 子は後ろから積み、前の文字から取り出す
+                for (&c, child) in node.children.iter().rev() {
+                    let mut child_key = key.clone();
+                    child_key.push(c);
+                    stack.push((child_key, child));
+                }
+                if let Some(value) = &node.value {
+                    return Some((key, value));
+                }
+            }
+            None
+        })
+    }
+
+    // word との編集距離が k 以下のキーを、距離の順、同じ距離ならキーの順に返す
+    pub fn search_within_distance(&self, word: &str, k: usize) -> Vec<(String, &V, usize)> {
+        let word: Vec<char> = word.chars().collect();
+        let mut found = Vec::new();
+        // 行 row[j] は word[..j] とノードのキーの編集距離
+        let row: Vec<usize> = (0..=word.len()).collect();
+        let mut stack = vec![(String::new(), &self.root, row)];
+        while let Some((key, node, row)) = stack.pop() {
+            let distance = row[word.len()];
+            if let Some(value) = &node.value {
+                if distance <= k {
+                    found.push((key.clone(), value, distance));
+                }
+            }
+            for (&c, child) in &node.children {
+                let next = next_row(&word, &row, c);
+                if next.iter().any(|&d| d <= k) {
+                    let mut child_key = key.clone();
+                    child_key.push(c);
+                    stack.push((child_key, child, next));
+                }
+            }
+        }
+        found.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+        found
+    }
+
+    // 入力途中の prefix から補完する。キーのどこかまでの接頭辞と prefix の編集距離が k 以下のキーを返す
+    // 距離はそのうち最も近い接頭辞のもので、距離の順、同じ距離ならキーの順に並べる
+    pub fn complete_within_distance(&self, prefix: &str, k: usize) -> Vec<(String, &V, usize)> {
+        let prefix: Vec<char> = prefix.chars().collect();
+        let mut found = Vec::new();
+        let row: Vec<usize> = (0..=prefix.len()).collect();
+        // best は根からこのノードまでの接頭辞のうち、最も近いものの距離
+        let best = row[prefix.len()];
+        let mut stack = vec![(String::new(), &self.root, row, best)];
+        while let Some((key, node, row, best)) = stack.pop() {
+            // 行の最小値は降りても減らないので、best より近い接頭辞はこの下にない
+            // この下のキーは全て、距離 best で補完できる
+            if best <= k && row.iter().all(|&d| d >= best) {
+                found.extend(Self::walk(key, node).map(|(key, value)| (key, value, best)));
+                continue;
+            }
+            if let Some(value) = &node.value {
+                if best <= k {
+                    found.push((key.clone(), value, best));
+                }
+            }
+            for (&c, child) in &node.children {
+                let next = next_row(&prefix, &row, c);
+                let best = best.min(next[prefix.len()]);
+                if best <= k || next.iter().any(|&d| d <= k) {
+                    let mut child_key = key.clone();
+                    child_key.push(c);
+                    stack.push((child_key, child, next, best));
+                }
+            }
+        }
+        found.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+        found
+    }
+
+    // 葉に値があり、len が値の数と合うか確かめる
+    pub fn check_invariants(&self) {
+        fn count<V>(node: &Node<V>, root: bool) -> usize {
+            assert!(
+                root || node.value.is_some() || !node.children.is_empty(),
+                "empty leaf is left"
+            );
+            node.value.is_some() as usize
+                + node
+                    .children
+                    .values()
+                    .map(|child| count(child, false))
+                    .sum::<usize>()
+        }
+        assert_eq!(count(&self.root, true), self.len, "len is stale");
+    }
+}
+
+// word との編集距離の DP で、row の次に c を足した行
+fn next_row(word: &[char], row: &[usize], c: char) -> Vec<usize> {
+    let mut next = Vec::with_capacity(row.len());
+    next.push(row[0] + 1);
+    for (j, &w) in word.iter().enumerate() {
+        let replace = row[j] + (w != c) as usize;
+        let insert = next[j] + 1;
+        let delete = row[j + 1] + 1;
+        next.push(replace.min(insert).min(delete));
+    }
+    next
+}
+
+impl<V> Default for Trie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AsRef<str>, V> FromIterator<(K, V)> for Trie<V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        for (key, value) in iter {
+            trie.insert(key.as_ref(), value);
+        }
+        trie
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn words() {
+        let mut trie: Trie<usize> = ["car", "cart", "care", "cat", "dog", "かな"]
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (*w, i))
+            .collect();
+        trie.check_invariants();
+        assert_eq!(trie.get("cart"), Some(&1));
+        assert_eq!(trie.get("ca"), None);
+        let keys: Vec<_> = trie.prefix("car").map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["car", "care", "cart"]);
+
+        let near: Vec<_> = trie
+            .search_within_distance("dig", 1)
+            .into_iter()
+            .map(|(k, _, d)| (k, d))
+            .collect();
+        assert_eq!(near, vec![("dog".to_string(), 1)]);
+        let near: Vec<_> = trie
+            .search_within_distance("cat", 1)
+            .into_iter()
+            .map(|(k, _, d)| (k, d))
+            .collect();
+        assert_eq!(
+            near,
+            vec![
+                ("cat".to_string(), 0),
+                ("car".to_string(), 1),
+                ("cart".to_string(), 1)
+            ]
+        );
+        assert_eq!(trie.search_within_distance("かさ", 1).len(), 1);
+
+        // "cae" は "ca" と距離 1 なので、ca で始まるキーを全て補完する
+        let completed: Vec<_> = trie
+            .complete_within_distance("cae", 1)
+            .into_iter()
+            .map(|(k, _, _)| k)
+            .collect();
+        assert_eq!(completed, vec!["car", "care", "cart", "cat"]);
+
+        assert_eq!(trie.remove("cart"), Some(1));
+        assert_eq!(trie.remove("cart"), None);
+        assert_eq!(trie.remove("dog"), Some(4));
+        trie.check_invariants();
+        assert_eq!(trie.len(), 4);
+    }
+
+    fn levenshtein(a: &[char], b: &[char]) -> usize {
+        let mut row: Vec<usize> = (0..=a.len()).collect();
+        for &c in b {
+            row = next_row(a, &row, c);
+        }
+        row[a.len()]
+    }
+
+    proptest! {
+        // 全てのキーとの距離を求めたものと同じになる
+        #[test]
+        fn matches_naive(
+            keys in prop::collection::vec("[abc]{0,6}", 0..40),
+            removes in prop::collection::vec("[abc]{0,6}", 0..10),
+            word in "[abc]{0,5}",
+            k in 0..3usize,
+        ) {
+            let mut trie = Trie::new();
+            let mut expected = BTreeMap::new();
+            for (i, key) in keys.iter().enumerate() {
+                prop_assert_eq!(trie.insert(key, i), expected.insert(key.clone(), i));
+            }
+            for key in &removes {
+                prop_assert_eq!(trie.remove(key), expected.remove(key));
+            }
+            trie.check_invariants();
+            prop_assert!(trie.iter().map(|(k, v)| (k, *v)).eq(expected.iter().map(|(k, v)| (k.clone(), *v))));
+
+            let chars: Vec<char> = word.chars().collect();
+            let mut near: Vec<_> = expected
+                .iter()
+                .map(|(key, v)| (key.clone(), v, levenshtein(&chars, &key.chars().collect::<Vec<_>>())))
+                .filter(|(_, _, d)| *d <= k)
+                .collect();
+            near.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+            prop_assert_eq!(trie.search_within_distance(&word, k), near);
+
+            let mut completed: Vec<_> = expected
+                .iter()
+                .map(|(key, v)| {
+                    let key_chars: Vec<char> = key.chars().collect();
+                    let d = (0..=key_chars.len()).map(|i| levenshtein(&chars, &key_chars[..i])).min().unwrap();
+                    (key.clone(), v, d)
+                })
+                .filter(|(_, _, d)| *d <= k)
+                .collect();
+            completed.sort_by(|a, b| (a.2, &a.0).cmp(&(b.2, &b.0)));
+            prop_assert_eq!(trie.complete_within_distance(&word, k), completed);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Union-Find (disjoint set union)
+// 要素 0..n を互いに素な集合に分け、各集合を根を持つ木で表す。find は根を返し、union は根どうしを繋ぐ
+//
+// union は rank (木の高さの上界) の低い方の根を高い方の根の下に繋ぐ (union by rank)
+// find は辿った要素を全て根の直下に付け替える (path compression)
+// 両方を使うと、m 回の操作がほぼ O(m α(n)) になる (α は逆アッカーマン関数)
+//
+// rollback に、path compression を使わず、union を巻き戻せる版を置く
+use std::fmt;
+
+pub mod rollback;
+
+#[derive(Clone)]
+pub struct UnionFind {
+    // 根は自分自身を指す
+    parent: Vec<usize>,
+    // 根だけが意味を持つ
+    rank: Vec<u8>,
+    size: Vec<usize>,
+    // 集合の数
+    sets: usize,
+}
+
+impl UnionFind {
+    // 0..len の要素を、それぞれ 1 つだけの集合にして作る
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            rank: vec![0; len],
+            size: vec![1; len],
+            sets: len,
+        }
+    }
+
+    // 要素の数
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+    // 集合の数
+    pub fn sets(&self) -> usize {
+        self.sets
+    }
+
+    // 1 つだけの集合を足し、その要素を返す
+    pub fn push(&mut self) -> usize {
+        let x = self.len();
+        self.parent.push(x);
+        self.rank.push(0);
+        self.size.push(1);
+        self.sets += 1;
+        x
+    }
+
+    // x を含む集合の根
+    pub fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // 辿った要素を根の直下に付け替える
+        let mut x = x;
+        while self.parent[x] != root {
+            let next = self.parent[x];
+            self.parent[x] = root;
+            x = next;
+        }
+        root
+    }
+
+    // a と b の集合を 1 つにする。もともと同じ集合なら false
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        let (child, root) = if self.rank[a] < self.rank[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        self.parent[child] = root;
+        self.size[root] += self.size[child];
+        if self.rank[child] == self.rank[root] {
+            self.rank[root] += 1;
+        }
+        self.sets -= 1;
+        true
+    }
+
+    pub fn same_set(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+
+    // x を含む集合の要素の数
+    pub fn set_size(&mut self, x: usize) -> usize {
+        let root = self.find(x);
+        self.size[root]
+    }
+
+    // 各集合の要素を小さい順に並べたもの。集合は一番小さい要素の順
+    pub fn components(&mut self) -> impl Iterator<Item = Vec<usize>> {
+        let mut index = vec![usize::MAX; self.len()];
+        let mut components: Vec<Vec<usize>> = Vec::with_capacity(self.sets);
+        for x in 0..self.len() {
+            let root = self.find(x);
+            if index[root] == usize::MAX {
+                index[root] = components.len();
+                components.push(Vec::with_capacity(self.size[root]));
+            }
+            components[index[root]].push(x);
+        }
+        components.into_iter()
+    }
+
+    // 親が範囲内で、根の size と sets が実際の集合と合うか確かめる
+    pub fn check_invariants(&self) {
+        let root = |mut x: usize| {
+            while self.parent[x] != x {
+                x = self.parent[x];
+            }
+            x
+        };
+        let mut sizes = vec![0; self.len()];
+        for x in 0..self.len() {
+            assert!(self.parent[x] < self.len(), "parent is out of range");
+            if self.parent[x] != x {
+                assert!(
+                    self.rank[self.parent[x]] > self.rank[x],
+                    "rank does not increase toward the root"
+                );
+            }
+            sizes[root(x)] += 1;
+        }
+        let roots: Vec<_> = (0..self.len()).filter(|&x| self.parent[x] == x).collect();
+        assert_eq!(roots.len(), self.sets, "set count is stale");
+        for x in roots {
+            assert_eq!(self.size[x], sizes[x], "set size is stale");
+        }
+    }
+}
+
+impl fmt::Debug for UnionFind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut copy = self.clone();
+        f.debug_set().entries(copy.components()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn union_and_find() {
+        let mut uf = UnionFind::new(8);
+        assert!(uf.union(0, 1));
+        assert!(uf.union(2, 3));
+        assert!(uf.union(1, 3));
+        assert!(!uf.union(0, 2));
+        assert!(uf.same_set(0, 3));
+        assert!(!uf.same_set(0, 4));
+        assert_eq!(uf.set_size(2), 4);
+        assert_eq!(uf.sets(), 5);
+        let x = uf.push();
+        uf.union(x, 7);
+        assert_eq!(
+            uf.components().collect::<Vec<_>>(),
+            vec![vec![0, 1, 2, 3], vec![4], vec![5], vec![6], vec![7, 8]]
+        );
+        assert_eq!(format!("{:?}", uf), "{[0, 1, 2, 3], [4], [5], [6], [7, 8]}");
+        uf.check_invariants();
+    }
+
+    // 各要素に集合の番号を振っておき、union で片方の番号を全て書き換える素朴な実装
+    fn label(labels: &mut [usize], a: usize, b: usize) {
+        let (from, to) = (labels[b], labels[a]);
+        labels
+            .iter_mut()
+            .filter(|l| **l == from)
+            .for_each(|l| *l = to);
+    }
+
+    proptest! {
+        // 同じ集合かどうかと集合の大きさが、素朴な実装と同じになる
+        #[test]
+        fn matches_labels(
+            (len, ops) in (1..40usize).prop_flat_map(|len| {
+                (Just(len), prop::collection::vec((0..len, 0..len), 0..60))
+            })
+        ) {
+            let mut uf = UnionFind::new(len);
+            let mut labels: Vec<usize> = (0..len).collect();
+            for (a, b) in ops {
+                let merged = labels[a] != labels[b];
+                prop_assert_eq!(uf.union(a, b), merged);
+                label(&mut labels, a, b);
+                uf.check_invariants();
+            }
+            for a in 0..len {
+                let size = labels.iter().filter(|&&l| l == labels[a]).count();
+                prop_assert_eq!(uf.set_size(a), size);
+                for b in 0..len {
+                    prop_assert_eq!(uf.same_set(a, b), labels[a] == labels[b]);
+                }
+            }
+            prop_assert_eq!(uf.components().map(|c| c.len()).sum::<usize>(), len);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// 巻き戻せる Union-Find
+// path compression をすると 1 回の find で多くの親が変わるので、union by rank だけを使う
+// すると union で変わるのは、繋いだ根の親と、繋いだ先の rank と size だけになるので、それを積んでおけば戻せる
+// find は O(log n) で、自分を変えない
+//
+// snapshot はいまの変更の数を覚え、rollback はそこまで変更を逆順に戻す
+// 辺を足しては戻す、オフラインの動的連結性 (セグメント木の上の分割統治) のような使い方をする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot(usize);
+
+// union で child を root の下に繋いだ
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    child: usize,
+    root: usize,
+    rank_increased: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RollbackUnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+    size: Vec<usize>,
+    sets: usize,
+    history: Vec<Change>,
+}
+
+impl RollbackUnionFind {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len).collect(),
+            rank: vec![0; len],
+            size: vec![1; len],
+            sets: len,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+    pub fn sets(&self) -> usize {
+        self.sets
+    }
+
+    // 親を付け替えないので &self で辿れる
+    pub fn find(&self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            x = self.parent[x];
+        }
+        x
+    }
+
+    // もともと同じ集合なら false で、何も積まない
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        let (child, root) = if self.rank[a] < self.rank[b] {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let rank_increased = self.rank[child] == self.rank[root];
+        self.parent[child] = root;
+        self.size[root] += self.size[child];
+        if rank_increased {
+            self.rank[root] += 1;
+        }
+        self.sets -= 1;
+        self.history.push(Change {
+            child,
+            root,
+            rank_increased,
+        });
+        true
+    }
+
+    pub fn same_set(&self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+
+    pub fn set_size(&self, x: usize) -> usize {
+        self.size[self.find(x)]
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot(self.history.len())
+    }
+
+    // 最後の union を戻す。戻すものがなければ false
+    pub fn undo(&mut self) -> bool {
+        let change = match self.history.pop() {
+            Some(change) => change,
+            None => return false,
+        };
+        self.parent[change.child] = change.child;
+        self.size[change.root] -= self.size[change.child];
+        if change.rank_increased {
+            self.rank[change.root] -= 1;
+        }
+        self.sets += 1;
+        true
+    }
+
+    // snapshot を取った時点に戻す。それより前に戻した snapshot は使えない
+    pub fn rollback(&mut self, snapshot: Snapshot) {
+        assert!(
+            snapshot.0 <= self.history.len(),
+            "snapshot {} is newer than the history {}",
+            snapshot.0,
+            self.history.len()
+        );
+        while self.history.len() > snapshot.0 {
+            self.undo();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn rollback() {
+        let mut uf = RollbackUnionFind::new(6);
+        uf.union(0, 1);
+        let before = uf.snapshot();
+        uf.union(1, 2);
+        uf.union(3, 4);
+        assert!(!uf.union(0, 2));
+        assert_eq!(uf.set_size(2), 3);
+        assert_eq!(uf.sets(), 3);
+        uf.rollback(before);
+        assert!(uf.same_set(0, 1));
+        assert!(!uf.same_set(1, 2));
+        assert!(!uf.same_set(3, 4));
+        assert_eq!(uf.sets(), 5);
+        assert!(uf.undo());
+        assert!(!uf.undo());
+        assert_eq!(uf.sets(), 6);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Union(usize, usize),
+        Snapshot,
+        Rollback,
+    }
+
+    proptest! {
+        // 各時点の集合の番号を積んでおく素朴な実装と、同じ集合かどうかが同じになる
+        #[test]
+        fn matches_saved_labels(
+            (len, ops) in (1..20usize).prop_flat_map(|len| {
+                let op = prop_oneof![
+                    3 => (0..len, 0..len).prop_map(|(a, b)| Op::Union(a, b)),
+                    1 => Just(Op::Snapshot),
+                    1 => Just(Op::Rollback),
+                ];
+                (Just(len), prop::collection::vec(op, 0..60))
+            })
+        ) {
+            let mut uf = RollbackUnionFind::new(len);
+            let mut labels: Vec<usize> = (0..len).collect();
+            let mut saved = Vec::new();
+            for op in ops {
+                match op {
+                    Op::Union(a, b) => {
+                        prop_assert_eq!(uf.union(a, b), labels[a] != labels[b]);
+                        let (from, to) = (labels[b], labels[a]);
+                        labels.iter_mut().filter(|l| **l == from).for_each(|l| *l = to);
+                    }
+                    Op::Snapshot => saved.push((uf.snapshot(), labels.clone())),
+                    Op::Rollback => {
+                        if let Some((snapshot, old)) = saved.pop() {
+                            uf.rollback(snapshot);
+                            labels = old;
+                        }
+                    }
+                }
+                for a in 0..len {
+                    for b in 0..len {
+                        prop_assert_eq!(uf.same_set(a, b), labels[a] == labels[b]);
+                    }
+                    let size = labels.iter().filter(|&&l| l == labels[a]).count();
+                    prop_assert_eq!(uf.set_size(a), size);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// fuzz などで使う、任意のバイト列から作った木
+use std::ops::RangeInclusive;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{ArenaBPlusTree, BPlusTree, Data, Key, OrderedMap};
+
+// 小さい cap ほど分割が起きやすい
+const CAPS: RangeInclusive<usize> = 2..=64;
+
+// 同じキーは後の値で置き換える
+impl<'a> Arbitrary<'a> for BPlusTree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut tree = Self::new(u.int_in_range(CAPS)?);
+        for entry in u.arbitrary_iter::<(Key, Data)>()? {
+            let (key, value) = entry?;
+            OrderedMap::insert(&mut tree, key, value);
+        }
+        Ok(tree)
+    }
+}
+
+impl<'a> Arbitrary<'a> for ArenaBPlusTree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut tree = Self::new(u.int_in_range(CAPS)?);
+        for entry in u.arbitrary_iter::<(Key, Data)>()? {
+            let (key, value) = entry?;
+            OrderedMap::insert(&mut tree, key, value);
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn arbitrary_tree() {
+        let bytes: Vec<u8> = (0..4096).map(|i| (i * 31 % 251) as u8).collect();
+        let mut u = Unstructured::new(&bytes);
+        let tree = BPlusTree::arbitrary(&mut u).unwrap();
+        tree.check_invariants();
+        assert!(!tree.is_empty());
+        assert!(CAPS.contains(&tree.cap));
+
+        let mut u = Unstructured::new(&bytes);
+        let (mut tree, ops) = <(BPlusTree, Vec<crate::Op<Key, Data>>)>::arbitrary(&mut u).unwrap();
+        let mut expected: BTreeMap<_, _> = tree.iter().map(|(k, v)| (k, *v)).collect();
+        for op in ops {
+            assert_eq!(op.clone().apply(&mut tree), op.apply(&mut expected));
+        }
+        tree.check_invariants();
+    }
+}
@@ -0,0 +1,534 @@
+// leaf の next を生ポインタではなく、leaves の中の位置で持つ B+tree
+// unsafe を使わずに、search_range では leaf から leaf へ O(1) で移れる
+// leaf は leaves に置いたまま動かさない。削除で空になった leaf もそのまま残す
+#![forbid(unsafe_code)]
+
+use std::{iter::FusedIterator, slice};
+
+use crate::{
+    candidates, index_for_insert, Data, DataPair, Error, Key, OrderedMap, Pair, Result, MIN_CAP,
+};
+
+// leaves の中の位置
+type LeafId = usize;
+
+#[derive(Debug)]
+pub struct ArenaBPlusTree {
+    node: Option<Node>,
+    leaves: Leaves,
+    // エントリの数
+    len: usize,
+}
+
+#[derive(Debug)]
+enum Node {
+    Internal(InternalNode),
+    Leaf(LeafId),
+}
+
+#[derive(Debug)]
+struct InternalNode {
+    nodes: Vec<Pair<Node>>,
+}
+
+#[derive(Debug)]
+struct LeafNode {
+    data: Vec<DataPair>,
+    // 右隣の leaf
+    next: Option<LeafId>,
+}
+
+// 全ての leaf を持つ。ノードは LeafId で参照する
+#[derive(Debug)]
+struct Leaves {
+    cap: usize,
+    leaves: Vec<LeafNode>,
+}
+
+impl ArenaBPlusTree {
+    // cap が MIN_CAP より小さいと panic する
+    pub fn new(cap: usize) -> Self {
+        match Self::try_new(cap) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(cap: usize) -> Result<Self> {
+        if cap < MIN_CAP {
+            return Err(Error::InvalidCapacity { cap });
+        }
+        Ok(Self {
+            node: None,
+            leaves: Leaves {
+                cap,
+                leaves: Vec::new(),
+            },
+            len: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, key: Key, data: Data) {
+        self.len += 1;
+        let node = match &mut self.node {
+            Some(node) => node,
+            None => {
+                let id = self.leaves.push(vec![DataPair::new(key, data)], None);
+                self.node = Some(Node::Leaf(id));
+                return;
+            }
+        };
+        if let Some(right) = node.insert(&mut self.leaves, key, data) {
+            let left = self.node.take().unwrap();
+            let new_child = InternalNode {
+                nodes: vec![
+                    Pair::new(left.min_key(&self.leaves).unwrap(), left),
+                    Pair::new(right.min_key(&self.leaves).unwrap(), right),
+                ],
+            };
+            self.node = Some(Node::Internal(new_child));
+        }
+    }
+
+    // insert と違い、既にキーがあれば挿入しない
+    pub fn try_insert(&mut self, key: Key, data: Data) -> Result<()> {
+        if self.search(key).is_some() {
+            return Err(Error::DuplicateKey { key });
+        }
+        self.insert(key, data);
+        Ok(())
+    }
+
+    pub fn search(&self, key: Key) -> Option<&Data> {
+        let (id, index) = self.find(key)?;
+        Some(&self.leaves.leaves[id].data[index].value)
+    }
+
+    // 最初の候補の leaf から next で右に辿る
+    pub fn search_range(&self, min_key: Key, max_key: Key) -> Vec<&Data> {
+        let mut result = Vec::new();
+        if min_key > max_key {
+            return result;
+        }
+        let mut leaf_id = self.node.as_ref().map(|n| n.first_leaf(min_key));
+        while let Some(id) = leaf_id {
+            let leaf = &self.leaves.leaves[id];
+            for pair in &leaf.data {
+                if pair.key > max_key {
+                    return result;
+                }
+                if pair.key >= min_key {
+                    result.push(&pair.value);
+                }
+            }
+            leaf_id = leaf.next;
+        }
+        result
+    }
+
+    // 同じキーが複数ある場合は一つだけ削除する
+    pub fn remove(&mut self, key: Key) -> Option<Data> {
+        let (id, index) = self.find(key)?;
+        self.len -= 1;
+        Some(self.leaves.leaves[id].data.remove(index).value)
+    }
+
+    // キー順に並べた (key, value)
+    pub fn entries(&self) -> Vec<(Key, &Data)> {
+        self.iter().collect()
+    }
+
+    // キー順に (key, value) を返す
+    // next は使わずに木を辿る
+    pub fn iter(&self) -> ArenaIter<'_> {
+        ArenaIter {
+            leaves: &self.leaves.leaves,
+            front: self.node.iter().collect(),
+            back: self.node.iter().collect(),
+            front_leaf: [].iter(),
+            back_leaf: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    // key が入っている leaf と、leaf の中の位置
+    fn find(&self, key: Key) -> Option<(LeafId, usize)> {
+        self.node.as_ref()?.find(&self.leaves, key)
+    }
+}
+
+impl Node {
+    #[must_use = "insertion may fail"]
+    fn insert(&mut self, leaves: &mut Leaves, key: Key, data: Data) -> Option<Node> {
+        match self {
+            Node::Internal(internal) => internal.insert(leaves, key, data),
+            Node::Leaf(id) => leaves.insert(*id, key, data).map(Node::Leaf),
+        }
+    }
+
+    fn find(&self, leaves: &Leaves, key: Key) -> Option<(LeafId, usize)> {
+        match self {
+            Node::Internal(internal) => candidates(&internal.nodes, key)
+                .find_map(|index| internal.nodes[index].value.find(leaves, key)),
+            Node::Leaf(id) => leaves.leaves[*id]
+                .data
+                .iter()
+                .position(|p| p.key == key)
+                .map(|index| (*id, index)),
+        }
+    }
+
+    // min_key 以上のキーが入っている可能性のある、最も左の leaf
+    fn first_leaf(&self, min_key: Key) -> LeafId {
+        match self {
+            Node::Internal(internal) => internal.nodes[candidates(&internal.nodes, min_key).start]
+                .value
+                .first_leaf(min_key),
+            Node::Leaf(id) => *id,
+        }
+    }
+
+    fn min_key(&self, leaves: &Leaves) -> Option<Key> {
+        match self {
+            Node::Internal(internal) => internal.nodes.first().map(|p| p.key),
+            Node::Leaf(id) => leaves.leaves[*id].data.first().map(|p| p.key),
+        }
+    }
+}
+
+impl InternalNode {
+    // 子を取り除くことは無いので、nodes は空にならない
+    fn insert(&mut self, leaves: &mut Leaves, key: Key, data: Data) -> Option<Node> {
+        let index = index_for_insert(&self.nodes, key);
+        let node = &mut self.nodes[index];
+        // 最小値より小さいキーは先頭の子に入る。pair.key は子のキーの下限にしておく
+        if key < node.key {
+            node.key = key;
+        }
+        if let Some(n) = node.value.insert(leaves, key, data) {
+            if let Some(k) = n.min_key(leaves) {
+                // 同じキーの子の間で順序が入れ替わらないよう、分割した子のすぐ右に入れる
+                self.nodes.insert(index + 1, Pair::new(k, n));
+            }
+        }
+        // 暗黙的に最小値のキー分保持しているので、cap より一つ多くまで持てる
+        if self.nodes.len() > leaves.cap + 1 {
+            let right = self.nodes.split_off(self.nodes.len() / 2);
+            return Some(Node::Internal(InternalNode { nodes: right }));
+        }
+        None
+    }
+}
+
+impl Leaves {
+    fn push(&mut self, data: Vec<DataPair>, next: Option<LeafId>) -> LeafId {
+        self.leaves.push(LeafNode { data, next });
+        self.leaves.len() - 1
+    }
+
+    // 分割した場合は右側の leaf を返す
+    fn insert(&mut self, id: LeafId, key: Key, data: Data) -> Option<LeafId> {
+        let right_id = self.leaves.len();
+        let leaf = &mut self.leaves[id];
+        // 同じキーは後ろに入れる
+        let index = leaf.data.partition_point(|p| p.key <= key);
+        leaf.data.insert(index, DataPair::new(key, data));
+        if leaf.data.len() <= self.cap {
+            return None;
+        }
+        // 以下のようになるので、leaf.next を引き継ぐ
+        //   before split: leaf->other
+        //   after  split: leaf->right->other
+        let right = leaf.data.split_off(leaf.data.len() / 2);
+        let next = leaf.next.replace(right_id);
+        Some(self.push(right, next))
+    }
+}
+
+// 前からと後ろからはそれぞれ別に辿り、残りの数で重ならないようにする
+pub struct ArenaIter<'a> {
+    leaves: &'a [LeafNode],
+    // まだ辿っていないノード。末尾から取り出す
+    front: Vec<&'a Node>,
+    back: Vec<&'a Node>,
+    front_leaf: slice::Iter<'a, DataPair>,
+    back_leaf: slice::Iter<'a, DataPair>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for ArenaIter<'a> {
+    type Item = (Key, &'a Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.front_leaf.next() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.front.pop()? {
+                Node::Internal(internal) => self
+                    .front
+                    .extend(internal.nodes.iter().rev().map(|p| &p.value)),
+                Node::Leaf(id) => self.front_leaf = self.leaves[*id].data.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> DoubleEndedIterator for ArenaIter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.back_leaf.next_back() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.back.pop()? {
+                Node::Internal(internal) => {
+                    self.back.extend(internal.nodes.iter().map(|p| &p.value))
+                }
+                Node::Leaf(id) => self.back_leaf = self.leaves[*id].data.iter(),
+            }
+        }
+    }
+}
+
+impl<'a> ExactSizeIterator for ArenaIter<'a> {}
+
+impl<'a> FusedIterator for ArenaIter<'a> {}
+
+impl<'a> IntoIterator for &'a ArenaBPlusTree {
+    type Item = (Key, &'a Data);
+    type IntoIter = ArenaIter<'a>;
+
+    fn into_iter(self) -> ArenaIter<'a> {
+        self.iter()
+    }
+}
+
+impl OrderedMap<Key, Data> for ArenaBPlusTree {
+    type Iter<'a> = ArenaIter<'a>;
+
+    // ArenaBPlusTree::insert と違い、同じキーがあれば値を置き換える
+    fn insert(&mut self, key: Key, value: Data) -> Option<Data> {
+        if let Some((id, index)) = self.find(key) {
+            let data = &mut self.leaves.leaves[id].data[index].value;
+            return Some(std::mem::replace(data, value));
+        }
+        ArenaBPlusTree::insert(self, key, value);
+        None
+    }
+
+    fn get(&self, key: Key) -> Option<&Data> {
+        self.search(key)
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Data> {
+        ArenaBPlusTree::remove(self, key)
+    }
+
+    fn iter(&self) -> ArenaIter<'_> {
+        ArenaBPlusTree::iter(self)
+    }
+}
+
+impl ArenaBPlusTree {
+    // 壊れていれば panic する。テストや fuzz から呼ぶ
+    //   - 全ての leaf が同じ深さにある
+    //   - ノードの大きさが cap に収まっている。削除で空になった leaf は残ってよい
+    //   - pair.key は子のキーの下限で、次の pair.key 以下に子のキーが収まっている
+    //   - leaves の全ての leaf を一度ずつ参照していて、先頭の leaf から next を辿るとキー順に通る
+    pub fn check_invariants(&self) {
+        let mut order = Vec::new();
+        let mut leaf_depth = None;
+        if let Some(node) = &self.node {
+            self.check_node(node, 0, (Key::MIN, Key::MAX), &mut leaf_depth, &mut order);
+        }
+        let count: usize = order
+            .iter()
+            .map(|id| self.leaves.leaves[*id].data.len())
+            .sum();
+        assert_eq!(count, self.len, "len does not match the leaves");
+        assert_eq!(
+            order.len(),
+            self.leaves.leaves.len(),
+            "some leaves are not reachable"
+        );
+        for (i, id) in order.iter().enumerate() {
+            assert_eq!(
+                self.leaves.leaves[*id].next,
+                order.get(i + 1).copied(),
+                "leaf {} is not linked to the next leaf",
+                id
+            );
+        }
+    }
+
+    fn check_node(
+        &self,
+        node: &Node,
+        depth: usize,
+        (lower, upper): (Key, Key),
+        leaf_depth: &mut Option<usize>,
+        order: &mut Vec<LeafId>,
+    ) {
+        match node {
+            Node::Internal(internal) => {
+                assert!(!internal.nodes.is_empty(), "empty internal node");
+                assert!(
+                    internal.nodes.len() <= self.leaves.cap + 1,
+                    "internal node has {} children",
+                    internal.nodes.len()
+                );
+                for (i, pair) in internal.nodes.iter().enumerate() {
+                    let next = internal.nodes.get(i + 1).map_or(upper, |p| p.key);
+                    assert!(
+                        lower <= pair.key && pair.key <= next,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    assert!(
+                        pair.value
+                            .min_key(&self.leaves)
+                            .is_none_or(|min| pair.key <= min),
+                        "key {} is not a lower bound of its child",
+                        pair.key
+                    );
+                    self.check_node(&pair.value, depth + 1, (pair.key, next), leaf_depth, order);
+                }
+            }
+            Node::Leaf(id) => {
+                assert_eq!(
+                    *leaf_depth.get_or_insert(depth),
+                    depth,
+                    "leaves are at different depths"
+                );
+                assert!(!order.contains(id), "leaf {} is reachable twice", id);
+                let leaf = &self.leaves.leaves[*id];
+                assert!(
+                    leaf.data.len() <= self.leaves.cap,
+                    "leaf has {} entries",
+                    leaf.data.len()
+                );
+                let mut last = lower;
+                for pair in &leaf.data {
+                    assert!(
+                        last <= pair.key && pair.key <= upper,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    last = pair.key;
+                }
+                order.push(*id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn search_range_across_nodes() {
+        let mut b = ArenaBPlusTree::new(3);
+        for k in (0..100).rev() {
+            b.insert(k, k);
+        }
+        b.check_invariants();
+        let all: Vec<_> = (0..100).collect();
+        assert_eq!(b.search_range(0, 99), all.iter().collect::<Vec<_>>());
+        assert_eq!(
+            b.search_range(5, 60),
+            all[5..=60].iter().collect::<Vec<_>>()
+        );
+        assert_eq!(b.search_range(60, 5), Vec::<&Data>::new());
+        b.remove(30);
+        assert_eq!(b.search_range(29, 31), vec![&29, &31]);
+        assert_eq!(b.search(30), None);
+        assert_eq!(b.len(), 99);
+        assert!((&b)
+            .into_iter()
+            .rev()
+            .map(|(k, _)| k)
+            .eq((0..100).rev().filter(|k| *k != 30)));
+    }
+
+    #[test]
+    fn try_new_and_try_insert() {
+        assert_eq!(
+            ArenaBPlusTree::try_new(0).err(),
+            Some(Error::InvalidCapacity { cap: 0 })
+        );
+        let mut b = ArenaBPlusTree::try_new(MIN_CAP).unwrap();
+        b.try_insert(1, 1).unwrap();
+        assert_eq!(b.try_insert(1, 2), Err(Error::DuplicateKey { key: 1 }));
+        assert_eq!(b.search(1), Some(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "not linked")]
+    fn detects_broken_link() {
+        let mut b = ArenaBPlusTree::new(3);
+        for k in 0..4 {
+            b.insert(k, k);
+        }
+        b.leaves.leaves[0].next = None;
+        b.check_invariants();
+    }
+
+    proptest! {
+        // BPlusTree と同じ操作列を与え、全ての結果を比べる
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn same_as_btree_map_random(
+            cap in 2..64usize,
+            ops in prop::collection::vec((0..4u8, 0..200usize, 0..200usize), 0..400),
+        ) {
+            let mut tree = ArenaBPlusTree::new(cap);
+            let mut expected = BTreeMap::new();
+            for (op, a, b) in ops {
+                match op {
+                    0 | 1 => prop_assert_eq!(
+                        OrderedMap::insert(&mut tree, a, b),
+                        expected.insert(a, b)
+                    ),
+                    2 => prop_assert_eq!(tree.remove(a), expected.remove(&a)),
+                    _ => {
+                        let values: Vec<_> = OrderedMap::range(&expected, a, b)
+                            .into_iter()
+                            .map(|(_, v)| v)
+                            .collect();
+                        prop_assert_eq!(tree.search_range(a, b), values);
+                        prop_assert_eq!(tree.search(a), expected.get(&a));
+                    }
+                }
+                tree.check_invariants();
+                prop_assert_eq!(tree.len(), expected.len());
+            }
+            prop_assert!(tree.iter().eq(OrderedMap::iter(&expected)));
+            prop_assert!(tree.iter().rev().eq(OrderedMap::iter(&expected).rev()));
+        }
+    }
+}
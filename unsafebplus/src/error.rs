@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+use crate::Key;
+
+// 0 だと leaf を分割したときに空の leaf ができてしまう
+pub const MIN_CAP: usize = 1;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("invalid capacity: {cap} (must be at least {MIN_CAP})")]
+    InvalidCapacity { cap: usize },
+    #[error("duplicate key: {key}")]
+    DuplicateKey { key: Key },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
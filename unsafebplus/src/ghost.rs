@@ -0,0 +1,498 @@
+// GhostCell を使った B+tree (実験的)
+// leaf を Rc<GhostCell> で持ち、next も同じ Rc で繋ぐ。生ポインタも RefCell の実行時チェックも使わない
+// GhostCell は lifetime 'id で GhostToken と結びつき、中身の借用は token の借用で決まる
+//   &GhostToken<'id>     があれば、同じ 'id の全ての cell を共有参照で読める
+//   &mut GhostToken<'id> があれば、同じ 'id の cell を一つずつ可変で借用できる
+// 'id は GhostToken::scope に渡すクロージャの中だけで作られるので、他の token で開けることはない
+//
+//   GhostToken::scope(|token| {
+//       let mut tree = GhostBPlusTree::new(token, 3);
+//       tree.insert(1, 10);
+//       tree.search_range(0, 5);
+//   });
+use std::{cell::UnsafeCell, iter::FusedIterator, marker::PhantomData, rc::Rc, slice};
+
+use crate::{
+    candidates, index_for_insert, Data, DataPair, Error, Key, OrderedMap, Pair, Result, MIN_CAP,
+};
+
+// 'id を不変にして、別の 'id に変換できないようにする
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+pub struct GhostToken<'id> {
+    _brand: Brand<'id>,
+}
+
+impl<'id> GhostToken<'id> {
+    // 'id ごとに token は一つだけ作られる
+    pub fn scope<R>(f: impl for<'new> FnOnce(GhostToken<'new>) -> R) -> R {
+        f(GhostToken {
+            _brand: PhantomData,
+        })
+    }
+}
+
+pub struct GhostCell<'id, T: ?Sized> {
+    _brand: Brand<'id>,
+    value: UnsafeCell<T>,
+}
+
+impl<'id, T> GhostCell<'id, T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            _brand: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<'id, T: ?Sized> GhostCell<'id, T> {
+    pub fn borrow<'a>(&'a self, _token: &'a GhostToken<'id>) -> &'a T {
+        // SAFETY: 'id の token は一つだけなので、token を共有参照で借用している間は
+        // 同じ 'id のどの cell も borrow_mut されていない
+        unsafe { &*self.value.get() }
+    }
+
+    pub fn borrow_mut<'a>(&'a self, _token: &'a mut GhostToken<'id>) -> &'a mut T {
+        // SAFETY: token を可変で借用している間は、同じ 'id の他の borrow も borrow_mut もできない
+        unsafe { &mut *self.value.get() }
+    }
+}
+
+// leaf は親の InternalNode と左隣の leaf の next から共有される
+type LeafRef<'id> = Rc<GhostCell<'id, LeafNode<'id>>>;
+
+struct LeafNode<'id> {
+    data: Vec<DataPair>,
+    // 右隣の leaf
+    next: Option<LeafRef<'id>>,
+}
+
+enum Node<'id> {
+    Internal(InternalNode<'id>),
+    Leaf(LeafRef<'id>),
+}
+
+struct InternalNode<'id> {
+    nodes: Vec<Pair<Node<'id>>>,
+}
+
+// token を持つので、木の外から leaf を開くことはできない
+pub struct GhostBPlusTree<'id> {
+    cap: usize,
+    node: Option<Node<'id>>,
+    token: GhostToken<'id>,
+    // エントリの数
+    len: usize,
+}
+
+impl<'id> GhostBPlusTree<'id> {
+    // cap が MIN_CAP より小さいと panic する
+    pub fn new(token: GhostToken<'id>, cap: usize) -> Self {
+        match Self::try_new(token, cap) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(token: GhostToken<'id>, cap: usize) -> Result<Self> {
+        if cap < MIN_CAP {
+            return Err(Error::InvalidCapacity { cap });
+        }
+        Ok(Self {
+            cap,
+            node: None,
+            token,
+            len: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, key: Key, data: Data) {
+        self.len += 1;
+        let node = match &mut self.node {
+            Some(node) => node,
+            None => {
+                let leaf = LeafNode {
+                    data: vec![DataPair::new(key, data)],
+                    next: None,
+                };
+                self.node = Some(Node::Leaf(Rc::new(GhostCell::new(leaf))));
+                return;
+            }
+        };
+        if let Some(right) = node.insert(&mut self.token, self.cap, key, data) {
+            let left = self.node.take().unwrap();
+            let new_child = InternalNode {
+                nodes: vec![
+                    Pair::new(left.min_key(&self.token).unwrap(), left),
+                    Pair::new(right.min_key(&self.token).unwrap(), right),
+                ],
+            };
+            self.node = Some(Node::Internal(new_child));
+        }
+    }
+
+    // insert と違い、既にキーがあれば挿入しない
+    pub fn try_insert(&mut self, key: Key, data: Data) -> Result<()> {
+        if self.search(key).is_some() {
+            return Err(Error::DuplicateKey { key });
+        }
+        self.insert(key, data);
+        Ok(())
+    }
+
+    pub fn search(&self, key: Key) -> Option<&Data> {
+        let (leaf, index) = self.node.as_ref()?.find(&self.token, key)?;
+        Some(&leaf.borrow(&self.token).data[index].value)
+    }
+
+    // 最初の候補の leaf から next で右に辿る
+    pub fn search_range(&self, min_key: Key, max_key: Key) -> Vec<&Data> {
+        let mut result = Vec::new();
+        if min_key > max_key {
+            return result;
+        }
+        let mut cell = self.node.as_ref().map(|n| n.first_leaf(min_key));
+        while let Some(current) = cell {
+            let leaf = current.borrow(&self.token);
+            for pair in &leaf.data {
+                if pair.key > max_key {
+                    return result;
+                }
+                if pair.key >= min_key {
+                    result.push(&pair.value);
+                }
+            }
+            cell = leaf.next.as_ref();
+        }
+        result
+    }
+
+    // 同じキーが複数ある場合は一つだけ削除する
+    // leaf の next を付け替えずに済むよう、空になった leaf もそのまま残す
+    pub fn remove(&mut self, key: Key) -> Option<Data> {
+        let (leaf, index) = self.node.as_ref()?.find(&self.token, key)?;
+        self.len -= 1;
+        Some(leaf.borrow_mut(&mut self.token).data.remove(index).value)
+    }
+
+    // キー順に並べた (key, value)
+    pub fn entries(&self) -> Vec<(Key, &Data)> {
+        self.iter().collect()
+    }
+
+    // キー順に (key, value) を返す
+    // next は使わずに木を辿る
+    pub fn iter(&self) -> GhostIter<'_, 'id> {
+        GhostIter {
+            token: &self.token,
+            front: self.node.iter().collect(),
+            back: self.node.iter().collect(),
+            front_leaf: [].iter(),
+            back_leaf: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    fn search_mut(&mut self, key: Key) -> Option<&mut Data> {
+        let (leaf, index) = self.node.as_ref()?.find(&self.token, key)?;
+        Some(&mut leaf.borrow_mut(&mut self.token).data[index].value)
+    }
+}
+
+impl<'id> Node<'id> {
+    #[must_use = "insertion may fail"]
+    fn insert(
+        &mut self,
+        token: &mut GhostToken<'id>,
+        cap: usize,
+        key: Key,
+        data: Data,
+    ) -> Option<Node<'id>> {
+        match self {
+            Node::Internal(internal) => internal.insert(token, cap, key, data),
+            Node::Leaf(cell) => {
+                let leaf = cell.borrow_mut(token);
+                // 同じキーは後ろに入れる
+                let index = leaf.data.partition_point(|p| p.key <= key);
+                leaf.data.insert(index, DataPair::new(key, data));
+                if leaf.data.len() <= cap {
+                    return None;
+                }
+                // 以下のようになるので、leaf.next を引き継ぐ
+                //   before split: leaf->other
+                //   after  split: leaf->right->other
+                let right = Rc::new(GhostCell::new(LeafNode {
+                    data: leaf.data.split_off(leaf.data.len() / 2),
+                    next: leaf.next.take(),
+                }));
+                leaf.next = Some(Rc::clone(&right));
+                Some(Node::Leaf(right))
+            }
+        }
+    }
+
+    // key が入っている leaf と、leaf の中の位置
+    fn find(&self, token: &GhostToken<'id>, key: Key) -> Option<(&LeafRef<'id>, usize)> {
+        match self {
+            Node::Internal(internal) => candidates(&internal.nodes, key)
+                .find_map(|index| internal.nodes[index].value.find(token, key)),
+            Node::Leaf(cell) => cell
+                .borrow(token)
+                .data
+                .iter()
+                .position(|p| p.key == key)
+                .map(|index| (cell, index)),
+        }
+    }
+
+    // min_key 以上のキーが入っている可能性のある、最も左の leaf
+    fn first_leaf(&self, min_key: Key) -> &LeafRef<'id> {
+        match self {
+            Node::Internal(internal) => internal.nodes[candidates(&internal.nodes, min_key).start]
+                .value
+                .first_leaf(min_key),
+            Node::Leaf(cell) => cell,
+        }
+    }
+
+    fn min_key(&self, token: &GhostToken<'id>) -> Option<Key> {
+        match self {
+            Node::Internal(internal) => internal.nodes.first().map(|p| p.key),
+            Node::Leaf(cell) => cell.borrow(token).data.first().map(|p| p.key),
+        }
+    }
+}
+
+impl<'id> InternalNode<'id> {
+    // 子を取り除くことは無いので、nodes は空にならない
+    fn insert(
+        &mut self,
+        token: &mut GhostToken<'id>,
+        cap: usize,
+        key: Key,
+        data: Data,
+    ) -> Option<Node<'id>> {
+        let index = index_for_insert(&self.nodes, key);
+        let node = &mut self.nodes[index];
+        // 最小値より小さいキーは先頭の子に入る。pair.key は子のキーの下限にしておく
+        if key < node.key {
+            node.key = key;
+        }
+        if let Some(n) = node.value.insert(token, cap, key, data) {
+            if let Some(k) = n.min_key(token) {
+                // 同じキーの子の間で順序が入れ替わらないよう、分割した子のすぐ右に入れる
+                self.nodes.insert(index + 1, Pair::new(k, n));
+            }
+        }
+        // 暗黙的に最小値のキー分保持しているので、cap より一つ多くまで持てる
+        if self.nodes.len() > cap + 1 {
+            let right = self.nodes.split_off(self.nodes.len() / 2);
+            return Some(Node::Internal(InternalNode { nodes: right }));
+        }
+        None
+    }
+}
+
+// 前からと後ろからはそれぞれ別に辿り、残りの数で重ならないようにする
+pub struct GhostIter<'a, 'id> {
+    token: &'a GhostToken<'id>,
+    // まだ辿っていないノード。末尾から取り出す
+    front: Vec<&'a Node<'id>>,
+    back: Vec<&'a Node<'id>>,
+    front_leaf: slice::Iter<'a, DataPair>,
+    back_leaf: slice::Iter<'a, DataPair>,
+    remaining: usize,
+}
+
+impl<'a, 'id> Iterator for GhostIter<'a, 'id> {
+    type Item = (Key, &'a Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.front_leaf.next() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.front.pop()? {
+                Node::Internal(internal) => self
+                    .front
+                    .extend(internal.nodes.iter().rev().map(|p| &p.value)),
+                Node::Leaf(cell) => self.front_leaf = cell.borrow(self.token).data.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, 'id> DoubleEndedIterator for GhostIter<'a, 'id> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.back_leaf.next_back() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.back.pop()? {
+                Node::Internal(internal) => {
+                    self.back.extend(internal.nodes.iter().map(|p| &p.value))
+                }
+                Node::Leaf(cell) => self.back_leaf = cell.borrow(self.token).data.iter(),
+            }
+        }
+    }
+}
+
+impl<'a, 'id> ExactSizeIterator for GhostIter<'a, 'id> {}
+
+impl<'a, 'id> FusedIterator for GhostIter<'a, 'id> {}
+
+impl<'a, 'id> IntoIterator for &'a GhostBPlusTree<'id> {
+    type Item = (Key, &'a Data);
+    type IntoIter = GhostIter<'a, 'id>;
+
+    fn into_iter(self) -> GhostIter<'a, 'id> {
+        self.iter()
+    }
+}
+
+impl<'id> OrderedMap<Key, Data> for GhostBPlusTree<'id> {
+    type Iter<'a>
+        = GhostIter<'a, 'id>
+    where
+        'id: 'a;
+
+    // GhostBPlusTree::insert と違い、同じキーがあれば値を置き換える
+    fn insert(&mut self, key: Key, value: Data) -> Option<Data> {
+        if let Some(data) = self.search_mut(key) {
+            return Some(std::mem::replace(data, value));
+        }
+        GhostBPlusTree::insert(self, key, value);
+        None
+    }
+
+    fn get(&self, key: Key) -> Option<&Data> {
+        self.search(key)
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Data> {
+        GhostBPlusTree::remove(self, key)
+    }
+
+    fn iter(&self) -> GhostIter<'_, 'id> {
+        GhostBPlusTree::iter(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn ghost_cell() {
+        GhostToken::scope(|mut token| {
+            let a = GhostCell::new(1);
+            let b = GhostCell::new(2);
+            *a.borrow_mut(&mut token) += 10;
+            *b.borrow_mut(&mut token) += 20;
+            assert_eq!((*a.borrow(&token), *b.borrow(&token)), (11, 22));
+        });
+    }
+
+    // 別の InternalNode の下にある leaf にも next で辿れる
+    #[test]
+    fn search_range_across_nodes() {
+        GhostToken::scope(|token| {
+            let mut b = GhostBPlusTree::new(token, 3);
+            for k in (0..100).rev() {
+                b.insert(k, k);
+            }
+            let all: Vec<_> = (0..100).collect();
+            assert_eq!(b.search_range(0, 99), all.iter().collect::<Vec<_>>());
+            assert_eq!(
+                b.search_range(5, 60),
+                all[5..=60].iter().collect::<Vec<_>>()
+            );
+            b.remove(30);
+            assert_eq!(b.search_range(29, 31), vec![&29, &31]);
+            assert_eq!(b.len(), 99);
+            assert!((&b)
+                .into_iter()
+                .rev()
+                .map(|(k, _)| k)
+                .eq((0..100).rev().filter(|k| *k != 30)));
+        });
+    }
+
+    #[test]
+    fn try_new_and_try_insert() {
+        GhostToken::scope(|token| {
+            assert_eq!(
+                GhostBPlusTree::try_new(token, 0).err(),
+                Some(Error::InvalidCapacity { cap: 0 })
+            );
+        });
+        GhostToken::scope(|token| {
+            let mut b = GhostBPlusTree::try_new(token, MIN_CAP).unwrap();
+            b.try_insert(1, 1).unwrap();
+            assert_eq!(b.try_insert(1, 2), Err(Error::DuplicateKey { key: 1 }));
+            assert_eq!(b.search(1), Some(&1));
+        });
+    }
+
+    proptest! {
+        // BPlusTree と同じ操作列を与え、全ての結果を比べる
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn same_as_btree_map_random(
+            cap in 2..64usize,
+            ops in prop::collection::vec((0..4u8, 0..200usize, 0..200usize), 0..400),
+        ) {
+            GhostToken::scope(|token| {
+                let mut tree = GhostBPlusTree::new(token, cap);
+                let mut expected = BTreeMap::new();
+                for (op, a, b) in ops {
+                    match op {
+                        0 | 1 => prop_assert_eq!(
+                            OrderedMap::insert(&mut tree, a, b),
+                            expected.insert(a, b)
+                        ),
+                        2 => prop_assert_eq!(tree.remove(a), expected.remove(&a)),
+                        _ => {
+                            let values: Vec<_> = OrderedMap::range(&expected, a, b)
+                                .into_iter()
+                                .map(|(_, v)| v)
+                                .collect();
+                            prop_assert_eq!(tree.search_range(a, b), values);
+                            prop_assert_eq!(tree.search(a), expected.get(&a));
+                        }
+                    }
+                    prop_assert_eq!(tree.len(), expected.len());
+                }
+                prop_assert!(tree.iter().eq(OrderedMap::iter(&expected)));
+                prop_assert!(tree.iter().rev().eq(OrderedMap::iter(&expected).rev()));
+                Ok(())
+            })?;
+        }
+    }
+}
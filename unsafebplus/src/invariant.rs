@@ -0,0 +1,136 @@
+// 木の構造が壊れていないかの確認。テストや fuzz から呼ぶ
+use std::ptr::NonNull;
+
+use crate::{Allocator, BPlusTree, Key, LeafNode, Node};
+
+impl<A: Allocator + Clone> BPlusTree<A> {
+    // 壊れていれば panic する
+    //   - 全ての leaf が同じ深さにある
+    //   - ノードの大きさが cap に収まっている。削除で空になった leaf は残ってよい
+    //   - pair.key は子のキーの下限で、次の pair.key 以下に子のキーが収まっている
+    //   - 左端の leaf から next を辿ると、全ての leaf をキー順に一度ずつ通る
+    pub fn check_invariants(&self) {
+        let mut leaves = Vec::new();
+        let mut leaf_depth = None;
+        let mut count = 0;
+        if let Some(node) = &self.node {
+            self.check_node(
+                node,
+                0,
+                (Key::MIN, Key::MAX),
+                &mut leaf_depth,
+                &mut leaves,
+                &mut count,
+            );
+        }
+        assert_eq!(count, self.len, "len does not match the leaves");
+        // next はポインタを比べるだけで、参照先は読まない
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(
+                leaf.next,
+                leaves.get(i + 1).map(|l| NonNull::from(*l)),
+                "leaf {} is not linked to the next leaf",
+                i
+            );
+        }
+    }
+
+    fn check_node<'a>(
+        &self,
+        node: &'a Node<A>,
+        depth: usize,
+        (lower, upper): (Key, Key),
+        leaf_depth: &mut Option<usize>,
+        leaves: &mut Vec<&'a LeafNode<A>>,
+        count: &mut usize,
+    ) {
+        match node {
+            Node::Internal(internal) => {
+                assert!(!internal.nodes.is_empty(), "empty internal node");
+                assert!(
+                    internal.nodes.len() <= self.cap + 1,
+                    "internal node has {} children",
+                    internal.nodes.len()
+                );
+                for (i, pair) in internal.nodes.iter().enumerate() {
+                    let next = internal.nodes.get(i + 1).map_or(upper, |p| p.key);
+                    assert!(
+                        lower <= pair.key && pair.key <= next,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    assert!(
+                        pair.value.min_key().is_none_or(|min| pair.key <= min),
+                        "key {} is not a lower bound of its child",
+                        pair.key
+                    );
+                    self.check_node(
+                        &pair.value,
+                        depth + 1,
+                        (pair.key, next),
+                        leaf_depth,
+                        leaves,
+                        count,
+                    );
+                }
+            }
+            Node::Leaf(leaf) => {
+                assert_eq!(
+                    *leaf_depth.get_or_insert(depth),
+                    depth,
+                    "leaves are at different depths"
+                );
+                assert!(
+                    leaf.data.len() <= self.cap,
+                    "leaf has {} entries",
+                    leaf.data.len()
+                );
+                let mut last = lower;
+                for pair in &leaf.data {
+                    assert!(
+                        last <= pair.key && pair.key <= upper,
+                        "key {} is out of order",
+                        pair.key
+                    );
+                    last = pair.key;
+                }
+                *count += leaf.data.len();
+                leaves.push(leaf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{BPlusTree, Node};
+
+    #[test]
+    fn check_invariants() {
+        let mut b = BPlusTree::new(2);
+        b.check_invariants();
+        for k in (0..200).map(|k| (k * 37) % 101) {
+            b.insert(k, k);
+            b.check_invariants();
+        }
+        for k in (0..101).step_by(2) {
+            b.remove(k);
+            b.check_invariants();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not linked")]
+    fn detects_broken_link() {
+        let mut b = BPlusTree::new(3);
+        for k in 0..4 {
+            b.insert(k, k);
+        }
+        if let Some(Node::Internal(internal)) = &mut b.node {
+            if let Node::Leaf(leaf) = &mut internal.nodes[0].value {
+                leaf.next = None;
+            }
+        }
+        b.check_invariants();
+    }
+}
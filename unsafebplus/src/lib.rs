@@ -1,7 +1,32 @@
+#![cfg_attr(feature = "nightly", feature(allocator_api))]
+
 use std::{
+    collections::VecDeque,
     fmt::{self},
-    ptr,
+    iter::FusedIterator,
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut, Range},
+    ptr::NonNull,
 };
+
+// nightly feature では std の allocator_api を使う
+pub use allocator_api2::alloc::{Allocator, Global};
+use allocator_api2::{boxed::Box, vec::Vec as AllocVec};
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impl;
+mod arena;
+mod error;
+mod ghost;
+mod invariant;
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+pub use arena::{ArenaBPlusTree, ArenaIter};
+pub use error::{Error, Result, MIN_CAP};
+pub use ghost::{GhostBPlusTree, GhostCell, GhostIter, GhostToken};
+pub use orderedmap::{Op, OrderedMap};
+
 pub type Key = usize;
 pub type Data = usize;
 #[derive(Debug)]
@@ -16,29 +41,90 @@ impl<T> Pair<T> {
     }
 }
 
-type NodePair = Pair<Node>;
+type NodePair<A> = Pair<Node<A>>;
 type DataPair = Pair<Data>;
 
+// 同じキーは右側の子に入れる。どの pair.key よりも小さければ先頭の子
+fn index_for_insert<T>(nodes: &[Pair<T>], key: Key) -> usize {
+    nodes
+        .iter()
+        .take_while(|pair| pair.key <= key)
+        .count()
+        .saturating_sub(1)
+}
+
+// key が入っている可能性のある子の位置
+// 同じキーが続くと分割で左右の子に分かれるので、pair.key == key の子の左隣から見る
+fn candidates<T>(nodes: &[Pair<T>], key: Key) -> Range<usize> {
+    let lt = nodes.iter().take_while(|pair| pair.key < key).count();
+    let le = nodes.iter().take_while(|pair| pair.key <= key).count();
+    let start = lt.saturating_sub(1);
+    start..le.max(start + 1).min(nodes.len())
+}
+
+// ノードとノード内の Vec は全て A から確保する
 #[derive(Debug)]
-pub struct BPlusTree {
+pub struct BPlusTree<A: Allocator + Clone = Global> {
     cap: usize,
-    node: Option<Node>,
+    node: Option<Node<A>>,
+    // エントリの数
+    len: usize,
+    alloc: A,
 }
 
 impl BPlusTree {
+    // cap が MIN_CAP より小さいと panic する
     pub fn new(cap: usize) -> Self {
-        Self { cap, node: None }
+        match Self::try_new(cap) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(cap: usize) -> Result<Self> {
+        Self::try_new_in(cap, Global)
+    }
+}
+
+impl<A: Allocator + Clone> BPlusTree<A> {
+    // cap が MIN_CAP より小さいと panic する
+    pub fn new_in(cap: usize, alloc: A) -> Self {
+        match Self::try_new_in(cap, alloc) {
+            Ok(tree) => tree,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new_in(cap: usize, alloc: A) -> Result<Self> {
+        if cap < MIN_CAP {
+            return Err(Error::InvalidCapacity { cap });
+        }
+        Ok(Self {
+            cap,
+            node: None,
+            len: 0,
+            alloc,
+        })
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn insert(&mut self, key: Key, data: Data) {
+        self.len += 1;
         // data.id は self.dataのindexが入る
         // この値は現在の長さに等しい
         if self.node.is_none() {
-            let child = Node::Leaf(LeafNode {
-                cap: self.cap,
-                data: vec![DataPair::new(key, data)],
-                next: ptr::null_mut(),
-            });
+            let child = Node::Leaf(LeafPtr::single(self.cap, key, data, self.alloc.clone()));
             self.node = Some(child);
             return;
         }
@@ -46,31 +132,32 @@ impl BPlusTree {
         let splited = self.node.as_mut().and_then(|n| n.insert(key, data));
         if let Some(node) = splited {
             let old_child = self.node.take().unwrap();
-            let mut new_child = InternalNode {
+            let mut nodes = AllocVec::with_capacity_in(2, self.alloc.clone());
+            nodes.push(NodePair {
+                key: old_child.min_key().unwrap(),
+                value: old_child,
+            });
+            nodes.push(NodePair {
+                key: node.min_key().unwrap(),
+                value: node,
+            });
+            let new_child = InternalNode {
                 cap: self.cap,
-                nodes: vec![
-                    NodePair {
-                        key: old_child.min_key().unwrap(),
-                        value: old_child,
-                    },
-                    NodePair {
-                        key: node.min_key().unwrap(),
-                        value: node,
-                    },
-                ],
+                nodes,
             };
-            let mut next_node_ptr = ptr::null();
-            if let Node::Leaf(node) = &new_child.nodes.get(1).unwrap().value {
-                next_node_ptr = node as *const _;
-            }
-            if let Node::Leaf(node) = &mut new_child.nodes.get_mut(0).unwrap().value {
-                node.next = next_node_ptr;
-            }
-
             self.node = Some(Node::Internal(new_child));
         }
     }
 
+    // insert と違い、既にキーがあれば挿入しない
+    pub fn try_insert(&mut self, key: Key, data: Data) -> Result<()> {
+        if self.search(key).is_some() {
+            return Err(Error::DuplicateKey { key });
+        }
+        self.insert(key, data);
+        Ok(())
+    }
+
     pub fn search(&self, key: Key) -> Option<&Data> {
         self.node.as_ref().and_then(|n| n.search(key))
     }
@@ -81,16 +168,228 @@ impl BPlusTree {
             .map(|n| n.search_range(min_key, max_key))
             .unwrap_or_default()
     }
+
+    // 同じキーが複数ある場合は一つだけ削除する
+    // leaf の next を付け替えずに済むよう、空になった leaf もそのまま残す
+    pub fn remove(&mut self, key: Key) -> Option<Data> {
+        let removed = self.node.as_mut().and_then(|n| n.remove(key));
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    // キー順に並べた (key, value)
+    pub fn entries(&self) -> Vec<(Key, &Data)> {
+        self.iter().collect()
+    }
+
+    // キー順に (key, value) を返す
+    // next は使わずに木を辿る
+    pub fn iter(&self) -> Iter<'_, A> {
+        Iter {
+            front: self.node.iter().collect(),
+            back: self.node.iter().collect(),
+            front_leaf: [].iter(),
+            back_leaf: [].iter(),
+            remaining: self.len,
+        }
+    }
+
+    // キー順に (key, &mut value) を返す
+    pub fn iter_mut(&mut self) -> IterMut<'_, A> {
+        IterMut {
+            nodes: self.node.iter_mut().collect(),
+            front_leaf: [].iter_mut(),
+            back_leaf: [].iter_mut(),
+            remaining: self.len,
+        }
+    }
+}
+
+// 前からと後ろからはそれぞれ別に辿り、残りの数で重ならないようにする
+pub struct Iter<'a, A: Allocator + Clone = Global> {
+    // まだ辿っていないノード。末尾から取り出す
+    front: Vec<&'a Node<A>>,
+    back: Vec<&'a Node<A>>,
+    front_leaf: std::slice::Iter<'a, DataPair>,
+    back_leaf: std::slice::Iter<'a, DataPair>,
+    remaining: usize,
+}
+
+impl<'a, A: Allocator + Clone> Iterator for Iter<'a, A> {
+    type Item = (Key, &'a Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.front_leaf.next() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.front.pop()? {
+                Node::Internal(internal) => self
+                    .front
+                    .extend(internal.nodes.iter().rev().map(|p| &p.value)),
+                Node::Leaf(leaf) => self.front_leaf = leaf.data.iter(),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, A: Allocator + Clone> DoubleEndedIterator for Iter<'a, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        loop {
+            if let Some(p) = self.back_leaf.next_back() {
+                self.remaining -= 1;
+                return Some((p.key, &p.value));
+            }
+            match self.back.pop()? {
+                Node::Internal(internal) => {
+                    self.back.extend(internal.nodes.iter().map(|p| &p.value))
+                }
+                Node::Leaf(leaf) => self.back_leaf = leaf.data.iter(),
+            }
+        }
+    }
+}
+
+impl<'a, A: Allocator + Clone> ExactSizeIterator for Iter<'a, A> {}
+
+impl<'a, A: Allocator + Clone> FusedIterator for Iter<'a, A> {}
+
+// 同じノードを前後から可変で借用しないよう、まだ辿っていないノードを一つの列で持つ
+// 前からは先頭のノードを、後ろからは末尾のノードを展開する
+// 列が空になったら、残りは反対側の leaf にある
+pub struct IterMut<'a, A: Allocator + Clone = Global> {
+    // キー順に並んでいる
+    nodes: VecDeque<&'a mut Node<A>>,
+    front_leaf: std::slice::IterMut<'a, DataPair>,
+    back_leaf: std::slice::IterMut<'a, DataPair>,
+    remaining: usize,
+}
+
+impl<'a, A: Allocator + Clone> Iterator for IterMut<'a, A> {
+    type Item = (Key, &'a mut Data);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(p) = self.front_leaf.next() {
+                self.remaining -= 1;
+                return Some((p.key, &mut p.value));
+            }
+            match self.nodes.pop_front() {
+                Some(Node::Internal(internal)) => {
+                    for pair in internal.nodes.iter_mut().rev() {
+                        self.nodes.push_front(&mut pair.value);
+                    }
+                }
+                Some(Node::Leaf(leaf)) => self.front_leaf = leaf.data.iter_mut(),
+                None => {
+                    let p = self.back_leaf.next()?;
+                    self.remaining -= 1;
+                    return Some((p.key, &mut p.value));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, A: Allocator + Clone> DoubleEndedIterator for IterMut<'a, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(p) = self.back_leaf.next_back() {
+                self.remaining -= 1;
+                return Some((p.key, &mut p.value));
+            }
+            match self.nodes.pop_back() {
+                Some(Node::Internal(internal)) => {
+                    for pair in internal.nodes.iter_mut() {
+                        self.nodes.push_back(&mut pair.value);
+                    }
+                }
+                Some(Node::Leaf(leaf)) => self.back_leaf = leaf.data.iter_mut(),
+                None => {
+                    let p = self.front_leaf.next_back()?;
+                    self.remaining -= 1;
+                    return Some((p.key, &mut p.value));
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Allocator + Clone> ExactSizeIterator for IterMut<'a, A> {}
+
+impl<'a, A: Allocator + Clone> FusedIterator for IterMut<'a, A> {}
+
+impl<'a, A: Allocator + Clone> IntoIterator for &'a BPlusTree<A> {
+    type Item = (Key, &'a Data);
+    type IntoIter = Iter<'a, A>;
+
+    fn into_iter(self) -> Iter<'a, A> {
+        self.iter()
+    }
+}
+
+impl<'a, A: Allocator + Clone> IntoIterator for &'a mut BPlusTree<A> {
+    type Item = (Key, &'a mut Data);
+    type IntoIter = IterMut<'a, A>;
+
+    fn into_iter(self) -> IterMut<'a, A> {
+        self.iter_mut()
+    }
+}
+
+impl<A: Allocator + Clone> OrderedMap<Key, Data> for BPlusTree<A> {
+    type Iter<'a>
+        = Iter<'a, A>
+    where
+        A: 'a;
+
+    // BPlusTree::insert と違い、同じキーがあれば値を置き換える
+    fn insert(&mut self, key: Key, value: Data) -> Option<Data> {
+        if let Some(data) = self.node.as_mut().and_then(|n| n.search_mut(key)) {
+            return Some(std::mem::replace(data, value));
+        }
+        BPlusTree::insert(self, key, value);
+        None
+    }
+
+    fn get(&self, key: Key) -> Option<&Data> {
+        self.search(key)
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Data> {
+        BPlusTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, A> {
+        BPlusTree::iter(self)
+    }
 }
 #[derive(Debug)]
-enum Node {
-    Internal(InternalNode),
-    Leaf(LeafNode),
+enum Node<A: Allocator + Clone> {
+    Internal(InternalNode<A>),
+    Leaf(LeafPtr<A>),
 }
 
-impl Node {
+impl<A: Allocator + Clone> Node<A> {
     #[must_use = "insertion may fail"]
-    fn insert(&mut self, key: Key, data: Data) -> Option<Node> {
+    fn insert(&mut self, key: Key, data: Data) -> Option<Node<A>> {
         match self {
             Node::Internal(internal) => internal.insert(key, data),
             Node::Leaf(leaf) => leaf.insert(key, data),
@@ -120,43 +419,57 @@ impl Node {
             Node::Leaf(leaf) => leaf.data.first().map(|r| r.key),
         }
     }
+
+    fn search_mut(&mut self, key: Key) -> Option<&mut Data> {
+        match self {
+            Node::Internal(internal) => internal.search_mut(key),
+            Node::Leaf(leaf) => leaf
+                .data
+                .iter_mut()
+                .find(|p| p.key == key)
+                .map(|p| &mut p.value),
+        }
+    }
+
+    fn remove(&mut self, key: Key) -> Option<Data> {
+        match self {
+            Node::Internal(internal) => internal.remove(key),
+            Node::Leaf(leaf) => {
+                let index = leaf.data.iter().position(|p| p.key == key)?;
+                Some(leaf.data.remove(index).value)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
-struct InternalNode {
+struct InternalNode<A: Allocator + Clone> {
     cap: usize,
     // Vec ではなく配列にしてもいいかも。const generics
-    nodes: Vec<NodePair>,
+    nodes: AllocVec<NodePair<A>, A>,
 }
-impl InternalNode {
-    fn insert(&mut self, key: Key, data: Data) -> Option<Node> {
-        // TODO 同値のkeyが存在している場合がおかしいので、要修正
+impl<A: Allocator + Clone> InternalNode<A> {
+    fn insert(&mut self, key: Key, data: Data) -> Option<Node<A>> {
         if self.nodes.is_empty() {
+            let alloc = self.nodes.allocator().clone();
             self.nodes.push(NodePair::new(
                 key,
-                Node::Leaf(LeafNode {
-                    cap: self.cap,
-                    data: vec![DataPair::new(key, data)],
-                    next: ptr::null_mut(),
-                }),
+                Node::Leaf(LeafPtr::single(self.cap, key, data, alloc)),
             ));
             return None;
         }
-        let node = self.find_node_for_insert(key, data);
+        let index = index_for_insert(&self.nodes, key);
+        let node = &mut self.nodes[index];
+        // 最小値より小さいキーは先頭の子に入る。pair.key は子のキーの下限にしておく
+        if key < node.key {
+            node.key = key;
+        }
         let splited_node = node.value.insert(key, data);
         if let Some(n) = splited_node {
             if let Some(k) = n.min_key() {
-                self.nodes.push(Pair { key: k, value: n });
-                self.nodes.sort_by_key(|p| p.key);
-                // 並び替えたので、nextを並び替え後のものに変更
-                // TODO 全要素を付け替える実装をやめる
-                let mut next_node_ptr = ptr::null();
-                for n in self.nodes.iter_mut().rev().map(|p| &mut p.value) {
-                    if let Node::Leaf(node) = n {
-                        node.next = next_node_ptr;
-                        next_node_ptr = node as *const _;
-                    }
-                }
+                // 並び替えると、同じキーの子の間で順序が入れ替わることがある
+                // 分割した子のすぐ右に入れる。leaf はヒープ上で動かないので next はそのまま使える
+                self.nodes.insert(index + 1, Pair { key: k, value: n });
             }
         }
         if self.is_full() {
@@ -165,7 +478,7 @@ impl InternalNode {
         None
     }
 
-    fn split(&mut self) -> Node {
+    fn split(&mut self) -> Node<A> {
         let right = self.nodes.split_off(self.nodes.len() / 2);
         let new_next = Self {
             cap: self.cap,
@@ -174,50 +487,26 @@ impl InternalNode {
         Node::Internal(new_next)
     }
 
-    fn find_node_for_insert(&mut self, key: Key, data: Data) -> &mut NodePair {
-        if self.nodes.is_empty() {
-            self.nodes.push(NodePair::new(
-                key,
-                Node::Leaf(LeafNode {
-                    cap: self.cap,
-                    data: vec![DataPair::new(key, data)],
-                    next: ptr::null_mut(),
-                }),
-            ))
-        }
-        return self.find_mut_node(key).unwrap();
+    fn search(&self, key: Key) -> Option<&Data> {
+        candidates(&self.nodes, key).find_map(|index| self.nodes[index].value.search(key))
     }
 
-    fn find_mut_node(&mut self, key: Key) -> Option<&mut NodePair> {
-        let exist = self.nodes.iter().any(|pair| pair.key <= key);
-        if exist {
-            self.nodes
-                .iter_mut()
-                .take_while(|pair| pair.key <= key)
-                .last()
-        } else {
-            self.nodes.first_mut()
-        }
+    fn search_mut(&mut self, key: Key) -> Option<&mut Data> {
+        let index = candidates(&self.nodes, key)
+            .find(|index| self.nodes[*index].value.search(key).is_some())?;
+        self.nodes[index].value.search_mut(key)
     }
 
-    fn search(&self, key: Key) -> Option<&Data> {
-        // TODO 同値のkeyが存在している場合がおかしいので、要修正
-        let p = self.find_node(key);
-        p.and_then(|p| p.value.search(key))
+    fn remove(&mut self, key: Key) -> Option<Data> {
+        candidates(&self.nodes, key).find_map(|index| self.nodes[index].value.remove(key))
     }
 
+    // 最初の候補の leaf から next で右に辿る
     fn search_range(&self, min_key: Key, max_key: Key) -> Vec<&Data> {
-        let p = self.find_node(min_key);
-        p.map(|p| p.value.search_range(min_key, max_key))
-            .unwrap_or_default()
-    }
-
-    fn find_node(&self, key: Key) -> Option<&NodePair> {
         self.nodes
-            .iter()
-            .take_while(|pair| pair.key <= key)
-            .last()
-            .or_else(|| self.nodes.first())
+            .get(candidates(&self.nodes, min_key).start)
+            .map(|p| p.value.search_range(min_key, max_key))
+            .unwrap_or_default()
     }
 
     // capacityに空きがあるかどうか
@@ -227,14 +516,91 @@ impl InternalNode {
         self.nodes.len() > (self.cap + 1)
     }
 }
+// LeafNode を所有するポインタ
+// Box のまま持つと、Vec の並び替えなどで Box が move されるたびに一意な参照とみなされ、
+// 他の leaf の next から辿るのが未定義動作になる
+// そのため Box::into_raw_with_allocator で得た生ポインタだけを持ち、drop で解放する
+struct LeafPtr<A: Allocator + Clone> {
+    ptr: NonNull<LeafNode<A>>,
+    // drop で Box に戻すときに move する
+    alloc: ManuallyDrop<A>,
+}
+
+impl<A: Allocator + Clone> LeafPtr<A> {
+    fn new(leaf: LeafNode<A>, alloc: A) -> Self {
+        let (ptr, alloc) = Box::into_raw_with_allocator(Box::new_in(leaf, alloc));
+        Self {
+            // SAFETY: Box から得たポインタは null にならない
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+            alloc: ManuallyDrop::new(alloc),
+        }
+    }
+
+    // エントリが一つだけの leaf
+    fn single(cap: usize, key: Key, data: Data, alloc: A) -> Self {
+        let mut entries = AllocVec::new_in(alloc.clone());
+        entries.push(DataPair::new(key, data));
+        Self::new(
+            LeafNode {
+                cap,
+                data: entries,
+                next: None,
+            },
+            alloc,
+        )
+    }
+
+    // 他の leaf の next に入れるためのポインタ
+    fn as_ptr(&self) -> NonNull<LeafNode<A>> {
+        self.ptr
+    }
+}
+
+impl<A: Allocator + Clone> Deref for LeafPtr<A> {
+    type Target = LeafNode<A>;
+
+    fn deref(&self) -> &LeafNode<A> {
+        // SAFETY: self.ptr は new で確保したもので、drop するまで解放しない
+        // next から作る参照は共有参照だけで、&self を借用している間は &mut を作れない
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<A: Allocator + Clone> DerefMut for LeafPtr<A> {
+    fn deref_mut(&mut self) -> &mut LeafNode<A> {
+        // SAFETY: deref と同じ。&mut self を借用している間は木全体を可変で借用しているので、
+        // next から作った参照は残っていない
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<A: Allocator + Clone> Drop for LeafPtr<A> {
+    fn drop(&mut self) {
+        // SAFETY: self.ptr と self.alloc は Box::into_raw_with_allocator で得たもので、
+        // 所有しているのは self だけ。self.alloc はこの後使わない
+        // 他の leaf の next は残るが、木を drop した後に辿ることはない
+        unsafe {
+            let alloc = ManuallyDrop::take(&mut self.alloc);
+            drop(Box::from_raw_in(self.ptr.as_ptr(), alloc))
+        }
+    }
+}
+
+impl<A: Allocator + Clone + fmt::Debug> fmt::Debug for LeafPtr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
 #[derive(Debug)]
-struct LeafNode {
+struct LeafNode<A: Allocator + Clone> {
     cap: usize,
-    data: Vec<DataPair>, // TODO generics
-    next: *const LeafNode,
+    data: AllocVec<DataPair, A>,
+    // 右隣の leaf。同じ木の LeafPtr が所有している
+    next: Option<NonNull<LeafNode<A>>>,
 }
 
-impl fmt::Pointer for LeafNode {
+impl<A: Allocator + Clone> fmt::Pointer for LeafNode<A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // use `as` to convert to a `*const T`, which implements Pointer, which we can use
         let ptr = self as *const Self;
@@ -242,8 +608,8 @@ impl fmt::Pointer for LeafNode {
     }
 }
 
-impl LeafNode {
-    fn insert(&mut self, key: Key, data_id: Data) -> Option<Node> {
+impl<A: Allocator + Clone> LeafNode<A> {
+    fn insert(&mut self, key: Key, data_id: Data) -> Option<Node<A>> {
         // 末尾に常に入れるわけではない
         self.data.push(DataPair::new(key, data_id));
         self.data.sort_by_key(|r| r.key);
@@ -253,17 +619,21 @@ impl LeafNode {
         None
     }
 
-    fn split(&mut self) -> Node {
+    fn split(&mut self) -> Node<A> {
         let right = self.data.split_off(self.data.len() / 2);
-        let mut new_next = Self {
-            cap: self.cap,
-            data: right,
-            next: ptr::null_mut(),
-        };
         // 以下のようになるので、self.nextを引き継ぐ
         //   before split: self->other
         //   after  split: self->new_next->other
-        new_next.next = self.next;
+        let alloc = self.data.allocator().clone();
+        let new_next = LeafPtr::new(
+            Self {
+                cap: self.cap,
+                data: right,
+                next: self.next,
+            },
+            alloc,
+        );
+        self.next = Some(new_next.as_ptr());
         Node::Leaf(new_next)
     }
 
@@ -280,7 +650,8 @@ impl LeafNode {
             .map(|x| &x.value)
             .collect();
         loop {
-            target_leaf_node = target_leaf_node.and_then(|x| unsafe { x.next.as_ref() });
+            // SAFETY: next は同じ木の leaf を指していて、&self を借用している間は解放も変更もされない
+            target_leaf_node = target_leaf_node.and_then(|x| x.next.map(|p| unsafe { p.as_ref() }));
             if target_leaf_node.is_none() {
                 break result;
             }
@@ -307,7 +678,9 @@ impl LeafNode {
 
 #[cfg(test)]
 mod test {
-    use std::fmt;
+    use std::{collections::BTreeMap, fmt};
+
+    use proptest::prelude::*;
 
     use super::*;
     #[test]
@@ -438,4 +811,212 @@ mod test {
         let d = return_data();
         println!("{:p}", d);
     }
+
+    // OrderedMap の操作だけを使い、BTreeMap と同じ結果になることを確認する
+    fn same_as_btree_map<M: OrderedMap<Key, Data>>(mut m: M) {
+        let mut expected = BTreeMap::new();
+        for k in (0..300).map(|k| (k * 37) % 101) {
+            let v = k * 2;
+            assert_eq!(m.insert(k, v), OrderedMap::insert(&mut expected, k, v));
+        }
+        for k in (0..110).step_by(3) {
+            assert_eq!(m.remove(k), OrderedMap::remove(&mut expected, k));
+        }
+        assert_eq!(m.get(1), Some(&2));
+        assert_eq!(m.get(3), None);
+        assert!(m.iter().eq(OrderedMap::iter(&expected)));
+        assert_eq!(m.range(10, 40), OrderedMap::range(&expected, 10, 40));
+        for k in 0..101 {
+            assert_eq!(m.remove(k), OrderedMap::remove(&mut expected, k));
+        }
+        assert_eq!(m.iter().next(), None);
+    }
+
+    #[test]
+    fn ordered_map() {
+        same_as_btree_map(BPlusTree::new(3));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(Key, Data),
+        Remove(Key),
+        Get(Key),
+        Range(Key, Key),
+    }
+
+    // 重複や分割が起きやすいように、キーは狭い範囲から選ぶ
+    fn op() -> impl Strategy<Value = Op> {
+        let key = 0..200usize;
+        prop_oneof![
+            4 => (key.clone(), any::<Data>()).prop_map(|(k, v)| Op::Insert(k, v)),
+            2 => key.clone().prop_map(Op::Remove),
+            1 => key.clone().prop_map(Op::Get),
+            1 => (key.clone(), key).prop_map(|(min, max)| Op::Range(min, max)),
+        ]
+    }
+
+    proptest! {
+        // ランダムな操作列を BTreeMap と同時に適用し、全ての結果を比べる
+        // Miri では遅すぎるので動かさない
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn same_as_btree_map_random(cap in 2..64usize, ops in prop::collection::vec(op(), 0..400)) {
+            let mut tree = BPlusTree::new(cap);
+            let mut expected = BTreeMap::new();
+            for op in ops {
+                match op {
+                    Op::Insert(k, v) => prop_assert_eq!(
+                        OrderedMap::insert(&mut tree, k, v),
+                        expected.insert(k, v)
+                    ),
+                    Op::Remove(k) => prop_assert_eq!(tree.remove(k), expected.remove(&k)),
+                    Op::Get(k) => prop_assert_eq!(tree.search(k), expected.get(&k)),
+                    Op::Range(min, max) => {
+                        let values: Vec<_> = OrderedMap::range(&expected, min, max)
+                            .into_iter()
+                            .map(|(_, v)| v)
+                            .collect();
+                        prop_assert_eq!(tree.search_range(min, max), values);
+                        prop_assert_eq!(
+                            OrderedMap::range(&tree, min, max),
+                            OrderedMap::range(&expected, min, max)
+                        )
+                    }
+                }
+                tree.check_invariants();
+                prop_assert_eq!(tree.len(), expected.len());
+            }
+            prop_assert!(tree.iter().eq(OrderedMap::iter(&expected)));
+            prop_assert!(tree.iter().rev().eq(OrderedMap::iter(&expected).rev()));
+        }
+    }
+
+    // 別の InternalNode の下にある leaf にも next で辿れる
+    // Miri でも確認する: cargo +nightly miri test -p unsafebplus
+    #[test]
+    fn search_range_across_nodes() {
+        let mut b = BPlusTree::new(3);
+        for k in 0..100 {
+            b.insert(k, k);
+        }
+        let all: Vec<_> = (0..100).collect();
+        assert_eq!(b.search_range(0, 99), all.iter().collect::<Vec<_>>());
+        assert_eq!(
+            b.search_range(5, 60),
+            all[5..=60].iter().collect::<Vec<_>>()
+        );
+        b.remove(30);
+        assert_eq!(b.search_range(29, 31), vec![&29, &31]);
+    }
+
+    #[test]
+    fn try_new_and_try_insert() {
+        assert_eq!(
+            BPlusTree::try_new(0).err(),
+            Some(Error::InvalidCapacity { cap: 0 })
+        );
+        let mut b = BPlusTree::try_new(MIN_CAP).unwrap();
+        b.try_insert(1, 1).unwrap();
+        assert_eq!(b.try_insert(1, 2), Err(Error::DuplicateKey { key: 1 }));
+        assert_eq!(b.search(1), Some(&1));
+        assert_eq!(b.len(), 1);
+    }
+
+    #[test]
+    fn iter_both_ends() {
+        let mut b = BPlusTree::new(3);
+        for k in 0..50 {
+            b.insert(k, k);
+        }
+        b.remove(10);
+        assert_eq!(b.len(), 49);
+        let mut iter = b.iter();
+        assert_eq!(iter.len(), 49);
+        assert_eq!(iter.next(), Some((0, &0)));
+        assert_eq!(iter.next_back(), Some((49, &49)));
+        assert_eq!(iter.len(), 47);
+        let rest: Vec<_> = iter.rev().map(|(k, _)| k).collect();
+        let expected: Vec<_> = (1..49).rev().filter(|k| *k != 10).collect();
+        assert_eq!(rest, expected);
+        let mut iter = b.iter();
+        while iter.next().is_some() {}
+        assert_eq!(iter.next_back(), None);
+    }
+
+    // 確保中のバイト数を数える allocator
+    #[derive(Debug, Clone, Default)]
+    struct Counting(std::rc::Rc<std::cell::Cell<usize>>);
+
+    unsafe impl Allocator for Counting {
+        fn allocate(
+            &self,
+            layout: allocator_api2::alloc::Layout,
+        ) -> std::result::Result<NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+            let ptr = Global.allocate(layout)?;
+            self.0.set(self.0.get() + layout.size());
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: allocator_api2::alloc::Layout) {
+            self.0.set(self.0.get() - layout.size());
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn custom_allocator() {
+        let alloc = Counting::default();
+        let mut b = BPlusTree::new_in(3, alloc.clone());
+        for k in (0..200).rev() {
+            b.insert(k, k);
+        }
+        b.check_invariants();
+        assert!(alloc.0.get() > 0);
+        assert_eq!(b.search_range(10, 12), vec![&10, &11, &12]);
+        for k in 0..100 {
+            b.remove(k);
+        }
+        b.check_invariants();
+        assert!(b.iter().map(|(k, _)| k).eq(100..200));
+        drop(b);
+        // 全てのノードを同じ allocator に返している
+        assert_eq!(alloc.0.get(), 0);
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut b = BPlusTree::new(3);
+        for k in 0..50 {
+            b.insert(k, 0);
+        }
+        b.remove(10);
+        for (k, v) in &mut b {
+            *v = k * 2;
+        }
+        // 前後から交互に取り出しても、全てのエントリを一度ずつ返す
+        let mut iter = b.iter_mut();
+        let mut keys = Vec::new();
+        for i in 0.. {
+            let next = if i % 3 == 0 {
+                iter.next_back()
+            } else {
+                iter.next()
+            };
+            let Some((k, v)) = next else { break };
+            assert_eq!(iter.len(), 48 - keys.len());
+            *v += 1;
+            keys.push(k);
+        }
+        keys.sort();
+        let expected: Vec<_> = (0..50).filter(|k| *k != 10).collect();
+        assert_eq!(keys, expected);
+
+        let mut entries = Vec::new();
+        for (k, v) in &b {
+            entries.push((k, *v));
+        }
+        let expected: Vec<_> = expected.into_iter().map(|k| (k, k * 2 + 1)).collect();
+        assert_eq!(entries, expected);
+    }
 }
@@ -0,0 +1,59 @@
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Allocator, BPlusTree, Data, Key};
+
+// ポインタはそのまま保存できないので、cap とキー順のエントリだけを保存する
+// 読み込み時は insert し直すので、leaf の next も作り直される
+#[derive(Serialize)]
+struct TreeRef<'a> {
+    cap: usize,
+    entries: Vec<(Key, &'a Data)>,
+}
+
+#[derive(Deserialize)]
+struct TreeOwned {
+    cap: usize,
+    entries: Vec<(Key, Data)>,
+}
+
+impl<A: Allocator + Clone> Serialize for BPlusTree<A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TreeRef {
+            cap: self.cap,
+            entries: self.entries(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BPlusTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let owned = TreeOwned::deserialize(deserializer)?;
+        let mut tree = BPlusTree::try_new(owned.cap).map_err(de::Error::custom)?;
+        for (key, data) in owned.entries {
+            tree.insert(key, data);
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut b = BPlusTree::new(3);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            b.insert(k, k * 10);
+        }
+        let json = serde_json::to_string(&b).unwrap();
+        let restored: BPlusTree = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.cap, 3);
+        assert_eq!(restored.entries(), b.entries());
+        assert_eq!(
+            restored.search_range(11, 24),
+            vec![&110, &120, &130, &140, &240]
+        );
+    }
+}
@@ -0,0 +1,520 @@
+// 部分木の大きさで平衡を保つ二分探索木 (weight-balanced tree, BB[α])
+// 左右の重み (大きさ + 1) の比が DELTA 倍を超えたら回転する
+// DELTA = 3, GAMMA = 2 は整数だけで平衡を保てる組み合わせ (Hirai and Yamamoto, 2011)
+//
+// どのノードも部分木の大きさを持っているので、順序統計量を O(log n) で求められる
+//   select(k): k 番目 (0 始まり) に小さいエントリ
+//   rank(key): key より小さいキーの数
+use std::{
+    cmp::Ordering,
+    iter::{FromIterator, FusedIterator},
+    mem,
+};
+
+pub use orderedmap::{Op, OrderedMap};
+
+// 片方の重みが、もう片方の DELTA 倍を超えたら偏っているとみなす
+const DELTA: usize = 3;
+// 回転で持ち上げる子の内側の重みが、外側の GAMMA 倍以上なら二重回転にする
+const GAMMA: usize = 2;
+
+type Link<K, V> = Option<Box<Node<K, V>>>;
+
+#[derive(Debug)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    // このノードを根とする部分木のノードの数
+    size: usize,
+    left: Link<K, V>,
+    right: Link<K, V>,
+}
+
+impl<K, V> Node<K, V> {
+    fn update_size(&mut self) {
+        self.size = 1 + size(&self.left) + size(&self.right);
+    }
+}
+
+fn size<K, V>(link: &Link<K, V>) -> usize {
+    link.as_ref().map_or(0, |n| n.size)
+}
+
+fn weight<K, V>(link: &Link<K, V>) -> usize {
+    size(link) + 1
+}
+
+fn rotate_left<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut right = node.right.take().expect("rotate_left needs a right child");
+    node.right = right.left.take();
+    node.update_size();
+    right.left = Some(node);
+    right.update_size();
+    right
+}
+
+fn rotate_right<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    let mut left = node.left.take().expect("rotate_right needs a left child");
+    node.left = left.right.take();
+    node.update_size();
+    left.right = Some(node);
+    left.update_size();
+    left
+}
+
+// 1 つ追加または削除した後の node を、重みの比が DELTA 以内になるように回転する
+fn balance<K, V>(mut node: Box<Node<K, V>>) -> Box<Node<K, V>> {
+    node.update_size();
+    let (left, right) = (weight(&node.left), weight(&node.right));
+    if right > DELTA * left {
+        let r = node.right.as_ref().expect("heavy side is not empty");
+        if weight(&r.left) >= GAMMA * weight(&r.right) {
+            node.right = node.right.take().map(rotate_right);
+        }
+        rotate_left(node)
+    } else if left > DELTA * right {
+        let l = node.left.as_ref().expect("heavy side is not empty");
+        if weight(&l.right) >= GAMMA * weight(&l.left) {
+            node.left = node.left.take().map(rotate_left);
+        }
+        rotate_right(node)
+    } else {
+        node
+    }
+}
+
+fn insert<K: Ord, V>(link: Link<K, V>, key: K, value: V) -> (Box<Node<K, V>>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => {
+            let node = Box::new(Node {
+                key,
+                value,
+                size: 1,
+                left: None,
+                right: None,
+            });
+            return (node, None);
+        }
+    };
+    let old = match key.cmp(&node.key) {
+        Ordering::Equal => Some(mem::replace(&mut node.value, value)),
+        Ordering::Less => {
+            let (left, old) = insert(node.left.take(), key, value);
+            node.left = Some(left);
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = insert(node.right.take(), key, value);
+            node.right = Some(right);
+            old
+        }
+    };
+    // 置き換えただけなら大きさは変わらない
+    match old {
+        Some(old) => (node, Some(old)),
+        None => (balance(node), None),
+    }
+}
+
+fn remove<K: Ord, V>(link: Link<K, V>, key: &K) -> (Link<K, V>, Option<V>) {
+    let mut node = match link {
+        Some(node) => node,
+        None => return (None, None),
+    };
+    let old = match key.cmp(&node.key) {
+        Ordering::Less => {
+            let (left, old) = remove(node.left.take(), key);
+            node.left = left;
+            old
+        }
+        Ordering::Greater => {
+            let (right, old) = remove(node.right.take(), key);
+            node.right = right;
+            old
+        }
+        Ordering::Equal => {
+            let Node {
+                value, left, right, ..
+            } = *node;
+            return (glue(left, right), Some(value));
+        }
+    };
+    match old {
+        Some(old) => (Some(balance(node)), Some(old)),
+        None => (Some(node), None),
+    }
+}
+
+// 消したノードの左右を繋ぐ。重い方から端のノードを外して根にする
+fn glue<K, V>(left: Link<K, V>, right: Link<K, V>) -> Link<K, V> {
+    match (left, right) {
+        (None, link) | (link, None) => link,
+        (Some(left), Some(right)) => {
+            let mut root = if left.size > right.size {
+                let (left, mut max) = remove_max(left);
+                max.left = left;
+                max.right = Some(right);
+                max
+            } else {
+                let (right, mut min) = remove_min(right);
+                min.left = Some(left);
+                min.right = right;
+                min
+            };
+            root.update_size();
+            Some(root)
+        }
+    }
+}
+
+fn remove_min<K, V>(mut node: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    match node.left.take() {
+        None => (node.right.take(), node),
+        Some(left) => {
+            let (left, min) = remove_min(left);
+            node.left = left;
+            (Some(balance(node)), min)
+        }
+    }
+}
+
+fn remove_max<K, V>(mut node: Box<Node<K, V>>) -> (Link<K, V>, Box<Node<K, V>>) {
+    match node.right.take() {
+        None => (node.left.take(), node),
+        Some(right) => {
+            let (right, max) = remove_max(right);
+            node.right = right;
+            (Some(balance(node)), max)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WBTree<K, V> {
+    root: Link<K, V>,
+}
+
+impl<K: Ord + Copy, V> WBTree<K, V> {
+    pub fn new() -> Self {
+        Self { root: None }
+    }
+
+    pub fn len(&self) -> usize {
+        size(&self.root)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    // root から一番遠い空の部分木までのノードの数
+    pub fn height(&self) -> usize {
+        fn height<K, V>(link: &Link<K, V>) -> usize {
+            link.as_ref()
+                .map_or(0, |n| 1 + height(&n.left).max(height(&n.right)))
+        }
+        height(&self.root)
+    }
+
+    pub fn get(&self, key: K) -> Option<&V> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&node.value),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => node.right.as_deref(),
+            };
+        }
+        None
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut V> {
+        let mut current = self.root.as_deref_mut();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return Some(&mut node.value),
+                Ordering::Less => node.left.as_deref_mut(),
+                Ordering::Greater => node.right.as_deref_mut(),
+            };
+        }
+        None
+    }
+
+    // 既にキーがあれば値を置き換えて、前の値を返す
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let (root, old) = insert(self.root.take(), key, value);
+        self.root = Some(root);
+        old
+    }
+
+    pub fn remove(&mut self, key: K) -> Option<V> {
+        let (root, old) = remove(self.root.take(), &key);
+        self.root = root;
+        old
+    }
+
+    // k 番目 (0 始まり) に小さいエントリ。k >= len なら None
+    pub fn select(&self, mut k: usize) -> Option<(K, &V)> {
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            let left = size(&node.left);
+            current = match k.cmp(&left) {
+                Ordering::Equal => return Some((node.key, &node.value)),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => {
+                    k -= left + 1;
+                    node.right.as_deref()
+                }
+            };
+        }
+        None
+    }
+
+    // key より小さいキーの数。key があれば select(rank(key)) で key が返る
+    pub fn rank(&self, key: K) -> usize {
+        let mut rank = 0;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            current = match key.cmp(&node.key) {
+                Ordering::Equal => return rank + size(&node.left),
+                Ordering::Less => node.left.as_deref(),
+                Ordering::Greater => {
+                    rank += size(&node.left) + 1;
+                    node.right.as_deref()
+                }
+            };
+        }
+        rank
+    }
+
+    // キー順に (key, value) を返す
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining: self.len(),
+        };
+        iter.push_left(self.root.as_deref());
+        iter
+    }
+
+    // min_key <= key <= max_key のエントリをキー順に返す
+    pub fn range(&self, min_key: K, max_key: K) -> Iter<'_, K, V> {
+        // 大きさが分かっているので、範囲の件数も rank で求まる
+        let remaining = if min_key > max_key {
+            0
+        } else {
+            self.rank(max_key) + self.get(max_key).is_some() as usize - self.rank(min_key)
+        };
+        let mut iter = Iter {
+            stack: Vec::new(),
+            remaining,
+        };
+        if remaining == 0 {
+            return iter;
+        }
+        // min_key 以上のノードだけを積む
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key < min_key {
+                current = node.right.as_deref();
+            } else {
+                iter.stack.push(node);
+                current = node.left.as_deref();
+            }
+        }
+        iter
+    }
+
+    // キーの順序、部分木の大きさ、重みの比が正しいか確かめる
+    pub fn check_invariants(&self) {
+        fn check<K: Ord + Copy, V>(node: &Node<K, V>, (lower, upper): (Option<K>, Option<K>)) {
+            assert!(
+                lower.is_none_or(|l| l < node.key) && upper.is_none_or(|u| node.key < u),
+                "keys are out of order"
+            );
+            if let Some(left) = node.left.as_deref() {
+                check(left, (lower, Some(node.key)));
+            }
+            if let Some(right) = node.right.as_deref() {
+                check(right, (Some(node.key), upper));
+            }
+            assert_eq!(
+                node.size,
+                1 + size(&node.left) + size(&node.right),
+                "size is stale"
+            );
+            let (left, right) = (weight(&node.left), weight(&node.right));
+            assert!(
+                left <= DELTA * right && right <= DELTA * left,
+                "node is unbalanced"
+            );
+        }
+        if let Some(root) = self.root.as_deref() {
+            check(root, (None, None));
+        }
+    }
+}
+
+impl<K: Ord + Copy, V> Default for WBTree<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 通ったノードのうち、まだ返していないものを積んでおく
+// range は件数を先に数えるので、終わりのキーを比べずに remaining で止める
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a Node<K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left(&mut self, mut current: Option<&'a Node<K, V>>) {
+        while let Some(node) = current {
+            self.stack.push(node);
+            current = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K: Ord + Copy, V> Iterator for Iter<'a, K, V> {
+    type Item = (K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.stack.pop()?;
+        self.push_left(node.right.as_deref());
+        self.remaining -= 1;
+        Some((node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K: Ord + Copy, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> FusedIterator for Iter<'a, K, V> {}
+
+impl<'a, K: Ord + Copy, V> IntoIterator for &'a WBTree<K, V> {
+    type Item = (K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Ord + Copy, V> FromIterator<(K, V)> for WBTree<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        for (key, value) in iter {
+            tree.insert(key, value);
+        }
+        tree
+    }
+}
+
+impl<K: Ord + Copy, V> OrderedMap<K, V> for WBTree<K, V> {
+    type Iter<'a>
+        = Iter<'a, K, V>
+    where
+        Self: 'a,
+        V: 'a;
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        WBTree::insert(self, key, value)
+    }
+
+    fn get(&self, key: K) -> Option<&V> {
+        WBTree::get(self, key)
+    }
+
+    fn remove(&mut self, key: K) -> Option<V> {
+        WBTree::remove(self, key)
+    }
+
+    fn iter(&self) -> Iter<'_, K, V> {
+        WBTree::iter(self)
+    }
+
+    fn range(&self, min_key: K, max_key: K) -> Vec<(K, &V)> {
+        WBTree::range(self, min_key, max_key).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let mut t = WBTree::new();
+        assert_eq!(t.get(1), None);
+        assert_eq!(t.remove(1), None);
+        for k in [11, 25, 12, 24, 13, 10, 14] {
+            assert_eq!(t.insert(k, -k), None);
+            t.check_invariants();
+        }
+        assert_eq!(t.insert(12, 120), Some(-12));
+        assert_eq!(t.get(12), Some(&120));
+        *t.get_mut(24).unwrap() = 240;
+        assert_eq!(t.len(), 7);
+        for k in [12, 11, 25] {
+            assert!(t.remove(k).is_some());
+            t.check_invariants();
+        }
+        assert_eq!(t.remove(12), None);
+        let all: Vec<_> = t.iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(all, vec![(10, -10), (13, -13), (14, -14), (24, 240)]);
+    }
+
+    #[test]
+    fn select_and_rank() {
+        let t: WBTree<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        assert_eq!(t.select(0), Some((0, &0)));
+        assert_eq!(t.select(37), Some((74, &37)));
+        assert_eq!(t.select(99), Some((198, &99)));
+        assert_eq!(t.select(100), None);
+        assert_eq!(t.rank(0), 0);
+        assert_eq!(t.rank(74), 37);
+        // 無いキーは入る位置を返す
+        assert_eq!(t.rank(75), 38);
+        assert_eq!(t.rank(1000), 100);
+        for k in 0..100 {
+            assert_eq!(t.select(t.rank(k * 2)).map(|(k, _)| k), Some(k * 2));
+        }
+    }
+
+    #[test]
+    fn height_is_logarithmic() {
+        // 昇順に入れても偏らない。重みの比が 3 以内なので高さは log_{4/3}(n + 1) 以下
+        let n = 100_000;
+        let mut t: WBTree<_, _> = (0..n).map(|k| (k, k)).collect();
+        t.check_invariants();
+        let bound = ((n + 1) as f64).ln() / (4.0f64 / 3.0).ln();
+        assert!((t.height() as f64) <= bound);
+        for k in 0..n / 2 {
+            t.remove(k);
+        }
+        t.check_invariants();
+        assert_eq!(t.select(0), Some((n / 2, &(n / 2))));
+    }
+
+    #[test]
+    fn range() {
+        let t: WBTree<_, _> = (0..100).map(|k| (k * 2, k)).collect();
+        let keys: Vec<_> = t.range(9, 15).map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![10, 12, 14]);
+        assert_eq!(t.range(10, 14).len(), 3);
+        assert_eq!(t.range(10, 10).count(), 1);
+        assert_eq!(t.range(15, 9).count(), 0);
+        assert_eq!(t.range(199, 1000).count(), 0);
+        assert_eq!(t.iter().len(), 100);
+        assert!(t.iter().map(|(k, _)| k).eq((0..100).map(|k| k * 2)));
+    }
+}